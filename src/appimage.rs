@@ -0,0 +1,150 @@
+//! AppImage application bundles.
+//!
+//! An AppImage is an ELF executable, the runtime, marked with `AI` and the
+//! AppImage type in the padding of its ELF identification. Type 2 images
+//! append a SquashFS filesystem to the runtime, starting where the ELF
+//! section header table ends. Type 1 images are ISO 9660 images whose
+//! system area holds the runtime, so they are read as plain images.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{
+    entry::EntryInfo,
+    error::ArchiveError,
+    format::ArchiveFormat,
+    iso,
+    squashfs::{self, SQUASHFS_MAGIC},
+};
+
+/// Magic number at the start of every ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+/// Marker in the ELF identification padding, followed by the type byte.
+const APPIMAGE_MARKER: &[u8] = b"AI";
+/// Offset of [`APPIMAGE_MARKER`].
+const MARKER_OFFSET: usize = 8;
+/// Size of the ELF header of 64-bit executables, the larger class.
+const ELF_HEADER_SIZE: usize = 64;
+
+/// Returns `true` if `data` starts with an ELF header carrying the marker
+/// of a type 1 or type 2 AppImage.
+pub(crate) fn is_appimage(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+        && data.get(MARKER_OFFSET..MARKER_OFFSET + 2) == Some(APPIMAGE_MARKER)
+        && matches!(data.get(MARKER_OFFSET + 2), Some(1 | 2))
+}
+
+/// Walks every entry of the filesystem embedded in an AppImage, parents
+/// before their children.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(mut reader: R, visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut header = Vec::with_capacity(ELF_HEADER_SIZE);
+    reader.seek(SeekFrom::Start(0))?;
+    reader
+        .by_ref()
+        .take(ELF_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    if !is_appimage(&header) {
+        return Err(invalid("missing AppImage marker"));
+    }
+    if header[MARKER_OFFSET + 2] == 1 {
+        return iso::walk(reader, visit);
+    }
+
+    let offset = squashfs_offset(&header)?;
+    let mut magic = Vec::with_capacity(SQUASHFS_MAGIC.len());
+    reader.seek(SeekFrom::Start(offset))?;
+    reader
+        .by_ref()
+        .take(SQUASHFS_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    if magic != SQUASHFS_MAGIC {
+        return Err(invalid("no SquashFS image follows the runtime"));
+    }
+    squashfs::walk(
+        Embedded {
+            reader,
+            offset,
+        },
+        visit,
+    )
+}
+
+/// Returns the offset of the SquashFS image of a type 2 AppImage: the end
+/// of the runtime's section header table, which the linker places last.
+fn squashfs_offset(header: &[u8]) -> Result<u64, ArchiveError> {
+    let big_endian = match header.get(5) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return Err(invalid("unknown ELF byte order")),
+    };
+    let field = |offset: usize, len: usize| -> Result<u64, ArchiveError> {
+        let bytes = header
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("truncated ELF header"))?;
+        let mut value = 0u64;
+        for i in 0..len {
+            let byte = if big_endian {
+                bytes[i]
+            } else {
+                bytes[len - 1 - i]
+            };
+            value = value << 8 | u64::from(byte);
+        }
+        Ok(value)
+    };
+    // Section header offset, entry size and entry count
+    let (shoff, shentsize, shnum) = match header.get(4) {
+        Some(1) => (field(0x20, 4)?, field(0x2E, 2)?, field(0x30, 2)?),
+        Some(2) => (field(0x28, 8)?, field(0x3A, 2)?, field(0x3C, 2)?),
+        _ => return Err(invalid("unknown ELF class")),
+    };
+    shoff
+        .checked_add(shentsize * shnum)
+        .ok_or_else(|| invalid("section header table is out of range"))
+}
+
+/// A reader over the part of `reader` from `offset` on, seen as starting
+/// at position 0.
+struct Embedded<R> {
+    reader: R,
+    offset: u64,
+}
+
+impl<R: Read> Read for Embedded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Embedded<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(position) => {
+                SeekFrom::Start(self.offset.checked_add(position).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "seek position overflows")
+                })?)
+            }
+            relative => relative,
+        };
+        let absolute = self.reader.seek(position)?;
+        absolute.checked_sub(self.offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the embedded image",
+            )
+        })
+    }
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::AppImage,
+        reason: reason.into(),
+    }
+}
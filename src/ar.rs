@@ -0,0 +1,259 @@
+//! Unix `ar` archives and the Debian packages built on them.
+//!
+//! Both the GNU/System V and the BSD variants of the format are read. GNU
+//! archives keep long member names in a `//` table and terminate names with
+//! `/`; BSD archives store long names as `#1/<len>` followed by the name at
+//! the start of the member data. Symbol tables (`/` and `__.SYMDEF`) are
+//! skipped.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    entry::{EntryInfo, EntryKind, Owner},
+    error::{ArchiveError, PathContext},
+    extract::{self, ExtractEvent, ExtractOptions, ExtractReport},
+    format::{self, ArchiveFormat},
+    volume::Location,
+};
+
+/// Global header at the start of every `ar` archive.
+pub(crate) const AR_MAGIC: &[u8] = b"!<arch>\n";
+/// Name of the first member of a Debian package.
+const DEB_MARKER: &[u8] = b"debian-binary";
+/// Size of a member header.
+const HEADER_SIZE: usize = 60;
+/// Terminator of every member header.
+const HEADER_END: &[u8] = b"`\n";
+/// Upper bound on the size of the GNU long name table.
+const MAX_NAME_TABLE_SIZE: u64 = 16 * 1024 * 1024;
+/// Upper bound on the length of a BSD member name.
+const MAX_NAME_SIZE: u64 = 64 * 1024;
+
+/// Returns `true` if `data` starts like a Debian package: an `ar` archive
+/// whose first member is `debian-binary`.
+pub(crate) fn is_deb(data: &[u8]) -> bool {
+    data.starts_with(AR_MAGIC) && data[AR_MAGIC.len()..].starts_with(DEB_MARKER)
+}
+
+/// Walks every member of an `ar` archive in storage order.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(
+    mut reader: R,
+    format: ArchiveFormat,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut magic = [0u8; AR_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != AR_MAGIC {
+        return Err(invalid(format, "missing '!<arch>' header"));
+    }
+
+    let mut long_names = Vec::new();
+    let mut header = [0u8; HEADER_SIZE];
+    loop {
+        if !read_header(&mut reader, &mut header)? {
+            return Ok(());
+        }
+        if &header[58..60] != HEADER_END {
+            return Err(invalid(format, "malformed member header"));
+        }
+
+        let raw_name = field(&header[0..16]);
+        let mut size =
+            number(&header[48..58], 10).ok_or_else(|| invalid(format, "malformed member size"))?;
+        let padding = size % 2;
+
+        let name = if raw_name == "//" {
+            if size > MAX_NAME_TABLE_SIZE {
+                return Err(invalid(format, "long name table too large"));
+            }
+            long_names = vec![0; size as usize];
+            reader.read_exact(&mut long_names)?;
+            skip_padding(&mut reader, padding)?;
+            continue;
+        } else if raw_name == "/" || raw_name == "/SYM64/" || raw_name.starts_with("__.SYMDEF") {
+            skip(&mut reader, size + padding)?;
+            continue;
+        } else if let Some(len) = raw_name.strip_prefix("#1/") {
+            // BSD: the name is stored in front of the data
+            let len: u64 = len
+                .parse()
+                .map_err(|_| invalid(format, "malformed BSD name length"))?;
+            if len > size {
+                return Err(invalid(format, "BSD name longer than member"));
+            }
+            if len > MAX_NAME_SIZE {
+                return Err(invalid(format, "BSD name too long"));
+            }
+            let mut name = vec![0; len as usize];
+            reader.read_exact(&mut name)?;
+            size -= len;
+            String::from_utf8_lossy(&name)
+                .trim_end_matches('\0')
+                .to_string()
+        } else if let Some(offset) = raw_name.strip_prefix('/') {
+            // GNU: `/<offset>` into the long name table
+            let offset: usize = offset
+                .parse()
+                .map_err(|_| invalid(format, "malformed long name reference"))?;
+            let rest = long_names
+                .get(offset..)
+                .ok_or_else(|| invalid(format, "long name reference out of range"))?;
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end])
+                .trim_end_matches('/')
+                .to_string()
+        } else {
+            raw_name.trim_end_matches('/').to_string()
+        };
+
+        let info = EntryInfo {
+            name,
+            kind: EntryKind::File,
+            size,
+            compressed_size: Some(size),
+            mtime: number(&header[16..28], 10).map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            mode: number(&header[40..48], 8).map(|m| (m & 0o7777) as u32),
+            link_target: None,
+            owner: number(&header[28..34], 10)
+                .zip(number(&header[34..40], 10))
+                .map(|(uid, gid)| {
+                    Owner {
+                        uid,
+                        gid,
+                        user: None,
+                        group: None,
+                    }
+                }),
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+
+        let mut data = (&mut reader).take(size);
+        let keep_going = visit(&info, &mut data)?;
+        io::copy(&mut data, &mut io::sink())?;
+        if data.limit() != 0 {
+            return Err(invalid(
+                format,
+                "member extends past the end of the archive",
+            ));
+        }
+        if !keep_going {
+            return Ok(());
+        }
+        skip_padding(&mut reader, padding)?;
+    }
+}
+
+/// Extracts the contents of a Debian package.
+///
+/// The `data.tar.*` member is unpacked into `output_dir` and the
+/// `control.tar.*` member into `output_dir/DEBIAN`, like `dpkg-deb -R`.
+/// Both may be uncompressed or compressed with any codec the TAR-based
+/// formats support.
+pub(crate) fn extract_deb(
+    location: &Location,
+    output_dir: &Path,
+    options: &ExtractOptions,
+) -> Result<ExtractReport, ArchiveError> {
+    extract::atomically(output_dir, options, |output_dir| {
+        fs::create_dir_all(output_dir).at_path(output_dir)?;
+        // Both members go into the one directory made atomic here
+        let members = options.clone().atomic(false);
+        let file = location.open(ArchiveFormat::Deb)?;
+        let mut found_data = false;
+        let mut report = ExtractReport::default();
+
+        walk(file, ArchiveFormat::Deb, |info, reader| {
+            let dir = if info.name.starts_with("data.tar") {
+                found_data = true;
+                output_dir.to_path_buf()
+            } else if info.name.starts_with("control.tar") {
+                output_dir.join("DEBIAN")
+            } else {
+                return Ok(true);
+            };
+            let format = format::detect_from_extension(&info.name).map_err(|_| {
+                ArchiveError::InvalidArchive {
+                    format: ArchiveFormat::Deb,
+                    reason: format!("unsupported member compression: {}", info.name).into(),
+                }
+            })?;
+            report.absorb(extract::extract_stream(
+                reader,
+                format,
+                &dir,
+                &members,
+                &mut |_: ExtractEvent| {},
+            )?);
+            Ok(true)
+        })?;
+
+        if found_data {
+            Ok(report)
+        } else {
+            Err(invalid(ArchiveFormat::Deb, "missing data.tar member"))
+        }
+    })
+}
+
+/// Reads the next member header, returning `false` at the end of the archive.
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8; HEADER_SIZE]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn skip<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Skips the padding byte after odd-sized members, which some writers omit
+/// after the last one.
+fn skip_padding<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    io::copy(&mut reader.take(n), &mut io::sink())?;
+    Ok(())
+}
+
+/// Returns a space-padded header field as a string.
+fn field(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).unwrap_or_default().trim_end()
+}
+
+fn number(bytes: &[u8], radix: u32) -> Option<u64> {
+    u64::from_str_radix(field(bytes), radix).ok()
+}
+
+fn invalid(format: ArchiveFormat, reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format,
+        reason: reason.into(),
+    }
+}
@@ -1,17 +1,37 @@
 use std::{
-    io::{self, Read},
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, Write},
     path::{Path, PathBuf},
 };
 
 use crate::{
+    ar,
+    audit::{self, Finding},
+    edit,
+    entry::{self, Entries, EntryInfo, EntryReader},
     error::ArchiveError,
+    extract::{
+        self, ExtractLimits, ExtractOptions, ExtractReport, ExtractSink, ExtractionOutcome,
+        MemorySink,
+    },
     format::{self, ArchiveFormat},
+    hash::{self, ChecksumManifest, ContentHash, HashAlgo},
+    normalize::{self, NormalizeOptions},
+    oci,
+    salvage::{self, SalvageReport},
+    stat::{self, ArchiveStats},
+    verify::{self, VerifyReport},
+    volume::{Location, SharedReader},
+    writer::{self, ArchiveBuilder, Codec, CompressionOptions},
 };
 
 /// A handle to an archive file that can be extracted.
 ///
 /// This struct represents an archive file along with its detected format.
 /// It provides methods to extract the archive contents to a directory.
+/// Archives that are not files can be read through
+/// [`Archive::from_reader`].
 ///
 /// # Examples
 ///
@@ -27,9 +47,20 @@ use crate::{
 ///     Ok(())
 /// }
 /// ```
+///
+/// An `Archive` is only made through its constructors, as archives read
+/// from a reader carry state of their own besides the path and format.
+#[derive(Clone)]
+#[non_exhaustive]
 pub struct Archive {
+    /// Path of the archive file, empty if the archive is read from a reader.
+    ///
+    /// Changing it has no effect on archives read from a reader; prefer
+    /// [`Archive::path`], which tells the two apart.
     pub path: PathBuf,
+    /// Format of the archive.
     pub format: ArchiveFormat,
+    reader: Option<SharedReader>,
 }
 
 impl Archive {
@@ -38,21 +69,1646 @@ impl Archive {
     /// This method reads the file to detect the archive format using magic numbers
     /// (file signatures) and falls back to extension-based detection if needed.
     ///
+    /// Split ZIP archives (`archive.z01`, `archive.z02`, ..., `archive.zip`)
+    /// and split 7z archives (`archive.7z.001`, `archive.7z.002`, ...) may be
+    /// opened through any of their parts; the parts are joined whenever the
+    /// archive is read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the archive file to open
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Archive)` - Successfully opened archive with detected format
+    /// * `Err(ArchiveError)` - Failed to open file or detect format
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file does not exist or cannot be read
+    /// * The file format cannot be detected or is unsupported
+    /// * There are I/O errors while reading the file
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("data.zip")?;
+    ///     println!("Opened archive with format: {}", archive.format);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        let path = path.as_ref().to_path_buf();
+        let format = format::detect_from_file(&path)?;
+
+        Ok(Archive {
+            path,
+            format,
+            reader: None,
+        })
+    }
+
+    /// Opens an archive held by a reader and detects its format.
+    ///
+    /// The archive is everything from the start of `reader` to its end,
+    /// wherever the reader is positioned when it is passed in. Its format
+    /// is detected from its contents like [`Archive::open`] does, but
+    /// without a file extension to fall back on; set [`Archive::format`]
+    /// afterwards to override the detected format.
+    ///
+    /// Every method that reads an archive works on the returned instance,
+    /// which shares `reader` between clones and concurrent entry readers.
+    /// Methods that write to the archive's path, like
+    /// [`Archive::create_from_dir`], return an error instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reader holding the archive, like a file or a
+    ///   `Cursor<Vec<u8>>`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Archive)` - Archive reading from `reader`, with detected format
+    /// * `Err(ArchiveError)` - Failed to read the archive or detect its format
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * `reader` fails to read or seek
+    /// * The format cannot be detected or is unsupported
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::Cursor;
+    ///
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let data = std::fs::read("bundle.tar.gz")?;
+    ///     let archive = Archive::from_reader(Cursor::new(data))?;
+    ///     for entry in archive.entries()? {
+    ///         println!("{}", entry.name);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn from_reader<R: Read + Seek + Send + 'static>(
+        mut reader: R,
+    ) -> Result<Self, ArchiveError> {
+        let format = format::detect_from_seekable(&mut reader, None)?;
+
+        Ok(Archive {
+            path: PathBuf::new(),
+            format,
+            reader: Some(SharedReader::new(reader)?),
+        })
+    }
+
+    /// Opens an archive held in memory and detects its format.
+    ///
+    /// This is [`Archive::from_reader`] over a `Cursor`. `data` may be a
+    /// `Vec<u8>`, a `&'static [u8]` such as the output of `include_bytes!`,
+    /// or any other owned byte buffer; borrowed slices need to be copied
+    /// into a `Vec<u8>` first.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Bytes of the archive
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Archive)` - Archive reading from `data`, with detected format
+    /// * `Err(ArchiveError)` - The format could not be detected
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the format cannot be detected
+    /// or is unsupported.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let bundle: Vec<u8> = std::fs::read("plugin.zip")?;
+    ///     let archive = Archive::from_bytes(bundle)?;
+    ///     archive.extract_to("./plugins/example")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn from_bytes<B: AsRef<[u8]> + Send + 'static>(data: B) -> Result<Self, ArchiveError> {
+        Self::from_reader(io::Cursor::new(data))
+    }
+
+    /// Creates a new archive instance with format determined by file extension.
+    ///
+    /// This method is typically used when you want to create a new archive.
+    /// The format is determined solely by the file extension of the provided path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path where the archive will be created (format determined by extension)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Archive)` - Successfully created archive instance
+    /// * `Err(ArchiveError)` - Format could not be determined from extension
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file extension is not recognized
+    /// * The path has no extension or an unsupported extension
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::new("output.tar.gz")?;
+    ///     println!("Created archive for format: {}", archive.format);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        let path = path.as_ref().to_path_buf();
+        let format = format::detect_from_extension(&path)?;
+
+        Ok(Archive {
+            path,
+            format,
+            reader: None,
+        })
+    }
+
+    /// Returns the path of the archive file, or `None` if the archive is
+    /// read from a reader.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::{io::Cursor, path::Path};
+    ///
+    /// use compak::{Archive, ArchiveFormat, CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// let mut builder =
+    ///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &CompressionOptions::new())
+    ///         .unwrap();
+    /// builder.add_bytes("hello.txt", b"hello").unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let archive = Archive::from_bytes(zip).unwrap();
+    /// assert_eq!(archive.path(), None);
+    /// assert_eq!(archive.format(), ArchiveFormat::Zip);
+    /// assert_eq!(Archive::new("out.zip").unwrap().path(), Some(Path::new("out.zip")));
+    /// ```
+    pub fn path(&self) -> Option<&Path> {
+        self.reader.is_none().then_some(self.path.as_path())
+    }
+
+    /// Returns the format of the archive.
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Packs the contents of a directory into this archive.
+    ///
+    /// The archive is written to the path this instance was created with,
+    /// in the format detected from its extension (see [`Archive::new`]).
+    /// Any existing file at that path is overwritten. Entries are named
+    /// relative to `src_dir`, so the directory itself does not appear as a
+    /// top-level entry. Entries are added in sorted order, and symlinks are
+    /// stored as links rather than followed.
+    ///
+    /// # Arguments
+    ///
+    /// * `src_dir` - Directory whose contents should be packed
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The archive was written
+    /// * `Err(ArchiveError)` - Packing failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The source directory cannot be read
+    /// * A file in the source directory cannot be opened
+    /// * The archive file cannot be written
+    /// * An entry type cannot be represented in the archive format
+    /// * The archive was opened with [`Archive::from_reader`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::new("backup.tar.zst")?;
+    ///     archive.create_from_dir("./project")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn create_from_dir<P: AsRef<Path>>(&self, src_dir: P) -> Result<(), ArchiveError> {
+        self.create_from_dir_with(src_dir, &CompressionOptions::default())
+    }
+
+    /// Packs the contents of a directory into this archive, compressed at
+    /// the levels set in `options`.
+    ///
+    /// This behaves like [`Archive::create_from_dir`], which uses the
+    /// default levels of [`CompressionOptions`].
+    ///
+    /// # Arguments
+    ///
+    /// * `src_dir` - Directory whose contents should be packed
+    /// * `options` - Compression levels of the archive
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The archive was written
+    /// * `Err(ArchiveError)` - Packing failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * A level in `options` is out of range
+    /// * Packing fails, under the same conditions as
+    ///   [`Archive::create_from_dir`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, CompressionOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = CompressionOptions::new().xz_level(9);
+    ///     Archive::new("snapshot.tar.xz")?.create_from_dir_with("./data", &options)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn create_from_dir_with<P: AsRef<Path>>(
+        &self,
+        src_dir: P,
+        options: &CompressionOptions,
+    ) -> Result<(), ArchiveError> {
+        if self.reader.is_some() {
+            return Err(ArchiveError::unsupported_static(
+                "writing to an archive opened from a reader",
+            ));
+        }
+        writer::create_from_dir(&self.path, self.format, src_dir.as_ref(), options)
+    }
+
+    /// Writes this archive with a file for each of `entries`, named by the
+    /// string and holding whatever its reader yields until EOF.
+    ///
+    /// Nothing needs to exist on disk besides the archive itself, so data
+    /// generated on the fly can go straight into it. Entries are streamed as
+    /// [`ArchiveBuilder::add_reader`] does: TAR-based formats record each
+    /// size before the data, so they buffer one entry at a time in memory.
+    /// Any existing file at the archive's path is overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Pairs of entry name and contents
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The archive was written
+    /// * `Err(ArchiveError)` - Writing failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * Reading from one of the readers fails
+    /// * The archive file cannot be written
+    /// * The archive was opened with [`Archive::from_reader`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::Cursor;
+    ///
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let reports = (1..=12).map(|month| {
+    ///         let csv = format!("month,total\n{month},0\n");
+    ///         (format!("reports/{month:02}.csv"), Cursor::new(csv))
+    ///     });
+    ///     Archive::new("reports.zip")?.create_from_entries(reports)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn create_from_entries<I, R>(&self, entries: I) -> Result<(), ArchiveError>
+    where
+        I: IntoIterator<Item = (String, R)>,
+        R: Read,
+    {
+        if self.reader.is_some() {
+            return Err(ArchiveError::unsupported_static(
+                "writing to an archive opened from a reader",
+            ));
+        }
+        writer::create_from_entries(
+            &self.path,
+            self.format,
+            entries,
+            &CompressionOptions::default(),
+        )
+    }
+
+    /// Opens this ZIP archive for adding entries to it.
+    ///
+    /// Entries added to the returned builder are appended after the
+    /// existing ones, which are neither read nor rewritten; only the
+    /// central directory is replaced when the builder is finished. See
+    /// [`ArchiveBuilder::append`] for what happens if appending is
+    /// interrupted.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ArchiveBuilder)` - Builder adding entries to this archive
+    /// * `Err(ArchiveError)` - The archive cannot be appended to
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive, or is a split one
+    /// * The archive file cannot be opened for writing
+    /// * The central directory cannot be read
+    /// * The archive was opened with [`Archive::from_reader`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut builder = Archive::open("releases.zip")?.append()?;
+    ///     builder.add_bytes("1.4.2/NOTES.md", b"Fixes the installer.\n")?;
+    ///     builder.finish()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn append(&self) -> Result<ArchiveBuilder<File>, ArchiveError> {
+        if self.reader.is_some() {
+            return Err(ArchiveError::unsupported_static(
+                "writing to an archive opened from a reader",
+            ));
+        }
+        if self.format != ArchiveFormat::Zip {
+            return Err(ArchiveError::unsupported_static(
+                "appending to non-ZIP archives",
+            ));
+        }
+        ArchiveBuilder::append(&self.path)
+    }
+
+    /// Extracts the entire archive to the specified output directory.
+    ///
+    /// This method creates the output directory if it doesn't exist and extracts
+    /// all contents of the archive while preserving the directory structure.
+    /// Symlinks, hard links, permission bits and modification times are
+    /// restored where the format records them. Entries whose paths contain
+    /// `..` are skipped and leading `/` is stripped, and symlinks leading
+    /// outside `output_dir` are skipped; see
+    /// [`UnsafePathPolicy`](crate::UnsafePathPolicy) and
+    /// [`SymlinkPolicy`](crate::SymlinkPolicy) for the alternatives.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ExtractReport)` - Extraction completed; the report counts what
+    ///   was written
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The output directory cannot be created
+    /// * There are permission issues
+    /// * The archive is corrupted
+    /// * An entry would be written through a symlink leading outside `output_dir`
+    /// * The archive format is not yet implemented
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("backup.tar.gz")?;
+    ///     let report = archive.extract_to("./restored_files")?;
+    ///     println!("Extracted {} files ({} bytes)", report.files, report.bytes_written);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to<P: AsRef<Path>>(&self, output_dir: P) -> Result<ExtractReport, ArchiveError> {
+        self.extract_to_with(output_dir, &ExtractOptions::default())
+    }
+
+    /// Extracts the entire archive to the specified output directory using
+    /// custom options.
+    ///
+    /// This behaves like [`Archive::extract_to`], with the differences
+    /// described by each [`ExtractOptions`] setting. Every format honors
+    /// every option.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    /// * `options` - Extraction options
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ExtractReport)` - Extraction completed; the report lists any
+    ///   conflicts with existing paths and how they were resolved
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::extract_to`], or if:
+    /// * An entry conflicts with an existing path and the overwrite policy
+    ///   is [`OverwritePolicy::Error`](crate::OverwritePolicy::Error)
+    /// * An entry path is unsafe and the policy is
+    ///   [`UnsafePathPolicy::Error`](crate::UnsafePathPolicy::Error)
+    /// * An entry name can't be stored on Windows and the policy is
+    ///   [`WindowsNamePolicy::Error`](crate::WindowsNamePolicy::Error)
+    /// * One of the configured [`ExtractLimits`] is
+    ///   exceeded (`ArchiveError::LimitExceeded`)
+    /// * The archive is encrypted and the password is missing or wrong
+    ///   (`ArchiveError::InvalidPassword`)
+    /// * The cancellation token is triggered (`ArchiveError::Cancelled`)
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions, OverwritePolicy};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("release.zip")?;
+    ///     let options = ExtractOptions::new().overwrite(OverwritePolicy::Skip);
+    ///     let report = archive.extract_to_with("./release", &options)?;
+    ///     println!("{} existing files kept", report.conflicts.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to_with<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        extract::extract(
+            &self.location(),
+            self.format,
+            output_dir.as_ref(),
+            options,
+            &mut |_| {},
+        )
+    }
+
+    /// Extracts every entry that can be extracted, carrying on past the
+    /// ones that fail.
+    ///
+    /// Where [`Archive::extract_to_with`] stops at the first entry that
+    /// can't be written, this records the entry with its error and moves on
+    /// to the next, and stops reading the archive only once it can't be
+    /// read any further. Entries are extracted on the calling thread,
+    /// whatever [`ExtractOptions::threads`] says.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the entries will be extracted
+    /// * `options` - Extraction options
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ExtractionOutcome)` - The paths written, the entries that
+    ///   failed and why the archive couldn't be read to its end, if it
+    ///   couldn't
+    /// * `Err(ArchiveError)` - The extraction was stopped
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The output directory cannot be created
+    /// * One of the configured [`ExtractLimits`] is
+    ///   exceeded (`ArchiveError::LimitExceeded`)
+    /// * The cancellation token is triggered (`ArchiveError::Cancelled`)
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("photos.zip")?;
+    ///     let outcome = archive.extract_to_best_effort("./photos", &ExtractOptions::new())?;
+    ///     println!("{} paths written", outcome.extracted.len());
+    ///     for (entry, err) in &outcome.failed {
+    ///         eprintln!("{}: {}", entry.name, err);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to_best_effort<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractionOutcome, ArchiveError> {
+        extract::extract_best_effort(
+            &self.location(),
+            self.format,
+            output_dir.as_ref(),
+            options,
+            &mut |_| {},
+        )
+    }
+
+    /// Extracts only the entries whose names match one of the given glob
+    /// patterns.
+    ///
+    /// This is a shorthand for [`Archive::extract_to_with`] with
+    /// [`ExtractOptions::include`]; see there for the pattern syntax.
+    /// Non-matching entries are skipped without being written, and for ZIP
+    /// archives without being decompressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the matching entries will be extracted
+    /// * `patterns` - Glob patterns selecting the entries to extract
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::extract_to`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("sdk.tar.xz")?;
+    ///     archive.extract_matching("./sdk", &["*.so", "docs/**"])?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_matching<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        patterns: &[&str],
+    ) -> Result<ExtractReport, ArchiveError> {
+        let options = ExtractOptions::default().include(patterns);
+        self.extract_to_with(output_dir, &options)
+    }
+
+    /// Extracts only the entries accepted by a filter callback.
+    ///
+    /// This is a shorthand for [`Archive::extract_to_with`] with
+    /// [`ExtractOptions::filter`]. The callback runs once per entry, before
+    /// anything is written, and rejected entries are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the accepted entries will be extracted
+    /// * `filter` - Returns `true` for entries that should be extracted
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::extract_to`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, EntryInfo};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("upload.zip")?;
+    ///     archive.extract_filtered("./upload", |entry: &EntryInfo| {
+    ///         !entry.is_symlink() && !entry.name.starts_with(".git/")
+    ///     })?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_filtered<P, F>(
+        &self,
+        output_dir: P,
+        filter: F,
+    ) -> Result<ExtractReport, ArchiveError>
+    where
+        P: AsRef<Path>,
+        F: Fn(&EntryInfo) -> bool + Send + Sync + 'static,
+    {
+        let options = ExtractOptions::default().filter(filter);
+        self.extract_to_with(output_dir, &options)
+    }
+
+    /// Unpacks a Debian package, including the tarballs nested inside it.
+    ///
+    /// [`Archive::extract_to`] treats a `.deb` like any `ar` archive and
+    /// writes out its raw members (`debian-binary`, `control.tar.*` and
+    /// `data.tar.*`). This method instead unpacks `data.tar.*` into
+    /// `output_dir` and `control.tar.*` into `output_dir/DEBIAN`, matching
+    /// the layout produced by `dpkg-deb -R`. `options` apply to both.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the package contents will be extracted
+    /// * `options` - Options applied to the nested tarballs
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a Debian package
+    /// * The package has no `data.tar.*` member, or a member uses an
+    ///   unknown compression
+    /// * Extracting a nested tarball fails, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let package = Archive::open("hello_2.10-3_amd64.deb")?;
+    ///     package.extract_deb("./hello", &ExtractOptions::new())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_deb<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        if self.format != ArchiveFormat::Deb {
+            return Err(ArchiveError::unsupported_static(
+                "unpacking non-Debian archives as packages",
+            ));
+        }
+        ar::extract_deb(&self.location(), output_dir.as_ref(), options)
+    }
+
+    /// Extracts a single image of a Windows Imaging (WIM) file.
+    ///
+    /// A WIM can hold several directory trees, such as the editions of a
+    /// Windows installation image. [`Archive::extract_to`] unpacks all of
+    /// them, each into a directory named after its index unless the file
+    /// holds just one; this method unpacks only image `index` directly into
+    /// `output_dir`. Images are numbered from 1, as DISM and wimlib do.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Number of the image to extract, starting at 1
+    /// * `output_dir` - Directory where the image contents will be extracted
+    /// * `options` - Options controlling the extraction
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a WIM
+    /// * The WIM has no image `index` (`ArchiveError::NotFound`)
+    /// * Extracting the image fails, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let image = Archive::open("install.wim")?;
+    ///     image.extract_wim_image(1, "./windows", &ExtractOptions::new())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_wim_image<P: AsRef<Path>>(
+        &self,
+        index: u32,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        if self.format != ArchiveFormat::Wim {
+            return Err(ArchiveError::unsupported_static(
+                "selecting images of non-WIM archives",
+            ));
+        }
+        extract::extract_wim_image(
+            &self.location(),
+            index,
+            output_dir.as_ref(),
+            options,
+            &mut |_| {},
+        )
+    }
+
+    /// Extracts a macOS installer package, unpacking its payloads.
+    ///
+    /// Flat `.pkg` files are xar archives whose `Payload` and `Scripts`
+    /// members are compressed `cpio` archives. [`Archive::extract_to`]
+    /// writes those members out as they are stored; this method instead
+    /// unpacks each into a directory of the same name, matching the layout
+    /// produced by `pkgutil --expand-full`. Payloads compressed with gzip,
+    /// bzip2, xz or pbzx are recognised.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the package contents will be extracted
+    /// * `options` - Options controlling the extraction
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a xar archive
+    /// * A payload uses an unknown compression or is not a `cpio` archive
+    /// * Extracting fails, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let package = Archive::open("Installer.pkg")?;
+    ///     package.extract_pkg("./installer", &ExtractOptions::new())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_pkg<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        if self.format != ArchiveFormat::Xar {
+            return Err(ArchiveError::unsupported_static(
+                "unpacking non-xar archives as packages",
+            ));
+        }
+        extract::extract_pkg(&self.location(), output_dir.as_ref(), options, &mut |_| {})
+    }
+
+    /// Extracts a Windows Installer package, unpacking its embedded
+    /// cabinets.
+    ///
+    /// An `.msi` is an OLE compound file holding the installer database
+    /// and, usually, the installed files in cabinet streams.
+    /// [`Archive::extract_to`] writes every stream out as it is stored,
+    /// cabinets included; this method instead unpacks each cabinet into a
+    /// directory of the same name. The files inside keep the names the
+    /// cabinet gives them, which are the keys of the database's `File`
+    /// table rather than their installed names. Cabinets shipped next to
+    /// the package are not read.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the package contents will be extracted
+    /// * `options` - Options controlling the extraction
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not an MSI package or other OLE compound file
+    /// * A cabinet uses Quantum compression or spans several files
+    /// * Extracting fails, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let package = Archive::open("setup.msi")?;
+    ///     package.extract_msi("./setup", &ExtractOptions::new())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_msi<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        if self.format != ArchiveFormat::Msi {
+            return Err(ArchiveError::unsupported_static(
+                "unpacking cabinets of non-MSI archives",
+            ));
+        }
+        extract::extract_msi(&self.location(), output_dir.as_ref(), options, &mut |_| {})
+    }
+
+    /// Extracts a container image saved as a tarball into a single root
+    /// filesystem.
+    ///
+    /// Both `docker save` tarballs and OCI image layouts packed into a tar
+    /// are read. The layers of the first image are unpacked bottom to top,
+    /// each replacing what the layers below it wrote. Whiteout files are
+    /// applied rather than extracted: `.wh.<name>` deletes `<name>` from
+    /// the lower layers, and `.wh..wh..opq` hides everything they put in
+    /// its directory. Layers may be uncompressed or compressed with gzip,
+    /// xz, bzip2 or Zstandard. Use [`extract_layers`] for layer tarballs
+    /// stored as separate files.
+    ///
+    /// Root filesystems are full of absolute symlinks, which are skipped by
+    /// default like every link leading outside `output_dir`. Extract with
+    /// [`UnsafePathPolicy::Sanitize`](crate::UnsafePathPolicy::Sanitize) to
+    /// keep them, rewritten to point below `output_dir`.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the root filesystem will be extracted
+    /// * `options` - Options applied to every layer
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not an uncompressed TAR archive
+    /// * The tarball has neither a `manifest.json` nor an `index.json`, or
+    ///   they do not lead to a list of layers
+    /// * A layer is missing or is not a tarball
+    /// * Extracting a layer fails, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions, UnsafePathPolicy};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     // Written by `docker save alpine > alpine.tar`
+    ///     let image = Archive::open("alpine.tar")?;
+    ///     let options = ExtractOptions::new().unsafe_paths(UnsafePathPolicy::Sanitize);
+    ///     image.extract_image("./rootfs", &options)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_image<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        if self.format != ArchiveFormat::Tar {
+            return Err(ArchiveError::unsupported_static(
+                "unpacking container images from non-TAR or compressed archives",
+            ));
+        }
+        oci::extract_image(&self.location(), output_dir.as_ref(), options, &mut |_| {})
+    }
+
+    /// Extracts an archive read from an arbitrary stream.
+    ///
+    /// This lets archives arriving over sockets, HTTP bodies or stdin be
+    /// extracted without first writing them to a temporary file. Since a
+    /// stream cannot be sniffed and rewound, the format must be given
+    /// explicitly.
+    ///
+    /// TAR-based formats are decompressed and unpacked on the fly. ZIP
+    /// archives are read via their local file headers rather than the
+    /// central directory. 7z archives require random access, so the whole
+    /// stream is buffered in memory before extraction, and RAR archives,
+    /// with the `rar` feature, are copied to a temporary file. Single-file streams
+    /// such as [`ArchiveFormat::Gzip`] have no name to go by and are written
+    /// to a file called `data`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Source of the archive bytes
+    /// * `format` - Format of the archive
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ExtractReport)` - Extraction completed; the report counts what
+    ///   was written
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * Reading from the stream fails
+    /// * The output directory cannot be created
+    /// * The archive is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ArchiveFormat};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     // e.g. `curl -L https://example.com/release.tar.gz | my-tool`
+    ///     let stdin = std::io::stdin().lock();
+    ///     Archive::extract_from_reader(stdin, ArchiveFormat::TarGz, "./release")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_from_reader<R: Read, P: AsRef<Path>>(
+        reader: R,
+        format: ArchiveFormat,
+        output_dir: P,
+    ) -> Result<ExtractReport, ArchiveError> {
+        extract::extract_stream(
+            reader,
+            format,
+            output_dir.as_ref(),
+            &ExtractOptions::default(),
+            &mut |_| {},
+        )
+    }
+
+    /// Extracts every file of the archive into memory.
+    ///
+    /// The returned map is keyed by the paths the files would have been
+    /// written to below an output directory by [`Archive::extract_to`]:
+    /// leading `./` and `/` are stripped, and entries whose paths contain
+    /// `..` are left out, so the keys are safe to join to a directory. Only
+    /// regular files and hard links are included; directories and symlinks
+    /// carry no data and are omitted. Nothing is written to the local
+    /// filesystem.
+    ///
+    /// This is [`Archive::extract_to_memory_with`] with the default
+    /// options.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HashMap)` - Map of entry path to file contents
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let files = Archive::open("plugin.zip")?.extract_to_memory()?;
+    ///     let manifest = &files[Path::new("manifest.json")];
+    ///     println!("Manifest is {} bytes", manifest.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// Paths that would escape an output directory don't make it into the
+    /// map:
+    ///
+    /// ```rust
+    /// use std::{io::Cursor, path::PathBuf};
+    ///
+    /// use compak::{Archive, ArchiveFormat, CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// let mut builder =
+    ///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &CompressionOptions::new())
+    ///         .unwrap();
+    /// builder.add_bytes("../evil.txt", b"evil").unwrap();
+    /// builder.add_bytes("/etc/motd", b"hi").unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let files = Archive::from_bytes(zip).unwrap().extract_to_memory().unwrap();
+    /// assert_eq!(files.keys().collect::<Vec<_>>(), [&PathBuf::from("etc/motd")]);
+    /// ```
+    pub fn extract_to_memory(&self) -> Result<HashMap<PathBuf, Vec<u8>>, ArchiveError> {
+        self.extract_to_memory_with(&ExtractOptions::default())
+    }
+
+    /// Extracts every file of the archive into memory, refusing to buffer
+    /// more than `max_total_size` bytes in total.
+    ///
+    /// This behaves like [`Archive::extract_to_memory`], but stops as soon as
+    /// the combined size of the extracted files would exceed the cap. The
+    /// check is made against the actual decompressed bytes, not the sizes
+    /// recorded in the archive headers, so lying headers cannot bypass it.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_total_size` - Maximum number of bytes to hold in memory
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HashMap)` - Map of entry path to file contents
+    /// * `Err(ArchiveError)` - Extraction failed or the cap was exceeded
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    /// * The extracted data exceeds `max_total_size` (`ArchiveError::LimitExceeded`)
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("upload.zip")?;
+    ///     let files = archive.extract_to_memory_with_limit(16 * 1024 * 1024)?;
+    ///     println!("Extracted {} files", files.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to_memory_with_limit(
+        &self,
+        max_total_size: u64,
+    ) -> Result<HashMap<PathBuf, Vec<u8>>, ArchiveError> {
+        let limits = ExtractLimits::new().max_total_size(max_total_size);
+        self.extract_to_memory_with(&ExtractOptions::default().limits(limits))
+    }
+
+    /// Extracts the files of the archive into memory, applying `options`.
+    ///
+    /// Entries are selected, renamed and checked against limits as
+    /// [`Archive::extract_to_with`] does, so the map is keyed by the paths
+    /// the files would have been written to below an output directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Extraction options
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HashMap)` - Map of mapped entry path to file contents
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    /// * A configured [`ExtractLimits`] limit is
+    ///   exceeded
+    /// * The operation is cancelled
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractLimits, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = ExtractOptions::new()
+    ///         .include(["*.csv"])
+    ///         .limits(ExtractLimits::new().max_total_size(64 * 1024 * 1024));
+    ///     let files = Archive::open("upload.zip")?.extract_to_memory_with(&options)?;
+    ///     println!("Extracted {} files", files.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to_memory_with(
+        &self,
+        options: &ExtractOptions,
+    ) -> Result<HashMap<PathBuf, Vec<u8>>, ArchiveError> {
+        let mut sink = MemorySink::new();
+        self.extract_to_sink(&mut sink, options)?;
+        Ok(sink.into_files())
+    }
+
+    /// Reads a single file of the archive into memory.
+    ///
+    /// The entry is looked up by its path inside the archive, with leading
+    /// `./` and `/` ignored, and nothing is written to the local filesystem.
+    /// ZIP archives jump straight to the entry through their central
+    /// directory; other formats are read only as far as the entry. Hard
+    /// links are followed to the file they name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Path of the entry inside the archive
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The contents of the entry
+    /// * `Err(ArchiveError)` - The entry could not be found or read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    /// * No entry is called `name` (`ArchiveError::NotFound`)
+    /// * The entry is a directory, symlink or special file
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let manifest = Archive::open("plugin.zip")?.read_entry("manifest.json")?;
+    ///     println!("Manifest is {} bytes", manifest.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn read_entry(&self, name: &str) -> Result<Vec<u8>, ArchiveError> {
+        entry::find_entry(&self.location(), self.format, name, |_, reader| {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    /// Streams a single file of the archive into `writer`.
+    ///
+    /// This finds the entry like [`Archive::read_entry`], but copies its
+    /// contents to `writer` as they are decompressed instead of collecting
+    /// them, so large entries can go straight into a hasher, a socket or a
+    /// pipe.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Path of the entry inside the archive
+    /// * `writer` - Destination for the entry's contents
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(u64)` - Number of bytes written
+    /// * `Err(ArchiveError)` - The entry could not be found, read or written
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::read_entry`], or if writing to `writer` fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("logs.tar.zst")?;
+    ///     archive.extract_entry_to("var/log/app.log", std::io::stdout().lock())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_entry_to<W: Write>(
+        &self,
+        name: &str,
+        mut writer: W,
+    ) -> Result<u64, ArchiveError> {
+        entry::find_entry(&self.location(), self.format, name, |_, reader| {
+            Ok(io::copy(reader, &mut writer)?)
+        })
+    }
+
+    /// Opens a single file of the archive as a streaming reader.
+    ///
+    /// This finds the entry like [`Archive::read_entry`] and returns once
+    /// it has been found. Its contents are then decompressed as the reader
+    /// is read, so large entries can be parsed incrementally without being
+    /// held in memory. The reader owns everything it needs and may outlive
+    /// this `Archive`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Path of the entry inside the archive
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EntryReader)` - Reader over the contents of the entry
+    /// * `Err(ArchiveError)` - The entry could not be found
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::read_entry`]. Errors met while decompressing the entry are
+    /// returned by the reader.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::{BufRead, BufReader};
+    ///
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let reader = Archive::open("events.tar.gz")?.entry_reader("events.ndjson")?;
+    ///     for line in BufReader::new(reader).lines() {
+    ///         println!("{}", line?);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn entry_reader(&self, name: &str) -> Result<EntryReader, ArchiveError> {
+        entry::entry_reader(&self.location(), self.format, name)
+    }
+
+    /// Lists the entries of the archive without extracting them.
+    ///
+    /// ZIP and 7z listings are read from the archive's index, so they are
+    /// cheap even for very large archives. TAR-based formats have no index
+    /// and are scanned header by header, decompressing the stream but
+    /// skipping over entry data.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Entries)` - Iterator over the metadata of every entry, in storage order
+    /// * `Err(ArchiveError)` - The archive metadata could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive headers are corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("data.zip")?;
+    ///     for entry in archive.entries()? {
+    ///         println!("{:?} {} {}", entry.kind, entry.size, entry.name);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn entries(&self) -> Result<Entries, ArchiveError> {
+        entry::list_entries(&self.location(), self.format)
+    }
+
+    /// Returns the number of entries in a ZIP archive.
+    ///
+    /// The count is read from the central directory, which also numbers
+    /// the entries for [`Archive::entry_info`] and
+    /// [`Archive::entry_reader_at`]. Their indices run from zero up to the
+    /// count and follow the order of [`Archive::entries`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - Number of entries in the archive
+    /// * `Err(ArchiveError)` - The central directory could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive (`ArchiveError::Unsupported`)
+    /// * The archive file cannot be read
+    /// * The central directory is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("photos.zip")?;
+    ///     println!("{} entries", archive.entry_count()?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn entry_count(&self) -> Result<usize, ArchiveError> {
+        entry::zip_entry_count(&self.location(), self.format)
+    }
+
+    /// Returns the metadata of the entry at `index` in a ZIP archive.
+    ///
+    /// Only the central directory is read, apart from the target of a
+    /// symlink. See [`Archive::entry_count`] for how entries are numbered.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Position of the entry in the central directory
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EntryInfo)` - Metadata of the entry
+    /// * `Err(ArchiveError)` - The entry could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive (`ArchiveError::Unsupported`)
+    /// * There is no entry at `index` (`ArchiveError::NotFound`)
+    /// * The archive file cannot be read
+    /// * The central directory is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("photos.zip")?;
+    ///     let last = archive.entry_info(archive.entry_count()? - 1)?;
+    ///     println!("Last entry is {}", last.name);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn entry_info(&self, index: usize) -> Result<EntryInfo, ArchiveError> {
+        entry::zip_entry_info(&self.location(), self.format, index)
+    }
+
+    /// Returns the comment stored at the end of a ZIP archive.
+    ///
+    /// The comment is returned as it is stored, empty if the archive has
+    /// none. Nothing records its encoding, though it is usually ASCII or
+    /// UTF-8. Only the end of the archive is read.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The bytes of the comment
+    /// * `Err(ArchiveError)` - The comment could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive (`ArchiveError::Unsupported`)
+    /// * The archive file cannot be read
+    /// * The central directory is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let comment = Archive::open("release.zip")?.comment()?;
+    ///     println!("Built from {}", String::from_utf8_lossy(&comment));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn comment(&self) -> Result<Vec<u8>, ArchiveError> {
+        entry::zip_comment(&self.location(), self.format)
+    }
+
+    /// Opens the file at `index` in a ZIP archive as a streaming reader.
+    ///
+    /// This behaves like [`Archive::entry_reader`], but jumps straight to
+    /// the entry through the central directory instead of looking it up by
+    /// name. See [`Archive::entry_count`] for how entries are numbered.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Position of the entry in the central directory
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EntryReader)` - Reader over the contents of the entry
+    /// * `Err(ArchiveError)` - The entry could not be opened
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive (`ArchiveError::Unsupported`)
+    /// * There is no entry at `index` (`ArchiveError::NotFound`)
+    /// * The entry is a directory or symlink
+    /// * The archive file cannot be read or is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("photos.zip")?;
+    ///     let mut first = String::new();
+    ///     archive.entry_reader_at(0)?.read_to_string(&mut first)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn entry_reader_at(&self, index: usize) -> Result<EntryReader, ArchiveError> {
+        entry::zip_entry_reader(&self.location(), self.format, index)
+    }
+
+    /// Removes the entries whose names match one of the given glob patterns
+    /// from a ZIP archive.
+    ///
+    /// The patterns work like those of [`Archive::extract_matching`], so a
+    /// pattern without a `/` is matched against file names at any depth.
+    /// The remaining entries are copied without being recompressed into a
+    /// temporary file next to the archive, which then replaces it; if
+    /// anything fails, the archive is left as it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - Glob patterns selecting the entries to remove
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - Number of entries removed
+    /// * `Err(ArchiveError)` - The archive could not be rewritten
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive, or is a split one
+    /// * The archive file cannot be read or is corrupted
+    /// * The directory holding the archive is not writable
+    /// * The archive was opened with [`Archive::from_reader`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("vendor-sdk.zip")?;
+    ///     let removed = archive.delete_entries(&[".env", "*.pem", "__MACOSX/**"])?;
+    ///     println!("Removed {} entries", removed);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn delete_entries(&self, patterns: &[&str]) -> Result<usize, ArchiveError> {
+        if self.reader.is_some() {
+            return Err(ArchiveError::unsupported_static(
+                "writing to an archive opened from a reader",
+            ));
+        }
+        edit::delete_zip_entries(&self.location(), self.format, &self.path, patterns)
+    }
+
+    /// Writes a copy of a ZIP archive to `dst` without the entries whose
+    /// names match one of the given glob patterns.
+    ///
+    /// This behaves like [`Archive::delete_entries`] but leaves the archive
+    /// itself alone, unless `dst` is the archive. `dst` is always written
+    /// as a ZIP archive, whatever its extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - Path of the copy to create
+    /// * `patterns` - Glob patterns selecting the entries to leave out
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - Number of entries left out
+    /// * `Err(ArchiveError)` - The copy could not be written
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive
+    /// * The archive file cannot be read or is corrupted
+    /// * `dst` cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("third-party.zip")?;
+    ///     archive.delete_entries_to("third-party-clean.zip", &["*.DS_Store", "Thumbs.db"])?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn delete_entries_to<P: AsRef<Path>>(
+        &self,
+        dst: P,
+        patterns: &[&str],
+    ) -> Result<usize, ArchiveError> {
+        edit::delete_zip_entries(&self.location(), self.format, dst.as_ref(), patterns)
+    }
+
+    /// Replaces the content of the named files in the archive.
+    ///
+    /// Each update pairs the name of a file with its new content. Updated
+    /// files keep their permissions and owner and get the current time as
+    /// their modification time. In ZIP archives the other entries are
+    /// copied without being recompressed, and sizes, CRCs and the central
+    /// directory are rewritten to match; TAR-based and 7z archives are
+    /// rewritten in full. The result goes to a temporary file next to the
+    /// archive, which then replaces it; if anything fails, the archive is
+    /// left as it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `updates` - Names of the files to update and their new content
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - Every file was updated
+    /// * `Err(ArchiveError)` - The archive could not be rewritten
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive format cannot be written, or the archive is a split ZIP
+    /// * A name is not in the archive, or names a directory or link
+    /// * The archive file cannot be read or is corrupted
+    /// * The directory holding the archive is not writable
+    /// * The archive was opened with [`Archive::from_reader`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let config = std::fs::read("config/production.toml")?;
+    ///     Archive::open("app-bundle.tar.gz")?
+    ///         .update_entries(&[("app/config.toml", config.as_slice())])?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn update_entries(&self, updates: &[(&str, &[u8])]) -> Result<(), ArchiveError> {
+        if self.reader.is_some() {
+            return Err(ArchiveError::unsupported_static(
+                "writing to an archive opened from a reader",
+            ));
+        }
+        edit::update_entries(&self.location(), self.format, &self.path, updates)
+    }
+
+    /// Writes a copy of the archive to `dst` with the content of the named
+    /// files replaced.
+    ///
+    /// This behaves like [`Archive::update_entries`] but leaves the archive
+    /// itself alone, unless `dst` is the archive. `dst` is always written
+    /// in the archive's format, whatever its extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - Path of the copy to create
+    /// * `updates` - Names of the files to update and their new content
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The copy was written
+    /// * `Err(ArchiveError)` - The copy could not be written
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive format cannot be written
+    /// * A name is not in the archive, or names a directory or link
+    /// * The archive file cannot be read or is corrupted
+    /// * `dst` cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("firmware.zip")?;
+    ///     archive.update_entries_to(
+    ///         "firmware-patched.zip",
+    ///         &[("boot/version.txt", b"2.4.1\n".as_slice())],
+    ///     )?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn update_entries_to<P: AsRef<Path>>(
+        &self,
+        dst: P,
+        updates: &[(&str, &[u8])],
+    ) -> Result<(), ArchiveError> {
+        edit::update_entries(&self.location(), self.format, dst.as_ref(), updates)
+    }
+
+    /// Writes the contents of the archive to `dst` in another format.
+    ///
+    /// The new format is determined by the extension of `dst`, so a ZIP
+    /// archive can be turned into a `.tar.zst` and the other way round.
+    /// Entries are streamed one at a time and keep their paths, types and
+    /// as much of their metadata as the new format can record: TAR keeps
+    /// owners and hard links, while ZIP and 7z keep modification times and
+    /// permissions and store hard links as copies of their targets. Special
+    /// files such as device nodes are left out, since none of the writable
+    /// formats can hold them.
+    ///
     /// # Arguments
     ///
-    /// * `path` - Path to the archive file to open
+    /// * `dst` - Path of the archive to create
     ///
     /// # Returns
     ///
-    /// * `Ok(Archive)` - Successfully opened archive with detected format
-    /// * `Err(ArchiveError)` - Failed to open file or detect format
+    /// * `Ok(Archive)` - The newly written archive
+    /// * `Err(ArchiveError)` - The archive could not be converted
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    /// * The file does not exist or cannot be read
-    /// * The file format cannot be detected or is unsupported
-    /// * There are I/O errors while reading the file
+    /// * The extension of `dst` is not recognized or names a format that
+    ///   cannot be written
+    /// * An entry cannot be represented in the new format, such as a
+    ///   directory in a single-file compressed stream
+    /// * The archive file cannot be read or is corrupted
+    /// * `dst` cannot be written
     ///
     /// # Examples
     ///
@@ -60,84 +1716,175 @@ impl Archive {
     /// use compak::Archive;
     ///
     /// fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let archive = Archive::open("data.zip")?;
-    ///     println!("Opened archive with format: {}", archive.format);
+    ///     let converted = Archive::open("dataset.zip")?.convert_to("dataset.tar.zst")?;
+    ///     println!("Wrote {} archive", converted.format);
     ///     Ok(())
     /// }
     /// ```
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
-        let path = path.as_ref().to_path_buf();
-        let format = format::detect_from_file(&path)?;
-
+    pub fn convert_to<P: AsRef<Path>>(&self, dst: P) -> Result<Archive, ArchiveError> {
+        let path = dst.as_ref().to_path_buf();
+        let format = edit::convert(&self.location(), self.format, &path)?;
         Ok(Archive {
             path,
             format,
+            reader: None,
         })
     }
 
-    /// Creates a new archive instance with format determined by file extension.
+    /// Writes a copy of the archive to `dst` with different compression.
     ///
-    /// This method is typically used when you want to create a new archive.
-    /// The format is determined solely by the file extension of the provided path.
+    /// The container stays the same and only the compression changes.
+    /// TAR archives and single-file streams are decompressed and
+    /// compressed again as one stream, so the copy holds exactly the same
+    /// bytes once decompressed, and its format follows `codec`: a
+    /// `.tar.gz` recompressed with [`Codec::Zstd`] becomes a `.tar.zst`.
+    /// The path of `dst` is used as given, so it should carry the matching
+    /// extension. ZIP and 7z archives are rewritten entry by entry with
+    /// every file compressed again; directories, links and encrypted files
+    /// in ZIP archives are copied as they are, and the archive comment is
+    /// kept. Nothing is buffered in memory, and if anything fails `dst`
+    /// is left as it was.
     ///
     /// # Arguments
     ///
-    /// * `path` - Path where the archive will be created (format determined by extension)
+    /// * `dst` - Path of the copy to create, which may be the archive itself
+    /// * `codec` - Compression method and level of the copy
     ///
     /// # Returns
     ///
-    /// * `Ok(Archive)` - Successfully created archive instance
-    /// * `Err(ArchiveError)` - Format could not be determined from extension
+    /// * `Ok(Archive)` - The newly written archive
+    /// * `Err(ArchiveError)` - The archive could not be recompressed
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    /// * The file extension is not recognized
-    /// * The path has no extension or an unsupported extension
+    /// * The level of `codec` is out of range
+    /// * The archive is not a ZIP, 7z or TAR archive or a single-file stream
+    /// * `codec` can't be used in the format, such as Zstandard in 7z
+    ///   archives or no compression in single-file streams
+    /// * The archive file cannot be read or is corrupted
+    /// * `dst` cannot be written
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use compak::Archive;
+    /// use compak::{Archive, Codec};
     ///
     /// fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let archive = Archive::new("output.tar.gz")?;
-    ///     println!("Created archive for format: {}", archive.format);
+    ///     let archive = Archive::open("artifacts.tar.gz")?;
+    ///     let smaller = archive.recompress("artifacts.tar.zst", Codec::Zstd { level: 19 })?;
+    ///     println!("Wrote {} archive", smaller.format);
     ///     Ok(())
     /// }
     /// ```
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
-        let path = path.as_ref().to_path_buf();
-        let format = format::detect_from_extension(&path)?;
-
+    pub fn recompress<P: AsRef<Path>>(
+        &self,
+        dst: P,
+        codec: Codec,
+    ) -> Result<Archive, ArchiveError> {
+        let path = dst.as_ref().to_path_buf();
+        let format = edit::recompress(&self.location(), self.format, &path, codec)?;
         Ok(Archive {
             path,
             format,
+            reader: None,
         })
     }
 
-    /// Extracts the entire archive to the specified output directory.
+    /// Computes a hash of the archive's logical contents.
     ///
-    /// This method creates the output directory if it doesn't exist and extracts
-    /// all contents of the archive while preserving the directory structure.
+    /// Entries are hashed in canonical (sorted) order with normalized
+    /// metadata: only paths, entry types, file contents and link targets are
+    /// taken into account. Two archives with identical content therefore
+    /// compare equal even if they use different formats, compression levels
+    /// or entry ordering, or carry different timestamps and permissions.
+    /// Hard links count as copies of the files they name.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ContentHash)` - The SHA-256 based content hash
+    /// * `Err(ArchiveError)` - The archive could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let a = Archive::open("release.zip")?.content_hash()?;
+    ///     let b = Archive::open("release.tar.zst")?.content_hash()?;
+    ///     assert_eq!(a, b);
+    ///     println!("Content hash: {}", a);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn content_hash(&self) -> Result<ContentHash, ArchiveError> {
+        hash::content_hash(&self.location(), self.format)
+    }
+
+    /// Computes a digest of every file in the archive without extracting
+    /// it.
+    ///
+    /// Entries are streamed through the hash function one at a time.
+    /// Directories and links are left out, since they have no contents of
+    /// their own. The returned manifest can be printed or written in the
+    /// format `sha256sum -c` and `b3sum -c` read.
     ///
     /// # Arguments
     ///
-    /// * `output_dir` - Directory where the archive contents will be extracted
+    /// * `algorithm` - The hash function to use
     ///
     /// # Returns
     ///
-    /// * `Ok(())` - Extraction completed successfully
-    /// * `Err(ArchiveError)` - Extraction failed
+    /// * `Ok(ChecksumManifest)` - The name and digest of each file
+    /// * `Err(ArchiveError)` - The archive could not be read
     ///
     /// # Errors
     ///
     /// This function will return an error if:
     /// * The archive file cannot be read
-    /// * The output directory cannot be created
-    /// * There are permission issues
     /// * The archive is corrupted
-    /// * The archive format is not yet implemented
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, HashAlgo};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let manifest = Archive::open("release.tar.gz")?.checksums(HashAlgo::Sha256)?;
+    ///     manifest.write_to(std::fs::File::create("SHA256SUMS")?)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn checksums(&self, algorithm: HashAlgo) -> Result<ChecksumManifest, ArchiveError> {
+        hash::checksums(&self.location(), self.format, algorithm)
+    }
+
+    /// Checks that every entry of the archive decodes, without writing
+    /// anything, like `unzip -t` or `7z t`.
+    ///
+    /// Each entry is decoded in full, which checks the CRC-32 of ZIP and 7z
+    /// entries and the checksums of gzip, xz and Zstandard streams. Checking
+    /// goes on past a failed entry wherever the format allows reaching the
+    /// next one.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(VerifyReport)` - Whether each entry passed, and why checking
+    ///   stopped early if it did
+    /// * `Err(ArchiveError)` - The archive could not be read at all
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted before its first entry
     ///
     /// # Examples
     ///
@@ -145,15 +1892,297 @@ impl Archive {
     /// use compak::Archive;
     ///
     /// fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let archive = Archive::open("backup.tar.gz")?;
-    ///     archive.extract_to("./restored_files")?;
-    ///     println!("Archive extracted successfully!");
+    ///     let report = Archive::open("backup.zip")?.verify()?;
+    ///     for entry in report.failures() {
+    ///         println!("{}: {:?}", entry.name, entry.error);
+    ///     }
+    ///     println!("{}", if report.passed() { "OK" } else { "FAILED" });
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn verify(&self) -> Result<VerifyReport, ArchiveError> {
+        verify::verify(&self.location(), self.format)
+    }
+
+    /// Checks the metadata of every entry for signs of an attack or a
+    /// tampered archive, without reading any entry contents.
+    ///
+    /// Entries are flagged for names that repeat an earlier entry's, are
+    /// absolute or contain `..`, for being device nodes or other special
+    /// files, for setuid or setgid bits, and for modification times more
+    /// than a day in the future. An entry may be flagged for several
+    /// issues. [`ExtractOptions::strict`] runs the same checks before
+    /// extracting.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Finding>)` - Every issue found, in archive order, empty if
+    ///   there were none
+    /// * `Err(ArchiveError)` - The archive could not be listed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive metadata is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, Issue};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let findings = Archive::open("upload.tar")?.audit()?;
+    ///     for finding in &findings {
+    ///         println!("{}", finding);
+    ///     }
+    ///     if findings.iter().any(|finding| finding.issue == Issue::PathTraversal) {
+    ///         return Err("archive escapes its directory".into());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn audit(&self) -> Result<Vec<Finding>, ArchiveError> {
+        audit::audit(&self.location(), self.format)
+    }
+
+    /// Totals the entries of the archive from its metadata alone.
+    ///
+    /// Counts entries by type and adds up their sizes without extracting
+    /// anything, reading only the central directory of ZIP archives and the
+    /// headers of TAR archives.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ArchiveStats)` - Entry counts and sizes
+    /// * `Err(ArchiveError)` - The archive metadata could not be read
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive metadata is corrupted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let stats = Archive::open("release.tar.gz")?.stat()?;
+    ///     println!("{} files, {} bytes", stats.files, stats.size);
+    ///     if let Some(ratio) = stats.compression_ratio() {
+    ///         println!("compressed {:.1}:1", ratio);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn stat(&self) -> Result<ArchiveStats, ArchiveError> {
+        stat::stat(&self.location(), self.format)
+    }
+
+    /// Returns whether the archive holds encrypted entries, so that the
+    /// password can be asked for before extraction rather than after it
+    /// fails with [`ArchiveError::InvalidPassword`].
+    ///
+    /// Only the metadata is read, as [`entries`](Self::entries) does; which
+    /// entries are encrypted shows in [`EntryInfo::encrypted`]. 7z archives
+    /// whose headers are encrypted count as encrypted even though they
+    /// can't be listed. Formats without encryption always return `false`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the archive cannot be opened
+    /// or its metadata cannot be decoded.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("secret.7z")?;
+    ///     let mut options = ExtractOptions::new();
+    ///     if archive.is_encrypted()? {
+    ///         let mut password = String::new();
+    ///         std::io::stdin().read_line(&mut password)?;
+    ///         options = options.password(password.trim_end());
+    ///     }
+    ///     archive.extract_to_with("out", &options)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn is_encrypted(&self) -> Result<bool, ArchiveError> {
+        entry::is_encrypted(&self.location(), self.format)
+    }
+
+    /// Extracts every entry of the archive into `sink` instead of a
+    /// directory.
+    ///
+    /// Entries are selected, renamed and checked against limits as
+    /// [`Archive::extract_to_with`] does, and `sink` decides how to store
+    /// them, so archives can be extracted into object storage, a database or
+    /// a test harness. The overwrite and CRC mismatch policies, sparse files,
+    /// threads and the other options that concern files on disk are left to
+    /// the sink; [`FsSink`](crate::FsSink) writes to the local filesystem.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - Where the entries go
+    /// * `options` - Extraction options
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ExtractReport)` - Extraction completed; the report counts what
+    ///   was handed to the sink
+    /// * `Err(ArchiveError)` - Extraction failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read
+    /// * The archive is corrupted
+    /// * An entry would be written outside the sink's root under
+    ///   [`UnsafePathPolicy::Error`](crate::UnsafePathPolicy::Error)
+    /// * A configured [`ExtractLimits`] limit is
+    ///   exceeded
+    /// * The sink fails
+    /// * The operation is cancelled
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions, FsSink};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut sink = FsSink::new("./site")?;
+    ///     let options = ExtractOptions::new().strip_components(1);
+    ///     let report = Archive::open("site.zip")?.extract_to_sink(&mut sink, &options)?;
+    ///     println!("{} files", report.files);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_to_sink(
+        &self,
+        sink: &mut dyn ExtractSink,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, ArchiveError> {
+        extract::sink::extract(&self.location(), self.format, sink, options)
+    }
+
+    /// Extracts whatever entries of a damaged ZIP archive are intact, such
+    /// as one whose download was cut short.
+    ///
+    /// The central directory that ZIP archives are normally read through is
+    /// ignored, and the archive is scanned for the header preceding each
+    /// entry instead, so entries are found even when the end of the archive
+    /// is missing. Each entry is checked against its CRC-32 before being
+    /// extracted, and damaged ones are left out. As the central directory
+    /// alone records permissions and symbolic links, every entry is
+    /// extracted as a plain file or directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the intact entries will be extracted
+    /// * `options` - Extraction options
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(SalvageReport)` - Every entry found and whether it was
+    ///   recovered, along with the report of the extraction
+    /// * `Err(ArchiveError)` - The archive could not be read, or an intact
+    ///   entry could not be extracted
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive
+    /// * The archive file cannot be read
+    /// * An intact entry cannot be extracted, under the same conditions as
+    ///   [`Archive::extract_to_with`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("partial-download.zip")?;
+    ///     let report = archive.salvage_to("./recovered", &ExtractOptions::new())?;
+    ///     println!("{} entries recovered", report.recovered().count());
+    ///     for entry in report.damaged() {
+    ///         println!("{}: {:?}", entry.name, entry.error);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn salvage_to<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> Result<SalvageReport, ArchiveError> {
+        if self.format != ArchiveFormat::Zip {
+            return Err(ArchiveError::unsupported_static(
+                "salvaging archives other than ZIP",
+            ));
+        }
+        salvage::salvage(&self.location(), output_dir.as_ref(), options, &mut |_| {})
+    }
+
+    /// Rewrites the archive into `dst` in a reproducible, canonical form.
+    ///
+    /// Entries are sorted by name, every timestamp is replaced with the one
+    /// from `options`, ownership is reset to `0:0` and permissions are
+    /// flattened to a consistent set of modes. Running this on two archives
+    /// with the same content produces byte-identical output.
+    ///
+    /// The destination format is determined by the extension of `dst`, so
+    /// this can also be used to convert between formats. All entries are
+    /// held in memory while sorting.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - Path of the normalized archive to create
+    /// * `options` - Timestamps and permissions to apply
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The normalized archive was written
+    /// * `Err(ArchiveError)` - Reading or writing failed
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive file cannot be read or is corrupted
+    /// * The destination extension is not recognized
+    /// * An entry type cannot be represented in the destination format
+    /// * The destination file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, NormalizeOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open("build-output.tar.gz")?;
+    ///     archive.normalize("reproducible.tar.gz", &NormalizeOptions::default())?;
     ///     Ok(())
     /// }
     /// ```
-    pub fn extract_to<P: AsRef<Path>>(&self, output_dir: P) -> Result<(), ArchiveError> {
-        let output_dir = output_dir.as_ref();
-        extract_archive_with_format(self.path.as_ref(), output_dir, self.format)
+    pub fn normalize<P: AsRef<Path>>(
+        &self,
+        dst: P,
+        options: &NormalizeOptions,
+    ) -> Result<(), ArchiveError> {
+        normalize::normalize(&self.location(), self.format, dst.as_ref(), options)
+    }
+
+    /// Returns where the archive is read from.
+    pub(crate) fn location(&self) -> Location {
+        match &self.reader {
+            Some(reader) => Location::Reader(reader.clone()),
+            None => Location::Path(self.path.clone()),
+        }
     }
 }
 
@@ -169,7 +2198,8 @@ impl Archive {
 ///
 /// # Returns
 ///
-/// * `Ok(())` - Archive extracted successfully
+/// * `Ok(ExtractReport)` - Archive extracted successfully; the report
+///   counts what was written
 /// * `Err(ArchiveError)` - Failed to open or extract the archive
 ///
 /// # Errors
@@ -191,180 +2221,129 @@ impl Archive {
 ///     Ok(())
 /// }
 /// ```
-pub fn extract_archive<P: AsRef<Path>>(archive_path: P, output_dir: P) -> Result<(), ArchiveError> {
+pub fn extract_archive<P: AsRef<Path>>(
+    archive_path: P,
+    output_dir: P,
+) -> Result<ExtractReport, ArchiveError> {
     let archive = Archive::open(archive_path)?;
     archive.extract_to(output_dir)
 }
 
-/// Internal function that extracts the contents of an archive file to a directory.
+/// Extracts a stack of container image layers into a single root
+/// filesystem.
 ///
-/// This function handles the actual extraction logic for different archive formats.
-/// It creates the output directory if it doesn't exist and delegates to format-specific
-/// extraction functions.
+/// `layers` are tarballs, uncompressed or compressed with gzip, xz, bzip2
+/// or Zstandard, listed bottom layer first. Whiteout files are applied as
+/// described for [`Archive::extract_image`].
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the archive file to be extracted
-/// * `output_dir` - Path where contents should be extracted
-/// * `format` - The detected archive format to use for extraction
-///
-/// # Returns
-///
-/// * `Ok(())` - Extraction was successful
-/// * `Err(ArchiveError)` - An error occurred during extraction
+/// * `layers` - Paths of the layer tarballs, bottom layer first
+/// * `output_dir` - Directory where the root filesystem will be extracted
+/// * `options` - Options applied to every layer
 ///
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The output directory cannot be created
-/// * The archive format is not yet implemented
-/// * Format-specific extraction fails
-fn extract_archive_with_format<P: AsRef<Path>>(
-    path: P,
-    output_dir: P,
-    format: ArchiveFormat,
-) -> Result<(), ArchiveError> {
-    let path = path.as_ref();
-    let output_dir = output_dir.as_ref();
-
-    // Ensure output directory exists
-    if !output_dir.exists() {
-        std::fs::create_dir_all(output_dir)?;
-    }
-
-    match format {
-        ArchiveFormat::Zip => extract_zip(path, output_dir),
-        ArchiveFormat::TarGz => extract_tar(path, output_dir, flate2::read::GzDecoder::new),
-        ArchiveFormat::TarXz => extract_tar(path, output_dir, liblzma::read::XzDecoder::new),
-        ArchiveFormat::TarBz2 => extract_tar(path, output_dir, bzip2::read::BzDecoder::new),
-        ArchiveFormat::TarZst => {
-            extract_tar(path, output_dir, |f| {
-                zstd::stream::read::Decoder::new(f).unwrap()
-            })
-        }
-        ArchiveFormat::Tar => extract_tar(path, output_dir, |f| f),
-        ArchiveFormat::SevenZ => extract_7z(path, output_dir),
-    }
-}
-/// Generic function for extracting TAR-based archives with different compression formats.
-///
-/// This function handles the common extraction logic for all TAR-based formats by
-/// accepting a decompression function that converts the compressed stream to a
-/// readable stream. This allows the same TAR extraction logic to work with
-/// GZIP, XZ, BZIP2, and Zstandard compression.
-///
-/// # Type Parameters
-///
-/// * `F` - Function type that creates a decompressor from a file
-/// * `R` - Reader type returned by the decompression function
-///
-/// # Arguments
-///
-/// * `path` - Path to the compressed TAR archive file
-/// * `output_dir` - Path where contents should be extracted
-/// * `decompress` - Function that takes a file and returns a decompressed reader
+/// * A layer cannot be opened or is not a tarball
+/// * Extracting a layer fails, under the same conditions as
+///   [`Archive::extract_to_with`]
 ///
-/// # Returns
-///
-/// * `Ok(())` - Extraction was successful
-/// * `Err(ArchiveError)` - An error occurred during extraction
+/// # Examples
 ///
-/// # Errors
+/// ```no_run
+/// use compak::{extract_layers, ExtractOptions};
 ///
-/// This function will return an error if:
-/// * The archive file cannot be opened
-/// * The decompression fails
-/// * The TAR extraction fails
-/// * There are I/O errors during extraction
-fn extract_tar<F, R>(path: &Path, output_dir: &Path, decode: F) -> Result<(), ArchiveError>
-where
-    F: FnOnce(std::fs::File) -> R + Send + 'static,
-    R: Read + Send + 'static,
-{
-    let path = path.to_path_buf();
-    let output_dir = output_dir.to_path_buf();
-
-    let file = std::fs::File::open(&path)?;
-    let reader = decode(file);
-    let mut archive = tar::Archive::new(reader);
-    archive.unpack(&output_dir)?;
-
-    Ok(())
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let layers = ["base.tar.gz", "app.tar.zst"];
+///     extract_layers(&layers, "./rootfs", &ExtractOptions::new())?;
+///     Ok(())
+/// }
+/// ```
+pub fn extract_layers<P: AsRef<Path>, Q: AsRef<Path>>(
+    layers: &[P],
+    output_dir: Q,
+    options: &ExtractOptions,
+) -> Result<ExtractReport, ArchiveError> {
+    oci::extract_layers(layers, output_dir.as_ref(), options, &mut |_| {})
 }
 
-/// Extracts a ZIP archive to the specified output directory.
+/// Convenience function to create an archive from a directory in a single call.
 ///
-/// This function handles ZIP-specific extraction, including proper handling
-/// of directories and file paths. It creates necessary parent directories
-/// and extracts all files while preserving the archive's directory structure.
+/// The archive format is determined by the extension of `archive_path`.
+/// This is equivalent to `Archive::new(archive_path)?.create_from_dir(src_dir)`.
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the ZIP archive file
-/// * `output_dir` - Directory where the contents should be extracted
+/// * `archive_path` - Path of the archive to create
+/// * `src_dir` - Directory whose contents should be packed
 ///
 /// # Returns
 ///
-/// * `Ok(())` - Extraction was successful
-/// * `Err(ArchiveError)` - An error occurred during extraction
+/// * `Ok(Archive)` - The newly written archive
+/// * `Err(ArchiveError)` - The format is unknown or packing failed
 ///
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The ZIP file cannot be opened or is corrupted
-/// * There are permission issues creating directories or files
-/// * There are I/O errors during file extraction
-/// * The ZIP contains invalid file paths
-fn extract_zip(path: &Path, output_dir: &Path) -> Result<(), ArchiveError> {
-    let path = path.to_path_buf();
-    let output_dir = output_dir.to_path_buf();
-
-    let file = std::fs::File::open(&path)?;
-    let mut archive = zip::ZipArchive::new(file)?;
-
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let out_path = output_dir.join(file.name());
-
-        if file.name().ends_with('/') {
-            std::fs::create_dir_all(&out_path)?;
-        } else {
-            if let Some(p) = out_path.parent()
-                && !p.exists()
-            {
-                std::fs::create_dir_all(p)?;
-            }
-            let mut out_file = std::fs::File::create(&out_path)?;
-            io::copy(&mut file, &mut out_file)?;
-        }
-    }
-    Ok(())
+/// * The archive extension is not recognized
+/// * The source directory cannot be read
+/// * The archive file cannot be written
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::create_archive;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let archive = create_archive("site.zip", "./public")?;
+///     println!("Created {} archive", archive.format);
+///     Ok(())
+/// }
+/// ```
+pub fn create_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+    archive_path: P,
+    src_dir: Q,
+) -> Result<Archive, ArchiveError> {
+    let archive = Archive::new(archive_path)?;
+    archive.create_from_dir(src_dir)?;
+    Ok(archive)
 }
 
-/// Extracts a 7-Zip archive to the specified output directory.
+/// Convenience function to convert an archive to another format in a single
+/// call.
 ///
-/// This function handles 7-Zip-specific extraction
+/// The format of `src` is detected from its contents and that of `dst` from
+/// its extension. This is equivalent to
+/// `Archive::open(src)?.convert_to(dst)`.
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the 7-Zip archive file
-/// * `output_dir` - Directory where the contents should be extracted
+/// * `src` - Path of the archive to convert
+/// * `dst` - Path of the archive to create
 ///
 /// # Returns
 ///
-/// * `Ok(())` - Extraction was successful
-/// * `Err(ArchiveError)` - An error occurred during extraction
+/// * `Ok(Archive)` - The newly written archive
+/// * `Err(ArchiveError)` - The archive could not be converted
 ///
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The 7-Zip file cannot be opened or read
-/// * There are permission issues creating directories or files
-/// * There are I/O errors during file extraction
-/// * The 7-Zip contains invalid file paths
-fn extract_7z(path: &Path, output_dir: &Path) -> Result<(), ArchiveError> {
-    let path = path.to_path_buf();
-    let output_dir = output_dir.to_path_buf();
-
-    Ok(sevenz_rust2::decompress_file(path, output_dir)?)
+/// * `src` cannot be opened or its format cannot be detected
+/// * The conversion fails, under the same conditions as
+///   [`Archive::convert_to`]
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::convert;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     convert("build-artifacts.zip", "build-artifacts.tar.zst")?;
+///     Ok(())
+/// }
+/// ```
+pub fn convert<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<Archive, ArchiveError> {
+    Archive::open(src)?.convert_to(dst)
 }
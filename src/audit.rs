@@ -0,0 +1,150 @@
+//! Checking archive metadata for entries that are commonly used in attacks
+//! or point to a tampered archive.
+//!
+//! Nothing here reads entry contents: every check is made on the metadata
+//! alone, so auditing costs no more than listing the archive.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    volume::Location,
+};
+
+/// How far past the current time a modification time may lie before it
+/// counts as [`Issue::FutureTimestamp`], allowing for clock skew between
+/// the machine that wrote the archive and the one reading it.
+const FUTURE_TOLERANCE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Mode bits that make an executable run with the privileges of its owner
+/// or group.
+const SETID_BITS: u32 = 0o6000;
+
+/// What is suspicious about an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Issue {
+    /// An earlier entry has the same name, so one would silently replace
+    /// the other on extraction.
+    DuplicateName,
+    /// The name starts with `/`, `\` or a drive prefix such as `C:`.
+    AbsolutePath,
+    /// The name has a `..` component.
+    PathTraversal,
+    /// The entry is a device node, FIFO or other special file.
+    SpecialFile,
+    /// The setuid or setgid bit is set.
+    Setuid,
+    /// The modification time lies more than a day in the future.
+    FutureTimestamp,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Issue::DuplicateName => "duplicate name",
+            Issue::AbsolutePath => "absolute path",
+            Issue::PathTraversal => "path traversal",
+            Issue::SpecialFile => "special file",
+            Issue::Setuid => "setuid or setgid bit set",
+            Issue::FutureTimestamp => "modification time in the future",
+        };
+        f.write_str(description)
+    }
+}
+
+/// A suspicious entry found by [`Archive::audit`](crate::Archive::audit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Name of the entry as stored in the archive.
+    pub name: String,
+    /// What is suspicious about it.
+    pub issue: Issue,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.issue)
+    }
+}
+
+/// Checks entries one at a time, remembering the names seen so far.
+pub(crate) struct Auditor {
+    names: HashSet<String>,
+    horizon: SystemTime,
+}
+
+impl Auditor {
+    pub(crate) fn new() -> Self {
+        Self {
+            names: HashSet::new(),
+            horizon: SystemTime::now() + FUTURE_TOLERANCE,
+        }
+    }
+
+    /// Forgets the names seen so far, for archives made of layers that
+    /// are expected to replace each other's entries.
+    pub(crate) fn forget_names(&mut self) {
+        self.names.clear();
+    }
+
+    /// Appends whatever is suspicious about `info` to `findings`.
+    pub(crate) fn check(&mut self, info: &EntryInfo, findings: &mut Vec<Finding>) {
+        let mut flag = |issue| {
+            findings.push(Finding {
+                name: info.name.clone(),
+                issue,
+            })
+        };
+        if !self.names.insert(entry::normalize_name(&info.name)) {
+            flag(Issue::DuplicateName);
+        }
+        if is_absolute(&info.name) {
+            flag(Issue::AbsolutePath);
+        }
+        if info.name.split(['/', '\\']).any(|segment| segment == "..") {
+            flag(Issue::PathTraversal);
+        }
+        if info.kind == EntryKind::Other {
+            flag(Issue::SpecialFile);
+        }
+        if info.mode.is_some_and(|mode| mode & SETID_BITS != 0) {
+            flag(Issue::Setuid);
+        }
+        if info.mtime.is_some_and(|mtime| mtime > self.horizon) {
+            flag(Issue::FutureTimestamp);
+        }
+    }
+}
+
+/// Returns `true` if `name` starts with a separator or a drive prefix.
+fn is_absolute(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    name.starts_with(['/', '\\'])
+        || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+/// Checks the metadata of every entry of the archive at `location`,
+/// returning the findings in archive order.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// its metadata cannot be decoded.
+pub(crate) fn audit(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<Vec<Finding>, ArchiveError> {
+    let mut auditor = Auditor::new();
+    let mut findings = Vec::new();
+    for info in entry::list_entries(location, format)? {
+        auditor.check(&info, &mut findings);
+    }
+    Ok(findings)
+}
@@ -0,0 +1,595 @@
+//! The interface between the archive handling shared by every format and
+//! the code reading and writing each one.
+//!
+//! A [`FormatBackend`] knows how to recognize one format, walk the entries
+//! of an archive in it and, if the format can be written, how to start a
+//! new archive. Extraction, listing, verification and the other operations
+//! of [`Archive`](crate::Archive) are built on top of it, so they work the
+//! same for every format.
+//!
+//! Besides the built-in backends, formats of other crates can be added at
+//! runtime with [`register_format`], after which detection and
+//! [`Archive::open`](crate::Archive::open) recognize them like any other.
+
+use std::{
+    io::{Read, Seek, Write},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use crate::{
+    appimage, ar, cfb,
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::{self, ArchiveFormat, CustomFormat},
+    iso,
+    password::{Passwords, SecretString},
+    squashfs, wim,
+    writer::{CompressionOptions, FormatWriter},
+    xar,
+};
+
+/// A seekable reader over a whole archive.
+pub trait ArchiveRead: Read + Seek {}
+
+impl<T: Read + Seek> ArchiveRead for T {}
+
+/// A seekable writer receiving a new archive.
+pub trait ArchiveWrite: Write + Seek {}
+
+impl<T: Write + Seek> ArchiveWrite for T {}
+
+/// The callback a backend hands each entry to, with a reader over its
+/// contents. It returns `false` to stop the walk early.
+pub type Visit<'a> = dyn FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError> + 'a;
+
+/// Reading and writing of one archive format.
+///
+/// Every built-in format has a backend, and formats of other crates are
+/// added by implementing this trait and passing the implementation to
+/// [`register_format`].
+///
+/// Only [`detect`](FormatBackend::detect) and
+/// [`extract`](FormatBackend::extract) must be implemented: listing falls
+/// back to walking every entry, and formats that can't be written keep the
+/// default [`create`](FormatBackend::create), which fails.
+pub trait FormatBackend: Send + Sync {
+    /// Returns `true` if `data`, the start of a file, carries the magic
+    /// number of the format.
+    fn detect(&self, data: &[u8]) -> bool;
+
+    /// Hands every entry of the archive in `reader` to `unpack`, in storage
+    /// order, until it returns `false`.
+    ///
+    /// `password` decrypts encrypted entries, for formats that have them.
+    /// Symbolic links are expected to carry their target in
+    /// [`EntryInfo::link_target`]; the reader passed for them is not read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the archive cannot be
+    /// decoded, or if `unpack` returns an error.
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        password: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError>;
+
+    /// Reads the metadata of every entry of the archive in `reader`, in
+    /// storage order.
+    ///
+    /// The default implementation walks the entries with
+    /// [`extract`](FormatBackend::extract) without reading their contents;
+    /// formats with an index can read it instead.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the archive cannot be
+    /// decoded.
+    fn list(&self, reader: &mut dyn ArchiveRead) -> Result<Vec<EntryInfo>, ArchiveError> {
+        let mut entries = Vec::new();
+        self.extract(reader, None, &mut |info, _| {
+            entries.push(info.clone());
+            Ok(true)
+        })?;
+        Ok(entries)
+    }
+
+    /// Starts a new archive on top of `writer`, compressed at the levels
+    /// set in `options` where the format compresses.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the format can't be written,
+    /// which is what the default implementation does, or if the archive
+    /// cannot be started.
+    fn create<'a>(
+        &self,
+        writer: Box<dyn ArchiveWrite + 'a>,
+        options: &CompressionOptions,
+    ) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+        let _ = (writer, options);
+        Err(ArchiveError::unsupported_static(
+            "creating archives in this format",
+        ))
+    }
+}
+
+/// An archive being written by a [`FormatBackend`], one entry at a time.
+pub trait EntryWriter {
+    /// Appends an entry.
+    ///
+    /// For files `data` yields exactly [`EntryInfo::size`] bytes; for
+    /// other entries it is not read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry cannot be
+    /// represented in the format, or if reading `data` or writing the
+    /// archive fails.
+    fn add(&mut self, info: &EntryInfo, data: &mut dyn Read) -> Result<(), ArchiveError>;
+
+    /// Completes the archive and flushes it to its writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the archive cannot be written.
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError>;
+}
+
+impl<W: Write + Seek> EntryWriter for FormatWriter<W> {
+    fn add(&mut self, info: &EntryInfo, data: &mut dyn Read) -> Result<(), ArchiveError> {
+        FormatWriter::add(self, info, data)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError> {
+        FormatWriter::finish(*self)?.flush()?;
+        Ok(())
+    }
+}
+
+/// A format added with [`register_format`].
+struct Registered {
+    format: CustomFormat,
+    /// The extension [`ArchiveFormat::extension`] returns.
+    extension: &'static str,
+    /// Extensions in lowercase, with a leading dot.
+    extensions: Vec<String>,
+    backend: Arc<dyn FormatBackend>,
+}
+
+/// The formats added with [`register_format`], in the order they were
+/// added.
+static REGISTRY: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+/// Adds a format read by `backend`, and returns it.
+///
+/// From then on, [`detect_from_file`](crate::detect_from_file) and the
+/// other detection functions, and with them
+/// [`Archive::open`](crate::Archive::open), recognize files for which
+/// [`FormatBackend::detect`] returns `true` or, failing any magic number,
+/// whose name ends in one of `extensions`. Registered formats are tried
+/// before the built-in ones, in the order they were added, so they can
+/// claim files that would otherwise be taken for a built-in format, such
+/// as game archives that are ZIP files under another name.
+///
+/// Registering a name again replaces the backend and extensions it was
+/// registered with.
+///
+/// # Arguments
+///
+/// * `name` - Name of the format, as displayed for [`ArchiveFormat`]
+/// * `extensions` - File extensions of the format, without the leading
+///   dot; the first is the one [`ArchiveFormat::extension`] returns
+/// * `backend` - The backend reading the format
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use compak::{ArchiveError, ArchiveFormat, ArchiveRead, FormatBackend, Visit};
+///
+/// /// Archives that store nothing but a magic number.
+/// struct Empty;
+///
+/// impl FormatBackend for Empty {
+///     fn detect(&self, data: &[u8]) -> bool {
+///         data.starts_with(b"EMPTYPAK")
+///     }
+///
+///     fn extract(
+///         &self,
+///         _: &mut dyn ArchiveRead,
+///         _: Option<&str>,
+///         _: &mut Visit<'_>,
+///     ) -> Result<(), ArchiveError> {
+///         Ok(())
+///     }
+/// }
+///
+/// let format = compak::register_format("EMPTYPAK", &["epak"], Empty);
+/// assert_eq!(format.extension(), "epak");
+/// assert_eq!(compak::detect_from_bytes(b"EMPTYPAK"), Some(format));
+/// ```
+pub fn register_format<B: FormatBackend + 'static>(
+    name: &'static str,
+    extensions: &[&'static str],
+    backend: B,
+) -> ArchiveFormat {
+    let format = CustomFormat {
+        name,
+    };
+    let registered = Registered {
+        format,
+        extension: extensions.first().copied().unwrap_or(name),
+        extensions: extensions
+            .iter()
+            .map(|extension| format!(".{}", extension.to_lowercase()))
+            .collect(),
+        backend: Arc::new(backend),
+    };
+    let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+    match registry.iter_mut().find(|other| other.format.name == name) {
+        Some(other) => *other = registered,
+        None => registry.push(registered),
+    }
+    ArchiveFormat::Custom(format)
+}
+
+/// Returns the formats added with [`register_format`], in the order they
+/// were added.
+pub fn registered_formats() -> Vec<ArchiveFormat> {
+    registry()
+        .iter()
+        .map(|registered| ArchiveFormat::Custom(registered.format))
+        .collect()
+}
+
+fn registry() -> std::sync::RwLockReadGuard<'static, Vec<Registered>> {
+    REGISTRY.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the registration of `format`.
+fn find(registry: &[Registered], format: CustomFormat) -> &Registered {
+    registry
+        .iter()
+        .find(|registered| registered.format == format)
+        .expect("custom formats are only made by registering them")
+}
+
+/// Returns the main extension `format` was registered with.
+pub(crate) fn registered_extension(format: CustomFormat) -> &'static str {
+    find(&registry(), format).extension
+}
+
+/// Returns the first registered format whose backend recognizes `data`.
+pub(crate) fn detect_registered(data: &[u8]) -> Option<ArchiveFormat> {
+    registry()
+        .iter()
+        .find(|registered| registered.backend.detect(data))
+        .map(|registered| ArchiveFormat::Custom(registered.format))
+}
+
+/// Returns the format registered under `name`.
+#[cfg(feature = "serde")]
+pub(crate) fn registered_by_name(name: &str) -> Option<ArchiveFormat> {
+    registry()
+        .iter()
+        .find(|registered| registered.format.name == name)
+        .map(|registered| ArchiveFormat::Custom(registered.format))
+}
+
+/// Returns the first registered format with an extension ending `name`,
+/// which must be in lowercase.
+pub(crate) fn registered_by_extension(name: &str) -> Option<ArchiveFormat> {
+    registry()
+        .iter()
+        .find(|registered| {
+            registered
+                .extensions
+                .iter()
+                .any(|extension| name.ends_with(extension.as_str()))
+        })
+        .map(|registered| ArchiveFormat::Custom(registered.format))
+}
+
+/// Returns the backend of `format`.
+pub(crate) fn for_format(format: ArchiveFormat) -> Arc<dyn FormatBackend> {
+    match format {
+        ArchiveFormat::Custom(custom) => Arc::clone(&find(&registry(), custom).backend),
+        ArchiveFormat::Zip => Arc::new(Zip),
+        ArchiveFormat::SevenZ => Arc::new(SevenZ),
+        ArchiveFormat::Rar => Arc::new(Rar),
+        ArchiveFormat::Ar | ArchiveFormat::Deb => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, format, visit| ar::walk(reader, format, visit),
+            })
+        }
+        ArchiveFormat::Iso => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| iso::walk(reader, visit),
+            })
+        }
+        ArchiveFormat::Squashfs => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| squashfs::walk(reader, visit),
+            })
+        }
+        ArchiveFormat::Wim => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| wim::walk(reader, None, visit),
+            })
+        }
+        ArchiveFormat::Xar => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| xar::walk(reader, visit),
+            })
+        }
+        ArchiveFormat::Msi => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| cfb::walk(reader, false, visit),
+            })
+        }
+        ArchiveFormat::AppImage => {
+            Arc::new(Walker {
+                format,
+                walk: |reader, _, visit| appimage::walk(reader, visit),
+            })
+        }
+        ArchiveFormat::Gzip
+        | ArchiveFormat::Xz
+        | ArchiveFormat::Bzip2
+        | ArchiveFormat::Zstd
+        | ArchiveFormat::Lz4
+        | ArchiveFormat::Brotli
+        | ArchiveFormat::Lzip
+        | ArchiveFormat::Lzma
+        | ArchiveFormat::Compress => Arc::new(Stream(format)),
+        ArchiveFormat::Tar
+        | ArchiveFormat::TarGz
+        | ArchiveFormat::TarXz
+        | ArchiveFormat::TarBz2
+        | ArchiveFormat::TarZst
+        | ArchiveFormat::TarLz4
+        | ArchiveFormat::TarBr
+        | ArchiveFormat::TarLz
+        | ArchiveFormat::TarLzma
+        | ArchiveFormat::TarZ => Arc::new(Tar(format)),
+    }
+}
+
+/// Returns `true` if the magic number at the start of `data` is that of
+/// `format`.
+///
+/// Compressed tarballs and single compressed files share their magic
+/// number, which matches both.
+fn has_signature(format: ArchiveFormat, data: &[u8]) -> bool {
+    format::detect_signature(data)
+        .is_some_and(|found| found == format || found.without_tar() == Some(format))
+}
+
+struct Zip;
+
+impl FormatBackend for Zip {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(ArchiveFormat::Zip, data)
+    }
+
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        password: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        entry::walk_zip(
+            reader,
+            &Passwords::new(password.map(SecretString::from), &[], None),
+            unpack,
+        )
+    }
+
+    /// Reads the central directory, decompressing only the targets of
+    /// symbolic links.
+    fn list(&self, reader: &mut dyn ArchiveRead) -> Result<Vec<EntryInfo>, ArchiveError> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let mut entries = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let mut info = entry::zip_info(&archive.by_index_raw(i)?);
+            if info.kind == EntryKind::Symlink
+                && let Ok(mut file) = archive.by_index(i)
+            {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                info.link_target = Some(target);
+            }
+            entries.push(info);
+        }
+        Ok(entries)
+    }
+
+    fn create<'a>(
+        &self,
+        writer: Box<dyn ArchiveWrite + 'a>,
+        options: &CompressionOptions,
+    ) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+        create(ArchiveFormat::Zip, writer, options)
+    }
+}
+
+struct SevenZ;
+
+impl FormatBackend for SevenZ {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(ArchiveFormat::SevenZ, data)
+    }
+
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        password: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        entry::walk_7z(
+            reader,
+            &Passwords::new(password.map(SecretString::from), &[], None),
+            unpack,
+        )
+    }
+
+    /// Reads the archive header, which records every entry up front.
+    /// Symbolic link targets are stored as entry data, so they are not
+    /// resolved.
+    fn list(&self, reader: &mut dyn ArchiveRead) -> Result<Vec<EntryInfo>, ArchiveError> {
+        let archive =
+            sevenz_rust2::Archive::read(&mut &mut *reader, &sevenz_rust2::Password::empty())?;
+        Ok(archive
+            .files
+            .iter()
+            .zip(&archive.stream_map.file_block_index)
+            .map(|(file, block)| {
+                let mut info = entry::sevenz_info(file);
+                if !archive.is_solid {
+                    info.compressed_size = Some(file.compressed_size);
+                }
+                info.encrypted =
+                    block.is_some_and(|block| entry::sevenz_encrypted(&archive.blocks[block]));
+                info
+            })
+            .collect())
+    }
+
+    fn create<'a>(
+        &self,
+        writer: Box<dyn ArchiveWrite + 'a>,
+        options: &CompressionOptions,
+    ) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+        create(ArchiveFormat::SevenZ, writer, options)
+    }
+}
+
+struct Rar;
+
+impl FormatBackend for Rar {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(ArchiveFormat::Rar, data)
+    }
+
+    #[cfg(feature = "rar")]
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        password: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        crate::rar::walk(
+            reader,
+            &Passwords::new(password.map(SecretString::from), &[], None),
+            unpack,
+        )
+    }
+
+    #[cfg(not(feature = "rar"))]
+    fn extract(
+        &self,
+        _: &mut dyn ArchiveRead,
+        _: Option<&str>,
+        _: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        Err(ArchiveError::unsupported_static("reading RAR archives"))
+    }
+
+    #[cfg(feature = "rar")]
+    fn list(&self, reader: &mut dyn ArchiveRead) -> Result<Vec<EntryInfo>, ArchiveError> {
+        crate::rar::list(reader)
+    }
+}
+
+/// A TAR archive, either plain or inside a compressed stream.
+struct Tar(ArchiveFormat);
+
+impl FormatBackend for Tar {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(self.0, data)
+    }
+
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        _: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        entry::walk_tar(entry::decoder(self.0, reader)?, unpack)
+    }
+
+    fn create<'a>(
+        &self,
+        writer: Box<dyn ArchiveWrite + 'a>,
+        options: &CompressionOptions,
+    ) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+        create(self.0, writer, options)
+    }
+}
+
+/// A single compressed file, presented as an archive with one entry.
+struct Stream(ArchiveFormat);
+
+impl FormatBackend for Stream {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(self.0, data)
+    }
+
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        _: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        entry::walk_single(reader, self.0, entry::STREAM_ENTRY_NAME, unpack)
+    }
+
+    fn create<'a>(
+        &self,
+        writer: Box<dyn ArchiveWrite + 'a>,
+        options: &CompressionOptions,
+    ) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+        create(self.0, writer, options)
+    }
+}
+
+/// A read-only format whose entries are walked by `walk`.
+struct Walker {
+    format: ArchiveFormat,
+    walk: fn(&mut dyn ArchiveRead, ArchiveFormat, &mut Visit<'_>) -> Result<(), ArchiveError>,
+}
+
+impl FormatBackend for Walker {
+    fn detect(&self, data: &[u8]) -> bool {
+        has_signature(self.format, data)
+    }
+
+    fn extract(
+        &self,
+        reader: &mut dyn ArchiveRead,
+        _: Option<&str>,
+        unpack: &mut Visit<'_>,
+    ) -> Result<(), ArchiveError> {
+        (self.walk)(reader, self.format, unpack)
+    }
+}
+
+fn create<'a>(
+    format: ArchiveFormat,
+    writer: Box<dyn ArchiveWrite + 'a>,
+    options: &CompressionOptions,
+) -> Result<Box<dyn EntryWriter + 'a>, ArchiveError> {
+    Ok(Box::new(FormatWriter::with_options(
+        format, writer, options,
+    )?))
+}
@@ -0,0 +1,255 @@
+//! The BLAKE3 hash function, for manifests of entry digests.
+//!
+//! A straightforward port of the portable reference implementation: the
+//! input is split into 1 KiB chunks, each chunk is compressed in 64-byte
+//! blocks, and the chaining values of the chunks are merged pairwise into
+//! a binary tree whose root gives the digest. Only the default unkeyed
+//! mode with 32-byte output is supported.
+
+/// Size of the digest.
+pub(crate) const OUT_LEN: usize = 32;
+/// Size of the blocks the compression function takes.
+const BLOCK_LEN: usize = 64;
+/// Size of the chunks forming the leaves of the tree.
+const CHUNK_LEN: usize = 1024;
+/// Deepest the tree can get with inputs of up to 2^64 bytes.
+const MAX_DEPTH: usize = 54;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const IV: [u32; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+
+/// Order of the message words in each round after the first.
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+/// Mixes the message words `x` and `y` into a column or diagonal.
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn compress(
+    chaining_value: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [0; 16];
+    state[..8].copy_from_slice(chaining_value);
+    state[8..12].copy_from_slice(&IV[..4]);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = block_len;
+    state[15] = flags;
+
+    let mut m = *block;
+    for i in 0..7 {
+        if i > 0 {
+            m = MSG_PERMUTATION.map(|j| m[j]);
+        }
+        round(&mut state, &m);
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+    state
+}
+
+fn first_8_words(words: [u32; 16]) -> [u32; 8] {
+    let mut first = [0; 8];
+    first.copy_from_slice(&words[..8]);
+    first
+}
+
+fn block_words(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// The last compression of a chunk or parent node, which is done with the
+/// root flag if the node turns out to be the root.
+struct Output {
+    chaining_value: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(
+            &self.chaining_value,
+            &self.block,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root_bytes(&self) -> [u8; OUT_LEN] {
+        let words = compress(
+            &self.chaining_value,
+            &self.block,
+            0,
+            self.block_len,
+            self.flags | ROOT,
+        );
+        let mut out = [0; OUT_LEN];
+        for (bytes, word) in out.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// The chunk being hashed.
+struct ChunkState {
+    chaining_value: [u32; 8],
+    counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+impl ChunkState {
+    fn new(counter: u64) -> Self {
+        Self {
+            chaining_value: IV,
+            counter,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            blocks_compressed: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 {
+            CHUNK_START
+        } else {
+            0
+        }
+    }
+
+    fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // More input is coming, so a full block isn't the chunk's last
+            if self.block_len == BLOCK_LEN {
+                self.chaining_value = first_8_words(compress(
+                    &self.chaining_value,
+                    &block_words(&self.block),
+                    self.counter,
+                    BLOCK_LEN as u32,
+                    self.start_flag(),
+                ));
+                self.blocks_compressed += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+            let take = (BLOCK_LEN - self.block_len).min(input.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&input[..take]);
+            self.block_len += take;
+            input = &input[take..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            chaining_value: self.chaining_value,
+            block: block_words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+fn parent_output(left: [u32; 8], right: [u32; 8]) -> Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(&left);
+    block[8..].copy_from_slice(&right);
+    Output {
+        chaining_value: IV,
+        block,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: PARENT,
+    }
+}
+
+/// An incremental BLAKE3 hasher.
+pub(crate) struct Hasher {
+    chunk: ChunkState,
+    /// Chaining values of the complete subtrees left of the current chunk.
+    stack: Vec<[u32; 8]>,
+}
+
+impl Hasher {
+    pub(crate) fn new() -> Self {
+        Self {
+            chunk: ChunkState::new(0),
+            stack: Vec::with_capacity(MAX_DEPTH),
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // More input is coming, so a full chunk isn't the root
+            if self.chunk.len() == CHUNK_LEN {
+                let mut chaining_value = self.chunk.output().chaining_value();
+                let mut chunks = self.chunk.counter + 1;
+                // Every trailing zero bit of the chunk count is a subtree
+                // this chunk completes
+                while chunks & 1 == 0 {
+                    let left = self.stack.pop().expect("subtree on the stack");
+                    chaining_value = parent_output(left, chaining_value).chaining_value();
+                    chunks >>= 1;
+                }
+                self.stack.push(chaining_value);
+                self.chunk = ChunkState::new(self.chunk.counter + 1);
+            }
+            let take = (CHUNK_LEN - self.chunk.len()).min(input.len());
+            self.chunk.update(&input[..take]);
+            input = &input[take..];
+        }
+    }
+
+    pub(crate) fn finalize(&self) -> [u8; OUT_LEN] {
+        let mut output = self.chunk.output();
+        for &left in self.stack.iter().rev() {
+            output = parent_output(left, output.chaining_value());
+        }
+        output.root_bytes()
+    }
+}
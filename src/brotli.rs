@@ -0,0 +1,1172 @@
+//! Decoder for Brotli streams (RFC 7932).
+//!
+//! Brotli streams carry no magic number, so they can only be recognized
+//! by their file extension or by decoding them. The static dictionary
+//! shared by all streams is embedded from `brotli/dictionary.bin`.
+
+use std::io::{self, Read};
+
+/// The RFC 7932 static dictionary.
+static DICTIONARY: &[u8; 122_784] = include_bytes!("brotli/dictionary.bin");
+/// log2 of the number of dictionary words of each length.
+const DICTIONARY_SIZE_BITS: [u8; 25] = [
+    0, 0, 0, 0, 10, 10, 11, 11, 10, 10, 10, 10, 10, 9, 9, 8, 7, 7, 8, 7, 7, 6, 6, 5, 5,
+];
+/// Offset of the words of each length in [`DICTIONARY`].
+const DICTIONARY_OFFSETS: [u32; 25] = [
+    0, 0, 0, 0, 0, 4096, 9216, 21504, 35840, 44032, 53248, 63488, 74752, 87040, 93696, 100864,
+    104704, 106752, 108928, 113536, 115968, 118528, 119872, 121280, 122016,
+];
+
+/// Alphabet sizes of the literal and insert-and-copy codes.
+const LITERAL_ALPHABET: usize = 256;
+const COMMAND_ALPHABET: usize = 704;
+/// Alphabet size of the block count code.
+const BLOCK_COUNT_ALPHABET: usize = 26;
+/// Longest prefix code allowed.
+const MAX_CODE_LENGTH: usize = 15;
+/// Prefix codes up to this length are decoded with a single table lookup.
+const TABLE_BITS: u32 = 8;
+/// Block length used when a category has a single block type.
+const UNLIMITED_BLOCK: usize = 1 << 24;
+/// Order in which code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// Lengths of the fixed code used for code length code lengths.
+const CODE_LENGTH_CODE_LENGTHS: [u8; 6] = [2, 4, 3, 2, 2, 4];
+/// Base values and extra bits of block counts.
+const BLOCK_COUNTS: [(u32, u32); 26] = [
+    (1, 2),
+    (5, 2),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 3),
+    (41, 3),
+    (49, 4),
+    (65, 4),
+    (81, 4),
+    (97, 4),
+    (113, 5),
+    (145, 5),
+    (177, 5),
+    (209, 5),
+    (241, 6),
+    (305, 6),
+    (369, 7),
+    (497, 8),
+    (753, 9),
+    (1265, 10),
+    (2289, 11),
+    (4337, 12),
+    (8433, 13),
+    (16625, 24),
+];
+/// Base values and extra bits of insert lengths.
+const INSERT_LENGTHS: [(u32, u32); 24] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 1),
+    (8, 1),
+    (10, 2),
+    (14, 2),
+    (18, 3),
+    (26, 3),
+    (34, 4),
+    (50, 4),
+    (66, 5),
+    (98, 5),
+    (130, 6),
+    (194, 7),
+    (322, 8),
+    (578, 9),
+    (1090, 10),
+    (2114, 12),
+    (6210, 14),
+    (22594, 24),
+];
+/// Base values and extra bits of copy lengths.
+const COPY_LENGTHS: [(u32, u32); 24] = [
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 1),
+    (12, 1),
+    (14, 2),
+    (18, 2),
+    (22, 3),
+    (30, 3),
+    (38, 4),
+    (54, 4),
+    (70, 5),
+    (102, 5),
+    (134, 6),
+    (198, 7),
+    (326, 8),
+    (582, 9),
+    (1094, 10),
+    (2118, 24),
+];
+/// Insert and copy length code offsets of the 11 cells of the
+/// insert-and-copy alphabet; the first two cells reuse the last distance.
+const COMMAND_CELLS: [(u32, u32); 11] = [
+    (0, 0),
+    (0, 8),
+    (0, 0),
+    (0, 8),
+    (8, 0),
+    (8, 8),
+    (0, 16),
+    (16, 0),
+    (8, 16),
+    (16, 8),
+    (16, 16),
+];
+
+/// Reads a stream bit by bit, least significant bit first.
+struct BitReader<R> {
+    reader: R,
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![0; 8192].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Tops the bit buffer up to at least 32 bits, unless the input ends.
+    fn refill(&mut self) -> io::Result<()> {
+        while self.count <= 56 {
+            if self.start == self.end {
+                if self.count >= 32 {
+                    return Ok(());
+                }
+                self.end = loop {
+                    match self.reader.read(&mut self.buffer) {
+                        Ok(n) => break n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                };
+                self.start = 0;
+                if self.end == 0 {
+                    return Ok(());
+                }
+            }
+            self.bits |= u64::from(self.buffer[self.start]) << self.count;
+            self.start += 1;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    /// Returns the next `n` bits without consuming them, padded with zeros
+    /// past the end of the input.
+    fn peek(&mut self, n: u32) -> io::Result<u32> {
+        if self.count < n {
+            self.refill()?;
+        }
+        Ok((self.bits & ((1 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> io::Result<()> {
+        if self.count < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, n: u32) -> io::Result<u32> {
+        let value = self.peek(n)?;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary; the skipped bits must be zero.
+    fn align(&mut self) -> io::Result<()> {
+        if self.read_bits(self.count % 8)? != 0 {
+            return Err(invalid("non-zero Brotli padding bits"));
+        }
+        Ok(())
+    }
+}
+
+/// A canonical prefix code.
+struct PrefixCode {
+    /// `(symbol, length)` for every `TABLE_BITS`-bit input, with a length
+    /// of 0 for codes longer than `TABLE_BITS`.
+    table: Vec<(u16, u8)>,
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl PrefixCode {
+    /// Builds the code from per-symbol lengths, which must describe a
+    /// complete code or a single symbol.
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<(u8, u16)> = lengths
+            .iter()
+            .enumerate()
+            .filter(|&(_, &len)| len > 0)
+            .map(|(symbol, &len)| (len, symbol as u16))
+            .collect();
+        symbols.sort_unstable();
+        if symbols.is_empty() {
+            return Err(invalid("empty Brotli prefix code"));
+        }
+        if symbols.len() == 1 {
+            // A lone symbol takes no bits at all
+            return Ok(Self {
+                table: Vec::new(),
+                counts,
+                symbols: vec![symbols[0].1],
+            });
+        }
+
+        let mut table = vec![(0u16, 0u8); 1 << TABLE_BITS];
+        let mut code = 0u32;
+        let mut previous = 0u8;
+        for &(len, symbol) in &symbols {
+            code <<= len - previous;
+            previous = len;
+            if u32::from(len) <= TABLE_BITS {
+                // Codes are stored most significant bit first
+                let reversed = code.reverse_bits() >> (32 - u32::from(len));
+                let mut index = reversed as usize;
+                while index < table.len() {
+                    table[index] = (symbol, len);
+                    index += 1 << len;
+                }
+            }
+            code += 1;
+        }
+        if code != 1 << previous {
+            return Err(invalid("incomplete Brotli prefix code"));
+        }
+        Ok(Self {
+            table,
+            counts,
+            symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect(),
+        })
+    }
+
+    fn decode<R: Read>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        if self.table.is_empty() {
+            return Ok(self.symbols[0]);
+        }
+        let (symbol, len) = self.table[bits.peek(TABLE_BITS)? as usize];
+        if len > 0 {
+            bits.consume(u32::from(len))?;
+            return Ok(symbol);
+        }
+
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_CODE_LENGTH {
+            code |= bits.read_bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Brotli prefix code"))
+    }
+}
+
+/// Block switching state of one of the three categories.
+struct BlockTypes {
+    count: usize,
+    codes: Option<(PrefixCode, PrefixCode)>,
+    current: usize,
+    previous: usize,
+    remaining: usize,
+}
+
+impl BlockTypes {
+    fn read<R: Read>(bits: &mut BitReader<R>) -> io::Result<Self> {
+        let count = read_count(bits)?;
+        let mut types = Self {
+            count,
+            codes: None,
+            current: 0,
+            previous: 1,
+            remaining: UNLIMITED_BLOCK,
+        };
+        if count >= 2 {
+            let type_code = read_prefix_code(bits, count + 2)?;
+            let count_code = read_prefix_code(bits, BLOCK_COUNT_ALPHABET)?;
+            types.remaining = read_block_count(bits, &count_code)?;
+            types.codes = Some((type_code, count_code));
+        }
+        Ok(types)
+    }
+
+    /// Counts one symbol against the current block, switching blocks
+    /// first if the current one is exhausted.
+    fn advance<R: Read>(&mut self, bits: &mut BitReader<R>) -> io::Result<()> {
+        if self.remaining == 0 {
+            let (type_code, count_code) = self
+                .codes
+                .as_ref()
+                .ok_or_else(|| invalid("Brotli block overrun"))?;
+            let next = match type_code.decode(bits)? {
+                0 => self.previous,
+                1 => self.current + 1,
+                code => usize::from(code) - 2,
+            } % self.count;
+            self.previous = self.current;
+            self.current = next;
+            self.remaining = read_block_count(bits, count_code)?;
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+}
+
+/// Decoding state of a compressed meta-block.
+struct MetaBlock {
+    literals: BlockTypes,
+    commands: BlockTypes,
+    distances: BlockTypes,
+    postfix_bits: u32,
+    direct: u32,
+    context_modes: Vec<u8>,
+    literal_map: Vec<u8>,
+    distance_map: Vec<u8>,
+    literal_codes: Vec<PrefixCode>,
+    command_codes: Vec<PrefixCode>,
+    distance_codes: Vec<PrefixCode>,
+}
+
+/// Where the decoder is in the stream.
+enum State {
+    Header,
+    /// Expecting a meta-block header.
+    Next,
+    Uncompressed,
+    Compressed(Box<MetaBlock>),
+    Done,
+}
+
+/// A reader that decompresses a Brotli stream.
+pub(crate) struct Decoder<R> {
+    bits: BitReader<R>,
+    state: State,
+    /// Whether the current meta-block is the last one.
+    last: bool,
+    /// Bytes left in the current meta-block.
+    remaining: usize,
+    window_size: usize,
+    /// Decoded data not yet read, preceded by up to `window_size` bytes of
+    /// earlier output that later commands may copy from.
+    output: Vec<u8>,
+    position: usize,
+    /// Total number of bytes decoded.
+    total: u64,
+    /// The last four distances, most recent last.
+    distances: [u32; 4],
+}
+
+impl<R: Read> Decoder<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            bits: BitReader::new(reader),
+            state: State::Header,
+            last: false,
+            remaining: 0,
+            window_size: 0,
+            output: Vec::new(),
+            position: 0,
+            total: 0,
+            distances: [16, 15, 11, 4],
+        }
+    }
+
+    /// Decodes until there is unread output, returning `false` at the end
+    /// of the stream.
+    fn fill(&mut self) -> io::Result<bool> {
+        // Drop history that can no longer be referenced
+        if self.position == self.output.len() && self.output.len() > 2 * self.window_size {
+            self.output.drain(..self.output.len() - self.window_size);
+            self.position = self.output.len();
+        }
+
+        while self.position == self.output.len() {
+            match &mut self.state {
+                State::Done => return Ok(false),
+                State::Header => {
+                    self.window_size = (1 << read_window_bits(&mut self.bits)?) - 16;
+                    self.state = State::Next;
+                }
+                State::Next => {
+                    if self.last {
+                        self.state = State::Done;
+                    } else {
+                        self.read_meta_block_header()?;
+                    }
+                }
+                State::Uncompressed => {
+                    let n = self.remaining.min(64 * 1024);
+                    for _ in 0..n {
+                        let byte = self.bits.read_bits(8)? as u8;
+                        self.output.push(byte);
+                    }
+                    self.total += n as u64;
+                    self.remaining -= n;
+                    if self.remaining == 0 {
+                        self.state = State::Next;
+                    }
+                }
+                State::Compressed(_) => {
+                    let State::Compressed(mut block) =
+                        std::mem::replace(&mut self.state, State::Next)
+                    else {
+                        unreachable!();
+                    };
+                    self.command(&mut block)?;
+                    if self.remaining > 0 {
+                        self.state = State::Compressed(block);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_meta_block_header(&mut self) -> io::Result<()> {
+        let bits = &mut self.bits;
+        self.last = bits.read_bits(1)? == 1;
+        if self.last && bits.read_bits(1)? == 1 {
+            // ISLASTEMPTY
+            self.state = State::Done;
+            return Ok(());
+        }
+
+        let nibbles = match bits.read_bits(2)? {
+            3 => 0,
+            n => n + 4,
+        };
+        if nibbles == 0 {
+            // Metadata, which is skipped
+            if bits.read_bits(1)? != 0 {
+                return Err(invalid("reserved Brotli bit set"));
+            }
+            let skip_bytes = bits.read_bits(2)?;
+            let mut skip = 0usize;
+            for i in 0..skip_bytes {
+                let byte = bits.read_bits(8)?;
+                if i + 1 == skip_bytes && skip_bytes > 1 && byte == 0 {
+                    return Err(invalid("non-minimal Brotli metadata length"));
+                }
+                skip |= (byte as usize) << (8 * i);
+            }
+            bits.align()?;
+            if skip_bytes > 0 {
+                for _ in 0..=skip {
+                    bits.read_bits(8)?;
+                }
+            }
+            self.state = State::Next;
+            return Ok(());
+        }
+
+        let mut length = 0usize;
+        for i in 0..nibbles {
+            let nibble = bits.read_bits(4)?;
+            if i + 1 == nibbles && nibbles > 4 && nibble == 0 {
+                return Err(invalid("non-minimal Brotli meta-block length"));
+            }
+            length |= (nibble as usize) << (4 * i);
+        }
+        self.remaining = length + 1;
+
+        if !self.last && bits.read_bits(1)? == 1 {
+            bits.align()?;
+            self.state = State::Uncompressed;
+            return Ok(());
+        }
+
+        let literals = BlockTypes::read(bits)?;
+        let commands = BlockTypes::read(bits)?;
+        let distances = BlockTypes::read(bits)?;
+        let postfix_bits = bits.read_bits(2)?;
+        let direct = bits.read_bits(4)? << postfix_bits;
+        let mut context_modes = Vec::with_capacity(literals.count);
+        for _ in 0..literals.count {
+            context_modes.push(bits.read_bits(2)? as u8);
+        }
+        let (literal_trees, literal_map) = read_context_map(bits, 64 * literals.count)?;
+        let (distance_trees, distance_map) = read_context_map(bits, 4 * distances.count)?;
+
+        let distance_alphabet = 16 + direct as usize + (48 << postfix_bits);
+        let literal_codes = (0..literal_trees)
+            .map(|_| read_prefix_code(bits, LITERAL_ALPHABET))
+            .collect::<io::Result<_>>()?;
+        let command_codes = (0..commands.count)
+            .map(|_| read_prefix_code(bits, COMMAND_ALPHABET))
+            .collect::<io::Result<_>>()?;
+        let distance_codes = (0..distance_trees)
+            .map(|_| read_prefix_code(bits, distance_alphabet))
+            .collect::<io::Result<_>>()?;
+
+        self.state = State::Compressed(Box::new(MetaBlock {
+            literals,
+            commands,
+            distances,
+            postfix_bits,
+            direct,
+            context_modes,
+            literal_map,
+            distance_map,
+            literal_codes,
+            command_codes,
+            distance_codes,
+        }));
+        Ok(())
+    }
+
+    /// Decodes one insert-and-copy command.
+    fn command(&mut self, block: &mut MetaBlock) -> io::Result<()> {
+        let bits = &mut self.bits;
+        block.commands.advance(bits)?;
+        let symbol = u32::from(block.command_codes[block.commands.current].decode(bits)?);
+        let cell = (symbol >> 6) as usize;
+        let (insert_offset, copy_offset) = COMMAND_CELLS[cell];
+        let (insert_base, insert_extra) =
+            INSERT_LENGTHS[(insert_offset + ((symbol >> 3) & 7)) as usize];
+        let (copy_base, copy_extra) = COPY_LENGTHS[(copy_offset + (symbol & 7)) as usize];
+        let insert = (insert_base + bits.read_bits(insert_extra)?) as usize;
+        let copy = (copy_base + bits.read_bits(copy_extra)?) as usize;
+
+        if insert > self.remaining {
+            return Err(invalid("Brotli insert past the end of a meta-block"));
+        }
+        for _ in 0..insert {
+            block.literals.advance(bits)?;
+            let len = self.output.len();
+            let p1 = if len > 0 { self.output[len - 1] } else { 0 };
+            let p2 = if len > 1 { self.output[len - 2] } else { 0 };
+            let mode = block.context_modes[block.literals.current];
+            let context = usize::from(literal_context(mode, p1, p2));
+            let tree = block.literal_map[64 * block.literals.current + context];
+            let literal = block.literal_codes[usize::from(tree)].decode(bits)?;
+            self.output.push(literal as u8);
+        }
+        self.total += insert as u64;
+        self.remaining -= insert;
+        if self.remaining == 0 {
+            // The copy of the final command is ignored
+            return Ok(());
+        }
+
+        let code = if cell < 2 {
+            0
+        } else {
+            block.distances.advance(bits)?;
+            let context = copy.clamp(2, 5) - 2;
+            let tree = block.distance_map[4 * block.distances.current + context];
+            u32::from(block.distance_codes[usize::from(tree)].decode(bits)?)
+        };
+        let distance = self.distance(code, block.postfix_bits, block.direct)?;
+
+        let max_distance = self.total.min(self.window_size as u64);
+        if u64::from(distance) > max_distance {
+            let word_id = u64::from(distance) - max_distance - 1;
+            let word = dictionary_word(copy, word_id)?;
+            if word.len() > self.remaining {
+                return Err(invalid("Brotli copy past the end of a meta-block"));
+            }
+            self.output.extend_from_slice(&word);
+            self.total += word.len() as u64;
+            self.remaining -= word.len();
+            return Ok(());
+        }
+
+        if code != 0 {
+            self.distances.rotate_left(1);
+            self.distances[3] = distance;
+        }
+        if copy > self.remaining {
+            return Err(invalid("Brotli copy past the end of a meta-block"));
+        }
+        let start = self.output.len() - distance as usize;
+        if distance as usize >= copy {
+            self.output.extend_from_within(start..start + copy);
+        } else {
+            for i in 0..copy {
+                self.output.push(self.output[start + i]);
+            }
+        }
+        self.total += copy as u64;
+        self.remaining -= copy;
+        Ok(())
+    }
+
+    /// Resolves a distance code against the recent distances.
+    fn distance(&mut self, code: u32, postfix_bits: u32, direct: u32) -> io::Result<u32> {
+        let [fourth, third, second, last] = self.distances.map(i64::from);
+        let distance = match code {
+            0 => last,
+            1 => second,
+            2 => third,
+            3 => fourth,
+            4..=9 => last + delta(code - 4),
+            10..=15 => second + delta(code - 10),
+            _ if code < 16 + direct => i64::from(code - 15),
+            _ => {
+                let code = code - direct - 16;
+                let extra_bits = 1 + (code >> (postfix_bits + 1));
+                let high = code >> postfix_bits;
+                let low = code & ((1 << postfix_bits) - 1);
+                let offset = ((2 + (high & 1)) << extra_bits) - 4;
+                let extra = self.bits.read_bits(extra_bits)?;
+                i64::from(((offset + extra) << postfix_bits) + low + direct + 1)
+            }
+        };
+        u32::try_from(distance)
+            .ok()
+            .filter(|&d| d > 0)
+            .ok_or_else(|| invalid("invalid Brotli distance"))
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || !self.fill()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.output.len() - self.position);
+        buf[..n].copy_from_slice(&self.output[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Wraps `data` in a Brotli stream of uncompressed meta-blocks, which every
+/// Brotli decoder accepts.
+#[cfg(feature = "testing")]
+pub(crate) fn encode_stored(data: &[u8]) -> Vec<u8> {
+    struct BitWriter {
+        stream: Vec<u8>,
+        bits: u32,
+        count: u32,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            self.bits |= value << self.count;
+            self.count += n;
+            while self.count >= 8 {
+                self.stream.push(self.bits as u8);
+                self.bits >>= 8;
+                self.count -= 8;
+            }
+        }
+
+        fn align(&mut self) {
+            self.put(0, (8 - self.count % 8) % 8);
+        }
+    }
+
+    let mut writer = BitWriter {
+        stream: Vec::new(),
+        bits: 0,
+        count: 0,
+    };
+    // WBITS = 22
+    writer.put(1, 1);
+    writer.put(5, 3);
+    for block in data.chunks(1 << 16) {
+        // Not last, four nibbles of length, uncompressed
+        writer.put(0, 1);
+        writer.put(0, 2);
+        writer.put(block.len() as u32 - 1, 16);
+        writer.put(1, 1);
+        writer.align();
+        writer.stream.extend_from_slice(block);
+    }
+    // ISLAST and ISLASTEMPTY
+    writer.put(3, 2);
+    writer.align();
+    writer.stream
+}
+
+/// Returns the offset applied to a recent distance by distance codes
+/// 4-9 and 10-15: -1, +1, -2, +2, -3, +3.
+fn delta(index: u32) -> i64 {
+    let magnitude = i64::from(index / 2 + 1);
+    if index.is_multiple_of(2) {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Reads the WBITS field of the stream header.
+fn read_window_bits<R: Read>(bits: &mut BitReader<R>) -> io::Result<u32> {
+    if bits.read_bits(1)? == 0 {
+        return Ok(16);
+    }
+    match bits.read_bits(3)? {
+        0 => {
+            match bits.read_bits(3)? {
+                0 => Ok(17),
+                1 => Err(invalid("large-window Brotli streams are not supported")),
+                n => Ok(8 + n),
+            }
+        }
+        n => Ok(17 + n),
+    }
+}
+
+/// Reads the 1-11 bit encoding of the number of block types or trees.
+fn read_count<R: Read>(bits: &mut BitReader<R>) -> io::Result<usize> {
+    if bits.read_bits(1)? == 0 {
+        return Ok(1);
+    }
+    let n = bits.read_bits(3)?;
+    Ok(if n == 0 {
+        2
+    } else {
+        (1 << n) + bits.read_bits(n)? as usize + 1
+    })
+}
+
+fn read_block_count<R: Read>(bits: &mut BitReader<R>, code: &PrefixCode) -> io::Result<usize> {
+    let (base, extra) = BLOCK_COUNTS[usize::from(code.decode(bits)?)];
+    Ok((base + bits.read_bits(extra)?) as usize)
+}
+
+/// Reads a simple or complex prefix code over `alphabet` symbols.
+fn read_prefix_code<R: Read>(bits: &mut BitReader<R>, alphabet: usize) -> io::Result<PrefixCode> {
+    let skip = bits.read_bits(2)?;
+    let mut lengths = vec![0u8; alphabet];
+
+    if skip == 1 {
+        // Simple code: up to four symbols with implied lengths
+        let symbol_bits = usize::BITS - (alphabet - 1).leading_zeros();
+        let count = bits.read_bits(2)? as usize + 1;
+        let mut symbols = [0usize; 4];
+        for i in 0..count {
+            let symbol = bits.read_bits(symbol_bits)? as usize;
+            if symbol >= alphabet || symbols[..i].contains(&symbol) {
+                return Err(invalid("invalid Brotli simple prefix code"));
+            }
+            symbols[i] = symbol;
+        }
+        let implied: &[u8] = match count {
+            1 => &[1],
+            2 => &[1, 1],
+            3 => &[1, 2, 2],
+            _ if bits.read_bits(1)? == 0 => &[2, 2, 2, 2],
+            _ => &[1, 2, 3, 3],
+        };
+        for (&symbol, &len) in symbols.iter().zip(implied) {
+            lengths[symbol] = len;
+        }
+        return PrefixCode::new(&lengths);
+    }
+
+    // Complex code: first the lengths of the code length code
+    let code_length_code = PrefixCode::new(&CODE_LENGTH_CODE_LENGTHS)?;
+    let mut code_lengths = [0u8; 18];
+    let mut space = 32i32;
+    let mut nonzero = 0;
+    for &index in &CODE_LENGTH_ORDER[skip as usize..] {
+        let len = code_length_code.decode(bits)? as u8;
+        code_lengths[index] = len;
+        if len != 0 {
+            space -= 32 >> len;
+            nonzero += 1;
+            if space <= 0 {
+                break;
+            }
+        }
+    }
+    if nonzero != 1 && space != 0 {
+        return Err(invalid("invalid Brotli code length code"));
+    }
+    let code_length_code = PrefixCode::new(&code_lengths)?;
+
+    let mut symbol = 0;
+    let mut previous = 8u8;
+    let mut repeat = 0usize;
+    let mut repeat_length = 0u8;
+    let mut space = 32768i32;
+    while symbol < alphabet && space > 0 {
+        let code = code_length_code.decode(bits)? as u8;
+        if code < 16 {
+            repeat = 0;
+            lengths[symbol] = code;
+            symbol += 1;
+            if code != 0 {
+                previous = code;
+                space -= 32768 >> code;
+            }
+            continue;
+        }
+
+        let (extra_bits, new_length) = if code == 16 { (2, previous) } else { (3, 0) };
+        if repeat_length != new_length {
+            repeat = 0;
+            repeat_length = new_length;
+        }
+        let old_repeat = repeat;
+        if repeat > 0 {
+            repeat = (repeat - 2) << extra_bits;
+        }
+        repeat += bits.read_bits(extra_bits)? as usize + 3;
+        let delta = repeat - old_repeat;
+        if symbol + delta > alphabet {
+            return Err(invalid("Brotli code length repeat overflows the alphabet"));
+        }
+        lengths[symbol..symbol + delta].fill(repeat_length);
+        symbol += delta;
+        if repeat_length != 0 {
+            space -= (delta as i32) << (15 - repeat_length);
+        }
+    }
+    if space != 0 {
+        return Err(invalid("incomplete Brotli prefix code"));
+    }
+    PrefixCode::new(&lengths)
+}
+
+/// Reads a context map of `size` entries, returning the number of trees
+/// and the map.
+fn read_context_map<R: Read>(bits: &mut BitReader<R>, size: usize) -> io::Result<(usize, Vec<u8>)> {
+    let trees = read_count(bits)?;
+    let mut map = vec![0u8; size];
+    if trees < 2 {
+        return Ok((trees, map));
+    }
+
+    let max_run_prefix = if bits.read_bits(1)? == 1 {
+        bits.read_bits(4)? as usize + 1
+    } else {
+        0
+    };
+    let code = read_prefix_code(bits, trees + max_run_prefix)?;
+    let mut i = 0;
+    while i < size {
+        let symbol = usize::from(code.decode(bits)?);
+        if symbol == 0 {
+            i += 1;
+        } else if symbol <= max_run_prefix {
+            // A run of zeros
+            let run = (1 << symbol) + bits.read_bits(symbol as u32)? as usize;
+            if i + run > size {
+                return Err(invalid("Brotli context map run overflows"));
+            }
+            i += run;
+        } else {
+            map[i] = (symbol - max_run_prefix) as u8;
+            i += 1;
+        }
+    }
+
+    if bits.read_bits(1)? == 1 {
+        // Inverse move-to-front transform
+        let mut mtf: Vec<u8> = (0..=255).collect();
+        for value in &mut map {
+            let index = usize::from(*value);
+            let entry = mtf.remove(index);
+            mtf.insert(0, entry);
+            *value = entry;
+        }
+    }
+    if map.iter().any(|&tree| usize::from(tree) >= trees) {
+        return Err(invalid("Brotli context map refers to a missing tree"));
+    }
+    Ok((trees, map))
+}
+
+/// Computes the context of a literal from the two bytes before it.
+fn literal_context(mode: u8, p1: u8, p2: u8) -> u8 {
+    match mode {
+        0 => p1 & 0x3F,
+        1 => p1 >> 2,
+        2 => UTF8_CONTEXT[usize::from(p1)] | UTF8_CONTEXT[256 + usize::from(p2)],
+        _ => SIGNED_CONTEXT[usize::from(p1)] | SIGNED_CONTEXT[256 + usize::from(p2)],
+    }
+}
+
+/// Word transforms applied to static dictionary references.
+#[derive(Clone, Copy)]
+enum Transform {
+    Identity,
+    OmitLast(usize),
+    OmitFirst(usize),
+    UppercaseFirst,
+    UppercaseAll,
+}
+
+/// Looks up and transforms the static dictionary word referenced by a
+/// copy of `length` bytes beyond the window.
+fn dictionary_word(length: usize, word_id: u64) -> io::Result<Vec<u8>> {
+    let size_bits = *DICTIONARY_SIZE_BITS
+        .get(length)
+        .filter(|&&bits| bits > 0)
+        .ok_or_else(|| invalid("invalid Brotli dictionary reference"))?;
+    let index = (word_id & ((1 << size_bits) - 1)) as usize;
+    let (prefix, transform, suffix) = usize::try_from(word_id >> size_bits)
+        .ok()
+        .and_then(|id| TRANSFORMS.get(id))
+        .ok_or_else(|| invalid("invalid Brotli dictionary transform"))?;
+    let start = DICTIONARY_OFFSETS[length] as usize + index * length;
+    let mut word = DICTIONARY[start..start + length].to_vec();
+
+    match *transform {
+        Transform::Identity => {}
+        Transform::OmitLast(n) => word.truncate(length.saturating_sub(n)),
+        Transform::OmitFirst(n) => {
+            word.drain(..n.min(length));
+        }
+        Transform::UppercaseFirst => {
+            uppercase(&mut word);
+        }
+        Transform::UppercaseAll => {
+            let mut i = 0;
+            while i < word.len() {
+                i += uppercase(&mut word[i..]);
+            }
+        }
+    }
+
+    let mut output = Vec::with_capacity(prefix.len() + word.len() + suffix.len());
+    output.extend_from_slice(prefix);
+    output.extend_from_slice(&word);
+    output.extend_from_slice(suffix);
+    Ok(output)
+}
+
+/// Uppercases the UTF-8 character at the start of `word` the way the
+/// reference decoder does, returning its length.
+fn uppercase(word: &mut [u8]) -> usize {
+    match word[0] {
+        b'a'..=b'z' => {
+            word[0] ^= 32;
+            1
+        }
+        0..0xC0 => 1,
+        0xC0..0xE0 => {
+            if let Some(byte) = word.get_mut(1) {
+                *byte ^= 32;
+            }
+            2
+        }
+        _ => {
+            if let Some(byte) = word.get_mut(2) {
+                *byte ^= 5;
+            }
+            3
+        }
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Context lookup for the UTF-8 mode: the first 256 entries are indexed by
+/// the last byte, the second 256 by the byte before it.
+const UTF8_CONTEXT: [u8; 512] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 4, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    8, 12, 16, 12, 12, 20, 12, 16, 24, 28, 12, 12, 32, 12, 36, 12, 44, 44, 44, 44, 44, 44, 44, 44,
+    44, 44, 32, 32, 24, 40, 28, 12, 12, 48, 52, 52, 52, 48, 52, 52, 52, 48, 52, 52, 52, 52, 52, 48,
+    52, 52, 52, 52, 52, 48, 52, 52, 52, 52, 52, 24, 12, 28, 12, 12, 12, 56, 60, 60, 60, 56, 60, 60,
+    60, 56, 60, 60, 60, 60, 60, 56, 60, 60, 60, 60, 60, 56, 60, 60, 60, 60, 60, 24, 12, 28, 12, 0,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1,
+    1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1,
+    1, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+
+/// Context lookup for the signed mode, laid out like [`UTF8_CONTEXT`].
+const SIGNED_CONTEXT: [u8; 512] = [
+    0, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32,
+    32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32,
+    32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 40, 40, 40, 40,
+    40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40,
+    40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 40, 48, 48, 48, 48,
+    48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 56, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 7,
+];
+
+/// The 121 word transforms of RFC 7932 Appendix B: prefix, transform and
+/// suffix.
+const TRANSFORMS: [(&[u8], Transform, &[u8]); 121] = [
+    (b"", Transform::Identity, b""),
+    (b"", Transform::Identity, b" "),
+    (b" ", Transform::Identity, b" "),
+    (b"", Transform::OmitFirst(1), b""),
+    (b"", Transform::UppercaseFirst, b" "),
+    (b"", Transform::Identity, b" the "),
+    (b" ", Transform::Identity, b""),
+    (b"s ", Transform::Identity, b" "),
+    (b"", Transform::Identity, b" of "),
+    (b"", Transform::UppercaseFirst, b""),
+    (b"", Transform::Identity, b" and "),
+    (b"", Transform::OmitFirst(2), b""),
+    (b"", Transform::OmitLast(1), b""),
+    (b", ", Transform::Identity, b" "),
+    (b"", Transform::Identity, b", "),
+    (b" ", Transform::UppercaseFirst, b" "),
+    (b"", Transform::Identity, b" in "),
+    (b"", Transform::Identity, b" to "),
+    (b"e ", Transform::Identity, b" "),
+    (b"", Transform::Identity, b"\""),
+    (b"", Transform::Identity, b"."),
+    (b"", Transform::Identity, b"\">"),
+    (b"", Transform::Identity, b"\n"),
+    (b"", Transform::OmitLast(3), b""),
+    (b"", Transform::Identity, b"]"),
+    (b"", Transform::Identity, b" for "),
+    (b"", Transform::OmitFirst(3), b""),
+    (b"", Transform::OmitLast(2), b""),
+    (b"", Transform::Identity, b" a "),
+    (b"", Transform::Identity, b" that "),
+    (b" ", Transform::UppercaseFirst, b""),
+    (b"", Transform::Identity, b". "),
+    (b".", Transform::Identity, b""),
+    (b" ", Transform::Identity, b", "),
+    (b"", Transform::OmitFirst(4), b""),
+    (b"", Transform::Identity, b" with "),
+    (b"", Transform::Identity, b"'"),
+    (b"", Transform::Identity, b" from "),
+    (b"", Transform::Identity, b" by "),
+    (b"", Transform::OmitFirst(5), b""),
+    (b"", Transform::OmitFirst(6), b""),
+    (b" the ", Transform::Identity, b""),
+    (b"", Transform::OmitLast(4), b""),
+    (b"", Transform::Identity, b". The "),
+    (b"", Transform::UppercaseAll, b""),
+    (b"", Transform::Identity, b" on "),
+    (b"", Transform::Identity, b" as "),
+    (b"", Transform::Identity, b" is "),
+    (b"", Transform::OmitLast(7), b""),
+    (b"", Transform::OmitLast(1), b"ing "),
+    (b"", Transform::Identity, b"\n\t"),
+    (b"", Transform::Identity, b":"),
+    (b" ", Transform::Identity, b". "),
+    (b"", Transform::Identity, b"ed "),
+    (b"", Transform::OmitFirst(9), b""),
+    (b"", Transform::OmitFirst(7), b""),
+    (b"", Transform::OmitLast(6), b""),
+    (b"", Transform::Identity, b"("),
+    (b"", Transform::UppercaseFirst, b", "),
+    (b"", Transform::OmitLast(8), b""),
+    (b"", Transform::Identity, b" at "),
+    (b"", Transform::Identity, b"ly "),
+    (b" the ", Transform::Identity, b" of "),
+    (b"", Transform::OmitLast(5), b""),
+    (b"", Transform::OmitLast(9), b""),
+    (b" ", Transform::UppercaseFirst, b", "),
+    (b"", Transform::UppercaseFirst, b"\""),
+    (b".", Transform::Identity, b"("),
+    (b"", Transform::UppercaseAll, b" "),
+    (b"", Transform::UppercaseFirst, b"\">"),
+    (b"", Transform::Identity, b"=\""),
+    (b" ", Transform::Identity, b"."),
+    (b".com/", Transform::Identity, b""),
+    (b" the ", Transform::Identity, b" of the "),
+    (b"", Transform::UppercaseFirst, b"'"),
+    (b"", Transform::Identity, b". This "),
+    (b"", Transform::Identity, b","),
+    (b".", Transform::Identity, b" "),
+    (b"", Transform::UppercaseFirst, b"("),
+    (b"", Transform::UppercaseFirst, b"."),
+    (b"", Transform::Identity, b" not "),
+    (b" ", Transform::Identity, b"=\""),
+    (b"", Transform::Identity, b"er "),
+    (b" ", Transform::UppercaseAll, b" "),
+    (b"", Transform::Identity, b"al "),
+    (b" ", Transform::UppercaseAll, b""),
+    (b"", Transform::Identity, b"='"),
+    (b"", Transform::UppercaseAll, b"\""),
+    (b"", Transform::UppercaseFirst, b". "),
+    (b" ", Transform::Identity, b"("),
+    (b"", Transform::Identity, b"ful "),
+    (b" ", Transform::UppercaseFirst, b". "),
+    (b"", Transform::Identity, b"ive "),
+    (b"", Transform::Identity, b"less "),
+    (b"", Transform::UppercaseAll, b"'"),
+    (b"", Transform::Identity, b"est "),
+    (b" ", Transform::UppercaseFirst, b"."),
+    (b"", Transform::UppercaseAll, b"\">"),
+    (b" ", Transform::Identity, b"='"),
+    (b"", Transform::UppercaseFirst, b","),
+    (b"", Transform::Identity, b"ize "),
+    (b"", Transform::UppercaseAll, b"."),
+    (b"\xc2\xa0", Transform::Identity, b""),
+    (b" ", Transform::Identity, b","),
+    (b"", Transform::UppercaseFirst, b"=\""),
+    (b"", Transform::UppercaseAll, b"=\""),
+    (b"", Transform::Identity, b"ous "),
+    (b"", Transform::UppercaseAll, b", "),
+    (b"", Transform::UppercaseFirst, b"='"),
+    (b" ", Transform::UppercaseFirst, b","),
+    (b" ", Transform::UppercaseAll, b"=\""),
+    (b" ", Transform::UppercaseAll, b", "),
+    (b"", Transform::UppercaseAll, b","),
+    (b"", Transform::UppercaseAll, b"("),
+    (b"", Transform::UppercaseAll, b". "),
+    (b" ", Transform::UppercaseAll, b"."),
+    (b"", Transform::UppercaseAll, b"='"),
+    (b" ", Transform::UppercaseAll, b". "),
+    (b" ", Transform::UppercaseFirst, b"=\""),
+    (b" ", Transform::UppercaseAll, b"='"),
+    (b" ", Transform::UppercaseFirst, b"='"),
+];
@@ -0,0 +1,384 @@
+//! Cabinet (`.cab`) files, as embedded in Windows Installer packages.
+//!
+//! A cabinet groups its files into folders, each compressed as a single
+//! stream split into data blocks of at most 32 KiB. Stored, MSZIP and LZX
+//! folders are read; Quantum compression and cabinet sets spanning
+//! several files are rejected as unsupported.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    lzx,
+};
+
+/// Magic number at the start of every cabinet.
+pub(crate) const CAB_MAGIC: &[u8] = b"MSCF";
+/// Size of the fixed part of the cabinet header.
+const HEADER_SIZE: usize = 36;
+/// Size of the fixed parts of folder, file and data block entries.
+const FOLDER_SIZE: usize = 8;
+const FILE_SIZE: usize = 16;
+const DATA_SIZE: usize = 8;
+/// Header flags.
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+/// Set in a file's attributes when its name is UTF-8.
+const ATTRIBUTE_NAME_IS_UTF: u16 = 0x80;
+/// Upper bound on the length of a file name.
+const MAX_NAME_SIZE: usize = 256;
+/// Largest block of uncompressed data, which is also the MSZIP window.
+const MAX_BLOCK_SIZE: usize = 32768;
+/// Signature at the start of every MSZIP block.
+const MSZIP_SIGNATURE: &[u8] = b"CK";
+
+/// How a folder's data is compressed.
+#[derive(Debug, Clone, Copy)]
+enum Compression {
+    None,
+    MsZip,
+    /// LZX with a window of 2^n bytes.
+    Lzx(u32),
+}
+
+struct Folder {
+    /// Offset of the first data block.
+    start: u64,
+    blocks: u16,
+    compression: Option<Compression>,
+}
+
+struct File {
+    name: String,
+    size: u64,
+    /// Offset of the file's data in the uncompressed folder.
+    offset: u64,
+    folder: u16,
+    mtime: Option<std::time::SystemTime>,
+}
+
+/// Walks every file of a cabinet, in the order the files are stored,
+/// attributing errors to `format`, the archive it is nested in.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(
+    mut reader: R,
+    format: ArchiveFormat,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut header = [0u8; HEADER_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(CAB_MAGIC) {
+        return Err(invalid(format, "missing 'MSCF' magic"));
+    }
+    if header[25] != 1 {
+        return Err(invalid(format, "unsupported cabinet version"));
+    }
+    let files_offset = u64::from(le32(&header[16..]));
+    let folder_count = le16(&header[26..]);
+    let file_count = le16(&header[28..]);
+    let flags = le16(&header[30..]);
+    if flags & (FLAG_PREV_CABINET | FLAG_NEXT_CABINET) != 0 {
+        return Err(ArchiveError::unsupported_static(
+            "cabinet sets spanning several files",
+        ));
+    }
+    let (mut folder_reserve, mut data_reserve) = (0, 0);
+    if flags & FLAG_RESERVE_PRESENT != 0 {
+        let mut sizes = [0u8; 4];
+        reader.read_exact(&mut sizes)?;
+        reader.seek(SeekFrom::Current(i64::from(le16(&sizes))))?;
+        folder_reserve = i64::from(sizes[2]);
+        data_reserve = usize::from(sizes[3]);
+    }
+
+    let mut folders = Vec::with_capacity(usize::from(folder_count));
+    for _ in 0..folder_count {
+        let mut entry = [0u8; FOLDER_SIZE];
+        reader.read_exact(&mut entry)?;
+        reader.seek(SeekFrom::Current(folder_reserve))?;
+        let kind = le16(&entry[6..]);
+        folders.push(Folder {
+            start: u64::from(le32(&entry)),
+            blocks: le16(&entry[4..]),
+            compression: match kind & 0xF {
+                0 => Some(Compression::None),
+                1 => Some(Compression::MsZip),
+                3 => Some(Compression::Lzx(u32::from(kind >> 8) & 0x1F)),
+                _ => None,
+            },
+        });
+    }
+
+    reader.seek(SeekFrom::Start(files_offset))?;
+    let mut files = Vec::with_capacity(usize::from(file_count));
+    for _ in 0..file_count {
+        let mut entry = [0u8; FILE_SIZE];
+        reader.read_exact(&mut entry)?;
+        let mut name = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] == 0 {
+                break;
+            }
+            if name.len() == MAX_NAME_SIZE {
+                return Err(invalid(format, "cabinet file name is too long"));
+            }
+            name.push(byte[0]);
+        }
+        // Other names are in the system code page; ASCII is the common case
+        let attributes = le16(&entry[14..]);
+        let name = if attributes & ATTRIBUTE_NAME_IS_UTF != 0 {
+            String::from_utf8_lossy(&name).into_owned()
+        } else {
+            name.iter().map(|&byte| char::from(byte)).collect()
+        };
+        files.push(File {
+            name: entry::normalize_name(&name),
+            size: u64::from(le32(&entry)),
+            offset: u64::from(le32(&entry[4..])),
+            folder: le16(&entry[8..]),
+            mtime: zip::DateTime::try_from_msdos(le16(&entry[10..]), le16(&entry[12..]))
+                .ok()
+                .and_then(entry::dos_time),
+        });
+    }
+    if files
+        .iter()
+        .any(|file| usize::from(file.folder) >= folders.len())
+    {
+        return Err(ArchiveError::unsupported_static(
+            "cabinet sets spanning several files",
+        ));
+    }
+    // Reading each folder's files in order decompresses it only once
+    files.sort_by_key(|file| (file.folder, file.offset));
+
+    let mut current: Option<(u16, FolderReader<'_, R>)> = None;
+    for file in &files {
+        let info = EntryInfo {
+            name: file.name.clone(),
+            kind: EntryKind::File,
+            size: file.size,
+            compressed_size: None,
+            mtime: file.mtime,
+            mode: None,
+            link_target: None,
+            owner: None,
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        if info.name.is_empty() {
+            return Err(invalid(format, "invalid cabinet file name"));
+        }
+        let keep_going = if file.size == 0 {
+            visit(&info, &mut io::empty())?
+        } else {
+            let reusable = matches!(&current, Some((index, folder))
+                if *index == file.folder && folder.position <= file.offset);
+            if !reusable {
+                let folder = &folders[usize::from(file.folder)];
+                let compression = folder.compression.ok_or_else(|| {
+                    ArchiveError::unsupported_static("Quantum-compressed cabinets")
+                })?;
+                let lzx = match compression {
+                    Compression::Lzx(order) => {
+                        Some(lzx::Decoder::new(lzx::Variant::Cabinet, order)?)
+                    }
+                    _ => None,
+                };
+                current = Some((
+                    file.folder,
+                    FolderReader {
+                        reader: &mut reader,
+                        format,
+                        compression,
+                        data_reserve,
+                        next_block: folder.start,
+                        blocks_left: folder.blocks,
+                        buffer: Vec::new(),
+                        consumed: 0,
+                        history: Vec::new(),
+                        lzx,
+                        position: 0,
+                    },
+                ));
+            }
+            let (_, folder) = current.as_mut().expect("folder reader was just opened");
+            let skip = file.offset - folder.position;
+            if io::copy(&mut folder.take(skip), &mut io::sink())? < skip {
+                return Err(invalid(format, "cabinet file extends past its folder"));
+            }
+            let mut data = folder.take(file.size);
+            let keep_going = visit(&info, &mut data)?;
+            io::copy(&mut data, &mut io::sink())?;
+            if data.limit() != 0 {
+                return Err(invalid(format, "cabinet file extends past its folder"));
+            }
+            keep_going
+        };
+        if !keep_going {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Reads a folder's data, decompressing its blocks in order.
+struct FolderReader<'a, R> {
+    reader: &'a mut R,
+    format: ArchiveFormat,
+    compression: Compression,
+    /// Size of the reserved area in every data block header.
+    data_reserve: usize,
+    /// Offset of the next data block.
+    next_block: u64,
+    blocks_left: u16,
+    buffer: Vec<u8>,
+    consumed: usize,
+    /// Uncompressed data of the last MSZIP block, which later blocks may
+    /// refer back to.
+    history: Vec<u8>,
+    lzx: Option<lzx::Decoder>,
+    /// Bytes of the folder read so far.
+    position: u64,
+}
+
+impl<R: Read + Seek> FolderReader<'_, R> {
+    /// Loads the next data block into the buffer, returning `false` after
+    /// the last.
+    fn fill(&mut self) -> Result<bool, ArchiveError> {
+        if self.blocks_left == 0 {
+            return Ok(false);
+        }
+        self.blocks_left -= 1;
+        self.reader.seek(SeekFrom::Start(self.next_block))?;
+        let mut header = vec![0u8; DATA_SIZE + self.data_reserve];
+        self.reader.read_exact(&mut header)?;
+        let stored_size = usize::from(le16(&header[4..]));
+        let size = usize::from(le16(&header[6..]));
+        let mut data = vec![0u8; stored_size];
+        self.reader.read_exact(&mut data)?;
+        self.next_block += (header.len() + stored_size) as u64;
+
+        let expected = le32(&header);
+        if expected != 0 && checksum(&header[4..], checksum(&data, 0)) != expected {
+            return Err(invalid(self.format, "cabinet data block checksum mismatch"));
+        }
+        if size == 0 || size > MAX_BLOCK_SIZE {
+            return Err(invalid(self.format, "invalid cabinet data block size"));
+        }
+        self.consumed = 0;
+        match self.compression {
+            Compression::None => {
+                if stored_size != size {
+                    return Err(invalid(self.format, "invalid cabinet data block size"));
+                }
+                self.buffer = data;
+            }
+            Compression::MsZip => {
+                self.buffer = self.inflate(&data, size)?;
+                self.history.clone_from(&self.buffer);
+            }
+            Compression::Lzx(_) => {
+                self.buffer.resize(size, 0);
+                if let Some(decoder) = &mut self.lzx {
+                    decoder.decompress(&data, &mut self.buffer)?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decompresses an MSZIP block, a deflate stream that may refer back
+    /// to the data of the previous block.
+    fn inflate(&self, data: &[u8], size: usize) -> Result<Vec<u8>, ArchiveError> {
+        let stream = data
+            .strip_prefix(MSZIP_SIGNATURE)
+            .ok_or_else(|| invalid(self.format, "missing MSZIP block signature"))?;
+        // Feeding the history as a stored block first makes it the window
+        // the block's matches refer to
+        let mut input = Vec::with_capacity(5 + self.history.len() + stream.len());
+        let history_len = self.history.len() as u16;
+        input.push(0);
+        input.extend_from_slice(&history_len.to_le_bytes());
+        input.extend_from_slice(&(!history_len).to_le_bytes());
+        input.extend_from_slice(&self.history);
+        input.extend_from_slice(stream);
+
+        // One spare byte tells a block longer than declared from a full one
+        let mut output = vec![0u8; self.history.len() + size + 1];
+        let mut inflater = Decompress::new(false);
+        let status = inflater
+            .decompress(&input, &mut output, FlushDecompress::Finish)
+            .map_err(|_| invalid(self.format, "corrupt MSZIP block"))?;
+        if status != Status::StreamEnd || inflater.total_out() as usize != output.len() - 1 {
+            return Err(invalid(self.format, "MSZIP block does not match its size"));
+        }
+        output.pop();
+        output.drain(..self.history.len());
+        Ok(output)
+    }
+}
+
+impl<R: Read + Seek> Read for FolderReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.consumed == self.buffer.len() {
+            if !self.fill().map_err(io::Error::other)? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.consumed);
+        buf[..n].copy_from_slice(&self.buffer[self.consumed..self.consumed + n]);
+        self.consumed += n;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// Computes the checksum of data blocks, which XORs little-endian 32-bit
+/// words and then the trailing bytes read most significant first.
+fn checksum(data: &[u8], seed: u32) -> u32 {
+    let mut words = data.chunks_exact(4);
+    let mut sum = words.by_ref().fold(seed, |sum, word| sum ^ le32(word));
+    let tail = words
+        .remainder()
+        .iter()
+        .fold(0u32, |value, &byte| (value << 8) | u32::from(byte));
+    sum ^= tail;
+    sum
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(format: ArchiveFormat, reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format,
+        reason: reason.into(),
+    }
+}
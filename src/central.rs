@@ -0,0 +1,355 @@
+//! Entry comments in the central directory of ZIP archives being written.
+//!
+//! The `zip` crate writes every new entry without a comment and has no way
+//! to set one. [`Commented`] sits between the crate and the output instead:
+//! while the archive is finished, it holds back everything written, so
+//! that the comments can be spliced into the central directory before it
+//! is passed on, and the footer after it updated to match. Entries with
+//! comments that aren't ASCII are flagged as UTF-8 on the way, which the
+//! crate only does for names, so that the comments of old entries kept by
+//! an append read back as they were.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{encoding::ZIP_FLAG_UTF8, error::ArchiveError, format::ArchiveFormat};
+
+const CENTRAL_HEADER_SIGNATURE: [u8; 4] = *b"PK\x01\x02";
+const ZIP64_END_SIGNATURE: [u8; 4] = *b"PK\x06\x06";
+const ZIP64_LOCATOR_SIGNATURE: [u8; 4] = *b"PK\x06\x07";
+const END_SIGNATURE: [u8; 4] = *b"PK\x05\x06";
+/// Size of a central directory header up to the name.
+const CENTRAL_HEADER_SIZE: usize = 46;
+/// Size of the end of central directory record without its comment.
+const END_SIZE: usize = 22;
+/// Size of the ZIP64 end of central directory locator.
+const ZIP64_LOCATOR_SIZE: usize = 20;
+/// Extra field holding the sizes and offsets too large for the header.
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// Value of a classic footer field whose real value is in the ZIP64 record.
+const ZIP64_MARKER: u32 = u32::MAX;
+
+/// The output of a ZIP archive that can take entry comments.
+pub(crate) struct Commented<W> {
+    inner: W,
+    /// Set once the archive is being finished.
+    holding: Arc<AtomicBool>,
+    /// Writes held back since then, with their position.
+    held: Vec<(u64, Vec<u8>)>,
+    /// Position and length of the output as the writes held back leave it,
+    /// once holding has started.
+    cursor: Option<(u64, u64)>,
+}
+
+/// The comments of the entries of a ZIP archive, keyed by stored name.
+pub(crate) struct EntryComments {
+    holding: Arc<AtomicBool>,
+    comments: HashMap<String, String>,
+}
+
+/// Wraps the output of a ZIP archive, returning it with the comments of
+/// the entries to be written into it.
+pub(crate) fn wrap<W>(inner: W) -> (Commented<W>, EntryComments) {
+    let holding = Arc::new(AtomicBool::new(false));
+    let writer = Commented {
+        inner,
+        holding: Arc::clone(&holding),
+        held: Vec::new(),
+        cursor: None,
+    };
+    let comments = EntryComments {
+        holding,
+        comments: HashMap::new(),
+    };
+    (writer, comments)
+}
+
+impl EntryComments {
+    /// Records the comment of the entry stored as `name`.
+    pub(crate) fn insert(&mut self, name: String, comment: String) {
+        self.comments.insert(name, comment);
+    }
+
+    /// Finishes the archive of `writer` with the comments recorded and
+    /// returns the output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be written, or if the
+    /// comments leave the central directory too large for an archive
+    /// without ZIP64 records.
+    pub(crate) fn finish<W: Write + Seek>(
+        self,
+        writer: zip::ZipWriter<Commented<W>>,
+    ) -> Result<W, ArchiveError> {
+        self.holding.store(true, Ordering::Relaxed);
+        writer.finish()?.splice(&self.comments)
+    }
+}
+
+impl<W: Seek> Commented<W> {
+    /// Returns the position and length of the output, starting to track
+    /// them if holding has just started.
+    fn cursor(&mut self) -> io::Result<(u64, u64)> {
+        if let Some(cursor) = self.cursor {
+            return Ok(cursor);
+        }
+        let position = self.inner.stream_position()?;
+        let len = self.inner.seek(SeekFrom::End(0))?;
+        self.cursor = Some((position, len));
+        Ok((position, len))
+    }
+}
+
+impl<W: Write + Seek> Commented<W> {
+    /// Writes what was held back to the output, with `comments` added to
+    /// the central directory, and returns the output.
+    fn splice(mut self, comments: &HashMap<String, String>) -> Result<W, ArchiveError> {
+        let Some((_, len)) = self.cursor else {
+            return Ok(self.inner);
+        };
+        let footer_start = len.saturating_sub((END_SIZE + usize::from(u16::MAX)) as u64);
+        let footer = self.image(footer_start, len);
+        let end = (0..=footer.len().saturating_sub(END_SIZE))
+            .rev()
+            .find(|&at| {
+                footer[at..].starts_with(&END_SIGNATURE)
+                    && at + END_SIZE + usize::from(le16(&footer[at + 20..])) == footer.len()
+            })
+            .ok_or_else(|| invalid("the central directory of the archive written is missing"))?;
+        let end = footer_start + end as u64;
+
+        // The ZIP64 record, if any, follows the central directory and is
+        // found through the locator right before the classic record
+        let locator = end.checked_sub(ZIP64_LOCATOR_SIZE as u64);
+        let zip64 = locator
+            .map(|at| self.image(at, end))
+            .filter(|locator| locator.starts_with(&ZIP64_LOCATOR_SIGNATURE))
+            .map(|locator| le64(&locator[8..]));
+        let (central_start, central_size) = match zip64 {
+            Some(record) => {
+                let fields = self.image(record, record + 56);
+                if !fields.starts_with(&ZIP64_END_SIGNATURE) {
+                    return Err(invalid(
+                        "the ZIP64 record of the archive written is missing",
+                    ));
+                }
+                (le64(&fields[48..]), le64(&fields[40..]))
+            }
+            None => {
+                let fields = self.image(end, end + END_SIZE as u64);
+                (
+                    u64::from(le32(&fields[16..])),
+                    u64::from(le32(&fields[12..])),
+                )
+            }
+        };
+
+        let central = self.image(central_start, central_start + central_size);
+        let mut directory = Vec::with_capacity(central.len());
+        let mut rest = central.as_slice();
+        // Local headers whose flags must now mark UTF-8 too, by position
+        let mut relabeled = Vec::new();
+        while rest.starts_with(&CENTRAL_HEADER_SIGNATURE) && rest.len() >= CENTRAL_HEADER_SIZE {
+            let name_len = usize::from(le16(&rest[28..]));
+            let comment_len = usize::from(le16(&rest[32..]));
+            let len = CENTRAL_HEADER_SIZE + name_len + usize::from(le16(&rest[30..])) + comment_len;
+            let Some(header) = rest.get(..len) else {
+                break;
+            };
+            rest = &rest[len..];
+            let name = &header[CENTRAL_HEADER_SIZE..CENTRAL_HEADER_SIZE + name_len];
+            let start = directory.len();
+            let comment = match std::str::from_utf8(name).ok().and_then(|n| comments.get(n)) {
+                Some(comment) => {
+                    directory.extend_from_slice(&header[..len - comment_len]);
+                    directory.extend_from_slice(comment.as_bytes());
+                    comment.as_bytes()
+                }
+                None => {
+                    directory.extend_from_slice(header);
+                    &header[len - comment_len..]
+                }
+            };
+            let comment_len = comment.len();
+            let header = &mut directory[start..];
+            header[32..34].copy_from_slice(&(comment_len as u16).to_le_bytes());
+            // The crate flags names alone, though it writes any comment,
+            // old entries' included, as UTF-8
+            let flags = le16(&header[8..]);
+            if !comment.is_ascii()
+                && flags & ZIP_FLAG_UTF8 == 0
+                && let Some(local) = local_header(header)
+            {
+                let flags = flags | ZIP_FLAG_UTF8;
+                header[8..10].copy_from_slice(&flags.to_le_bytes());
+                relabeled.push((local, flags));
+            }
+        }
+        directory.extend_from_slice(rest);
+        let directory_size = directory.len() as u64;
+
+        let mut tail = directory;
+        let footer_start = tail.len();
+        tail.extend(self.image(central_start + central_size, len));
+        let footer = &mut tail[footer_start..];
+        let end = (end - central_start - central_size) as usize;
+        if let Some(record) = zip64 {
+            let record = (record - central_start - central_size) as usize;
+            footer[record + 40..record + 48].copy_from_slice(&directory_size.to_le_bytes());
+            footer[end - ZIP64_LOCATOR_SIZE + 8..end - ZIP64_LOCATOR_SIZE + 16]
+                .copy_from_slice(&(central_start + directory_size).to_le_bytes());
+        }
+        let classic_size = match u32::try_from(directory_size) {
+            Ok(size) if size < ZIP64_MARKER => size,
+            _ if zip64.is_some() => ZIP64_MARKER,
+            _ => {
+                return Err(invalid(
+                    "the entry comments make the central directory larger than 4 GiB",
+                ));
+            }
+        };
+        footer[end + 12..end + 16].copy_from_slice(&classic_size.to_le_bytes());
+
+        // Whatever was written ahead of the central directory, such as the
+        // sizes of the last entry, goes out as it was
+        for (position, bytes) in std::mem::take(&mut self.held) {
+            let Some(ahead) = central_start.checked_sub(position) else {
+                continue;
+            };
+            self.inner.seek(SeekFrom::Start(position))?;
+            self.inner
+                .write_all(&bytes[..bytes.len().min(ahead as usize)])?;
+        }
+        for (local, flags) in relabeled {
+            self.inner.seek(SeekFrom::Start(local + 6))?;
+            self.inner.write_all(&flags.to_le_bytes())?;
+        }
+        self.inner.seek(SeekFrom::Start(central_start))?;
+        self.inner.write_all(&tail)?;
+        Ok(self.inner)
+    }
+
+    /// Returns the bytes between `start` and `end` as the writes held back
+    /// leave them.
+    fn image(&self, start: u64, end: u64) -> Vec<u8> {
+        let mut image = vec![0; end.saturating_sub(start) as usize];
+        for (position, bytes) in &self.held {
+            let from = start.max(*position);
+            let to = end.min(*position + bytes.len() as u64);
+            if from < to {
+                image[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&bytes[(from - position) as usize..(to - position) as usize]);
+            }
+        }
+        image
+    }
+}
+
+impl<W: Write + Seek> Write for Commented<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.holding.load(Ordering::Relaxed) {
+            return self.inner.write(buf);
+        }
+        let (position, len) = self.cursor()?;
+        match self.held.last_mut() {
+            Some((start, bytes)) if *start + bytes.len() as u64 == position => {
+                bytes.extend_from_slice(buf);
+            }
+            _ => self.held.push((position, buf.to_vec())),
+        }
+        let position = position + buf.len() as u64;
+        self.cursor = Some((position, len.max(position)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.holding.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for Commented<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if !self.holding.load(Ordering::Relaxed) {
+            return self.inner.seek(pos);
+        }
+        let (position, len) = self.cursor()?;
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => position.checked_add_signed(offset),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.cursor = Some((position, len));
+        Ok(position)
+    }
+}
+
+impl<W: Read> Read for Commented<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.holding.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "reading a ZIP archive while it is finished",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Returns the position of the local header of the entry of a central
+/// directory `header`, which is in its ZIP64 extra field when it doesn't
+/// fit.
+fn local_header(header: &[u8]) -> Option<u64> {
+    let offset = le32(&header[42..]);
+    if offset != ZIP64_MARKER {
+        return Some(offset.into());
+    }
+    let name_len = usize::from(le16(&header[28..]));
+    let mut extra = header.get(CENTRAL_HEADER_SIZE + name_len..)?;
+    extra = extra.get(..usize::from(le16(&header[30..])))?;
+    while extra.len() >= 4 {
+        let (id, len) = (le16(extra), usize::from(le16(&extra[2..])));
+        let data = extra.get(4..4 + len)?;
+        if id == ZIP64_EXTRA_ID {
+            // The sizes come first, for those that don't fit either
+            let skip = [24, 20]
+                .iter()
+                .filter(|&&at| le32(&header[at..]) == ZIP64_MARKER)
+                .count();
+            return data.get(8 * skip..8 * skip + 8).map(le64);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Zip,
+        reason: reason.into(),
+    }
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(le)
+}
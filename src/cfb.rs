@@ -0,0 +1,515 @@
+//! OLE compound files, the container of Windows Installer packages.
+//!
+//! A compound file is a small file system: the file is divided into
+//! sectors chained together by a file allocation table, and a directory
+//! of storages (directories) and streams (files) organised as red-black
+//! trees of siblings. Streams below a size cutoff live in a separate mini
+//! stream with its own allocation table. Windows Installer packages store
+//! their database tables as streams with compressed names, and their
+//! files in cabinets, either as streams or next to the package.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    cab,
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+};
+
+/// Magic number at the start of every compound file.
+pub(crate) const CFB_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+/// Size of the header, which the first sector follows in version 3 files.
+const HEADER_SIZE: usize = 512;
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 128;
+/// Header entries of the DIFAT, the list of FAT sectors.
+const HEADER_DIFAT_ENTRIES: usize = 109;
+/// Sector numbers above this one have special meanings.
+const MAX_REGULAR_SECTOR: u32 = 0xFFFF_FFFA;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+/// Marks an absent sibling or child in the directory.
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+const TYPE_STORAGE: u8 = 1;
+const TYPE_STREAM: u8 = 2;
+const TYPE_ROOT: u8 = 5;
+/// Upper bound on storage nesting.
+const MAX_DEPTH: usize = 64;
+/// Class ID of Windows Installer storages, apart from its first byte.
+const MSI_CLSID_TAIL: [u8; 15] = [
+    0x10, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46,
+];
+/// Marks the name of a Windows Installer database table.
+const MSI_TABLE_MARKER: u16 = 0x4840;
+/// Windows Installer stream names pack two characters of this alphabet
+/// into each code unit from 0x3800, or one from 0x4800.
+const MSI_NAME_ALPHABET: &[u8; 64] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz._";
+
+struct DirEntry {
+    name: String,
+    kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    mtime: u64,
+    start: u32,
+    size: u64,
+}
+
+/// Walks every storage and stream of a compound file, parents before
+/// their children.
+///
+/// Windows Installer stream names are decoded, with database tables
+/// prefixed by `!`, and control characters in names are written as their
+/// number in brackets, such as `[5]SummaryInformation`. If
+/// `expand_cabinets` is set, streams holding a cabinet are listed as a
+/// directory of its files instead.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(reader: R, expand_cabinets: bool, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut cfb = CompoundFile::open(reader)?;
+    let root = cfb
+        .entries
+        .first()
+        .ok_or_else(|| invalid("missing root entry"))?;
+    if root.kind != TYPE_ROOT {
+        return Err(invalid("missing root entry"));
+    }
+
+    // Storages still to list, with their path and depth
+    let mut pending = vec![(0u32, String::new(), 0usize)];
+    let mut listed = HashSet::new();
+    while let Some((storage, prefix, depth)) = pending.pop() {
+        if depth > MAX_DEPTH {
+            return Err(invalid("directory hierarchy is too deep"));
+        }
+        for index in cfb.children(storage, &mut listed)? {
+            let child = &cfb.entries[index as usize];
+            let name = if prefix.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", prefix, child.name)
+            };
+            let mut info = EntryInfo {
+                name: name.clone(),
+                kind: EntryKind::Directory,
+                size: 0,
+                compressed_size: None,
+                mtime: entry::filetime(child.mtime),
+                mode: None,
+                link_target: None,
+                owner: None,
+                xattrs: Vec::new(),
+                raw_name: None,
+                special: None,
+                comment: None,
+                extra_fields: Vec::new(),
+                encrypted: false,
+            };
+            let keep_going = match child.kind {
+                TYPE_STORAGE => {
+                    pending.push((index, name, depth + 1));
+                    visit(&info, &mut io::empty())?
+                }
+                TYPE_STREAM => {
+                    let mut stream = cfb.stream(index)?;
+                    if expand_cabinets && is_cabinet(&mut stream)? {
+                        visit(&info, &mut io::empty())?
+                            && expand_cabinet(&mut stream, &name, &mut visit)?
+                    } else {
+                        info.kind = EntryKind::File;
+                        info.size = stream.len;
+                        visit(&info, &mut stream)?
+                    }
+                }
+                _ => true,
+            };
+            if !keep_going {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a stream holds a cabinet, leaving it at its start.
+fn is_cabinet<R: Read + Seek>(stream: &mut Stream<'_, R>) -> io::Result<bool> {
+    let mut magic = Vec::new();
+    stream
+        .take(cab::CAB_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    stream.seek(SeekFrom::Start(0))?;
+    Ok(magic == cab::CAB_MAGIC)
+}
+
+/// Lists the files of the cabinet in `stream` below `name`, returning
+/// `false` if the callback asked to stop.
+fn expand_cabinet<R, F>(
+    stream: &mut Stream<'_, R>,
+    name: &str,
+    visit: &mut F,
+) -> Result<bool, ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut keep_going = true;
+    cab::walk(stream, ArchiveFormat::Msi, |file, data| {
+        let mut file = file.clone();
+        file.name = format!("{}/{}", name, file.name);
+        keep_going = visit(&file, data)?;
+        Ok(keep_going)
+    })?;
+    Ok(keep_going)
+}
+
+/// The allocation tables and directory of a compound file.
+struct CompoundFile<R> {
+    reader: R,
+    sector_shift: u32,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    /// Sectors of the mini stream, which holds the small streams.
+    mini_stream: Vec<u32>,
+    mini_cutoff: u64,
+    entries: Vec<DirEntry>,
+}
+
+impl<R: Read + Seek> CompoundFile<R> {
+    fn open(mut reader: R) -> Result<Self, ArchiveError> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        if !header.starts_with(CFB_MAGIC) {
+            return Err(invalid("missing compound file magic"));
+        }
+        let major_version = le16(&header[26..]);
+        let sector_shift = u32::from(le16(&header[30..]));
+        let valid_shift = match major_version {
+            3 => sector_shift == 9,
+            4 => sector_shift == 12,
+            _ => false,
+        };
+        if !valid_shift || le16(&header[28..]) != 0xFFFE || le16(&header[32..]) != 6 {
+            return Err(invalid("unsupported compound file version"));
+        }
+        let fat_sectors = le32(&header[44..]);
+        let first_dir_sector = le32(&header[48..]);
+        let mini_cutoff = u64::from(le32(&header[56..]));
+        let first_mini_fat_sector = le32(&header[60..]);
+        let mut difat_sector = le32(&header[68..]);
+        let difat_sectors = le32(&header[72..]);
+
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let sector_size = 1usize << sector_shift;
+        // No table can list more sectors than the file holds
+        let max_sectors = (file_len >> sector_shift) as usize + 1;
+        let mut cfb = Self {
+            reader,
+            sector_shift,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            mini_cutoff,
+            entries: Vec::new(),
+        };
+
+        let mut fat_locations: Vec<u32> = header[76..76 + HEADER_DIFAT_ENTRIES * 4]
+            .chunks_exact(4)
+            .map(le32)
+            .collect();
+        let mut sector = vec![0u8; sector_size];
+        for _ in 0..difat_sectors.min(max_sectors as u32) {
+            if difat_sector > MAX_REGULAR_SECTOR {
+                break;
+            }
+            cfb.read_sector(difat_sector, &mut sector)?;
+            let (entries, next) = sector.split_at(sector_size - 4);
+            fat_locations.extend(entries.chunks_exact(4).map(le32));
+            difat_sector = le32(next);
+        }
+        fat_locations.truncate(fat_sectors.min(max_sectors as u32) as usize);
+        for &location in &fat_locations {
+            if location > MAX_REGULAR_SECTOR {
+                break;
+            }
+            cfb.read_sector(location, &mut sector)?;
+            cfb.fat.extend(sector.chunks_exact(4).map(le32));
+        }
+
+        let directory = cfb.read_chain(first_dir_sector)?;
+        for entry in directory.chunks_exact(DIR_ENTRY_SIZE) {
+            cfb.entries.push(parse_dir_entry(entry, major_version));
+        }
+        let is_msi = directory
+            .get(80..96)
+            .is_some_and(|clsid| clsid[1..] == MSI_CLSID_TAIL);
+        if is_msi {
+            for entry in &mut cfb.entries {
+                entry.name = msi_name(&entry.name);
+            }
+        }
+        for entry in &mut cfb.entries {
+            entry.name = printable_name(&entry.name);
+        }
+
+        let mini_fat = cfb.read_chain(first_mini_fat_sector)?;
+        cfb.mini_fat = mini_fat.chunks_exact(4).map(le32).collect();
+        if let Some(root) = cfb.entries.first() {
+            let start = root.start;
+            cfb.mini_stream = cfb.chain(&cfb.fat, start)?;
+        }
+        Ok(cfb)
+    }
+
+    /// Returns the entries of `storage`'s sibling tree in name order,
+    /// recording each in `listed` to reject loops.
+    fn children(&self, storage: u32, listed: &mut HashSet<u32>) -> Result<Vec<u32>, ArchiveError> {
+        let mut children = Vec::new();
+        let mut stack = Vec::new();
+        let mut node = self.entries[storage as usize].child;
+        loop {
+            while node != NO_STREAM {
+                let entry = self
+                    .entries
+                    .get(node as usize)
+                    .ok_or_else(|| invalid("directory entry out of range"))?;
+                if !listed.insert(node) {
+                    return Err(invalid("directory tree loops"));
+                }
+                stack.push(node);
+                node = entry.left;
+            }
+            let Some(next) = stack.pop() else {
+                return Ok(children);
+            };
+            children.push(next);
+            node = self.entries[next as usize].right;
+        }
+    }
+
+    /// Opens the stream of directory entry `index`.
+    fn stream(&mut self, index: u32) -> Result<Stream<'_, R>, ArchiveError> {
+        let entry = &self.entries[index as usize];
+        let (start, len) = (entry.start, entry.size);
+        if len < self.mini_cutoff {
+            let sectors = self.chain(&self.mini_fat, start)?;
+            if (sectors.len() as u64) << 6 < len {
+                return Err(invalid("stream is truncated"));
+            }
+            // Small streams are read in full from the mini stream
+            let mut data = vec![0u8; len as usize];
+            for (chunk, &sector) in data.chunks_mut(64).zip(&sectors) {
+                let offset = u64::from(sector) << 6;
+                let mut mini_stream = Stream {
+                    cfb: self,
+                    sectors: None,
+                    len: u64::MAX,
+                    position: offset,
+                };
+                mini_stream.read_exact(chunk)?;
+            }
+            return Ok(Stream {
+                cfb: self,
+                sectors: Some(StreamData::Memory(data)),
+                len,
+                position: 0,
+            });
+        }
+        let sectors = self.chain(&self.fat, start)?;
+        if (sectors.len() as u64) << self.sector_shift < len {
+            return Err(invalid("stream is truncated"));
+        }
+        Ok(Stream {
+            cfb: self,
+            sectors: Some(StreamData::Sectors(sectors)),
+            len,
+            position: 0,
+        })
+    }
+
+    /// Follows the chain of sectors starting at `start` through `table`.
+    fn chain(&self, table: &[u32], start: u32) -> Result<Vec<u32>, ArchiveError> {
+        let mut sectors = Vec::new();
+        let mut sector = start;
+        while sector != END_OF_CHAIN {
+            if sectors.len() == table.len() {
+                return Err(invalid("sector chain loops"));
+            }
+            sectors.push(sector);
+            sector = *table
+                .get(sector as usize)
+                .ok_or_else(|| invalid("sector out of range"))?;
+        }
+        Ok(sectors)
+    }
+
+    /// Reads the whole chain of regular sectors starting at `start`.
+    fn read_chain(&mut self, start: u32) -> Result<Vec<u8>, ArchiveError> {
+        let sectors = self.chain(&self.fat, start)?;
+        let mut data = vec![0u8; sectors.len() << self.sector_shift];
+        for (chunk, &sector) in data.chunks_mut(1 << self.sector_shift).zip(&sectors) {
+            self.read_sector(sector, chunk)?;
+        }
+        Ok(data)
+    }
+
+    fn read_sector(&mut self, sector: u32, buffer: &mut [u8]) -> Result<(), ArchiveError> {
+        let offset = (u64::from(sector) + 1) << self.sector_shift;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(buffer)?;
+        Ok(())
+    }
+}
+
+enum StreamData {
+    /// Regular sectors holding the stream.
+    Sectors(Vec<u32>),
+    /// A small stream read from the mini stream.
+    Memory(Vec<u8>),
+}
+
+/// Reads a stream, or the mini stream itself when `sectors` is `None`.
+struct Stream<'a, R> {
+    cfb: &'a mut CompoundFile<R>,
+    sectors: Option<StreamData>,
+    len: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> Read for Stream<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let shift = self.cfb.sector_shift;
+        let sectors = match &self.sectors {
+            Some(StreamData::Memory(data)) => {
+                let start = self.position as usize;
+                let n = buf.len().min(remaining as usize);
+                buf[..n].copy_from_slice(&data[start..start + n]);
+                self.position += n as u64;
+                return Ok(n);
+            }
+            Some(StreamData::Sectors(sectors)) => sectors,
+            None => &self.cfb.mini_stream,
+        };
+        let Some(&sector) = sectors.get((self.position >> shift) as usize) else {
+            return Err(io::Error::other(invalid("stream is truncated")));
+        };
+        let within = self.position & ((1 << shift) - 1);
+        let n = buf
+            .len()
+            .min(((1 << shift) - within) as usize)
+            .min(remaining.min(usize::MAX as u64) as usize);
+        let offset = ((u64::from(sector) + 1) << shift) + within;
+        self.cfb.reader.seek(SeekFrom::Start(offset))?;
+        self.cfb.reader.read_exact(&mut buf[..n])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for Stream<'_, R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+fn parse_dir_entry(entry: &[u8], major_version: u16) -> DirEntry {
+    let name_len = usize::from(le16(&entry[64..])).min(64);
+    let units: Vec<u16> = entry[..name_len]
+        .chunks_exact(2)
+        .map(le16)
+        .take_while(|&unit| unit != 0)
+        .collect();
+    let mut size = le64(&entry[120..]);
+    // Version 3 files may leave garbage in the high half
+    if major_version == 3 {
+        size &= 0xFFFF_FFFF;
+    }
+    DirEntry {
+        name: String::from_utf16_lossy(&units),
+        kind: entry[66],
+        left: le32(&entry[68..]),
+        right: le32(&entry[72..]),
+        child: le32(&entry[76..]),
+        mtime: le64(&entry[108..]),
+        start: le32(&entry[116..]),
+        size,
+    }
+}
+
+/// Decodes a Windows Installer stream name.
+fn msi_name(name: &str) -> String {
+    let mut decoded = String::new();
+    for c in name.chars() {
+        let unit = c as u32;
+        match unit {
+            0x3800..0x4800 => {
+                let value = (unit - 0x3800) as usize;
+                decoded.push(char::from(MSI_NAME_ALPHABET[value & 0x3F]));
+                decoded.push(char::from(MSI_NAME_ALPHABET[value >> 6]));
+            }
+            0x4800..0x4840 => {
+                decoded.push(char::from(MSI_NAME_ALPHABET[(unit - 0x4800) as usize]));
+            }
+            _ if unit == u32::from(MSI_TABLE_MARKER) => decoded.push('!'),
+            _ => decoded.push(c),
+        }
+    }
+    decoded
+}
+
+/// Writes control characters, which mark special streams such as
+/// `\x05SummaryInformation`, as their number in brackets.
+fn printable_name(name: &str) -> String {
+    let mut printable = String::new();
+    for c in name.chars() {
+        if c.is_control() {
+            printable.push_str(&format!("[{}]", c as u32));
+        } else {
+            printable.push(c);
+        }
+    }
+    printable
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(array)
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Msi,
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,306 @@
+//! `cpio` archives, as found in the payloads of macOS installer packages.
+//!
+//! The portable ASCII (`odc`, magic `070707`) and SVR4 (`newc`, magic
+//! `070701`, and `070702` with checksums) variants are read. The old
+//! binary variant is rejected as unsupported.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    entry::{EntryInfo, EntryKind, Owner, SpecialFile, SpecialKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+};
+
+/// Magic numbers of the supported variants.
+const ODC_MAGIC: &[u8] = b"070707";
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+/// Header sizes, including the magic.
+const ODC_HEADER_SIZE: usize = 76;
+const NEWC_HEADER_SIZE: usize = 110;
+/// Name of the entry marking the end of the archive.
+const TRAILER: &str = "TRAILER!!!";
+/// Upper bound on the length of an entry name.
+const MAX_NAME_SIZE: u64 = 64 * 1024;
+/// Upper bound on the length of a symlink target.
+const MAX_LINK_SIZE: u64 = 64 * 1024;
+
+/// `S_IFMT` and the file types read from it.
+const TYPE_MASK: u32 = 0o170000;
+const TYPE_DIRECTORY: u32 = 0o040000;
+const TYPE_FILE: u32 = 0o100000;
+const TYPE_SYMLINK: u32 = 0o120000;
+const TYPE_FIFO: u32 = 0o010000;
+const TYPE_CHAR: u32 = 0o020000;
+const TYPE_BLOCK: u32 = 0o060000;
+const TYPE_SOCKET: u32 = 0o140000;
+
+/// Returns `true` if `data` starts with the magic of a supported variant.
+pub(crate) fn is_cpio(data: &[u8]) -> bool {
+    [ODC_MAGIC, NEWC_MAGIC, NEWC_CRC_MAGIC]
+        .iter()
+        .any(|magic| data.starts_with(magic))
+}
+
+/// The fields of an entry header that are used.
+struct Header {
+    newc: bool,
+    inode: (u64, u64),
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    links: u64,
+    /// Major and minor number of a device node.
+    rdev: (u32, u32),
+    mtime: u64,
+    name_size: u64,
+    file_size: u64,
+}
+
+/// Walks every entry of a `cpio` archive in storage order, attributing
+/// errors to `format`, the archive it is nested in.
+///
+/// A leading `./` is stripped from names, and the `.` entry is skipped.
+/// In `newc` archives only the last of a set of hard links carries the
+/// data; the others are reported as hard links to it once it is read.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(
+    mut reader: R,
+    format: ArchiveFormat,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    // Hard links still waiting for the entry with their data
+    let mut pending_links: HashMap<(u64, u64), Vec<EntryInfo>> = HashMap::new();
+    loop {
+        let header = read_header(&mut reader, format)?;
+        if header.name_size == 0 || header.name_size > MAX_NAME_SIZE {
+            return Err(invalid(format, "invalid cpio name size"));
+        }
+        let mut name = vec![0u8; header.name_size as usize];
+        reader.read_exact(&mut name)?;
+        // `newc` pads the header and name to a multiple of four bytes
+        let header_size = if header.newc {
+            NEWC_HEADER_SIZE
+        } else {
+            ODC_HEADER_SIZE
+        };
+        skip(
+            &mut reader,
+            padding(&header, header_size as u64 + header.name_size),
+        )?;
+        let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(&name)).into_owned();
+        if name == TRAILER {
+            // Links without data in any member are empty files
+            let mut empty: Vec<_> = pending_links.into_values().flatten().collect();
+            empty.sort_by(|a, b| a.name.cmp(&b.name));
+            for info in empty {
+                if !visit(&info, &mut io::empty())? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let name = name.trim_start_matches("./").trim_end_matches('/');
+
+        let kind = match header.mode & TYPE_MASK {
+            TYPE_DIRECTORY => EntryKind::Directory,
+            TYPE_FILE => EntryKind::File,
+            TYPE_SYMLINK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let mut info = EntryInfo {
+            name: name.to_string(),
+            kind,
+            size: if kind == EntryKind::File {
+                header.file_size
+            } else {
+                0
+            },
+            compressed_size: None,
+            mtime: Some(UNIX_EPOCH + Duration::from_secs(header.mtime)),
+            mode: Some(header.mode & 0o7777),
+            link_target: None,
+            owner: Some(Owner {
+                uid: header.uid,
+                gid: header.gid,
+                user: None,
+                group: None,
+            }),
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: special(&header),
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        if kind == EntryKind::Symlink {
+            if header.file_size > MAX_LINK_SIZE {
+                return Err(invalid(format, "cpio symlink target is too long"));
+            }
+            let mut target = vec![0u8; header.file_size as usize];
+            reader.read_exact(&mut target)?;
+            info.link_target = Some(String::from_utf8_lossy(&target).into_owned());
+        }
+
+        let keep_going = if name.is_empty() || name == "." {
+            skip(&mut reader, header.file_size)?;
+            true
+        } else if header.newc && kind == EntryKind::File && header.links > 1 {
+            if header.file_size == 0 {
+                pending_links.entry(header.inode).or_default().push(info);
+                true
+            } else {
+                let links = pending_links.remove(&header.inode).unwrap_or_default();
+                visit_data(&mut reader, &header, &info, &mut visit)?
+                    && links.into_iter().try_fold(true, |keep_going, mut link| {
+                        if !keep_going {
+                            return Ok(false);
+                        }
+                        link.kind = EntryKind::Hardlink;
+                        link.link_target = Some(info.name.clone());
+                        visit(&link, &mut io::empty())
+                    })?
+            }
+        } else if kind == EntryKind::Symlink {
+            visit(&info, &mut io::empty())?
+        } else {
+            visit_data(&mut reader, &header, &info, &mut visit)?
+        };
+        if !keep_going {
+            return Ok(());
+        }
+        skip(&mut reader, padding(&header, header.file_size))?;
+    }
+}
+
+/// Passes an entry's data to `visit` and skips whatever it left unread.
+fn visit_data<R, F>(
+    reader: &mut R,
+    header: &Header,
+    info: &EntryInfo,
+    visit: &mut F,
+) -> Result<bool, ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut data = reader.take(header.file_size);
+    let keep_going = visit(info, &mut data)?;
+    let remaining = data.limit();
+    skip(reader, remaining)?;
+    Ok(keep_going)
+}
+
+/// Reads and decodes the next entry header.
+fn read_header<R: Read>(reader: &mut R, format: ArchiveFormat) -> Result<Header, ArchiveError> {
+    let mut header = [0u8; NEWC_HEADER_SIZE];
+    reader.read_exact(&mut header[..ODC_HEADER_SIZE])?;
+    let magic = &header[..6];
+    if magic == ODC_MAGIC {
+        let field = |start: usize, len: usize| {
+            number(&header[start..start + len], 8)
+                .ok_or_else(|| invalid(format, "malformed cpio header"))
+        };
+        return Ok(Header {
+            newc: false,
+            inode: (field(6, 6)?, field(12, 6)?),
+            mode: field(18, 6)? as u32,
+            uid: field(24, 6)?,
+            gid: field(30, 6)?,
+            links: field(36, 6)?,
+            // Packed the way 16-bit systems did
+            rdev: {
+                let rdev = field(42, 6)?;
+                ((rdev >> 8) as u32, (rdev & 0xff) as u32)
+            },
+            mtime: field(48, 11)?,
+            name_size: field(59, 6)?,
+            file_size: field(65, 11)?,
+        });
+    }
+    if magic != NEWC_MAGIC && magic != NEWC_CRC_MAGIC {
+        if magic[..2] == [0xC7, 0x71] || magic[..2] == [0x71, 0xC7] {
+            return Err(ArchiveError::unsupported_static("binary cpio archives"));
+        }
+        return Err(invalid(format, "missing cpio magic"));
+    }
+    reader.read_exact(&mut header[ODC_HEADER_SIZE..])?;
+    let field = |index: usize| {
+        let start = 6 + index * 8;
+        number(&header[start..start + 8], 16)
+            .ok_or_else(|| invalid(format, "malformed cpio header"))
+    };
+    Ok(Header {
+        newc: true,
+        // The device is split into major and minor numbers
+        inode: (field(7)? << 32 | field(8)?, field(0)?),
+        mode: field(1)? as u32,
+        uid: field(2)?,
+        gid: field(3)?,
+        links: field(4)?,
+        rdev: (field(9)? as u32, field(10)? as u32),
+        mtime: field(5)?,
+        name_size: field(11)?,
+        file_size: field(6)?,
+    })
+}
+
+/// Returns the special file described by `header`, if it is one.
+fn special(header: &Header) -> Option<SpecialFile> {
+    let kind = match header.mode & TYPE_MASK {
+        TYPE_FIFO => SpecialKind::Fifo,
+        TYPE_CHAR => SpecialKind::CharDevice,
+        TYPE_BLOCK => SpecialKind::BlockDevice,
+        TYPE_SOCKET => SpecialKind::Socket,
+        _ => return None,
+    };
+    let (major, minor) = if kind.is_device() {
+        header.rdev
+    } else {
+        (0, 0)
+    };
+    Some(SpecialFile {
+        kind,
+        major,
+        minor,
+    })
+}
+
+/// Returns the bytes of padding that follow `len` bytes in `newc` archives.
+fn padding(header: &Header, len: u64) -> u64 {
+    if header.newc {
+        len.next_multiple_of(4) - len
+    } else {
+        0
+    }
+}
+
+fn skip<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn number(bytes: &[u8], radix: u32) -> Option<u64> {
+    u64::from_str_radix(std::str::from_utf8(bytes).ok()?, radix).ok()
+}
+
+fn invalid(format: ArchiveFormat, reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format,
+        reason: reason.into(),
+    }
+}
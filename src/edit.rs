@@ -0,0 +1,368 @@
+//! Editing existing archives.
+//!
+//! ZIP entries are copied between archives in their compressed form, so
+//! entries that are kept are neither decompressed nor recompressed. Other
+//! formats have no such shortcut and are rewritten entry by entry. Edited
+//! archives are written to a temporary file next to their destination,
+//! which then replaces it, so a failed edit leaves files untouched.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::{ArchiveError, PathContext},
+    format::{self, ArchiveFormat},
+    glob::Glob,
+    volume::{self, Location},
+    writer::{self, Codec, Encoder, FormatWriter},
+};
+
+/// Copies the ZIP archive at `location` to `dst`, leaving out the entries
+/// matching any of `patterns`, and returns the number of entries left out.
+///
+/// `dst` may be the archive itself, in which case it is replaced once the
+/// copy is complete.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * `format` is not ZIP
+/// * The archive cannot be read
+/// * `dst` cannot be written
+/// * `dst` is the archive itself and the archive is split
+pub(crate) fn delete_zip_entries(
+    location: &Location,
+    format: ArchiveFormat,
+    dst: &Path,
+    patterns: &[&str],
+) -> Result<usize, ArchiveError> {
+    if format != ArchiveFormat::Zip {
+        return Err(ArchiveError::unsupported_static(
+            "deleting entries from non-ZIP archives",
+        ));
+    }
+    let patterns: Vec<Glob> = patterns.iter().map(|pattern| Glob::new(pattern)).collect();
+    write_over(location, dst, |file| {
+        let mut archive = zip::ZipArchive::new(location.open(format)?)?;
+        let mut writer = zip::ZipWriter::new(BufWriter::new(file));
+        writer.set_raw_comment(archive.comment().into());
+
+        let mut removed = 0;
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            let name = entry::normalize_name(file.name());
+            if patterns.iter().any(|glob| glob.matches(&name)) {
+                removed += 1;
+            } else {
+                writer.raw_copy_file(file)?;
+            }
+        }
+        writer.finish()?.flush()?;
+        Ok(removed)
+    })
+}
+
+/// Copies the archive at `location` to `dst` in the same format, with the
+/// content of the files named in `updates` replaced.
+///
+/// Replaced files keep their name and permissions and get the current time
+/// as their modification time. ZIP entries that are not replaced are copied
+/// without being recompressed; other formats are rewritten in full. `dst`
+/// may be the archive itself, in which case it is replaced once the copy is
+/// complete.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The format cannot be written
+/// * A name in `updates` is missing from the archive or is not a file
+/// * The archive cannot be read
+/// * `dst` cannot be written
+/// * `dst` is the archive itself and the archive is split
+pub(crate) fn update_entries(
+    location: &Location,
+    format: ArchiveFormat,
+    dst: &Path,
+    updates: &[(&str, &[u8])],
+) -> Result<(), ArchiveError> {
+    let updates: HashMap<String, &[u8]> = updates
+        .iter()
+        .map(|&(name, data)| (entry::normalize_name(name), data))
+        .collect();
+    let mut missing: BTreeSet<&str> = updates.keys().map(String::as_str).collect();
+    write_over(location, dst, |file| {
+        let file = BufWriter::new(file);
+        if format == ArchiveFormat::Zip {
+            let mut archive = zip::ZipArchive::new(location.open(format)?)?;
+            let mut writer = zip::ZipWriter::new(file);
+            writer.set_raw_comment(archive.comment().into());
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                match replacement(&entry::zip_info(&file), &updates, &mut missing)? {
+                    Some((info, mut data)) => {
+                        writer::add_zip(&mut writer, &info, &mut data, None, false)?
+                    }
+                    None => writer.raw_copy_file(file)?,
+                }
+            }
+            require_found(&missing)?;
+            writer.finish()?.flush()?;
+        } else {
+            let mut writer = FormatWriter::new(format, file)?;
+            entry::for_each_entry(location, format, |info, data| {
+                match replacement(info, &updates, &mut missing)? {
+                    Some((info, mut replacement)) => writer.add(&info, &mut replacement)?,
+                    None => writer.add(info, data)?,
+                }
+                Ok(true)
+            })?;
+            require_found(&missing)?;
+            writer.finish()?.flush()?;
+        }
+        Ok(())
+    })
+}
+
+/// Returns the entry to write in place of `info` and its new content, or
+/// `None` if `info` is not to be updated.
+fn replacement<'a>(
+    info: &EntryInfo,
+    updates: &HashMap<String, &'a [u8]>,
+    missing: &mut BTreeSet<&str>,
+) -> Result<Option<(EntryInfo, &'a [u8])>, ArchiveError> {
+    let name = entry::normalize_name(&info.name);
+    let Some(&data) = updates.get(&name) else {
+        return Ok(None);
+    };
+    if info.kind != EntryKind::File {
+        return Err(entry::not_a_file(&info.name));
+    }
+    // Archives may hold several entries with the same name, which are all
+    // updated
+    missing.remove(name.as_str());
+    let info = EntryInfo {
+        size: data.len() as u64,
+        compressed_size: None,
+        mtime: Some(SystemTime::now()),
+        ..info.clone()
+    };
+    Ok(Some((info, data)))
+}
+
+/// Fails with the first of the files to update that were not found.
+fn require_found(missing: &BTreeSet<&str>) -> Result<(), ArchiveError> {
+    match missing.first() {
+        Some(name) => Err(ArchiveError::not_found_dynamic(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Writes the entries of the archive at `location` to `dst`, in the format
+/// given by the extension of `dst`, and returns that format.
+///
+/// Entries keep their paths, types and whatever metadata the new format
+/// can record. Hard links become copies of the files they point to in
+/// formats without hard links, and special files are left out, as none of
+/// the writable formats can hold them.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The extension of `dst` is not recognized or its format can't be
+///   written
+/// * An entry cannot be represented in the new format
+/// * The archive cannot be read
+/// * `dst` cannot be written
+pub(crate) fn convert(
+    location: &Location,
+    format: ArchiveFormat,
+    dst: &Path,
+) -> Result<ArchiveFormat, ArchiveError> {
+    let dst_format = format::detect_from_extension(dst)?;
+    write_over(location, dst, |file| {
+        let mut writer = FormatWriter::new(dst_format, BufWriter::new(file))?;
+        let keeps_links = writer.supports_hard_links();
+        entry::for_each_entry(location, format, |info, data| {
+            let mut name = entry::normalize_name(&info.name);
+            // The archive root (`./`) carries no content of its own
+            if name.is_empty() || info.kind == EntryKind::Other {
+                return Ok(true);
+            }
+            if info.kind == EntryKind::Directory {
+                name.push('/');
+            }
+            let info = EntryInfo {
+                name,
+                ..info.clone()
+            };
+            if info.kind == EntryKind::Hardlink && !keeps_links {
+                let target = info.link_target.as_deref().unwrap_or_default();
+                entry::find_entry(location, format, target, |found, data| {
+                    let copy = EntryInfo {
+                        kind: EntryKind::File,
+                        size: found.size,
+                        link_target: None,
+                        ..info.clone()
+                    };
+                    writer.add(&copy, data)
+                })?;
+            } else if format.is_single_file() {
+                // Single-file streams don't record the size of their content
+                writer.add_unsized(&info, data)?;
+            } else {
+                writer.add(&info, data)?;
+            }
+            Ok(true)
+        })?;
+        writer.finish()?.flush()?;
+        Ok(dst_format)
+    })
+}
+
+/// Copies the archive at `location` to `dst` compressed with `codec`, and
+/// returns the format of the copy.
+///
+/// TAR archives and single-file streams are decompressed and compressed
+/// again as a whole, so their contents stay byte for byte the same; the
+/// copy takes the format matching `codec`, such as `.tar.zst` for
+/// Zstandard. ZIP and 7z archives stay ZIP and 7z archives with every file
+/// compressed again. Directories, links and encrypted files in ZIP
+/// archives are copied as they are.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The level of `codec` is out of range
+/// * The format or `codec` can't be written, or `codec` can't be used in
+///   the format
+/// * The archive cannot be read
+/// * `dst` cannot be written
+pub(crate) fn recompress(
+    location: &Location,
+    format: ArchiveFormat,
+    dst: &Path,
+    codec: Codec,
+) -> Result<ArchiveFormat, ArchiveError> {
+    let codec = codec.validate()?;
+    if format.is_tar() || format.is_single_file() {
+        let dst_format = if format.is_tar() {
+            codec.tar_format()
+        } else {
+            codec.single_format()?
+        };
+        return write_over(location, dst, |file| {
+            let mut decoder = entry::decoder(format, location.open(format)?)?;
+            let mut encoder = Encoder::with_codec(codec, BufWriter::new(file))?;
+            io::copy(&mut decoder, &mut encoder)?;
+            encoder.finish()?.flush()?;
+            Ok(dst_format)
+        });
+    }
+
+    match format {
+        ArchiveFormat::Zip => {
+            write_over(location, dst, |file| {
+                let mut archive = zip::ZipArchive::new(location.open(format)?)?;
+                let mut writer = zip::ZipWriter::new(BufWriter::new(file));
+                writer.set_raw_comment(archive.comment().into());
+                for i in 0..archive.len() {
+                    let file = archive.by_index_raw(i)?;
+                    let info = entry::zip_info(&file);
+                    if info.kind != EntryKind::File || file.encrypted() {
+                        writer.raw_copy_file(file)?;
+                        continue;
+                    }
+                    drop(file);
+                    writer::add_zip(
+                        &mut writer,
+                        &info,
+                        &mut archive.by_index(i)?,
+                        Some(codec),
+                        false,
+                    )?;
+                }
+                writer.finish()?.flush()?;
+                Ok(format)
+            })
+        }
+        ArchiveFormat::SevenZ => {
+            let method = codec.sevenz_method()?;
+            write_over(location, dst, |file| {
+                let mut archive = sevenz_rust2::ArchiveWriter::new(BufWriter::new(file))?;
+                archive.set_content_methods(vec![method]);
+                let mut writer = FormatWriter::SevenZ(archive);
+                entry::for_each_entry(location, format, |info, data| {
+                    writer.add(info, data)?;
+                    Ok(true)
+                })?;
+                writer.finish()?.flush()?;
+                Ok(format)
+            })
+        }
+        _ => {
+            Err(ArchiveError::unsupported_static(
+                "recompressing archives other than ZIP, 7z, TAR and single-file streams",
+            ))
+        }
+    }
+}
+
+/// Writes a new archive at `dst` with `write`, which may read the archive
+/// at `location` while doing so.
+///
+/// The new archive goes to a temporary file first and replaces `dst` once
+/// `write` succeeds, so `dst` may be the archive at `location` and is left
+/// alone on failure.
+fn write_over<T, F>(location: &Location, dst: &Path, write: F) -> Result<T, ArchiveError>
+where
+    F: FnOnce(File) -> Result<T, ArchiveError>,
+{
+    let in_place = location.path().is_some_and(|path| is_same_file(path, dst));
+    if in_place && volume::is_split_zip(dst)? {
+        return Err(ArchiveError::unsupported_static(
+            "rewriting split ZIP archives in place",
+        ));
+    }
+
+    let temp = temp_path(dst);
+    let result = File::create(&temp)
+        .at_path(&temp)
+        .and_then(|file| {
+            if in_place {
+                let permissions = fs::metadata(dst).at_path(dst)?.permissions();
+                fs::set_permissions(&temp, permissions).at_path(&temp)?;
+            }
+            write(file)
+        })
+        .and_then(|value| {
+            fs::rename(&temp, dst).at_path(dst)?;
+            Ok(value)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Returns a hidden path next to `path` for the archive or directory
+/// replacing it.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
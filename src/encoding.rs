@@ -0,0 +1,200 @@
+//! Decoding entry names that archives store in a legacy code page.
+//!
+//! ZIP names are UTF-8 only when bit 11 of the entry flags says so. Without
+//! it they are in IBM code page 437, the DOS default that Windows still
+//! writes, unless an Info-ZIP Unicode Path extra field carries a UTF-8
+//! copy of the name. Tools running on Japanese or Chinese Windows write the
+//! code page of the system instead, which only the caller can know, so
+//! such names can be decoded again with an [`Encoding`] of their choice.
+//! The double-byte tables of those code pages are embedded from
+//! `encoding/shift_jis.bin` and `encoding/gbk.bin`.
+
+/// Flag marking a ZIP entry whose name and comment are UTF-8.
+pub(crate) const ZIP_FLAG_UTF8: u16 = 1 << 11;
+/// Info-ZIP Unicode Path extra field.
+pub(crate) const UNICODE_PATH_EXTRA_ID: u16 = 0x7075;
+
+/// The characters of bytes `0x80` to `0xff` in code page 437; the lower
+/// half is ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The characters of the two-byte sequences of Windows code page 932, as
+/// little-endian UTF-16 units indexed by lead and trail byte; 0 marks an
+/// unmapped sequence.
+static SHIFT_JIS: &[u8; 22_680] = include_bytes!("encoding/shift_jis.bin");
+/// The same for Windows code page 936.
+static GBK: &[u8; 48_132] = include_bytes!("encoding/gbk.bin");
+
+/// A character encoding for entry names that aren't stored as UTF-8.
+///
+/// Set it with
+/// [`ExtractOptions::filename_encoding`](crate::ExtractOptions::filename_encoding),
+/// or decode [`EntryInfo::raw_name`](crate::EntryInfo::raw_name) with it
+/// directly. Invalid sequences become `U+FFFD`.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::Encoding;
+///
+/// assert_eq!(Encoding::ShiftJis.decode(b"\x93\xfa\x96{\x8c\xea.txt"), "日本語.txt");
+/// assert_eq!(Encoding::Gbk.decode(b"\xd6\xd0\xce\xc4.txt"), "中文.txt");
+/// assert_eq!(Encoding::Cp437.decode(b"caf\x82.txt"), "café.txt");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// UTF-8, for archives that store it without saying so.
+    Utf8,
+    /// IBM code page 437, the DOS default and what ZIP names are without
+    /// the UTF-8 flag.
+    Cp437,
+    /// Shift-JIS as extended by Windows code page 932, written by Japanese
+    /// Windows.
+    ShiftJis,
+    /// GBK as extended by Windows code page 936, written by Simplified
+    /// Chinese Windows.
+    Gbk,
+}
+
+impl Encoding {
+    /// Decodes `bytes` in this encoding.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Cp437 => decode_cp437(bytes),
+            Encoding::ShiftJis => decode_double_byte(bytes, SHIFT_JIS, shift_jis_index),
+            Encoding::Gbk => decode_double_byte(bytes, GBK, gbk_index),
+        }
+    }
+}
+
+/// Decodes a double-byte code page in which bytes up to `0x7f` are ASCII
+/// and `index` locates the others in `table`, or returns the character of
+/// a lone byte.
+fn decode_double_byte(
+    bytes: &[u8],
+    table: &[u8],
+    index: fn(u8, Option<u8>) -> Result<usize, Option<char>>,
+) -> String {
+    let mut name = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while let [byte, tail @ ..] = rest {
+        rest = tail;
+        if byte.is_ascii() {
+            name.push(char::from(*byte));
+            continue;
+        }
+        let decoded = match index(*byte, tail.first().copied()) {
+            Ok(index) => {
+                let unit = u16::from_le_bytes([table[2 * index], table[2 * index + 1]]);
+                // The trail byte of an unmapped pair is decoded on its own
+                if unit == 0 {
+                    None
+                } else {
+                    rest = &tail[1..];
+                    char::from_u32(unit.into())
+                }
+            }
+            Err(single) => single,
+        };
+        name.push(decoded.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    name
+}
+
+/// Locates a lead and trail byte in [`SHIFT_JIS`], or returns the
+/// halfwidth katakana of a single byte.
+fn shift_jis_index(lead: u8, trail: Option<u8>) -> Result<usize, Option<char>> {
+    let row = match lead {
+        0xa1..=0xdf => return Err(char::from_u32(0xff61 + u32::from(lead - 0xa1))),
+        0x81..=0x9f => lead - 0x81,
+        0xe0..=0xfc => lead - 0xe0 + 31,
+        _ => return Err(None),
+    };
+    match trail {
+        Some(trail @ 0x40..=0xfc) => Ok(usize::from(row) * 189 + usize::from(trail - 0x40)),
+        _ => Err(None),
+    }
+}
+
+/// Locates a lead and trail byte in [`GBK`], or returns the euro sign
+/// Windows puts at `0x80`.
+fn gbk_index(lead: u8, trail: Option<u8>) -> Result<usize, Option<char>> {
+    match (lead, trail) {
+        (0x80, _) => Err(Some('€')),
+        (0x81..=0xfe, Some(trail @ 0x40..=0xfe)) => {
+            Ok(usize::from(lead - 0x81) * 191 + usize::from(trail - 0x40))
+        }
+        _ => Err(None),
+    }
+}
+
+/// Decodes `bytes` as code page 437, which maps every byte to a character.
+pub(crate) fn decode_cp437(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii() {
+                char::from(byte)
+            } else {
+                CP437_HIGH[usize::from(byte - 0x80)]
+            }
+        })
+        .collect()
+}
+
+/// Decodes the name of a ZIP entry from the `raw` bytes of its header,
+/// returning it with the bytes worth keeping in
+/// [`EntryInfo::raw_name`](crate::EntryInfo::raw_name).
+///
+/// `unicode_path` is the content of the entry's Unicode Path extra field,
+/// if it has one. The field is only used when the name bytes aren't flagged
+/// as UTF-8 already, and ignored unless it is valid and its checksum shows
+/// it was written for this very name rather than one renamed since. A name
+/// taken from it replaces the stored one altogether, the way the `zip`
+/// crate reads the central directory.
+pub(crate) fn zip_name(
+    raw: Vec<u8>,
+    flags: u16,
+    unicode_path: Option<&[u8]>,
+) -> (String, Option<Vec<u8>>) {
+    if flags & ZIP_FLAG_UTF8 != 0 {
+        return match String::from_utf8(raw) {
+            Ok(name) => (name, None),
+            Err(err) => {
+                let name = String::from_utf8_lossy(err.as_bytes()).into_owned();
+                (name, Some(err.into_bytes()))
+            }
+        };
+    }
+    let unicode = unicode_path
+        .filter(|field| field.len() > 5 && field[0] == 1)
+        .filter(|field| u32::from_le_bytes([field[1], field[2], field[3], field[4]]) == crc32(&raw))
+        .and_then(|field| std::str::from_utf8(&field[5..]).ok());
+    if let Some(name) = unicode {
+        return (name.to_owned(), None);
+    }
+    let name = decode_cp437(&raw);
+    let raw_name = raw_name(&raw, &name);
+    (name, raw_name)
+}
+
+/// Returns `raw` unless it is the UTF-8 encoding of `name` already.
+pub(crate) fn raw_name(raw: &[u8], name: &str) -> Option<Vec<u8>> {
+    (raw != name.as_bytes()).then(|| raw.to_vec())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
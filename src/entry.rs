@@ -0,0 +1,1951 @@
+use std::{
+    fmt,
+    io::{self, Read, Seek},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    ar, backend, brotli, encoding,
+    error::ArchiveError,
+    format::ArchiveFormat,
+    lz4, lzw,
+    password::{PasswordSource, Passwords, SecretString},
+    sparse,
+    volume::{self, Location},
+};
+
+/// The type of an entry stored in an archive.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::EntryKind;
+///
+/// let kind = EntryKind::Symlink;
+/// assert_ne!(kind, EntryKind::File);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntryKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+    /// A symbolic link.
+    Symlink,
+    /// A hard link to another entry in the same archive.
+    Hardlink,
+    /// Any other entry type (device nodes, FIFOs, ...).
+    ///
+    /// [`EntryInfo::special`] says which, for formats that record it.
+    Other,
+}
+
+/// Metadata describing a single entry of an archive.
+///
+/// Returned by [`Archive::entries`](crate::Archive::entries). Fields that a
+/// format does not record are `None`: for example TAR has no per-entry
+/// compressed size, and ZIP archives created on Windows usually carry no
+/// unix mode.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::Archive;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     for entry in Archive::open("dist.tar.gz")?.entries()? {
+///         if entry.is_file() {
+///             println!("{} ({} bytes)", entry.name, entry.size);
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Path of the entry inside the archive, using `/` as separator.
+    pub name: String,
+    /// The type of the entry.
+    pub kind: EntryKind,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    /// Compressed size in bytes, when the format records it per entry.
+    pub compressed_size: Option<u64>,
+    /// Last modification time, when recorded.
+    pub mtime: Option<SystemTime>,
+    /// Unix permission bits (including setuid/setgid/sticky), when recorded.
+    ///
+    /// File type bits are stripped; use [`kind`](Self::kind) instead.
+    pub mode: Option<u32>,
+    /// Target of a symbolic or hard link.
+    pub link_target: Option<String>,
+    /// Owning user and group, when recorded.
+    pub owner: Option<Owner>,
+    /// Extended attributes as name and value pairs, as recorded by TAR
+    /// archives in PAX `SCHILY.xattr` records.
+    pub xattrs: Vec<(String, Vec<u8>)>,
+    /// The name exactly as stored in a ZIP archive, when it isn't the UTF-8
+    /// encoding of [`name`](Self::name).
+    ///
+    /// Names recorded without the UTF-8 flag are decoded as the DOS code
+    /// page 437, and these are the bytes before decoding. An entry with a
+    /// valid Info-ZIP Unicode Path extra field takes its name from there
+    /// instead, and the stored name it replaces isn't kept.
+    /// [`Encoding::decode`](crate::Encoding::decode) reads these bytes in
+    /// another code page, for archives made on non-English Windows systems.
+    pub raw_name: Option<Vec<u8>>,
+    /// What a special file is, for entries of kind [`EntryKind::Other`]
+    /// in TAR and cpio archives.
+    pub special: Option<SpecialFile>,
+    /// Comment recorded for the entry, by ZIP archives or by TAR archives
+    /// in a PAX `comment` record.
+    pub comment: Option<String>,
+    /// The extra fields of a ZIP entry, in the order its central directory
+    /// header stores them, or its local header for entries recovered by
+    /// [`Archive::salvage_to`](crate::Archive::salvage_to).
+    ///
+    /// The fields compak understands are applied to the other fields as
+    /// well: extended timestamps to [`mtime`](Self::mtime), Info-ZIP unix
+    /// owners to [`owner`](Self::owner) and Unicode paths to
+    /// [`name`](Self::name).
+    pub extra_fields: Vec<ExtraField>,
+    /// Whether the data of the entry is encrypted, so that reading it
+    /// takes the password set with
+    /// [`ExtractOptions::password`](crate::ExtractOptions::password).
+    ///
+    /// ZIP archives flag each entry; in 7z archives the entries of blocks
+    /// compressed through AES are encrypted. Archives whose headers are
+    /// encrypted too can't be listed without the password at all; see
+    /// [`Archive::is_encrypted`](crate::Archive::is_encrypted).
+    pub encrypted: bool,
+}
+
+/// The user and group owning an entry, as recorded by unix formats such as
+/// TAR and cpio.
+///
+/// Names are recorded by some formats next to the numeric IDs and, when
+/// present, take precedence during extraction unless
+/// [`ExtractOptions::numeric_owner`](crate::ExtractOptions::numeric_owner)
+/// is set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Owner {
+    /// Numeric user ID.
+    pub uid: u64,
+    /// Numeric group ID.
+    pub gid: u64,
+    /// User name, when recorded.
+    pub user: Option<String>,
+    /// Group name, when recorded.
+    pub group: Option<String>,
+}
+
+/// An extra field of a ZIP entry: a header ID and the data that follows.
+///
+/// PKWARE's APPNOTE and Info-ZIP's `extrafld.txt` assign the IDs of the
+/// common fields; tools keep data of their own under other IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExtraField {
+    /// Header ID of the field.
+    pub id: u16,
+    /// The data of the field, without its ID and length.
+    pub data: Vec<u8>,
+}
+
+/// A device node, FIFO or socket, as recorded by TAR and cpio archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpecialFile {
+    /// The type of special file.
+    pub kind: SpecialKind,
+    /// Major device number, 0 for FIFOs and sockets.
+    pub major: u32,
+    /// Minor device number, 0 for FIFOs and sockets.
+    pub minor: u32,
+}
+
+/// The type of a [`SpecialFile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecialKind {
+    /// A named pipe.
+    Fifo,
+    /// A character device, such as a terminal.
+    CharDevice,
+    /// A block device, such as a disk.
+    BlockDevice,
+    /// A unix domain socket.
+    Socket,
+}
+
+impl SpecialKind {
+    /// Returns `true` for character and block devices.
+    pub fn is_device(self) -> bool {
+        matches!(self, SpecialKind::CharDevice | SpecialKind::BlockDevice)
+    }
+}
+
+impl EntryInfo {
+    /// Returns `true` if the entry is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    /// Returns `true` if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+
+    /// Returns `true` if the entry is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.kind == EntryKind::Symlink
+    }
+}
+
+/// Iterator over the entries of an archive.
+///
+/// Created by [`Archive::entries`](crate::Archive::entries). The archive's
+/// metadata is read up front, so iterating never fails and never touches
+/// the archive file again.
+#[derive(Debug, Clone)]
+pub struct Entries {
+    inner: std::vec::IntoIter<EntryInfo>,
+}
+
+impl Iterator for Entries {
+    type Item = EntryInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Entries {}
+
+impl Entries {
+    /// Returns `true` if a ZIP archive holding the remaining entries needs
+    /// ZIP64 extensions.
+    ///
+    /// That is the case for more than 65,534 entries, for entries of 4 GiB
+    /// or more, and for entries whose local header starts 4 GiB or more into
+    /// the archive. Offsets are estimated from the compressed size of the
+    /// entries before, or their size where it is not recorded, leaving out
+    /// headers, so archives just below the limit may need ZIP64 regardless.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let entries = Archive::open("dataset.tar")?.entries()?;
+    ///     if entries.requires_zip64() {
+    ///         println!("repacking as ZIP needs ZIP64");
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn requires_zip64(&self) -> bool {
+        let entries = self.inner.as_slice();
+        if entries.len() > ZIP_MAX_ENTRIES {
+            return true;
+        }
+        let mut offset = 0u64;
+        entries.iter().any(|entry| {
+            let needs_zip64 = offset > ZIP_MAX_SIZE
+                || entry.size > ZIP_MAX_SIZE
+                || entry
+                    .compressed_size
+                    .is_some_and(|size| size > ZIP_MAX_SIZE);
+            offset = offset.saturating_add(entry.compressed_size.unwrap_or(entry.size));
+            needs_zip64
+        })
+    }
+}
+
+/// A reader over the contents of a single archive entry.
+///
+/// Created by [`Archive::entry_reader`](crate::Archive::entry_reader). The
+/// entry is decoded on a background thread, which stays a few chunks ahead
+/// of the reader, so the entry is never held in memory in full. Dropping
+/// the reader stops the decoding at the next chunk.
+///
+/// Errors met while decoding are returned from [`Read::read`] as
+/// [`io::Error`]s wrapping the [`ArchiveError`].
+#[derive(Debug)]
+pub struct EntryReader {
+    info: EntryInfo,
+    receiver: mpsc::Receiver<Result<Vec<u8>, ArchiveError>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl EntryReader {
+    /// Returns the metadata of the entry being read.
+    pub fn info(&self) -> &EntryInfo {
+        &self.info
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Ok(Err(err)) => return Err(io::Error::other(err)),
+                // The decoding thread hangs up once the entry is done
+                Err(mpsc::RecvError) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Set in 7z/zip attributes when the high 16 bits carry a unix mode.
+const UNIX_EXTENSION: u32 = 0x8000;
+/// Mask for the file type bits of a unix mode.
+const UNIX_TYPE_MASK: u32 = 0o170000;
+/// `S_IFLNK` file type bits.
+const UNIX_SYMLINK: u32 = 0o120000;
+
+/// Largest entry count a ZIP archive records without ZIP64 extensions.
+const ZIP_MAX_ENTRIES: usize = 0xFFFE;
+/// Largest size or offset a ZIP archive records without ZIP64 extensions.
+const ZIP_MAX_SIZE: u64 = 0xFFFF_FFFE;
+/// Info-ZIP unix extra field recording owners with IDs of any width.
+const UNIX_OWNER_EXTRA_ID: u16 = 0x7875;
+/// Info-ZIP unix extra field that preceded it, with 16-bit IDs.
+const OLD_UNIX_EXTRA_ID: u16 = 0x7855;
+/// ZIP extra field recording Windows file times.
+const NTFS_EXTRA_ID: u16 = 0x000a;
+/// ZIP extended timestamp extra field, recording Unix times.
+const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+
+/// Bytes of an encrypted 7z entry decoded to check a password.
+const SEVENZ_PROBE_SIZE: u64 = 64 * 1024;
+
+/// Size of the chunks an [`EntryReader`] receives from its thread.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks an [`EntryReader`]'s thread decodes ahead of the reader.
+const READ_AHEAD_CHUNKS: usize = 4;
+/// Number of hard links [`find_entry`] follows before giving up.
+const MAX_LINK_DEPTH: usize = 32;
+
+/// Entry name used for single-file streams read without a file name.
+pub(crate) const STREAM_ENTRY_NAME: &str = "data";
+/// Seconds between the Windows epoch (1601) and the Unix epoch.
+const WINDOWS_EPOCH_OFFSET: u64 = 11_644_473_600;
+
+/// Walks every entry of an archive in storage order.
+///
+/// The callback receives the entry metadata and a reader over its
+/// decompressed contents. It does not have to consume the reader; any unread
+/// data is skipped before moving on. Returning `Ok(false)` stops the walk.
+///
+/// Symlink targets are resolved up front and exposed through
+/// [`EntryInfo::link_target`]; the reader passed for symlinks is empty.
+///
+/// # Arguments
+///
+/// * `location` - Where the archive is read from
+/// * `format` - The archive format to decode
+/// * `visit` - Callback invoked once per entry
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// decoded, or if the callback returns an error.
+pub(crate) fn for_each_entry<F>(
+    location: &Location,
+    format: ArchiveFormat,
+    visit: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    for_each_entry_with_password(location, format, &Passwords::default(), visit)
+}
+
+/// Walks every entry of an archive, decrypting entries with
+/// `passwords`.
+///
+/// The passwords are used for encrypted ZIP entries and encrypted 7z and
+/// RAR archives and ignored by every other format. See [`for_each_entry`] for
+/// the callback contract.
+///
+/// # Errors
+///
+/// In addition to the errors of [`for_each_entry`], this function returns
+/// [`ArchiveError::InvalidPassword`] if an encrypted archive is walked
+/// without a password or with the wrong one.
+pub(crate) fn for_each_entry_with_password<F>(
+    location: &Location,
+    format: ArchiveFormat,
+    passwords: &Passwords,
+    visit: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    #[cfg(feature = "rar")]
+    if format == ArchiveFormat::Rar
+        && let Some(path) = location.path()
+    {
+        return crate::rar::walk_path(path, passwords, visit);
+    }
+    let file = location.open(format)?;
+    if format.is_single_file() {
+        return walk_single(file, format, &single_entry_name(location, format), visit);
+    }
+    for_each_entry_in(file, format, passwords, visit)
+}
+
+/// Walks every entry of an archive read from `reader`.
+///
+/// See [`for_each_entry_with_password`] for the callback contract.
+pub(crate) fn for_each_entry_in<R, F>(
+    mut reader: R,
+    format: ArchiveFormat,
+    passwords: &Passwords,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    // Backends are handed a fixed password, so the formats that can ask a
+    // provider for one are walked directly
+    match format {
+        ArchiveFormat::Zip => walk_zip(reader, passwords, visit),
+        ArchiveFormat::SevenZ => walk_7z(reader, passwords, visit),
+        #[cfg(feature = "rar")]
+        ArchiveFormat::Rar => crate::rar::walk(reader, passwords, visit),
+        _ => backend::for_format(format).extract(&mut reader, passwords.fixed(), &mut visit),
+    }
+}
+
+/// Walks every entry of an archive read from a non-seekable stream.
+///
+/// TAR-based formats are decoded on the fly. ZIP archives are read via
+/// their local file headers rather than the central directory, which only
+/// works if the local headers carry accurate sizes (or the entries are
+/// deflate-compressed and therefore self-terminating), and cannot decrypt
+/// entries. 7z archives and ISO and SquashFS images need random access, so
+/// the whole stream is buffered in memory first, and RAR archives, with the
+/// `rar` feature, are copied to a temporary file.
+///
+/// See [`for_each_entry_with_password`] for the callback contract.
+pub(crate) fn for_each_streamed_entry<R, F>(
+    mut reader: R,
+    format: ArchiveFormat,
+    passwords: &Passwords,
+    visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    match format {
+        ArchiveFormat::Zip => walk_zip_stream(reader, visit),
+        #[cfg(feature = "rar")]
+        ArchiveFormat::Rar => crate::rar::walk(reader, passwords, visit),
+        #[cfg(not(feature = "rar"))]
+        ArchiveFormat::Rar => Err(ArchiveError::unsupported_static("reading RAR archives")),
+        ArchiveFormat::Ar | ArchiveFormat::Deb => ar::walk(reader, format, visit),
+        _ if format.is_single_file() => walk_single(reader, format, STREAM_ENTRY_NAME, visit),
+        _ if format.is_tar() => walk_tar(decoder(format, reader)?, visit),
+        _ => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            for_each_entry_in(io::Cursor::new(data), format, passwords, visit)
+        }
+    }
+}
+
+/// Reads the metadata of every entry of an archive, in storage order.
+///
+/// ZIP listings come from the central directory and 7z listings from the
+/// archive header, so no entry data is decompressed apart from ZIP symlink
+/// targets. TAR-based formats have no index and are scanned header by
+/// header. 7z stores symlink targets as entry data, so they are not
+/// resolved here.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// its metadata cannot be decoded.
+pub(crate) fn list_entries(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<Entries, ArchiveError> {
+    #[cfg(feature = "rar")]
+    if format == ArchiveFormat::Rar
+        && let Some(path) = location.path()
+    {
+        return Ok(Entries {
+            inner: crate::rar::list_path(path)?.into_iter(),
+        });
+    }
+    let mut reader = location.open(format)?;
+    let entries = if format.is_single_file() {
+        let mut entries = Vec::new();
+        walk_single(
+            reader,
+            format,
+            &single_entry_name(location, format),
+            |info, _| {
+                entries.push(info.clone());
+                Ok(true)
+            },
+        )?;
+        entries
+    } else {
+        backend::for_format(format).list(&mut reader)?
+    };
+
+    Ok(Entries {
+        inner: entries.into_iter(),
+    })
+}
+
+/// Returns whether any entry of the archive at `location` is encrypted.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// its metadata cannot be decoded.
+pub(crate) fn is_encrypted(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<bool, ArchiveError> {
+    match list_entries(location, format) {
+        Ok(mut entries) => Ok(entries.any(|info| info.encrypted)),
+        // 7z archives with encrypted headers can't be listed at all
+        Err(ArchiveError::InvalidPassword) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+/// Finds the file called `name` in an archive and hands its metadata
+/// and contents to `read`.
+///
+/// Names are compared after [`normalize_name`], so `./a.txt` and `a.txt`
+/// name the same entry. ZIP archives are searched through the central
+/// directory and only the matching entry is decompressed; other formats
+/// are walked up to the first entry with that name. Hard links are
+/// followed to the file they name, which walks the archive again.
+///
+/// # Errors
+///
+/// This function will return [`ArchiveError::NotFound`] if no entry is
+/// called `name`, and an error if the entry is a directory, symlink or
+/// special file, if the archive cannot be decoded, or if `read` fails.
+pub(crate) fn find_entry<T, F>(
+    location: &Location,
+    format: ArchiveFormat,
+    name: &str,
+    read: F,
+) -> Result<T, ArchiveError>
+where
+    F: FnOnce(&EntryInfo, &mut dyn Read) -> Result<T, ArchiveError>,
+{
+    let mut wanted = normalize_name(name);
+
+    if format == ArchiveFormat::Zip {
+        let mut archive = zip::ZipArchive::new(location.open(format)?)?;
+        let index = (0..archive.len())
+            .find(|&i| archive.name_for_index(i).map(normalize_name).as_ref() == Some(&wanted))
+            .ok_or_else(|| ArchiveError::not_found_dynamic(name))?;
+        let mut file = archive.by_index(index)?;
+        let info = zip_info(&file);
+        if info.kind != EntryKind::File {
+            return Err(not_a_file(name));
+        }
+        return read(&info, &mut file);
+    }
+
+    let mut read = Some(read);
+    // Hard links can only name earlier entries, but a broken archive may
+    // still link in a circle
+    for _ in 0..MAX_LINK_DEPTH {
+        let mut result = None;
+        let mut link = None;
+        for_each_entry(location, format, |info, reader| {
+            if normalize_name(&info.name) != wanted {
+                return Ok(true);
+            }
+            match info.kind {
+                EntryKind::Hardlink => link = info.link_target.clone(),
+                EntryKind::File => result = read.take().map(|read| read(info, reader)),
+                _ => result = Some(Err(not_a_file(name))),
+            }
+            Ok(false)
+        })?;
+        if let Some(result) = result {
+            return result;
+        }
+        match link {
+            Some(target) => wanted = normalize_name(&target),
+            None => return Err(ArchiveError::not_found_dynamic(name)),
+        }
+    }
+    Err(ArchiveError::InvalidArchive {
+        format,
+        reason: "too many levels of hard links".into(),
+    })
+}
+
+/// Starts decoding the file called `name` on a background thread and
+/// returns a reader over its contents once the entry has been found.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`find_entry`], or if the thread cannot be spawned. Errors met after the
+/// entry has been found are returned by the reader instead.
+pub(crate) fn entry_reader(
+    location: &Location,
+    format: ArchiveFormat,
+    name: &str,
+) -> Result<EntryReader, ArchiveError> {
+    let location = location.clone();
+    let name = name.to_string();
+    spawn_reader(move |stream| find_entry(&location, format, &name, stream))
+}
+
+/// Starts decoding the file at `index` in the central directory of a ZIP
+/// archive on a background thread, like [`entry_reader`].
+pub(crate) fn zip_entry_reader(
+    location: &Location,
+    format: ArchiveFormat,
+    index: usize,
+) -> Result<EntryReader, ArchiveError> {
+    require_zip(format)?;
+    let location = location.clone();
+    spawn_reader(move |stream| read_zip_entry(&location, index, stream))
+}
+
+/// Runs `locate` on a background thread, sending the metadata of the entry
+/// it hands over and then its contents in chunks.
+fn spawn_reader<L>(locate: L) -> Result<EntryReader, ArchiveError>
+where
+    L: FnOnce(
+            &mut dyn FnMut(&EntryInfo, &mut dyn Read) -> Result<(), ArchiveError>,
+        ) -> Result<(), ArchiveError>
+        + Send
+        + 'static,
+{
+    let (found_sender, found) = mpsc::sync_channel(1);
+    let (sender, receiver) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+
+    thread::Builder::new()
+        .name("compak-entry-reader".into())
+        .spawn(move || {
+            let result = locate(&mut |info, reader| {
+                if found_sender.send(Ok(info.clone())).is_err() {
+                    return Err(ArchiveError::Cancelled);
+                }
+                loop {
+                    let mut chunk = vec![0; CHUNK_SIZE];
+                    let n = match reader.read(&mut chunk) {
+                        Ok(0) => return Ok(()),
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    chunk.truncate(n);
+                    if sender.send(Ok(chunk)).is_err() {
+                        // The reader was dropped
+                        return Err(ArchiveError::Cancelled);
+                    }
+                }
+            });
+            if let Err(err) = result {
+                // Only one of these still has a receiver, depending on
+                // whether the entry was found
+                let _ = found_sender.send(Err(err.clone()));
+                let _ = sender.send(Err(err));
+            }
+        })?;
+
+    let info = found.recv().unwrap_or_else(|_| {
+        Err(ArchiveError::custom_static(
+            "entry reader thread exited unexpectedly",
+        ))
+    })?;
+    Ok(EntryReader {
+        info,
+        receiver,
+        chunk: Vec::new(),
+        position: 0,
+    })
+}
+
+/// Opens the central directory of a ZIP archive.
+fn zip_archive(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<zip::ZipArchive<volume::Source>, ArchiveError> {
+    require_zip(format)?;
+    Ok(zip::ZipArchive::new(location.open(format)?)?)
+}
+
+fn require_zip(format: ArchiveFormat) -> Result<(), ArchiveError> {
+    if format != ArchiveFormat::Zip {
+        return Err(ArchiveError::unsupported_static(
+            "indexed access to entries of non-ZIP archives",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the number of entries in the central directory of a ZIP
+/// archive.
+///
+/// # Errors
+///
+/// This function will return an error if `format` is not ZIP or if the
+/// central directory cannot be read.
+pub(crate) fn zip_entry_count(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<usize, ArchiveError> {
+    Ok(zip_archive(location, format)?.len())
+}
+
+/// Reads the comment that ends the central directory of a ZIP archive.
+///
+/// # Errors
+///
+/// This function will return an error if `format` is not ZIP or if the
+/// central directory cannot be read.
+pub(crate) fn zip_comment(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<Vec<u8>, ArchiveError> {
+    if format != ArchiveFormat::Zip {
+        return Err(ArchiveError::unsupported_static(
+            "archive comments outside ZIP archives",
+        ));
+    }
+    Ok(zip::ZipArchive::new(location.open(format)?)?
+        .comment()
+        .to_vec())
+}
+
+/// Reads the metadata of the entry at `index` in the central directory of
+/// a ZIP archive, resolving symlink targets like [`list_entries`].
+///
+/// # Errors
+///
+/// This function will return [`ArchiveError::NotFound`] if there is no
+/// entry at `index`, and an error if `format` is not ZIP or if the central
+/// directory cannot be read.
+pub(crate) fn zip_entry_info(
+    location: &Location,
+    format: ArchiveFormat,
+    index: usize,
+) -> Result<EntryInfo, ArchiveError> {
+    let mut archive = zip_archive(location, format)?;
+    if index >= archive.len() {
+        return Err(no_entry_at(index));
+    }
+    let mut info = zip_info(&archive.by_index_raw(index)?);
+    if info.kind == EntryKind::Symlink
+        && let Ok(mut file) = archive.by_index(index)
+    {
+        let mut target = String::new();
+        file.read_to_string(&mut target)?;
+        info.link_target = Some(target);
+    }
+    Ok(info)
+}
+
+/// Hands the metadata and contents of the file at `index` in the central
+/// directory of a ZIP archive to `read`.
+///
+/// # Errors
+///
+/// This function will return [`ArchiveError::NotFound`] if there is no
+/// entry at `index`, and an error if the entry is not a file, if the
+/// archive cannot be decoded, or if `read` fails.
+pub(crate) fn read_zip_entry<T, F>(
+    location: &Location,
+    index: usize,
+    read: F,
+) -> Result<T, ArchiveError>
+where
+    F: FnOnce(&EntryInfo, &mut dyn Read) -> Result<T, ArchiveError>,
+{
+    let mut archive = zip_archive(location, ArchiveFormat::Zip)?;
+    if index >= archive.len() {
+        return Err(no_entry_at(index));
+    }
+    let mut file = archive.by_index(index)?;
+    let info = zip_info(&file);
+    if info.kind != EntryKind::File {
+        return Err(not_a_file(&info.name));
+    }
+    read(&info, &mut file)
+}
+
+fn no_entry_at(index: usize) -> ArchiveError {
+    ArchiveError::not_found_dynamic(format!("entry #{index}"))
+}
+
+pub(crate) fn not_a_file(name: &str) -> ArchiveError {
+    ArchiveError::Custom {
+        message: format!("'{name}' is not a file").into(),
+    }
+}
+
+/// Wraps `reader` in the decompressor for the outer layer of a TAR-based
+/// format, or for a single-file stream.
+///
+/// # Errors
+///
+/// Returns an error if `format` is neither TAR-based nor a single-file
+/// stream, or if the decoder cannot be initialized.
+pub(crate) fn decoder<'a, R: Read + 'a>(
+    format: ArchiveFormat,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    decoded(reader, |reader| decoder_for(format, reader))
+}
+
+/// Returns the decompressor of [`decoder`] without the error mapping.
+fn decoder_for<'a, R: Read + 'a>(
+    format: ArchiveFormat,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    Ok(match format {
+        ArchiveFormat::Tar => Box::new(reader),
+        ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(reader)),
+        ArchiveFormat::TarXz => Box::new(liblzma::read::XzDecoder::new(reader)),
+        ArchiveFormat::TarBz2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+        ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        ArchiveFormat::TarLz4 | ArchiveFormat::Lz4 => Box::new(lz4::FrameDecoder::new(reader)),
+        ArchiveFormat::TarBr | ArchiveFormat::Brotli => Box::new(brotli::Decoder::new(reader)),
+        // Multi-member lzip files are read whole, checking every member
+        ArchiveFormat::TarLz | ArchiveFormat::Lzip => {
+            Box::new(liblzma::read::XzDecoder::new_stream(
+                reader,
+                liblzma::stream::Stream::new_lzip_decoder(u64::MAX, liblzma::stream::CONCATENATED)
+                    .map_err(io::Error::from)?,
+            ))
+        }
+        ArchiveFormat::TarZ | ArchiveFormat::Compress => Box::new(lzw::Decoder::new(reader)),
+        ArchiveFormat::TarLzma | ArchiveFormat::Lzma => {
+            Box::new(liblzma::read::XzDecoder::new_stream(
+                reader,
+                liblzma::stream::Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::from)?,
+            ))
+        }
+        // `gzip` concatenates members when appending, so read all of them
+        ArchiveFormat::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        ArchiveFormat::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(reader)),
+        ArchiveFormat::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        ArchiveFormat::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        ArchiveFormat::Zip
+        | ArchiveFormat::SevenZ
+        | ArchiveFormat::Rar
+        | ArchiveFormat::Ar
+        | ArchiveFormat::Deb
+        | ArchiveFormat::Iso
+        | ArchiveFormat::Squashfs
+        | ArchiveFormat::Wim
+        | ArchiveFormat::Xar
+        | ArchiveFormat::Msi
+        | ArchiveFormat::AppImage
+        | ArchiveFormat::Custom(_) => {
+            return Err(ArchiveError::unsupported_static(
+                "streaming decode of non-TAR formats",
+            ));
+        }
+    })
+}
+
+/// Builds a decoder with `decode` on top of `reader`, reporting the
+/// errors of the decoder as [`io::ErrorKind::InvalidData`].
+///
+/// Codecs only fail on their own when the data is corrupt, but give those
+/// errors whatever kind they like: `flate2` uses `InvalidInput` and `zstd`
+/// `Other`. Errors of `reader` itself come out as they went in.
+///
+/// # Errors
+///
+/// Returns the error of `decode`.
+pub(crate) fn decoded<'a, R, F>(reader: R, decode: F) -> Result<Box<dyn Read + 'a>, ArchiveError>
+where
+    R: Read + 'a,
+    F: FnOnce(Source<R>) -> Result<Box<dyn Read + 'a>, ArchiveError>,
+{
+    Ok(Box::new(Decoded(decode(Source(reader))?)))
+}
+
+/// An error of the reader under a decoder, told apart from the errors of
+/// the decoder itself by [`Decoded`].
+#[derive(Debug)]
+struct SourceError(io::Error);
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// The reader under a decoder built with [`decoded`], which wraps its
+/// errors in [`SourceError`].
+pub(crate) struct Source<R>(R);
+
+impl<R: Seek> Seek for Source<R> {
+    fn seek(&mut self, position: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(position)
+    }
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|err| {
+            match err.kind() {
+                io::ErrorKind::Interrupted => err,
+                kind => io::Error::new(kind, SourceError(err)),
+            }
+        })
+    }
+}
+
+/// A decoder built with [`decoded`].
+struct Decoded<D>(D);
+
+impl<D: Read> Read for Decoded<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|err| {
+            if err.get_ref().is_some_and(|inner| inner.is::<SourceError>()) {
+                let inner = err.into_inner().expect("the error has an inner error");
+                return inner
+                    .downcast::<SourceError>()
+                    .expect("the inner error is a source error")
+                    .0;
+            }
+            match err.kind() {
+                io::ErrorKind::Interrupted
+                | io::ErrorKind::InvalidData
+                | io::ErrorKind::UnexpectedEof => err,
+                _ => io::Error::new(io::ErrorKind::InvalidData, err),
+            }
+        })
+    }
+}
+
+pub(crate) fn walk_tar<R, F>(reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    match walk_tar_entries(&mut tar::Archive::new(reader), &mut visit)? {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Walks a TAR archive like [`walk_tar`], but skips the entries whose
+/// headers can't be parsed, such as ones with a bad checksum, by scanning
+/// ahead for the next valid header.
+///
+/// Returns the number of entries skipped. A header cut off by the end of
+/// the stream isn't counted.
+pub(crate) fn walk_tar_leniently<'r, R, F>(reader: R, mut visit: F) -> Result<u64, ArchiveError>
+where
+    R: Read + 'r,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut reader: Box<dyn Read + 'r> = Box::new(reader);
+    let mut skipped = 0;
+    loop {
+        let mut archive = tar::Archive::new(reader);
+        if walk_tar_entries(&mut archive, &mut visit)?.is_none() {
+            return Ok(skipped);
+        }
+        // The tar crate leaves the stream right after the header it failed
+        // on, with the data of the entry, if any, still to come
+        reader = archive.into_inner();
+        let (header, scanned) = next_tar_header(&mut reader)?;
+        if scanned {
+            skipped += 1;
+        }
+        match header {
+            Some(header) => reader = Box::new(io::Cursor::new(header).chain(reader)),
+            None => return Ok(skipped),
+        }
+    }
+}
+
+/// Reads blocks from `reader` until one holds a TAR header with a valid
+/// checksum, and returns it, or `None` if the stream ends or breaks off
+/// first, along with whether any block was read.
+fn next_tar_header(reader: &mut dyn Read) -> Result<(Option<Vec<u8>>, bool), ArchiveError> {
+    let mut block = vec![0; TAR_BLOCK_SIZE];
+    let mut scanned = false;
+    loop {
+        match reader.read_exact(&mut block) {
+            Ok(()) => scanned = true,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                ) =>
+            {
+                return Ok((None, scanned));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if block.iter().any(|&b| b != 0) && has_tar_checksum(&block) {
+            return Ok((Some(block), scanned));
+        }
+    }
+}
+
+/// Returns `true` if the TAR header `block` holds its own checksum.
+fn has_tar_checksum(block: &[u8]) -> bool {
+    // The checksum field counts as spaces
+    let sum = block[..148]
+        .iter()
+        .chain(&block[156..])
+        .map(|&b| u32::from(b))
+        .sum::<u32>()
+        + 8 * u32::from(b' ');
+    tar::Header::from_byte_slice(block)
+        .cksum()
+        .is_ok_and(|cksum| cksum == sum)
+}
+
+/// Size of the blocks TAR archives are made of.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Visits the entries of `archive`, returning the error the archive
+/// couldn't be walked past, if a header or the metadata of an entry is
+/// malformed. Errors from `visit` are returned as is.
+fn walk_tar_entries<R, F>(
+    archive: &mut tar::Archive<R>,
+    visit: &mut F,
+) -> Result<Option<ArchiveError>, ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    for entry in archive.entries()? {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => return Ok(Some(err.into())),
+        };
+        let sparse = match entry.pax_extensions() {
+            Ok(Some(extensions)) => {
+                match sparse::pax_sparse(extensions) {
+                    Ok(sparse) => sparse,
+                    Err(err) => return Ok(Some(err)),
+                }
+            }
+            Ok(None) => None,
+            Err(err) => return Ok(Some(err.into())),
+        };
+        let pax = match entry.pax_extensions() {
+            Ok(Some(extensions)) => Pax::parse(extensions),
+            _ => Pax::default(),
+        };
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            // The tar crate fills in the holes of old GNU sparse files
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+                EntryKind::File
+            }
+            tar::EntryType::Directory => EntryKind::Directory,
+            tar::EntryType::Symlink => EntryKind::Symlink,
+            tar::EntryType::Link => EntryKind::Hardlink,
+            _ => EntryKind::Other,
+        };
+        let (name, link_target) = match (entry.path(), entry.link_name()) {
+            (Ok(name), Ok(link_target)) => {
+                (
+                    name.to_string_lossy().into_owned(),
+                    link_target.map(|target| target.to_string_lossy().into_owned()),
+                )
+            }
+            (Err(err), _) | (_, Err(err)) => return Ok(Some(err.into())),
+        };
+        let mut info = EntryInfo {
+            name,
+            kind,
+            size: entry.size(),
+            compressed_size: None,
+            mtime: header
+                .mtime()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            mode: header.mode().ok().map(|m| m & 0o7777),
+            link_target,
+            owner: tar_owner(header),
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: tar_special(header),
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        pax.apply(&mut info);
+        let stored = entry.size();
+        let mut data = Exact {
+            inner: &mut entry,
+            remaining: stored,
+        };
+        let keep_going = match sparse {
+            Some(sparse) if kind == EntryKind::File => {
+                if let Some(name) = &sparse.name {
+                    info.name.clone_from(name);
+                }
+                info.size = sparse.size;
+                let mut expanded = match sparse::Expanded::new(&mut data, sparse, stored) {
+                    Ok(expanded) => expanded,
+                    Err(err) => return Ok(Some(err)),
+                };
+                visit(&info, &mut expanded)?
+            }
+            _ => visit(&info, &mut data)?,
+        };
+        if !keep_going {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the contents of an entry, failing if the archive ends before all
+/// of them.
+///
+/// The tar crate ends the contents of an entry cut off by the end of the
+/// archive early without an error, as does a `take` on a short stream,
+/// which would leave a short file.
+pub(crate) struct Exact<R> {
+    inner: R,
+    /// Bytes of the contents not read yet.
+    remaining: u64,
+}
+
+impl<R> Exact<R> {
+    /// Reads the `size` bytes of contents from `inner`.
+    pub(crate) fn new(inner: R, size: u64) -> Self {
+        Self {
+            inner,
+            remaining: size,
+        }
+    }
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "entry is cut off by the end of the archive",
+            ));
+        }
+        self.remaining = self.remaining.saturating_sub(n as u64);
+        Ok(n)
+    }
+}
+
+/// Returns the owner recorded in a TAR header.
+fn tar_owner(header: &tar::Header) -> Option<Owner> {
+    let name = |name: Option<&str>| name.filter(|name| !name.is_empty()).map(str::to_string);
+    Some(Owner {
+        uid: header.uid().ok()?,
+        gid: header.gid().ok()?,
+        user: name(header.username().ok().flatten()),
+        group: name(header.groupname().ok().flatten()),
+    })
+}
+
+/// Returns the special file described by a TAR header, if it is one.
+fn tar_special(header: &tar::Header) -> Option<SpecialFile> {
+    let kind = match header.entry_type() {
+        tar::EntryType::Fifo => SpecialKind::Fifo,
+        tar::EntryType::Char => SpecialKind::CharDevice,
+        tar::EntryType::Block => SpecialKind::BlockDevice,
+        _ => return None,
+    };
+    let number = |number: io::Result<Option<u32>>| number.ok().flatten().unwrap_or(0);
+    Some(SpecialFile {
+        kind,
+        major: number(header.device_major()),
+        minor: number(header.device_minor()),
+    })
+}
+
+/// The records of a PAX extended header that the tar crate leaves to its
+/// callers.
+///
+/// Paths, link targets, sizes and numeric owners are applied by the tar
+/// crate itself.
+#[derive(Default)]
+struct Pax {
+    /// Modification time, to the nanosecond.
+    mtime: Option<SystemTime>,
+    user: Option<String>,
+    group: Option<String>,
+    comment: Option<String>,
+    xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl Pax {
+    /// Picks the records out of `extensions`, ignoring malformed ones.
+    fn parse(extensions: tar::PaxExtensions<'_>) -> Self {
+        let mut pax = Self::default();
+        for extension in extensions.flatten() {
+            let Ok(key) = extension.key() else {
+                continue;
+            };
+            let value = extension.value().ok();
+            match key {
+                "mtime" => pax.mtime = value.and_then(pax_time).or(pax.mtime),
+                "uname" => pax.user = value.map(str::to_string),
+                "gname" => pax.group = value.map(str::to_string),
+                "comment" => pax.comment = value.map(str::to_string),
+                _ => {
+                    if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                        pax.xattrs
+                            .push((name.to_string(), extension.value_bytes().to_vec()));
+                    }
+                }
+            }
+        }
+        pax
+    }
+
+    /// Overrides the fields of `info` the records carry.
+    fn apply(self, info: &mut EntryInfo) {
+        if let Some(mtime) = self.mtime {
+            info.mtime = Some(mtime);
+        }
+        if let Some(owner) = &mut info.owner {
+            let name = |name: Option<String>| name.filter(|name| !name.is_empty());
+            owner.user = name(self.user).or(owner.user.take());
+            owner.group = name(self.group).or(owner.group.take());
+        }
+        info.comment = self.comment;
+        info.xattrs = self.xattrs;
+    }
+}
+
+/// Parses a PAX timestamp: seconds since the Unix epoch, possibly negative
+/// and with a fraction.
+fn pax_time(value: &str) -> Option<SystemTime> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Digits past the ninth are below a nanosecond
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = format!("{digits:0<9}").parse().ok()?;
+    let offset = Duration::new(secs.parse().ok()?, nanos);
+    if negative {
+        UNIX_EPOCH.checked_sub(offset)
+    } else {
+        UNIX_EPOCH.checked_add(offset)
+    }
+}
+
+/// Presents a single-file stream as an archive with one file entry.
+pub(crate) fn walk_single<R, F>(
+    reader: R,
+    format: ArchiveFormat,
+    name: &str,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let info = EntryInfo {
+        name: name.to_string(),
+        kind: EntryKind::File,
+        size: 0,
+        compressed_size: None,
+        mtime: None,
+        mode: None,
+        link_target: None,
+        owner: None,
+        xattrs: Vec::new(),
+        raw_name: None,
+        special: None,
+        comment: None,
+        extra_fields: Vec::new(),
+        encrypted: false,
+    };
+    visit(&info, &mut decoder(format, reader)?)?;
+    Ok(())
+}
+
+/// Derives the name of the file inside a single-file stream from the
+/// stream's path, dropping the format's extension if present.
+fn single_entry_name(location: &Location, format: ArchiveFormat) -> String {
+    let file_name = location
+        .path()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let suffix = format!(".{}", format.extension());
+    let stem = file_name.len().saturating_sub(suffix.len());
+    match file_name.get(stem..) {
+        Some(tail) if stem > 0 && tail.eq_ignore_ascii_case(&suffix) => {
+            file_name[..stem].to_string()
+        }
+        _ if file_name.is_empty() => STREAM_ENTRY_NAME.to_string(),
+        _ => format!("{}.out", file_name),
+    }
+}
+
+pub(crate) fn walk_zip<R, F>(
+    reader: R,
+    passwords: &Passwords,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut archive = zip::ZipArchive::new(Source(reader))?;
+    for i in 0..archive.len() {
+        let mut file = zip_entry(&mut archive, i, passwords)?;
+        let mut info = zip_info(&file);
+        if info.kind == EntryKind::Symlink {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            info.link_target = Some(target);
+            if !visit(&info, &mut io::empty())? {
+                break;
+            }
+        } else if !visit(&info, &mut Decoded(ZipCrcReader::new(&mut file)))? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Error ZIP entries fail with at their end when their data doesn't match
+/// their recorded CRC-32.
+#[derive(Debug)]
+struct CrcMismatchError;
+
+impl fmt::Display for CrcMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("data doesn't match its CRC-32")
+    }
+}
+
+impl std::error::Error for CrcMismatchError {}
+
+/// Returns `true` if `error` is the one [`ZipCrcReader`] fails with on a
+/// CRC-32 mismatch.
+pub(crate) fn is_crc_mismatch(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|error| error.is::<CrcMismatchError>())
+}
+
+/// Reads the data of a ZIP entry, checking it against the entry's CRC-32.
+///
+/// The zip crate checks the CRC as well, but only says so in the message
+/// of its error. This sums the data itself, so a mismatch is told apart
+/// from other failures whatever the zip crate reports at the end.
+pub(crate) struct ZipCrcReader<R> {
+    inner: R,
+    crc: flate2::Crc,
+    read: u64,
+    expected: u32,
+    size: u64,
+    /// Unset for AE-2 encrypted entries, which record no CRC.
+    enabled: bool,
+}
+
+impl<'a, 'b, R: Read> ZipCrcReader<&'a mut zip::read::ZipFile<'b, R>> {
+    pub(crate) fn new(file: &'a mut zip::read::ZipFile<'b, R>) -> Self {
+        let (expected, size) = (file.crc32(), file.size());
+        Self {
+            enabled: !(file.encrypted() && expected == 0),
+            inner: file,
+            crc: flate2::Crc::new(),
+            read: 0,
+            expected,
+            size,
+        }
+    }
+}
+
+impl<R: Read> ZipCrcReader<R> {
+    fn mismatched(&self) -> bool {
+        self.enabled && self.read == self.size && self.crc.sum() != self.expected
+    }
+}
+
+impl<R: Read> Read for ZipCrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(0) if !buf.is_empty() && self.mismatched() => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, CrcMismatchError))
+            }
+            Ok(n) => {
+                self.crc.update(&buf[..n]);
+                self.read += n as u64;
+                Ok(n)
+            }
+            Err(_) if self.mismatched() => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, CrcMismatchError))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Opens entry `index` of a ZIP archive, decrypting it with the first of
+/// `passwords` that fits if it is encrypted.
+pub(crate) fn zip_entry<'a, R: Read + Seek>(
+    archive: &'a mut zip::ZipArchive<R>,
+    index: usize,
+    passwords: &Passwords,
+) -> Result<zip::read::ZipFile<'a, R>, ArchiveError> {
+    if passwords.is_empty() {
+        return Ok(archive.by_index(index)?);
+    }
+    let file = archive.by_index_raw(index)?;
+    if !file.encrypted() {
+        drop(file);
+        return Ok(archive.by_index(index)?);
+    }
+    let info = zip_info(&file);
+    drop(file);
+    // Opening the entry checks the password against the header
+    let password = passwords.unlock(&info, |password| {
+        match password {
+            Some(password) => {
+                archive
+                    .by_index_decrypt(index, password.as_bytes())
+                    .map(drop)
+            }
+            None => archive.by_index(index).map(drop),
+        }
+        .map_err(ArchiveError::from)
+    })?;
+    let file = match password {
+        Some((password, source)) => {
+            passwords.record(index, &info.name, source);
+            archive.by_index_decrypt(index, password.expose_secret().as_bytes())?
+        }
+        None => archive.by_index(index)?,
+    };
+    Ok(file)
+}
+
+fn walk_zip_stream<R, F>(mut reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut reader)? {
+        let mut info = zip_info(&file);
+        if info.kind == EntryKind::Symlink {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            info.link_target = Some(target);
+            if !visit(&info, &mut io::empty())? {
+                break;
+            }
+        } else if !visit(&info, &mut file)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn walk_7z<R, F>(
+    mut reader: R,
+    passwords: &Passwords,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let (archive, password) = read_7z(&mut reader, passwords)?;
+    let has_password = !password.is_empty();
+    let mut archive = sevenz_rust2::ArchiveReader::from_archive(archive, Source(reader), password);
+    // Entries aren't told apart by block here, but 7-Zip encrypts either
+    // every block or none
+    let encrypted = archive.archive().blocks.iter().any(sevenz_encrypted);
+    let mut failure = None;
+    let mut done = false;
+
+    archive.for_each_entries(|entry, data| {
+        // Returning `false` only ends the current block, so keep declining
+        // until every remaining block has been offered.
+        if done {
+            return Ok(false);
+        }
+        match visit_7z_entry(entry, data, has_password, encrypted, &mut visit) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                done = true;
+                Ok(false)
+            }
+            Err(err) => {
+                failure = Some(err);
+                done = true;
+                Ok(false)
+            }
+        }
+    })?;
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Reads the headers of the 7z archive in `reader`, returning them with
+/// the first of `passwords` that decrypts the archive.
+///
+/// Without candidates or a provider the fixed password is taken as it is,
+/// and only found to be wrong once entries fail to decode. Otherwise the
+/// passwords are tried for the first encrypted entry, or for an
+/// empty-named one if the headers are encrypted as well, and checked by
+/// decoding the start of the first encrypted block.
+///
+/// # Errors
+///
+/// Returns [`ArchiveError::InvalidPassword`] if no password fits, and an
+/// error if the headers cannot be read.
+pub(crate) fn read_7z<R: Read + Seek>(
+    reader: &mut R,
+    passwords: &Passwords,
+) -> Result<(sevenz_rust2::Archive, sevenz_rust2::Password), ArchiveError> {
+    let sevenz_password = |password: Option<&str>| {
+        password.map_or_else(sevenz_rust2::Password::empty, sevenz_rust2::Password::new)
+    };
+    let encrypted = |archive: &sevenz_rust2::Archive, file: usize| {
+        archive.stream_map.file_block_index[file]
+            .is_some_and(|block| sevenz_encrypted(&archive.blocks[block]))
+    };
+    let first_encrypted = |archive: &sevenz_rust2::Archive| {
+        (0..archive.files.len()).find(|&file| encrypted(archive, file))
+    };
+    // Every encrypted entry is opened with the one password
+    let record = |archive: &sevenz_rust2::Archive, source: PasswordSource| {
+        for (file, entry) in archive.files.iter().enumerate() {
+            if encrypted(archive, file) {
+                passwords.record(file, entry.name(), source);
+            }
+        }
+    };
+    if !passwords.has_choices() {
+        let password = sevenz_password(passwords.fixed());
+        let archive = sevenz_rust2::Archive::read(reader, &password)?;
+        if passwords.fixed().is_some() {
+            record(&archive, PasswordSource::Fixed);
+        }
+        return Ok((archive, password));
+    }
+    let entry = match sevenz_rust2::Archive::read(reader, &sevenz_rust2::Password::empty()) {
+        Ok(archive) => {
+            match first_encrypted(&archive) {
+                Some(file) => sevenz_info(&archive.files[file]),
+                None => return Ok((archive, sevenz_password(passwords.fixed()))),
+            }
+        }
+        Err(sevenz_rust2::Error::PasswordRequired) => {
+            sevenz_info(&sevenz_rust2::ArchiveEntry::default())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut unlocked = None;
+    let password = passwords.unlock(&entry, |password| {
+        let password = sevenz_password(password);
+        let archive = sevenz_rust2::Archive::read(reader, &password)?;
+        if let Some(file) = first_encrypted(&archive) {
+            let block = archive.stream_map.file_block_index[file].unwrap_or_default();
+            probe_7z_block(&archive, block, reader, &password)?;
+        }
+        unlocked = Some(archive);
+        Ok(())
+    })?;
+    let archive = unlocked.expect("an unlocked archive has been read");
+    let password = password.map(|(password, source)| {
+        record(&archive, source);
+        password
+    });
+    Ok((
+        archive,
+        sevenz_password(password.as_ref().map(SecretString::expose_secret)),
+    ))
+}
+
+/// Checks that `password` decrypts block `block` of a 7z archive by
+/// decoding the start of its first entry with data.
+///
+/// Garbage decrypted with a wrong password rarely decodes for long, and
+/// entries shorter than [`SEVENZ_PROBE_SIZE`] are checked against their
+/// CRC as well.
+fn probe_7z_block<R: Read + Seek>(
+    archive: &sevenz_rust2::Archive,
+    block: usize,
+    reader: &mut R,
+    password: &sevenz_rust2::Password,
+) -> Result<(), ArchiveError> {
+    let mut decoded = Ok(());
+    sevenz_rust2::BlockDecoder::new(1, block, archive, password, reader).for_each_entries(
+        &mut |entry, data| {
+            if entry.size == 0 {
+                return Ok(true);
+            }
+            if io::copy(&mut data.take(SEVENZ_PROBE_SIZE), &mut io::sink()).is_err() {
+                decoded = Err(ArchiveError::InvalidPassword);
+            }
+            Ok(false)
+        },
+    )?;
+    decoded
+}
+
+/// Walks the entries of block `block` of a 7z archive, whose headers have
+/// been read into `archive`, decoding them from `source`.
+///
+/// Entries without data, which belong to no block, are not visited.
+pub(crate) fn walk_7z_block<R, F>(
+    archive: &sevenz_rust2::Archive,
+    block: usize,
+    source: &mut R,
+    password: &sevenz_rust2::Password,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let has_password = !password.is_empty();
+    let encrypted = sevenz_encrypted(&archive.blocks[block]);
+    let mut failure = None;
+    sevenz_rust2::BlockDecoder::new(1, block, archive, password, &mut Source(source))
+        .for_each_entries(&mut |entry, data| {
+            visit_7z_entry(entry, data, has_password, encrypted, &mut visit).or_else(|err| {
+                failure = Some(err);
+                Ok(false)
+            })
+        })?;
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Visits a 7z entry being decoded from `data`, returning whether to go on.
+///
+/// `encrypted` tells whether the entry's block is encrypted, which only
+/// matters if the entry has data.
+fn visit_7z_entry<F>(
+    entry: &sevenz_rust2::ArchiveEntry,
+    data: &mut dyn Read,
+    has_password: bool,
+    encrypted: bool,
+    visit: &mut F,
+) -> Result<bool, ArchiveError>
+where
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    // Failures to decode the entry are reported like those of the 7z
+    // reader itself
+    let decode_error = |e: io::Error| {
+        if has_password {
+            ArchiveError::InvalidPassword
+        } else {
+            sevenz_rust2::Error::from(e).into()
+        }
+    };
+    let mut info = sevenz_info(entry);
+    info.encrypted = encrypted && entry.has_stream;
+    let mut data = TrackedReader {
+        inner: &mut Decoded(data),
+        failed: false,
+    };
+    let result = if info.kind == EntryKind::Symlink {
+        let mut target = String::new();
+        data.read_to_string(&mut target).map_err(decode_error)?;
+        info.link_target = Some(target);
+        visit(&info, &mut io::empty())
+    } else {
+        let result = visit(&info, &mut data);
+        // Solid blocks are decoded sequentially, so unread data must be
+        // drained before the next entry can be reached.
+        io::copy(&mut data, &mut io::sink()).map_err(decode_error)?;
+        result
+    };
+    // 7z has no password verifier, so a wrong password only shows up as a
+    // decoding or CRC failure of the entry data
+    match result {
+        Err(_) if has_password && data.failed => Err(ArchiveError::InvalidPassword),
+        result => result,
+    }
+}
+
+/// A reader that remembers whether reading from it ever failed, to tell
+/// decoding errors apart from errors raised by the consumer.
+struct TrackedReader<'a> {
+    inner: &'a mut dyn Read,
+    failed: bool,
+}
+
+impl Read for TrackedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        if result
+            .as_ref()
+            .is_err_and(|e| e.kind() != io::ErrorKind::Interrupted)
+        {
+            self.failed = true;
+        }
+        result
+    }
+}
+
+/// Builds entry metadata from a ZIP central directory record.
+pub(crate) fn zip_info<R: Read>(file: &zip::read::ZipFile<'_, R>) -> EntryInfo {
+    let extra_fields = extra_fields(file.extra_data().unwrap_or_default());
+    let kind = if file.is_dir() {
+        EntryKind::Directory
+    } else if file.is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    };
+    EntryInfo {
+        name: file.name().to_string(),
+        kind,
+        size: file.size(),
+        compressed_size: Some(file.compressed_size()),
+        mtime: zip_mtime(&extra_fields, file.last_modified()),
+        mode: zip_mode(file),
+        link_target: None,
+        owner: zip_owner(&extra_fields),
+        xattrs: Vec::new(),
+        raw_name: encoding::raw_name(file.name_raw(), file.name()),
+        special: None,
+        comment: (!file.comment().is_empty()).then(|| file.comment().to_string()),
+        extra_fields,
+        encrypted: file.encrypted(),
+    }
+}
+
+/// Splits the extra data of a ZIP header into its fields, leaving out a
+/// truncated last one.
+pub(crate) fn extra_fields(mut data: &[u8]) -> Vec<ExtraField> {
+    let mut fields = Vec::new();
+    while let [a, b, c, d, rest @ ..] = data {
+        let len = usize::from(u16::from_le_bytes([*c, *d]));
+        let Some(field) = rest.get(..len) else {
+            break;
+        };
+        fields.push(ExtraField {
+            id: u16::from_le_bytes([*a, *b]),
+            data: field.to_vec(),
+        });
+        data = &rest[len..];
+    }
+    fields
+}
+
+/// Returns the owner recorded in the Info-ZIP unix extra fields of a ZIP
+/// entry.
+///
+/// The current field holds IDs of any width; the old one holds 16-bit IDs,
+/// in local headers only.
+pub(crate) fn zip_owner(fields: &[ExtraField]) -> Option<Owner> {
+    let id = |bytes: &[u8]| {
+        (bytes.len() <= 8).then(|| {
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |id, &byte| id << 8 | u64::from(byte))
+        })
+    };
+    fields.iter().find_map(|field| {
+        let (uid, gid) = match (field.id, field.data.as_slice()) {
+            (UNIX_OWNER_EXTRA_ID, [1, rest @ ..]) => {
+                let (&uid_len, rest) = rest.split_first()?;
+                let (uid, rest) = rest.split_at_checked(usize::from(uid_len))?;
+                let (&gid_len, rest) = rest.split_first()?;
+                (id(uid)?, id(rest.get(..usize::from(gid_len))?)?)
+            }
+            (OLD_UNIX_EXTRA_ID, [a, b, c, d, ..]) => (id(&[*a, *b])?, id(&[*c, *d])?),
+            _ => return None,
+        };
+        Some(Owner {
+            uid,
+            gid,
+            user: None,
+            group: None,
+        })
+    })
+}
+
+/// Returns the modification time of a ZIP entry, preferring the NTFS
+/// field, which counts 100 ns intervals, over the extended timestamp field,
+/// which counts whole seconds in UTC, over the `dos` time, which only has
+/// two-second resolution.
+pub(crate) fn zip_mtime(fields: &[ExtraField], dos: Option<zip::DateTime>) -> Option<SystemTime> {
+    let ntfs = fields.iter().find_map(|field| {
+        match (field.id, field.data.as_slice()) {
+            // A reserved word, then attribute 1 of 24 bytes: the times
+            // modified, accessed and created
+            (NTFS_EXTRA_ID, [_, _, _, _, 1, 0, 24, 0, times @ ..]) if times.len() >= 24 => {
+                filetime(u64::from_le_bytes(times[..8].try_into().ok()?))
+            }
+            _ => None,
+        }
+    });
+    let extended = || {
+        fields.iter().find_map(|field| {
+            match (field.id, field.data.as_slice()) {
+                // The modification time comes first if the flags say it is
+                // there, as a signed 32-bit count
+                (EXTENDED_TIMESTAMP_EXTRA_ID, [flags, a, b, c, d, ..]) if flags & 1 != 0 => {
+                    let secs = i32::from_le_bytes([*a, *b, *c, *d]);
+                    let offset = Duration::from_secs(secs.unsigned_abs().into());
+                    if secs < 0 {
+                        UNIX_EPOCH.checked_sub(offset)
+                    } else {
+                        UNIX_EPOCH.checked_add(offset)
+                    }
+                }
+                _ => None,
+            }
+        })
+    };
+    ntfs.or_else(extended)
+        .or_else(|| dos.filter(zip::DateTime::is_valid).and_then(dos_time))
+}
+
+/// Returns the permission bits of a ZIP entry made on unix.
+///
+/// Entries from other systems only carry DOS attributes, from which the
+/// zip crate makes up a mode; those are left alone like 7z's Windows
+/// attributes, and so is a unix mode of zero.
+fn zip_mode<R: Read>(file: &zip::read::ZipFile<'_, R>) -> Option<u32> {
+    use zip::read::HasZipMetadata;
+
+    let recorded = file.get_metadata().external_attributes >> 16;
+    file.unix_mode()
+        .filter(|&mode| mode != 0 && mode == recorded)
+        .map(|mode| mode & 0o7777)
+}
+
+/// Returns whether a 7z block is decoded through AES, and so needs a
+/// password.
+pub(crate) fn sevenz_encrypted(block: &sevenz_rust2::Block) -> bool {
+    block
+        .coders
+        .iter()
+        .any(|coder| coder.encoder_method_id() == sevenz_rust2::EncoderMethod::ID_AES256_SHA256)
+}
+
+/// Builds entry metadata from a 7z header record.
+pub(crate) fn sevenz_info(entry: &sevenz_rust2::ArchiveEntry) -> EntryInfo {
+    let mode = (entry.has_windows_attributes && entry.windows_attributes & UNIX_EXTENSION != 0)
+        .then_some(entry.windows_attributes >> 16);
+    let kind = if entry.is_directory {
+        EntryKind::Directory
+    } else if mode.is_some_and(|m| m & UNIX_TYPE_MASK == UNIX_SYMLINK) {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    };
+    EntryInfo {
+        name: entry.name.clone(),
+        kind,
+        size: entry.size,
+        compressed_size: None,
+        mtime: entry
+            .has_last_modified_date
+            .then(|| entry.last_modified_date.into()),
+        mode: mode.map(|m| m & 0o7777),
+        link_target: None,
+        owner: None,
+        xattrs: Vec::new(),
+        raw_name: None,
+        special: None,
+        comment: None,
+        extra_fields: Vec::new(),
+        encrypted: false,
+    }
+}
+
+/// Strips leading `./` and `/` and trailing `/` from an entry name, and
+/// converts `\\` separators to `/`.
+pub(crate) fn normalize_name(name: &str) -> String {
+    let mut name = name.replace('\\', "/");
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.to_string();
+    }
+    name.trim_matches('/').to_string()
+}
+
+/// Converts a zip DOS timestamp (interpreted as UTC) to a `SystemTime`.
+pub(crate) fn dos_time(time: zip::DateTime) -> Option<SystemTime> {
+    let days = days_from_civil(time.year() as i64, time.month() as i64, time.day() as i64);
+    let secs = days * 86_400
+        + time.hour() as i64 * 3_600
+        + time.minute() as i64 * 60
+        + time.second() as i64;
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Converts a Windows FILETIME, in 100 ns units since 1601, to a
+/// [`SystemTime`].
+pub(crate) fn filetime(value: u64) -> Option<SystemTime> {
+    if value == 0 {
+        return None;
+    }
+    let since_1601 = Duration::new(value / 10_000_000, (value % 10_000_000) as u32 * 100);
+    let epoch_offset = Duration::from_secs(WINDOWS_EPOCH_OFFSET);
+    match since_1601.checked_sub(epoch_offset) {
+        Some(since_1970) => UNIX_EPOCH.checked_add(since_1970),
+        None => UNIX_EPOCH.checked_sub(epoch_offset - since_1601),
+    }
+}
+
+/// Returns the number of days between 1970-01-01 and the given civil date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
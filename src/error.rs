@@ -2,10 +2,11 @@ use std::{
     borrow::Cow,
     fmt::{self, Display},
     io,
+    path::Path,
     sync::Arc,
 };
 
-use crate::format::ArchiveFormat;
+use crate::{audit::Finding, format::ArchiveFormat};
 
 pub type Result<T> = std::result::Result<T, ArchiveError>;
 
@@ -103,6 +104,26 @@ pub enum ArchiveError {
     /// is incorrect or when no password is provided for an encrypted archive.
     InvalidPassword,
 
+    /// A configured resource limit was exceeded.
+    ///
+    /// This error occurs when an operation would use more memory or disk
+    /// space than the caller allowed, and is the usual symptom of a
+    /// decompression bomb.
+    LimitExceeded { limit: ErrorStr, max: u64 },
+
+    /// The operation was cancelled.
+    ///
+    /// This error is returned when a [`CancellationToken`](crate::CancellationToken)
+    /// passed to an extraction or creation routine is triggered.
+    Cancelled,
+
+    /// The archive was rejected for suspicious metadata.
+    ///
+    /// This error is returned by extractions under
+    /// [`ExtractOptions::strict`](crate::ExtractOptions::strict), and lists
+    /// every suspicious entry found.
+    Rejected { findings: Vec<Finding> },
+
     /// A custom error with a specific message.
     ///
     /// This variant allows for application-specific errors that don't
@@ -170,6 +191,24 @@ impl Display for ArchiveError {
             ArchiveError::InvalidPassword => {
                 write!(f, "Invalid password provided for encrypted archive")
             }
+            ArchiveError::LimitExceeded {
+                limit,
+                max,
+            } => {
+                write!(f, "Limit exceeded: {} (maximum {})", limit, max)
+            }
+            ArchiveError::Cancelled => {
+                write!(f, "Operation cancelled")
+            }
+            ArchiveError::Rejected {
+                findings,
+            } => {
+                write!(f, "Archive rejected for suspicious metadata")?;
+                for (i, finding) in findings.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { ": " } else { ", " }, finding)?;
+                }
+                Ok(())
+            }
             ArchiveError::Custom {
                 message,
             } => {
@@ -292,6 +331,63 @@ impl ArchiveError {
         }
     }
 
+    /// Creates an `ArchiveError` from a standard `io::Error` raised while
+    /// accessing `path`.
+    ///
+    /// Missing files, denied access and existing files give
+    /// [`NotFound`](Self::NotFound), [`PermissionDenied`](Self::PermissionDenied)
+    /// and [`AlreadyExists`](Self::AlreadyExists) errors naming `path`, and
+    /// other errors an [`Io`](Self::Io) error whose context names it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::fs::File;
+    /// use compak::ArchiveError;
+    ///
+    /// if let Err(io_err) = File::open("missing.zip") {
+    ///     let archive_err = ArchiveError::io_at_path("missing.zip", io_err);
+    ///     assert!(matches!(archive_err, ArchiveError::NotFound { .. }));
+    ///     assert!(archive_err.to_string().contains("missing.zip"));
+    /// }
+    /// ```
+    pub fn io_at_path(path: impl AsRef<Path>, source: io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        let context = format!("access to {}", path);
+        Self::from_io(Cow::Owned(path), Cow::Owned(context), source)
+    }
+
+    /// Creates an `ArchiveError` from an `io::Error` raised while the entry
+    /// `name` was extracted to `path`.
+    pub(crate) fn io_at_entry(name: &str, path: &Path, source: io::Error) -> Self {
+        let path = path.display().to_string();
+        let context = format!("extracting {} to {}", name, path);
+        Self::from_io(Cow::Owned(path), Cow::Owned(context), source)
+    }
+
+    /// Maps the kinds of `source` that are about a path to their own
+    /// variants, and wraps the others with `context`.
+    fn from_io(path: ErrorStr, context: ErrorStr, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => {
+                Self::NotFound {
+                    path,
+                }
+            }
+            io::ErrorKind::PermissionDenied => {
+                Self::PermissionDenied {
+                    path,
+                }
+            }
+            io::ErrorKind::AlreadyExists => {
+                Self::AlreadyExists {
+                    path,
+                }
+            }
+            _ => Self::io_from_error(context, source),
+        }
+    }
+
     /// Creates a format error with a static message.
     ///
     /// # Examples
@@ -428,6 +524,22 @@ impl ArchiveError {
         }
     }
 
+    /// Creates a "limit exceeded" error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ArchiveError;
+    ///
+    /// let error = ArchiveError::limit_exceeded("total uncompressed size", 1 << 30);
+    /// ```
+    pub const fn limit_exceeded(limit: &'static str, max: u64) -> Self {
+        Self::LimitExceeded {
+            limit: Cow::Borrowed(limit),
+            max,
+        }
+    }
+
     /// Creates a custom error with a static message.
     ///
     /// # Examples
@@ -444,6 +556,205 @@ impl ArchiveError {
     }
 }
 
+impl ArchiveError {
+    /// Returns the broad class of the error.
+    ///
+    /// Errors wrapped by [`ArchiveError::nested`] take the category of the
+    /// error they wrap, if it is an `ArchiveError` or an `io::Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::{ArchiveError, ErrorCategory};
+    ///
+    /// let error = ArchiveError::zip_invalid("corrupted central directory");
+    /// assert_eq!(error.category(), ErrorCategory::Corruption);
+    ///
+    /// let error = ArchiveError::nested("extracting archive", error);
+    /// assert!(error.is_corruption());
+    /// ```
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ArchiveError::Io {
+                kind, ..
+            } => ErrorCategory::from_io(*kind),
+            ArchiveError::Format {
+                ..
+            }
+            | ArchiveError::Compression {
+                ..
+            }
+            | ArchiveError::InvalidArchive {
+                ..
+            } => ErrorCategory::Corruption,
+            ArchiveError::NotFound {
+                ..
+            } => ErrorCategory::NotFound,
+            ArchiveError::PermissionDenied {
+                ..
+            } => ErrorCategory::PermissionDenied,
+            ArchiveError::AlreadyExists {
+                ..
+            } => ErrorCategory::AlreadyExists,
+            ArchiveError::Unsupported {
+                ..
+            } => ErrorCategory::Unsupported,
+            ArchiveError::InvalidPassword => ErrorCategory::Password,
+            ArchiveError::LimitExceeded {
+                ..
+            } => ErrorCategory::LimitExceeded,
+            ArchiveError::Cancelled => ErrorCategory::Cancelled,
+            ArchiveError::Rejected {
+                ..
+            } => ErrorCategory::Rejected,
+            ArchiveError::Custom {
+                ..
+            } => ErrorCategory::Other,
+            ArchiveError::Nested {
+                source, ..
+            } => {
+                if let Some(inner) = source.downcast_ref::<ArchiveError>() {
+                    inner.category()
+                } else if let Some(inner) = source.downcast_ref::<io::Error>() {
+                    ErrorCategory::from_io(inner.kind())
+                } else {
+                    ErrorCategory::Other
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the archive is damaged or not in the format it was
+    /// read as.
+    ///
+    /// Data a codec fails to decode counts, whichever codec it is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::TarGz).unwrap();
+    /// builder.add_bytes("notes.txt", b"to be damaged").unwrap();
+    /// let mut tar_gz = builder.finish().unwrap().into_inner();
+    /// tar_gz[3] = 0xff; // reserved header flags
+    ///
+    /// let error = Archive::from_bytes(tar_gz).unwrap().extract_to_memory().unwrap_err();
+    /// assert!(error.is_corruption());
+    /// ```
+    pub fn is_corruption(&self) -> bool {
+        self.category() == ErrorCategory::Corruption
+    }
+
+    /// Returns `true` if a file, archive or entry doesn't exist.
+    pub fn is_not_found(&self) -> bool {
+        self.category() == ErrorCategory::NotFound
+    }
+
+    /// Returns `true` if access to a file or directory was denied.
+    pub fn is_permission(&self) -> bool {
+        self.category() == ErrorCategory::PermissionDenied
+    }
+
+    /// Returns `true` if the error is a transient I/O failure, such as an
+    /// interrupted or timed out read, that may not recur if the operation is
+    /// tried again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ArchiveError::Io {
+                kind, ..
+            } => is_transient(*kind),
+            ArchiveError::Nested {
+                source, ..
+            } => {
+                if let Some(inner) = source.downcast_ref::<ArchiveError>() {
+                    inner.is_retryable()
+                } else {
+                    source
+                        .downcast_ref::<io::Error>()
+                        .is_some_and(|inner| is_transient(inner.kind()))
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns `true` for the kinds of I/O errors that may not recur.
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// The broad class of an [`ArchiveError`], from [`ArchiveError::category`].
+///
+/// Unlike the variants of `ArchiveError`, which say where an error came
+/// from, categories say what went wrong, so callers can decide how to react
+/// without matching every variant. More categories may be added, so matches
+/// need a wildcard arm.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::{Archive, ErrorCategory};
+///
+/// match Archive::open("backup.zip").and_then(|archive| archive.extract_to("out")) {
+///     Ok(_) => {}
+///     Err(err) if err.category() == ErrorCategory::Password => {
+///         eprintln!("the archive is encrypted");
+///     }
+///     Err(err) if err.is_corruption() => eprintln!("the archive is damaged: {}", err),
+///     Err(err) => eprintln!("extraction failed: {}", err),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+    /// A file, archive or entry doesn't exist.
+    NotFound,
+    /// Access to a file or directory was denied.
+    PermissionDenied,
+    /// A file or directory already exists where one was to be created.
+    AlreadyExists,
+    /// The archive is damaged, truncated or not in the format it was read
+    /// as.
+    Corruption,
+    /// The archive or the operation needs something that isn't supported.
+    Unsupported,
+    /// A password is missing or wrong.
+    Password,
+    /// A configured resource limit was exceeded.
+    LimitExceeded,
+    /// The operation was cancelled.
+    Cancelled,
+    /// The archive was rejected for suspicious metadata.
+    Rejected,
+    /// Any other I/O failure, such as a full disk.
+    Io,
+    /// Anything else.
+    Other,
+}
+
+impl ErrorCategory {
+    fn from_io(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => ErrorCategory::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorCategory::PermissionDenied,
+            io::ErrorKind::AlreadyExists => ErrorCategory::AlreadyExists,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => ErrorCategory::Corruption,
+            _ => ErrorCategory::Io,
+        }
+    }
+}
+
 impl From<zip::result::ZipError> for ArchiveError {
     fn from(err: zip::result::ZipError) -> Self {
         use zip::result::ZipError;
@@ -456,6 +767,7 @@ impl From<zip::result::ZipError> for ArchiveError {
                     reason: msg,
                 }
             }
+            ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => Self::InvalidPassword,
             ZipError::UnsupportedArchive(msg) => {
                 Self::Unsupported {
                     feature: Cow::Owned(format!("ZIP feature: {}", msg)),
@@ -478,35 +790,90 @@ impl From<zip::result::ZipError> for ArchiveError {
 
 impl From<sevenz_rust2::Error> for ArchiveError {
     fn from(err: sevenz_rust2::Error) -> Self {
-        Self::Custom {
-            message: Cow::Owned(format!("7-Zip error: {}", err)),
+        use sevenz_rust2::Error;
+
+        match err {
+            Error::PasswordRequired | Error::MaybeBadPassword(_) => Self::InvalidPassword,
+            Error::Io(io_err, _) | Error::FileOpen(io_err, _) => {
+                Self::io_from_error("7z I/O operation", io_err)
+            }
+            Error::BadSignature(_)
+            | Error::ChecksumVerificationFailed
+            | Error::NextHeaderCrcMismatch
+            | Error::BadTerminatedStreamsInfo(_)
+            | Error::BadTerminatedUnpackInfo
+            | Error::BadTerminatedPackInfo(_)
+            | Error::BadTerminatedSubStreamsInfo
+            | Error::BadTerminatedHeader(_)
+            | Error::Other(_) => {
+                Self::InvalidArchive {
+                    format: ArchiveFormat::SevenZ,
+                    reason: Cow::Owned(err.to_string()),
+                }
+            }
+            Error::UnsupportedVersion {
+                ..
+            }
+            | Error::ExternalUnsupported
+            | Error::UnsupportedCompressionMethod(_)
+            | Error::Unsupported(_) => {
+                Self::Unsupported {
+                    feature: Cow::Owned(format!("7-Zip feature: {}", err)),
+                }
+            }
+            Error::FileNotFound => {
+                Self::NotFound {
+                    path: Cow::Borrowed("file in 7z archive"),
+                }
+            }
+            _ => {
+                Self::Custom {
+                    message: Cow::Owned(format!("7-Zip error: {}", err)),
+                }
+            }
         }
     }
 }
 
-impl From<io::Error> for ArchiveError {
-    fn from(err: io::Error) -> Self {
-        match err.kind() {
-            io::ErrorKind::NotFound => {
-                Self::NotFound {
-                    path: Cow::Borrowed("unknown"),
+#[cfg(feature = "rar")]
+impl From<unrar::error::UnrarError> for ArchiveError {
+    fn from(err: unrar::error::UnrarError) -> Self {
+        use unrar::error::Code;
+
+        match err.code {
+            Code::MissingPassword | Code::BadPassword => Self::InvalidPassword,
+            Code::BadData | Code::BadArchive => {
+                Self::InvalidArchive {
+                    format: ArchiveFormat::Rar,
+                    reason: Cow::Owned(err.to_string()),
                 }
             }
-            io::ErrorKind::PermissionDenied => {
-                Self::PermissionDenied {
-                    path: Cow::Borrowed("unknown"),
+            Code::UnknownFormat => {
+                Self::Unsupported {
+                    feature: Cow::Owned(format!("RAR feature: {}", err)),
                 }
             }
-            io::ErrorKind::AlreadyExists => {
-                Self::AlreadyExists {
-                    path: Cow::Borrowed("unknown"),
+            _ => {
+                Self::Custom {
+                    message: Cow::Owned(format!("RAR error: {}", err)),
                 }
             }
-            _ => Self::io_from_error("I/O operation", err),
         }
     }
 }
 
+/// Converts I/O errors whose path isn't known. Errors from accessing a
+/// file go through [`ArchiveError::io_at_path`] instead.
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        Self::from_io(
+            Cow::Borrowed("unknown"),
+            Cow::Borrowed("I/O operation"),
+            err,
+        )
+    }
+}
+
 /// Extension trait for creating `ErrorStr` from different string types.
 ///
 /// This trait is used internally to provide a consistent interface for
@@ -575,3 +942,23 @@ impl<T> ErrorContext<T> for Result<T> {
         self.map_err(|err| ArchiveError::nested(ErrorStr::from_static(context), err))
     }
 }
+
+/// Extension trait for naming the path an I/O operation failed on.
+pub(crate) trait PathContext<T> {
+    /// Records `path` in the error, as [`ArchiveError::io_at_path`] does.
+    fn at_path(self, path: impl AsRef<Path>) -> Result<T>;
+
+    /// Records `path` and the entry `name` being extracted to it in the
+    /// error.
+    fn at_entry(self, name: &str, path: &Path) -> Result<T>;
+}
+
+impl<T> PathContext<T> for std::result::Result<T, io::Error> {
+    fn at_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|err| ArchiveError::io_at_path(path, err))
+    }
+
+    fn at_entry(self, name: &str, path: &Path) -> Result<T> {
+        self.map_err(|err| ArchiveError::io_at_entry(name, path, err))
+    }
+}
@@ -0,0 +1,3043 @@
+//! The extraction engine shared by every archive format.
+//!
+//! Each format only has to walk its entries in storage order; turning those
+//! entries into files, directories and links on disk happens here, so every
+//! format gets the same path checks, link handling and metadata restoration.
+
+mod parallel;
+pub(crate) mod sink;
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub use self::sink::{ExtractSink, FsSink, MemorySink};
+use crate::{
+    audit::{self, Auditor},
+    cfb, edit,
+    encoding::Encoding,
+    entry::{self, EntryInfo, EntryKind, SpecialFile, SpecialKind},
+    error::{ArchiveError, ErrorCategory, PathContext},
+    format::ArchiveFormat,
+    glob::Glob,
+    hint::DropBehind,
+    owner::Owners,
+    password::{PasswordProvider, Passwords, SecretString, UnlockedEntry},
+    progress::{CancellationToken, Progress, ProgressCallback, ProgressTracker},
+    volume::Location,
+    wim, xar, xattr,
+};
+
+/// Default size of the buffer used to copy file contents.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files each thread writing files keeps open: the archive and the file
+/// being written.
+const FILES_PER_THREAD: usize = 2;
+
+/// A caller-supplied predicate deciding which entries are extracted.
+type EntryFilter = Arc<dyn Fn(&EntryInfo) -> bool + Send + Sync>;
+
+/// A caller-supplied rewrite of output paths relative to the output directory.
+type PathMapper = Arc<dyn Fn(&Path) -> Option<PathBuf> + Send + Sync>;
+
+/// What to do when an entry would be written over an existing path.
+///
+/// Conflicts are detected for files, symlinks and hard links; a directory
+/// entry for a directory that already exists simply merges into it. Every
+/// conflict that is resolved is recorded in
+/// [`ExtractReport::conflicts`].
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, OverwritePolicy};
+///
+/// let options = ExtractOptions::new().overwrite(OverwritePolicy::Skip);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace the existing file or link.
+    #[default]
+    Overwrite,
+    /// Keep the existing path and drop the entry.
+    Skip,
+    /// Abort extraction with [`ArchiveError::AlreadyExists`].
+    Error,
+    /// Write the entry next to the existing path as `name (1).ext`, using
+    /// the first free number.
+    RenameNew,
+    /// Move the existing path aside to `name~`, replacing any earlier
+    /// backup, then write the entry.
+    BackupExisting,
+}
+
+/// What to do with entries whose paths would escape the output directory.
+///
+/// An entry path is unsafe if it contains a `..` component or is absolute
+/// (starts with `/` or a drive prefix such as `C:`). `\` is treated as a
+/// path separator on every platform, so `..\..\evil` is caught everywhere.
+/// Symlinks whose target leads outside the output directory, including
+/// every absolute target, are handled by this policy too; see
+/// [`SymlinkPolicy::Preserve`]. Independently of it, no entry is ever
+/// written through a symlink that resolves outside the output directory.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, UnsafePathPolicy};
+///
+/// let options = ExtractOptions::new().unsafe_paths(UnsafePathPolicy::Error);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsafePathPolicy {
+    /// Skip entries containing `..` and strip the root from absolute paths,
+    /// like `tar` does.
+    #[default]
+    Skip,
+    /// Rewrite unsafe paths by dropping `..` components and the root, so
+    /// `../../etc/passwd` is written to `etc/passwd`.
+    Sanitize,
+    /// Abort extraction with [`ArchiveError::InvalidArchive`] on the first
+    /// unsafe path, including absolute ones.
+    Error,
+}
+
+/// What to do with entry names that Windows can't store.
+///
+/// Windows refuses names containing `<`, `>`, `:`, `"`, `|`, `?`, `*` or
+/// control characters, silently drops trailing dots and spaces, so `file.`
+/// and `file` become the same file, and opens a device instead of a file
+/// for `con`, `aux`, `nul`, `com1` and the other DOS device names, whatever
+/// their extension. Every component of an output path is checked, after
+/// [`ExtractOptions::map_path`]. The defaults are [`Escape`](Self::Escape)
+/// on Windows and [`Keep`](Self::Keep) elsewhere, but any policy can be
+/// used on any platform, to extract a tree that can be copied to Windows
+/// later.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, WindowsNamePolicy};
+///
+/// let options = ExtractOptions::new().windows_names(WindowsNamePolicy::Rename);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowsNamePolicy {
+    /// Write names as they are.
+    Keep,
+    /// Replace the offending characters by their `%XX` escapes, so
+    /// `a:b.txt` is written to `a%3Ab.txt`, `file.` to `file%2E` and
+    /// `aux.txt` to `au%78.txt`.
+    Escape,
+    /// Replace the offending characters by `_` and append `_` to device
+    /// names, so `a:b.txt` is written to `a_b.txt`, `file.` to `file_`
+    /// and `aux.txt` to `aux_.txt`.
+    Rename,
+    /// Drop the entries.
+    Skip,
+    /// Abort extraction with [`ArchiveError::InvalidArchive`] on the first
+    /// such name.
+    Error,
+}
+
+impl Default for WindowsNamePolicy {
+    fn default() -> Self {
+        if cfg!(windows) {
+            WindowsNamePolicy::Escape
+        } else {
+            WindowsNamePolicy::Keep
+        }
+    }
+}
+
+/// How symbolic link entries are extracted.
+///
+/// Whatever the policy, no entry is ever written through a symlink that
+/// resolves outside the output directory.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, SymlinkPolicy};
+///
+/// let options = ExtractOptions::new().symlinks(SymlinkPolicy::Materialize);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Create symlinks whose target stays inside the output directory.
+    ///
+    /// Targets are resolved from the link's directory, following the links
+    /// already extracted. A link leading elsewhere, such as any absolute
+    /// target, is handled according to [`UnsafePathPolicy`]: skipped,
+    /// rewritten to point to the same path below the output directory, so
+    /// `/usr/lib/libz.so` becomes `../usr/lib/libz.so` for a link in `lib`,
+    /// or rejected.
+    #[default]
+    Preserve,
+    /// Drop symlink entries.
+    Skip,
+    /// Write a copy of the file or directory each link points to in place
+    /// of the link, once every other entry has been written.
+    ///
+    /// Targets are contained like under [`Preserve`](Self::Preserve), and
+    /// links whose target does not exist are dropped. Symlinks inside a
+    /// copied directory are not copied. Copies count towards the size
+    /// limits of [`ExtractLimits`].
+    Materialize,
+    /// Abort extraction with [`ArchiveError::InvalidArchive`] on the first
+    /// symlink entry.
+    Error,
+}
+
+/// How device nodes, FIFOs and sockets are extracted.
+///
+/// Only TAR and cpio archives record which special file an entry is, in
+/// [`EntryInfo::special`]; the special files of other formats are always
+/// dropped unless the policy is [`Error`](Self::Error). Sockets are always
+/// dropped too, since only a process listening on one can create it, and
+/// so is everything under an [`ExtractSink`]. Every special file dropped
+/// counts in [`ExtractReport::skipped`].
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, SpecialFilePolicy};
+///
+/// let options = ExtractOptions::new().special_files(SpecialFilePolicy::Fifos);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecialFilePolicy {
+    /// Drop every special file.
+    #[default]
+    Skip,
+    /// Recreate FIFOs and drop device nodes.
+    Fifos,
+    /// Recreate FIFOs and device nodes.
+    ///
+    /// Creating a device node takes privileges, such as `CAP_MKNOD` on
+    /// Linux; without them, device nodes are dropped. Has no effect on
+    /// non-unix platforms.
+    Recreate,
+    /// Abort extraction with [`ArchiveError::InvalidArchive`] on the first
+    /// special file.
+    Error,
+}
+
+/// What to do with ZIP entries whose data doesn't match their recorded
+/// CRC-32.
+///
+/// The mismatch only shows once a file has been decoded in full, so the
+/// file is on disk by then. Every mismatch that doesn't stop extraction is
+/// recorded in [`ExtractReport::crc_mismatches`].
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{CrcMismatchPolicy, ExtractOptions};
+///
+/// let options = ExtractOptions::new().crc_mismatch(CrcMismatchPolicy::Warn);
+/// ```
+///
+/// A stored file with a flipped bit fails extraction by default, and is
+/// kept and reported under [`Warn`](Self::Warn):
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use compak::{
+///     Archive, ArchiveFormat, Codec, CompressionOptions, CrcMismatchPolicy, ExtractOptions,
+///     writer::ArchiveBuilder,
+/// };
+///
+/// let options = CompressionOptions::new().zip_compression(Codec::Store);
+/// let mut builder =
+///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &options).unwrap();
+/// builder.add_bytes("notes.txt", b"hello world").unwrap();
+/// let mut zip = builder.finish().unwrap().into_inner();
+/// let at = zip.windows(11).position(|data| data == b"hello world").unwrap();
+/// zip[at] ^= 0x20;
+/// let archive = Archive::from_bytes(zip).unwrap();
+///
+/// let out = std::env::temp_dir().join("compak-crc-mismatch-doctest");
+/// assert!(archive.extract_to(out.join("strict")).is_err());
+/// let options = ExtractOptions::new().crc_mismatch(CrcMismatchPolicy::Warn);
+/// let report = archive.extract_to_with(out.join("warn"), &options).unwrap();
+/// assert_eq!(report.crc_mismatches[0].name, "notes.txt");
+/// assert_eq!(std::fs::read(out.join("warn/notes.txt")).unwrap(), b"Hello world");
+/// # std::fs::remove_dir_all(&out).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CrcMismatchPolicy {
+    /// Abort extraction with [`ArchiveError::Io`].
+    #[default]
+    Error,
+    /// Keep the file as it was decoded and go on.
+    Warn,
+    /// Remove the file and go on.
+    Skip,
+}
+
+/// How a single conflict was resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// The existing path was replaced.
+    Overwritten,
+    /// The entry was not written.
+    Skipped,
+    /// The entry was written to a different path instead.
+    Renamed(PathBuf),
+    /// The existing path was moved to the given backup path.
+    BackedUp(PathBuf),
+}
+
+/// An entry that collided with an existing path during extraction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Name of the entry inside the archive.
+    pub name: String,
+    /// Path the entry would originally have been written to.
+    pub path: PathBuf,
+    /// What was done about it.
+    pub resolution: ConflictResolution,
+}
+
+/// A file whose data didn't match its recorded CRC-32.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrcMismatch {
+    /// Name of the entry inside the archive.
+    pub name: String,
+    /// Path the file was written to, which no longer exists under
+    /// [`CrcMismatchPolicy::Skip`].
+    pub path: PathBuf,
+}
+
+/// Summary of a completed extraction.
+///
+/// Returned by [`Archive::extract_to_with`](crate::Archive::extract_to_with).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    /// Regular files written, including copies made of links.
+    pub files: u64,
+    /// Directories created, including the missing parents of entries.
+    pub directories: u64,
+    /// Symbolic links created.
+    pub symlinks: u64,
+    /// Hard links created.
+    pub hardlinks: u64,
+    /// FIFOs and device nodes created under
+    /// [`ExtractOptions::special_files`].
+    pub special_files: u64,
+    /// Bytes of file contents written.
+    pub bytes_written: u64,
+    /// Bytes of entry contents read from the archive, after decompression.
+    pub bytes_read: u64,
+    /// Entries left out by filters, [`ExtractOptions::strip_components`],
+    /// the path mapper or a policy, or because they are special files.
+    pub skipped: u64,
+    /// Time the extraction took.
+    pub duration: Duration,
+    /// Conflicts with existing paths, in the order they were encountered.
+    pub conflicts: Vec<Conflict>,
+    /// Files that failed their CRC check, in the order they were found,
+    /// under [`CrcMismatchPolicy::Warn`] and [`CrcMismatchPolicy::Skip`].
+    pub crc_mismatches: Vec<CrcMismatch>,
+    /// Entries skipped as malformed under [`ExtractOptions::lenient`].
+    pub malformed: u64,
+    /// What a dry run under [`ExtractOptions::dry_run`] would have written,
+    /// in archive order.
+    pub planned: Vec<PlannedEntry>,
+    /// Encrypted entries of ZIP and 7z archives and the passwords that
+    /// opened them, in archive order.
+    pub unlocked: Vec<UnlockedEntry>,
+}
+
+impl ExtractReport {
+    /// Adds the counts and lists of `other`, from extracting another part
+    /// of the same archive, to this report.
+    pub(crate) fn absorb(&mut self, other: ExtractReport) {
+        self.files += other.files;
+        self.directories += other.directories;
+        self.symlinks += other.symlinks;
+        self.hardlinks += other.hardlinks;
+        self.special_files += other.special_files;
+        self.bytes_written += other.bytes_written;
+        self.bytes_read += other.bytes_read;
+        self.skipped += other.skipped;
+        self.duration += other.duration;
+        self.conflicts.extend(other.conflicts);
+        self.crc_mismatches.extend(other.crc_mismatches);
+        self.malformed += other.malformed;
+        self.planned.extend(other.planned);
+        self.unlocked.extend(other.unlocked);
+    }
+}
+
+/// An entry a dry run would have written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedEntry {
+    /// Name of the entry as stored in the archive.
+    pub name: String,
+    /// Path the entry would have been written to, after conflicts are
+    /// resolved.
+    pub path: PathBuf,
+    /// Kind of the entry.
+    pub kind: EntryKind,
+    /// Uncompressed size recorded for the entry.
+    pub size: u64,
+}
+
+/// The result of a best-effort extraction, which carries on past entries
+/// that fail.
+///
+/// Returned by
+/// [`Archive::extract_to_best_effort`](crate::Archive::extract_to_best_effort).
+#[derive(Debug, Clone, Default)]
+pub struct ExtractionOutcome {
+    /// Paths written, in the order their entries were extracted.
+    pub extracted: Vec<PathBuf>,
+    /// Entries that could not be extracted, with why, in archive order.
+    pub failed: Vec<(EntryInfo, ArchiveError)>,
+    /// Why the archive could not be read past the last entry visited, if
+    /// it couldn't.
+    pub error: Option<ArchiveError>,
+    /// Conflicts and CRC mismatches of the entries extracted.
+    pub report: ExtractReport,
+}
+
+impl ExtractionOutcome {
+    /// Returns `true` if every entry was extracted and the whole archive
+    /// was read.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.error.is_none()
+    }
+}
+
+/// Resource limits enforced while extracting untrusted archives.
+///
+/// Every limit is off by default. Sizes are checked against the bytes
+/// actually decompressed rather than the sizes recorded in entry headers,
+/// so an archive that lies about its contents cannot get past them.
+/// Exceeding any limit stops extraction with
+/// [`ArchiveError::LimitExceeded`]; entries written so far, including the
+/// partially written one, are left in place.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractLimits, ExtractOptions};
+///
+/// let limits = ExtractLimits::new()
+///     .max_total_size(1 << 30)
+///     .max_entry_size(256 << 20)
+///     .max_entries(10_000)
+///     .max_compression_ratio(100)
+///     .max_path_length(1024)
+///     .max_depth(32);
+/// let options = ExtractOptions::new().limits(limits);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractLimits {
+    max_total_size: Option<u64>,
+    max_entry_size: Option<u64>,
+    max_entries: Option<u64>,
+    max_compression_ratio: Option<u64>,
+    max_path_length: Option<u64>,
+    max_depth: Option<u64>,
+}
+
+impl ExtractLimits {
+    /// Creates a set of limits with every limit disabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps the combined uncompressed size of all extracted files.
+    pub fn max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = Some(bytes);
+        self
+    }
+
+    /// Caps the uncompressed size of any single file.
+    pub fn max_entry_size(mut self, bytes: u64) -> Self {
+        self.max_entry_size = Some(bytes);
+        self
+    }
+
+    /// Caps the number of entries read from the archive, including entries
+    /// that are filtered out or skipped.
+    pub fn max_entries(mut self, count: u64) -> Self {
+        self.max_entries = Some(count);
+        self
+    }
+
+    /// Caps how many times larger the extracted data may be than the
+    /// compressed data it came from.
+    ///
+    /// The ratio is checked per entry when the format records compressed
+    /// sizes per entry (ZIP and non-solid 7z), and for the archive as a
+    /// whole against the size of the archive file. Archives read from a
+    /// stream only get the per-entry check.
+    pub fn max_compression_ratio(mut self, ratio: u64) -> Self {
+        self.max_compression_ratio = Some(ratio);
+        self
+    }
+
+    /// Caps the length in bytes of the path of any entry, relative to the
+    /// output directory.
+    ///
+    /// Paths are measured as they would be written, after
+    /// [`ExtractOptions::strip_components`] and
+    /// [`ExtractOptions::map_path`].
+    pub fn max_path_length(mut self, bytes: u64) -> Self {
+        self.max_path_length = Some(bytes);
+        self
+    }
+
+    /// Caps how many directories deep any entry may be written, counting
+    /// the entry itself, so `a/b/c.txt` is three levels deep.
+    ///
+    /// Depths are measured like [`max_path_length`](Self::max_path_length).
+    pub fn max_depth(mut self, levels: u64) -> Self {
+        self.max_depth = Some(levels);
+        self
+    }
+
+    /// Checks the output `path` of an entry, relative to the output
+    /// directory, against the path limits.
+    fn check_path(&self, path: &Path) -> Result<(), ArchiveError> {
+        if let Some(max) = self.max_path_length
+            && path.as_os_str().len() as u64 > max
+        {
+            return Err(ArchiveError::limit_exceeded("path length", max));
+        }
+        if let Some(max) = self.max_depth
+            && path.components().count() as u64 > max
+        {
+            return Err(ArchiveError::limit_exceeded("path depth", max));
+        }
+        Ok(())
+    }
+}
+
+/// Options controlling how an archive is extracted.
+///
+/// Passed to [`Archive::extract_to_with`](crate::Archive::extract_to_with).
+/// The defaults match [`Archive::extract_to`](crate::Archive::extract_to):
+/// permission bits and modification times are restored, existing files are
+/// overwritten and file contents are copied through a 64 KiB buffer.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::ExtractOptions;
+///
+/// let options = ExtractOptions::new()
+///     .preserve_permissions(false)
+///     .buffer_size(1024 * 1024);
+/// ```
+#[derive(Clone)]
+pub struct ExtractOptions {
+    preserve_permissions: bool,
+    preserve_mtime: bool,
+    preserve_ownership: bool,
+    numeric_owner: bool,
+    preserve_xattrs: bool,
+    overwrite: OverwritePolicy,
+    unsafe_paths: UnsafePathPolicy,
+    windows_names: WindowsNamePolicy,
+    symlinks: SymlinkPolicy,
+    special_files: SpecialFilePolicy,
+    crc_mismatch: CrcMismatchPolicy,
+    lenient: bool,
+    strict: bool,
+    dry_run: bool,
+    atomic: bool,
+    preserve_hardlinks: bool,
+    sparse_files: bool,
+    limits: ExtractLimits,
+    password: Option<SecretString>,
+    candidate_passwords: Vec<SecretString>,
+    password_provider: Option<Arc<dyn PasswordProvider>>,
+    include: Vec<Glob>,
+    filter: Option<EntryFilter>,
+    strip_components: usize,
+    map_path: Option<PathMapper>,
+    filename_encoding: Option<Encoding>,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    buffer_size: usize,
+    threads: usize,
+    max_open_files: Option<usize>,
+}
+
+impl fmt::Debug for ExtractOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtractOptions")
+            .field("preserve_permissions", &self.preserve_permissions)
+            .field("preserve_mtime", &self.preserve_mtime)
+            .field("preserve_ownership", &self.preserve_ownership)
+            .field("numeric_owner", &self.numeric_owner)
+            .field("preserve_xattrs", &self.preserve_xattrs)
+            .field("overwrite", &self.overwrite)
+            .field("unsafe_paths", &self.unsafe_paths)
+            .field("windows_names", &self.windows_names)
+            .field("symlinks", &self.symlinks)
+            .field("special_files", &self.special_files)
+            .field("crc_mismatch", &self.crc_mismatch)
+            .field("lenient", &self.lenient)
+            .field("strict", &self.strict)
+            .field("dry_run", &self.dry_run)
+            .field("atomic", &self.atomic)
+            .field("preserve_hardlinks", &self.preserve_hardlinks)
+            .field("sparse_files", &self.sparse_files)
+            .field("limits", &self.limits)
+            .field("password", &self.password)
+            .field("candidate_passwords", &self.candidate_passwords)
+            .field(
+                "password_provider",
+                &self.password_provider.as_ref().map(|_| ".."),
+            )
+            .field("include", &self.include)
+            .field("filter", &self.filter.as_ref().map(|_| ".."))
+            .field("strip_components", &self.strip_components)
+            .field("map_path", &self.map_path.as_ref().map(|_| ".."))
+            .field("filename_encoding", &self.filename_encoding)
+            .field("progress", &self.progress.as_ref().map(|_| ".."))
+            .field("cancellation", &self.cancellation)
+            .field("buffer_size", &self.buffer_size)
+            .field("threads", &self.threads)
+            .field("max_open_files", &self.max_open_files)
+            .finish()
+    }
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            preserve_permissions: true,
+            preserve_mtime: true,
+            preserve_ownership: false,
+            numeric_owner: false,
+            preserve_xattrs: false,
+            overwrite: OverwritePolicy::Overwrite,
+            unsafe_paths: UnsafePathPolicy::Skip,
+            windows_names: WindowsNamePolicy::default(),
+            symlinks: SymlinkPolicy::Preserve,
+            special_files: SpecialFilePolicy::Skip,
+            crc_mismatch: CrcMismatchPolicy::Error,
+            lenient: false,
+            strict: false,
+            dry_run: false,
+            atomic: false,
+            preserve_hardlinks: true,
+            sparse_files: true,
+            limits: ExtractLimits::default(),
+            password: None,
+            candidate_passwords: Vec::new(),
+            password_provider: None,
+            include: Vec::new(),
+            filter: None,
+            strip_components: 0,
+            map_path: None,
+            filename_encoding: None,
+            progress: None,
+            cancellation: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            threads: 1,
+            max_open_files: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Creates options with the same behavior as a plain `extract_to`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether permission bits recorded in the archive are applied to
+    /// extracted files and directories.
+    ///
+    /// Every format that records unix modes is covered, ZIP and 7z included.
+    /// Entries without one, such as those of archives made on Windows, get
+    /// the default permissions for new files either way. setuid, setgid and
+    /// sticky bits are never restored. Has no effect on non-unix platforms.
+    pub fn preserve_permissions(mut self, preserve: bool) -> Self {
+        self.preserve_permissions = preserve;
+        self
+    }
+
+    /// Whether modification times recorded in the archive are applied to
+    /// extracted files and directories.
+    ///
+    /// ZIP entries take the time from their extended timestamp field where
+    /// there is one, and from the two-second resolution DOS time otherwise.
+    /// When disabled, everything is left with the time of extraction.
+    pub fn preserve_mtime(mut self, preserve: bool) -> Self {
+        self.preserve_mtime = preserve;
+        self
+    }
+
+    /// Whether the user and group recorded in the archive are applied to
+    /// extracted files, directories and symlinks.
+    ///
+    /// Off by default, since giving files away to other users takes root
+    /// privileges; with it on, extracting an archive whose entries belong
+    /// to someone else fails otherwise. Recorded user and group names are
+    /// looked up on this system and take precedence over the recorded IDs,
+    /// which are used for names that don't exist here; see
+    /// [`numeric_owner`](Self::numeric_owner). Has no effect on non-unix
+    /// platforms.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ExtractOptions;
+    ///
+    /// // Unpacking a root filesystem, as root
+    /// let options = ExtractOptions::new()
+    ///     .preserve_ownership(true)
+    ///     .numeric_owner(true);
+    /// ```
+    pub fn preserve_ownership(mut self, preserve: bool) -> Self {
+        self.preserve_ownership = preserve;
+        self
+    }
+
+    /// Whether recorded owners are applied by their numeric IDs alone,
+    /// ignoring recorded user and group names, like `tar --numeric-owner`.
+    ///
+    /// Useful when the names refer to the accounts of another system, as
+    /// with root filesystem images. Only matters with
+    /// [`preserve_ownership`](Self::preserve_ownership).
+    pub fn numeric_owner(mut self, numeric: bool) -> Self {
+        self.numeric_owner = numeric;
+        self
+    }
+
+    /// Whether the extended attributes recorded in the archive are applied
+    /// to extracted files, directories and symlinks, like `tar --xattrs`.
+    ///
+    /// Attributes come from the PAX `SCHILY.xattr` records of TAR archives;
+    /// see [`EntryInfo::xattrs`]. Off by default, since some namespaces
+    /// take privileges to write. Attributes the filesystem can't store are
+    /// skipped, while other failures fail the extraction. Only has an
+    /// effect on Linux, Android and macOS.
+    pub fn preserve_xattrs(mut self, preserve: bool) -> Self {
+        self.preserve_xattrs = preserve;
+        self
+    }
+
+    /// Sets how entries that collide with existing paths are handled.
+    pub fn overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    /// Sets how entries whose paths would escape the output directory are
+    /// handled.
+    pub fn unsafe_paths(mut self, policy: UnsafePathPolicy) -> Self {
+        self.unsafe_paths = policy;
+        self
+    }
+
+    /// Sets how entry names that Windows can't store are handled.
+    pub fn windows_names(mut self, policy: WindowsNamePolicy) -> Self {
+        self.windows_names = policy;
+        self
+    }
+
+    /// Sets how symbolic link entries are extracted.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Sets how device nodes, FIFOs and sockets are extracted.
+    pub fn special_files(mut self, policy: SpecialFilePolicy) -> Self {
+        self.special_files = policy;
+        self
+    }
+
+    /// Sets how ZIP entries that fail their CRC check are handled.
+    pub fn crc_mismatch(mut self, policy: CrcMismatchPolicy) -> Self {
+        self.crc_mismatch = policy;
+        self
+    }
+
+    /// Whether malformed entries are skipped instead of failing the
+    /// extraction.
+    ///
+    /// TAR headers with a bad checksum or unreadable fields are skipped by
+    /// scanning ahead for the next valid header, files whose contents are
+    /// cut off or fail to decode are removed again, and entries whose
+    /// names couldn't be decoded are left out. Where an archive breaks off
+    /// after its first entry, what was read of it is kept. Every entry
+    /// skipped is counted in [`ExtractReport::malformed`]. Lenient
+    /// extractions always run on the calling thread. Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = ExtractOptions::new().lenient(true);
+    ///     let report = Archive::open("old-backup.tar")?.extract_to_with("./backup", &options)?;
+    ///     println!("{} malformed entries skipped", report.malformed);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Whether archives with suspicious metadata are rejected.
+    ///
+    /// Entries are checked as [`Archive::audit`](crate::Archive::audit)
+    /// checks them, for duplicate names, absolute paths, `..` components,
+    /// special files, setuid bits and timestamps in the future, and any
+    /// finding fails the extraction with [`ArchiveError::Rejected`]
+    /// listing them all. Archives that can be read twice are checked in
+    /// full before anything is written; streamed archives are checked
+    /// entry by entry and fail at the first suspicious one. Defaults to
+    /// `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ArchiveError, ExtractOptions};
+    ///
+    /// let options = ExtractOptions::new().strict(true);
+    /// match Archive::open("upload.tar")?.extract_to_with("./upload", &options) {
+    ///     Err(ArchiveError::Rejected { findings }) => {
+    ///         for finding in findings {
+    ///             eprintln!("rejected: {}", finding);
+    ///         }
+    ///     }
+    ///     result => {
+    ///         result?;
+    ///     }
+    /// }
+    /// # Ok::<(), ArchiveError>(())
+    /// ```
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether the extraction only reports what it would write.
+    ///
+    /// Entries go through the same filters, path mapping, safety checks,
+    /// overwrite policy and limits as in a real extraction, and each one
+    /// that would be written is recorded in [`ExtractReport::planned`],
+    /// with conflicts in [`ExtractReport::conflicts`] as usual. Nothing is
+    /// created, removed or renamed, not even the output directory. Entry
+    /// contents are not decoded, so limits are checked against the sizes
+    /// recorded in the archive and CRC mismatches go unnoticed. Dry runs
+    /// always run on the calling thread. Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = ExtractOptions::new().dry_run(true);
+    ///     let report = Archive::open("release.tar.gz")?.extract_to_with("/opt/app", &options)?;
+    ///     for entry in &report.planned {
+    ///         println!("{} -> {}", entry.name, entry.path.display());
+    ///     }
+    ///     for conflict in &report.conflicts {
+    ///         println!("would replace {}", conflict.path.display());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Whether the output directory only appears once extraction succeeds.
+    ///
+    /// Entries are extracted into a hidden directory next to the output
+    /// directory, which is renamed to it once every entry is written and
+    /// removed if extraction fails, so a crash or error never leaves a
+    /// half-extracted output directory behind. The output directory must
+    /// not exist yet or be empty, and paths in the report refer to it, not
+    /// to the hidden directory. Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractOptions};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = ExtractOptions::new().atomic(true);
+    ///     Archive::open("release-1.2.tar.gz")?.extract_to_with("/opt/app/1.2", &options)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Whether hard link entries are recreated as hard links.
+    ///
+    /// When disabled, each link is written as a copy of the file it points
+    /// to. Links are copied anyway on filesystems that don't support them.
+    /// Copies count toward the [`limits`](Self::limits) like other files.
+    pub fn preserve_hardlinks(mut self, preserve: bool) -> Self {
+        self.preserve_hardlinks = preserve;
+        self
+    }
+
+    /// Whether runs of zeros in file contents are left as holes rather
+    /// than written out.
+    ///
+    /// This brings back the holes of sparse TAR entries, in the GNU and PAX
+    /// formats alike, and saves space on any other mostly empty file, such
+    /// as a disk image. The contents read back the same either way, but
+    /// filesystems without holes store the zeros regardless.
+    pub fn sparse_files(mut self, sparse: bool) -> Self {
+        self.sparse_files = sparse;
+        self
+    }
+
+    /// Sets the resource limits enforced during extraction.
+    ///
+    /// Use this when extracting archives from untrusted sources to guard
+    /// against decompression bombs.
+    pub fn limits(mut self, limits: ExtractLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the password used to decrypt encrypted archives.
+    ///
+    /// Applies to 7z archives with encrypted contents or headers and to
+    /// encrypted ZIP entries; other formats ignore it. Extracting an
+    /// encrypted archive without the right password fails with
+    /// [`ArchiveError::InvalidPassword`]. The password is kept as a
+    /// [`SecretString`], so it is wiped from memory with the options.
+    pub fn password(mut self, password: impl Into<SecretString>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Tries each of `candidates` on encrypted entries, in order, recording
+    /// in [`ExtractReport::unlocked`] which one opened each.
+    ///
+    /// Meant for batches of archives protected with one of a few known
+    /// passwords. A password set with [`password`](Self::password) is tried
+    /// first, and a [`password_provider`](Self::password_provider) is only
+    /// asked once every candidate has failed. A 7z archive has a single
+    /// password, checked as described for [`PasswordProvider`]. Setting new
+    /// candidates replaces the previous ones.
+    pub fn candidate_passwords<I>(mut self, candidates: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<SecretString>,
+    {
+        self.candidate_passwords = candidates.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Asks `provider` for passwords as encrypted entries come up, and
+    /// again when one turns out to be wrong.
+    ///
+    /// A password set with [`password`](Self::password) and the
+    /// [`candidate_passwords`](Self::candidate_passwords) are tried before
+    /// the provider is asked. See [`PasswordProvider`] for when it is
+    /// called. Setting a new provider replaces the previous one.
+    pub fn password_provider(mut self, provider: impl PasswordProvider + 'static) -> Self {
+        self.password_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Restricts extraction to entries matching at least one of `patterns`.
+    ///
+    /// Patterns are globs: `?` and `*` match within a single path
+    /// component, `**` matches across components, and `[a-z]` matches a set.
+    /// A pattern without a `/` is matched against the file name alone, so
+    /// `*.so` selects shared objects at any depth, while `docs/**` selects
+    /// everything below `docs`. Calling this again adds more patterns.
+    ///
+    /// Parent directories of selected entries are created as needed even
+    /// if they don't match themselves.
+    pub fn include<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.include
+            .extend(patterns.into_iter().map(|p| Glob::new(p.as_ref())));
+        self
+    }
+
+    /// Only extracts entries for which `filter` returns `true`.
+    ///
+    /// The filter sees each entry's metadata before anything is written, so
+    /// it can implement size limits, name allowlists or type checks. It is
+    /// combined with [`include`](Self::include): an entry must pass both.
+    /// Setting a new filter replaces the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ExtractOptions;
+    ///
+    /// // Regular files up to 10 MiB only
+    /// let options = ExtractOptions::new()
+    ///     .filter(|entry| entry.is_file() && entry.size <= 10 * 1024 * 1024);
+    /// ```
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&EntryInfo) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Removes the first `count` path components from every entry name, like
+    /// `tar --strip-components`.
+    ///
+    /// Entries that have no components left, such as the top-level
+    /// directory itself, are skipped. Hard link targets are stripped the
+    /// same way; symlink targets are left untouched. Patterns passed to
+    /// [`include`](Self::include) and the [`filter`](Self::filter) callback
+    /// still see the original names.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ExtractOptions;
+    ///
+    /// // `repo-1.2.3/src/main.rs` is written to `src/main.rs`
+    /// let options = ExtractOptions::new().strip_components(1);
+    /// ```
+    pub fn strip_components(mut self, count: usize) -> Self {
+        self.strip_components = count;
+        self
+    }
+
+    /// Rewrites the output path of every entry.
+    ///
+    /// The callback receives the path an entry would be written to,
+    /// relative to the output directory and after
+    /// [`strip_components`](Self::strip_components), and returns the path to
+    /// use instead, or `None` to skip the entry. The returned path is
+    /// sanitized like an entry name, according to
+    /// [`unsafe_paths`](Self::unsafe_paths). Hard link targets are mapped too, so
+    /// links keep pointing at their (renamed) targets.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// use compak::ExtractOptions;
+    ///
+    /// // Move everything under `vendor/`, dropping any `.git` metadata
+    /// let options = ExtractOptions::new().map_path(|path: &Path| {
+    ///     if path.starts_with(".git") {
+    ///         None
+    ///     } else {
+    ///         Some(PathBuf::from("vendor").join(path))
+    ///     }
+    /// });
+    /// ```
+    pub fn map_path<F>(mut self, map: F) -> Self
+    where
+        F: Fn(&Path) -> Option<PathBuf> + Send + Sync + 'static,
+    {
+        self.map_path = Some(Arc::new(map));
+        self
+    }
+
+    /// Decodes entry names the archive doesn't store as UTF-8 with
+    /// `encoding`, for archives written in the code page of a non-English
+    /// Windows system.
+    ///
+    /// Only names with an [`EntryInfo::raw_name`] are decoded again, which
+    /// means ZIP entries without the UTF-8 flag or a Unicode Path extra
+    /// field, and only when they aren't plain ASCII. Filters, paths, events
+    /// and reports all see the decoded names. By default such names are
+    /// read as [`Encoding::Cp437`], as the ZIP specification says.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::{Encoding, ExtractOptions};
+    ///
+    /// // An archive made on Japanese Windows
+    /// let options = ExtractOptions::new().filename_encoding(Encoding::ShiftJis);
+    /// ```
+    pub fn filename_encoding(mut self, encoding: Encoding) -> Self {
+        self.filename_encoding = Some(encoding);
+        self
+    }
+
+    /// Registers a callback that receives a [`Progress`] snapshot after
+    /// every buffer of file data written and after every entry completes.
+    ///
+    /// Skipped entries are not counted. Setting a new callback replaces the
+    /// previous one.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress<'_>) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Makes extraction stop with [`ArchiveError::Cancelled`] once `token`
+    /// is cancelled.
+    ///
+    /// The token is checked before each entry and before each buffer of
+    /// file data, so cancellation takes effect quickly even inside large
+    /// files. Entries written so far are kept.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Sets the size of the buffer used to copy file contents.
+    ///
+    /// This is also the granularity of [`ExtractEvent::BytesWritten`]
+    /// events. Values below one byte are rounded up.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    /// Sets how many threads write files at once.
+    ///
+    /// With more than one thread, the files of ZIP archives and the blocks
+    /// of 7z archives are decompressed concurrently. Entries are still
+    /// checked, placed and linked in archive order, so paths, conflicts and
+    /// limits are handled as with a single thread, but the
+    /// [`ExtractEvent::BytesWritten`] and [`ExtractEvent::EntryFinished`]
+    /// events of files written at the same time interleave. Other formats,
+    /// and 7z archives with a single block or with symbolic links, are
+    /// always extracted on the calling thread. `0` uses one thread per CPU.
+    /// Defaults to 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Caps how many files extraction keeps open at once, to stay under
+    /// the limit of the process, such as `ulimit -n`.
+    ///
+    /// Each thread writing files keeps the archive and the file it writes
+    /// open, and the calling thread keeps the archive open while it walks
+    /// the entries, so [`threads`](Self::threads) is lowered as far as
+    /// needed and the files left wait their turn. A budget too small for
+    /// two threads extracts on the calling thread. Unlimited by default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ExtractOptions;
+    ///
+    /// // One thread per CPU, but never more than 64 open files
+    /// let options = ExtractOptions::new().threads(0).max_open_files(64);
+    /// ```
+    pub fn max_open_files(mut self, files: usize) -> Self {
+        self.max_open_files = Some(files);
+        self
+    }
+
+    /// Returns the number of threads to extract on.
+    fn thread_count(&self) -> usize {
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        };
+        match self.max_open_files {
+            // The calling thread holds the archive open on top
+            Some(files) => {
+                threads
+                    .min(files.saturating_sub(1) / FILES_PER_THREAD)
+                    .max(1)
+            }
+            None => threads,
+        }
+    }
+
+    /// Returns the passwords to decrypt entries with.
+    pub(crate) fn passwords(&self) -> Passwords {
+        Passwords::new(
+            self.password.clone(),
+            &self.candidate_passwords,
+            self.password_provider.clone(),
+        )
+    }
+
+    /// Returns the token set with [`cancellation`](Self::cancellation), or
+    /// a new one if there is none.
+    #[cfg(feature = "async")]
+    pub(crate) fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone().unwrap_or_default()
+    }
+
+    /// Returns `info` with its name decoded in the configured
+    /// [`filename_encoding`](Self::filename_encoding).
+    pub(crate) fn decoded<'i>(&self, info: &'i EntryInfo) -> Cow<'i, EntryInfo> {
+        match (self.filename_encoding, &info.raw_name) {
+            (Some(encoding), Some(raw)) => {
+                Cow::Owned(EntryInfo {
+                    name: encoding.decode(raw),
+                    ..info.clone()
+                })
+            }
+            _ => Cow::Borrowed(info),
+        }
+    }
+
+    /// Returns `true` if the special file `info` is to be recreated under
+    /// the [`SpecialFilePolicy`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the policy is
+    /// [`SpecialFilePolicy::Error`].
+    pub(crate) fn recreates(
+        &self,
+        format: ArchiveFormat,
+        info: &EntryInfo,
+    ) -> Result<bool, ArchiveError> {
+        let kind = info.special.map(|special| special.kind);
+        match self.special_files {
+            SpecialFilePolicy::Skip => Ok(false),
+            SpecialFilePolicy::Fifos => Ok(cfg!(unix) && kind == Some(SpecialKind::Fifo)),
+            SpecialFilePolicy::Recreate => {
+                Ok(cfg!(unix) && kind.is_some_and(|kind| kind != SpecialKind::Socket))
+            }
+            SpecialFilePolicy::Error => {
+                Err(ArchiveError::InvalidArchive {
+                    format,
+                    reason: format!("entry '{}' is a special file", info.name).into(),
+                })
+            }
+        }
+    }
+
+    /// Returns `true` if the entry passes the configured selection.
+    pub(crate) fn selects(&self, info: &EntryInfo) -> bool {
+        if let Some(filter) = &self.filter
+            && !filter(info)
+        {
+            return false;
+        }
+        if self.include.is_empty() {
+            return true;
+        }
+        let name = entry::normalize_name(&info.name);
+        self.include.iter().any(|glob| glob.matches(&name))
+    }
+
+    /// Maps an entry name to its path relative to the output directory.
+    ///
+    /// Returns `None` for the archive root itself, for names that are
+    /// entirely removed by `strip_components` or dropped by the path
+    /// mapper, and for unsafe names the [`UnsafePathPolicy`] skips.
+    pub(crate) fn relative_path(
+        &self,
+        format: ArchiveFormat,
+        name: &str,
+    ) -> Result<Option<PathBuf>, ArchiveError> {
+        let policy = self.unsafe_paths;
+        let mut relative = match sanitize(name, self.strip_components, policy) {
+            Sanitized::Path(path) => path,
+            Sanitized::Empty => return Ok(None),
+            Sanitized::Unsafe => return self.unsafe_path(format, name),
+        };
+        if let Some(map_path) = &self.map_path {
+            let Some(mapped) = map_path(&relative) else {
+                return Ok(None);
+            };
+            let mapped = mapped.to_string_lossy();
+            relative = match sanitize(&mapped, 0, policy) {
+                Sanitized::Path(path) => path,
+                Sanitized::Empty => return Ok(None),
+                Sanitized::Unsafe => return self.unsafe_path(format, &mapped),
+            };
+        }
+        if self.windows_names != WindowsNamePolicy::Keep {
+            let mut safe = PathBuf::new();
+            for component in relative.iter() {
+                let component = component.to_string_lossy();
+                let Some(fixed) = windows_name(&component, self.windows_names) else {
+                    safe.push(&*component);
+                    continue;
+                };
+                match self.windows_names {
+                    WindowsNamePolicy::Skip => return Ok(None),
+                    WindowsNamePolicy::Error => {
+                        return Err(ArchiveError::InvalidArchive {
+                            format,
+                            reason: format!("entry '{}' has a name Windows can't store", name)
+                                .into(),
+                        });
+                    }
+                    _ => safe.push(fixed),
+                }
+            }
+            relative = safe;
+        }
+        self.limits.check_path(&relative)?;
+        Ok(Some(relative))
+    }
+
+    fn unsafe_path(
+        &self,
+        format: ArchiveFormat,
+        name: &str,
+    ) -> Result<Option<PathBuf>, ArchiveError> {
+        match self.unsafe_paths {
+            UnsafePathPolicy::Error => {
+                Err(ArchiveError::InvalidArchive {
+                    format,
+                    reason: format!("entry '{}' escapes the output directory", name).into(),
+                })
+            }
+            UnsafePathPolicy::Skip | UnsafePathPolicy::Sanitize => Ok(None),
+        }
+    }
+}
+
+/// A notification emitted while an archive is being extracted.
+///
+/// For every entry written to disk, one `EntryStarted` is followed by zero or
+/// more `BytesWritten` and a final `EntryFinished`. Entries that are skipped
+/// (such as ones whose path would leave the output directory) produce no
+/// events. `Done` and `Failed` are only emitted by APIs that report the
+/// outcome of the whole extraction through the event channel itself.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::ExtractEvent;
+///
+/// fn describe(event: &ExtractEvent) -> String {
+///     match event {
+///         ExtractEvent::EntryStarted(info) => format!("extracting {}", info.name),
+///         ExtractEvent::BytesWritten { name, written, total } => {
+///             format!("{}: {}/{} bytes", name, written, total)
+///         }
+///         ExtractEvent::EntryFinished { name } => format!("finished {}", name),
+///         ExtractEvent::Done => "done".to_string(),
+///         ExtractEvent::Failed(err) => format!("failed: {}", err),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub enum ExtractEvent {
+    /// An entry is about to be written.
+    EntryStarted(Box<EntryInfo>),
+    /// More of the current file has been written.
+    BytesWritten {
+        /// Name of the entry inside the archive.
+        name: String,
+        /// Bytes of this entry written so far.
+        written: u64,
+        /// Uncompressed size of this entry as recorded by the archive.
+        total: u64,
+    },
+    /// An entry has been fully written.
+    EntryFinished {
+        /// Name of the entry inside the archive.
+        name: String,
+    },
+    /// The whole archive was extracted successfully.
+    Done,
+    /// Extraction stopped with an error.
+    Failed(ArchiveError),
+}
+
+/// Extracts every entry of the archive at `location` below `output_dir`.
+///
+/// Unsafe entry paths are handled according to
+/// [`ExtractOptions::unsafe_paths`], and no entry is ever written through a
+/// symlink that points outside `output_dir`. Directory permissions and
+/// timestamps are applied last, deepest first, so read-only directories
+/// don't block writing their contents.
+///
+/// # Arguments
+///
+/// * `location` - Where the archive is read from
+/// * `format` - The archive format to decode
+/// * `output_dir` - Directory where contents should be extracted
+/// * `options` - Extraction options
+/// * `observer` - Callback receiving progress events
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The archive cannot be opened or decoded
+/// * The output directory cannot be created
+/// * An entry would be written outside the output directory
+/// * An entry conflicts with an existing path under [`OverwritePolicy::Error`]
+/// * A configured [`ExtractLimits`] limit is exceeded
+/// * Suspicious metadata is found under [`ExtractOptions::strict`]
+/// * An entry cannot be written
+/// * The operation is cancelled
+pub(crate) fn extract(
+    location: &Location,
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    if options.strict {
+        let findings = audit::audit(location, format)?;
+        if !findings.is_empty() {
+            return Err(ArchiveError::Rejected {
+                findings,
+            });
+        }
+    }
+    atomically(output_dir, options, |output_dir| {
+        extract_into(location, format, output_dir, options, observer)
+    })
+}
+
+/// Does the work of [`extract`] once `output_dir` is settled.
+fn extract_into(
+    location: &Location,
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    // Shared with the serial fallback, so that passwords found while
+    // planning aren't asked for again
+    let passwords = options.passwords();
+    if options.thread_count() > 1
+        && !options.lenient
+        && !options.dry_run
+        && let Some(report) =
+            parallel::extract(location, format, output_dir, options, &passwords, observer)?
+    {
+        return Ok(ExtractReport {
+            unlocked: passwords.take_unlocked(),
+            ..report
+        });
+    }
+    let mut malformed = 0;
+    let mut report = run(
+        format,
+        output_dir,
+        options,
+        location.len(),
+        observer,
+        |visit| {
+            if options.lenient && format.is_tar() {
+                let decoder = entry::decoder(format, location.open(format)?)?;
+                malformed = entry::walk_tar_leniently(decoder, visit)?;
+                return Ok(());
+            }
+            walk_leniently(options, &mut malformed, visit, |visit| {
+                entry::for_each_entry_with_password(location, format, &passwords, visit)
+            })
+        },
+    )?;
+    report.malformed += malformed;
+    report.unlocked = passwords.take_unlocked();
+    Ok(report)
+}
+
+/// Extracts image `index` of the WIM at `location`.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`extract`], or if the WIM has no image `index`.
+pub(crate) fn extract_wim_image(
+    location: &Location,
+    index: u32,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    atomically(output_dir, options, |output_dir| {
+        run(
+            ArchiveFormat::Wim,
+            output_dir,
+            options,
+            location.len(),
+            observer,
+            |visit| wim::walk(location.open(ArchiveFormat::Wim)?, Some(index), visit),
+        )
+    })
+}
+
+/// Extracts the macOS installer package at `location`, unpacking its
+/// payloads.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`extract`], or if a payload is not a readable `cpio` archive.
+pub(crate) fn extract_pkg(
+    location: &Location,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    atomically(output_dir, options, |output_dir| {
+        run(
+            ArchiveFormat::Xar,
+            output_dir,
+            options,
+            location.len(),
+            observer,
+            |visit| xar::walk_pkg(location.open(ArchiveFormat::Xar)?, visit),
+        )
+    })
+}
+
+/// Extracts the Windows Installer package at `location`, unpacking its
+/// embedded cabinets.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`extract`], or if a cabinet cannot be read.
+pub(crate) fn extract_msi(
+    location: &Location,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    atomically(output_dir, options, |output_dir| {
+        run(
+            ArchiveFormat::Msi,
+            output_dir,
+            options,
+            location.len(),
+            observer,
+            |visit| cfb::walk(location.open(ArchiveFormat::Msi)?, true, visit),
+        )
+    })
+}
+
+/// Extracts every entry of an archive read from a non-seekable stream.
+///
+/// Behaves like [`extract`]; see [`entry::for_each_streamed_entry`] for
+/// how each format is read from a stream.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`extract`].
+pub(crate) fn extract_stream<R: Read>(
+    reader: R,
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    atomically(output_dir, options, |output_dir| {
+        let mut malformed = 0;
+        let mut report = run(format, output_dir, options, None, observer, |visit| {
+            if options.lenient && format.is_tar() {
+                malformed = entry::walk_tar_leniently(entry::decoder(format, reader)?, visit)?;
+                return Ok(());
+            }
+            walk_leniently(options, &mut malformed, visit, |visit| {
+                entry::for_each_streamed_entry(reader, format, &options.passwords(), visit)
+            })
+        })?;
+        report.malformed += malformed;
+        Ok(report)
+    })
+}
+
+/// Walks an archive with `walk`, and under [`ExtractOptions::lenient`]
+/// ends the walk without an error if the archive turns out to be damaged
+/// after its first entry, counting the entry it broke off at in
+/// `malformed`.
+fn walk_leniently<F>(
+    options: &ExtractOptions,
+    malformed: &mut u64,
+    visit: &mut dyn FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    walk: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnOnce(
+        &mut dyn FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    ) -> Result<(), ArchiveError>,
+{
+    if !options.lenient {
+        return walk(visit);
+    }
+    let mut visited = false;
+    let mut visit_failed = false;
+    let walked = walk(&mut |info, reader| {
+        visited = true;
+        let result = visit(info, reader);
+        visit_failed = result.is_err();
+        result
+    });
+    match walked {
+        Err(err) if visited && !visit_failed && err.is_corruption() => {
+            *malformed += 1;
+            Ok(())
+        }
+        walked => walked,
+    }
+}
+
+/// Extracts every entry of the archive at `location` that can be, on the
+/// calling thread, recording the entries that fail instead of stopping.
+///
+/// Cancellation and exceeded limits still stop the extraction, as does
+/// failing to create the output directory.
+pub(crate) fn extract_best_effort(
+    location: &Location,
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractionOutcome, ArchiveError> {
+    atomically(output_dir, options, |output_dir| {
+        if !options.dry_run {
+            fs::create_dir_all(output_dir).at_path(output_dir)?;
+        }
+
+        let mut extractor = Extractor::new(output_dir, format, options, location.len(), observer)?;
+        extractor.extracted = Some(Vec::new());
+        let mut failed = Vec::new();
+        let passwords = options.passwords();
+        let walked =
+            entry::for_each_entry_with_password(location, format, &passwords, |info, reader| {
+                match extractor.unpack(info, reader) {
+                    Err(err) if !stops_extraction(&err) => {
+                        failed.push((options.decoded(info).into_owned(), err))
+                    }
+                    result => result?,
+                }
+                Ok(true)
+            });
+        let error = match walked {
+            Err(err) if stops_extraction(&err) => return Err(err),
+            walked => walked.err(),
+        };
+        let extracted = extractor.extracted.take().unwrap_or_default();
+        Ok(ExtractionOutcome {
+            extracted,
+            failed,
+            error,
+            report: ExtractReport {
+                unlocked: passwords.take_unlocked(),
+                ..extractor.finish()?
+            },
+        })
+    })
+}
+
+/// Returns `true` if `error` ends a best-effort extraction rather than
+/// just the entry it was raised for.
+fn stops_extraction(error: &ArchiveError) -> bool {
+    matches!(
+        error.category(),
+        ErrorCategory::Cancelled | ErrorCategory::LimitExceeded
+    )
+}
+
+/// Runs `extract` on a new directory next to `output_dir` under
+/// [`ExtractOptions::atomic`], renaming it to `output_dir` once `extract`
+/// succeeds and removing it if it fails. Otherwise `extract` runs on
+/// `output_dir` itself.
+///
+/// # Errors
+///
+/// This function will return an error if `output_dir` exists and isn't
+/// empty, if the new directory cannot be created or renamed, or if
+/// `extract` fails.
+pub(crate) fn atomically<T, F>(
+    output_dir: &Path,
+    options: &ExtractOptions,
+    extract: F,
+) -> Result<T, ArchiveError>
+where
+    T: Relocate,
+    F: FnOnce(&Path) -> Result<T, ArchiveError>,
+{
+    if !options.atomic || options.dry_run {
+        return extract(output_dir);
+    }
+    match fs::read_dir(output_dir) {
+        Ok(mut children) => {
+            if children.next().is_some() {
+                return Err(ArchiveError::AlreadyExists {
+                    path: output_dir.display().to_string().into(),
+                });
+            }
+        }
+        Err(e) if is_missing(&e) => {}
+        Err(e) => return Err(ArchiveError::io_at_path(output_dir, e)),
+    }
+    if let Some(parent) = output_dir
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).at_path(parent)?;
+    }
+
+    let temp = edit::temp_path(output_dir);
+    remove_all(&temp)?;
+    fs::create_dir(&temp).at_path(&temp)?;
+    let result = temp.canonicalize().at_path(&temp).and_then(|from| {
+        let mut value = extract(&temp)?;
+        // Windows can't rename a directory over an empty one
+        #[cfg(not(unix))]
+        let _ = fs::remove_dir(output_dir);
+        fs::rename(&temp, output_dir).at_path(output_dir)?;
+        value.relocate(&from, &output_dir.canonicalize().at_path(output_dir)?);
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp);
+    }
+    result
+}
+
+/// Results holding paths below the output directory, which
+/// [`atomically`] moves.
+pub(crate) trait Relocate {
+    /// Rewrites the paths below `from` to lie below `to`.
+    fn relocate(&mut self, from: &Path, to: &Path);
+}
+
+/// Returns `path` moved from below `from` to below `to`.
+fn relocated(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(relative) => to.join(relative),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+impl Relocate for ExtractReport {
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        for conflict in &mut self.conflicts {
+            conflict.path = relocated(&conflict.path, from, to);
+            if let ConflictResolution::Renamed(path) | ConflictResolution::BackedUp(path) =
+                &mut conflict.resolution
+            {
+                *path = relocated(path, from, to);
+            }
+        }
+        for mismatch in &mut self.crc_mismatches {
+            mismatch.path = relocated(&mismatch.path, from, to);
+        }
+    }
+}
+
+impl Relocate for ExtractionOutcome {
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        for path in &mut self.extracted {
+            *path = relocated(path, from, to);
+        }
+        self.report.relocate(from, to);
+    }
+}
+
+/// Unpacks the entries produced by `walk` below `output_dir`.
+///
+/// `archive_size` is the compressed size of the whole archive, if known.
+pub(crate) fn run<F>(
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    archive_size: Option<u64>,
+    observer: &mut dyn FnMut(ExtractEvent),
+    walk: F,
+) -> Result<ExtractReport, ArchiveError>
+where
+    F: FnOnce(
+        &mut dyn FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    ) -> Result<(), ArchiveError>,
+{
+    if !options.dry_run {
+        fs::create_dir_all(output_dir).at_path(output_dir)?;
+    }
+
+    let mut extractor = Extractor::new(output_dir, format, options, archive_size, observer)?;
+    walk(&mut |info, reader| {
+        extractor.unpack(info, reader)?;
+        Ok(true)
+    })?;
+    extractor.finish()
+}
+
+/// Unpacks container image layers into a single root filesystem.
+///
+/// Layers are applied bottom to top. Paths an earlier layer wrote are
+/// replaced without being reported as conflicts, and each layer may delete
+/// paths of the layers below it with whiteout files: `.wh.<name>` removes
+/// `<name>`, and `.wh..wh..opq` empties its directory of everything the
+/// lower layers put there.
+pub(crate) struct LayerExtractor<'a> {
+    extractor: Extractor<'a>,
+}
+
+impl<'a> LayerExtractor<'a> {
+    pub(crate) fn new(
+        output_dir: &Path,
+        options: &'a ExtractOptions,
+        observer: &'a mut dyn FnMut(ExtractEvent),
+    ) -> Result<Self, ArchiveError> {
+        if !options.dry_run {
+            fs::create_dir_all(output_dir).at_path(output_dir)?;
+        }
+        let mut extractor =
+            Extractor::new(output_dir, ArchiveFormat::Tar, options, None, observer)?;
+        extractor.layers = Some(Layers::default());
+        Ok(Self {
+            extractor,
+        })
+    }
+
+    /// Unpacks the next layer, a tarball in `format` read from `layer`.
+    pub(crate) fn apply<R: Read>(
+        &mut self,
+        layer: R,
+        format: ArchiveFormat,
+    ) -> Result<(), ArchiveError> {
+        if let Some(layers) = &mut self.extractor.layers {
+            layers.current.clear();
+        }
+        if let Some(auditor) = &mut self.extractor.auditor {
+            auditor.forget_names();
+        }
+        entry::for_each_streamed_entry(layer, format, &Passwords::default(), |info, reader| {
+            self.extractor.unpack(info, reader)?;
+            Ok(true)
+        })
+    }
+
+    /// Applies deferred directory metadata.
+    pub(crate) fn finish(self) -> Result<ExtractReport, ArchiveError> {
+        self.extractor.finish()
+    }
+}
+
+/// Prefix of the whiteout files that delete paths of lower layers.
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Name of the whiteout file that hides a whole directory of lower layers.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Paths written while unpacking container image layers.
+#[derive(Default)]
+struct Layers {
+    /// Every path written by the layers so far.
+    written: HashSet<PathBuf>,
+    /// Paths written by the current layer, which its whiteouts leave alone.
+    current: HashSet<PathBuf>,
+}
+
+/// Writes walked entries to disk below a fixed root.
+struct Extractor<'a> {
+    root: PathBuf,
+    format: ArchiveFormat,
+    options: &'a ExtractOptions,
+    observer: &'a mut dyn FnMut(ExtractEvent),
+    /// Directories already known to lie inside `root`.
+    verified: HashSet<PathBuf>,
+    /// Directories whose metadata is applied once all entries are written.
+    directories: Vec<(PathBuf, EntryInfo)>,
+    /// Symlinks replaced by copies of their targets once all entries are
+    /// written.
+    materialized: Vec<(PathBuf, EntryInfo)>,
+    progress: ProgressTracker,
+    report: ExtractReport,
+    /// Compressed size of the whole archive, if known.
+    archive_size: Option<u64>,
+    /// Entries read from the archive so far.
+    entries_seen: u64,
+    /// File bytes written so far.
+    total_written: u64,
+    /// Set while unpacking container image layers.
+    layers: Option<Layers>,
+    /// Set when owners are restored.
+    owners: Option<Owners>,
+    /// Set when the paths written are collected.
+    extracted: Option<Vec<PathBuf>>,
+    /// Set when suspicious metadata is rejected.
+    auditor: Option<Auditor>,
+    /// Paths a dry run would have written so far.
+    planned: HashSet<PathBuf>,
+    /// Large files written, evicted from the page cache.
+    drop_behind: DropBehind,
+    started: Instant,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(
+        output_dir: &Path,
+        format: ArchiveFormat,
+        options: &'a ExtractOptions,
+        archive_size: Option<u64>,
+        observer: &'a mut dyn FnMut(ExtractEvent),
+    ) -> Result<Self, ArchiveError> {
+        // A dry run's output directory may not exist yet
+        let root = if options.dry_run {
+            real_path(&std::path::absolute(output_dir).at_path(output_dir)?)
+        } else {
+            output_dir.canonicalize().at_path(output_dir)?
+        };
+        Ok(Self {
+            root,
+            format,
+            options,
+            observer,
+            verified: HashSet::new(),
+            directories: Vec::new(),
+            materialized: Vec::new(),
+            progress: ProgressTracker::new(options.progress.clone(), options.cancellation.clone()),
+            report: ExtractReport::default(),
+            archive_size,
+            entries_seen: 0,
+            total_written: 0,
+            layers: None,
+            owners: options
+                .preserve_ownership
+                .then(|| Owners::new(options.numeric_owner)),
+            extracted: None,
+            auditor: options.strict.then(Auditor::new),
+            planned: HashSet::new(),
+            drop_behind: DropBehind::default(),
+            started: Instant::now(),
+        })
+    }
+
+    fn unpack(&mut self, info: &EntryInfo, reader: &mut dyn Read) -> Result<(), ArchiveError> {
+        let options = self.options;
+        let info = &*options.decoded(info);
+        if let Some(dest) = self.place(info)? {
+            match self.write_file(info, reader, &dest) {
+                // Only reading the contents fails with corruption
+                Err(err) if self.options.lenient && err.is_corruption() => {
+                    remove_existing(&dest)?;
+                    self.report.malformed += 1;
+                    return Ok(());
+                }
+                result => result?,
+            }
+            self.entry_finished(&info.name);
+            if let Some(extracted) = &mut self.extracted {
+                extracted.push(dest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks everything about an entry but the contents of a file,
+    /// returning where those go for a file about to be written.
+    fn place(&mut self, info: &EntryInfo) -> Result<Option<PathBuf>, ArchiveError> {
+        self.progress.check()?;
+        self.entries_seen += 1;
+        if let Some(max) = self.options.limits.max_entries
+            && self.entries_seen > max
+        {
+            return Err(ArchiveError::limit_exceeded("entry count", max));
+        }
+        if let Some(auditor) = &mut self.auditor {
+            let mut findings = Vec::new();
+            auditor.check(info, &mut findings);
+            if !findings.is_empty() {
+                return Err(ArchiveError::Rejected {
+                    findings,
+                });
+            }
+        }
+        if self.layers.is_some() && self.whiteout(info)? {
+            return Ok(None);
+        }
+        if !self.options.selects(info)
+            || (info.kind == EntryKind::Other && !self.options.recreates(self.format, info)?)
+        {
+            return Ok(self.skip());
+        }
+        if self.options.lenient && is_undecodable(&info.name) {
+            self.report.malformed += 1;
+            return Ok(None);
+        }
+        if info.kind == EntryKind::Symlink {
+            match self.options.symlinks {
+                SymlinkPolicy::Preserve | SymlinkPolicy::Materialize => {}
+                SymlinkPolicy::Skip => return Ok(self.skip()),
+                SymlinkPolicy::Error => {
+                    return Err(ArchiveError::InvalidArchive {
+                        format: self.format,
+                        reason: format!("entry '{}' is a symbolic link", info.name).into(),
+                    });
+                }
+            }
+        }
+        let Some(mut dest) = self.destination(&info.name)? else {
+            return Ok(self.skip());
+        };
+        let from_lower_layer = self
+            .layers
+            .as_ref()
+            .is_some_and(|layers| layers.written.contains(&dest));
+        if from_lower_layer && !self.options.dry_run {
+            self.replace_lower(info, &dest)?;
+        }
+        let mut link_target = String::new();
+        if info.kind != EntryKind::Directory {
+            self.create_parent(&dest)?;
+            if info.kind == EntryKind::Symlink {
+                match self.contained_target(info, &dest)? {
+                    Some(target) => link_target = target,
+                    None => return Ok(self.skip()),
+                }
+            }
+            if !from_lower_layer {
+                match self.resolve_conflict(info, dest)? {
+                    Some(resolved) => dest = resolved,
+                    None => return Ok(self.skip()),
+                }
+            }
+        }
+        if let Some(layers) = &mut self.layers {
+            layers.written.insert(dest.clone());
+            layers.current.insert(dest.clone());
+        }
+        if self.options.dry_run {
+            self.plan(info, dest)?;
+            return Ok(None);
+        }
+
+        if info.kind == EntryKind::File {
+            // Fail early on honest headers; writing catches lying ones
+            self.check_limits(info, info.size, info.size)?;
+            remove_existing(&dest)?;
+            (self.observer)(ExtractEvent::EntryStarted(Box::new(info.clone())));
+            return Ok(Some(dest));
+        }
+        if info.kind == EntryKind::Other && !self.write_special(info, &dest)? {
+            return Ok(self.skip());
+        }
+
+        (self.observer)(ExtractEvent::EntryStarted(Box::new(info.clone())));
+        match info.kind {
+            EntryKind::Directory => {
+                self.create_dirs(&dest)?;
+                self.directories.push((dest.clone(), info.clone()));
+            }
+            EntryKind::Symlink if self.options.symlinks == SymlinkPolicy::Materialize => {
+                let mut link = info.clone();
+                link.link_target = Some(link_target);
+                self.materialized.push((dest.clone(), link));
+            }
+            EntryKind::Symlink => {
+                self.write_symlink(&link_target, &dest)?;
+                self.set_owner(&dest, info)?;
+                set_xattrs(&dest, info, self.options)?;
+            }
+            EntryKind::Hardlink => self.write_hardlink(info, &dest)?,
+            EntryKind::Other => {
+                self.set_owner(&dest, info)?;
+                set_xattrs(&dest, info, self.options)?;
+                if self.options.preserve_permissions {
+                    set_mode(&dest, info.mode)?;
+                }
+            }
+            EntryKind::File => unreachable!(),
+        }
+        self.entry_finished(&info.name);
+        if let Some(extracted) = &mut self.extracted {
+            extracted.push(dest);
+        }
+        Ok(None)
+    }
+
+    /// Records that `info` would be written to `dest` in a dry run.
+    fn plan(&mut self, info: &EntryInfo, dest: PathBuf) -> Result<(), ArchiveError> {
+        if info.kind == EntryKind::File {
+            self.check_limits(info, info.size, info.size)?;
+            self.total_written += info.size;
+        }
+        (self.observer)(ExtractEvent::EntryStarted(Box::new(info.clone())));
+        self.planned.insert(dest.clone());
+        self.report.planned.push(PlannedEntry {
+            name: info.name.clone(),
+            path: dest.clone(),
+            kind: info.kind,
+            size: info.size,
+        });
+        self.entry_finished(&info.name);
+        if let Some(extracted) = &mut self.extracted {
+            extracted.push(dest);
+        }
+        Ok(())
+    }
+
+    /// Counts an entry left out, for returning from [`Extractor::place`].
+    fn skip(&mut self) -> Option<PathBuf> {
+        self.report.skipped += 1;
+        None
+    }
+
+    fn entry_finished(&mut self, name: &str) {
+        (self.observer)(ExtractEvent::EntryFinished {
+            name: name.to_string(),
+        });
+        self.progress.finish_entry(name);
+    }
+
+    /// Writes deferred symlink copies and applies deferred directory
+    /// metadata.
+    fn finish(mut self) -> Result<ExtractReport, ArchiveError> {
+        // Links may point to other links, so keep going as long as that
+        // lets more of them be copied; the rest dangle
+        let mut pending = std::mem::take(&mut self.materialized);
+        loop {
+            let count = pending.len();
+            let mut waiting = Vec::new();
+            for (dest, info) in pending {
+                if !self.materialize(&dest, &info)? {
+                    waiting.push((dest, info));
+                }
+            }
+            if waiting.is_empty() || waiting.len() == count {
+                break;
+            }
+            pending = waiting;
+        }
+
+        self.directories.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (dir, info) in &self.directories {
+            if let (Some(owners), Some(owner)) = (&mut self.owners, &info.owner) {
+                owners.apply(dir, owner).at_path(dir)?;
+            }
+            set_xattrs(dir, info, self.options)?;
+            if self.options.preserve_permissions {
+                set_mode(dir, info.mode)?;
+            }
+            if self.options.preserve_mtime
+                && let Some(mtime) = info.mtime
+                && let Ok(handle) = File::open(dir)
+            {
+                // Not every platform can open directories for this
+                let _ = handle.set_modified(mtime);
+            }
+        }
+        // Dry runs count what they would write towards the limits only
+        if !self.options.dry_run {
+            self.report.bytes_written = self.total_written;
+        }
+        self.report.duration = self.started.elapsed();
+        Ok(self.report)
+    }
+
+    /// Applies the overwrite policy to an entry about to be written to
+    /// `dest`, returning the path to write to or `None` to skip it.
+    fn resolve_conflict(
+        &mut self,
+        info: &EntryInfo,
+        dest: PathBuf,
+    ) -> Result<Option<PathBuf>, ArchiveError> {
+        if fs::symlink_metadata(&dest).is_err() && !self.planned.contains(&dest) {
+            return Ok(Some(dest));
+        }
+
+        let (target, resolution) = match self.options.overwrite {
+            OverwritePolicy::Overwrite => (Some(dest.clone()), ConflictResolution::Overwritten),
+            OverwritePolicy::Skip => (None, ConflictResolution::Skipped),
+            OverwritePolicy::Error => {
+                return Err(ArchiveError::AlreadyExists {
+                    path: dest.display().to_string().into(),
+                });
+            }
+            OverwritePolicy::RenameNew => {
+                let renamed = free_sibling(&dest);
+                (Some(renamed.clone()), ConflictResolution::Renamed(renamed))
+            }
+            OverwritePolicy::BackupExisting => {
+                let mut backup = dest.clone().into_os_string();
+                backup.push("~");
+                let backup = PathBuf::from(backup);
+                if !self.options.dry_run {
+                    remove_existing(&backup)?;
+                    fs::rename(&dest, &backup).at_path(&dest)?;
+                    self.verified.retain(|dir| !dir.starts_with(&dest));
+                }
+                (Some(dest.clone()), ConflictResolution::BackedUp(backup))
+            }
+        };
+
+        self.report.conflicts.push(Conflict {
+            name: info.name.clone(),
+            path: dest,
+            resolution,
+        });
+        Ok(target)
+    }
+
+    /// Applies `info` if it is a whiteout file of a container image layer,
+    /// returning whether it was one.
+    fn whiteout(&mut self, info: &EntryInfo) -> Result<bool, ArchiveError> {
+        let name = entry::normalize_name(&info.name);
+        let file_name = name.rsplit('/').next().unwrap_or_default();
+        let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) else {
+            return Ok(false);
+        };
+        // The marker's own destination carries strip_components and
+        // map_path over to the path it hides
+        let Some(marker) = self.destination(&name)? else {
+            return Ok(true);
+        };
+        if self.options.dry_run {
+            return Ok(true);
+        }
+        if file_name == OPAQUE_WHITEOUT {
+            if let Some(dir) = marker.parent() {
+                self.clear_lower(dir)?;
+            }
+        } else if !hidden.starts_with(WHITEOUT_PREFIX) {
+            // Other `.wh..wh.` names are aufs bookkeeping, not whiteouts
+            self.remove_lower(&marker.with_file_name(hidden))?;
+        }
+        Ok(true)
+    }
+
+    /// Removes what the lower layers put at `path`, unless the current
+    /// layer wrote it.
+    fn remove_lower(&mut self, path: &Path) -> Result<(), ArchiveError> {
+        if self
+            .layers
+            .as_ref()
+            .is_some_and(|layers| layers.current.contains(path))
+        {
+            return Ok(());
+        }
+        let Some(resolved) = self.resolve_parent(path)? else {
+            return Ok(());
+        };
+        remove_all(&resolved)?;
+        self.forget(path);
+        Ok(())
+    }
+
+    /// Removes everything below `dir` that the current layer did not write.
+    fn clear_lower(&mut self, dir: &Path) -> Result<(), ArchiveError> {
+        let resolved = match dir.canonicalize() {
+            Ok(resolved) if resolved.starts_with(&self.root) => resolved,
+            Ok(_) => return Err(self.escape_error(dir)),
+            Err(e) if is_missing(&e) => return Ok(()),
+            Err(e) => return Err(ArchiveError::io_at_path(dir, e)),
+        };
+        let children = match fs::read_dir(&resolved) {
+            Ok(children) => children,
+            Err(e) if is_missing(&e) => return Ok(()),
+            Err(e) => return Err(ArchiveError::io_at_path(&resolved, e)),
+        };
+        for child in children {
+            let child = child.at_path(&resolved)?;
+            let path = dir.join(child.file_name());
+            let from_current = self
+                .layers
+                .as_ref()
+                .is_some_and(|layers| layers.current.contains(&path));
+            if !from_current {
+                remove_all(&child.path())?;
+                self.forget(&path);
+            } else if child.file_type().at_path(child.path())?.is_dir() {
+                self.clear_lower(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a lower layer's entry at `dest` that the entry `info` of
+    /// another type replaces.
+    fn replace_lower(&mut self, info: &EntryInfo, dest: &Path) -> Result<(), ArchiveError> {
+        let Some(resolved) = self.resolve_parent(dest)? else {
+            return Ok(());
+        };
+        if let Ok(meta) = fs::symlink_metadata(&resolved)
+            && meta.is_dir() != (info.kind == EntryKind::Directory)
+        {
+            remove_all(&resolved)?;
+            self.forget(dest);
+        }
+        Ok(())
+    }
+
+    /// Drops what is known about `path` and everything below it once it
+    /// has been removed.
+    fn forget(&mut self, path: &Path) {
+        self.verified.retain(|dir| !dir.starts_with(path));
+        self.directories.retain(|(dir, _)| !dir.starts_with(path));
+    }
+
+    /// Returns `path` with its parent resolved, or `None` if the parent
+    /// does not exist.
+    ///
+    /// Paths are removed through this, so a symlink a layer planted in
+    /// their parent cannot redirect the removal outside the root.
+    fn resolve_parent(&self, path: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(None);
+        };
+        match parent.canonicalize() {
+            Ok(parent) if parent.starts_with(&self.root) => Ok(Some(parent.join(name))),
+            Ok(_) => Err(self.escape_error(path)),
+            Err(e) if is_missing(&e) => Ok(None),
+            Err(e) => Err(ArchiveError::io_at_path(parent, e)),
+        }
+    }
+
+    /// Maps an entry name to its path below the root, as
+    /// [`ExtractOptions::relative_path`] does.
+    fn destination(&self, name: &str) -> Result<Option<PathBuf>, ArchiveError> {
+        let relative = self.options.relative_path(self.format, name)?;
+        Ok(relative.map(|relative| self.root.join(relative)))
+    }
+
+    /// Creates `dir` and any missing parents, refusing to follow symlinks
+    /// that lead outside the root.
+    fn create_dirs(&mut self, dir: &Path) -> Result<(), ArchiveError> {
+        if self.verified.contains(dir) {
+            return Ok(());
+        }
+        let relative = dir.strip_prefix(&self.root).unwrap_or(dir).to_path_buf();
+        let mut current = self.root.clone();
+        for component in relative.components() {
+            current.push(component);
+            match fs::symlink_metadata(&current) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    let resolved = current.canonicalize().at_path(&current)?;
+                    if !resolved.starts_with(&self.root) || !resolved.is_dir() {
+                        return Err(self.escape_error(&current));
+                    }
+                }
+                Ok(_) => {}
+                // Nothing below a missing directory exists to check
+                Err(e) if e.kind() == io::ErrorKind::NotFound && self.options.dry_run => break,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    fs::create_dir(&current).at_path(&current)?;
+                    self.report.directories += 1;
+                }
+                Err(e) => return Err(ArchiveError::io_at_path(&current, e)),
+            }
+        }
+        self.verified.insert(dir.to_path_buf());
+        Ok(())
+    }
+
+    fn create_parent(&mut self, dest: &Path) -> Result<(), ArchiveError> {
+        match dest.parent() {
+            Some(parent) => self.create_dirs(parent),
+            None => Ok(()),
+        }
+    }
+
+    fn write_file(
+        &mut self,
+        info: &EntryInfo,
+        reader: &mut dyn Read,
+        dest: &Path,
+    ) -> Result<(), ArchiveError> {
+        let mut file = File::create(dest).at_entry(&info.name, dest)?;
+        let intact = copy_contents(&mut file, dest, info, reader, self.options, |written, n| {
+            self.progress.check()?;
+            self.check_limits(info, written, n)?;
+            self.report.bytes_read += n;
+            self.total_written += n;
+            (self.observer)(ExtractEvent::BytesWritten {
+                name: info.name.clone(),
+                written,
+                total: info.size,
+            });
+            self.progress.add_bytes(&info.name, n);
+            Ok(())
+        })?;
+        if !intact {
+            self.report.crc_mismatches.push(CrcMismatch {
+                name: info.name.clone(),
+                path: dest.to_path_buf(),
+            });
+            if self.options.crc_mismatch == CrcMismatchPolicy::Skip {
+                drop(file);
+                self.report.skipped += 1;
+                return fs::remove_file(dest).at_path(dest);
+            }
+        }
+        finish_file(&file, dest, info, self.options, &mut self.owners)?;
+        self.drop_behind.written(&file, info.size);
+        self.report.files += 1;
+        Ok(())
+    }
+
+    /// Applies the owner of `info` to `path` if owners are restored.
+    fn set_owner(&mut self, path: &Path, info: &EntryInfo) -> Result<(), ArchiveError> {
+        if let (Some(owners), Some(owner)) = (&mut self.owners, &info.owner) {
+            owners.apply(path, owner).at_path(path)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if `info` growing to `entry_bytes` would exceed a configured
+    /// limit, given `incoming` of those bytes are not yet counted in
+    /// `total_written`.
+    fn check_limits(
+        &self,
+        info: &EntryInfo,
+        entry_bytes: u64,
+        incoming: u64,
+    ) -> Result<(), ArchiveError> {
+        check_limits(
+            &self.options.limits,
+            self.archive_size,
+            info,
+            entry_bytes,
+            self.total_written.saturating_add(incoming),
+        )
+    }
+
+    /// Creates the FIFO or device node `info` at `dest`, returning `false`
+    /// if it is a device node that isn't permitted.
+    fn write_special(&mut self, info: &EntryInfo, dest: &Path) -> Result<bool, ArchiveError> {
+        let Some(special) = info.special else {
+            return Ok(false);
+        };
+        remove_existing(dest)?;
+        match make_special(dest, special) {
+            Err(e) if special.kind.is_device() && e.kind() == io::ErrorKind::PermissionDenied => {
+                return Ok(false);
+            }
+            result => result.at_path(dest)?,
+        }
+        self.report.special_files += 1;
+        Ok(true)
+    }
+
+    fn write_symlink(&mut self, target: &str, dest: &Path) -> Result<(), ArchiveError> {
+        remove_existing(dest)?;
+        // A new link may redirect paths that were checked through the old one
+        self.verified.retain(|dir| !dir.starts_with(dest));
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, dest).at_path(dest)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(target.replace('/', "\\"), dest).at_path(dest)?;
+        #[cfg(not(any(unix, windows)))]
+        let _ = target;
+
+        self.report.symlinks += 1;
+        Ok(())
+    }
+
+    /// Returns the target to create the symlink `info` at `dest` with, or
+    /// `None` to skip it, applying the [`UnsafePathPolicy`] if the recorded
+    /// target leads outside the root.
+    fn contained_target(
+        &self,
+        info: &EntryInfo,
+        dest: &Path,
+    ) -> Result<Option<String>, ArchiveError> {
+        let target = info.link_target.as_deref().unwrap_or_default();
+        let dir = dest.parent().unwrap_or(&self.root);
+        if self.resolves_inside(dir, target) {
+            return Ok(Some(target.to_string()));
+        }
+        match self.options.unsafe_paths {
+            UnsafePathPolicy::Skip => Ok(None),
+            UnsafePathPolicy::Sanitize => {
+                let rerooted = self.reroot(dir, target);
+                Ok(self.resolves_inside(dir, &rerooted).then_some(rerooted))
+            }
+            UnsafePathPolicy::Error => {
+                Err(ArchiveError::InvalidArchive {
+                    format: self.format,
+                    reason: format!(
+                        "symlink '{}' points outside the output directory",
+                        info.name
+                    )
+                    .into(),
+                })
+            }
+        }
+    }
+
+    /// Returns `true` if the symlink target `target` of a link in `dir`
+    /// stays inside the root.
+    ///
+    /// `..` is resolved the way the kernel does, from the real location of
+    /// the path before it, so links already on disk cannot be used to climb
+    /// out.
+    fn resolves_inside(&self, dir: &Path, target: &str) -> bool {
+        let target = target.replace('\\', "/");
+        if target.starts_with('/') || has_drive(&target) {
+            return false;
+        }
+        let mut current = dir.to_path_buf();
+        for segment in target.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    current = real_path(&current);
+                    if !current.pop() || !current.starts_with(&self.root) {
+                        return false;
+                    }
+                }
+                _ => current.push(segment),
+            }
+        }
+        real_path(&current).starts_with(&self.root)
+    }
+
+    /// Rewrites the target of a symlink in `dir` to point to the same path
+    /// below the root, treating absolute targets as relative to the root
+    /// and dropping `..` components that would climb above it.
+    fn reroot(&self, dir: &Path, target: &str) -> String {
+        let target = target.replace('\\', "/");
+        let (absolute, target) = if has_drive(&target) {
+            (true, &target[2..])
+        } else {
+            (target.starts_with('/'), &target[..])
+        };
+        let base: Vec<String> = dir
+            .strip_prefix(&self.root)
+            .unwrap_or(Path::new(""))
+            .iter()
+            .map(|component| component.to_string_lossy().into_owned())
+            .collect();
+        let mut resolved = if absolute { Vec::new() } else { base.clone() };
+        for segment in target.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    resolved.pop();
+                }
+                _ => resolved.push(segment.to_string()),
+            }
+        }
+        let common = base
+            .iter()
+            .zip(&resolved)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut parts = vec![".."; base.len() - common];
+        parts.extend(resolved[common..].iter().map(String::as_str));
+        if parts.is_empty() {
+            ".".to_string()
+        } else {
+            parts.join("/")
+        }
+    }
+
+    /// Replaces the symlink `info` meant for `dest` with a copy of its
+    /// target, returning `false` if the target does not exist (yet).
+    fn materialize(&mut self, dest: &Path, info: &EntryInfo) -> Result<bool, ArchiveError> {
+        let target = info.link_target.as_deref().unwrap_or_default();
+        let Some(dest) = self.resolve_parent(dest)? else {
+            return Ok(true);
+        };
+        let dir = dest.parent().unwrap_or(&self.root);
+        let source = match dir.join(target.replace('\\', "/")).canonicalize() {
+            Ok(source) => source,
+            Err(e) if is_missing(&e) => return Ok(false),
+            Err(e) => return Err(ArchiveError::io_at_path(dir.join(target), e)),
+        };
+        if !source.starts_with(&self.root) {
+            return Err(self.escape_error(&dest));
+        }
+        // A directory cannot be copied into itself
+        if dest.starts_with(&source) {
+            return Ok(true);
+        }
+        let copy = EntryInfo {
+            compressed_size: None,
+            ..info.clone()
+        };
+        remove_existing(&dest)?;
+        self.copy_tree(&copy, &source, &dest)?;
+        self.verified.retain(|dir| !dir.starts_with(&dest));
+        Ok(true)
+    }
+
+    /// Copies the file or directory `source` to `dest`, leaving out
+    /// symlinks and special files.
+    fn copy_tree(
+        &mut self,
+        info: &EntryInfo,
+        source: &Path,
+        dest: &Path,
+    ) -> Result<(), ArchiveError> {
+        self.progress.check()?;
+        let meta = fs::symlink_metadata(source).at_path(source)?;
+        if meta.is_dir() {
+            match fs::create_dir(dest) {
+                Ok(()) => self.report.directories += 1,
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(ArchiveError::io_at_path(dest, e));
+                }
+                Err(_) => {}
+            }
+            for child in fs::read_dir(source).at_path(source)? {
+                let child = child.at_path(source)?;
+                self.copy_tree(info, &child.path(), &dest.join(child.file_name()))?;
+            }
+        } else if meta.is_file() {
+            self.check_limits(info, meta.len(), meta.len())?;
+            fs::copy(source, dest).at_path(dest)?;
+            self.total_written += meta.len();
+            self.report.files += 1;
+        }
+        Ok(())
+    }
+
+    /// Links `dest` to the earlier entry named by the hard link `info`, or
+    /// copies it where hard links are disabled or not supported.
+    fn write_hardlink(&mut self, info: &EntryInfo, dest: &Path) -> Result<(), ArchiveError> {
+        let target = info.link_target.as_deref().unwrap_or_default();
+        let Some(source) = self.destination(target)? else {
+            return Err(self.escape_error(Path::new(target)));
+        };
+        // Don't reach outside the root through symlinked parents
+        let Some(source) = self.resolve_parent(&source)? else {
+            return Err(ArchiveError::not_found_dynamic(target));
+        };
+        remove_existing(dest)?;
+        if self.options.preserve_hardlinks {
+            match fs::hard_link(&source, dest) {
+                Ok(()) => {
+                    self.report.hardlinks += 1;
+                    return Ok(());
+                }
+                Err(e) if is_missing(&e) => return Err(ArchiveError::not_found_dynamic(target)),
+                // e.g. FAT filesystems or too many links, so copy instead
+                Err(_) => {}
+            }
+        }
+
+        let meta = fs::symlink_metadata(&source).at_path(&source)?;
+        if meta.is_symlink() {
+            let link_target = fs::read_link(&source).at_path(&source)?;
+            self.write_symlink(&link_target.to_string_lossy(), dest)
+        } else if meta.is_file() {
+            let copy = EntryInfo {
+                compressed_size: None,
+                ..info.clone()
+            };
+            self.check_limits(&copy, meta.len(), meta.len())?;
+            fs::copy(&source, dest).at_path(dest)?;
+            self.total_written += meta.len();
+            self.report.files += 1;
+            // Hard links share their metadata, so the copy takes the source's
+            if self.options.preserve_mtime
+                && let Ok(mtime) = meta.modified()
+            {
+                File::options()
+                    .write(true)
+                    .open(dest)
+                    .and_then(|file| file.set_modified(mtime))
+                    .at_path(dest)?;
+            }
+            Ok(())
+        } else {
+            Err(ArchiveError::InvalidArchive {
+                format: self.format,
+                reason: format!("hard link '{}' does not point to a file", info.name).into(),
+            })
+        }
+    }
+
+    fn escape_error(&self, path: &Path) -> ArchiveError {
+        ArchiveError::InvalidArchive {
+            format: self.format,
+            reason: format!(
+                "entry path '{}' resolves outside the output directory",
+                path.strip_prefix(&self.root).unwrap_or(path).display()
+            )
+            .into(),
+        }
+    }
+}
+
+/// Copies the contents of a file from `reader` to `file`, calling `chunk`
+/// with the bytes of the file read so far and the number just read before
+/// each chunk is written.
+///
+/// Runs of zeros are left as holes if sparse files are enabled. Returns
+/// `false` if the contents failed their CRC check and the
+/// [`CrcMismatchPolicy`] lets extraction go on.
+fn copy_contents<F>(
+    file: &mut File,
+    dest: &Path,
+    info: &EntryInfo,
+    reader: &mut dyn Read,
+    options: &ExtractOptions,
+    mut chunk: F,
+) -> Result<bool, ArchiveError>
+where
+    F: FnMut(u64, u64) -> Result<(), ArchiveError>,
+{
+    let mut buf = vec![0; options.buffer_size];
+    let mut written = 0u64;
+    let mut in_hole = false;
+    let mut intact = true;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if options.crc_mismatch != CrcMismatchPolicy::Error
+                    && entry::is_crc_mismatch(&e) =>
+            {
+                intact = false;
+                break;
+            }
+            Err(e) => {
+                return Err(ArchiveError::io_from_error(
+                    format!("reading {}", info.name),
+                    e,
+                ));
+            }
+        };
+        written += n as u64;
+        chunk(written, n as u64)?;
+        in_hole = options.sparse_files && buf[..n].iter().all(|&b| b == 0);
+        if in_hole {
+            file.seek(SeekFrom::Current(n as i64))
+                .at_entry(&info.name, dest)?;
+        } else {
+            file.write_all(&buf[..n]).at_entry(&info.name, dest)?;
+        }
+    }
+    // Seeking alone doesn't extend the file over a trailing hole
+    if in_hole {
+        file.set_len(written).at_entry(&info.name, dest)?;
+    }
+    Ok(intact)
+}
+
+/// Applies the metadata of `info` to the file just written to `dest`.
+fn finish_file(
+    file: &File,
+    dest: &Path,
+    info: &EntryInfo,
+    options: &ExtractOptions,
+    owners: &mut Option<Owners>,
+) -> Result<(), ArchiveError> {
+    if options.preserve_mtime
+        && let Some(mtime) = info.mtime
+    {
+        file.set_modified(mtime).at_path(dest)?;
+    }
+    // Changing the owner may clear permission bits, so it goes first
+    if let (Some(owners), Some(owner)) = (owners, &info.owner) {
+        owners.apply(dest, owner).at_path(dest)?;
+    }
+    // Read-only files can't take attributes, so they go before the mode
+    set_xattrs(dest, info, options)?;
+    if options.preserve_permissions {
+        set_mode(dest, info.mode)?;
+    }
+    Ok(())
+}
+
+/// Fails if a file growing to `entry_bytes`, with `total` bytes written in
+/// all, would exceed one of `limits`.
+///
+/// `archive_size` is the compressed size of the whole archive, if known.
+fn check_limits(
+    limits: &ExtractLimits,
+    archive_size: Option<u64>,
+    info: &EntryInfo,
+    entry_bytes: u64,
+    total: u64,
+) -> Result<(), ArchiveError> {
+    if let Some(max) = limits.max_entry_size
+        && entry_bytes > max
+    {
+        return Err(ArchiveError::limit_exceeded("entry size", max));
+    }
+    if let Some(max) = limits.max_total_size
+        && total > max
+    {
+        return Err(ArchiveError::limit_exceeded("total extracted size", max));
+    }
+    if let Some(ratio) = limits.max_compression_ratio {
+        let exceeds = |bytes: u64, compressed: u64| bytes > compressed.max(1).saturating_mul(ratio);
+        if info
+            .compressed_size
+            .is_some_and(|c| exceeds(entry_bytes, c))
+            || archive_size.is_some_and(|a| exceeds(total, a))
+        {
+            return Err(ArchiveError::limit_exceeded("compression ratio", ratio));
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if `name` couldn't be decoded, going by the replacement
+/// characters and NUL bytes decoding leaves in undecodable names.
+fn is_undecodable(name: &str) -> bool {
+    name.contains(['\u{FFFD}', '\0'])
+}
+
+/// Removes a file or symlink at `path` so it can be replaced.
+///
+/// Existing directories are left alone; writing a file over one fails.
+fn remove_existing(path: &Path) -> Result<(), ArchiveError> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if !meta.is_dir() => fs::remove_file(path).at_path(path),
+        _ => Ok(()),
+    }
+}
+
+/// Removes whatever is at `path`, recursively for directories.
+fn remove_all(path: &Path) -> Result<(), ArchiveError> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path).at_path(path),
+        Ok(_) => fs::remove_file(path).at_path(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ArchiveError::io_at_path(path, e)),
+    }
+}
+
+/// Returns `true` if `error` means a path or one of its parents does not
+/// exist.
+fn is_missing(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+    )
+}
+
+/// Result of sanitizing an entry path.
+enum Sanitized {
+    /// A safe path relative to the output directory.
+    Path(PathBuf),
+    /// Nothing is left, e.g. for the archive root.
+    Empty,
+    /// The path escapes the output directory and the policy doesn't fix it.
+    Unsafe,
+}
+
+/// Turns an entry name into a safe relative path, dropping the first
+/// `strip` components.
+///
+/// Both `/` and `\` are treated as separators on every platform, so an
+/// archive is interpreted the same way wherever it is extracted. A leading
+/// `/` or drive prefix (`C:`) marks the name as absolute.
+fn sanitize(name: &str, mut strip: usize, policy: UnsafePathPolicy) -> Sanitized {
+    let name = name.replace('\\', "/");
+    let has_drive = has_drive(&name);
+    let absolute = name.starts_with('/') || has_drive;
+    if absolute && policy == UnsafePathPolicy::Error {
+        return Sanitized::Unsafe;
+    }
+
+    let mut relative = PathBuf::new();
+    for (i, segment) in name.split('/').enumerate() {
+        match segment {
+            "" | "." => {}
+            _ if i == 0 && has_drive => {
+                // Keep whatever follows a relative drive prefix like `C:foo`
+                let rest = &segment[2..];
+                if !rest.is_empty() {
+                    if strip > 0 {
+                        strip -= 1;
+                    } else {
+                        relative.push(rest);
+                    }
+                }
+            }
+            ".." if policy == UnsafePathPolicy::Sanitize => {}
+            ".." => return Sanitized::Unsafe,
+            _ if strip > 0 => strip -= 1,
+            _ => relative.push(segment),
+        }
+    }
+
+    if relative.as_os_str().is_empty() {
+        Sanitized::Empty
+    } else {
+        Sanitized::Path(relative)
+    }
+}
+
+/// Characters Windows refuses in names, besides control characters.
+const WINDOWS_ILLEGAL: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// DOS device names, which Windows opens a device for whatever their
+/// extension.
+const WINDOWS_DEVICES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "COM¹", "COM²", "COM³", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8",
+    "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// Returns `segment` made storable on Windows as `policy` says, or `None`
+/// if Windows can store it already.
+fn windows_name(segment: &str, policy: WindowsNamePolicy) -> Option<String> {
+    // Devices are recognized by what precedes the first dot, ignoring
+    // trailing spaces
+    let stem = segment
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(' ');
+    let device = WINDOWS_DEVICES
+        .iter()
+        .any(|device| device.eq_ignore_ascii_case(stem));
+    let kept = segment.trim_end_matches(['.', ' ']).len();
+    if !device && kept == segment.len() && !segment.contains(is_windows_illegal) {
+        return None;
+    }
+
+    let replace = |fixed: &mut String, c: char| {
+        if policy == WindowsNamePolicy::Escape {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                fixed.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            fixed.push('_');
+        }
+    };
+    let mut fixed = String::with_capacity(segment.len() + 3);
+    for (i, c) in segment.char_indices() {
+        let last_of_device = device && i + c.len_utf8() == stem.len();
+        if is_windows_illegal(c)
+            || i >= kept
+            || (last_of_device && policy == WindowsNamePolicy::Escape)
+        {
+            replace(&mut fixed, c);
+        } else {
+            fixed.push(c);
+        }
+        if last_of_device && policy != WindowsNamePolicy::Escape {
+            fixed.push('_');
+        }
+    }
+    Some(fixed)
+}
+
+/// Returns `true` if Windows refuses `c` in names.
+fn is_windows_illegal(c: char) -> bool {
+    c.is_ascii_control() || WINDOWS_ILLEGAL.contains(&c)
+}
+
+/// Returns `true` if `name` starts with a drive prefix such as `C:`.
+fn has_drive(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Resolves the symlinks in the part of `path` that exists, keeping the
+/// rest as it is.
+fn real_path(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(real) = existing.canonicalize() {
+            return rest.iter().rev().fold(real, |real, name| real.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Returns the first `name (n).ext` next to `path` that does not exist yet.
+fn free_sibling(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    (1u64..)
+        .map(|n| {
+            let name = match &extension {
+                Some(ext) => format!("{} ({}).{}", stem, n, ext),
+                None => format!("{} ({})", stem, n),
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Applies the extended attributes of `info` to `path` if they are
+/// restored.
+fn set_xattrs(path: &Path, info: &EntryInfo, options: &ExtractOptions) -> Result<(), ArchiveError> {
+    if options.preserve_xattrs {
+        xattr::apply(path, &info.xattrs).at_path(path)?;
+    }
+    Ok(())
+}
+
+/// Creates the FIFO or device node `special` at `path`.
+fn make_special(path: &Path, special: SpecialFile) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let file_type = match special.kind {
+            SpecialKind::Fifo => None,
+            SpecialKind::CharDevice => Some(libc::S_IFCHR),
+            SpecialKind::BlockDevice => Some(libc::S_IFBLK),
+            SpecialKind::Socket => return Err(io::ErrorKind::Unsupported.into()),
+        };
+        // SAFETY: the path is NUL-terminated and outlives the call.
+        let status = unsafe {
+            match file_type {
+                None => libc::mkfifo(path.as_ptr(), 0o666),
+                Some(file_type) => {
+                    let device = libc::makedev(special.major as _, special.minor as _);
+                    libc::mknod(path.as_ptr(), file_type | 0o666, device)
+                }
+            }
+        };
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, special);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Applies the permission bits of an entry, without setuid/setgid/sticky.
+fn set_mode(path: &Path, mode: Option<u32>) -> Result<(), ArchiveError> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).at_path(path)?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+
+    Ok(())
+}
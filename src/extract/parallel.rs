@@ -0,0 +1,425 @@
+//! Extraction that writes several files at once.
+//!
+//! Entries are still walked in archive order on the calling thread, which
+//! creates directories and links and settles where each file goes, so path
+//! checks and conflicts behave as in a sequential extraction. Only the
+//! contents of files are left to worker threads. Each ZIP entry can be
+//! decompressed on its own, while a 7z block can only be decoded from its
+//! start, so the files of one block are written by one worker in order.
+//!
+//! Queued files don't exist until a worker writes them, so an entry whose
+//! path involves a queued file waits for the queue to be written first.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+};
+
+use super::{
+    CrcMismatch, CrcMismatchPolicy, ExtractEvent, ExtractOptions, ExtractReport, Extractor,
+    check_limits, copy_contents, finish_file,
+};
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::{ArchiveError, PathContext},
+    format::ArchiveFormat,
+    owner::Owners,
+    password::Passwords,
+    progress::CancellationToken,
+    volume::Location,
+};
+
+/// Extracts a ZIP or 7z archive on [`ExtractOptions::threads`] threads.
+///
+/// Returns `None` without extracting anything if the archive can't be
+/// split up between threads, which is the case for 7z archives with a
+/// single block or with symbolic links.
+///
+/// # Errors
+///
+/// This function will return an error under the same conditions as
+/// [`super::extract`].
+pub(super) fn extract(
+    location: &Location,
+    format: ArchiveFormat,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    passwords: &Passwords,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<Option<ExtractReport>, ArchiveError> {
+    match format {
+        ArchiveFormat::Zip => {
+            fs::create_dir_all(output_dir).at_path(output_dir)?;
+            let mut extractor =
+                Extractor::new(output_dir, format, options, location.len(), observer)?;
+            let source = Source::Zip {
+                passwords,
+            };
+            let mut queue = Queue::default();
+            // Entries are visited in the order of their indices
+            let mut index = 0;
+            entry::for_each_entry_with_password(location, format, passwords, |info, _| {
+                queue.place(&mut extractor, location, &source, info, index, index)?;
+                index += 1;
+                Ok(true)
+            })?;
+            queue.write(&mut extractor, location, &source)?;
+            extractor.finish().map(Some)
+        }
+        ArchiveFormat::SevenZ => {
+            let (archive, password) = entry::read_7z(&mut location.open(format)?, passwords)?;
+            let infos: Vec<EntryInfo> = archive.files.iter().map(entry::sevenz_info).collect();
+            // Link targets are stored as data, so they'd only be known once
+            // their block is decoded
+            if archive.blocks.len() < 2 || infos.iter().any(|info| info.kind == EntryKind::Symlink)
+            {
+                return Ok(None);
+            }
+
+            fs::create_dir_all(output_dir).at_path(output_dir)?;
+            let mut extractor =
+                Extractor::new(output_dir, format, options, location.len(), observer)?;
+            let source = Source::SevenZ {
+                archive: &archive,
+                password: &password,
+            };
+            let mut queue = Queue::default();
+            // The files of each block follow each other, and the entries
+            // without data come last
+            let blocks = &archive.stream_map.file_block_index;
+            for (index, info) in infos.iter().enumerate() {
+                if let Some(block) = blocks[index] {
+                    queue.place(&mut extractor, location, &source, info, block, index)?;
+                }
+            }
+            queue.write(&mut extractor, location, &source)?;
+            for (index, info) in infos.iter().enumerate() {
+                if blocks[index].is_none() {
+                    extractor.unpack(info, &mut io::empty())?;
+                }
+            }
+            extractor.finish().map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Where workers read the queued files from.
+enum Source<'a> {
+    Zip {
+        passwords: &'a Passwords,
+    },
+    SevenZ {
+        archive: &'a sevenz_rust2::Archive,
+        password: &'a sevenz_rust2::Password,
+    },
+}
+
+/// A file left to the workers.
+struct Job {
+    /// Index of the entry in the archive.
+    index: usize,
+    info: EntryInfo,
+    dest: PathBuf,
+}
+
+/// Files placed but not written yet.
+#[derive(Default)]
+struct Queue {
+    /// Files grouped by what a worker decodes at once: the ZIP entry, or
+    /// the 7z block holding them.
+    units: Vec<(usize, Vec<Job>)>,
+    /// Paths of the queued files.
+    files: HashSet<PathBuf>,
+    /// Directories holding the queued files.
+    parents: HashSet<PathBuf>,
+}
+
+impl Queue {
+    /// Unpacks `info`, queueing it as part of `unit` if it is a file.
+    fn place(
+        &mut self,
+        extractor: &mut Extractor<'_>,
+        location: &Location,
+        source: &Source<'_>,
+        info: &EntryInfo,
+        unit: usize,
+        index: usize,
+    ) -> Result<(), ArchiveError> {
+        let options = extractor.options;
+        let info = &*options.decoded(info);
+        if let Some(dest) = extractor.destination(&info.name)?
+            && self.disturbs(&dest, info.kind)
+        {
+            self.write(extractor, location, source)?;
+        }
+        let Some(dest) = extractor.place(info)? else {
+            return Ok(());
+        };
+
+        for parent in dest.ancestors().skip(1) {
+            if !self.parents.insert(parent.to_path_buf()) {
+                break;
+            }
+        }
+        self.files.insert(dest.clone());
+        let job = Job {
+            index,
+            info: info.clone(),
+            dest,
+        };
+        match self.units.last_mut() {
+            Some((last, jobs)) if *last == unit => jobs.push(job),
+            _ => self.units.push((unit, vec![job])),
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if an entry of `kind` at `dest` can't be unpacked
+    /// before the queued files are written.
+    fn disturbs(&self, dest: &Path, kind: EntryKind) -> bool {
+        // Directories merge into the ones holding queued files, but
+        // anything else would replace them
+        kind == EntryKind::Hardlink
+            || (kind != EntryKind::Directory && self.parents.contains(dest))
+            || dest.ancestors().any(|path| self.files.contains(path))
+    }
+
+    /// Writes the queued files on the configured number of threads.
+    fn write(
+        &mut self,
+        extractor: &mut Extractor<'_>,
+        location: &Location,
+        source: &Source<'_>,
+    ) -> Result<(), ArchiveError> {
+        if self.units.is_empty() {
+            return Ok(());
+        }
+        self.files.clear();
+        self.parents.clear();
+        let units = mem::take(&mut self.units);
+        let threads = extractor.options.thread_count().min(units.len());
+        let pool = Pool {
+            location,
+            format: extractor.format,
+            source,
+            options: extractor.options,
+            archive_size: extractor.archive_size,
+            units: Mutex::new(units.into()),
+            total_written: AtomicU64::new(extractor.total_written),
+            stopped: AtomicBool::new(false),
+        };
+
+        let mut failure = None;
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let pool = &pool;
+                scope.spawn(move || {
+                    // Only the first failure is reported; the workers it
+                    // stops fail as a consequence
+                    if let Err(err) = pool.work(&sender)
+                        && !pool.stopped.swap(true, Ordering::Relaxed)
+                    {
+                        let _ = sender.send(Message::Failed(err));
+                    }
+                });
+            }
+            drop(sender);
+
+            for message in receiver {
+                match message {
+                    Message::Written {
+                        name,
+                        written,
+                        total,
+                        n,
+                    } => {
+                        (extractor.observer)(ExtractEvent::BytesWritten {
+                            name: name.clone(),
+                            written,
+                            total,
+                        });
+                        extractor.progress.add_bytes(&name, n);
+                        extractor.report.bytes_read += n;
+                    }
+                    Message::Finished {
+                        name,
+                        kept,
+                    } => {
+                        if kept {
+                            extractor.report.files += 1;
+                        } else {
+                            extractor.report.skipped += 1;
+                        }
+                        extractor.entry_finished(&name);
+                    }
+                    Message::CrcMismatch(mismatch) => {
+                        extractor.report.crc_mismatches.push(mismatch);
+                    }
+                    Message::Failed(err) => failure = Some(err),
+                }
+            }
+        });
+        extractor.total_written = pool.total_written.into_inner();
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What workers report back to the calling thread.
+enum Message {
+    Written {
+        name: String,
+        written: u64,
+        total: u64,
+        n: u64,
+    },
+    /// A file is done, and was `kept` unless it failed its CRC check.
+    Finished {
+        name: String,
+        kept: bool,
+    },
+    CrcMismatch(CrcMismatch),
+    Failed(ArchiveError),
+}
+
+/// State shared by the workers writing a queue.
+struct Pool<'a> {
+    location: &'a Location,
+    format: ArchiveFormat,
+    source: &'a Source<'a>,
+    options: &'a ExtractOptions,
+    archive_size: Option<u64>,
+    units: Mutex<VecDeque<(usize, Vec<Job>)>>,
+    /// File bytes written so far by every worker.
+    total_written: AtomicU64,
+    /// Set once a worker fails, to stop the others.
+    stopped: AtomicBool,
+}
+
+impl Pool<'_> {
+    /// Writes units of files until none are left.
+    fn work(&self, sender: &Sender<Message>) -> Result<(), ArchiveError> {
+        let mut owners = self
+            .options
+            .preserve_ownership
+            .then(|| Owners::new(self.options.numeric_owner));
+        match self.source {
+            Source::Zip {
+                passwords,
+            } => {
+                let mut archive = zip::ZipArchive::new(self.location.open(self.format)?)?;
+                while let Some((_, jobs)) = self.next_unit() {
+                    for job in &jobs {
+                        let mut file = entry::zip_entry(&mut archive, job.index, passwords)?;
+                        let mut data = entry::ZipCrcReader::new(&mut file);
+                        self.write(job, &mut data, &mut owners, sender)?;
+                    }
+                }
+            }
+            Source::SevenZ {
+                archive,
+                password,
+            } => {
+                let mut reader = self.location.open(self.format)?;
+                while let Some((block, jobs)) = self.next_unit() {
+                    let mut jobs = jobs.iter().peekable();
+                    let mut index = archive.stream_map.block_first_file_index[block];
+                    entry::walk_7z_block(archive, block, &mut reader, password, |_, data| {
+                        if let Some(job) = jobs.next_if(|job| job.index == index) {
+                            self.write(job, data, &mut owners, sender)?;
+                        }
+                        index += 1;
+                        Ok(jobs.peek().is_some())
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_unit(&self) -> Option<(usize, Vec<Job>)> {
+        if self.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.units
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .pop_front()
+    }
+
+    /// Writes the contents of `job` from `reader` to its destination.
+    fn write(
+        &self,
+        job: &Job,
+        reader: &mut dyn Read,
+        owners: &mut Option<Owners>,
+        sender: &Sender<Message>,
+    ) -> Result<(), ArchiveError> {
+        let info = &job.info;
+        let mut file = File::create(&job.dest).at_entry(&info.name, &job.dest)?;
+        let intact = copy_contents(
+            &mut file,
+            &job.dest,
+            info,
+            reader,
+            self.options,
+            |written, n| {
+                let cancelled = self
+                    .options
+                    .cancellation
+                    .as_ref()
+                    .is_some_and(CancellationToken::is_cancelled);
+                if cancelled || self.stopped.load(Ordering::Relaxed) {
+                    return Err(ArchiveError::Cancelled);
+                }
+                let total = self.total_written.fetch_add(n, Ordering::Relaxed) + n;
+                check_limits(
+                    &self.options.limits,
+                    self.archive_size,
+                    info,
+                    written,
+                    total,
+                )?;
+                let _ = sender.send(Message::Written {
+                    name: info.name.clone(),
+                    written,
+                    total: info.size,
+                    n,
+                });
+                Ok(())
+            },
+        )?;
+        if !intact {
+            let _ = sender.send(Message::CrcMismatch(CrcMismatch {
+                name: info.name.clone(),
+                path: job.dest.clone(),
+            }));
+        }
+        let kept = intact || self.options.crc_mismatch != CrcMismatchPolicy::Skip;
+        if kept {
+            finish_file(&file, &job.dest, info, self.options, owners)?;
+        } else {
+            drop(file);
+            fs::remove_file(&job.dest).at_path(&job.dest)?;
+        }
+        let _ = sender.send(Message::Finished {
+            name: info.name.clone(),
+            kept,
+        });
+        Ok(())
+    }
+}
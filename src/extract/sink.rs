@@ -0,0 +1,529 @@
+//! Extraction into destinations the caller supplies.
+//!
+//! Entries go through the same selection, path mapping and limits as in
+//! [`super::extract`], and leave the sink with paths that are already
+//! relative and free of `..` components. How entries end up stored is up to
+//! the [`ExtractSink`]: the local filesystem through [`FsSink`], or object
+//! storage, a database or an in-memory map through one of the caller's.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use super::{
+    ExtractOptions, ExtractReport, SymlinkPolicy, UnsafePathPolicy, check_limits, has_drive,
+    remove_existing, set_mode,
+};
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::{ArchiveError, PathContext},
+    format::ArchiveFormat,
+    progress::ProgressTracker,
+    volume::Location,
+    xattr,
+};
+
+/// A destination for extracted entries.
+///
+/// Paths handed to a sink are relative to wherever it stores entries and
+/// never contain `..`. Directories may be created after the entries below
+/// them, if at all, so [`ExtractSink::create_file`] and the link methods
+/// should create missing parents themselves.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{
+///     collections::BTreeMap,
+///     io::{self, Write},
+///     path::{Path, PathBuf},
+/// };
+///
+/// use compak::{Archive, ArchiveError, EntryInfo, ExtractOptions, ExtractSink};
+///
+/// /// Collects the size of every file instead of its contents.
+/// #[derive(Default)]
+/// struct Sizes(BTreeMap<PathBuf, u64>);
+///
+/// struct Counter<'a>(&'a mut u64);
+///
+/// impl Write for Counter<'_> {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         *self.0 += buf.len() as u64;
+///         Ok(buf.len())
+///     }
+///
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl ExtractSink for Sizes {
+///     fn create_dir(&mut self, _path: &Path) -> Result<(), ArchiveError> {
+///         Ok(())
+///     }
+///
+///     fn create_file(
+///         &mut self,
+///         path: &Path,
+///         _info: &EntryInfo,
+///     ) -> Result<Box<dyn Write + '_>, ArchiveError> {
+///         Ok(Box::new(Counter(self.0.entry(path.to_path_buf()).or_default())))
+///     }
+///
+///     fn symlink(&mut self, _path: &Path, _target: &str) -> Result<(), ArchiveError> {
+///         Ok(())
+///     }
+/// }
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut sizes = Sizes::default();
+///     Archive::open("dataset.tar.gz")?.extract_to_sink(&mut sizes, &ExtractOptions::new())?;
+///     for (path, size) in &sizes.0 {
+///         println!("{}: {} bytes", path.display(), size);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub trait ExtractSink {
+    /// Creates the directory at `path`, along with any missing parents.
+    fn create_dir(&mut self, path: &Path) -> Result<(), ArchiveError>;
+
+    /// Creates the file at `path`, replacing whatever is there, and returns
+    /// a writer for its contents.
+    ///
+    /// The writer is flushed and dropped once the contents are written,
+    /// before [`ExtractSink::set_metadata`] is called for the file.
+    fn create_file(
+        &mut self,
+        path: &Path,
+        info: &EntryInfo,
+    ) -> Result<Box<dyn Write + '_>, ArchiveError>;
+
+    /// Creates a symbolic link at `path` pointing to `target`, which stays
+    /// below the sink's root.
+    fn symlink(&mut self, path: &Path, target: &str) -> Result<(), ArchiveError>;
+
+    /// Creates `path` as a hard link to the file an earlier entry wrote at
+    /// `target`.
+    ///
+    /// The default implementation fails with
+    /// [`ArchiveError::Unsupported`].
+    fn hard_link(&mut self, path: &Path, target: &Path) -> Result<(), ArchiveError> {
+        let _ = (path, target);
+        Err(ArchiveError::unsupported_static("hard links in this sink"))
+    }
+
+    /// Applies the permissions, modification time, owner and extended
+    /// attributes of `info` to the file or directory at `path`.
+    ///
+    /// Only what the [`ExtractOptions`] say to restore is set on `info`.
+    /// Directories are given their metadata once every entry is written,
+    /// deepest first. The default implementation does nothing.
+    fn set_metadata(&mut self, path: &Path, info: &EntryInfo) -> Result<(), ArchiveError> {
+        let _ = (path, info);
+        Ok(())
+    }
+}
+
+/// An [`ExtractSink`] writing below a directory of the local filesystem.
+///
+/// Parents that are symlinks are only followed while they lead to a
+/// directory below the root, and existing files and symlinks are removed
+/// before being replaced, so entries cannot be written outside the root
+/// through links that are already there. Owners are not restored.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::{Archive, ExtractOptions, FsSink};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut sink = FsSink::new("./restored")?;
+///     Archive::open("backup.tar.gz")?.extract_to_sink(&mut sink, &ExtractOptions::new())?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FsSink {
+    root: PathBuf,
+}
+
+impl FsSink {
+    /// Creates a sink writing below `root`, creating the directory if it
+    /// doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `root` cannot be created or
+    /// resolved.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, ArchiveError> {
+        let root = root.as_ref();
+        fs::create_dir_all(root).at_path(root)?;
+        Ok(Self {
+            root: root.canonicalize().at_path(root)?,
+        })
+    }
+
+    /// Returns the directory entries are written below.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns where `path` lies below the root, refusing parents that are
+    /// symlinks leading elsewhere.
+    fn resolve(&self, path: &Path) -> Result<PathBuf, ArchiveError> {
+        let mut current = self.root.clone();
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            current.push(component);
+            if let Ok(meta) = fs::symlink_metadata(&current)
+                && meta.file_type().is_symlink()
+            {
+                let resolved = current.canonicalize().at_path(&current)?;
+                if !resolved.starts_with(&self.root) || !resolved.is_dir() {
+                    return Err(ArchiveError::Custom {
+                        message: format!(
+                            "'{}' resolves outside the output directory",
+                            path.display()
+                        )
+                        .into(),
+                    });
+                }
+            }
+        }
+        Ok(self.root.join(path))
+    }
+
+    /// Resolves `path` and makes room for a new file or link there.
+    fn replace(&self, path: &Path) -> Result<PathBuf, ArchiveError> {
+        let dest = self.resolve(path)?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).at_path(parent)?;
+        }
+        remove_existing(&dest)?;
+        Ok(dest)
+    }
+}
+
+impl ExtractSink for FsSink {
+    fn create_dir(&mut self, path: &Path) -> Result<(), ArchiveError> {
+        let dir = self.resolve(path)?;
+        fs::create_dir_all(&dir).at_path(&dir)
+    }
+
+    fn create_file(
+        &mut self,
+        path: &Path,
+        _info: &EntryInfo,
+    ) -> Result<Box<dyn Write + '_>, ArchiveError> {
+        let dest = self.replace(path)?;
+        Ok(Box::new(File::create(&dest).at_path(&dest)?))
+    }
+
+    fn symlink(&mut self, path: &Path, target: &str) -> Result<(), ArchiveError> {
+        let dest = self.replace(path)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, &dest).at_path(&dest)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(target.replace('/', "\\"), &dest).at_path(&dest)?;
+        #[cfg(not(any(unix, windows)))]
+        let _ = target;
+        Ok(())
+    }
+
+    fn hard_link(&mut self, path: &Path, target: &Path) -> Result<(), ArchiveError> {
+        let source = self.resolve(target)?;
+        let dest = self.replace(path)?;
+        // e.g. FAT filesystems, so copy instead
+        if fs::hard_link(&source, &dest).is_err() {
+            fs::copy(&source, &dest).at_path(&dest)?;
+        }
+        Ok(())
+    }
+
+    fn set_metadata(&mut self, path: &Path, info: &EntryInfo) -> Result<(), ArchiveError> {
+        let dest = self.resolve(path)?;
+        let meta = fs::symlink_metadata(&dest).at_path(&dest)?;
+        if meta.is_symlink() {
+            return Ok(());
+        }
+        xattr::apply(&dest, &info.xattrs).at_path(&dest)?;
+        if let Some(mtime) = info.mtime {
+            let handle = if meta.is_dir() {
+                File::open(&dest)
+            } else {
+                File::options().write(true).open(&dest)
+            };
+            // Not every platform can open directories for this
+            if let Ok(handle) = handle {
+                handle.set_modified(mtime).at_path(&dest)?;
+            }
+        }
+        set_mode(&dest, info.mode)
+    }
+}
+
+/// An [`ExtractSink`] keeping the contents of every file in memory.
+///
+/// Files are keyed by their path after the [`ExtractOptions`] have mapped
+/// it, and hard links get a copy of the contents of their target.
+/// Directories and symlinks carry no data and are not kept.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::{Archive, ExtractOptions, MemorySink};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let options = ExtractOptions::new().include(["*.json"]);
+///     let mut sink = MemorySink::new();
+///     Archive::open("upload.zip")?.extract_to_sink(&mut sink, &options)?;
+///     for (path, data) in sink.files() {
+///         println!("{}: {} bytes", path.display(), data.len());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemorySink {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the files extracted so far.
+    pub fn files(&self) -> &HashMap<PathBuf, Vec<u8>> {
+        &self.files
+    }
+
+    /// Consumes the sink, returning the extracted files.
+    pub fn into_files(self) -> HashMap<PathBuf, Vec<u8>> {
+        self.files
+    }
+}
+
+impl ExtractSink for MemorySink {
+    fn create_dir(&mut self, _path: &Path) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    fn create_file(
+        &mut self,
+        path: &Path,
+        _info: &EntryInfo,
+    ) -> Result<Box<dyn Write + '_>, ArchiveError> {
+        let data = self.files.entry(path.to_path_buf()).or_default();
+        data.clear();
+        Ok(Box::new(data))
+    }
+
+    fn symlink(&mut self, _path: &Path, _target: &str) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    fn hard_link(&mut self, path: &Path, target: &Path) -> Result<(), ArchiveError> {
+        if let Some(data) = self.files.get(target).cloned() {
+            self.files.insert(path.to_path_buf(), data);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts every entry of the archive at `location` into `sink`.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be decoded,
+/// if an entry is refused by the [`ExtractOptions`], if a configured limit
+/// is exceeded, if the operation is cancelled, or if `sink` fails.
+pub(crate) fn extract(
+    location: &Location,
+    format: ArchiveFormat,
+    sink: &mut dyn ExtractSink,
+    options: &ExtractOptions,
+) -> Result<ExtractReport, ArchiveError> {
+    let started = Instant::now();
+    let mut progress = ProgressTracker::new(options.progress.clone(), options.cancellation.clone());
+    let archive_size = location.len();
+    let mut report = ExtractReport::default();
+    let mut directories = Vec::new();
+    let mut entries = 0u64;
+    let mut buf = vec![0; options.buffer_size];
+
+    entry::for_each_entry_with_password(location, format, &options.passwords(), |info, reader| {
+        let info = &*options.decoded(info);
+        progress.check()?;
+        entries += 1;
+        if let Some(max) = options.limits.max_entries
+            && entries > max
+        {
+            return Err(ArchiveError::limit_exceeded("entry count", max));
+        }
+        if !options.selects(info) {
+            report.skipped += 1;
+            return Ok(true);
+        }
+        // Sinks have nowhere to put special files, but the policy may
+        // still reject them
+        if info.kind == EntryKind::Other {
+            options.recreates(format, info)?;
+            report.skipped += 1;
+            return Ok(true);
+        }
+        let Some(path) = options.relative_path(format, &info.name)? else {
+            report.skipped += 1;
+            return Ok(true);
+        };
+        let info = restored(info, options);
+
+        match info.kind {
+            EntryKind::Directory => {
+                sink.create_dir(&path)?;
+                report.directories += 1;
+                directories.push((path, info.clone()));
+            }
+            EntryKind::File => {
+                let total = report.bytes_written;
+                check_limits(
+                    &options.limits,
+                    archive_size,
+                    &info,
+                    info.size,
+                    total.saturating_add(info.size),
+                )?;
+                let mut writer = sink.create_file(&path, &info)?;
+                let mut written = 0u64;
+                loop {
+                    let n = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            return Err(ArchiveError::io_from_error(
+                                format!("reading {}", info.name),
+                                e,
+                            ));
+                        }
+                    };
+                    written += n as u64;
+                    progress.check()?;
+                    check_limits(
+                        &options.limits,
+                        archive_size,
+                        &info,
+                        written,
+                        total + written,
+                    )?;
+                    writer.write_all(&buf[..n]).at_entry(&info.name, &path)?;
+                    progress.add_bytes(&info.name, n as u64);
+                }
+                writer.flush().at_entry(&info.name, &path)?;
+                drop(writer);
+                sink.set_metadata(&path, &info)?;
+                report.files += 1;
+                report.bytes_read += written;
+                report.bytes_written += written;
+            }
+            EntryKind::Symlink => {
+                let target = info.link_target.as_deref().unwrap_or_default();
+                match options.symlinks {
+                    SymlinkPolicy::Skip => report.skipped += 1,
+                    SymlinkPolicy::Error => {
+                        return Err(ArchiveError::InvalidArchive {
+                            format,
+                            reason: format!("entry '{}' is a symbolic link", info.name).into(),
+                        });
+                    }
+                    // The sink can't be read back to copy the target
+                    SymlinkPolicy::Preserve | SymlinkPolicy::Materialize => {
+                        if stays_inside(&path, target) {
+                            sink.symlink(&path, target)?;
+                            report.symlinks += 1;
+                        } else if options.unsafe_paths == UnsafePathPolicy::Error {
+                            return Err(ArchiveError::InvalidArchive {
+                                format,
+                                reason: format!(
+                                    "symlink '{}' points outside the output directory",
+                                    info.name
+                                )
+                                .into(),
+                            });
+                        } else {
+                            report.skipped += 1;
+                        }
+                    }
+                }
+            }
+            EntryKind::Hardlink => {
+                let target = info.link_target.as_deref().unwrap_or_default();
+                let Some(target) = options.relative_path(format, target)? else {
+                    return Err(ArchiveError::InvalidArchive {
+                        format,
+                        reason: format!(
+                            "hard link '{}' points outside the output directory",
+                            info.name
+                        )
+                        .into(),
+                    });
+                };
+                sink.hard_link(&path, &target)?;
+                report.hardlinks += 1;
+            }
+            EntryKind::Other => unreachable!(),
+        }
+        progress.finish_entry(&info.name);
+        Ok(true)
+    })?;
+
+    directories.sort_by(|(a, _), (b, _)| b.cmp(a));
+    for (dir, info) in &directories {
+        sink.set_metadata(dir, info)?;
+    }
+    report.duration = started.elapsed();
+    Ok(report)
+}
+
+/// Returns `info` with the metadata `options` don't restore cleared.
+fn restored(info: &EntryInfo, options: &ExtractOptions) -> EntryInfo {
+    let mut info = info.clone();
+    if !options.preserve_permissions {
+        info.mode = None;
+    }
+    if !options.preserve_mtime {
+        info.mtime = None;
+    }
+    if !options.preserve_ownership {
+        info.owner = None;
+    }
+    if !options.preserve_xattrs {
+        info.xattrs.clear();
+    }
+    info
+}
+
+/// Returns `true` if the symlink target `target` of a link at `path`
+/// stays below the root, going by the names alone.
+fn stays_inside(path: &Path, target: &str) -> bool {
+    let target = target.replace('\\', "/");
+    if target.starts_with('/') || has_drive(&target) {
+        return false;
+    }
+    let mut depth = path.components().count().saturating_sub(1);
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." if depth == 0 => return false,
+            ".." => depth -= 1,
+            _ => depth += 1,
+        }
+    }
+    true
+}
@@ -1,11 +1,15 @@
 use std::{
     fmt::{self, Display},
     fs::File,
-    io::Read,
+    io::{self, BufReader, Read, Seek, SeekFrom},
     path::Path,
 };
 
-use crate::error::ArchiveError;
+use crate::{
+    appimage, ar, backend, brotli, cfb, entry,
+    error::{ArchiveError, PathContext},
+    iso, lz4, lzw, squashfs, volume, wim, xar,
+};
 
 /// Enumeration of supported archive formats.
 ///
@@ -23,7 +27,7 @@ use crate::error::ArchiveError;
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArchiveFormat {
-    /// ZIP archive format (.zip)
+    /// ZIP archive format (.zip), including split archives (.z01, .z02, ...)
     Zip,
     /// TAR archive compressed with GZIP (.tar.gz, .tgz)
     TarGz,
@@ -33,10 +37,76 @@ pub enum ArchiveFormat {
     TarBz2,
     /// TAR archive compressed with Zstandard (.tar.zst)
     TarZst,
-    /// Plain TAR archive (.tar) - not yet implemented
+    /// TAR archive compressed with LZ4 (.tar.lz4), read-only
+    TarLz4,
+    /// TAR archive compressed with Brotli (.tar.br), read-only
+    TarBr,
+    /// TAR archive compressed with lzip (.tar.lz), read-only
+    TarLz,
+    /// TAR archive compressed with legacy LZMA (.tar.lzma, .tlz), read-only
+    TarLzma,
+    /// TAR archive compressed with Unix `compress` (.tar.Z, .taz), read-only
+    TarZ,
+    /// Plain TAR archive (.tar)
     Tar,
-    /// 7-Zip archive (.7z) - not yet implemented
+    /// 7-Zip archive (.7z), including split archives (.7z.001, .7z.002, ...)
     SevenZ,
+    /// RAR archive (.rar) - read with the `rar` feature, never written
+    Rar,
+    /// A single file compressed with GZIP (.gz)
+    Gzip,
+    /// A single file compressed with XZ (.xz)
+    Xz,
+    /// A single file compressed with BZIP2 (.bz2)
+    Bzip2,
+    /// A single file compressed with Zstandard (.zst)
+    Zstd,
+    /// A single file compressed with LZ4 (.lz4), read-only
+    Lz4,
+    /// A single file compressed with Brotli (.br), read-only
+    Brotli,
+    /// A single file compressed with lzip (.lz), read-only
+    Lzip,
+    /// A single file compressed with legacy LZMA (.lzma), read-only
+    Lzma,
+    /// A single file compressed with Unix `compress` (.Z), read-only
+    Compress,
+    /// Unix `ar` archive (.a, .ar)
+    Ar,
+    /// Debian package (.deb), an `ar` archive of nested tarballs
+    Deb,
+    /// ISO 9660 disc image (.iso), read-only
+    Iso,
+    /// SquashFS filesystem image (.squashfs, .sqfs, .snap), read-only
+    Squashfs,
+    /// Windows Imaging file (.wim), read-only
+    Wim,
+    /// xar archive (.xar), including macOS installer packages (.pkg), read-only
+    Xar,
+    /// Windows Installer package (.msi) or other OLE compound file, read-only
+    Msi,
+    /// AppImage application bundle (.AppImage), read-only
+    AppImage,
+    /// A format added with [`register_format`](crate::register_format),
+    /// read-only
+    Custom(CustomFormat),
+}
+
+/// A format added at runtime with
+/// [`register_format`](crate::register_format).
+///
+/// Values are only made by registering a format, so each one names a
+/// registered backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomFormat {
+    pub(crate) name: &'static str,
+}
+
+impl CustomFormat {
+    /// Returns the name the format was registered under.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
 }
 
 impl Display for ArchiveFormat {
@@ -48,7 +118,31 @@ impl Display for ArchiveFormat {
             ArchiveFormat::TarBz2 => write!(f, "TAR.BZ2"),
             ArchiveFormat::TarXz => write!(f, "TAR.XZ"),
             ArchiveFormat::TarZst => write!(f, "TAR.ZST"),
+            ArchiveFormat::TarLz4 => write!(f, "TAR.LZ4"),
+            ArchiveFormat::TarBr => write!(f, "TAR.BR"),
+            ArchiveFormat::TarLz => write!(f, "TAR.LZ"),
+            ArchiveFormat::TarLzma => write!(f, "TAR.LZMA"),
+            ArchiveFormat::TarZ => write!(f, "TAR.Z"),
             ArchiveFormat::SevenZ => write!(f, "7Z"),
+            ArchiveFormat::Rar => write!(f, "RAR"),
+            ArchiveFormat::Gzip => write!(f, "GZ"),
+            ArchiveFormat::Xz => write!(f, "XZ"),
+            ArchiveFormat::Bzip2 => write!(f, "BZ2"),
+            ArchiveFormat::Zstd => write!(f, "ZST"),
+            ArchiveFormat::Lz4 => write!(f, "LZ4"),
+            ArchiveFormat::Brotli => write!(f, "BR"),
+            ArchiveFormat::Lzip => write!(f, "LZ"),
+            ArchiveFormat::Lzma => write!(f, "LZMA"),
+            ArchiveFormat::Compress => write!(f, "Z"),
+            ArchiveFormat::Ar => write!(f, "AR"),
+            ArchiveFormat::Deb => write!(f, "DEB"),
+            ArchiveFormat::Iso => write!(f, "ISO"),
+            ArchiveFormat::Squashfs => write!(f, "SQUASHFS"),
+            ArchiveFormat::Wim => write!(f, "WIM"),
+            ArchiveFormat::Xar => write!(f, "XAR"),
+            ArchiveFormat::Msi => write!(f, "MSI"),
+            ArchiveFormat::AppImage => write!(f, "APPIMAGE"),
+            ArchiveFormat::Custom(custom) => write!(f, "{}", custom.name),
         }
     }
 }
@@ -80,7 +174,31 @@ impl ArchiveFormat {
             ArchiveFormat::TarBz2 => "tar.bz2",
             ArchiveFormat::TarXz => "tar.xz",
             ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::TarLz4 => "tar.lz4",
+            ArchiveFormat::TarBr => "tar.br",
+            ArchiveFormat::TarLz => "tar.lz",
+            ArchiveFormat::TarLzma => "tar.lzma",
+            ArchiveFormat::TarZ => "tar.Z",
             ArchiveFormat::SevenZ => "7z",
+            ArchiveFormat::Rar => "rar",
+            ArchiveFormat::Gzip => "gz",
+            ArchiveFormat::Xz => "xz",
+            ArchiveFormat::Bzip2 => "bz2",
+            ArchiveFormat::Zstd => "zst",
+            ArchiveFormat::Lz4 => "lz4",
+            ArchiveFormat::Brotli => "br",
+            ArchiveFormat::Lzip => "lz",
+            ArchiveFormat::Lzma => "lzma",
+            ArchiveFormat::Compress => "Z",
+            ArchiveFormat::Ar => "a",
+            ArchiveFormat::Deb => "deb",
+            ArchiveFormat::Iso => "iso",
+            ArchiveFormat::Squashfs => "squashfs",
+            ArchiveFormat::Wim => "wim",
+            ArchiveFormat::Xar => "xar",
+            ArchiveFormat::Msi => "msi",
+            ArchiveFormat::AppImage => "AppImage",
+            ArchiveFormat::Custom(custom) => backend::registered_extension(*custom),
         }
     }
 
@@ -113,14 +231,107 @@ impl ArchiveFormat {
             ArchiveFormat::TarXz => "application/x-xz",
             ArchiveFormat::TarBz2 => "application/x-bzip2",
             ArchiveFormat::TarZst => "application/zstd",
+            ArchiveFormat::TarLz4 => "application/x-lz4",
+            ArchiveFormat::TarBr => "application/x-brotli",
+            ArchiveFormat::TarLz => "application/x-lzip",
+            ArchiveFormat::TarLzma => "application/x-lzma",
+            ArchiveFormat::TarZ => "application/x-compress",
             ArchiveFormat::Tar => "application/x-tar",
             ArchiveFormat::SevenZ => "application/x-7z-compressed",
+            ArchiveFormat::Rar => "application/vnd.rar",
+            ArchiveFormat::Gzip => "application/gzip",
+            ArchiveFormat::Xz => "application/x-xz",
+            ArchiveFormat::Bzip2 => "application/x-bzip2",
+            ArchiveFormat::Zstd => "application/zstd",
+            ArchiveFormat::Lz4 => "application/x-lz4",
+            ArchiveFormat::Brotli => "application/x-brotli",
+            ArchiveFormat::Lzip => "application/x-lzip",
+            ArchiveFormat::Lzma => "application/x-lzma",
+            ArchiveFormat::Compress => "application/x-compress",
+            ArchiveFormat::Ar => "application/x-archive",
+            ArchiveFormat::Deb => "application/vnd.debian.binary-package",
+            ArchiveFormat::Iso => "application/x-iso9660-image",
+            ArchiveFormat::Squashfs => "application/vnd.squashfs",
+            ArchiveFormat::Wim => "application/x-ms-wim",
+            ArchiveFormat::Xar => "application/x-xar",
+            ArchiveFormat::Msi => "application/x-msi",
+            ArchiveFormat::AppImage => "application/vnd.appimage",
+            ArchiveFormat::Custom(_) => "application/octet-stream",
+        }
+    }
+
+    /// Returns `true` for formats that hold a single compressed file rather
+    /// than a collection of entries.
+    ///
+    /// These formats don't record the original file name, so it is derived
+    /// from the archive's own name by dropping the extension, the way
+    /// `gunzip` does. Their uncompressed size isn't recorded either and is
+    /// reported as 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::ArchiveFormat;
+    ///
+    /// assert!(ArchiveFormat::Gzip.is_single_file());
+    /// assert!(!ArchiveFormat::TarGz.is_single_file());
+    /// ```
+    pub fn is_single_file(&self) -> bool {
+        matches!(
+            self,
+            ArchiveFormat::Gzip
+                | ArchiveFormat::Xz
+                | ArchiveFormat::Bzip2
+                | ArchiveFormat::Zstd
+                | ArchiveFormat::Lz4
+                | ArchiveFormat::Brotli
+                | ArchiveFormat::Lzip
+                | ArchiveFormat::Lzma
+                | ArchiveFormat::Compress
+        )
+    }
+
+    /// Whether this is a TAR archive, compressed or not.
+    pub(crate) fn is_tar(&self) -> bool {
+        matches!(
+            self,
+            ArchiveFormat::Tar
+                | ArchiveFormat::TarGz
+                | ArchiveFormat::TarXz
+                | ArchiveFormat::TarBz2
+                | ArchiveFormat::TarZst
+                | ArchiveFormat::TarLz4
+                | ArchiveFormat::TarBr
+                | ArchiveFormat::TarLz
+                | ArchiveFormat::TarLzma
+                | ArchiveFormat::TarZ
+        )
+    }
+
+    /// Returns the single-file format using the same compression as this
+    /// compressed TAR format.
+    pub(crate) fn without_tar(self) -> Option<ArchiveFormat> {
+        match self {
+            ArchiveFormat::TarGz => Some(ArchiveFormat::Gzip),
+            ArchiveFormat::TarXz => Some(ArchiveFormat::Xz),
+            ArchiveFormat::TarBz2 => Some(ArchiveFormat::Bzip2),
+            ArchiveFormat::TarZst => Some(ArchiveFormat::Zstd),
+            ArchiveFormat::TarLz4 => Some(ArchiveFormat::Lz4),
+            ArchiveFormat::TarBr => Some(ArchiveFormat::Brotli),
+            ArchiveFormat::TarLz => Some(ArchiveFormat::Lzip),
+            ArchiveFormat::TarLzma => Some(ArchiveFormat::Lzma),
+            ArchiveFormat::TarZ => Some(ArchiveFormat::Compress),
+            _ => None,
         }
     }
 }
 
 /// File signature for ZIP files
 const ZIP_SIGNATURE: &[u8] = &[0x50, 0x4B, 0x03, 0x04];
+/// Markers ahead of the first local header of split ZIP archives: the
+/// spanning signature, and the marker left by writers that set out to
+/// split an archive that then fit in a single part
+const ZIP_SPLIT_MARKERS: &[&[u8]] = &[b"PK\x07\x08", b"PK00"];
 /// File signature for GZIP files
 const GZIP_SIGNATURE: &[u8] = &[0x1F, 0x8B];
 /// File signature for XZ files
@@ -129,10 +340,21 @@ const XZ_SIGNATURE: &[u8] = &[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
 const BZIP2_SIGNATURE: &[u8] = &[0x42, 0x5A, 0x68];
 /// File signature for Zstandard files
 const ZSTD_SIGNATURE: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+/// File signature for lzip files
+const LZIP_SIGNATURE: &[u8] = b"LZIP";
+/// File signatures of LZ4 frames and of legacy LZ4 frames
+const LZ4_SIGNATURE: &[u8] = &lz4::FRAME_MAGIC.to_le_bytes();
+const LZ4_LEGACY_SIGNATURE: &[u8] = &lz4::LEGACY_MAGIC.to_le_bytes();
 /// File signature for TAR files (located at offset 257)
 const TAR_SIGNATURE: &[u8] = &[0x75, 0x73, 0x74, 0x61, 0x72];
+/// Size of a TAR header block
+const TAR_BLOCK_SIZE: usize = 512;
+/// Offset of the checksum field in a TAR header
+const TAR_CHECKSUM_OFFSET: usize = 148;
 /// File signature for 7-Zip files
 const SEVENZIP_SIGNATURE: &[u8] = &[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+/// File signature shared by RAR 1.5-4.x and RAR 5 files (`Rar!\x1A\x07`)
+const RAR_SIGNATURE: &[u8] = &[0x52, 0x61, 0x72, 0x21, 0x1A, 0x07];
 
 /// Detects archive format from the raw bytes of a file.
 ///
@@ -140,6 +362,16 @@ const SEVENZIP_SIGNATURE: &[u8] = &[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
 /// of the file data to determine the archive format. It checks for known
 /// byte patterns that identify different archive formats.
 ///
+/// A compressor signature alone doesn't tell a compressed tarball from a
+/// single compressed file, so the start of the compressed stream is
+/// decompressed and checked for a TAR header. If `data` is too short to
+/// decide, the compressed TAR format is assumed.
+///
+/// Data matching no signature is tried as a Brotli-compressed tarball,
+/// since Brotli streams have no magic number. Formats added with
+/// [`register_format`](crate::register_format) are tried before any
+/// built-in one.
+///
 /// # Arguments
 ///
 /// * `data` - A byte slice containing the beginning of the file
@@ -160,20 +392,66 @@ const SEVENZIP_SIGNATURE: &[u8] = &[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
 /// assert_eq!(format, Some(ArchiveFormat::Zip));
 /// ```
 pub fn detect_from_bytes(data: &[u8]) -> Option<ArchiveFormat> {
-    if data.starts_with(ZIP_SIGNATURE) {
+    if let Some(format) = backend::detect_registered(data) {
+        return Some(format);
+    }
+    let Some(format) = detect_signature(data) else {
+        return is_brotli_tar(data).then_some(ArchiveFormat::TarBr);
+    };
+    match format.without_tar() {
+        Some(single) if sniff_tar(format, data) == Some(false) => Some(single),
+        _ => Some(format),
+    }
+}
+
+/// Detects a format from magic numbers alone, reporting every compressor
+/// signature as the matching compressed TAR format.
+pub(crate) fn detect_signature(data: &[u8]) -> Option<ArchiveFormat> {
+    if data.starts_with(ZIP_SIGNATURE)
+        || ZIP_SPLIT_MARKERS.iter().any(|marker| {
+            data.strip_prefix(*marker)
+                .is_some_and(|rest| rest.starts_with(ZIP_SIGNATURE))
+        })
+    {
         Some(ArchiveFormat::Zip)
     } else if data.starts_with(GZIP_SIGNATURE) {
         Some(ArchiveFormat::TarGz)
     } else if data.starts_with(XZ_SIGNATURE) {
         Some(ArchiveFormat::TarXz)
+    } else if data.starts_with(&lzw::MAGIC) {
+        Some(ArchiveFormat::TarZ)
     } else if data.starts_with(BZIP2_SIGNATURE) {
         Some(ArchiveFormat::TarBz2)
     } else if data.starts_with(ZSTD_SIGNATURE) {
         Some(ArchiveFormat::TarZst)
+    } else if data.starts_with(LZIP_SIGNATURE) {
+        Some(ArchiveFormat::TarLz)
+    } else if data.starts_with(LZ4_SIGNATURE) || data.starts_with(LZ4_LEGACY_SIGNATURE) {
+        Some(ArchiveFormat::TarLz4)
     } else if data.starts_with(SEVENZIP_SIGNATURE) {
         Some(ArchiveFormat::SevenZ)
+    } else if data.starts_with(RAR_SIGNATURE) {
+        Some(ArchiveFormat::Rar)
+    } else if data.starts_with(squashfs::SQUASHFS_MAGIC) {
+        Some(ArchiveFormat::Squashfs)
+    } else if data.starts_with(wim::WIM_MAGIC) {
+        Some(ArchiveFormat::Wim)
+    } else if data.starts_with(xar::XAR_MAGIC) {
+        Some(ArchiveFormat::Xar)
+    } else if data.starts_with(cfb::CFB_MAGIC) {
+        Some(ArchiveFormat::Msi)
+    } else if appimage::is_appimage(data) {
+        Some(ArchiveFormat::AppImage)
+    } else if ar::is_deb(data) {
+        Some(ArchiveFormat::Deb)
+    } else if data.starts_with(ar::AR_MAGIC) {
+        Some(ArchiveFormat::Ar)
     } else if data.len() >= 265 && &data[257..262] == TAR_SIGNATURE {
         Some(ArchiveFormat::Tar)
+    } else if iso::is_iso(data) {
+        Some(ArchiveFormat::Iso)
+    } else if is_lzma_alone(data) {
+        Some(ArchiveFormat::TarLzma)
     } else {
         None
     }
@@ -183,6 +461,8 @@ pub fn detect_from_bytes(data: &[u8]) -> Option<ArchiveFormat> {
 ///
 /// This function examines the file extension to determine the archive format.
 /// It handles common variations of extensions (e.g., both .tar.gz and .tgz).
+/// The `.z01`, `.z02`, ... parts of split ZIP archives are detected as ZIP
+/// and the `.7z.001`, `.7z.002`, ... parts of split 7z archives as 7z.
 ///
 /// # Arguments
 ///
@@ -201,11 +481,15 @@ pub fn detect_from_bytes(data: &[u8]) -> Option<ArchiveFormat> {
 ///
 /// let format = detect_from_extension(Path::new("archive.tar.gz"));
 /// let format = detect_from_extension(Path::new("data.zip"));
+/// assert_eq!(detect_from_extension("notes.txt.br").unwrap(), ArchiveFormat::Brotli);
+/// assert_eq!(detect_from_extension("notes.tar.br").unwrap(), ArchiveFormat::TarBr);
 /// ```
 pub fn detect_from_extension<P: AsRef<Path>>(path: P) -> Result<ArchiveFormat, ArchiveError> {
     let path_str = path.as_ref().to_string_lossy().to_lowercase();
 
-    if path_str.ends_with(".tar.gz") || path_str.ends_with(".tgz") {
+    if let Some(format) = backend::registered_by_extension(&path_str) {
+        Ok(format)
+    } else if path_str.ends_with(".tar.gz") || path_str.ends_with(".tgz") {
         Ok(ArchiveFormat::TarGz)
     } else if path_str.ends_with(".tar.xz") || path_str.ends_with(".txz") {
         Ok(ArchiveFormat::TarXz)
@@ -213,12 +497,65 @@ pub fn detect_from_extension<P: AsRef<Path>>(path: P) -> Result<ArchiveFormat, A
         Ok(ArchiveFormat::TarBz2)
     } else if path_str.ends_with(".tar.zst") {
         Ok(ArchiveFormat::TarZst)
+    } else if path_str.ends_with(".tar.lz4") {
+        Ok(ArchiveFormat::TarLz4)
+    } else if path_str.ends_with(".tar.br") {
+        Ok(ArchiveFormat::TarBr)
+    } else if path_str.ends_with(".tar.lz") {
+        Ok(ArchiveFormat::TarLz)
+    } else if path_str.ends_with(".tar.lzma") || path_str.ends_with(".tlz") {
+        Ok(ArchiveFormat::TarLzma)
+    } else if path_str.ends_with(".tar.z") || path_str.ends_with(".taz") {
+        Ok(ArchiveFormat::TarZ)
     } else if path_str.ends_with(".tar") {
         Ok(ArchiveFormat::Tar)
-    } else if path_str.ends_with(".zip") {
+    } else if path_str.ends_with(".zip")
+        || path_str
+            .rsplit_once('.')
+            .is_some_and(|(_, extension)| volume::is_zip_part(extension))
+    {
         Ok(ArchiveFormat::Zip)
-    } else if path_str.ends_with(".7z") {
+    } else if path_str.ends_with(".7z") || volume::is_sevenz_part(&path_str) {
         Ok(ArchiveFormat::SevenZ)
+    } else if path_str.ends_with(".rar") {
+        Ok(ArchiveFormat::Rar)
+    } else if path_str.ends_with(".gz") {
+        Ok(ArchiveFormat::Gzip)
+    } else if path_str.ends_with(".xz") {
+        Ok(ArchiveFormat::Xz)
+    } else if path_str.ends_with(".bz2") {
+        Ok(ArchiveFormat::Bzip2)
+    } else if path_str.ends_with(".zst") {
+        Ok(ArchiveFormat::Zstd)
+    } else if path_str.ends_with(".lz4") {
+        Ok(ArchiveFormat::Lz4)
+    } else if path_str.ends_with(".br") {
+        Ok(ArchiveFormat::Brotli)
+    } else if path_str.ends_with(".lz") {
+        Ok(ArchiveFormat::Lzip)
+    } else if path_str.ends_with(".lzma") {
+        Ok(ArchiveFormat::Lzma)
+    } else if path_str.ends_with(".z") {
+        Ok(ArchiveFormat::Compress)
+    } else if path_str.ends_with(".deb") {
+        Ok(ArchiveFormat::Deb)
+    } else if path_str.ends_with(".a") || path_str.ends_with(".ar") {
+        Ok(ArchiveFormat::Ar)
+    } else if path_str.ends_with(".iso") {
+        Ok(ArchiveFormat::Iso)
+    } else if path_str.ends_with(".squashfs")
+        || path_str.ends_with(".sqfs")
+        || path_str.ends_with(".snap")
+    {
+        Ok(ArchiveFormat::Squashfs)
+    } else if path_str.ends_with(".wim") {
+        Ok(ArchiveFormat::Wim)
+    } else if path_str.ends_with(".xar") || path_str.ends_with(".pkg") {
+        Ok(ArchiveFormat::Xar)
+    } else if path_str.ends_with(".msi") {
+        Ok(ArchiveFormat::Msi)
+    } else if path_str.ends_with(".appimage") {
+        Ok(ArchiveFormat::AppImage)
     } else {
         Err(ArchiveError::unsupported_static("format"))
     }
@@ -230,6 +567,17 @@ pub fn detect_from_extension<P: AsRef<Path>>(path: P) -> Result<ArchiveFormat, A
 /// by reading the beginning of the file. If that fails, it falls back to
 /// extension-based detection.
 ///
+/// For compressed files, as much of the file is decompressed as needed to
+/// see whether it starts with a TAR header, so compressed tarballs and
+/// single compressed files are told apart regardless of their extension.
+/// Only if the stream cannot be decoded that far does the extension
+/// decide, with a bare `.gz`, `.xz`, `.bz2`, `.zst`, `.lz4`, `.lz`, `.lzma`
+/// or `.Z` selecting the single-file format.
+///
+/// Brotli streams have no magic number, so a file matching no signature is
+/// detected by its extension, a bare `.br` selecting a single Brotli file,
+/// or failing that by decoding its start as a Brotli-compressed tarball.
+///
 /// # Arguments
 ///
 /// * `path` - Path to the file to examine
@@ -254,11 +602,421 @@ pub fn detect_from_extension<P: AsRef<Path>>(path: P) -> Result<ArchiveFormat, A
 /// let format = detect_from_file("archive.bin");
 /// ```
 pub fn detect_from_file<P: AsRef<Path>>(path: P) -> Result<ArchiveFormat, ArchiveError> {
-    let mut file = File::open(&path)?;
+    detect_from_seekable(File::open(&path).at_path(&path)?, Some(path.as_ref()))
+}
+
+/// Detects archive format from a seekable reader, such as an in-memory
+/// buffer or an open file.
+///
+/// The archive is taken to fill `reader` from its start, and its format is
+/// detected as by [`detect_from_file`], only without an extension to fall
+/// back on. As much of `reader` is read as detection needs, after which it
+/// is seeked back to where it was, so it can be handed on to whatever
+/// reads the archive.
+///
+/// # Arguments
+///
+/// * `reader` - Reader over the whole archive
+///
+/// # Returns
+///
+/// * `Ok(ArchiveFormat)` - Format was successfully detected
+/// * `Err(ArchiveError)` - Format could not be determined or the reader
+///   cannot be read
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * `reader` cannot be read or seeked
+/// * No magic number matches
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::format::{detect_from_reader, ArchiveFormat};
+/// use std::io::{Cursor, Seek};
+///
+/// let mut reader = Cursor::new(b"PK\x03\x04\x14\x00\x00\x00".to_vec());
+/// reader.set_position(2);
+/// assert_eq!(detect_from_reader(&mut reader).unwrap(), ArchiveFormat::Zip);
+/// assert_eq!(reader.stream_position().unwrap(), 2);
+/// ```
+pub fn detect_from_reader<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+) -> Result<ArchiveFormat, ArchiveError> {
+    let position = reader.stream_position()?;
+    let format = detect_from_seekable(&mut *reader, None);
+    reader.seek(SeekFrom::Start(position))?;
+    format
+}
+
+/// Detects the format of the archive making up all of `reader`, like
+/// [`detect_from_file`].
+///
+/// The extension of `path`, if given, is consulted the same way.
+///
+/// # Errors
+///
+/// This function will return an error if `reader` cannot be read or
+/// neither magic number nor extension detection succeeds.
+pub(crate) fn detect_from_seekable<R: Read + Seek>(
+    mut reader: R,
+    path: Option<&Path>,
+) -> Result<ArchiveFormat, ArchiveError> {
+    reader.rewind()?;
+    let mut buffer = [0u8; 512];
+    let n = reader.read(&mut buffer)?;
+
+    // The ISO volume descriptor lies 32 KiB in, so it is only looked for
+    // when no signature at the start matched
+    let by_magic =
+        match backend::detect_registered(&buffer[..n]).or_else(|| detect_signature(&buffer[..n])) {
+            Some(format) => Some(format),
+            None => is_iso_file(&mut reader)?.then_some(ArchiveFormat::Iso),
+        };
+    let by_extension = path.and_then(|path| detect_from_extension(path).ok());
+    let Some(magic) = by_magic else {
+        if let Some(format) = by_extension {
+            return Ok(format);
+        }
+        reader.rewind()?;
+        return if is_brotli_tar(BufReader::new(reader)) {
+            Ok(ArchiveFormat::TarBr)
+        } else {
+            Err(ArchiveError::unsupported_static("format"))
+        };
+    };
+    let Some(single) = magic.without_tar() else {
+        return Ok(magic);
+    };
+
+    reader.rewind()?;
+    Ok(match sniff_tar(magic, BufReader::new(reader)) {
+        Some(true) => magic,
+        Some(false) => single,
+        None if by_extension == Some(single) => single,
+        None => magic,
+    })
+}
+
+/// How strongly the evidence points to a detected format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// The format is possible, but the evidence favors another one.
+    Low,
+    /// The format is the likeliest one, but nothing rules out the others.
+    Medium,
+    /// The content of the file confirms the format.
+    High,
+}
+
+/// What a format was detected from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Evidence {
+    /// A magic number at the start of the file, or where the format puts
+    /// it.
+    MagicBytes,
+    /// The decompressed start of the file, which tells compressed tarballs
+    /// from single compressed files and points out Brotli streams.
+    Contents,
+    /// The extension of the file name.
+    Extension,
+}
+
+/// A format a file may be in, as part of a [`DetectionReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The format.
+    pub format: ArchiveFormat,
+    /// How strongly the evidence points to the format.
+    pub confidence: Confidence,
+    /// Everything that points to the format.
+    pub evidence: Vec<Evidence>,
+}
+
+/// Every format a file may plausibly be in, from
+/// [`detect_report_from_file`] or [`detect_report_from_reader`].
+///
+/// A gzip stream may hold a tarball or a single file, and the extension of
+/// a file may disagree with its contents. Where [`detect_from_file`]
+/// settles such cases on its own, this lists each possibility with the
+/// evidence for it, so callers can decide for themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetectionReport {
+    /// The candidates, most likely first. The first is the format
+    /// [`detect_from_file`] would return.
+    pub candidates: Vec<Candidate>,
+}
+
+impl DetectionReport {
+    /// Returns the most likely candidate, or `None` if nothing points to
+    /// any format.
+    pub fn best(&self) -> Option<&Candidate> {
+        self.candidates.first()
+    }
+
+    /// Returns `true` if the content of the file confirms the most likely
+    /// format.
+    pub fn is_conclusive(&self) -> bool {
+        self.best()
+            .is_some_and(|candidate| candidate.confidence == Confidence::High)
+    }
+
+    /// Records `evidence` for `format`, raising its confidence to
+    /// `confidence` if that is higher.
+    fn add(&mut self, format: ArchiveFormat, confidence: Confidence, evidence: Evidence) {
+        match self
+            .candidates
+            .iter_mut()
+            .find(|candidate| candidate.format == format)
+        {
+            Some(candidate) => {
+                candidate.confidence = candidate.confidence.max(confidence);
+                candidate.evidence.push(evidence);
+            }
+            None => {
+                self.candidates.push(Candidate {
+                    format,
+                    confidence,
+                    evidence: vec![evidence],
+                });
+            }
+        }
+    }
+}
+
+/// Lists every format the file at `path` may plausibly be in, with how
+/// confident each is and what it was detected from.
+///
+/// The evidence is gathered as by [`detect_from_file`]: magic numbers,
+/// the decompressed start of compressed files, and the extension. The
+/// report only comes out empty if none of them points to any format.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file to examine
+///
+/// # Returns
+///
+/// * `Ok(DetectionReport)` - The candidates, most likely first
+/// * `Err(ArchiveError)` - The file cannot be read
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened or
+/// read.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::format::{detect_report_from_file, ArchiveFormat};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let report = detect_report_from_file("download.gz")?;
+///     for candidate in &report.candidates {
+///         println!(
+///             "{}: {:?} from {:?}",
+///             candidate.format, candidate.confidence, candidate.evidence
+///         );
+///     }
+///     if !report.is_conclusive() {
+///         println!("could be a tarball or a single file");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn detect_report_from_file<P: AsRef<Path>>(path: P) -> Result<DetectionReport, ArchiveError> {
+    report_from_seekable(File::open(&path).at_path(&path)?, Some(path.as_ref()))
+}
+
+/// Lists every format the archive filling `reader` may plausibly be in,
+/// like [`detect_report_from_file`] without an extension to consult.
+///
+/// `reader` is seeked back to where it was afterwards, as by
+/// [`detect_from_reader`].
+///
+/// # Errors
+///
+/// This function will return an error if `reader` cannot be read or
+/// seeked.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::format::{detect_report_from_reader, ArchiveFormat, Confidence};
+/// use std::io::Cursor;
+///
+/// let mut reader = Cursor::new(b"7z\xBC\xAF\x27\x1C\x00\x04".to_vec());
+/// let report = detect_report_from_reader(&mut reader).unwrap();
+/// let best = report.best().unwrap();
+/// assert_eq!(best.format, ArchiveFormat::SevenZ);
+/// assert_eq!(best.confidence, Confidence::High);
+/// ```
+pub fn detect_report_from_reader<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+) -> Result<DetectionReport, ArchiveError> {
+    let position = reader.stream_position()?;
+    let report = report_from_seekable(&mut *reader, None);
+    reader.seek(SeekFrom::Start(position))?;
+    report
+}
+
+/// Gathers the evidence on the format of the archive making up all of
+/// `reader`, weighing it as [`detect_from_seekable`] does.
+fn report_from_seekable<R: Read + Seek>(
+    mut reader: R,
+    path: Option<&Path>,
+) -> Result<DetectionReport, ArchiveError> {
+    reader.rewind()?;
     let mut buffer = [0u8; 512];
-    let n = file.read(&mut buffer)?;
+    let n = reader.read(&mut buffer)?;
+
+    let by_magic =
+        match backend::detect_registered(&buffer[..n]).or_else(|| detect_signature(&buffer[..n])) {
+            Some(format) => Some(format),
+            None => is_iso_file(&mut reader)?.then_some(ArchiveFormat::Iso),
+        };
+    let by_extension = path.and_then(|path| detect_from_extension(path).ok());
+    let mut report = DetectionReport::default();
+    let Some(magic) = by_magic else {
+        // Without a signature, the extension is all there is to go on
+        if let Some(format) = by_extension {
+            report.add(format, Confidence::Medium, Evidence::Extension);
+        }
+        reader.rewind()?;
+        if is_brotli_tar(BufReader::new(reader)) {
+            report.add(ArchiveFormat::TarBr, Confidence::Medium, Evidence::Contents);
+        }
+        return Ok(report);
+    };
+
+    match magic.without_tar() {
+        None => report.add(magic, Confidence::High, Evidence::MagicBytes),
+        Some(single) => {
+            reader.rewind()?;
+            match sniff_tar(magic, BufReader::new(reader)) {
+                Some(true) => {
+                    report.add(magic, Confidence::High, Evidence::MagicBytes);
+                    report.add(magic, Confidence::High, Evidence::Contents);
+                    // A compressed tarball is a single compressed file all
+                    // the same
+                    report.add(single, Confidence::Low, Evidence::MagicBytes);
+                }
+                Some(false) => {
+                    report.add(single, Confidence::High, Evidence::MagicBytes);
+                    report.add(single, Confidence::High, Evidence::Contents);
+                }
+                None => {
+                    let (tar, other) = if by_extension == Some(single) {
+                        (Confidence::Low, Confidence::Medium)
+                    } else {
+                        (Confidence::Medium, Confidence::Low)
+                    };
+                    report.add(magic, tar, Evidence::MagicBytes);
+                    report.add(single, other, Evidence::MagicBytes);
+                }
+            }
+        }
+    }
+    if let Some(format) = by_extension {
+        report.add(format, Confidence::Low, Evidence::Extension);
+    }
+    report
+        .candidates
+        .sort_by_key(|candidate| std::cmp::Reverse(candidate.confidence));
+    Ok(report)
+}
+
+/// Checks for a plausible LZMA-alone header, which has no magic number: a
+/// valid properties byte, a dictionary size of 2^n or 2^n + 2^(n-1) bytes
+/// as written by `lzma` and `xz --format=lzma`, and an uncompressed size
+/// that is either unknown or below 256 GiB.
+fn is_lzma_alone(data: &[u8]) -> bool {
+    let Some(header) = data.get(..13) else {
+        return false;
+    };
+    // (pb * 5 + lp) * 9 + lc, with lc <= 8 and lp, pb <= 4
+    if header[0] > 224 {
+        return false;
+    }
+    let dict_size = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    let n = 31 - dict_size.max(1).leading_zeros();
+    let standard = dict_size == 1 << n || (n > 0 && dict_size == (1 << n) + (1 << (n - 1)));
+    let size = u64::from_le_bytes(header[5..13].try_into().unwrap_or_default());
+    (12..=30).contains(&n) && standard && (size == u64::MAX || size < 1 << 38)
+}
+
+/// Checks for the ISO 9660 volume descriptor, which lies past the 32 KiB
+/// system area rather than at the start of the file.
+fn is_iso_file<R: Read + Seek>(file: &mut R) -> io::Result<bool> {
+    let mut data = [0u8; iso::STANDARD_ID.len()];
+    file.seek(SeekFrom::Start(iso::ISO_MAGIC_OFFSET))?;
+    match file.read_exact(&mut data) {
+        Ok(()) => Ok(data == iso::STANDARD_ID),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decompresses the start of a stream compressed as `format` and checks
+/// whether it begins with a TAR header.
+///
+/// Returns `None` if the stream ends or fails to decode before a full
+/// header block is available; a complete stream shorter than one block
+/// cannot be a tarball.
+fn sniff_tar<R: Read>(format: ArchiveFormat, reader: R) -> Option<bool> {
+    let mut decoder = entry::decoder(format, reader).ok()?;
+    let mut block = [0u8; TAR_BLOCK_SIZE];
+    let mut filled = 0;
+    while filled < block.len() {
+        match decoder.read(&mut block[filled..]) {
+            Ok(0) => return Some(false),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return None,
+        }
+    }
+    Some(is_tar_header(&block))
+}
+
+/// Trial-decodes a stream without a known signature as Brotli, which has
+/// no magic number of its own.
+///
+/// Only a non-empty TAR header counts, since short runs of arbitrary data
+/// can happen to decode as Brotli.
+fn is_brotli_tar<R: Read>(reader: R) -> bool {
+    let mut block = [0u8; TAR_BLOCK_SIZE];
+    brotli::Decoder::new(reader).read_exact(&mut block).is_ok()
+        && block.iter().any(|&b| b != 0)
+        && is_tar_header(&block)
+}
+
+/// Returns `true` if `block` is a TAR header, either carrying the POSIX
+/// `ustar` magic or, for old V7 archives, a valid header checksum. An
+/// all-zero block is the end-of-archive marker of an empty tarball.
+fn is_tar_header(block: &[u8; TAR_BLOCK_SIZE]) -> bool {
+    if &block[257..262] == TAR_SIGNATURE || block.iter().all(|&b| b == 0) {
+        return true;
+    }
 
-    detect_from_bytes(&buffer[..n])
-        .or_else(|| detect_from_extension(path.as_ref()).ok())
-        .ok_or(ArchiveError::unsupported_static("format"))
+    let field = &block[TAR_CHECKSUM_OFFSET..TAR_CHECKSUM_OFFSET + 8];
+    let digits = std::str::from_utf8(field)
+        .unwrap_or_default()
+        .trim_matches(|c: char| c == ' ' || c == '\0');
+    let Ok(expected) = u32::from_str_radix(digits, 8) else {
+        return false;
+    };
+    // The checksum is computed with its own field filled with spaces
+    let actual: u32 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (TAR_CHECKSUM_OFFSET..TAR_CHECKSUM_OFFSET + 8).contains(&i) {
+                u32::from(b' ')
+            } else {
+                u32::from(b)
+            }
+        })
+        .sum();
+    actual == expected
 }
@@ -0,0 +1,180 @@
+//! Minimal glob matching for archive entry names.
+//!
+//! Supported syntax:
+//!
+//! * `?` matches any single character except `/`
+//! * `*` matches any run of characters except `/`
+//! * `**` matches any run of characters, including `/`; `**/` also matches
+//!   zero directories, so `a/**/b` matches `a/b`
+//! * `[abc]`, `[a-z]` and `[!a-z]` match one character from (or not from) a set
+//! * `\` escapes the next character
+//!
+//! A pattern without a `/` is matched against the last path component only,
+//! so `*.so` matches `lib/libfoo.so`. Any other pattern must match the whole
+//! normalized entry name.
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+    basename_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    Star,
+    /// `**` not followed by `/`
+    DoubleStar,
+    /// `**/`
+    AnyDirs,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    /// Compiles `pattern`. Malformed sets such as an unclosed `[` are
+    /// treated as literal text.
+    pub(crate) fn new(pattern: &str) -> Self {
+        let pattern = pattern.trim_start_matches("./").trim_start_matches('/');
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Literal(chars[i + 1]));
+                    i += 2;
+                }
+                '?' => {
+                    tokens.push(Token::AnyChar);
+                    i += 1;
+                }
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    if chars.get(i + 2) == Some(&'/') {
+                        tokens.push(Token::AnyDirs);
+                        i += 3;
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                        i += 2;
+                    }
+                }
+                '*' => {
+                    tokens.push(Token::Star);
+                    i += 1;
+                }
+                '[' => {
+                    match parse_class(&chars[i + 1..]) {
+                        Some((token, consumed)) => {
+                            tokens.push(token);
+                            i += consumed + 1;
+                        }
+                        None => {
+                            tokens.push(Token::Literal('['));
+                            i += 1;
+                        }
+                    }
+                }
+                c => {
+                    tokens.push(Token::Literal(c));
+                    i += 1;
+                }
+            }
+        }
+
+        Self {
+            tokens,
+            basename_only: !pattern.contains('/'),
+        }
+    }
+
+    /// Returns `true` if the normalized entry name `name` matches.
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        let name = if self.basename_only {
+            name.rsplit('/').next().unwrap_or(name)
+        } else {
+            name
+        };
+        let text: Vec<char> = name.chars().collect();
+        match_tokens(&self.tokens, &text)
+    }
+}
+
+/// Parses the body of a `[...]` set, returning the token and the number of
+/// characters consumed including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    // A `]` right after the opening bracket is a literal member
+    let mut first = true;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && !first {
+            return Some((
+                Token::Class {
+                    negated,
+                    ranges,
+                },
+                i + 1,
+            ));
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+
+    match token {
+        Token::Literal(c) => text.first() == Some(c) && match_tokens(rest, &text[1..]),
+        Token::AnyChar => text.first().is_some_and(|&c| c != '/') && match_tokens(rest, &text[1..]),
+        Token::Class {
+            negated,
+            ranges,
+        } => {
+            text.first().is_some_and(|&c| {
+                c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }) && match_tokens(rest, &text[1..])
+        }
+        Token::Star => {
+            for i in 0..=text.len() {
+                if match_tokens(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Token::DoubleStar => (0..=text.len()).any(|i| match_tokens(rest, &text[i..])),
+        Token::AnyDirs => {
+            match_tokens(rest, text)
+                || text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &c)| c == '/' && match_tokens(rest, &text[i + 1..]))
+        }
+    }
+}
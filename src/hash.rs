@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    blake3,
+    entry::{self, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    volume::Location,
+};
+
+/// A SHA-256 digest identifying the logical contents of an archive.
+///
+/// Two archives produce the same hash when they contain the same set of
+/// paths with the same entry types, file contents and link targets,
+/// regardless of container format, compression, entry order, timestamps,
+/// ownership or permission bits. A hard link counts as a copy of the file
+/// it names, so a TAR archive linking two paths to one file hashes the same
+/// as a ZIP archive holding the file twice.
+///
+/// The `Display` implementation renders the digest as lowercase hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Returns the raw digest bytes.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A normalized entry record fed into the final digest.
+struct Record {
+    name: String,
+    kind: EntryKind,
+    digest: [u8; 32],
+}
+
+/// Computes the canonical content hash of an archive.
+///
+/// Every entry is hashed individually, then the resulting records are sorted
+/// by normalized name and folded into a single digest. Names are normalized
+/// by stripping leading `./` and `/` and trailing `/`. Directory entries that
+/// are implied by a deeper entry, as well as the archive root itself, are
+/// dropped, so archives that list parent directories explicitly hash the
+/// same as those that don't. Hard links whose target, normalized the same
+/// way, was hashed before take the target's digest and are recorded as
+/// files.
+///
+/// # Arguments
+///
+/// * `location` - Where the archive is read from
+/// * `format` - The archive format to decode
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be read or decoded.
+pub(crate) fn content_hash(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<ContentHash, ArchiveError> {
+    let mut records = Vec::new();
+    // Digests of the files seen so far, by name, for hard links to resolve
+    let mut files = HashMap::new();
+
+    entry::for_each_entry(location, format, |info, reader| {
+        let name = entry::normalize_name(&info.name);
+        let target = info.link_target.as_deref().unwrap_or_default();
+        let (kind, digest) = match info.kind {
+            EntryKind::Hardlink => {
+                let target = entry::normalize_name(target);
+                match files.get(&target) {
+                    Some(&digest) => (EntryKind::File, digest),
+                    None => (EntryKind::Hardlink, Sha256::digest(target).into()),
+                }
+            }
+            EntryKind::Symlink => (EntryKind::Symlink, Sha256::digest(target).into()),
+            kind => {
+                let mut hasher = Sha256::new();
+                io::copy(reader, &mut hasher)?;
+                (kind, hasher.finalize().into())
+            }
+        };
+        if kind == EntryKind::File {
+            files.insert(name.clone(), digest);
+        }
+        records.push(Record {
+            name,
+            kind,
+            digest,
+        });
+        Ok(true)
+    })?;
+
+    records.sort_by(|a, b| (&a.name, a.kind, a.digest).cmp(&(&b.name, b.kind, b.digest)));
+
+    let mut hasher = Sha256::new();
+    for (i, record) in records.iter().enumerate() {
+        if record.kind == EntryKind::Directory
+            && (record.name.is_empty() || is_implied_dir(&records[i + 1..], &record.name))
+        {
+            continue;
+        }
+        hasher.update((record.name.len() as u64).to_le_bytes());
+        hasher.update(record.name.as_bytes());
+        hasher.update([record.kind as u8]);
+        hasher.update(record.digest);
+    }
+
+    Ok(ContentHash(hasher.finalize().into()))
+}
+
+/// Returns `true` if any of the following (sorted) records lives below `dir`.
+fn is_implied_dir(following: &[Record], dir: &str) -> bool {
+    following
+        .iter()
+        .take_while(|r| r.name.starts_with(dir))
+        .any(|r| r.name.len() > dir.len() && r.name.as_bytes()[dir.len()] == b'/')
+}
+
+/// A hash function for [`Archive::checksums`](crate::Archive::checksums).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgo {
+    /// SHA-256, as computed by `sha256sum`.
+    Sha256,
+    /// BLAKE3 with 32-byte output, as computed by `b3sum`.
+    Blake3,
+}
+
+/// The digest of one file in a [`ChecksumManifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryDigest {
+    /// Name of the file, normalized like in [`ContentHash`].
+    pub name: String,
+    /// Digest of the file contents.
+    pub digest: [u8; 32],
+}
+
+impl EntryDigest {
+    /// Returns the digest as lowercase hex.
+    pub fn to_hex(&self) -> String {
+        self.digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Digests of every file in an archive.
+///
+/// The `Display` implementation renders one `<digest>  <name>` line per
+/// file, the format `sha256sum -c` and `b3sum -c` check files against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumManifest {
+    /// The hash function the digests were computed with.
+    pub algorithm: HashAlgo,
+    /// One digest per file, in archive order.
+    pub entries: Vec<EntryDigest>,
+}
+
+impl ChecksumManifest {
+    /// Writes the manifest to `writer` in the format of its `Display`
+    /// implementation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if writing fails.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+}
+
+impl Display for ChecksumManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}  {}", entry.to_hex(), entry.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// A running digest of either algorithm.
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgo) -> Self {
+        match algorithm {
+            HashAlgo::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgo::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().into(),
+            Self::Blake3(hasher) => hasher.finalize(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Sha256(hasher) => Digest::update(hasher, buf),
+            Self::Blake3(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hashes the contents of every file in an archive with `algorithm`.
+///
+/// Entries are streamed through the hasher one at a time, so nothing is
+/// held in memory or written to disk. Directories and links have no
+/// contents of their own and are left out.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be read or decoded.
+pub(crate) fn checksums(
+    location: &Location,
+    format: ArchiveFormat,
+    algorithm: HashAlgo,
+) -> Result<ChecksumManifest, ArchiveError> {
+    let mut entries = Vec::new();
+    entry::for_each_entry(location, format, |info, reader| {
+        if info.kind == EntryKind::File {
+            let mut hasher = Hasher::new(algorithm);
+            io::copy(reader, &mut hasher)?;
+            entries.push(EntryDigest {
+                name: entry::normalize_name(&info.name),
+                digest: hasher.finalize(),
+            });
+        }
+        Ok(true)
+    })?;
+    Ok(ChecksumManifest {
+        algorithm,
+        entries,
+    })
+}
@@ -0,0 +1,184 @@
+//! Best-effort kernel I/O hints.
+//!
+//! Extraction reads archives front to back and writes each output file
+//! exactly once, which is the worst case for a page cache tuned for random
+//! access. These helpers tell the OS about that pattern: archive files are
+//! opened for sequential readahead, and large written files are flushed and
+//! evicted so multi-GB extractions don't push everything else out of memory.
+//!
+//! Every hint is advisory. Failures are ignored, and platforms without an
+//! equivalent API simply get a plain `File`.
+
+use std::{fs::File, io, path::Path};
+
+/// Opens `path` for reading with sequential-access hints applied.
+///
+/// On Linux, Android and the BSDs this issues
+/// `posix_fadvise(POSIX_FADV_SEQUENTIAL)`, which widens readahead without
+/// reading anything up front. On macOS it enables `F_RDAHEAD`. On Windows
+/// the file is opened with `FILE_FLAG_SEQUENTIAL_SCAN`.
+///
+/// # Errors
+///
+/// Returns an error only if the file itself cannot be opened.
+pub(crate) fn open_sequential(path: &Path) -> io::Result<File> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        /// Win32 `FILE_FLAG_SEQUENTIAL_SCAN`.
+        const FILE_FLAG_SEQUENTIAL_SCAN: u32 = 0x0800_0000;
+
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(FILE_FLAG_SEQUENTIAL_SCAN)
+            .open(path)
+    }
+
+    #[cfg(not(windows))]
+    {
+        let file = File::open(path)?;
+        advise_sequential(&file);
+        Ok(file)
+    }
+}
+
+/// Files smaller than this are left in the page cache, since flushing them
+/// one by one would cost more than the memory they take.
+const DROP_BEHIND_MIN_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Evicts large written files from the page cache, one file behind.
+///
+/// Dirty pages cannot be dropped, so on Linux writeback of each file is
+/// started as soon as it is written, and the file is only evicted once the
+/// next large one is done, or the tracker is dropped, by which time its
+/// writeback has had a head start. Elsewhere only `POSIX_FADV_DONTNEED` is
+/// issued at that point, which evicts whatever has been written back.
+#[derive(Debug, Default)]
+pub(crate) struct DropBehind {
+    /// The last large file written, still to be evicted.
+    pending: Option<File>,
+}
+
+impl DropBehind {
+    /// Notes that `file`, `size` bytes long, has been written.
+    pub(crate) fn written(&mut self, file: &File, size: u64) {
+        if size < DROP_BEHIND_MIN_SIZE {
+            return;
+        }
+        let Ok(file) = file.try_clone() else {
+            return;
+        };
+        start_writeback(&file);
+        if let Some(previous) = self.pending.replace(file) {
+            evict(&previous);
+        }
+    }
+}
+
+impl Drop for DropBehind {
+    fn drop(&mut self) {
+        if let Some(file) = self.pending.take() {
+            evict(&file);
+        }
+    }
+}
+
+/// Starts writing back the dirty pages of `file` without waiting for it.
+fn start_writeback(file: &File) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor is valid for the duration of the call.
+        unsafe {
+            libc::sync_file_range(file.as_raw_fd(), 0, 0, libc::SYNC_FILE_RANGE_WRITE);
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = file;
+}
+
+/// Marks the pages of `file` as no longer needed, waiting for their
+/// writeback to finish first where the platform allows.
+fn evict(file: &File) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        // SAFETY: `fd` is a valid open descriptor owned by `file` for the
+        // duration of both calls.
+        unsafe {
+            libc::sync_file_range(
+                fd,
+                0,
+                0,
+                libc::SYNC_FILE_RANGE_WAIT_BEFORE
+                    | libc::SYNC_FILE_RANGE_WRITE
+                    | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+            );
+            libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd", target_os = "dragonfly"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor is valid for the duration of the call.
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    let _ = file;
+}
+
+#[cfg(not(windows))]
+fn advise_sequential(file: &File) {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor is valid for the duration of the call.
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor is valid for the duration of the call.
+        unsafe {
+            libc::fcntl(file.as_raw_fd(), libc::F_RDAHEAD, 1);
+        }
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "dragonfly",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    let _ = file;
+}
@@ -0,0 +1,102 @@
+//! Canonical Huffman codes shared by the XPRESS and LZX decoders.
+
+use std::io;
+
+/// A canonical Huffman code read most significant bit first.
+pub(crate) struct HuffmanCode {
+    /// Symbol and codeword length for every `TABLE_BITS`-bit prefix that
+    /// starts a codeword at most that long; longer codewords have length 0.
+    table: Vec<(u16, u8)>,
+    /// First codeword of each length, the position of its symbol in
+    /// `symbols`, and the number of codewords of that length.
+    first: [u32; MAX_CODEWORD_LEN + 1],
+    start: [u16; MAX_CODEWORD_LEN + 1],
+    count: [u16; MAX_CODEWORD_LEN + 1],
+    /// Symbols ordered by codeword.
+    symbols: Vec<u16>,
+}
+
+/// Longest codeword the formats using this allow.
+const MAX_CODEWORD_LEN: usize = 16;
+/// Codeword bits resolved with a single table lookup.
+const TABLE_BITS: u32 = 10;
+
+impl HuffmanCode {
+    /// Builds the code assigning each symbol its codeword length in
+    /// `lengths`, where 0 leaves the symbol unused. Incomplete codes are
+    /// accepted, as encoders emit them when few symbols occur.
+    pub(crate) fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut count = [0u16; MAX_CODEWORD_LEN + 1];
+        for &len in lengths {
+            if usize::from(len) > MAX_CODEWORD_LEN {
+                return Err(invalid("Huffman codeword is too long"));
+            }
+            count[usize::from(len)] += 1;
+        }
+        count[0] = 0;
+        let mut available = 1i32;
+        for &n in &count[1..] {
+            available = available * 2 - i32::from(n);
+            if available < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut first = [0u32; MAX_CODEWORD_LEN + 1];
+        let mut start = [0u16; MAX_CODEWORD_LEN + 1];
+        let mut code = 0;
+        let mut position = 0;
+        for len in 1..=MAX_CODEWORD_LEN {
+            first[len] = code;
+            start[len] = position;
+            code = (code + u32::from(count[len])) << 1;
+            position += count[len];
+        }
+        let mut symbols = vec![0u16; usize::from(position)];
+        let mut next = start;
+        let mut table = vec![(0, 0); 1 << TABLE_BITS];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            let len = usize::from(len);
+            if len == 0 {
+                continue;
+            }
+            let rank = next[len];
+            next[len] += 1;
+            symbols[usize::from(rank)] = symbol as u16;
+            if len as u32 <= TABLE_BITS {
+                let codeword = first[len] + u32::from(rank - start[len]);
+                let shift = TABLE_BITS - len as u32;
+                let range = (codeword << shift) as usize..((codeword + 1) << shift) as usize;
+                table[range].fill((symbol as u16, len as u8));
+            }
+        }
+        Ok(Self {
+            table,
+            first,
+            start,
+            count,
+            symbols,
+        })
+    }
+
+    /// Decodes the symbol whose codeword starts `bits`, the next 16 bits
+    /// of input, returning it with the codeword length.
+    pub(crate) fn decode(&self, bits: u32) -> io::Result<(u16, u32)> {
+        let (symbol, len) = self.table[(bits >> (16 - TABLE_BITS)) as usize];
+        if len > 0 {
+            return Ok((symbol, u32::from(len)));
+        }
+        for len in TABLE_BITS as usize + 1..=MAX_CODEWORD_LEN {
+            let rank = (bits >> (16 - len)).wrapping_sub(self.first[len]);
+            if rank < u32::from(self.count[len]) {
+                let symbol = self.symbols[usize::from(self.start[len]) + rank as usize];
+                return Ok((symbol, len as u32));
+            }
+        }
+        Err(invalid("invalid Huffman codeword"))
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,548 @@
+//! ISO 9660 CD/DVD images.
+//!
+//! Images are read through their primary volume descriptor. Rock Ridge
+//! extensions, when present, supply long names, unix modes, timestamps and
+//! symlinks; otherwise a Joliet supplementary volume is used for its
+//! Unicode names, and failing that the plain ISO 9660 names have their
+//! `;1` version suffix stripped. UDF-only images (without an ISO 9660
+//! bridge) are not supported.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek, SeekFrom},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+};
+
+/// Size of a logical sector.
+const SECTOR_SIZE: u64 = 2048;
+/// Sector holding the first volume descriptor.
+const FIRST_DESCRIPTOR: u64 = 16;
+/// Identifier following the type byte of every volume descriptor.
+pub(crate) const STANDARD_ID: &[u8] = b"CD001";
+/// Offset of the first volume descriptor's identifier, used for detection.
+pub(crate) const ISO_MAGIC_OFFSET: u64 = FIRST_DESCRIPTOR * SECTOR_SIZE + 1;
+/// Upper bound on the number of volume descriptors scanned.
+const MAX_DESCRIPTORS: u64 = 64;
+/// Upper bound on directory nesting, which also guards against loops.
+const MAX_DEPTH: usize = 256;
+/// Upper bound on the size of a single directory extent.
+const MAX_DIRECTORY_SIZE: u32 = 64 * 1024 * 1024;
+/// Upper bound on the number of Rock Ridge continuation areas per record.
+const MAX_CONTINUATIONS: usize = 16;
+
+/// Directory record flag: the entry is a directory.
+const FLAG_DIRECTORY: u8 = 0x02;
+/// Directory record flag: the file continues in the next record.
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// `S_IFMT` and `S_IFLNK` of a Rock Ridge `PX` mode.
+const UNIX_TYPE_MASK: u32 = 0o170000;
+const UNIX_SYMLINK: u32 = 0o120000;
+
+/// Returns `true` if `data` is long enough to contain the first volume
+/// descriptor and carries its `CD001` identifier.
+pub(crate) fn is_iso(data: &[u8]) -> bool {
+    let offset = ISO_MAGIC_OFFSET as usize;
+    data.get(offset..offset + STANDARD_ID.len()) == Some(STANDARD_ID)
+}
+
+/// Names used for the directory tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Naming {
+    RockRidge,
+    Joliet,
+    Plain,
+}
+
+/// A parsed directory record.
+struct Record {
+    name: String,
+    extent: u32,
+    size: u32,
+    flags: u8,
+    mtime: Option<SystemTime>,
+    mode: Option<u32>,
+    link_target: Option<String>,
+    /// Set by Rock Ridge `RE` on directories moved away to limit nesting.
+    relocated: bool,
+    /// Set by Rock Ridge `CL` on placeholders for relocated directories.
+    child_link: Option<u32>,
+}
+
+impl Record {
+    fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0 || self.child_link.is_some()
+    }
+}
+
+/// Walks every entry of an ISO 9660 image, parents before their children.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(mut reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut primary = None;
+    let mut joliet = None;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        let descriptor = read_at(&mut reader, sector * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        if &descriptor[1..6] != STANDARD_ID {
+            return Err(invalid("malformed volume descriptor"));
+        }
+        match descriptor[0] {
+            1 if primary.is_none() => primary = Some(root_record(&descriptor)),
+            // Joliet volumes are tagged with a UCS-2 escape sequence
+            2 if joliet.is_none()
+                && descriptor[88..90] == *b"%/"
+                && matches!(descriptor[90], b'@' | b'C' | b'E') =>
+            {
+                joliet = Some(root_record(&descriptor));
+            }
+            255 => break,
+            _ => {}
+        }
+    }
+    let (extent, size) = primary.ok_or_else(|| invalid("missing primary volume descriptor"))?;
+
+    let root = read_directory(&mut reader, extent, size)?;
+    // Rock Ridge is announced by an `SP` entry in the root's `.` record
+    let rock_ridge = root
+        .first()
+        .and_then(|raw| system_use(raw))
+        .is_some_and(|area| area.starts_with(b"SP"));
+    let (naming, extent, size) = match joliet {
+        Some((extent, size)) if !rock_ridge => (Naming::Joliet, extent, size),
+        _ if rock_ridge => (Naming::RockRidge, extent, size),
+        _ => (Naming::Plain, extent, size),
+    };
+
+    let mut walker = Walker {
+        reader: &mut reader,
+        naming,
+        visited: HashSet::new(),
+    };
+    walker.directory("", extent, size, 0, &mut visit)?;
+    Ok(())
+}
+
+struct Walker<'a, R> {
+    reader: &'a mut R,
+    naming: Naming,
+    visited: HashSet<u32>,
+}
+
+impl<R: Read + Seek> Walker<'_, R> {
+    /// Visits the contents of a directory, returning `false` once the
+    /// callback asked to stop.
+    fn directory<F>(
+        &mut self,
+        prefix: &str,
+        extent: u32,
+        size: u32,
+        depth: usize,
+        visit: &mut F,
+    ) -> Result<bool, ArchiveError>
+    where
+        F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    {
+        if depth > MAX_DEPTH || !self.visited.insert(extent) {
+            return Err(invalid("directory hierarchy is too deep or loops"));
+        }
+
+        let mut records = Vec::new();
+        for raw in read_directory(self.reader, extent, size)? {
+            match raw[33..33 + raw[32] as usize] {
+                // `.` and `..`
+                [0] | [1] => {}
+                _ => records.push(self.record(&raw)?),
+            }
+        }
+
+        let mut records = records.into_iter();
+        while let Some(record) = records.next() {
+            // Relocated directories are reached through their `CL`
+            // placeholders, which leaves the directory holding them empty
+            if record.relocated
+                || (self.naming == Naming::RockRidge
+                    && prefix.is_empty()
+                    && record.is_dir()
+                    && matches!(record.name.as_str(), "rr_moved" | ".rr_moved"))
+            {
+                continue;
+            }
+            let name = if prefix.is_empty() {
+                record.name.clone()
+            } else {
+                format!("{}/{}", prefix, record.name)
+            };
+
+            // Files over 4 GiB are split across consecutive records
+            let mut extents = vec![(record.extent, record.size)];
+            let mut flags = record.flags;
+            while flags & FLAG_MULTI_EXTENT != 0 {
+                let Some(next) = records.next() else {
+                    break;
+                };
+                extents.push((next.extent, next.size));
+                flags = next.flags;
+            }
+
+            let kind = if record.is_dir() {
+                EntryKind::Directory
+            } else if record.link_target.is_some() {
+                EntryKind::Symlink
+            } else {
+                EntryKind::File
+            };
+            let info = EntryInfo {
+                name,
+                kind,
+                size: match kind {
+                    EntryKind::File => extents.iter().map(|&(_, size)| u64::from(size)).sum(),
+                    _ => 0,
+                },
+                compressed_size: None,
+                mtime: record.mtime,
+                mode: record.mode.map(|m| m & 0o7777),
+                link_target: record.link_target.clone(),
+                owner: None,
+                xattrs: Vec::new(),
+                raw_name: None,
+                special: None,
+                comment: None,
+                extra_fields: Vec::new(),
+                encrypted: false,
+            };
+
+            let keep_going = if kind == EntryKind::File {
+                let mut data = ExtentReader {
+                    reader: self.reader,
+                    extents: extents.into_iter(),
+                    remaining: 0,
+                };
+                visit(&info, &mut data)?
+            } else {
+                visit(&info, &mut io::empty())?
+            };
+            if !keep_going {
+                return Ok(false);
+            }
+
+            if kind == EntryKind::Directory {
+                let (extent, size) = match record.child_link {
+                    Some(extent) => {
+                        // The relocated directory's own `.` record knows its size
+                        let dot = read_at(self.reader, u64::from(extent) * SECTOR_SIZE, 34)?;
+                        (extent, le32(&dot[10..14]))
+                    }
+                    None => (record.extent, record.size),
+                };
+                if !self.directory(&info.name, extent, size, depth + 1, visit)? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decodes a raw directory record, applying the image's naming scheme.
+    fn record(&mut self, raw: &[u8]) -> Result<Record, ArchiveError> {
+        let identifier = &raw[33..33 + raw[32] as usize];
+        let mut record = Record {
+            name: match self.naming {
+                Naming::Joliet => {
+                    let units: Vec<u16> = identifier
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect();
+                    plain_name(&String::from_utf16_lossy(&units))
+                }
+                _ => plain_name(&String::from_utf8_lossy(identifier)),
+            },
+            extent: le32(&raw[2..6]),
+            size: le32(&raw[10..14]),
+            flags: raw[25],
+            mtime: short_time(&raw[18..25]),
+            mode: None,
+            link_target: None,
+            relocated: false,
+            child_link: None,
+        };
+        if self.naming == Naming::RockRidge
+            && let Some(area) = system_use(raw)
+        {
+            self.rock_ridge(area.to_vec(), &mut record)?;
+        }
+        if record.name.is_empty() || record.name == "." || record.name == ".." {
+            return Err(invalid("entry with an empty name"));
+        }
+        Ok(record)
+    }
+
+    /// Applies the Rock Ridge entries of a System Use area to `record`.
+    fn rock_ridge(&mut self, mut area: Vec<u8>, record: &mut Record) -> Result<(), ArchiveError> {
+        let mut name: Option<String> = None;
+        let mut link: Option<String> = None;
+        let mut link_continues = false;
+        let mut mode = None;
+
+        for _ in 0..=MAX_CONTINUATIONS {
+            let mut continuation = None;
+            let mut rest = area.as_slice();
+            while rest.len() >= 4 {
+                let len = rest[2] as usize;
+                if len < 4 || len > rest.len() {
+                    break;
+                }
+                let (entry, tail) = rest.split_at(len);
+                rest = tail;
+                let data = &entry[4..];
+                match &entry[..2] {
+                    b"NM" if !data.is_empty() && data[0] & 0x06 == 0 => {
+                        name.get_or_insert_with(String::new)
+                            .push_str(&String::from_utf8_lossy(&data[1..]));
+                    }
+                    b"PX" if data.len() >= 8 => mode = Some(le32(&data[..4])),
+                    b"SL" if !data.is_empty() => {
+                        let target = link.get_or_insert_with(String::new);
+                        symlink_components(&data[1..], target, &mut link_continues);
+                    }
+                    b"TF" if !data.is_empty() => {
+                        if let Some(mtime) = modify_time(data) {
+                            record.mtime = Some(mtime);
+                        }
+                    }
+                    b"CE" if data.len() >= 24 => {
+                        continuation =
+                            Some((le32(&data[..4]), le32(&data[8..12]), le32(&data[16..20])));
+                    }
+                    b"RE" => record.relocated = true,
+                    b"CL" if data.len() >= 8 => record.child_link = Some(le32(&data[..4])),
+                    b"ST" => break,
+                    _ => {}
+                }
+            }
+
+            let Some((block, offset, len)) = continuation else {
+                break;
+            };
+            area = read_at(
+                self.reader,
+                u64::from(block) * SECTOR_SIZE + u64::from(offset),
+                len.min(SECTOR_SIZE as u32) as usize,
+            )?;
+        }
+
+        if let Some(name) = name {
+            record.name = name;
+        }
+        if let Some(m) = mode {
+            record.mode = Some(m);
+            if m & UNIX_TYPE_MASK == UNIX_SYMLINK {
+                record.link_target = link;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the file data scattered over one or more extents.
+struct ExtentReader<'a, R> {
+    reader: &'a mut R,
+    extents: std::vec::IntoIter<(u32, u32)>,
+    remaining: u64,
+}
+
+impl<R: Read + Seek> Read for ExtentReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            let Some((extent, size)) = self.extents.next() else {
+                return Ok(0);
+            };
+            self.reader
+                .seek(SeekFrom::Start(u64::from(extent) * SECTOR_SIZE))?;
+            self.remaining = u64::from(size);
+        }
+        let max = self.remaining.min(buf.len() as u64) as usize;
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Returns the extent and size of a volume descriptor's root directory.
+fn root_record(descriptor: &[u8]) -> (u32, u32) {
+    let root = &descriptor[156..190];
+    (le32(&root[2..6]), le32(&root[10..14]))
+}
+
+/// Reads the raw records of a directory.
+///
+/// Records never cross sector boundaries; a zero length byte marks the
+/// padding at the end of a sector.
+fn read_directory<R: Read + Seek>(
+    reader: &mut R,
+    extent: u32,
+    size: u32,
+) -> Result<Vec<Vec<u8>>, ArchiveError> {
+    if size > MAX_DIRECTORY_SIZE {
+        return Err(invalid("directory is too large"));
+    }
+    let data = read_at(reader, u64::from(extent) * SECTOR_SIZE, size as usize)?;
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = data[offset] as usize;
+        if len == 0 {
+            offset = (offset / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        let raw = data
+            .get(offset..offset + len)
+            .filter(|raw| raw.len() >= 34 && 33 + raw[32] as usize <= raw.len())
+            .ok_or_else(|| invalid("malformed directory record"))?;
+        records.push(raw.to_vec());
+        offset += len;
+    }
+    Ok(records)
+}
+
+/// Returns the System Use area following a record's identifier.
+fn system_use(raw: &[u8]) -> Option<&[u8]> {
+    let name_len = raw[32] as usize;
+    // The identifier is padded to an even length
+    let start = 33 + name_len + (1 - name_len % 2);
+    raw.get(start..).filter(|area| !area.is_empty())
+}
+
+/// Appends the components of a Rock Ridge `SL` entry to `target`.
+///
+/// `continues` carries over whether the last component was split across
+/// entries, in which case the next one is appended without a separator.
+fn symlink_components(mut data: &[u8], target: &mut String, continues: &mut bool) {
+    while data.len() >= 2 {
+        let (flags, len) = (data[0], data[1] as usize);
+        let Some(content) = data.get(2..2 + len) else {
+            return;
+        };
+        data = &data[2 + len..];
+
+        if !*continues && !target.is_empty() && !target.ends_with('/') {
+            target.push('/');
+        }
+        match flags & 0x0E {
+            0x02 => target.push('.'),
+            0x04 => target.push_str(".."),
+            0x08 => target.push('/'),
+            _ => target.push_str(&String::from_utf8_lossy(content)),
+        }
+        *continues = flags & 0x01 != 0;
+    }
+}
+
+/// Strips the `;1` version and the trailing dot of an extension-less name.
+fn plain_name(name: &str) -> String {
+    let name = name.split(';').next().unwrap_or_default();
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+/// Returns the modification time of a Rock Ridge `TF` entry.
+fn modify_time(data: &[u8]) -> Option<SystemTime> {
+    let flags = data[0];
+    // Stamps are stored in the order creation, modification, access, ...
+    if flags & 0x02 == 0 {
+        return None;
+    }
+    let len = if flags & 0x80 != 0 { 17 } else { 7 };
+    let start = 1 + usize::from(flags & 0x01 != 0) * len;
+    let stamp = data.get(start..start + len)?;
+    if len == 17 {
+        long_time(stamp)
+    } else {
+        short_time(stamp)
+    }
+}
+
+/// Decodes the 7-byte date and time of a directory record.
+fn short_time(b: &[u8]) -> Option<SystemTime> {
+    if b.iter().all(|&b| b == 0) {
+        return None;
+    }
+    timestamp(
+        1900 + i64::from(b[0]),
+        i64::from(b[1]),
+        i64::from(b[2]),
+        i64::from(b[3]),
+        i64::from(b[4]),
+        i64::from(b[5]),
+        b[6] as i8,
+    )
+}
+
+/// Decodes the 17-byte `YYYYMMDDHHMMSScc` format of volume descriptors.
+fn long_time(b: &[u8]) -> Option<SystemTime> {
+    let digits = |range: std::ops::Range<usize>| -> Option<i64> {
+        std::str::from_utf8(&b[range]).ok()?.parse().ok()
+    };
+    timestamp(
+        digits(0..4)?,
+        digits(4..6)?,
+        digits(6..8)?,
+        digits(8..10)?,
+        digits(10..12)?,
+        digits(12..14)?,
+        b[16] as i8,
+    )
+}
+
+/// Converts a local time with an offset in 15-minute intervals to a
+/// `SystemTime`.
+fn timestamp(
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    offset: i8,
+) -> Option<SystemTime> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let secs =
+        entry::days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second
+            - i64::from(offset) * 15 * 60;
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Reads the little-endian half of a both-endian 32-bit field.
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Iso,
+        reason: reason.into(),
+    }
+}
@@ -1,7 +1,67 @@
+mod appimage;
+mod ar;
 pub mod archive;
+pub mod audit;
+pub mod backend;
+mod blake3;
+mod brotli;
+mod cab;
+mod central;
+mod cfb;
+mod cpio;
+mod edit;
+pub mod encoding;
+pub mod entry;
 pub mod error;
+pub mod extract;
 pub mod format;
+mod glob;
+pub mod hash;
+mod hint;
+mod huffman;
+mod iso;
+mod lz4;
+mod lzw;
+mod lzx;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod normalize;
+mod oci;
+mod owner;
+pub mod password;
+pub mod progress;
+#[cfg(feature = "rar")]
+mod rar;
+pub mod salvage;
+#[cfg(feature = "serde")]
+mod serialize;
+mod sparse;
+mod squashfs;
+pub mod stat;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod verify;
+mod volume;
+mod wim;
+pub mod writer;
+mod xar;
+mod xattr;
 
 pub use archive::*;
+pub use audit::*;
+pub use backend::*;
+pub use encoding::*;
+pub use entry::*;
 pub use error::*;
+pub use extract::*;
 pub use format::*;
+pub use hash::*;
+#[cfg(feature = "async")]
+pub use nonblocking::*;
+pub use normalize::*;
+pub use password::*;
+pub use progress::*;
+pub use salvage::*;
+pub use stat::*;
+pub use verify::*;
+pub use writer::*;
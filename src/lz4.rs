@@ -0,0 +1,463 @@
+//! Decoder for the LZ4 frame format.
+//!
+//! Implements the frame format produced by the `lz4` command line tool:
+//! linked and independent blocks, block and content checksums, skippable
+//! frames, concatenated frames and the legacy frame format written by
+//! `lz4 -l`. Dictionaries are not supported.
+
+use std::io::{self, Read};
+
+/// Magic number of a standard frame.
+pub(crate) const FRAME_MAGIC: u32 = 0x184D_2204;
+/// Magic number of a legacy frame.
+pub(crate) const LEGACY_MAGIC: u32 = 0x184C_2102;
+/// Mask and value shared by the 16 skippable frame magic numbers.
+const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+/// Block size of legacy frames.
+const LEGACY_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+/// How far back a match may reach into earlier output.
+const WINDOW_SIZE: usize = 64 * 1024;
+/// Set in a block size when the block is stored uncompressed.
+const UNCOMPRESSED_BLOCK: u32 = 0x8000_0000;
+
+/// Where the decoder is in the stream.
+enum State {
+    /// Expecting a frame magic number, or the end of the stream.
+    Magic,
+    /// Inside a standard frame.
+    Frame(Frame),
+    /// Inside a legacy frame, which has no end marker.
+    Legacy,
+    Done,
+}
+
+/// Settings from a standard frame's descriptor.
+struct Frame {
+    independent: bool,
+    block_checksum: bool,
+    content_checksum: Option<Xxh32>,
+    max_block_size: usize,
+}
+
+/// A reader that decompresses an LZ4 stream.
+pub(crate) struct FrameDecoder<R> {
+    reader: R,
+    state: State,
+    /// Output of the current block, preceded by up to [`WINDOW_SIZE`]
+    /// bytes of earlier output that its matches may refer to.
+    output: Vec<u8>,
+    /// Position of the next unread byte in `output`.
+    position: usize,
+    /// Whether any frame has been read yet.
+    started: bool,
+}
+
+impl<R: Read> FrameDecoder<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            state: State::Magic,
+            output: Vec::new(),
+            position: 0,
+            started: false,
+        }
+    }
+
+    /// Decodes the next block into `output`, returning `false` at the end
+    /// of the stream.
+    fn next_block(&mut self) -> io::Result<bool> {
+        loop {
+            match &mut self.state {
+                State::Done => return Ok(false),
+                State::Magic => {
+                    let Some(magic) = self.read_u32_or_eof()? else {
+                        if !self.started {
+                            return Err(invalid("empty LZ4 stream"));
+                        }
+                        self.state = State::Done;
+                        continue;
+                    };
+                    self.start_frame(magic)?;
+                }
+                State::Legacy => {
+                    let Some(size) = self.read_u32_or_eof()? else {
+                        self.state = State::Done;
+                        continue;
+                    };
+                    if size == FRAME_MAGIC
+                        || size == LEGACY_MAGIC
+                        || size & SKIPPABLE_MASK == SKIPPABLE_MAGIC
+                    {
+                        self.start_frame(size)?;
+                        continue;
+                    }
+                    if size as usize > lz4_bound(LEGACY_BLOCK_SIZE) {
+                        return Err(invalid("LZ4 block is too large"));
+                    }
+                    let block = self.read_vec(size as usize)?;
+                    // Legacy blocks are always independent
+                    self.output.clear();
+                    decompress_block(&block, &mut self.output, LEGACY_BLOCK_SIZE)?;
+                    self.position = 0;
+                    return Ok(true);
+                }
+                State::Frame(frame) => {
+                    let (independent, block_checksum, max) = (
+                        frame.independent,
+                        frame.block_checksum,
+                        frame.max_block_size,
+                    );
+                    let size = self.read_u32()?;
+                    if size == 0 {
+                        self.end_frame()?;
+                        continue;
+                    }
+                    let len = (size & !UNCOMPRESSED_BLOCK) as usize;
+                    if len > max {
+                        return Err(invalid("LZ4 block is too large"));
+                    }
+                    let block = self.read_vec(len)?;
+                    if block_checksum {
+                        let expected = self.read_u32()?;
+                        if xxh32(&block) != expected {
+                            return Err(invalid("LZ4 block checksum mismatch"));
+                        }
+                    }
+
+                    // Keep the tail of earlier output for linked blocks
+                    if independent {
+                        self.output.clear();
+                    } else {
+                        let keep = self.output.len().min(WINDOW_SIZE);
+                        self.output.drain(..self.output.len() - keep);
+                    }
+                    let start = self.output.len();
+                    if size & UNCOMPRESSED_BLOCK != 0 {
+                        self.output.extend_from_slice(&block);
+                    } else {
+                        decompress_block(&block, &mut self.output, max)?;
+                    }
+                    if let State::Frame(Frame {
+                        content_checksum: Some(hasher),
+                        ..
+                    }) = &mut self.state
+                    {
+                        hasher.update(&self.output[start..]);
+                    }
+                    self.position = start;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of a frame header after its magic number.
+    fn start_frame(&mut self, magic: u32) -> io::Result<()> {
+        self.started = true;
+        if magic == LEGACY_MAGIC {
+            self.state = State::Legacy;
+            return Ok(());
+        }
+        if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+            let size = self.read_u32()?;
+            io::copy(
+                &mut (&mut self.reader).take(u64::from(size)),
+                &mut io::sink(),
+            )?;
+            self.state = State::Magic;
+            return Ok(());
+        }
+        if magic != FRAME_MAGIC {
+            return Err(invalid("invalid LZ4 frame magic"));
+        }
+
+        let mut descriptor = self.read_vec(2)?;
+        let (flags, block_descriptor) = (descriptor[0], descriptor[1]);
+        if flags >> 6 != 0b01 {
+            return Err(invalid("unsupported LZ4 frame version"));
+        }
+        if flags & 0x01 != 0 {
+            return Err(invalid("LZ4 dictionaries are not supported"));
+        }
+        if flags & 0x08 != 0 {
+            // The content size is informational only
+            descriptor.extend(self.read_vec(8)?);
+        }
+        let checksum = self.read_vec(1)?[0];
+        if (xxh32(&descriptor) >> 8) as u8 != checksum {
+            return Err(invalid("LZ4 frame header checksum mismatch"));
+        }
+        let max_block_size = match (block_descriptor >> 4) & 0x07 {
+            4 => 64 * 1024,
+            5 => 256 * 1024,
+            6 => 1024 * 1024,
+            7 => 4 * 1024 * 1024,
+            _ => return Err(invalid("invalid LZ4 block size")),
+        };
+
+        self.output.clear();
+        self.position = 0;
+        self.state = State::Frame(Frame {
+            independent: flags & 0x20 != 0,
+            block_checksum: flags & 0x10 != 0,
+            content_checksum: (flags & 0x04 != 0).then(Xxh32::new),
+            max_block_size,
+        });
+        Ok(())
+    }
+
+    /// Checks the content checksum after a frame's end marker.
+    fn end_frame(&mut self) -> io::Result<()> {
+        if let State::Frame(Frame {
+            content_checksum: Some(hasher),
+            ..
+        }) = &self.state
+        {
+            let actual = hasher.finish();
+            if self.read_u32()? != actual {
+                return Err(invalid("LZ4 content checksum mismatch"));
+            }
+        }
+        self.state = State::Magic;
+        Ok(())
+    }
+
+    fn read_vec(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Reads a little-endian `u32`, or `None` at a clean end of stream.
+    fn read_u32_or_eof(&mut self) -> io::Result<Option<u32>> {
+        let mut bytes = [0u8; 4];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(u32::from_le_bytes(bytes)))
+    }
+}
+
+impl<R: Read> Read for FrameDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.position == self.output.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.output.len() - self.position);
+        buf[..n].copy_from_slice(&self.output[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Wraps `data` in an LZ4 frame of uncompressed blocks, which every LZ4
+/// decoder accepts.
+#[cfg(feature = "testing")]
+pub(crate) fn encode_stored(data: &[u8]) -> Vec<u8> {
+    // Version 1, independent blocks of at most 64 KiB
+    let descriptor = [0x60, 0x40];
+    let mut frame = FRAME_MAGIC.to_le_bytes().to_vec();
+    frame.extend_from_slice(&descriptor);
+    frame.push((xxh32(&descriptor) >> 8) as u8);
+    for block in data.chunks(WINDOW_SIZE) {
+        frame.extend_from_slice(&(block.len() as u32 | UNCOMPRESSED_BLOCK).to_le_bytes());
+        frame.extend_from_slice(block);
+    }
+    frame.extend_from_slice(&0u32.to_le_bytes());
+    frame
+}
+
+/// Decompresses one LZ4 block, appending at most `limit` bytes to
+/// `output`. Matches may refer back into data already in `output`.
+fn decompress_block(block: &[u8], output: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    let end = output.len() + limit;
+    let mut i = 0;
+    loop {
+        let token = *block.get(i).ok_or_else(|| invalid("truncated LZ4 block"))?;
+        i += 1;
+
+        let literals = read_length(block, &mut i, usize::from(token >> 4))?;
+        let literal_bytes = block
+            .get(i..i + literals)
+            .ok_or_else(|| invalid("truncated LZ4 block"))?;
+        if output.len() + literals > end {
+            return Err(invalid("LZ4 block decompresses past its maximum size"));
+        }
+        output.extend_from_slice(literal_bytes);
+        i += literals;
+        // The last sequence consists of literals only
+        if i == block.len() {
+            return Ok(());
+        }
+
+        let offset = block
+            .get(i..i + 2)
+            .map(|b| usize::from(u16::from_le_bytes([b[0], b[1]])))
+            .ok_or_else(|| invalid("truncated LZ4 block"))?;
+        i += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(invalid("invalid LZ4 match offset"));
+        }
+        let length = read_length(block, &mut i, usize::from(token & 0x0F))? + 4;
+        if output.len() + length > end {
+            return Err(invalid("LZ4 block decompresses past its maximum size"));
+        }
+        // Matches may overlap the bytes they produce
+        let start = output.len() - offset;
+        if offset >= length {
+            output.extend_from_within(start..start + length);
+        } else {
+            for k in 0..length {
+                output.push(output[start + k]);
+            }
+        }
+    }
+}
+
+/// Reads a length that continues in extra bytes when its nibble is 15.
+fn read_length(block: &[u8], i: &mut usize, nibble: usize) -> io::Result<usize> {
+    let mut length = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *block
+                .get(*i)
+                .ok_or_else(|| invalid("truncated LZ4 block"))?;
+            *i += 1;
+            length += usize::from(byte);
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(length)
+}
+
+/// Largest compressed size of a block of `size` bytes.
+fn lz4_bound(size: usize) -> usize {
+    size + size / 255 + 16
+}
+
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+/// Streaming xxHash32 with a seed of 0, as used for LZ4 checksums.
+struct Xxh32 {
+    lanes: [u32; 4],
+    buffer: Vec<u8>,
+    total: u64,
+}
+
+impl Xxh32 {
+    fn new() -> Self {
+        Self {
+            lanes: [
+                PRIME1.wrapping_add(PRIME2),
+                PRIME2,
+                0,
+                0u32.wrapping_sub(PRIME1),
+            ],
+            buffer: Vec::with_capacity(16),
+            total: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let n = data.len().min(16 - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() < 16 {
+                return;
+            }
+            let stripe = std::mem::take(&mut self.buffer);
+            self.stripe(&stripe);
+        }
+        let mut stripes = data.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        self.buffer.extend_from_slice(stripes.remainder());
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(4)) {
+            *lane = round(
+                *lane,
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+            );
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        let [v1, v2, v3, v4] = self.lanes;
+        let mut hash = if self.total >= 16 {
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            PRIME5
+        };
+        hash = hash.wrapping_add(self.total as u32);
+
+        let mut words = self.buffer.chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            hash = hash
+                .wrapping_add(word.wrapping_mul(PRIME3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME4);
+        }
+        for &byte in words.remainder() {
+            hash = hash
+                .wrapping_add(u32::from(byte).wrapping_mul(PRIME5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME1);
+        }
+
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(PRIME2);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(PRIME3);
+        hash ^ (hash >> 16)
+    }
+}
+
+fn round(lane: u32, word: u32) -> u32 {
+    lane.wrapping_add(word.wrapping_mul(PRIME2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME1)
+}
+
+fn xxh32(data: &[u8]) -> u32 {
+    let mut hasher = Xxh32::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,256 @@
+//! Decoder for the LZW format of Unix `compress` (.Z).
+//!
+//! Follows the reference `compress` implementation, including its habit of
+//! reading codes in groups of eight: whenever the code width changes or
+//! the table is cleared, the rest of the current group is skipped.
+
+use std::io::{self, Read};
+
+/// Magic number at the start of every stream.
+pub(crate) const MAGIC: [u8; 2] = [0x1F, 0x9D];
+/// Header flag enabling the table clear code.
+const BLOCK_MODE: u8 = 0x80;
+/// Header bits holding the maximum code width.
+const MAX_BITS_MASK: u8 = 0x1F;
+/// Header bits that must be zero.
+const RESERVED_MASK: u8 = 0x60;
+/// Width of the first codes after the start or a table clear.
+const INITIAL_BITS: u32 = 9;
+/// Widest code the format allows.
+const MAX_BITS: u32 = 16;
+/// Code that resets the table in block mode.
+const CLEAR: u32 = 256;
+/// Codes are read in groups of this many, see the module docs.
+const GROUP_SIZE: u32 = 8;
+
+/// A reader that decompresses a `compress` stream.
+pub(crate) struct Decoder<R> {
+    reader: R,
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    /// Pending input bits, least significant first.
+    bits: u32,
+    count: u32,
+    /// Maximum code width and block mode flag, once the header is read.
+    header: Option<(u32, bool)>,
+    width: u32,
+    /// Codes read at the current width since the last group boundary.
+    in_group: u32,
+    /// Prefix code and last byte of every table entry above 255.
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+    next_code: u32,
+    previous: Option<u32>,
+    first_byte: u8,
+    /// Decoded data not yet read.
+    output: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![0; 8192].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            bits: 0,
+            count: 0,
+            header: None,
+            width: INITIAL_BITS,
+            in_group: 0,
+            prefix: vec![0; 1 << MAX_BITS],
+            suffix: vec![0; 1 << MAX_BITS],
+            next_code: 0,
+            previous: None,
+            first_byte: 0,
+            output: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.start == self.end {
+            self.end = loop {
+                match self.reader.read(&mut self.buffer) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            };
+            self.start = 0;
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+        self.start += 1;
+        Ok(Some(self.buffer[self.start - 1]))
+    }
+
+    /// Reads `n` bits, or `None` if the input ends first.
+    fn read_bits(&mut self, n: u32) -> io::Result<Option<u32>> {
+        while self.count < n {
+            let Some(byte) = self.read_byte()? else {
+                return Ok(None);
+            };
+            self.bits |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(Some(value))
+    }
+
+    fn read_header(&mut self) -> io::Result<(u32, bool)> {
+        let mut header = [0u8; 3];
+        for byte in &mut header {
+            *byte = self
+                .read_byte()?
+                .ok_or_else(|| invalid("truncated compress header"))?;
+        }
+        if header[..2] != MAGIC {
+            return Err(invalid("not a compress stream"));
+        }
+        let max_bits = u32::from(header[2] & MAX_BITS_MASK);
+        if header[2] & RESERVED_MASK != 0 || !(INITIAL_BITS..=MAX_BITS).contains(&max_bits) {
+            return Err(invalid("unsupported compress header flags"));
+        }
+        let block_mode = header[2] & BLOCK_MODE != 0;
+        self.next_code = if block_mode { CLEAR + 1 } else { CLEAR };
+        Ok((max_bits, block_mode))
+    }
+
+    /// Skips the unread codes of the current group, returning `false` if
+    /// the input ends first.
+    fn skip_group(&mut self) -> io::Result<bool> {
+        let remaining = (GROUP_SIZE - self.in_group) % GROUP_SIZE;
+        self.in_group = 0;
+        for _ in 0..remaining {
+            if self.read_bits(self.width)?.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decodes the next code into `output`, returning `false` at the end
+    /// of the stream.
+    fn next_code(&mut self) -> io::Result<bool> {
+        let (max_bits, block_mode) = match self.header {
+            Some(header) => header,
+            None => {
+                let header = self.read_header()?;
+                *self.header.insert(header)
+            }
+        };
+
+        if self.width < max_bits && self.next_code > (1 << self.width) - 1 {
+            if !self.skip_group()? {
+                return Ok(false);
+            }
+            self.width += 1;
+        }
+        let Some(code) = self.read_bits(self.width)? else {
+            return Ok(false);
+        };
+        self.in_group = (self.in_group + 1) % GROUP_SIZE;
+
+        let Some(previous) = self.previous else {
+            if code > 255 {
+                return Err(invalid("compress stream starts with a back-reference"));
+            }
+            self.previous = Some(code);
+            self.first_byte = code as u8;
+            self.output.push(code as u8);
+            return Ok(true);
+        };
+        if block_mode && code == CLEAR {
+            if !self.skip_group()? {
+                return Ok(false);
+            }
+            self.width = INITIAL_BITS;
+            self.next_code = CLEAR + 1;
+            self.previous = None;
+            return Ok(true);
+        }
+
+        // The table entry is expanded back to front
+        let start = self.output.len();
+        let mut current = code;
+        if code >= self.next_code {
+            if code > self.next_code {
+                return Err(invalid("invalid compress code"));
+            }
+            // A code referring to the entry being defined right now
+            self.output.push(self.first_byte);
+            current = previous;
+        }
+        while current > 255 {
+            self.output.push(self.suffix[current as usize]);
+            current = u32::from(self.prefix[current as usize]);
+        }
+        self.first_byte = current as u8;
+        self.output.push(self.first_byte);
+        self.output[start..].reverse();
+
+        if self.next_code < 1 << max_bits {
+            self.prefix[self.next_code as usize] = previous as u16;
+            self.suffix[self.next_code as usize] = self.first_byte;
+            self.next_code += 1;
+        }
+        self.previous = Some(code);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.output.len() {
+            self.output.clear();
+            self.position = 0;
+            // Decode in batches to keep the per-call overhead down
+            while !self.done && self.output.len() < 16 * 1024 {
+                self.done = !self.next_code()?;
+            }
+        }
+        let n = buf.len().min(self.output.len() - self.position);
+        buf[..n].copy_from_slice(&self.output[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Encodes `data` as a `compress` stream of literal codes only, clearing
+/// the table before the code width would grow, which every `compress`
+/// decoder accepts.
+#[cfg(feature = "testing")]
+pub(crate) fn encode_literals(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![MAGIC[0], MAGIC[1], BLOCK_MODE | MAX_BITS as u8];
+    let mut bits = 0u32;
+    let mut count = 0;
+    // 255 literals and a clear code fill exactly 32 groups of 9-bit codes
+    for (i, chunk) in data.chunks(255).enumerate() {
+        let clear = (i > 0).then_some(CLEAR);
+        for code in clear.into_iter().chain(chunk.iter().map(|&b| u32::from(b))) {
+            bits |= code << count;
+            count += INITIAL_BITS;
+            while count >= 8 {
+                stream.push(bits as u8);
+                bits >>= 8;
+                count -= 8;
+            }
+        }
+    }
+    if count > 0 {
+        stream.push(bits as u8);
+    }
+    stream
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
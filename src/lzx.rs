@@ -0,0 +1,480 @@
+//! LZX decompression, as used by WIM images and cabinet files.
+//!
+//! WIM compresses each chunk on its own: every chunk starts with fresh
+//! recent offsets and all-zero previous codeword lengths, and the
+//! call-instruction (E8) preprocessing is always enabled with a fixed
+//! translation size. Cabinets compress a whole folder as one stream of
+//! 32 KiB frames instead, carrying the window, the codes and the recent
+//! offsets from one frame to the next, and say in a stream header whether
+//! E8 preprocessing was used.
+
+use std::io;
+
+use crate::huffman::HuffmanCode;
+
+const BLOCK_VERBATIM: u32 = 1;
+const BLOCK_ALIGNED: u32 = 2;
+const BLOCK_UNCOMPRESSED: u32 = 3;
+/// Block size implied by the block header's default flag in WIM.
+const DEFAULT_BLOCK_SIZE: usize = 32768;
+/// Shortest match the format encodes.
+const MIN_MATCH: usize = 2;
+/// Match lengths below this are encoded in the main symbol alone.
+const NUM_PRIMARY_LENS: usize = 7;
+const NUM_CHARS: usize = 256;
+const NUM_LEN_SYMBOLS: usize = 249;
+const NUM_PRETREE_SYMBOLS: usize = 20;
+const NUM_ALIGNED_SYMBOLS: usize = 8;
+/// Smallest and largest window size, as powers of two.
+pub(crate) const MIN_WINDOW_ORDER: u32 = 15;
+pub(crate) const MAX_WINDOW_ORDER: u32 = 21;
+/// Offset slots by window order, from 2^15 to 2^21 bytes.
+const OFFSET_SLOTS: [usize; 7] = [30, 32, 34, 36, 38, 42, 50];
+/// Most extra offset bits any slot has.
+const MAX_FOOTER_BITS: u32 = 17;
+/// File size the E8 preprocessing assumes in WIM.
+const WIM_E8_FILE_SIZE: i32 = 12_000_000;
+/// Output size of every frame of a cabinet stream but the last.
+pub(crate) const FRAME_SIZE: usize = 32768;
+/// Cabinet streams only apply E8 preprocessing to their first 32768 frames.
+const E8_MAX_OFFSET: u64 = 32768 * FRAME_SIZE as u64;
+/// Upper bound on the input carried from one frame to the next.
+const MAX_CARRY: usize = 2 * FRAME_SIZE;
+
+/// The container an LZX stream comes from, which decides its framing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variant {
+    Wim,
+    Cabinet,
+}
+
+/// The state of an LZX stream between frames.
+pub(crate) struct Decoder {
+    variant: Variant,
+    window_order: u32,
+    window: Vec<u8>,
+    /// Bytes decoded since the stream started.
+    total: u64,
+    main_lens: Vec<u8>,
+    len_lens: [u8; NUM_LEN_SYMBOLS],
+    recent: [usize; 3],
+    /// Bytes left in the current block, 0 before the next block header.
+    block_remaining: usize,
+    /// Codes of the current block, or `None` if it is uncompressed.
+    codes: Option<Codes>,
+    /// Set after an uncompressed block of odd size, which is followed by
+    /// a byte of padding.
+    pending_padding: bool,
+    /// Bytes by which the last match ran past the end of its block, which
+    /// count towards the next block.
+    overrun: usize,
+    /// Translation size of the E8 preprocessing, 0 if it is disabled, or
+    /// `None` before the stream header is read.
+    e8_size: Option<i32>,
+    /// Input left over after the last frame, which the next one continues.
+    carry: Vec<u8>,
+}
+
+struct Codes {
+    main: HuffmanCode,
+    lengths: HuffmanCode,
+    aligned: Option<HuffmanCode>,
+}
+
+impl Decoder {
+    /// Creates a decoder for a stream with a window of 2^`window_order`
+    /// bytes.
+    pub(crate) fn new(variant: Variant, window_order: u32) -> io::Result<Self> {
+        if !(MIN_WINDOW_ORDER..=MAX_WINDOW_ORDER).contains(&window_order) {
+            return Err(invalid("unsupported LZX window size"));
+        }
+        let num_slots = OFFSET_SLOTS[(window_order - MIN_WINDOW_ORDER) as usize];
+        Ok(Self {
+            variant,
+            window_order,
+            window: vec![0; 1 << window_order],
+            total: 0,
+            main_lens: vec![0; NUM_CHARS + num_slots * 8],
+            len_lens: [0; NUM_LEN_SYMBOLS],
+            recent: [1; 3],
+            block_remaining: 0,
+            codes: None,
+            pending_padding: false,
+            overrun: 0,
+            e8_size: None,
+            carry: Vec::new(),
+        })
+    }
+
+    /// Returns the decoder to the start of a stream.
+    pub(crate) fn reset(&mut self) {
+        self.total = 0;
+        self.main_lens.fill(0);
+        self.len_lens.fill(0);
+        self.recent = [1; 3];
+        self.block_remaining = 0;
+        self.codes = None;
+        self.pending_padding = false;
+        self.overrun = 0;
+        self.e8_size = None;
+        self.carry.clear();
+    }
+
+    /// Decompresses the next frame, a WIM chunk or the data of a cabinet
+    /// block, filling `output`, which may not exceed the window.
+    ///
+    /// The input of a stream is read as one sequence realigned to 16 bits
+    /// after every frame, so bytes a frame leaves unread, such as the
+    /// padding of an uncompressed block ending with it, start the next.
+    pub(crate) fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<()> {
+        if output.len() > self.window.len() {
+            return Err(invalid("LZX frame is larger than the window"));
+        }
+        let mut joined = std::mem::take(&mut self.carry);
+        let input = if joined.is_empty() {
+            input
+        } else {
+            if joined.len() > MAX_CARRY {
+                return Err(invalid("LZX frame has trailing data"));
+            }
+            joined.extend_from_slice(input);
+            &joined
+        };
+        let mut bits = Bitstream {
+            data: input,
+            position: 0,
+            bits: 0,
+            count: 0,
+        };
+        let e8_size = match self.e8_size {
+            Some(size) => size,
+            None => {
+                let size = match self.variant {
+                    Variant::Wim => WIM_E8_FILE_SIZE,
+                    Variant::Cabinet if bits.read(1) == 1 => {
+                        ((bits.read(16) << 16) | bits.read(16)) as i32
+                    }
+                    Variant::Cabinet => 0,
+                };
+                self.e8_size = Some(size);
+                size
+            }
+        };
+
+        let mask = self.window.len() - 1;
+        let start = self.total;
+        // Where the frame starts in the window
+        let base = start as usize & mask;
+        let mut out = 0;
+        while out < output.len() {
+            if self.block_remaining == 0 {
+                self.read_block_header(&mut bits)?;
+            }
+            let n = self.block_remaining.min(output.len() - out);
+            let Some(codes) = &self.codes else {
+                let data = bits.bytes(n)?;
+                for (i, &byte) in data.iter().enumerate() {
+                    self.window[(base + out + i) & mask] = byte;
+                }
+                output[out..out + n].copy_from_slice(data);
+                out += n;
+                self.block_remaining -= n;
+                continue;
+            };
+
+            let end = out + n;
+            while out < end {
+                let symbol = usize::from(bits.symbol(&codes.main)?);
+                if symbol < NUM_CHARS {
+                    self.window[(base + out) & mask] = symbol as u8;
+                    output[out] = symbol as u8;
+                    out += 1;
+                    continue;
+                }
+
+                let header = (symbol - NUM_CHARS) & 7;
+                let slot = (symbol - NUM_CHARS) >> 3;
+                let mut length = header + MIN_MATCH;
+                if header == NUM_PRIMARY_LENS {
+                    length += usize::from(bits.symbol(&codes.lengths)?);
+                }
+                let offset = match slot {
+                    0 => self.recent[0],
+                    1 => {
+                        self.recent.swap(0, 1);
+                        self.recent[0]
+                    }
+                    2 => {
+                        self.recent.swap(0, 2);
+                        self.recent[0]
+                    }
+                    _ => {
+                        let footer_bits = footer_bits(slot);
+                        let extra = match &codes.aligned {
+                            Some(code) if footer_bits >= 3 => {
+                                (bits.read(footer_bits - 3) << 3) + u32::from(bits.symbol(code)?)
+                            }
+                            _ => bits.read(footer_bits),
+                        };
+                        // Slots 0 to 2 stand for the recent offsets, so the
+                        // rest are shifted by two
+                        let offset = POSITION_BASES[slot] + extra as usize - 2;
+                        self.recent = [offset, self.recent[0], self.recent[1]];
+                        offset
+                    }
+                };
+
+                if offset == 0
+                    || offset > self.window.len()
+                    || offset as u64 > start + out as u64
+                    || length > output.len() - out
+                {
+                    return Err(invalid("LZX match is out of bounds"));
+                }
+                for (i, slot) in output[out..out + length].iter_mut().enumerate() {
+                    let position = base + out + i;
+                    let byte = self.window[position.wrapping_sub(offset) & mask];
+                    self.window[position & mask] = byte;
+                    *slot = byte;
+                }
+                out += length;
+            }
+            // A match may run on into the next block
+            self.overrun = out - end;
+            self.block_remaining -= n;
+        }
+
+        if e8_size != 0 && (self.variant == Variant::Wim || start < E8_MAX_OFFSET) {
+            undo_e8_translation(output, start as i32, e8_size);
+        }
+        self.total += output.len() as u64;
+        if let Some(rest) = input.get(bits.consumed()..) {
+            self.carry = rest.to_vec();
+        }
+        Ok(())
+    }
+
+    /// Reads the header of the next block and the codes it defines.
+    fn read_block_header(&mut self, bits: &mut Bitstream<'_>) -> io::Result<()> {
+        if self.pending_padding {
+            self.pending_padding = false;
+            bits.bytes(1)?;
+        }
+        let block_type = bits.read(3);
+        let size = match self.variant {
+            Variant::Wim if bits.read(1) == 1 => DEFAULT_BLOCK_SIZE,
+            Variant::Wim if self.window_order >= 16 => {
+                ((bits.read(16) << 8) | bits.read(8)) as usize
+            }
+            Variant::Wim => bits.read(16) as usize,
+            Variant::Cabinet => ((bits.read(16) << 8) | bits.read(8)) as usize,
+        };
+        if size == 0 {
+            return Err(invalid("empty LZX block"));
+        }
+        let overrun = std::mem::take(&mut self.overrun);
+        self.block_remaining = size
+            .checked_sub(overrun)
+            .ok_or_else(|| invalid("LZX match is out of bounds"))?;
+
+        match block_type {
+            BLOCK_VERBATIM | BLOCK_ALIGNED => {
+                let mut aligned = None;
+                if block_type == BLOCK_ALIGNED {
+                    let mut lens = [0u8; NUM_ALIGNED_SYMBOLS];
+                    for len in &mut lens {
+                        *len = bits.read(3) as u8;
+                    }
+                    aligned = Some(HuffmanCode::new(&lens)?);
+                }
+                read_lens(bits, &mut self.main_lens[..NUM_CHARS])?;
+                read_lens(bits, &mut self.main_lens[NUM_CHARS..])?;
+                read_lens(bits, &mut self.len_lens)?;
+                self.codes = Some(Codes {
+                    main: HuffmanCode::new(&self.main_lens)?,
+                    lengths: HuffmanCode::new(&self.len_lens)?,
+                    aligned,
+                });
+            }
+            BLOCK_UNCOMPRESSED => {
+                bits.align();
+                for offset in &mut self.recent {
+                    let bytes = bits.bytes(4)?;
+                    *offset = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+                }
+                // Blocks are padded to a whole number of 16-bit words
+                self.pending_padding = size % 2 == 1;
+                self.codes = None;
+            }
+            _ => return Err(invalid("unknown LZX block type")),
+        }
+        Ok(())
+    }
+}
+
+/// Reads a pretree and the codeword lengths it encodes, each as a delta
+/// to the length the symbol had in the previous block.
+fn read_lens(bits: &mut Bitstream<'_>, lens: &mut [u8]) -> io::Result<()> {
+    let mut pretree_lens = [0u8; NUM_PRETREE_SYMBOLS];
+    for len in &mut pretree_lens {
+        *len = bits.read(4) as u8;
+    }
+    let pretree = HuffmanCode::new(&pretree_lens)?;
+    let delta = |previous: u8, symbol: u16| ((u16::from(previous) + 17 - symbol) % 17) as u8;
+
+    let mut i = 0;
+    while i < lens.len() {
+        let symbol = bits.symbol(&pretree)?;
+        let (run, len) = match symbol {
+            0..=16 => (1, delta(lens[i], symbol)),
+            17 => (4 + bits.read(4) as usize, 0),
+            18 => (20 + bits.read(5) as usize, 0),
+            _ => {
+                let run = 4 + bits.read(1) as usize;
+                let symbol = bits.symbol(&pretree)?;
+                if symbol > 16 {
+                    return Err(invalid("invalid LZX codeword length"));
+                }
+                (run, delta(lens[i], symbol))
+            }
+        };
+        lens.get_mut(i..i + run)
+            .ok_or_else(|| invalid("LZX codeword lengths overflow"))?
+            .fill(len);
+        i += run;
+    }
+    Ok(())
+}
+
+/// Number of extra offset bits following offset slot `slot`.
+const fn footer_bits(slot: usize) -> u32 {
+    if slot < 4 {
+        0
+    } else {
+        let bits = (slot as u32 - 2) / 2;
+        if bits < MAX_FOOTER_BITS {
+            bits
+        } else {
+            MAX_FOOTER_BITS
+        }
+    }
+}
+
+/// Smallest formatted offset of each offset slot.
+const POSITION_BASES: [usize; 50] = {
+    let mut bases = [0; 50];
+    let mut slot = 1;
+    while slot < bases.len() {
+        bases[slot] = bases[slot - 1] + (1 << footer_bits(slot - 1));
+        slot += 1;
+    }
+    bases
+};
+
+/// Restores the relative call targets the compressor made absolute, for
+/// `data` starting `start` bytes into the stream.
+fn undo_e8_translation(data: &mut [u8], start: i32, file_size: i32) {
+    if data.len() <= 10 {
+        return;
+    }
+    let mut i = 0;
+    while i < data.len() - 10 {
+        if data[i] != 0xE8 {
+            i += 1;
+            continue;
+        }
+        let target = &mut data[i + 1..i + 5];
+        let absolute = i32::from_le_bytes([target[0], target[1], target[2], target[3]]);
+        let position = start + i as i32;
+        let relative = if (0..file_size).contains(&absolute) {
+            absolute - position
+        } else if absolute < 0 && absolute >= -position {
+            absolute + file_size
+        } else {
+            absolute
+        };
+        target.copy_from_slice(&relative.to_le_bytes());
+        i += 5;
+    }
+}
+
+/// A bitstream of 16-bit little-endian words, read most significant bit
+/// first.
+struct Bitstream<'a> {
+    data: &'a [u8],
+    /// Offset of the next word to load.
+    position: usize,
+    /// Buffered bits, most significant first.
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> Bitstream<'a> {
+    /// Loads words until at least 48 bits are buffered, padding the end of
+    /// the frame with zeros.
+    fn refill(&mut self) {
+        while self.count <= 48 {
+            let word = self
+                .data
+                .get(self.position..self.position + 2)
+                .map_or(0, |word| u64::from(u16::from_le_bytes([word[0], word[1]])));
+            self.position += 2;
+            self.bits |= word << (48 - self.count);
+            self.count += 16;
+        }
+    }
+
+    /// Reads `n` bits of at most 32.
+    fn read(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        self.refill();
+        let value = (self.bits >> (64 - n)) as u32;
+        self.bits <<= n;
+        self.count -= n;
+        value
+    }
+
+    fn symbol(&mut self, code: &HuffmanCode) -> io::Result<u16> {
+        self.refill();
+        let (symbol, len) = code.decode((self.bits >> 48) as u32)?;
+        self.bits <<= len;
+        self.count -= len;
+        Ok(symbol)
+    }
+
+    /// Skips to the next word boundary, or past the next word if already
+    /// on one, switching to reading whole bytes.
+    fn align(&mut self) {
+        self.position = self.consumed();
+        if self.count.is_multiple_of(16) {
+            self.position += 2;
+        }
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Returns the bytes read so far, counting a partly read word as read.
+    ///
+    /// Words are loaded from wherever the byte reads left off, which need
+    /// not be an even offset once input carries over between frames.
+    fn consumed(&self) -> usize {
+        self.position - (self.count / 16) as usize * 2
+    }
+
+    /// Reads `n` raw bytes, which must start on a word boundary reached by
+    /// [`Bitstream::align`] or at the start of the frame.
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let data = self
+            .data
+            .get(self.position..self.position + n)
+            .ok_or_else(|| invalid("truncated LZX block"))?;
+        self.position += n;
+        Ok(data)
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,313 @@
+//! Async wrappers for use from Tokio-based services.
+//!
+//! Archive decoding is CPU-bound and built on blocking `std::io`, so running
+//! it directly on an async task would stall the reactor. Every function in
+//! this module instead moves the work onto Tokio's blocking thread pool with
+//! [`tokio::task::spawn_blocking`] and awaits the result.
+//!
+//! This module is only available with the `async` feature and must be called
+//! from within a Tokio runtime.
+
+use std::{
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    archive::Archive,
+    error::ArchiveError,
+    extract::{self, ExtractEvent, ExtractOptions, ExtractReport},
+    format::{self, ArchiveFormat},
+};
+
+/// Number of events buffered before the extraction thread waits for the
+/// consumer to catch up.
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// A stream of [`ExtractEvent`]s from an extraction running in the background.
+///
+/// Created by [`Archive::extract_events`] and
+/// [`Archive::extract_events_with`]. The stream always ends with either
+/// [`ExtractEvent::Done`] or [`ExtractEvent::Failed`], after which
+/// [`next`](Self::next) returns `None`.
+///
+/// The events are also a [`futures_core::Stream`], so the combinators of
+/// `futures` or `tokio-stream` work on them:
+///
+/// ```no_run
+/// use compak::{Archive, ExtractEvent};
+/// use futures_core::Stream;
+///
+/// fn events(archive: &Archive) -> impl Stream<Item = ExtractEvent> + use<> {
+///     archive.extract_events("./public")
+/// }
+/// ```
+///
+/// Dropping the stream cancels the extraction at the next entry or buffer
+/// boundary.
+#[derive(Debug)]
+pub struct ExtractEvents {
+    receiver: mpsc::Receiver<ExtractEvent>,
+}
+
+impl ExtractEvents {
+    /// Waits for the next event, returning `None` once the stream has ended.
+    pub async fn next(&mut self) -> Option<ExtractEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for ExtractEvents {
+    type Item = ExtractEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.receiver.len(), None)
+    }
+}
+
+impl Archive {
+    /// Opens an existing archive file without blocking the async runtime.
+    ///
+    /// This is the async counterpart of [`Archive::open`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the archive file to open
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file does not exist or cannot be read
+    /// * The file format cannot be detected or is unsupported
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// async fn handle_upload() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open_async("upload.zip").await?;
+    ///     println!("Opened archive with format: {}", archive.format);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn open_async<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        let path = path.as_ref().to_path_buf();
+        run_blocking(move || Archive::open(path)).await
+    }
+
+    /// Extracts the archive to a directory without blocking the async runtime.
+    ///
+    /// This is the async counterpart of [`Archive::extract_to`].
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`Archive::extract_to`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::Archive;
+    ///
+    /// async fn restore() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open_async("backup.tar.gz").await?;
+    ///     archive.extract_to_async("./restored_files").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn extract_to_async<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+    ) -> Result<ExtractReport, ArchiveError> {
+        let archive = self.clone();
+        let output_dir = output_dir.as_ref().to_path_buf();
+        run_blocking(move || archive.extract_to(output_dir)).await
+    }
+
+    /// Starts extracting the archive in the background and returns a stream
+    /// of progress events.
+    ///
+    /// Extraction runs on Tokio's blocking thread pool and produces the same
+    /// result as [`Archive::extract_to`]; its outcome is reported by the
+    /// final [`ExtractEvent::Done`] or [`ExtractEvent::Failed`] event. If the
+    /// consumer falls behind, the extraction pauses until events are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractEvent};
+    ///
+    /// async fn upload_progress() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open_async("site.tar.gz").await?;
+    ///     let mut events = archive.extract_events("./public");
+    ///     while let Some(event) = events.next().await {
+    ///         match event {
+    ///             ExtractEvent::EntryStarted(info) => println!("-> {}", info.name),
+    ///             ExtractEvent::Failed(err) => return Err(err.into()),
+    ///             _ => {}
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_events<P: AsRef<Path>>(&self, output_dir: P) -> ExtractEvents {
+        self.extract_events_with(output_dir, &ExtractOptions::default())
+    }
+
+    /// Starts extracting the archive in the background with custom options
+    /// and returns a stream of progress events.
+    ///
+    /// This is [`extract_events`](Self::extract_events) with the options of
+    /// [`Archive::extract_to_with`]. Cancelling the token set with
+    /// [`ExtractOptions::cancellation`] stops the extraction too, while
+    /// dropping the stream leaves that token as it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the archive contents will be extracted
+    /// * `options` - Options controlling how entries are extracted
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Archive, ExtractEvent, ExtractOptions, OverwritePolicy};
+    ///
+    /// async fn deploy() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let archive = Archive::open_async("site.tar.gz").await?;
+    ///     let options = ExtractOptions::new().overwrite(OverwritePolicy::Skip);
+    ///     let mut events = archive.extract_events_with("./public", &options);
+    ///     while let Some(event) = events.next().await {
+    ///         if let ExtractEvent::Failed(err) = event {
+    ///             return Err(err.into());
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_events_with<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        options: &ExtractOptions,
+    ) -> ExtractEvents {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let location = self.location();
+        let format = self.format;
+        let output_dir = output_dir.as_ref().to_path_buf();
+        // Dropping the stream must not cancel whatever else shares the
+        // caller's token
+        let token = options.cancellation_token().child_token();
+        let options = options.clone().cancellation(token.clone());
+
+        tokio::task::spawn_blocking(move || {
+            let result = extract::extract(&location, format, &output_dir, &options, &mut |event| {
+                // Send errors only mean the consumer is gone
+                if sender.blocking_send(event).is_err() {
+                    token.cancel();
+                }
+            });
+            let _ = sender.blocking_send(match result {
+                Ok(_) => ExtractEvent::Done,
+                Err(err) => ExtractEvent::Failed(err),
+            });
+        });
+
+        ExtractEvents {
+            receiver,
+        }
+    }
+}
+
+/// Detects the format of an archive file without blocking the async runtime.
+///
+/// This is the async counterpart of [`detect_from_file`](crate::detect_from_file).
+///
+/// # Arguments
+///
+/// * `path` - Path to the file to analyze
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read or its
+/// format cannot be determined.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::detect_from_file_async;
+///
+/// async fn inspect() -> Result<(), Box<dyn std::error::Error>> {
+///     let format = detect_from_file_async("unknown_file").await?;
+///     println!("Detected format: {}", format);
+///     Ok(())
+/// }
+/// ```
+pub async fn detect_from_file_async<P: AsRef<Path>>(
+    path: P,
+) -> Result<ArchiveFormat, ArchiveError> {
+    let path = path.as_ref().to_path_buf();
+    run_blocking(move || format::detect_from_file(path)).await
+}
+
+/// Opens and extracts an archive in a single call without blocking the async
+/// runtime.
+///
+/// This is the async counterpart of [`extract_archive`](crate::extract_archive).
+///
+/// # Arguments
+///
+/// * `archive_path` - Path to the archive file to extract
+/// * `output_dir` - Directory where contents should be extracted
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// extraction fails.
+pub async fn extract_archive_async<P: AsRef<Path>>(
+    archive_path: P,
+    output_dir: P,
+) -> Result<ExtractReport, ArchiveError> {
+    let archive_path = archive_path.as_ref().to_path_buf();
+    let output_dir = output_dir.as_ref().to_path_buf();
+    run_blocking(move || crate::archive::extract_archive(archive_path, output_dir)).await
+}
+
+/// Runs `f` on the blocking thread pool and waits for it to finish.
+///
+/// A panic inside `f` is resumed on the awaiting task rather than being
+/// turned into an error, matching what the synchronous call would do.
+pub(crate) async fn run_blocking<T, F>(f: F) -> Result<T, ArchiveError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ArchiveError> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(ArchiveError::Cancelled),
+    }
+}
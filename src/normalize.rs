@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::{ArchiveError, PathContext},
+    format::{self, ArchiveFormat},
+    volume::Location,
+    writer::FormatWriter,
+};
+
+/// Options controlling how [`Archive::normalize`](crate::Archive::normalize)
+/// rewrites an archive.
+///
+/// The defaults produce output suitable for reproducible builds: every entry
+/// gets the same timestamp (1980-01-01 00:00:00 UTC, the earliest time a ZIP
+/// archive can represent), files get `0o644` (or `0o755` if they were
+/// executable), directories get `0o755`, and ownership is reset to `0:0`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// use compak::NormalizeOptions;
+///
+/// let options = NormalizeOptions::new()
+///     .mtime(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+///     .preserve_executable(false);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    mtime: SystemTime,
+    file_mode: u32,
+    executable_mode: u32,
+    dir_mode: u32,
+    preserve_executable: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            mtime: UNIX_EPOCH + Duration::from_secs(315_532_800),
+            file_mode: 0o644,
+            executable_mode: 0o755,
+            dir_mode: 0o755,
+            preserve_executable: true,
+        }
+    }
+}
+
+impl NormalizeOptions {
+    /// Creates options with the reproducible-build defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the modification time applied to every entry.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Sets the permission bits applied to regular files.
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = mode & 0o7777;
+        self
+    }
+
+    /// Sets the permission bits applied to files that had any execute bit set.
+    ///
+    /// Only used when [`preserve_executable`](Self::preserve_executable) is enabled.
+    pub fn executable_mode(mut self, mode: u32) -> Self {
+        self.executable_mode = mode & 0o7777;
+        self
+    }
+
+    /// Sets the permission bits applied to directories.
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.dir_mode = mode & 0o7777;
+        self
+    }
+
+    /// Whether executable files keep a distinct mode instead of being
+    /// flattened to [`file_mode`](Self::file_mode).
+    pub fn preserve_executable(mut self, preserve: bool) -> Self {
+        self.preserve_executable = preserve;
+        self
+    }
+
+    fn apply(&self, info: &mut EntryInfo) {
+        info.name = entry::normalize_name(&info.name);
+        info.mtime = Some(self.mtime);
+        info.mode = Some(match info.kind {
+            EntryKind::Directory => self.dir_mode,
+            EntryKind::Symlink => 0o777,
+            _ if self.preserve_executable && info.mode.is_some_and(|m| m & 0o111 != 0) => {
+                self.executable_mode
+            }
+            _ => self.file_mode,
+        });
+    }
+}
+
+/// Rewrites the archive at `location` into `dst` in canonical form.
+///
+/// All entries are read into memory first so they can be sorted by name;
+/// the destination format is determined by the extension of `dst`.
+///
+/// # Arguments
+///
+/// * `location` - Where the source archive is read from
+/// * `format` - Format of the source archive
+/// * `dst` - Path of the archive to create
+/// * `options` - Normalization options
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The source archive cannot be read
+/// * The destination extension is not recognized
+/// * An entry cannot be represented in the destination format
+/// * The destination file cannot be written
+pub(crate) fn normalize(
+    location: &Location,
+    format: ArchiveFormat,
+    dst: &Path,
+    options: &NormalizeOptions,
+) -> Result<(), ArchiveError> {
+    let dst_format = format::detect_from_extension(dst)?;
+
+    let mut entries = Vec::new();
+    entry::for_each_entry(location, format, |info, reader| {
+        let mut info = info.clone();
+        options.apply(&mut info);
+        // The archive root (`./`) carries no content of its own
+        if info.name.is_empty() {
+            return Ok(true);
+        }
+        let mut data = Vec::new();
+        if info.kind == EntryKind::File {
+            reader.read_to_end(&mut data)?;
+            info.size = data.len() as u64;
+        }
+        entries.push((info, data));
+        Ok(true)
+    })?;
+    entries.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let file = BufWriter::new(File::create(dst).at_path(dst)?);
+    let mut writer = FormatWriter::new(dst_format, file)?;
+    for (info, data) in &entries {
+        writer.add(info, &mut data.as_slice())?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Container images saved as tarballs.
+//!
+//! `docker save` lists the layers of each image in `manifest.json`. An OCI
+//! image layout instead points from `index.json` to manifests stored as
+//! content-addressed blobs under `blobs/`, which list the layers in turn;
+//! recent Docker releases write both. The layers are tarballs, optionally
+//! compressed, that are unpacked bottom to top by a [`LayerExtractor`].
+//!
+//! Documents and layers are read in place, so the image tarball itself
+//! must be uncompressed.
+
+mod json;
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use self::json::Value;
+use crate::{
+    entry,
+    error::{ArchiveError, PathContext},
+    extract::{self, ExtractEvent, ExtractOptions, ExtractReport, LayerExtractor},
+    format::{self, ArchiveFormat},
+    volume::{Location, Source},
+};
+
+/// Layer list written by `docker save`.
+const DOCKER_MANIFEST: &str = "manifest.json";
+/// Entry point of an OCI image layout.
+const OCI_INDEX: &str = "index.json";
+/// Media types of indexes, whose `manifests` point to further manifests.
+const INDEX_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+/// Upper bound on the size of an index or manifest.
+const MAX_DOCUMENT_SIZE: u64 = 4 * 1024 * 1024;
+/// Upper bound on the number of indexes followed to reach a manifest,
+/// which also guards against loops.
+const MAX_INDEX_DEPTH: usize = 8;
+/// Upper bound on the number of links followed to reach a member's data.
+const MAX_LINKS: usize = 8;
+
+/// Where a member of the image tarball keeps its data.
+enum Member {
+    Data {
+        offset: u64,
+        size: u64,
+    },
+    /// A symlink or hard link, by the normalized name of its target.
+    Link(String),
+}
+
+/// Unpacks the first image of the `docker save` tarball or OCI image
+/// layout tarball at `location` into `output_dir`.
+pub(crate) fn extract_image(
+    location: &Location,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    let mut file = location.open(ArchiveFormat::Tar)?;
+    let members = scan(&mut file)?;
+    let layers = if members.contains_key(DOCKER_MANIFEST) {
+        docker_layers(&mut file, &members)?
+    } else if members.contains_key(OCI_INDEX) {
+        oci_layers(&mut file, &members)?
+    } else {
+        return Err(invalid("neither manifest.json nor index.json found"));
+    };
+
+    extract::atomically(output_dir, options, |output_dir| {
+        let mut extractor = LayerExtractor::new(output_dir, options, observer)?;
+        for name in &layers {
+            let (offset, size) = locate(&members, name)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut layer = BufReader::new((&mut file).take(size));
+            let format = layer_format(&mut layer)?;
+            extractor.apply(layer, format)?;
+        }
+        extractor.finish()
+    })
+}
+
+/// Unpacks the layer tarballs at `layers`, bottom layer first, into
+/// `output_dir`.
+pub(crate) fn extract_layers<P: AsRef<Path>>(
+    layers: &[P],
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<ExtractReport, ArchiveError> {
+    extract::atomically(output_dir, options, |output_dir| {
+        let mut extractor = LayerExtractor::new(output_dir, options, observer)?;
+        for path in layers {
+            let mut layer = BufReader::new(fs::File::open(path).at_path(path)?);
+            let format = layer_format(&mut layer)?;
+            extractor.apply(layer, format)?;
+        }
+        extractor.finish()
+    })
+}
+
+/// Detects the compression of a layer from its first bytes.
+fn layer_format<R: Read>(layer: &mut BufReader<R>) -> Result<ArchiveFormat, ArchiveError> {
+    match format::detect_from_bytes(layer.fill_buf()?) {
+        // Empty layers may be nothing but the end-of-archive blocks
+        None => Ok(ArchiveFormat::Tar),
+        Some(
+            format @ (ArchiveFormat::Tar
+            | ArchiveFormat::TarGz
+            | ArchiveFormat::TarXz
+            | ArchiveFormat::TarBz2
+            | ArchiveFormat::TarZst),
+        ) => Ok(format),
+        Some(_) => Err(invalid("layer is not a tarball")),
+    }
+}
+
+/// Records where every member of the image tarball keeps its data.
+fn scan(file: &mut Source) -> Result<HashMap<String, Member>, ArchiveError> {
+    let mut members = HashMap::new();
+    let mut archive = tar::Archive::new(file);
+    for member in archive.entries_with_seek()? {
+        let member = member?;
+        let name = entry::normalize_name(&member.path()?.to_string_lossy());
+        let link = member
+            .link_name()?
+            .map(|target| entry::normalize_name(&target.to_string_lossy()));
+        let data = match (member.header().entry_type(), link) {
+            (tar::EntryType::Regular | tar::EntryType::Continuous, _) => {
+                Member::Data {
+                    offset: member.raw_file_position(),
+                    size: member.size(),
+                }
+            }
+            (tar::EntryType::Link, Some(target)) => Member::Link(target),
+            (tar::EntryType::Symlink, Some(target)) => {
+                let dir = name.rsplit_once('/').map_or("", |(dir, _)| dir);
+                Member::Link(join(dir, &target))
+            }
+            _ => continue,
+        };
+        members.insert(name, data);
+    }
+    Ok(members)
+}
+
+/// Resolves the relative symlink target `target` of a member in `dir`.
+fn join(dir: &str, target: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Returns the offset and size of the data of member `name`.
+fn locate(members: &HashMap<String, Member>, name: &str) -> Result<(u64, u64), ArchiveError> {
+    let mut name = name;
+    for _ in 0..=MAX_LINKS {
+        match members.get(name) {
+            Some(Member::Data {
+                offset,
+                size,
+            }) => return Ok((*offset, *size)),
+            Some(Member::Link(target)) => name = target,
+            None => {
+                return Err(ArchiveError::InvalidArchive {
+                    format: ArchiveFormat::Tar,
+                    reason: format!("image member '{}' not found", name).into(),
+                });
+            }
+        }
+    }
+    Err(invalid("too many links between image members"))
+}
+
+/// Reads and parses the JSON document in member `name`.
+fn document(
+    file: &mut Source,
+    members: &HashMap<String, Member>,
+    name: &str,
+) -> Result<Value, ArchiveError> {
+    let (offset, size) = locate(members, name)?;
+    if size > MAX_DOCUMENT_SIZE {
+        return Err(invalid("image index or manifest is too large"));
+    }
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(size).read_to_end(&mut data)?;
+    json::parse(&data)
+}
+
+/// Returns the layers of the first image listed in `manifest.json`.
+fn docker_layers(
+    file: &mut Source,
+    members: &HashMap<String, Member>,
+) -> Result<Vec<String>, ArchiveError> {
+    let manifest = document(file, members, DOCKER_MANIFEST)?;
+    let image = manifest
+        .as_array()
+        .and_then(|images| images.first())
+        .ok_or_else(|| invalid("manifest.json lists no images"))?;
+    image
+        .get("Layers")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("image in manifest.json has no layer list"))?
+        .iter()
+        .map(|layer| {
+            layer
+                .as_str()
+                .map(entry::normalize_name)
+                .ok_or_else(|| invalid("malformed layer in manifest.json"))
+        })
+        .collect()
+}
+
+/// Returns the layers of the first image manifest reachable from
+/// `index.json`.
+fn oci_layers(
+    file: &mut Source,
+    members: &HashMap<String, Member>,
+) -> Result<Vec<String>, ArchiveError> {
+    let mut index = document(file, members, OCI_INDEX)?;
+    for _ in 0..MAX_INDEX_DEPTH {
+        let descriptor = index
+            .get("manifests")
+            .and_then(Value::as_array)
+            .and_then(|manifests| manifests.first())
+            .ok_or_else(|| invalid("image index lists no manifests"))?;
+        let nested = descriptor
+            .get("mediaType")
+            .and_then(Value::as_str)
+            .is_some_and(|media_type| INDEX_MEDIA_TYPES.contains(&media_type));
+        let target = document(file, members, &blob_name(descriptor)?)?;
+        if nested {
+            index = target;
+            continue;
+        }
+        return target
+            .get("layers")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("image manifest has no layer list"))?
+            .iter()
+            .map(blob_name)
+            .collect();
+    }
+    Err(invalid("image indexes are nested too deeply"))
+}
+
+/// Returns the member holding the blob a descriptor points to.
+fn blob_name(descriptor: &Value) -> Result<String, ArchiveError> {
+    let digest = descriptor
+        .get("digest")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("descriptor has no digest"))?;
+    let (algorithm, encoded) = digest
+        .split_once(':')
+        .ok_or_else(|| invalid("malformed descriptor digest"))?;
+    Ok(format!("blobs/{}/{}", algorithm, encoded))
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Tar,
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,213 @@
+//! A minimal JSON parser for image indexes and manifests.
+//!
+//! Only strings, arrays and objects are ever looked at, so numbers,
+//! booleans and null are checked loosely and not kept.
+
+use super::invalid;
+use crate::error::ArchiveError;
+
+/// Upper bound on array and object nesting.
+const MAX_DEPTH: usize = 128;
+
+/// A parsed JSON value.
+pub(super) enum Value {
+    /// A number, boolean or null.
+    Scalar,
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the member `key` of an object.
+    pub(super) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => {
+                members
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value)
+            }
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a document holding a single value.
+pub(super) fn parse(document: &[u8]) -> Result<Value, ArchiveError> {
+    let document =
+        std::str::from_utf8(document).map_err(|_| invalid("JSON document is not UTF-8"))?;
+    let mut parser = Parser {
+        input: document.strip_prefix('\u{feff}').unwrap_or(document),
+        position: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.position != parser.input.len() {
+        return Err(invalid("unexpected content after the JSON value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.position += prefix.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        let trimmed = rest.trim_start_matches([' ', '\t', '\n', '\r']);
+        self.position += rest.len() - trimmed.len();
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, ArchiveError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("JSON values are nested too deeply"));
+        }
+        self.skip_whitespace();
+        if self.eat("{") {
+            let mut members = Vec::new();
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Value::Object(members));
+            }
+            loop {
+                self.skip_whitespace();
+                let key = self.string()?;
+                self.skip_whitespace();
+                if !self.eat(":") {
+                    return Err(invalid("malformed JSON object"));
+                }
+                members.push((key, self.value(depth + 1)?));
+                self.skip_whitespace();
+                if self.eat("}") {
+                    return Ok(Value::Object(members));
+                }
+                if !self.eat(",") {
+                    return Err(invalid("malformed JSON object"));
+                }
+            }
+        } else if self.eat("[") {
+            let mut items = Vec::new();
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(Value::Array(items));
+            }
+            loop {
+                items.push(self.value(depth + 1)?);
+                self.skip_whitespace();
+                if self.eat("]") {
+                    return Ok(Value::Array(items));
+                }
+                if !self.eat(",") {
+                    return Err(invalid("malformed JSON array"));
+                }
+            }
+        } else if self.rest().starts_with('"') {
+            Ok(Value::String(self.string()?))
+        } else if self.eat("true") || self.eat("false") || self.eat("null") {
+            Ok(Value::Scalar)
+        } else {
+            let rest = self.rest();
+            let len = rest
+                .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(invalid("unexpected JSON token"));
+            }
+            self.position += len;
+            Ok(Value::Scalar)
+        }
+    }
+
+    /// Parses the string starting at the current position.
+    fn string(&mut self) -> Result<String, ArchiveError> {
+        if !self.eat("\"") {
+            return Err(invalid("expected a JSON string"));
+        }
+        let mut output = String::new();
+        loop {
+            let rest = self.rest();
+            let len = rest
+                .find(['"', '\\'])
+                .ok_or_else(|| invalid("unterminated JSON string"))?;
+            output.push_str(&rest[..len]);
+            self.position += len;
+            if self.eat("\"") {
+                return Ok(output);
+            }
+            self.position += 1;
+            let escape = self
+                .rest()
+                .chars()
+                .next()
+                .ok_or_else(|| invalid("unterminated JSON string"))?;
+            self.position += escape.len_utf8();
+            let decoded = match escape {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let high = self.code_unit()?;
+                    let code = if (0xD800..0xDC00).contains(&high) && self.eat("\\u") {
+                        match self.code_unit()? {
+                            low @ 0xDC00..0xE000 => {
+                                0x10000 + ((high - 0xD800) << 10) + low - 0xDC00
+                            }
+                            _ => 0xFFFD,
+                        }
+                    } else {
+                        high
+                    };
+                    char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                }
+                _ => return Err(invalid("unknown JSON string escape")),
+            };
+            output.push(decoded);
+        }
+    }
+
+    /// Parses the four hex digits of a `\u` escape.
+    fn code_unit(&mut self) -> Result<u32, ArchiveError> {
+        let digits = self
+            .rest()
+            .get(..4)
+            .ok_or_else(|| invalid("truncated JSON string escape"))?;
+        let unit =
+            u32::from_str_radix(digits, 16).map_err(|_| invalid("malformed JSON string escape"))?;
+        self.position += 4;
+        Ok(unit)
+    }
+}
@@ -0,0 +1,150 @@
+//! Restoring the owners of extracted entries.
+//!
+//! Recorded user and group names are mapped to the IDs they have on this
+//! system, falling back to the recorded IDs for names that are unknown,
+//! like `tar --same-owner`. Lookups are cached, since archives tend to name
+//! the same few owners over and over.
+
+use std::{collections::HashMap, io, path::Path};
+
+use crate::entry::Owner;
+
+/// Applies recorded owners to extracted paths.
+pub(crate) struct Owners {
+    /// Whether names are ignored in favor of the recorded IDs.
+    numeric: bool,
+    users: HashMap<String, Option<u32>>,
+    groups: HashMap<String, Option<u32>>,
+}
+
+impl Owners {
+    pub(crate) fn new(numeric: bool) -> Self {
+        Self {
+            numeric,
+            users: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Makes `owner` the owner of `path`, without following a symlink at
+    /// `path`.
+    ///
+    /// Has no effect on non-unix platforms.
+    pub(crate) fn apply(&mut self, path: &Path, owner: &Owner) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let uid = resolve(
+                &mut self.users,
+                owner.user.as_deref().filter(|_| !self.numeric),
+                owner.uid,
+                Kind::User,
+            )?;
+            let gid = resolve(
+                &mut self.groups,
+                owner.group.as_deref().filter(|_| !self.numeric),
+                owner.gid,
+                Kind::Group,
+            )?;
+            std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = (path, owner);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Clone, Copy)]
+enum Kind {
+    User,
+    Group,
+}
+
+/// Returns the ID of `name` on this system, or `id` if there is no name or
+/// no such user or group.
+#[cfg(unix)]
+fn resolve(
+    cache: &mut HashMap<String, Option<u32>>,
+    name: Option<&str>,
+    id: u64,
+    kind: Kind,
+) -> io::Result<u32> {
+    if let Some(name) = name {
+        let found = match cache.get(name) {
+            Some(found) => *found,
+            None => {
+                let found = lookup(name, kind);
+                cache.insert(name.to_string(), found);
+                found
+            }
+        };
+        if let Some(found) = found {
+            return Ok(found);
+        }
+    }
+    u32::try_from(id).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "recorded owner ID is out of range",
+        )
+    })
+}
+
+/// Looks up the ID of the user or group called `name`.
+#[cfg(unix)]
+fn lookup(name: &str, kind: Kind) -> Option<u32> {
+    use std::{ffi::CString, mem::MaybeUninit, ptr};
+
+    /// Upper bound on the buffer for the record's strings.
+    const MAX_BUFFER: usize = 1024 * 1024;
+
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let (status, id) = match kind {
+            Kind::User => {
+                let mut record = MaybeUninit::<libc::passwd>::uninit();
+                let mut result = ptr::null_mut();
+                // SAFETY: every pointer is valid for the duration of the
+                // call, and `buffer.len()` is the size of `buffer`.
+                let status = unsafe {
+                    libc::getpwnam_r(
+                        name.as_ptr(),
+                        record.as_mut_ptr(),
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                        &mut result,
+                    )
+                };
+                // SAFETY: a non-null result points to the filled-in record.
+                let id = (status == 0 && !result.is_null()).then(|| unsafe { (*result).pw_uid });
+                (status, id)
+            }
+            Kind::Group => {
+                let mut record = MaybeUninit::<libc::group>::uninit();
+                let mut result = ptr::null_mut();
+                // SAFETY: as above.
+                let status = unsafe {
+                    libc::getgrnam_r(
+                        name.as_ptr(),
+                        record.as_mut_ptr(),
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                        &mut result,
+                    )
+                };
+                // SAFETY: a non-null result points to the filled-in record.
+                let id = (status == 0 && !result.is_null()).then(|| unsafe { (*result).gr_gid });
+                (status, id)
+            }
+        };
+        if status == libc::ERANGE && buffer.len() < MAX_BUFFER {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        return id;
+    }
+}
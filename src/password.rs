@@ -0,0 +1,305 @@
+//! Passwords for encrypted ZIP entries and 7z archives.
+//!
+//! A password is either fixed up front with
+//! [`ExtractOptions::password`](crate::ExtractOptions::password), picked
+//! from a list set with
+//! [`ExtractOptions::candidate_passwords`](crate::ExtractOptions::candidate_passwords)
+//! or asked for as encrypted entries come up, from a [`PasswordProvider`]
+//! set with
+//! [`ExtractOptions::password_provider`](crate::ExtractOptions::password_provider).
+//! Passwords that opened an entry are tried first on the next, so a
+//! provider is only asked again when none of them fits.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use zeroize::Zeroize;
+
+use crate::{entry::EntryInfo, error::ArchiveError};
+
+/// A password, wiped from memory when it is dropped and left out of
+/// `Debug` output.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::SecretString;
+///
+/// let password = SecretString::new("hunter2");
+/// assert_eq!(password.expose_secret(), "hunter2");
+/// assert_eq!(format!("{password:?}"), "SecretString(***)");
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Wraps `secret`.
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// Returns the password itself.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Supplies passwords for encrypted entries as extraction reaches them.
+///
+/// `attempt` counts from 1 for each entry and goes up every time the
+/// password returned before was wrong; returning `None` gives up, and the
+/// extraction fails with [`ArchiveError::InvalidPassword`]. Entries are
+/// only asked about when neither the password set with
+/// [`ExtractOptions::password`](crate::ExtractOptions::password), nor the
+/// candidates, nor those that opened entries before open them, so a user
+/// is prompted once for an archive with a single password.
+///
+/// ZIP entries are asked about one by one, since each can have its own
+/// password. A 7z archive has one password, asked for with its first
+/// encrypted entry, or, if its headers are encrypted too, with an entry
+/// that has an empty name and stands for the whole archive. 7z keeps no
+/// check value for the password, so it is taken once the start of that
+/// entry decrypts and decodes.
+///
+/// Closures taking the entry and the attempt implement the trait. With
+/// [`ExtractOptions::threads`](crate::ExtractOptions::threads), the
+/// provider can be called from any of the threads, though never from two
+/// at once.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{EntryInfo, ExtractOptions, SecretString};
+///
+/// let options = ExtractOptions::new().password_provider(|entry: &EntryInfo, attempt: u32| {
+///     if attempt > 3 {
+///         return None;
+///     }
+///     eprint!("Password for {} (attempt {attempt} of 3): ", entry.name);
+///     let mut line = String::new();
+///     std::io::stdin().read_line(&mut line).ok()?;
+///     Some(SecretString::new(line.trim_end()))
+/// });
+/// ```
+pub trait PasswordProvider: Send + Sync {
+    /// Returns the password to try on `entry`, or `None` to give up.
+    fn password_for(&self, entry: &EntryInfo, attempt: u32) -> Option<SecretString>;
+}
+
+impl<F> PasswordProvider for F
+where
+    F: Fn(&EntryInfo, u32) -> Option<SecretString> + Send + Sync,
+{
+    fn password_for(&self, entry: &EntryInfo, attempt: u32) -> Option<SecretString> {
+        self(entry, attempt)
+    }
+}
+
+/// Where the password that opened an entry came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// The password set with
+    /// [`ExtractOptions::password`](crate::ExtractOptions::password).
+    Fixed,
+    /// The candidate at this index of the list set with
+    /// [`ExtractOptions::candidate_passwords`](crate::ExtractOptions::candidate_passwords).
+    Candidate(usize),
+    /// The [`PasswordProvider`].
+    Provider,
+}
+
+/// An encrypted entry and the password that opened it, as listed in
+/// [`ExtractReport::unlocked`](crate::ExtractReport::unlocked).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use compak::{Archive, ExtractOptions, PasswordSource};
+///
+/// let candidates = ["2019-backup", "hunter2", "letmein"];
+/// let options = ExtractOptions::new().candidate_passwords(candidates);
+/// let report = Archive::open("old.zip")?.extract_to_with("out", &options)?;
+/// for unlocked in &report.unlocked {
+///     if let PasswordSource::Candidate(index) = unlocked.password {
+///         println!("{}: {}", unlocked.name, candidates[index]);
+///     }
+/// }
+/// # Ok::<(), compak::ArchiveError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnlockedEntry {
+    /// Name of the entry inside the archive.
+    pub name: String,
+    /// Where its password came from.
+    pub password: PasswordSource,
+}
+
+/// The passwords of one extraction: the fixed one, the candidates, the
+/// provider and the passwords that opened entries.
+#[derive(Default)]
+pub(crate) struct Passwords {
+    fixed: Option<SecretString>,
+    candidates: Vec<SecretString>,
+    provider: Option<Arc<dyn PasswordProvider>>,
+    /// The passwords that fit, the latest last. Also held while a
+    /// password is looked for, so that threads take turns asking the
+    /// provider.
+    known: Mutex<Vec<(SecretString, PasswordSource)>>,
+    /// The entries opened with a password, by index, since an entry can be
+    /// opened more than once.
+    unlocked: Mutex<BTreeMap<usize, UnlockedEntry>>,
+}
+
+impl Passwords {
+    pub(crate) fn new(
+        fixed: Option<SecretString>,
+        candidates: &[SecretString],
+        provider: Option<Arc<dyn PasswordProvider>>,
+    ) -> Self {
+        Self {
+            fixed,
+            candidates: candidates.to_vec(),
+            provider,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the fixed password, for formats that take no other.
+    pub(crate) fn fixed(&self) -> Option<&str> {
+        self.fixed.as_ref().map(SecretString::expose_secret)
+    }
+
+    /// Returns `true` if there is more than the fixed password to try.
+    pub(crate) fn has_choices(&self) -> bool {
+        self.provider.is_some() || !self.candidates.is_empty()
+    }
+
+    /// Returns `true` if there is no password to try at all.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fixed.is_none() && !self.has_choices()
+    }
+
+    /// Notes that entry `index`, named `name`, was opened with the
+    /// password from `source`.
+    pub(crate) fn record(&self, index: usize, name: &str, source: PasswordSource) {
+        self.unlocked
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                index,
+                UnlockedEntry {
+                    name: name.to_string(),
+                    password: source,
+                },
+            );
+    }
+
+    /// Returns the entries recorded so far, in the order of their indices,
+    /// and forgets them.
+    pub(crate) fn take_unlocked(&self) -> Vec<UnlockedEntry> {
+        let mut unlocked = self.unlocked.lock().unwrap_or_else(PoisonError::into_inner);
+        std::mem::take(&mut *unlocked).into_values().collect()
+    }
+
+    /// Returns the password that `open` accepts for `entry` and where it
+    /// came from, or `None` if `open` needs no password.
+    ///
+    /// `open` is handed each password to try, and fails with
+    /// [`ArchiveError::InvalidPassword`] for a wrong one. The passwords
+    /// that fit before are tried first, latest first, then the fixed one,
+    /// the candidates in order and then those of the provider until it
+    /// gives up.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ArchiveError::InvalidPassword`] if no password fits, or
+    /// the first other error of `open`.
+    pub(crate) fn unlock<F>(
+        &self,
+        entry: &EntryInfo,
+        mut open: F,
+    ) -> Result<Option<(SecretString, PasswordSource)>, ArchiveError>
+    where
+        F: FnMut(Option<&str>) -> Result<(), ArchiveError>,
+    {
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        let fixed = self
+            .fixed
+            .iter()
+            .map(|fixed| (fixed, PasswordSource::Fixed));
+        let candidates = self
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| (candidate, PasswordSource::Candidate(index)));
+        let mut tried = known
+            .iter()
+            .rev()
+            .cloned()
+            .chain(
+                fixed
+                    .chain(candidates)
+                    .filter(|(password, _)| known.iter().all(|(known, _)| known != *password))
+                    .map(|(password, source)| (password.clone(), source)),
+            )
+            .collect::<Vec<_>>()
+            .into_iter();
+        let mut password = tried.next();
+        let mut attempt = 0;
+        loop {
+            match open(
+                password
+                    .as_ref()
+                    .map(|(password, _)| password.expose_secret()),
+            ) {
+                Ok(()) => {
+                    if let Some((password, _)) = &password {
+                        known.retain(|(known, _)| known != password);
+                    }
+                    known.extend(password.clone());
+                    return Ok(password);
+                }
+                Err(ArchiveError::InvalidPassword) => {}
+                Err(err) => return Err(err),
+            }
+            password = match (tried.next(), &self.provider) {
+                (Some(known), _) => Some(known),
+                (None, Some(provider)) => {
+                    attempt += 1;
+                    let password = provider
+                        .password_for(entry, attempt)
+                        .ok_or(ArchiveError::InvalidPassword)?;
+                    Some((password, PasswordSource::Provider))
+                }
+                (None, None) => return Err(ArchiveError::InvalidPassword),
+            };
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    io::{self, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::error::ArchiveError;
+
+/// A handle for cooperatively cancelling a running extraction or archive
+/// creation.
+///
+/// Clones share the same flag, so one clone can be handed to the operation
+/// and another kept (or sent to another thread) to trigger it. Operations
+/// check the token between entries and between buffer copies, and stop
+/// with [`ArchiveError::Cancelled`] once it is cancelled. Files already
+/// written are left in place.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{CancellationToken, ExtractOptions};
+///
+/// let token = CancellationToken::new();
+/// let options = ExtractOptions::new().cancellation(token.clone());
+///
+/// // e.g. from a Ctrl-C handler
+/// token.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// The token this one was made from with
+    /// [`child_token`](Self::child_token).
+    parent: Option<Box<CancellationToken>>,
+}
+
+impl CancellationToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token that is cancelled along with this one, but can also
+    /// be cancelled on its own without affecting this one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compak::CancellationToken;
+    ///
+    /// let shutdown = CancellationToken::new();
+    /// let upload = shutdown.child_token();
+    ///
+    /// upload.cancel();
+    /// assert!(!shutdown.is_cancelled());
+    ///
+    /// let download = shutdown.child_token();
+    /// shutdown.cancel();
+    /// assert!(download.is_cancelled());
+    /// ```
+    pub fn child_token(&self) -> CancellationToken {
+        Self {
+            cancelled: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// Requests cancellation of every operation holding a clone of this
+    /// token or of its children.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once [`cancel`](Self::cancel) has been called on this
+    /// token or on the one it is a child of.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+}
+
+/// A snapshot of how far an extraction or archive creation has come.
+///
+/// Passed to callbacks registered with
+/// [`ExtractOptions::on_progress`](crate::ExtractOptions::on_progress) and
+/// [`ArchiveBuilder::on_progress`](crate::writer::ArchiveBuilder::on_progress).
+/// The callback runs after every buffer of file data and once more when
+/// each entry completes.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::{ExtractOptions, Progress};
+///
+/// let options = ExtractOptions::new().on_progress(|p: &Progress<'_>| {
+///     eprint!(
+///         "\r{} entries, {} bytes ({})",
+///         p.entries_completed, p.bytes_processed, p.current_entry
+///     );
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// Name of the entry currently being processed.
+    pub current_entry: &'a str,
+    /// Uncompressed bytes processed so far, across all entries.
+    pub bytes_processed: u64,
+    /// Number of entries fully processed so far.
+    pub entries_completed: u64,
+}
+
+/// A caller-supplied progress callback.
+pub(crate) type ProgressCallback = Arc<dyn Fn(&Progress<'_>) + Send + Sync>;
+
+/// Accumulates progress counters, forwards them to an optional callback and
+/// checks for cancellation.
+#[derive(Default)]
+pub(crate) struct ProgressTracker {
+    callback: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    bytes: u64,
+    entries: u64,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(
+        callback: Option<ProgressCallback>,
+        cancellation: Option<CancellationToken>,
+    ) -> Self {
+        Self {
+            callback,
+            cancellation,
+            bytes: 0,
+            entries: 0,
+        }
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: ProgressCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub(crate) fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    /// Returns `true` if the operation has been cancelled.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Fails with [`ArchiveError::Cancelled`] if the operation has been
+    /// cancelled.
+    pub(crate) fn check(&self) -> Result<(), ArchiveError> {
+        if self.is_cancelled() {
+            Err(ArchiveError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records `n` more bytes of `entry`.
+    pub(crate) fn add_bytes(&mut self, entry: &str, n: u64) {
+        self.bytes += n;
+        self.report(entry);
+    }
+
+    /// Records that `entry` is complete.
+    pub(crate) fn finish_entry(&mut self, entry: &str) {
+        self.entries += 1;
+        self.report(entry);
+    }
+
+    /// Wraps `inner` so every read is counted towards `entry`.
+    pub(crate) fn reader<'a>(
+        &'a mut self,
+        entry: &'a str,
+        inner: &'a mut dyn Read,
+    ) -> ProgressReader<'a> {
+        ProgressReader {
+            tracker: self,
+            entry,
+            inner,
+        }
+    }
+
+    fn report(&self, entry: &str) {
+        if let Some(callback) = &self.callback {
+            callback(&Progress {
+                current_entry: entry,
+                bytes_processed: self.bytes,
+                entries_completed: self.entries,
+            });
+        }
+    }
+}
+
+/// A reader that reports every chunk it yields to a [`ProgressTracker`].
+///
+/// Reads fail once the operation is cancelled; callers should check
+/// [`ProgressTracker::is_cancelled`] to report such failures as
+/// [`ArchiveError::Cancelled`].
+pub(crate) struct ProgressReader<'a> {
+    tracker: &'a mut ProgressTracker,
+    entry: &'a str,
+    inner: &'a mut dyn Read,
+}
+
+impl Read for ProgressReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.tracker.is_cancelled() {
+            return Err(io::Error::other("operation cancelled"));
+        }
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.tracker.add_bytes(self.entry, n as u64);
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,295 @@
+//! RAR archives, read through the UnRAR library with the `rar` feature.
+//!
+//! UnRAR only opens archives by path, so archives read from a stream or a
+//! caller's reader are copied to a temporary file first. The later volumes
+//! of a multi-volume archive are looked for next to the first, under the
+//! names RAR gives them. UnRAR hands over the data of an entry in one
+//! piece, so each file is decoded into memory before it is visited.
+//!
+//! Like a 7z archive, a RAR archive is taken to have one password, asked
+//! for with its first encrypted entry, or, if its headers are encrypted
+//! too, with an entry that has an empty name and stands for the whole
+//! archive.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use unrar::{
+    FileHeader,
+    error::{Code, UnrarError},
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::{ArchiveError, PathContext},
+    password::{PasswordSource, Passwords, SecretString},
+};
+
+/// Mask of the file type bits of a Unix mode.
+const UNIX_TYPE_MASK: u32 = 0o170000;
+/// File type bits of a regular file.
+const UNIX_FILE: u32 = 0o100000;
+/// File type bits of a directory.
+const UNIX_DIRECTORY: u32 = 0o040000;
+/// File type bits of a symbolic link.
+const UNIX_SYMLINK: u32 = 0o120000;
+
+/// Number of temporary copies made so far, to keep their names apart.
+static COPIES: AtomicU64 = AtomicU64::new(0);
+
+/// Walks every entry of a RAR archive read from `reader`, which is copied
+/// to a temporary file first.
+///
+/// See [`walk_path`] for the password handling and
+/// [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(reader: R, passwords: &Passwords, visit: F) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let copy = TempCopy::new(reader)?;
+    walk_path(&copy.path, passwords, visit)
+}
+
+/// Walks every entry of the RAR archive at `path` in storage order,
+/// decrypting entries with `passwords`.
+///
+/// Without candidates or a provider the fixed password is taken as it is.
+/// Otherwise the passwords are tried until one opens the first encrypted
+/// entry, or reads the headers if they are encrypted. RAR 5 archives check
+/// passwords against a value stored with them; older ones only tell a
+/// wrong password by the CRC of the entry, which is decoded in full to
+/// find out.
+///
+/// # Errors
+///
+/// Returns [`ArchiveError::InvalidPassword`] if an encrypted entry is
+/// reached without a password that fits, and an error if the archive
+/// cannot be decoded or `visit` fails.
+pub(crate) fn walk_path<F>(
+    path: &Path,
+    passwords: &Passwords,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let password = password(path, passwords)?;
+    let secret = password
+        .as_ref()
+        .map(|(password, _)| password.expose_secret());
+    let mut archive = open(path, secret)?.open_for_processing()?;
+    let mut index = 0;
+    while let Some(header) = archive.read_header()? {
+        let mut info = info(header.entry());
+        let data;
+        (data, archive) = match info.kind {
+            EntryKind::Directory => (Vec::new(), header.skip()?),
+            _ => {
+                header
+                    .read()
+                    .map_err(|err| entry_error(err, info.encrypted))?
+            }
+        };
+        if info.encrypted
+            && let Some((_, source)) = &password
+        {
+            passwords.record(index, &info.name, *source);
+        }
+        if info.kind == EntryKind::Symlink {
+            info.link_target = Some(String::from_utf8_lossy(&data).into_owned());
+        }
+        if !visit(&info, &mut data.as_slice())? {
+            return Ok(());
+        }
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Reads the metadata of every entry of a RAR archive read from `reader`,
+/// which is copied to a temporary file first.
+///
+/// # Errors
+///
+/// See [`list_path`].
+pub(crate) fn list<R: Read>(reader: R) -> Result<Vec<EntryInfo>, ArchiveError> {
+    let copy = TempCopy::new(reader)?;
+    list_path(&copy.path)
+}
+
+/// Reads the metadata of every entry of the RAR archive at `path`, in
+/// storage order, without decoding any entry data.
+///
+/// Symlink targets are stored as entry data, so they are not resolved
+/// here.
+///
+/// # Errors
+///
+/// Returns [`ArchiveError::InvalidPassword`] if the headers are encrypted,
+/// and an error if they cannot be read.
+pub(crate) fn list_path(path: &Path) -> Result<Vec<EntryInfo>, ArchiveError> {
+    open(path, None)?
+        .open_for_listing()?
+        .map(|header| Ok(info(&header?)))
+        .collect()
+}
+
+/// Returns the password to walk the archive at `path` with and where it
+/// came from, or `None` if it needs none.
+fn password(
+    path: &Path,
+    passwords: &Passwords,
+) -> Result<Option<(SecretString, PasswordSource)>, ArchiveError> {
+    if !passwords.has_choices() {
+        return Ok(passwords
+            .fixed()
+            .map(|fixed| (SecretString::new(fixed), PasswordSource::Fixed)));
+    }
+    let (entry, index) = match first_encrypted(path) {
+        Ok(Some((index, entry))) => (entry, Some(index)),
+        Ok(None) => return Ok(None),
+        Err(ArchiveError::InvalidPassword) => (new_entry(String::new(), EntryKind::File), None),
+        Err(err) => return Err(err),
+    };
+    passwords.unlock(&entry, |password| probe(path, password, index))
+}
+
+/// Returns the first encrypted entry of the archive at `path` and its
+/// index, if there is one.
+fn first_encrypted(path: &Path) -> Result<Option<(usize, EntryInfo)>, ArchiveError> {
+    for (index, header) in open(path, None)?.open_for_listing()?.enumerate() {
+        let header = header?;
+        if header.is_encrypted() {
+            return Ok(Some((index, info(&header))));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks that `password` decrypts entry `index` of the archive at `path`,
+/// or its headers if `index` is `None`.
+fn probe(path: &Path, password: Option<&str>, index: Option<usize>) -> Result<(), ArchiveError> {
+    let mut archive = open(path, password)?.open_for_processing()?;
+    let mut position = 0;
+    while let Some(header) = archive
+        .read_header()
+        .map_err(|err| entry_error(err, true))?
+    {
+        if index.is_none_or(|index| index == position) {
+            header.test().map_err(|err| entry_error(err, true))?;
+            return Ok(());
+        }
+        archive = header.skip()?;
+        position += 1;
+    }
+    Ok(())
+}
+
+/// Prepares the archive at `path` for opening with `password`.
+fn open<'a>(path: &'a Path, password: Option<&'a str>) -> Result<unrar::Archive<'a>, ArchiveError> {
+    match password {
+        // UnRAR takes passwords as C strings
+        Some(password) if password.contains('\0') => Err(ArchiveError::InvalidPassword),
+        Some(password) => Ok(unrar::Archive::with_password(path, password)),
+        None => Ok(unrar::Archive::new(path)),
+    }
+}
+
+/// Converts an error decoding an entry, which for an `encrypted` one is
+/// most likely a wrong password if its data doesn't check out.
+fn entry_error(err: UnrarError, encrypted: bool) -> ArchiveError {
+    match err.code {
+        Code::BadData if encrypted => ArchiveError::InvalidPassword,
+        _ => err.into(),
+    }
+}
+
+/// Builds the metadata of the entry described by `header`.
+///
+/// RAR keeps Unix modes for archives made on Unix and Windows attributes
+/// for the others, so the attributes are only taken for a mode if they
+/// carry a file type Windows attributes don't combine to.
+fn info(header: &FileHeader) -> EntryInfo {
+    let attributes = header.file_attr;
+    let mode = matches!(
+        attributes & UNIX_TYPE_MASK,
+        UNIX_FILE | UNIX_DIRECTORY | UNIX_SYMLINK
+    )
+    .then_some(attributes);
+    let kind = if header.is_directory() {
+        EntryKind::Directory
+    } else if mode.is_some_and(|mode| mode & UNIX_TYPE_MASK == UNIX_SYMLINK) {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    };
+    let time = header.file_time;
+    EntryInfo {
+        size: header.unpacked_size,
+        mtime: zip::DateTime::try_from_msdos((time >> 16) as u16, time as u16)
+            .ok()
+            .and_then(entry::dos_time),
+        mode: mode.map(|mode| mode & 0o7777),
+        encrypted: header.is_encrypted(),
+        ..new_entry(header.filename.to_string_lossy().into_owned(), kind)
+    }
+}
+
+/// Returns the metadata of an empty entry called `name`.
+fn new_entry(name: String, kind: EntryKind) -> EntryInfo {
+    EntryInfo {
+        name,
+        kind,
+        size: 0,
+        compressed_size: None,
+        mtime: None,
+        mode: None,
+        link_target: None,
+        owner: None,
+        xattrs: Vec::new(),
+        raw_name: None,
+        special: None,
+        comment: None,
+        extra_fields: Vec::new(),
+        encrypted: false,
+    }
+}
+
+/// A copy of an archive in the temporary directory, removed when dropped.
+struct TempCopy {
+    path: PathBuf,
+}
+
+impl TempCopy {
+    /// Copies everything `reader` yields to a new temporary file.
+    fn new<R: Read>(mut reader: R) -> Result<Self, ArchiveError> {
+        let path = std::env::temp_dir().join(format!(
+            "compak-{}-{}.rar",
+            process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .at_path(&path)?;
+        let copy = Self {
+            path,
+        };
+        io::copy(&mut reader, &mut file)?;
+        Ok(copy)
+    }
+}
+
+impl Drop for TempCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
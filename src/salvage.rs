@@ -0,0 +1,426 @@
+//! Recovering the entries of damaged ZIP archives.
+//!
+//! ZIP archives are read through their central directory, which sits at
+//! the very end of the file and is the first thing an interrupted download
+//! loses. Every entry is also preceded by a local header of its own, so a
+//! damaged archive is scanned for those instead. Each entry found is
+//! decoded and checked against its CRC-32 before anything is written, and
+//! only the intact ones are extracted.
+//!
+//! Local headers lack what only the central directory records, such as
+//! permissions and symbolic links, so every entry comes out as a file or a
+//! directory. Entries whose sizes follow their data, as in archives written
+//! to a stream, can only be recovered if they are deflate-compressed, since
+//! the end of their data can't be found otherwise.
+
+use std::{
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use flate2::CrcWriter;
+
+use crate::{
+    encoding,
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    extract::{self, ExtractEvent, ExtractOptions, ExtractReport},
+    format::ArchiveFormat,
+    verify::EntryCheck,
+    volume::Location,
+};
+
+const LOCAL_HEADER_SIGNATURE: [u8; 4] = *b"PK\x03\x04";
+const DATA_DESCRIPTOR_SIGNATURE: [u8; 4] = *b"PK\x07\x08";
+/// Size of a local header up to the name.
+const LOCAL_HEADER_SIZE: usize = 30;
+/// Extra field holding the sizes of entries too large for the header.
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const METHOD_BZIP2: u16 = 12;
+const METHOD_ZSTD: u16 = 93;
+
+/// How much of the archive is searched for a header at once.
+const SCAN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The result of [`Archive::salvage_to`](crate::Archive::salvage_to).
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    /// Every entry found, in archive order, with why it couldn't be
+    /// recovered if it couldn't.
+    pub entries: Vec<EntryCheck>,
+    /// The extraction of the intact entries.
+    pub extraction: ExtractReport,
+}
+
+impl SalvageReport {
+    /// Returns the entries that were intact and extracted.
+    pub fn recovered(&self) -> impl Iterator<Item = &EntryCheck> {
+        self.entries.iter().filter(|entry| entry.passed())
+    }
+
+    /// Returns the entries that were found but damaged.
+    pub fn damaged(&self) -> impl Iterator<Item = &EntryCheck> {
+        self.entries.iter().filter(|entry| !entry.passed())
+    }
+}
+
+/// A local header, with what is known of the entry following it.
+struct Header {
+    info: EntryInfo,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    /// Whether the sizes are in a ZIP64 extra field, which makes those of
+    /// the data descriptor 8 bytes long.
+    zip64: bool,
+    /// Offset of the entry data.
+    data_start: u64,
+}
+
+/// Extracts every intact entry found by scanning the ZIP archive at
+/// `location` for local headers.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The archive cannot be read
+/// * An intact entry cannot be extracted, under the same conditions as
+///   [`extract::extract`]
+pub(crate) fn salvage(
+    location: &Location,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    observer: &mut dyn FnMut(ExtractEvent),
+) -> Result<SalvageReport, ArchiveError> {
+    let mut reader = location.open(ArchiveFormat::Zip)?;
+    let mut entries = Vec::new();
+    let mut intact = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = find_header(&mut reader, position)? {
+        let Some(mut header) = read_header(&mut reader, offset)? else {
+            position = offset + 1;
+            continue;
+        };
+        let mut bytes = 0;
+        match check(&mut reader, &mut header, &mut bytes) {
+            Ok(end) => {
+                entries.push(EntryCheck {
+                    name: header.info.name.clone(),
+                    kind: header.info.kind,
+                    bytes,
+                    error: None,
+                });
+                intact.push(header);
+                position = end;
+            }
+            Err(err) => {
+                entries.push(EntryCheck {
+                    name: header.info.name.clone(),
+                    kind: header.info.kind,
+                    bytes,
+                    error: Some(err),
+                });
+                // The sizes of a damaged entry can't be trusted to lead to
+                // the next one
+                position = offset + 1;
+            }
+        }
+    }
+
+    let extraction = extract::run(
+        ArchiveFormat::Zip,
+        output_dir,
+        options,
+        location.len(),
+        observer,
+        |visit| {
+            for header in &intact {
+                reader.seek(SeekFrom::Start(header.data_start))?;
+                let data = (&mut reader).take(header.compressed_size);
+                if !visit(&header.info, &mut decoder(header.method, data)?)? {
+                    break;
+                }
+            }
+            Ok(())
+        },
+    )?;
+    Ok(SalvageReport {
+        entries,
+        extraction,
+    })
+}
+
+/// Returns the offset of the first local header signature at or after
+/// `from`, or `None` if there is none.
+fn find_header<R: Read + Seek>(reader: &mut R, from: u64) -> io::Result<Option<u64>> {
+    reader.seek(SeekFrom::Start(from))?;
+    let mut buf = vec![0; SCAN_BUFFER_SIZE];
+    let mut start = from;
+    // Bytes kept from the end of the last read, which may begin a
+    // signature
+    let mut kept = 0;
+    loop {
+        let n = match reader.read(&mut buf[kept..]) {
+            Ok(0) => return Ok(None),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let len = kept + n;
+        if let Some(i) = buf[..len]
+            .windows(LOCAL_HEADER_SIGNATURE.len())
+            .position(|window| window == LOCAL_HEADER_SIGNATURE)
+        {
+            return Ok(Some(start + i as u64));
+        }
+        kept = len.min(LOCAL_HEADER_SIGNATURE.len() - 1);
+        buf.copy_within(len - kept..len, 0);
+        start += (len - kept) as u64;
+    }
+}
+
+/// Reads the local header at `offset`, or returns `None` if it is cut
+/// short or doesn't look like one.
+fn read_header<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<Option<Header>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut fixed = [0; LOCAL_HEADER_SIZE];
+    if !read_exact_or_eof(reader, &mut fixed)? {
+        return Ok(None);
+    }
+    let flags = le16(&fixed[6..]);
+    let method = le16(&fixed[8..]);
+    let time = le16(&fixed[10..]);
+    let date = le16(&fixed[12..]);
+    let crc = le32(&fixed[14..]);
+    let mut compressed_size = u64::from(le32(&fixed[18..]));
+    let mut size = u64::from(le32(&fixed[22..]));
+    let mut name = vec![0; le16(&fixed[26..]) as usize];
+    let mut extra = vec![0; le16(&fixed[28..]) as usize];
+    if name.is_empty()
+        || !read_exact_or_eof(reader, &mut name)?
+        || !read_exact_or_eof(reader, &mut extra)?
+    {
+        return Ok(None);
+    }
+
+    let mut zip64 = false;
+    let mut unicode_path = None;
+    let mut fields = extra.as_slice();
+    while fields.len() >= 4 {
+        let id = le16(fields);
+        let len = (le16(&fields[2..]) as usize).min(fields.len() - 4);
+        let mut data = &fields[4..4 + len];
+        if id == ZIP64_EXTRA_ID {
+            zip64 = true;
+            // Only the sizes that don't fit in the header are present, in
+            // this order
+            for value in [&mut size, &mut compressed_size] {
+                if *value == u64::from(u32::MAX) && data.len() >= 8 {
+                    *value = le64(data);
+                    data = &data[8..];
+                }
+            }
+        } else if id == encoding::UNICODE_PATH_EXTRA_ID {
+            unicode_path = Some(data);
+        }
+        fields = &fields[4 + len..];
+    }
+
+    let (name, raw_name) = encoding::zip_name(name, flags, unicode_path);
+    let extra_fields = entry::extra_fields(&extra);
+    let kind = if name.ends_with('/') {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+    let info = EntryInfo {
+        name,
+        kind,
+        size,
+        compressed_size: Some(compressed_size),
+        mtime: entry::zip_mtime(
+            &extra_fields,
+            zip::DateTime::try_from_msdos(date, time).ok(),
+        ),
+        mode: None,
+        link_target: None,
+        owner: entry::zip_owner(&extra_fields),
+        xattrs: Vec::new(),
+        raw_name,
+        special: None,
+        comment: None,
+        extra_fields,
+        encrypted: flags & FLAG_ENCRYPTED != 0,
+    };
+    Ok(Some(Header {
+        info,
+        flags,
+        method,
+        crc,
+        compressed_size,
+        zip64,
+        data_start: reader.stream_position()?,
+    }))
+}
+
+/// Decodes the entry of `header` and checks it against its CRC-32 and
+/// size, adding the bytes decoded to `bytes`, and returns the offset where
+/// the entry ends.
+///
+/// The sizes of `header` are filled in from the data descriptor of entries
+/// that have one.
+fn check<R: Read + Seek>(
+    reader: &mut R,
+    header: &mut Header,
+    bytes: &mut u64,
+) -> Result<u64, ArchiveError> {
+    if header.flags & FLAG_ENCRYPTED != 0 {
+        return Err(ArchiveError::unsupported_static(
+            "recovering encrypted ZIP entries",
+        ));
+    }
+    reader.seek(SeekFrom::Start(header.data_start))?;
+    let mut crc = CrcWriter::new(io::sink());
+    let descriptor = header.flags & FLAG_DATA_DESCRIPTOR != 0;
+
+    let data_end = if descriptor && header.compressed_size == 0 {
+        if header.method != METHOD_DEFLATED {
+            return Err(ArchiveError::unsupported_static(
+                "recovering ZIP entries without sizes that aren't deflate-compressed",
+            ));
+        }
+        // The deflate stream marks its own end, which gives the
+        // compressed size
+        let mut decoder = flate2::bufread::DeflateDecoder::new(BufReader::new(&mut *reader));
+        copy_counted(&mut decoder, &mut crc, bytes)?;
+        header.data_start + decoder.total_in()
+    } else {
+        let data = (&mut *reader).take(header.compressed_size);
+        copy_counted(&mut decoder(header.method, data)?, &mut crc, bytes)?;
+        header.data_start + header.compressed_size
+    };
+
+    let (expected_crc, expected_size, end) = if descriptor {
+        reader.seek(SeekFrom::Start(data_end))?;
+        let size_len = if header.zip64 { 8 } else { 4 };
+        let mut fields = [0; 20];
+        let len = 4 + 2 * size_len;
+        let fields = &mut fields[..len];
+        // The descriptor signature is optional, so the first four bytes are
+        // either the signature or the CRC-32
+        let cut_short = !read_exact_or_eof(reader, &mut fields[..4])?;
+        let signed = fields[..4] == DATA_DESCRIPTOR_SIGNATURE;
+        let cut_short = cut_short
+            || !read_exact_or_eof(reader, if signed { fields } else { &mut fields[4..] })?;
+        if cut_short {
+            return Err(invalid("data descriptor is cut short"));
+        }
+        let end = data_end + len as u64 + if signed { 4 } else { 0 };
+        let read_size = |bytes: &[u8]| {
+            if header.zip64 {
+                le64(bytes)
+            } else {
+                u64::from(le32(bytes))
+            }
+        };
+        let compressed_size = read_size(&fields[4..]);
+        if compressed_size != data_end - header.data_start {
+            return Err(invalid("compressed size doesn't match the data descriptor"));
+        }
+        let size = read_size(&fields[4 + size_len..]);
+        header.compressed_size = compressed_size;
+        header.info.compressed_size = Some(compressed_size);
+        header.info.size = size;
+        (le32(fields), size, end)
+    } else {
+        (header.crc, header.info.size, data_end)
+    };
+
+    if *bytes != expected_size {
+        return Err(invalid("entry is cut short"));
+    }
+    if crc.crc().sum() != expected_crc {
+        return Err(invalid("CRC-32 mismatch"));
+    }
+    Ok(end)
+}
+
+/// Returns a reader decompressing `data` with the ZIP compression
+/// `method`.
+fn decoder<'a, R: Read + 'a>(method: u16, data: R) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    entry::decoded(data, |data| method_decoder(method, data))
+}
+
+/// Returns the decompressor of [`decoder`] without the error mapping.
+fn method_decoder<'a, R: Read + 'a>(
+    method: u16,
+    data: R,
+) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    Ok(match method {
+        METHOD_STORED => Box::new(data),
+        METHOD_DEFLATED => Box::new(flate2::read::DeflateDecoder::new(data)),
+        METHOD_BZIP2 => Box::new(bzip2::read::BzDecoder::new(data)),
+        METHOD_ZSTD => Box::new(zstd::stream::read::Decoder::new(data)?),
+        _ => {
+            return Err(ArchiveError::unsupported_static(
+                "recovering ZIP entries with this compression method",
+            ));
+        }
+    })
+}
+
+/// Copies `reader` to `writer`, adding the bytes copied to `count` as it
+/// goes so they are known even if copying fails.
+fn copy_counted<R: Read, W: io::Write>(
+    reader: &mut R,
+    writer: &mut W,
+    count: &mut u64,
+) -> io::Result<()> {
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..n])?;
+        *count += n as u64;
+    }
+}
+
+/// Fills `buf`, returning `false` if the end of `reader` comes first.
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Zip,
+        reason: reason.into(),
+    }
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(le)
+}
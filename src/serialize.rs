@@ -0,0 +1,541 @@
+//! `serde` support, enabled by the `serde` feature.
+//!
+//! Enums without data are written as the names of their variants, and
+//! structs as maps of their fields, so listings and reports come out as
+//! plain JSON objects. Formats added with
+//! [`register_format`](crate::register_format) are written as the name
+//! they were registered under, and can only be read back while they are
+//! registered.
+//!
+//! Reports can be written but not read back: the errors they hold are
+//! written as their messages, which don't carry enough to rebuild them.
+
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    ser::SerializeStruct,
+};
+
+use crate::{
+    audit::{Finding, Issue},
+    backend,
+    entry::{EntryInfo, EntryKind, ExtraField, Owner, SpecialFile, SpecialKind},
+    error::ArchiveError,
+    extract::{
+        Conflict, ConflictResolution, CrcMismatch, CrcMismatchPolicy, ExtractReport,
+        ExtractionOutcome, OverwritePolicy, PlannedEntry, SpecialFilePolicy, SymlinkPolicy,
+        UnsafePathPolicy, WindowsNamePolicy,
+    },
+    format::{ArchiveFormat, Candidate, Confidence, DetectionReport, Evidence},
+    hash::{ChecksumManifest, ContentHash, EntryDigest, HashAlgo},
+    password::{PasswordSource, UnlockedEntry},
+    salvage::SalvageReport,
+    stat::ArchiveStats,
+    verify::{EntryCheck, VerifyReport},
+};
+
+/// Reads a string and looks it up with `parse`.
+struct NameVisitor<T> {
+    expecting: &'static str,
+    parse: fn(&str) -> Option<T>,
+    marker: PhantomData<T>,
+}
+
+impl<T> NameVisitor<T> {
+    fn new(expecting: &'static str, parse: fn(&str) -> Option<T>) -> Self {
+        Self {
+            expecting,
+            parse,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Visitor<'_> for NameVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.expecting)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        (self.parse)(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+/// Writes and reads an enum without data as the name of its variant.
+macro_rules! named_enum {
+    ($type:ident, $expecting:literal, { $($variant:ident),* $(,)? }) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(match self {
+                    $($type::$variant => stringify!($variant),)*
+                })
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_str(NameVisitor::new($expecting, |name| match name {
+                    $(stringify!($variant) => Some($type::$variant),)*
+                    _ => None,
+                }))
+            }
+        }
+    };
+}
+
+/// Writes a struct as a map of the given fields.
+macro_rules! serialize_struct {
+    ($type:ident { $($field:ident),* $(,)? }) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let fields = [$(stringify!($field)),*];
+                let mut state = serializer.serialize_struct(stringify!($type), fields.len())?;
+                $(state.serialize_field(stringify!($field), &self.$field)?;)*
+                state.end()
+            }
+        }
+    };
+}
+
+/// Reads a struct from a map or sequence of its fields, in declaration
+/// order. Optional fields default to `None` and defaulted ones to their
+/// [`Default`] when missing from a map, and unknown fields are ignored.
+macro_rules! deserialize_struct {
+    ($type:ident, $expecting:literal, { $($required:ident),* } { $($optional:ident),* }) => {
+        deserialize_struct!($type, $expecting, { $($required),* } { $($optional),* } {});
+    };
+    (
+        $type:ident,
+        $expecting:literal,
+        { $($required:ident),* } { $($optional:ident),* } { $($defaulted:ident),* }
+    ) => {
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct FieldVisitor;
+
+                impl<'de> Visitor<'de> for FieldVisitor {
+                    type Value = $type;
+
+                    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        f.write_str($expecting)
+                    }
+
+                    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<$type, A::Error> {
+                        $(let $required = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::missing_field(stringify!($required)))?;)*
+                        $(let $optional = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::missing_field(stringify!($optional)))?;)*
+                        $(let $defaulted = seq.next_element()?.unwrap_or_default();)*
+                        Ok($type {
+                            $($required,)*
+                            $($optional,)*
+                            $($defaulted,)*
+                        })
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<$type, A::Error> {
+                        $(let mut $required = None;)*
+                        $(let mut $optional = None;)*
+                        $(let mut $defaulted = None;)*
+                        while let Some(key) = map.next_key::<String>()? {
+                            match key.as_str() {
+                                $(stringify!($required) => $required = Some(map.next_value()?),)*
+                                $(stringify!($optional) => $optional = map.next_value()?,)*
+                                $(stringify!($defaulted) => $defaulted = Some(map.next_value()?),)*
+                                _ => {
+                                    map.next_value::<IgnoredAny>()?;
+                                }
+                            }
+                        }
+                        Ok($type {
+                            $($required: $required
+                                .ok_or_else(|| de::Error::missing_field(stringify!($required)))?,)*
+                            $($optional,)*
+                            $($defaulted: $defaulted.unwrap_or_default(),)*
+                        })
+                    }
+                }
+
+                const FIELDS: &[&str] = &[
+                    $(stringify!($required),)*
+                    $(stringify!($optional),)*
+                    $(stringify!($defaulted),)*
+                ];
+                deserializer.deserialize_struct(stringify!($type), FIELDS, FieldVisitor)
+            }
+        }
+    };
+}
+
+impl Serialize for ArchiveFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            ArchiveFormat::Zip => "Zip",
+            ArchiveFormat::TarGz => "TarGz",
+            ArchiveFormat::TarXz => "TarXz",
+            ArchiveFormat::TarBz2 => "TarBz2",
+            ArchiveFormat::TarZst => "TarZst",
+            ArchiveFormat::TarLz4 => "TarLz4",
+            ArchiveFormat::TarBr => "TarBr",
+            ArchiveFormat::TarLz => "TarLz",
+            ArchiveFormat::TarLzma => "TarLzma",
+            ArchiveFormat::TarZ => "TarZ",
+            ArchiveFormat::Tar => "Tar",
+            ArchiveFormat::SevenZ => "SevenZ",
+            ArchiveFormat::Rar => "Rar",
+            ArchiveFormat::Gzip => "Gzip",
+            ArchiveFormat::Xz => "Xz",
+            ArchiveFormat::Bzip2 => "Bzip2",
+            ArchiveFormat::Zstd => "Zstd",
+            ArchiveFormat::Lz4 => "Lz4",
+            ArchiveFormat::Brotli => "Brotli",
+            ArchiveFormat::Lzip => "Lzip",
+            ArchiveFormat::Lzma => "Lzma",
+            ArchiveFormat::Compress => "Compress",
+            ArchiveFormat::Ar => "Ar",
+            ArchiveFormat::Deb => "Deb",
+            ArchiveFormat::Iso => "Iso",
+            ArchiveFormat::Squashfs => "Squashfs",
+            ArchiveFormat::Wim => "Wim",
+            ArchiveFormat::Xar => "Xar",
+            ArchiveFormat::Msi => "Msi",
+            ArchiveFormat::AppImage => "AppImage",
+            ArchiveFormat::Custom(custom) => custom.name(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ArchiveFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(NameVisitor::new("an archive format", |name| {
+            Some(match name {
+                "Zip" => ArchiveFormat::Zip,
+                "TarGz" => ArchiveFormat::TarGz,
+                "TarXz" => ArchiveFormat::TarXz,
+                "TarBz2" => ArchiveFormat::TarBz2,
+                "TarZst" => ArchiveFormat::TarZst,
+                "TarLz4" => ArchiveFormat::TarLz4,
+                "TarBr" => ArchiveFormat::TarBr,
+                "TarLz" => ArchiveFormat::TarLz,
+                "TarLzma" => ArchiveFormat::TarLzma,
+                "TarZ" => ArchiveFormat::TarZ,
+                "Tar" => ArchiveFormat::Tar,
+                "SevenZ" => ArchiveFormat::SevenZ,
+                "Rar" => ArchiveFormat::Rar,
+                "Gzip" => ArchiveFormat::Gzip,
+                "Xz" => ArchiveFormat::Xz,
+                "Bzip2" => ArchiveFormat::Bzip2,
+                "Zstd" => ArchiveFormat::Zstd,
+                "Lz4" => ArchiveFormat::Lz4,
+                "Brotli" => ArchiveFormat::Brotli,
+                "Lzip" => ArchiveFormat::Lzip,
+                "Lzma" => ArchiveFormat::Lzma,
+                "Compress" => ArchiveFormat::Compress,
+                "Ar" => ArchiveFormat::Ar,
+                "Deb" => ArchiveFormat::Deb,
+                "Iso" => ArchiveFormat::Iso,
+                "Squashfs" => ArchiveFormat::Squashfs,
+                "Wim" => ArchiveFormat::Wim,
+                "Xar" => ArchiveFormat::Xar,
+                "Msi" => ArchiveFormat::Msi,
+                "AppImage" => ArchiveFormat::AppImage,
+                _ => return backend::registered_by_name(name),
+            })
+        }))
+    }
+}
+
+named_enum!(EntryKind, "an entry kind", {
+    File,
+    Directory,
+    Symlink,
+    Hardlink,
+    Other,
+});
+
+named_enum!(OverwritePolicy, "an overwrite policy", {
+    Overwrite,
+    Skip,
+    Error,
+    RenameNew,
+    BackupExisting,
+});
+
+named_enum!(UnsafePathPolicy, "an unsafe path policy", {
+    Skip,
+    Sanitize,
+    Error,
+});
+
+named_enum!(SpecialFilePolicy, "a special file policy", {
+    Skip,
+    Fifos,
+    Recreate,
+    Error,
+});
+
+named_enum!(WindowsNamePolicy, "a Windows name policy", {
+    Keep,
+    Escape,
+    Rename,
+    Skip,
+    Error,
+});
+
+named_enum!(SymlinkPolicy, "a symlink policy", {
+    Preserve,
+    Skip,
+    Materialize,
+    Error,
+});
+
+named_enum!(CrcMismatchPolicy, "a CRC mismatch policy", {
+    Error,
+    Warn,
+    Skip,
+});
+
+named_enum!(Confidence, "a detection confidence", {
+    Low,
+    Medium,
+    High,
+});
+
+named_enum!(Evidence, "a kind of detection evidence", {
+    MagicBytes,
+    Contents,
+    Extension,
+});
+
+named_enum!(HashAlgo, "a hash algorithm", {
+    Sha256,
+    Blake3,
+});
+
+named_enum!(Issue, "a metadata issue", {
+    DuplicateName,
+    AbsolutePath,
+    PathTraversal,
+    SpecialFile,
+    Setuid,
+    FutureTimestamp,
+});
+
+serialize_struct!(EntryInfo {
+    name,
+    kind,
+    size,
+    compressed_size,
+    mtime,
+    mode,
+    link_target,
+    owner,
+    xattrs,
+    raw_name,
+    special,
+    comment,
+    extra_fields,
+    encrypted,
+});
+
+deserialize_struct!(EntryInfo, "an archive entry", {
+    name, kind, size
+} {
+    compressed_size, mtime, mode, link_target, owner
+} {
+    xattrs, raw_name, special, comment, extra_fields, encrypted
+});
+
+serialize_struct!(Owner {
+    uid,
+    gid,
+    user,
+    group
+});
+
+deserialize_struct!(Owner, "an entry owner", { uid, gid } { user, group });
+
+serialize_struct!(SpecialFile {
+    kind,
+    major,
+    minor
+});
+
+deserialize_struct!(SpecialFile, "a special file", { kind, major, minor } {});
+
+serialize_struct!(ExtraField {
+    id,
+    data
+});
+
+deserialize_struct!(ExtraField, "a ZIP extra field", { id, data } {});
+
+named_enum!(SpecialKind, "a special file type", {
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+});
+
+/// Written as its message.
+impl Serialize for ArchiveError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Written as `"Overwritten"` or `"Skipped"`, or as a map from the variant
+/// name to the path for the others.
+impl Serialize for ConflictResolution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        const NAME: &str = "ConflictResolution";
+        match self {
+            ConflictResolution::Overwritten => {
+                serializer.serialize_unit_variant(NAME, 0, "Overwritten")
+            }
+            ConflictResolution::Skipped => serializer.serialize_unit_variant(NAME, 1, "Skipped"),
+            ConflictResolution::Renamed(path) => {
+                serializer.serialize_newtype_variant(NAME, 2, "Renamed", path)
+            }
+            ConflictResolution::BackedUp(path) => {
+                serializer.serialize_newtype_variant(NAME, 3, "BackedUp", path)
+            }
+        }
+    }
+}
+
+serialize_struct!(Conflict {
+    name,
+    path,
+    resolution,
+});
+
+serialize_struct!(CrcMismatch {
+    name,
+    path
+});
+
+serialize_struct!(ExtractReport {
+    files,
+    directories,
+    symlinks,
+    hardlinks,
+    special_files,
+    bytes_written,
+    bytes_read,
+    skipped,
+    duration,
+    conflicts,
+    crc_mismatches,
+    malformed,
+    planned,
+    unlocked,
+});
+
+/// Written as `"Fixed"` or `"Provider"`, or as a map from `"Candidate"` to
+/// the index of the candidate.
+impl Serialize for PasswordSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        const NAME: &str = "PasswordSource";
+        match self {
+            PasswordSource::Fixed => serializer.serialize_unit_variant(NAME, 0, "Fixed"),
+            PasswordSource::Candidate(index) => {
+                serializer.serialize_newtype_variant(NAME, 1, "Candidate", index)
+            }
+            PasswordSource::Provider => serializer.serialize_unit_variant(NAME, 2, "Provider"),
+        }
+    }
+}
+
+serialize_struct!(UnlockedEntry {
+    name,
+    password
+});
+
+serialize_struct!(PlannedEntry {
+    name,
+    path,
+    kind,
+    size,
+});
+
+serialize_struct!(ExtractionOutcome {
+    extracted,
+    failed,
+    error,
+    report,
+});
+
+serialize_struct!(EntryCheck {
+    name,
+    kind,
+    bytes,
+    error,
+});
+
+serialize_struct!(VerifyReport {
+    entries,
+    error
+});
+
+serialize_struct!(SalvageReport {
+    entries,
+    extraction,
+});
+
+serialize_struct!(Candidate {
+    format,
+    confidence,
+    evidence,
+});
+
+serialize_struct!(DetectionReport {
+    candidates
+});
+
+serialize_struct!(ArchiveStats {
+    entries,
+    files,
+    directories,
+    symlinks,
+    hardlinks,
+    other,
+    size,
+    compressed_size,
+    archive_size,
+});
+
+serialize_struct!(Finding {
+    name,
+    issue
+});
+
+deserialize_struct!(Finding, "an audit finding", { name, issue } {});
+
+/// Written as lowercase hex.
+impl Serialize for ContentHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Written with the digest as lowercase hex.
+impl Serialize for EntryDigest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("EntryDigest", 2)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("digest", &self.to_hex())?;
+        state.end()
+    }
+}
+
+serialize_struct!(ChecksumManifest {
+    algorithm,
+    entries
+});
@@ -0,0 +1,337 @@
+//! Sparse TAR entries, which leave the holes of a file out of the archive.
+//!
+//! GNU tar records the data regions of a sparse file either in old GNU
+//! sparse headers, which the tar crate expands by itself, or in PAX
+//! extended headers. The PAX formats come in three versions: 0.0 repeats
+//! `GNU.sparse.offset` and `GNU.sparse.numbytes` records, 0.1 lists the
+//! regions in a single `GNU.sparse.map` record, and 1.0 stores the map in
+//! front of the entry data. Either way the entry data is the concatenated
+//! regions, which [`Expanded`] turns back into the whole file.
+//!
+//! When packing, the regions of files with holes are found with
+//! `SEEK_DATA` and `SEEK_HOLE` and written as old GNU sparse entries.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::{error::ArchiveError, format::ArchiveFormat};
+
+/// Upper bound on the length of a number in a PAX 1.0 sparse map.
+const MAX_NUMBER_LEN: usize = 20;
+/// Size of a TAR block, which a PAX 1.0 sparse map is padded to.
+const BLOCK_SIZE: u64 = 512;
+
+/// A stretch of a sparse file that holds data, as offset and length.
+pub(crate) type Region = (u64, u64);
+
+/// The GNU sparse records of a PAX extended header.
+pub(crate) struct PaxSparse {
+    /// Name of the file, which the entry's own name only encodes.
+    pub(crate) name: Option<String>,
+    /// Size of the whole file, holes included.
+    pub(crate) size: u64,
+    /// Data regions, or `None` if they are stored in front of the data.
+    regions: Option<Vec<Region>>,
+}
+
+/// Parses the GNU sparse records among the PAX extensions of an entry,
+/// returning `None` if the entry is not a sparse file.
+pub(crate) fn pax_sparse(
+    extensions: tar::PaxExtensions<'_>,
+) -> Result<Option<PaxSparse>, ArchiveError> {
+    let mut name = None;
+    let mut size = None;
+    let mut major = None;
+    let mut regions = Vec::new();
+    let mut offset = None;
+    for extension in extensions {
+        let extension = extension?;
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        let value = || {
+            extension
+                .value()
+                .map_err(|_| invalid("malformed sparse record"))
+        };
+        let number = || {
+            value()?
+                .parse::<u64>()
+                .map_err(|_| invalid("malformed sparse record"))
+        };
+        match key {
+            "GNU.sparse.name" => name = Some(value()?.to_string()),
+            "GNU.sparse.size" | "GNU.sparse.realsize" => size = Some(number()?),
+            "GNU.sparse.major" => major = Some(number()?),
+            "GNU.sparse.offset" => offset = Some(number()?),
+            "GNU.sparse.numbytes" => {
+                let start = offset
+                    .take()
+                    .ok_or_else(|| invalid("sparse region without an offset"))?;
+                regions.push((start, number()?));
+            }
+            "GNU.sparse.map" => {
+                let numbers = value()?
+                    .split(',')
+                    .map(|n| n.parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid("malformed sparse map"))?;
+                if !numbers.len().is_multiple_of(2) {
+                    return Err(invalid("malformed sparse map"));
+                }
+                regions.extend(numbers.chunks(2).map(|pair| (pair[0], pair[1])));
+            }
+            _ => {}
+        }
+    }
+    let Some(size) = size else {
+        return Ok(None);
+    };
+    Ok(Some(PaxSparse {
+        name,
+        size,
+        regions: (major != Some(1)).then_some(regions),
+    }))
+}
+
+/// A reader over the whole of a sparse file, given its stored regions.
+pub(crate) struct Expanded<R> {
+    inner: R,
+    regions: std::vec::IntoIter<Region>,
+    /// The region being read, if the position is inside one.
+    current: Option<Region>,
+    position: u64,
+    size: u64,
+}
+
+impl<R: Read> Expanded<R> {
+    /// Expands the `stored` bytes of entry data read from `inner`.
+    pub(crate) fn new(mut inner: R, sparse: PaxSparse, stored: u64) -> Result<Self, ArchiveError> {
+        let (regions, data) = match sparse.regions {
+            Some(regions) => (regions, stored),
+            None => {
+                let (regions, consumed) = read_map(&mut inner)?;
+                let padded = consumed.next_multiple_of(BLOCK_SIZE);
+                io::copy(&mut (&mut inner).take(padded - consumed), &mut io::sink())?;
+                let data = stored
+                    .checked_sub(padded)
+                    .ok_or_else(|| invalid("sparse map is larger than the entry"))?;
+                (regions, data)
+            }
+        };
+
+        let mut end = 0u64;
+        let mut total = 0u64;
+        for &(offset, len) in &regions {
+            if offset < end {
+                return Err(invalid("sparse regions overlap"));
+            }
+            end = offset
+                .checked_add(len)
+                .filter(|&end| end <= sparse.size)
+                .ok_or_else(|| invalid("sparse region lies beyond the end of the file"))?;
+            total += len;
+        }
+        if total != data {
+            return Err(invalid("sparse regions don't match the entry size"));
+        }
+        Ok(Self {
+            inner,
+            regions: regions.into_iter(),
+            current: None,
+            position: 0,
+            size: sparse.size,
+        })
+    }
+}
+
+impl<R: Read> Read for Expanded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position >= self.size || buf.is_empty() {
+                return Ok(0);
+            }
+            let region = match self.current {
+                Some(region) => region,
+                None => {
+                    // Regions of length zero only mark where the file ends
+                    match self.regions.as_slice().first() {
+                        Some(&(offset, len)) if offset <= self.position => {
+                            self.regions.next();
+                            if len == 0 {
+                                continue;
+                            }
+                            self.current = Some((offset, len));
+                            (offset, len)
+                        }
+                        next => {
+                            // Inside a hole
+                            let end = next.map_or(self.size, |&(offset, _)| offset);
+                            let n = (end - self.position).min(buf.len() as u64) as usize;
+                            buf[..n].fill(0);
+                            self.position += n as u64;
+                            return Ok(n);
+                        }
+                    }
+                }
+            };
+            let remaining = region.0 + region.1 - self.position;
+            let n = (buf.len() as u64).min(remaining) as usize;
+            let n = self.inner.read(&mut buf[..n])?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.position += n as u64;
+            if self.position == region.0 + region.1 {
+                self.current = None;
+            }
+            return Ok(n);
+        }
+    }
+}
+
+/// Reads a PAX 1.0 sparse map, returning the regions and the number of
+/// bytes it took up.
+fn read_map<R: Read>(reader: &mut R) -> Result<(Vec<Region>, u64), ArchiveError> {
+    let mut consumed = 0u64;
+    let mut number = || -> Result<u64, ArchiveError> {
+        let mut digits = String::new();
+        loop {
+            let mut byte = [0u8];
+            reader.read_exact(&mut byte)?;
+            consumed += 1;
+            match byte[0] {
+                b'\n' => break,
+                digit @ b'0'..=b'9' if digits.len() < MAX_NUMBER_LEN => {
+                    digits.push(char::from(digit))
+                }
+                _ => return Err(invalid("malformed sparse map")),
+            }
+        }
+        digits.parse().map_err(|_| invalid("malformed sparse map"))
+    };
+    let count = number()?;
+    let mut regions = Vec::new();
+    for _ in 0..count {
+        regions.push((number()?, number()?));
+    }
+    Ok((regions, consumed))
+}
+
+/// Returns the data regions of `file` if it has holes.
+///
+/// Returns `None` for files without holes and where the platform or
+/// filesystem can't tell.
+pub(crate) fn data_regions(file: &File, len: u64) -> Option<Vec<Region>> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        use std::{
+            io::{Seek, SeekFrom},
+            os::fd::AsRawFd,
+        };
+
+        if len == 0 {
+            return None;
+        }
+        // `None` at the end of the file, where `SEEK_DATA` fails with ENXIO
+        let seek = |offset: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+            let offset = libc::off_t::try_from(offset).map_err(io::Error::other)?;
+            // SAFETY: the descriptor is valid for the duration of the call.
+            match unsafe { libc::lseek(file.as_raw_fd(), offset, whence) } {
+                -1 => {
+                    let error = io::Error::last_os_error();
+                    match error.raw_os_error() {
+                        Some(libc::ENXIO) => Ok(None),
+                        _ => Err(error),
+                    }
+                }
+                found => Ok(Some(found as u64)),
+            }
+        };
+        let find = || -> io::Result<Vec<Region>> {
+            let mut regions = Vec::new();
+            let mut position = 0;
+            while let Some(start) = seek(position, libc::SEEK_DATA)?.filter(|&start| start < len) {
+                let end = seek(start, libc::SEEK_HOLE)?.map_or(len, |end| end.min(len));
+                if end <= start {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+                regions.push((start, end - start));
+                position = end;
+            }
+            Ok(regions)
+        };
+        let regions = find();
+        (&*file).seek(SeekFrom::Start(0)).ok()?;
+        let mut regions = regions.ok()?;
+        // Every region but the last must fill whole TAR blocks
+        let unaligned = regions
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|&(_, n)| !n.is_multiple_of(BLOCK_SIZE));
+        if regions == [(0, len)] || unaligned {
+            return None;
+        }
+        // A zero-length region marks where the file ends after a hole
+        if regions.last().is_none_or(|&(offset, n)| offset + n < len) {
+            regions.push((len, 0));
+        }
+        Some(regions)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    {
+        let _ = (file, len);
+        None
+    }
+}
+
+/// A reader over the data regions of a file, one after the other.
+pub(crate) struct Packed<'a> {
+    file: &'a File,
+    regions: std::slice::Iter<'a, Region>,
+    /// Bytes left in the region being read.
+    remaining: u64,
+}
+
+impl<'a> Packed<'a> {
+    pub(crate) fn new(file: &'a File, regions: &'a [Region]) -> Self {
+        Self {
+            file,
+            regions: regions.iter(),
+            remaining: 0,
+        }
+    }
+}
+
+impl Read for Packed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::{Seek, SeekFrom};
+
+        while self.remaining == 0 {
+            let Some(&(offset, len)) = self.regions.next() else {
+                return Ok(0);
+            };
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.remaining = len;
+        }
+        let n = (buf.len() as u64).min(self.remaining) as usize;
+        let n = self.file.read(&mut buf[..n])?;
+        if n == 0 {
+            // The file shrank while it was being packed
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Tar,
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,596 @@
+//! SquashFS 4.0 filesystem images.
+//!
+//! Images compressed with gzip, xz, legacy LZMA and Zstandard are read;
+//! LZO and LZ4 images are rejected as unsupported. Inodes and directories
+//! live in compressed metadata blocks of up to 8 KiB that are decoded on
+//! demand and cached, since neighbouring entries share them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    entry::{EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+};
+
+/// Magic number at the start of every little-endian SquashFS image.
+pub(crate) const SQUASHFS_MAGIC: &[u8] = b"hsqs";
+/// Size of the superblock.
+const SUPERBLOCK_SIZE: usize = 96;
+/// Uncompressed size of a metadata block.
+const METADATA_SIZE: usize = 8192;
+/// Set in a metadata block header when the block is stored uncompressed.
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+/// Set in a data block size when the block is stored uncompressed.
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+/// Fragment index of files whose tail is not stored in a fragment.
+const NO_FRAGMENT: u32 = 0xFFFF_FFFF;
+/// Largest block size the format allows.
+const MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+/// Upper bound on directory nesting, which also guards against loops.
+const MAX_DEPTH: usize = 256;
+/// Size of a fragment table entry.
+const FRAGMENT_ENTRY_SIZE: u64 = 16;
+
+/// Inode types, with the extended variants of each basic type
+const BASIC_DIR: u16 = 1;
+const BASIC_FILE: u16 = 2;
+const BASIC_SYMLINK: u16 = 3;
+const EXT_DIR: u16 = 8;
+const EXT_FILE: u16 = 9;
+const EXT_SYMLINK: u16 = 10;
+
+/// Codecs a SquashFS image may be compressed with.
+#[derive(Clone, Copy)]
+enum Compression {
+    Gzip,
+    Lzma,
+    Xz,
+    Zstd,
+}
+
+/// Position in a metadata table: the on-disk offset of a block relative
+/// to the start of the table, and an offset in its decompressed contents.
+#[derive(Clone, Copy)]
+struct MetadataRef {
+    block: u64,
+    offset: usize,
+}
+
+impl MetadataRef {
+    /// Splits an inode reference into block and offset.
+    fn from_inode(reference: u64) -> Self {
+        Self {
+            block: reference >> 16,
+            offset: (reference & 0xFFFF) as usize,
+        }
+    }
+}
+
+/// How to locate a directory's listing in the directory table.
+struct Listing {
+    start: MetadataRef,
+    size: u64,
+}
+
+/// Where a regular file's data lives.
+struct FileData {
+    blocks_start: u64,
+    size: u64,
+    block_sizes: Vec<u32>,
+    fragment: Option<(u32, u32)>,
+}
+
+/// A decoded inode.
+enum Inode {
+    Directory(Listing),
+    File(FileData),
+    Symlink(String),
+    Other,
+}
+
+/// The fields of an inode that every type shares, plus its contents.
+struct Node {
+    number: u32,
+    mode: u32,
+    mtime: u32,
+    inode: Inode,
+}
+
+/// An open image with its decoded superblock and metadata caches.
+struct Image<R> {
+    reader: R,
+    compression: Compression,
+    block_size: u32,
+    inode_table: u64,
+    directory_table: u64,
+    fragment_table: u64,
+    fragment_count: u32,
+    /// Decompressed metadata blocks and the on-disk offset of the block
+    /// following them, keyed by absolute offset.
+    metadata: HashMap<u64, (Vec<u8>, u64)>,
+    /// The most recently decoded fragment block.
+    fragment: Option<(u32, Vec<u8>)>,
+}
+
+/// Walks every entry of a SquashFS image, parents before their children.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(mut reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let mut superblock = [0u8; SUPERBLOCK_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut superblock)?;
+    if !superblock.starts_with(SQUASHFS_MAGIC) {
+        return Err(invalid("missing 'hsqs' magic"));
+    }
+    if le16(&superblock[28..]) != 4 {
+        return Err(ArchiveError::unsupported_static(
+            "SquashFS images older than version 4.0",
+        ));
+    }
+    let compression = match le16(&superblock[20..]) {
+        1 => Compression::Gzip,
+        2 => Compression::Lzma,
+        4 => Compression::Xz,
+        6 => Compression::Zstd,
+        3 => return Err(ArchiveError::unsupported_static("LZO-compressed SquashFS")),
+        5 => return Err(ArchiveError::unsupported_static("LZ4-compressed SquashFS")),
+        _ => return Err(invalid("unknown compressor")),
+    };
+    let block_size = le32(&superblock[12..]);
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(invalid("invalid block size"));
+    }
+
+    let mut image = Image {
+        reader,
+        compression,
+        block_size,
+        inode_table: le64(&superblock[64..]),
+        directory_table: le64(&superblock[72..]),
+        fragment_table: le64(&superblock[80..]),
+        fragment_count: le32(&superblock[16..]),
+        metadata: HashMap::new(),
+        fragment: None,
+    };
+    let root = image.inode(MetadataRef::from_inode(le64(&superblock[32..])))?;
+    let Inode::Directory(listing) = root.inode else {
+        return Err(invalid("root inode is not a directory"));
+    };
+    let mut visited = HashSet::from([root.number]);
+    image.directory("", &listing, 0, &mut visited, &mut visit)?;
+    Ok(())
+}
+
+impl<R: Read + Seek> Image<R> {
+    /// Visits the contents of a directory, returning `false` once the
+    /// callback asked to stop.
+    fn directory<F>(
+        &mut self,
+        prefix: &str,
+        listing: &Listing,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        visit: &mut F,
+    ) -> Result<bool, ArchiveError>
+    where
+        F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    {
+        if depth > MAX_DEPTH {
+            return Err(invalid("directory hierarchy is too deep"));
+        }
+
+        for (name, reference) in self.listing(listing)? {
+            let node = self.inode(reference)?;
+            let name = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            let (kind, size, link_target) = match &node.inode {
+                Inode::Directory(_) => (EntryKind::Directory, 0, None),
+                Inode::File(data) => (EntryKind::File, data.size, None),
+                Inode::Symlink(target) => (EntryKind::Symlink, 0, Some(target.clone())),
+                Inode::Other => (EntryKind::Other, 0, None),
+            };
+            let info = EntryInfo {
+                name,
+                kind,
+                size,
+                compressed_size: None,
+                mtime: Some(UNIX_EPOCH + Duration::from_secs(u64::from(node.mtime))),
+                mode: Some(node.mode & 0o7777),
+                link_target,
+                owner: None,
+                xattrs: Vec::new(),
+                raw_name: None,
+                special: None,
+                comment: None,
+                extra_fields: Vec::new(),
+                encrypted: false,
+            };
+
+            let keep_going = match node.inode {
+                Inode::File(data) => {
+                    visit(
+                        &info,
+                        &mut FileReader {
+                            image: self,
+                            position: data.blocks_start,
+                            remaining: data.size,
+                            blocks: data.block_sizes.into_iter(),
+                            fragment: data.fragment,
+                            buffer: Vec::new(),
+                            consumed: 0,
+                        },
+                    )?
+                }
+                Inode::Directory(child) => {
+                    if !visited.insert(node.number) {
+                        return Err(invalid("directory hierarchy loops"));
+                    }
+                    visit(&info, &mut io::empty())?
+                        && self.directory(&info.name, &child, depth + 1, visited, visit)?
+                }
+                _ => visit(&info, &mut io::empty())?,
+            };
+            if !keep_going {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads the names and inode references in a directory listing.
+    fn listing(&mut self, listing: &Listing) -> Result<Vec<(String, MetadataRef)>, ArchiveError> {
+        // The recorded size includes the implicit `.` and `..` entries
+        let mut remaining = listing.size.saturating_sub(3);
+        let mut position = listing.start;
+        let mut entries = Vec::new();
+        while remaining > 0 {
+            let mut header = [0u8; 12];
+            self.read_metadata(self.directory_table, &mut position, &mut header)?;
+            remaining = remaining.saturating_sub(12);
+            let count = u64::from(le32(&header[0..])) + 1;
+            let inode_block = u64::from(le32(&header[4..]));
+            for _ in 0..count {
+                let mut entry = [0u8; 8];
+                self.read_metadata(self.directory_table, &mut position, &mut entry)?;
+                let mut name = vec![0u8; usize::from(le16(&entry[6..])) + 1];
+                self.read_metadata(self.directory_table, &mut position, &mut name)?;
+                remaining = remaining.saturating_sub(8 + name.len() as u64);
+
+                let name = String::from_utf8_lossy(&name).into_owned();
+                if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                    return Err(invalid("invalid entry name"));
+                }
+                let reference = MetadataRef {
+                    block: inode_block,
+                    offset: usize::from(le16(&entry[0..])),
+                };
+                entries.push((name, reference));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Decodes the inode at `reference`.
+    fn inode(&mut self, mut reference: MetadataRef) -> Result<Node, ArchiveError> {
+        let table = self.inode_table;
+        let mut header = [0u8; 16];
+        self.read_metadata(table, &mut reference, &mut header)?;
+        let kind = le16(&header[0..]);
+        let mode = u32::from(le16(&header[2..]));
+        let mtime = le32(&header[8..]);
+        let number = le32(&header[12..]);
+
+        let inode = match kind {
+            BASIC_DIR => {
+                let mut body = [0u8; 16];
+                self.read_metadata(table, &mut reference, &mut body)?;
+                Inode::Directory(Listing {
+                    start: MetadataRef {
+                        block: u64::from(le32(&body[0..])),
+                        offset: usize::from(le16(&body[10..])),
+                    },
+                    size: u64::from(le16(&body[8..])),
+                })
+            }
+            EXT_DIR => {
+                let mut body = [0u8; 24];
+                self.read_metadata(table, &mut reference, &mut body)?;
+                Inode::Directory(Listing {
+                    start: MetadataRef {
+                        block: u64::from(le32(&body[8..])),
+                        offset: usize::from(le16(&body[18..])),
+                    },
+                    size: u64::from(le32(&body[4..])),
+                })
+            }
+            BASIC_FILE => {
+                let mut body = [0u8; 16];
+                self.read_metadata(table, &mut reference, &mut body)?;
+                let size = u64::from(le32(&body[12..]));
+                self.file(
+                    &mut reference,
+                    u64::from(le32(&body[0..])),
+                    size,
+                    le32(&body[4..]),
+                    le32(&body[8..]),
+                )?
+            }
+            EXT_FILE => {
+                let mut body = [0u8; 40];
+                self.read_metadata(table, &mut reference, &mut body)?;
+                self.file(
+                    &mut reference,
+                    le64(&body[0..]),
+                    le64(&body[8..]),
+                    le32(&body[28..]),
+                    le32(&body[32..]),
+                )?
+            }
+            BASIC_SYMLINK | EXT_SYMLINK => {
+                let mut body = [0u8; 8];
+                self.read_metadata(table, &mut reference, &mut body)?;
+                let len = le32(&body[4..]);
+                if len > self.block_size {
+                    return Err(invalid("symlink target is too long"));
+                }
+                let mut target = vec![0u8; len as usize];
+                self.read_metadata(table, &mut reference, &mut target)?;
+                Inode::Symlink(String::from_utf8_lossy(&target).into_owned())
+            }
+            4..=7 | 11..=14 => Inode::Other,
+            _ => return Err(invalid("unknown inode type")),
+        };
+        Ok(Node {
+            number,
+            mode,
+            mtime,
+            inode,
+        })
+    }
+
+    /// Reads the block list following a file inode.
+    fn file(
+        &mut self,
+        reference: &mut MetadataRef,
+        blocks_start: u64,
+        size: u64,
+        fragment: u32,
+        fragment_offset: u32,
+    ) -> Result<Inode, ArchiveError> {
+        let block_size = u64::from(self.block_size);
+        // A file's tail shorter than a block may be packed into a fragment
+        let count = if fragment == NO_FRAGMENT {
+            size.div_ceil(block_size)
+        } else {
+            size / block_size
+        };
+        if count > u64::from(u32::MAX) {
+            return Err(invalid("file is too large"));
+        }
+        let mut block_sizes = Vec::new();
+        for _ in 0..count {
+            let mut entry = [0u8; 4];
+            self.read_metadata(self.inode_table, reference, &mut entry)?;
+            block_sizes.push(le32(&entry));
+        }
+        Ok(Inode::File(FileData {
+            blocks_start,
+            size,
+            block_sizes,
+            fragment: (fragment != NO_FRAGMENT).then_some((fragment, fragment_offset)),
+        }))
+    }
+
+    /// Fills `buf` from a metadata table, advancing `position` across
+    /// block boundaries.
+    fn read_metadata(
+        &mut self,
+        table: u64,
+        position: &mut MetadataRef,
+        buf: &mut [u8],
+    ) -> Result<(), ArchiveError> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let (block, next) = self.metadata_block(table + position.block)?;
+            let available = block.get(position.offset..).unwrap_or_default();
+            if available.is_empty() {
+                position.block = next - table;
+                position.offset = 0;
+                continue;
+            }
+            let n = available.len().min(buf.len() - filled);
+            buf[filled..filled + n].copy_from_slice(&available[..n]);
+            filled += n;
+            position.offset += n;
+        }
+        Ok(())
+    }
+
+    /// Returns the decompressed metadata block at absolute offset `offset`
+    /// and the offset of the block after it.
+    fn metadata_block(&mut self, offset: u64) -> Result<(&[u8], u64), ArchiveError> {
+        if !self.metadata.contains_key(&offset) {
+            let mut header = [0u8; 2];
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read_exact(&mut header)?;
+            let header = u16::from_le_bytes(header);
+            let size = header & !METADATA_UNCOMPRESSED;
+            let mut data = vec![0u8; usize::from(size)];
+            self.reader.read_exact(&mut data)?;
+            if header & METADATA_UNCOMPRESSED == 0 {
+                data = self.decompress(&data, METADATA_SIZE)?;
+            }
+            if data.is_empty() {
+                return Err(invalid("empty metadata block"));
+            }
+            self.metadata
+                .insert(offset, (data, offset + 2 + u64::from(size)));
+        }
+        let (data, next) = &self.metadata[&offset];
+        Ok((data, *next))
+    }
+
+    /// Reads one data block of `disk_size` bytes (with the uncompressed
+    /// flag) starting at `offset`.
+    fn data_block(&mut self, offset: u64, disk_size: u32) -> Result<Vec<u8>, ArchiveError> {
+        let size = disk_size & !DATA_UNCOMPRESSED;
+        if size > self.block_size {
+            return Err(invalid("data block is too large"));
+        }
+        let mut data = vec![0u8; size as usize];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut data)?;
+        if disk_size & DATA_UNCOMPRESSED == 0 {
+            data = self.decompress(&data, self.block_size as usize)?;
+        }
+        Ok(data)
+    }
+
+    /// Returns the decompressed contents of fragment block `index`.
+    fn fragment(&mut self, index: u32) -> Result<&[u8], ArchiveError> {
+        if self
+            .fragment
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != index)
+        {
+            if index >= self.fragment_count {
+                return Err(invalid("fragment index out of range"));
+            }
+            // The fragment table is itself stored in metadata blocks, which
+            // are located through an array of pointers
+            let offset = u64::from(index) * FRAGMENT_ENTRY_SIZE;
+            let mut pointer = [0u8; 8];
+            self.reader.seek(SeekFrom::Start(
+                self.fragment_table + offset / METADATA_SIZE as u64 * 8,
+            ))?;
+            self.reader.read_exact(&mut pointer)?;
+            let block = u64::from_le_bytes(pointer);
+            let mut entry = [0u8; FRAGMENT_ENTRY_SIZE as usize];
+            let mut position = MetadataRef {
+                block: 0,
+                offset: (offset % METADATA_SIZE as u64) as usize,
+            };
+            self.read_metadata(block, &mut position, &mut entry)?;
+            let data = self.data_block(le64(&entry[0..]), le32(&entry[8..]))?;
+            self.fragment = Some((index, data));
+        }
+        Ok(self
+            .fragment
+            .as_ref()
+            .map(|(_, data)| data.as_slice())
+            .unwrap_or_default())
+    }
+
+    /// Decompresses a metadata or data block of at most `limit` bytes.
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, ArchiveError> {
+        let decoder: Box<dyn Read + '_> = match self.compression {
+            Compression::Gzip => Box::new(flate2::read::ZlibDecoder::new(data)),
+            Compression::Lzma => {
+                let stream =
+                    liblzma::stream::Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::from)?;
+                Box::new(liblzma::read::XzDecoder::new_stream(data, stream))
+            }
+            Compression::Xz => Box::new(liblzma::read::XzDecoder::new(data)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+        };
+        let mut output = Vec::new();
+        decoder.take(limit as u64 + 1).read_to_end(&mut output)?;
+        if output.len() > limit {
+            return Err(invalid("block decompresses past its maximum size"));
+        }
+        Ok(output)
+    }
+}
+
+/// Reads a file's blocks and fragment tail in order.
+struct FileReader<'a, R> {
+    image: &'a mut Image<R>,
+    /// Offset of the next data block.
+    position: u64,
+    remaining: u64,
+    blocks: std::vec::IntoIter<u32>,
+    fragment: Option<(u32, u32)>,
+    buffer: Vec<u8>,
+    consumed: usize,
+}
+
+impl<R: Read + Seek> FileReader<'_, R> {
+    /// Loads the next block or the fragment tail into the buffer.
+    fn fill(&mut self) -> Result<(), ArchiveError> {
+        let block_size = u64::from(self.image.block_size);
+        let expected = self.remaining.min(block_size) as usize;
+        self.buffer = if let Some(disk_size) = self.blocks.next() {
+            if disk_size == 0 {
+                // Sparse block
+                vec![0; expected]
+            } else {
+                let data = self.image.data_block(self.position, disk_size)?;
+                self.position += u64::from(disk_size & !DATA_UNCOMPRESSED);
+                data
+            }
+        } else if let Some((index, offset)) = self.fragment.take() {
+            let data = self.image.fragment(index)?;
+            data.get(offset as usize..offset as usize + expected)
+                .ok_or_else(|| invalid("fragment is too short"))?
+                .to_vec()
+        } else {
+            return Err(invalid("file data ends early"));
+        };
+        if self.buffer.len() < expected {
+            return Err(invalid("data block is too short"));
+        }
+        self.buffer.truncate(expected);
+        self.consumed = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for FileReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.consumed == self.buffer.len() {
+            self.fill().map_err(io::Error::other)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.consumed);
+        buf[..n].copy_from_slice(&self.buffer[self.consumed..self.consumed + n]);
+        self.consumed += n;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(array)
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Squashfs,
+        reason: reason.into(),
+    }
+}
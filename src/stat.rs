@@ -0,0 +1,116 @@
+//! Totals over the metadata of an archive.
+//!
+//! Like auditing, this reads the central directory or entry headers alone,
+//! so it costs no more than listing the archive. Streams such as TAR inside
+//! gzip still have to be decompressed to reach every header.
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    volume::Location,
+};
+
+/// Totals returned by [`Archive::stat`](crate::Archive::stat).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use compak::{Archive, ArchiveFormat, CompressionOptions, writer::ArchiveBuilder};
+///
+/// let mut builder =
+///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &CompressionOptions::new())
+///         .unwrap();
+/// builder.add_empty_dir("docs").unwrap();
+/// builder.add_bytes("docs/readme.txt", &[b'a'; 4096]).unwrap();
+/// builder.add_symlink("readme", "docs/readme.txt").unwrap();
+/// let zip = builder.finish().unwrap().into_inner();
+///
+/// let stats = Archive::from_reader(Cursor::new(zip)).unwrap().stat().unwrap();
+/// assert_eq!(stats.entries, 3);
+/// assert_eq!((stats.files, stats.directories, stats.symlinks), (1, 1, 1));
+/// assert!(stats.compression_ratio().unwrap() > 10.0);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Number of entries.
+    pub entries: u64,
+    /// Regular files.
+    pub files: u64,
+    /// Directories.
+    pub directories: u64,
+    /// Symbolic links.
+    pub symlinks: u64,
+    /// Hard links.
+    pub hardlinks: u64,
+    /// Device nodes, FIFOs and other special files.
+    pub other: u64,
+    /// Combined uncompressed size of every entry.
+    pub size: u64,
+    /// Combined compressed size of every entry, if the format records one
+    /// per entry, as ZIP and non-solid 7z archives do.
+    pub compressed_size: Option<u64>,
+    /// Size of the archive itself, if it is known.
+    pub archive_size: Option<u64>,
+}
+
+impl ArchiveStats {
+    /// Returns how many times larger the entries are than what they take
+    /// up in the archive.
+    ///
+    /// The compressed sizes of the entries are used where the format
+    /// records them, and the size of the whole archive otherwise. Returns
+    /// `None` if neither is known, or if there is nothing compressed.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let compressed = self.compressed_size.or(self.archive_size)?;
+        (compressed > 0).then(|| self.size as f64 / compressed as f64)
+    }
+
+    /// Adds `info` to the totals.
+    fn add(&mut self, info: &EntryInfo) {
+        self.entries += 1;
+        let count = match info.kind {
+            EntryKind::File => &mut self.files,
+            EntryKind::Directory => &mut self.directories,
+            EntryKind::Symlink => &mut self.symlinks,
+            EntryKind::Hardlink => &mut self.hardlinks,
+            EntryKind::Other => &mut self.other,
+        };
+        *count += 1;
+        self.size = self.size.saturating_add(info.size);
+        // Entries without data record no compressed size in some formats
+        self.compressed_size = match (self.compressed_size, info.compressed_size) {
+            (Some(total), Some(compressed)) => Some(total.saturating_add(compressed)),
+            (total, None) if info.size == 0 => total,
+            _ => None,
+        };
+    }
+}
+
+/// Totals the metadata of every entry of the archive at `location`.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be opened or
+/// its metadata cannot be decoded.
+pub(crate) fn stat(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<ArchiveStats, ArchiveError> {
+    let mut stats = ArchiveStats {
+        compressed_size: Some(0),
+        archive_size: location.len(),
+        ..ArchiveStats::default()
+    };
+    let mut known = false;
+    for info in entry::list_entries(location, format)? {
+        known |= info.compressed_size.is_some();
+        stats.add(&info);
+    }
+    if !known {
+        stats.compressed_size = None;
+    }
+    Ok(stats)
+}
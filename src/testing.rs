@@ -0,0 +1,555 @@
+//! Programmatic archive fixtures for downstream tests.
+//!
+//! This module is only available with the `testing` feature. It generates
+//! small archives of every supported format entirely in memory, including
+//! entries that real-world tools refuse to create (symlinks pointing outside
+//! the archive, `..` path components, absolute paths) and deliberately
+//! corrupted variants, so that code built on compak can be tested against
+//! hostile input without checking binary blobs into the repository.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use compak::{ArchiveFormat, testing::Fixture};
+//!
+//! let bytes = Fixture::new(ArchiveFormat::TarGz)
+//!     .file("hello.txt", b"hello world")
+//!     .dir("docs/")
+//!     .symlink("link", "hello.txt")
+//!     .traversal("../escape.txt", b"nope")
+//!     .build()
+//!     .unwrap();
+//! assert!(!bytes.is_empty());
+//! ```
+
+use std::{
+    io::{self, Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use liblzma::stream::{Filters, LzmaOptions, Stream};
+
+use crate::{
+    error::{ArchiveError, PathContext},
+    format::ArchiveFormat,
+};
+
+/// A single entry to be written into a fixture archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixtureEntry {
+    /// A regular file with the given contents and unix mode.
+    File {
+        name: String,
+        data: Vec<u8>,
+        mode: u32,
+    },
+    /// A directory. The name may or may not end with `/`.
+    Dir { name: String },
+    /// A symbolic link pointing at `target`.
+    Symlink { name: String, target: String },
+}
+
+impl FixtureEntry {
+    /// Returns the entry name exactly as it will be stored in the archive.
+    pub fn name(&self) -> &str {
+        match self {
+            FixtureEntry::File {
+                name, ..
+            }
+            | FixtureEntry::Dir {
+                name,
+            }
+            | FixtureEntry::Symlink {
+                name, ..
+            } => name,
+        }
+    }
+}
+
+/// The kind of damage applied by [`Fixture::build_corrupt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// Cut the archive in half, simulating an interrupted download.
+    Truncated,
+    /// Overwrite the leading magic number so format detection fails.
+    BadMagic,
+    /// Flip bytes in the middle of the archive, damaging entry data or headers.
+    Garbled,
+}
+
+/// Builder for in-memory test archives.
+///
+/// Entries are written in the order they were added. Names are stored
+/// verbatim; no sanitization is applied, which is the whole point of
+/// [`Fixture::traversal`].
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    format: ArchiveFormat,
+    entries: Vec<FixtureEntry>,
+}
+
+impl Fixture {
+    /// Creates an empty fixture for the given format.
+    pub fn new(format: ArchiveFormat) -> Self {
+        Self {
+            format,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the format this fixture will be written as.
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Returns the entries added so far.
+    pub fn entries(&self) -> &[FixtureEntry] {
+        &self.entries
+    }
+
+    /// Adds a regular file with mode `0o644`.
+    pub fn file(self, name: impl Into<String>, data: impl AsRef<[u8]>) -> Self {
+        self.file_with_mode(name, data, 0o644)
+    }
+
+    /// Adds a regular file with an explicit unix mode.
+    pub fn file_with_mode(
+        mut self,
+        name: impl Into<String>,
+        data: impl AsRef<[u8]>,
+        mode: u32,
+    ) -> Self {
+        self.entries.push(FixtureEntry::File {
+            name: name.into(),
+            data: data.as_ref().to_vec(),
+            mode,
+        });
+        self
+    }
+
+    /// Adds a directory entry.
+    pub fn dir(mut self, name: impl Into<String>) -> Self {
+        self.entries.push(FixtureEntry::Dir {
+            name: name.into(),
+        });
+        self
+    }
+
+    /// Adds a symbolic link. The target is stored verbatim and may point
+    /// outside the archive root.
+    pub fn symlink(mut self, name: impl Into<String>, target: impl Into<String>) -> Self {
+        self.entries.push(FixtureEntry::Symlink {
+            name: name.into(),
+            target: target.into(),
+        });
+        self
+    }
+
+    /// Adds a file whose name attempts to escape the extraction directory,
+    /// such as `../evil.sh` or `/etc/passwd`.
+    ///
+    /// This is equivalent to [`Fixture::file`]; it exists to make the intent
+    /// of a test explicit.
+    pub fn traversal(self, name: impl Into<String>, data: impl AsRef<[u8]>) -> Self {
+        self.file(name, data)
+    }
+
+    /// Writes the fixture into a byte vector.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the underlying encoder fails,
+    /// or if an entry cannot be represented in the chosen format (e.g. a
+    /// symlink in an ar archive). TAR names and link targets of more than
+    /// 100 bytes are written as GNU `././@LongLink` entries.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, testing::Fixture};
+    ///
+    /// let deep = "nested/".repeat(30) + "file.txt";
+    /// let bytes = Fixture::new(ArchiveFormat::Tar)
+    ///     .file(&deep, b"deep")
+    ///     .symlink("link", &deep)
+    ///     .build()
+    ///     .unwrap();
+    /// let archive = Archive::from_reader(Cursor::new(bytes)).unwrap();
+    /// let names: Vec<_> = archive.entries().unwrap().map(|entry| entry.name).collect();
+    /// assert_eq!(names, [deep.as_str(), "link"]);
+    /// ```
+    pub fn build(&self) -> Result<Vec<u8>, ArchiveError> {
+        match self.format {
+            ArchiveFormat::Zip => self.build_zip(),
+            ArchiveFormat::Tar => self.build_tar(),
+            ArchiveFormat::TarGz | ArchiveFormat::Gzip => {
+                compress(self.build_payload()?, |w| {
+                    flate2::write::GzEncoder::new(w, flate2::Compression::default())
+                })
+            }
+            ArchiveFormat::TarXz | ArchiveFormat::Xz => {
+                compress(self.build_payload()?, |w| {
+                    liblzma::write::XzEncoder::new(w, 6)
+                })
+            }
+            ArchiveFormat::TarBz2 | ArchiveFormat::Bzip2 => {
+                compress(self.build_payload()?, |w| {
+                    bzip2::write::BzEncoder::new(w, bzip2::Compression::default())
+                })
+            }
+            ArchiveFormat::TarZst | ArchiveFormat::Zstd => {
+                let payload = self.build_payload()?;
+                Ok(zstd::stream::encode_all(payload.as_slice(), 0)?)
+            }
+            ArchiveFormat::TarLz4 | ArchiveFormat::Lz4 => {
+                Ok(crate::lz4::encode_stored(&self.build_payload()?))
+            }
+            ArchiveFormat::TarBr | ArchiveFormat::Brotli => {
+                Ok(crate::brotli::encode_stored(&self.build_payload()?))
+            }
+            ArchiveFormat::TarLz | ArchiveFormat::Lzip => encode_lzip(&self.build_payload()?),
+            ArchiveFormat::TarZ | ArchiveFormat::Compress => {
+                Ok(crate::lzw::encode_literals(&self.build_payload()?))
+            }
+            ArchiveFormat::TarLzma | ArchiveFormat::Lzma => {
+                let options = LzmaOptions::new_preset(6).map_err(io::Error::from)?;
+                let stream = Stream::new_lzma_encoder(&options).map_err(io::Error::from)?;
+                compress(self.build_payload()?, |w| {
+                    liblzma::write::XzEncoder::new_stream(w, stream)
+                })
+            }
+            ArchiveFormat::SevenZ => self.build_7z(),
+            ArchiveFormat::Rar => Err(ArchiveError::unsupported_static("creating RAR archives")),
+            ArchiveFormat::Ar | ArchiveFormat::Deb => self.build_ar(),
+            ArchiveFormat::Iso => Err(ArchiveError::unsupported_static("creating ISO images")),
+            ArchiveFormat::Squashfs => {
+                Err(ArchiveError::unsupported_static("creating SquashFS images"))
+            }
+            ArchiveFormat::Wim => Err(ArchiveError::unsupported_static("creating WIM images")),
+            ArchiveFormat::Xar => Err(ArchiveError::unsupported_static("creating xar archives")),
+            ArchiveFormat::Msi => Err(ArchiveError::unsupported_static("creating MSI packages")),
+            ArchiveFormat::AppImage => Err(ArchiveError::unsupported_static("creating AppImages")),
+            ArchiveFormat::Custom(_) => {
+                Err(ArchiveError::unsupported_static(
+                    "creating archives in custom formats",
+                ))
+            }
+        }
+    }
+
+    /// Writes the fixture and then damages it according to `corruption`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Fixture::build`].
+    pub fn build_corrupt(&self, corruption: Corruption) -> Result<Vec<u8>, ArchiveError> {
+        let mut data = self.build()?;
+        match corruption {
+            Corruption::Truncated => data.truncate(data.len() / 2),
+            Corruption::BadMagic => {
+                for byte in data.iter_mut().take(8) {
+                    *byte = 0;
+                }
+                // Plain tar carries its magic at offset 257
+                if let Some(magic) = data.get_mut(257..262) {
+                    magic.fill(0);
+                }
+            }
+            Corruption::Garbled => {
+                let mid = data.len() / 2;
+                let start = mid.saturating_sub(16);
+                let end = (mid + 16).min(data.len());
+                for byte in &mut data[start..end] {
+                    *byte ^= 0xFF;
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Writes the fixture into `dir`, naming the file `name` with the format's
+    /// standard extension appended, and returns the full path.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Fixture::build`], plus any I/O error
+    /// encountered writing the file.
+    pub fn write_to<P: AsRef<Path>>(&self, dir: P, name: &str) -> Result<PathBuf, ArchiveError> {
+        let path = dir
+            .as_ref()
+            .join(format!("{}.{}", name, self.format.extension()));
+        std::fs::write(&path, self.build()?).at_path(&path)?;
+        Ok(path)
+    }
+
+    fn build_zip(&self) -> Result<Vec<u8>, ArchiveError> {
+        use zip::write::SimpleFileOptions;
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for entry in &self.entries {
+            match entry {
+                FixtureEntry::File {
+                    name,
+                    data,
+                    mode,
+                } => {
+                    let options = SimpleFileOptions::default().unix_permissions(*mode);
+                    writer.start_file(name.as_str(), options)?;
+                    writer.write_all(data)?;
+                }
+                FixtureEntry::Dir {
+                    name,
+                } => {
+                    writer.add_directory(name.as_str(), SimpleFileOptions::default())?;
+                }
+                FixtureEntry::Symlink {
+                    name,
+                    target,
+                } => {
+                    writer.add_symlink(
+                        name.as_str(),
+                        target.as_str(),
+                        SimpleFileOptions::default(),
+                    )?;
+                }
+            }
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Returns the uncompressed data of a compressed format: the TAR stream,
+    /// or the contents of the only file of a single-file format.
+    fn build_payload(&self) -> Result<Vec<u8>, ArchiveError> {
+        if !self.format.is_single_file() {
+            return self.build_tar();
+        }
+        match self.entries.as_slice() {
+            [
+                FixtureEntry::File {
+                    data, ..
+                },
+            ] => Ok(data.clone()),
+            _ => {
+                Err(ArchiveError::unsupported_static(
+                    "single-file fixtures need exactly one file",
+                ))
+            }
+        }
+    }
+
+    fn build_tar(&self) -> Result<Vec<u8>, ArchiveError> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for entry in &self.entries {
+            let mut header = tar::Header::new_gnu();
+            // Names are written raw so that traversal attempts survive; the
+            // tar crate's own setters reject `..` and absolute paths.
+            if entry.name().len() > header.as_old().name.len() {
+                append_long_link(&mut builder, tar::EntryType::GNULongName, entry.name())?;
+            }
+            set_raw(&mut header.as_old_mut().name, entry.name());
+            header.set_mtime(0);
+            match entry {
+                FixtureEntry::File {
+                    data,
+                    mode,
+                    ..
+                } => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(*mode);
+                    header.set_size(data.len() as u64);
+                    header.set_cksum();
+                    builder.append(&header, data.as_slice())?;
+                }
+                FixtureEntry::Dir {
+                    ..
+                } => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    header.set_size(0);
+                    header.set_cksum();
+                    builder.append(&header, io::empty())?;
+                }
+                FixtureEntry::Symlink {
+                    target, ..
+                } => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_mode(0o777);
+                    header.set_size(0);
+                    if target.len() > header.as_old().linkname.len() {
+                        append_long_link(&mut builder, tar::EntryType::GNULongLink, target)?;
+                        set_raw(&mut header.as_old_mut().linkname, target);
+                    } else {
+                        header.set_link_name(target)?;
+                    }
+                    header.set_cksum();
+                    builder.append(&header, io::empty())?;
+                }
+            }
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    fn build_ar(&self) -> Result<Vec<u8>, ArchiveError> {
+        let mut out = b"!<arch>\n".to_vec();
+        for entry in &self.entries {
+            let FixtureEntry::File {
+                name,
+                data,
+                mode,
+            } = entry
+            else {
+                return Err(ArchiveError::unsupported_static(
+                    "non-file entries in ar fixtures",
+                ));
+            };
+            // Short names are stored inline like `dpkg` does; anything else
+            // uses a BSD-style long name so it survives verbatim
+            let inline = name.len() <= 16 && !name.contains([' ', '/']);
+            let (field, prefix) = if inline {
+                (name.clone(), "")
+            } else {
+                (format!("#1/{}", name.len()), name.as_str())
+            };
+            let size = prefix.len() + data.len();
+            let header = format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8o}{:<10}`\n",
+                field, 0, 0, 0, mode, size
+            );
+            out.extend_from_slice(header.as_bytes());
+            out.extend_from_slice(prefix.as_bytes());
+            out.extend_from_slice(data);
+            if size % 2 == 1 {
+                out.push(b'\n');
+            }
+        }
+        Ok(out)
+    }
+
+    fn build_7z(&self) -> Result<Vec<u8>, ArchiveError> {
+        use sevenz_rust2::{ArchiveEntry, ArchiveWriter};
+
+        /// Set in the attributes when the high 16 bits carry a unix mode.
+        const UNIX_EXTENSION: u32 = 0x8000;
+        /// `S_IFLNK` file type bits.
+        const UNIX_SYMLINK: u32 = 0o120000;
+
+        let mut writer = ArchiveWriter::new(Cursor::new(Vec::new()))?;
+        for entry in &self.entries {
+            match entry {
+                FixtureEntry::File {
+                    name,
+                    data,
+                    mode,
+                } => {
+                    let mut archive_entry = ArchiveEntry::new_file(name);
+                    archive_entry.has_windows_attributes = true;
+                    archive_entry.windows_attributes = UNIX_EXTENSION | ((0o100000 | mode) << 16);
+                    writer.push_archive_entry(archive_entry, Some(data.as_slice()))?;
+                }
+                FixtureEntry::Dir {
+                    name,
+                } => {
+                    let name = name.trim_end_matches('/');
+                    writer.push_archive_entry::<&[u8]>(ArchiveEntry::new_directory(name), None)?;
+                }
+                FixtureEntry::Symlink {
+                    name,
+                    target,
+                } => {
+                    // 7z stores symlinks as files whose content is the target
+                    // and whose unix mode marks them as links.
+                    let mut archive_entry = ArchiveEntry::new_file(name);
+                    archive_entry.has_windows_attributes = true;
+                    archive_entry.windows_attributes =
+                        UNIX_EXTENSION | ((UNIX_SYMLINK | 0o777) << 16);
+                    writer.push_archive_entry(archive_entry, Some(target.as_bytes()))?;
+                }
+            }
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+}
+
+/// Copies as much of `value` as fits into a header field, without
+/// validation.
+fn set_raw(field: &mut [u8], value: &str) {
+    let len = value.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+/// Appends a GNU `././@LongLink` entry of `kind` carrying the whole of
+/// `value`, for the name or link target of the entry that follows.
+fn append_long_link(
+    builder: &mut tar::Builder<Vec<u8>>,
+    kind: tar::EntryType,
+    value: &str,
+) -> Result<(), ArchiveError> {
+    let mut header = tar::Header::new_gnu();
+    set_raw(&mut header.as_old_mut().name, "././@LongLink");
+    header.set_entry_type(kind);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_size(value.len() as u64 + 1);
+    header.set_cksum();
+    builder.append(&header, value.as_bytes().chain(&[0][..]))?;
+    Ok(())
+}
+
+/// Runs `data` through the encoder produced by `encoder` and returns the output.
+fn compress<F, W>(data: Vec<u8>, encoder: F) -> Result<Vec<u8>, ArchiveError>
+where
+    F: FnOnce(Vec<u8>) -> W,
+    W: Write + Finish,
+{
+    let mut w = encoder(Vec::new());
+    w.write_all(&data)?;
+    Ok(w.finish_into()?)
+}
+
+/// Compresses `data` into a single lzip member: a header, a raw LZMA
+/// stream with an end marker, and a trailer carrying the CRC and sizes.
+fn encode_lzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    // Preset 6 uses an 8 MiB dictionary, coded as its base-2 logarithm
+    let options = LzmaOptions::new_preset(6).map_err(io::Error::from)?;
+    let mut filters = Filters::new();
+    filters.lzma1(&options);
+    let stream = Stream::new_raw_encoder(&filters).map_err(io::Error::from)?;
+    let body = compress(data.to_vec(), |w| {
+        liblzma::write::XzEncoder::new_stream(w, stream)
+    })?;
+
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    let mut member = b"LZIP\x01\x17".to_vec();
+    member.extend_from_slice(&body);
+    member.extend_from_slice(&crc.sum().to_le_bytes());
+    member.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    member.extend_from_slice(&(member.len() as u64 + 8).to_le_bytes());
+    Ok(member)
+}
+
+/// Uniform access to the `finish` methods of the various encoders.
+trait Finish {
+    fn finish_into(self) -> io::Result<Vec<u8>>;
+}
+
+impl Finish for flate2::write::GzEncoder<Vec<u8>> {
+    fn finish_into(self) -> io::Result<Vec<u8>> {
+        self.finish()
+    }
+}
+
+impl Finish for liblzma::write::XzEncoder<Vec<u8>> {
+    fn finish_into(self) -> io::Result<Vec<u8>> {
+        self.finish()
+    }
+}
+
+impl Finish for bzip2::write::BzEncoder<Vec<u8>> {
+    fn finish_into(self) -> io::Result<Vec<u8>> {
+        self.finish()
+    }
+}
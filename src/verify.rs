@@ -0,0 +1,121 @@
+//! Checking that every entry of an archive can be decoded.
+//!
+//! Entries are decoded in full and thrown away, which is where the
+//! checksums of each format are checked: the CRC-32 of ZIP and 7z entries,
+//! and the checks at the end of gzip, xz and Zstandard streams. TAR
+//! entries carry no checksum of their own, so they are only checked
+//! through the compression around them.
+
+use std::io;
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    volume::Location,
+};
+
+/// The result of checking one entry.
+#[derive(Debug, Clone)]
+pub struct EntryCheck {
+    /// Name of the entry as stored in the archive.
+    pub name: String,
+    /// Kind of the entry.
+    pub kind: EntryKind,
+    /// Bytes decoded before the entry ended or failed.
+    pub bytes: u64,
+    /// Why the entry failed, or `None` if it passed.
+    pub error: Option<ArchiveError>,
+}
+
+impl EntryCheck {
+    /// Returns `true` if the entry decoded without errors.
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Per-entry results of [`Archive::verify`](crate::Archive::verify).
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Every entry checked, in archive order.
+    pub entries: Vec<EntryCheck>,
+    /// Why the archive could not be read past the last entry checked, if
+    /// it couldn't.
+    pub error: Option<ArchiveError>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if every entry passed and the whole archive was read.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.entries.iter().all(EntryCheck::passed)
+    }
+
+    /// Returns the entries that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &EntryCheck> {
+        self.entries.iter().filter(|entry| !entry.passed())
+    }
+}
+
+/// Decodes every entry of the archive at `location`, recording for each
+/// whether it passed.
+///
+/// Checking goes on after an entry fails as long as the format allows
+/// reaching the next one. Once it doesn't, the failure to go on is
+/// recorded in [`VerifyReport::error`].
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be read at
+/// all, before its first entry.
+pub(crate) fn verify(
+    location: &Location,
+    format: ArchiveFormat,
+) -> Result<VerifyReport, ArchiveError> {
+    let mut report = VerifyReport::default();
+    let mut check = |info: &EntryInfo, reader: &mut dyn io::Read| {
+        let mut counter = Counter(0);
+        let error = io::copy(reader, &mut counter).err().map(ArchiveError::from);
+        report.entries.push(EntryCheck {
+            name: info.name.clone(),
+            kind: info.kind,
+            bytes: counter.0,
+            error,
+        });
+        Ok(true)
+    };
+
+    let result = if format.is_tar() {
+        entry::decoder(format, location.open(format)?).and_then(|mut decoder| {
+            entry::walk_tar(&mut decoder, &mut check)?;
+            // The check of the compressed stream comes after the end of the
+            // TAR archive, which the entries don't reach
+            io::copy(&mut decoder, &mut io::sink())?;
+            Ok(())
+        })
+    } else {
+        entry::for_each_entry(location, format, &mut check)
+    };
+    match result {
+        Err(err) if report.entries.is_empty() => Err(err),
+        Err(err) => {
+            report.error = Some(err);
+            Ok(report)
+        }
+        Ok(()) => Ok(report),
+    }
+}
+
+/// A writer that only counts the bytes written to it.
+struct Counter(u64);
+
+impl io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
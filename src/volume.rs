@@ -0,0 +1,674 @@
+//! Archives split across several files.
+//!
+//! 7-Zip splits an archive `name.7z` by cutting it into `name.7z.001`,
+//! `name.7z.002` and so on, so its parts are simply read back to back.
+//!
+//! Split ZIP archives keep their last part, which holds the central
+//! directory, in `name.zip` and the parts before it in `name.z01`,
+//! `name.z02` and so on. Local headers and the central directory are
+//! located by the number of the part they start in and an offset within
+//! that part, while entries and the central directory themselves may run
+//! on into the next part. The parts are therefore read back to back as one
+//! stream, ending in a copy of the central directory whose offsets point
+//! into that stream.
+//!
+//! Archives may also be read from a caller-supplied reader, which every
+//! handle opened on the archive shares.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{
+    error::{ArchiveError, PathContext},
+    format::ArchiveFormat,
+    hint,
+};
+
+/// Signature of a central directory file header.
+const CENTRAL_HEADER_SIGNATURE: &[u8] = b"PK\x01\x02";
+/// Signature of the end of central directory record.
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+/// Signature of the ZIP64 end of central directory record.
+const ZIP64_EOCD_SIGNATURE: &[u8] = b"PK\x06\x06";
+/// Signature of the ZIP64 end of central directory locator.
+const ZIP64_LOCATOR_SIGNATURE: &[u8] = b"PK\x06\x07";
+/// Size of a central directory file header without its variable fields.
+const CENTRAL_HEADER_SIZE: usize = 46;
+/// Size of the end of central directory record without its comment.
+const EOCD_SIZE: usize = 22;
+/// Size of the ZIP64 end of central directory record without its
+/// extensible data.
+const ZIP64_EOCD_SIZE: usize = 56;
+/// Size of the ZIP64 end of central directory locator.
+const ZIP64_LOCATOR_SIZE: usize = 20;
+/// Header ID of the ZIP64 extended information extra field.
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// ZIP specification version that introduced ZIP64.
+const ZIP64_VERSION: u16 = 45;
+
+/// Where the bytes of an archive come from.
+#[derive(Clone)]
+pub(crate) enum Location {
+    /// An archive file, or any part of a split archive.
+    Path(PathBuf),
+    /// A reader supplied by the caller.
+    Reader(SharedReader),
+}
+
+impl Location {
+    /// Opens the archive for reading, joining the parts of a split archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive file or one of its parts cannot be
+    /// opened.
+    pub(crate) fn open(&self, format: ArchiveFormat) -> Result<Source, ArchiveError> {
+        match self {
+            Location::Path(path) => open(path, format),
+            Location::Reader(reader) => Ok(Source::Shared(reader.handle())),
+        }
+    }
+
+    /// Returns the path of the archive file, if it is read from one.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        match self {
+            Location::Path(path) => Some(path),
+            Location::Reader(_) => None,
+        }
+    }
+
+    /// Returns the size of the archive, if it can be found out.
+    pub(crate) fn len(&self) -> Option<u64> {
+        match self {
+            Location::Path(path) => fs::metadata(path).ok().map(|meta| meta.len()),
+            Location::Reader(reader) => Some(reader.len),
+        }
+    }
+}
+
+/// An archive file, the parts of a split archive read as one, or a handle
+/// on a caller-supplied reader.
+pub(crate) enum Source {
+    File(File),
+    Volumes(Volumes),
+    Shared(Handle),
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            Source::Volumes(volumes) => volumes.read(buf),
+            Source::Shared(handle) => handle.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(position),
+            Source::Volumes(volumes) => volumes.seek(position),
+            Source::Shared(handle) => handle.seek(position),
+        }
+    }
+}
+
+/// A seekable reader that can be sent to another thread.
+pub(crate) trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// A caller-supplied reader, shared by every [`Handle`] opened on it.
+#[derive(Clone)]
+pub(crate) struct SharedReader {
+    inner: Arc<Mutex<Shared>>,
+    len: u64,
+}
+
+struct Shared {
+    reader: Box<dyn ReadSeek>,
+    /// Position of `reader`, or `None` after a failed read.
+    position: Option<u64>,
+}
+
+impl SharedReader {
+    /// Wraps `reader`, whose bytes from the start to the end are the
+    /// archive.
+    pub(crate) fn new<R: Read + Seek + Send + 'static>(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(SharedReader {
+            inner: Arc::new(Mutex::new(Shared {
+                reader: Box::new(reader),
+                position: Some(len),
+            })),
+            len,
+        })
+    }
+
+    fn handle(&self) -> Handle {
+        Handle {
+            reader: self.clone(),
+            position: 0,
+        }
+    }
+}
+
+/// A view of a [`SharedReader`] with a position of its own, so handles
+/// can be read independently of each other.
+pub(crate) struct Handle {
+    reader: SharedReader,
+    position: u64,
+}
+
+impl Read for Handle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut shared = self
+            .reader
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let synced = shared.position == Some(self.position);
+        // Unknown until the read has succeeded
+        shared.position = None;
+        if !synced {
+            shared.reader.seek(SeekFrom::Start(self.position))?;
+        }
+        let n = shared.reader.read(buf)?;
+        self.position += n as u64;
+        shared.position = Some(self.position);
+        Ok(n)
+    }
+}
+
+impl Seek for Handle {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        // Only reads move the shared reader
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.reader.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the archive",
+            )
+        })?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+/// Opens the archive at `path` for reading, joining the parts of a split
+/// archive.
+///
+/// A ZIP archive is split if a `.z01` part sits next to its `.zip` part,
+/// and a 7z archive if `path` is one of its numbered parts. Any part may be
+/// passed as `path`.
+pub(crate) fn open(path: &Path, format: ArchiveFormat) -> Result<Source, ArchiveError> {
+    match format {
+        ArchiveFormat::Zip => {
+            if let Some(parts) = zip_parts(path)? {
+                return Ok(Source::Volumes(join_zip(parts)?));
+            }
+        }
+        ArchiveFormat::SevenZ => {
+            if let Some(parts) = sevenz_parts(path)? {
+                return Ok(Source::Volumes(Volumes::new(parts)?));
+            }
+        }
+        _ => {}
+    }
+    Ok(Source::File(hint::open_sequential(path).at_path(path)?))
+}
+
+/// Returns `true` if the ZIP archive at `path` is split into several parts.
+///
+/// # Errors
+///
+/// Returns an error if some of the parts are missing.
+pub(crate) fn is_split_zip(path: &Path) -> Result<bool, ArchiveError> {
+    Ok(zip_parts(path)?.is_some())
+}
+
+/// Returns `true` if `name` ends in the `.7z.001`-style extension of a
+/// part of a split 7z archive.
+pub(crate) fn is_sevenz_part(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(stem, number)| {
+        number.len() >= 3
+            && number.bytes().all(|b| b.is_ascii_digit())
+            && stem.to_ascii_lowercase().ends_with(".7z")
+    })
+}
+
+/// Returns `true` if `extension` is that of a part of a split ZIP archive
+/// other than the last, `z` followed by a part number of two or more
+/// digits.
+pub(crate) fn is_zip_part(extension: &str) -> bool {
+    extension
+        .strip_prefix(['z', 'Z'])
+        .is_some_and(|number| number.len() >= 2 && number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Lists the parts of the split ZIP archive `path` belongs to, in order,
+/// or returns `None` if it is not split.
+fn zip_parts(path: &Path) -> Result<Option<Vec<PathBuf>>, ArchiveError> {
+    let Some((stem, extension)) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.rsplit_once('.'))
+    else {
+        return Ok(None);
+    };
+    let last = if extension.eq_ignore_ascii_case("zip") {
+        path.to_path_buf()
+    } else if is_zip_part(extension) {
+        path.with_extension(if extension.starts_with('Z') {
+            "ZIP"
+        } else {
+            "zip"
+        })
+    } else {
+        return Ok(None);
+    };
+    let letter = &extension[..1];
+    let part = |number: usize| path.with_file_name(format!("{}.{}{:02}", stem, letter, number));
+
+    let mut parts: Vec<PathBuf> = (1..).map(part).take_while(|part| part.is_file()).collect();
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    if !last.is_file() {
+        return Err(invalid("split archive is missing parts"));
+    }
+    parts.push(last);
+    Ok(Some(parts))
+}
+
+/// Lists the parts of the split 7z archive `path` belongs to, in order,
+/// or returns `None` if `path` is not a numbered part.
+fn sevenz_parts(path: &Path) -> Result<Option<Vec<PathBuf>>, ArchiveError> {
+    let Some((stem, number)) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| is_sevenz_part(name))
+        .and_then(|name| name.rsplit_once('.'))
+    else {
+        return Ok(None);
+    };
+    let width = number.len();
+    let parts: Vec<PathBuf> = (1..)
+        .map(|number| path.with_file_name(format!("{}.{:0width$}", stem, number)))
+        .take_while(|part| part.is_file())
+        .collect();
+    if parts.is_empty() {
+        return Err(ArchiveError::InvalidArchive {
+            format: ArchiveFormat::SevenZ,
+            reason: "split archive is missing parts".into(),
+        });
+    }
+    Ok(Some(parts))
+}
+
+/// Joins the parts of a split ZIP archive, appending a central directory
+/// that refers to entries by their offset in the joined stream.
+fn join_zip(paths: Vec<PathBuf>) -> Result<Volumes, ArchiveError> {
+    let mut volumes = Volumes::new(paths)?;
+    let starts: Vec<u64> = volumes.parts.iter().map(|part| part.start).collect();
+    let part_start = |disk: u64| {
+        usize::try_from(disk)
+            .ok()
+            .and_then(|disk| starts.get(disk))
+            .copied()
+            .ok_or_else(|| invalid("split archive is missing parts"))
+    };
+
+    // The end of central directory record, and any ZIP64 locator before
+    // it, are at the end of the last part
+    let last = volumes.parts.last().map_or(0, |part| part.len);
+    let search = (EOCD_SIZE + usize::from(u16::MAX) + ZIP64_LOCATOR_SIZE) as u64;
+    let tail_start = volumes.len - last.min(search);
+    let mut tail = Vec::new();
+    volumes.seek(SeekFrom::Start(tail_start))?;
+    volumes.by_ref().take(search).read_to_end(&mut tail)?;
+    let eocd = (0..=tail.len().saturating_sub(EOCD_SIZE))
+        .rev()
+        .find(|&offset| {
+            tail[offset..].starts_with(EOCD_SIGNATURE)
+                && le16(&tail, offset + 20)
+                    .is_ok_and(|comment| offset + EOCD_SIZE + usize::from(comment) <= tail.len())
+        })
+        .ok_or_else(|| invalid("end of central directory not found"))?;
+    let comment_len = usize::from(le16(&tail, eocd + 20)?);
+    let comment = tail[eocd + EOCD_SIZE..eocd + EOCD_SIZE + comment_len].to_vec();
+
+    let locator = eocd
+        .checked_sub(ZIP64_LOCATOR_SIZE)
+        .filter(|&offset| tail[offset..].starts_with(ZIP64_LOCATOR_SIGNATURE));
+    let (disk, cd_disk, entries, cd_size, cd_offset) = match locator {
+        Some(locator) => {
+            let record = part_start(u64::from(le32(&tail, locator + 4)?))?
+                .checked_add(le64(&tail, locator + 8)?)
+                .ok_or_else(|| invalid("ZIP64 end of central directory is out of range"))?;
+            let mut zip64 = vec![0; ZIP64_EOCD_SIZE];
+            volumes.seek(SeekFrom::Start(record))?;
+            volumes.read_exact(&mut zip64)?;
+            if !zip64.starts_with(ZIP64_EOCD_SIGNATURE) {
+                return Err(invalid("ZIP64 end of central directory not found"));
+            }
+            (
+                u64::from(le32(&zip64, 16)?),
+                u64::from(le32(&zip64, 20)?),
+                le64(&zip64, 32)?,
+                le64(&zip64, 40)?,
+                le64(&zip64, 48)?,
+            )
+        }
+        None => {
+            (
+                u64::from(le16(&tail, eocd + 4)?),
+                u64::from(le16(&tail, eocd + 6)?),
+                u64::from(le16(&tail, eocd + 10)?),
+                u64::from(le32(&tail, eocd + 12)?),
+                u64::from(le32(&tail, eocd + 16)?),
+            )
+        }
+    };
+    let disks = starts.len() as u64;
+    if disk + 1 > disks {
+        return Err(invalid("split archive is missing parts"));
+    } else if disk + 1 < disks {
+        return Err(invalid("split archive has more parts than it lists"));
+    }
+
+    let cd_start = part_start(cd_disk)?
+        .checked_add(cd_offset)
+        .filter(|start| start.saturating_add(cd_size) <= volumes.len)
+        .ok_or_else(|| invalid("central directory is out of range"))?;
+    let mut original = Vec::new();
+    volumes.seek(SeekFrom::Start(cd_start))?;
+    volumes.by_ref().take(cd_size).read_to_end(&mut original)?;
+
+    let mut central = Vec::with_capacity(original.len());
+    let mut offset = 0;
+    for _ in 0..entries {
+        offset += rewrite_header(&original[offset..], &part_start, &mut central)?;
+    }
+    let records = end_records(volumes.len, entries, &central, &comment);
+    volumes.tail = central;
+    volumes.tail.extend_from_slice(&records);
+    volumes.seek(SeekFrom::Start(0))?;
+    Ok(volumes)
+}
+
+/// Copies the central directory file header at the start of `header` to
+/// `output`, locating the entry's local header in the joined parts.
+/// Returns the size of the original header.
+fn rewrite_header(
+    header: &[u8],
+    part_start: &dyn Fn(u64) -> Result<u64, ArchiveError>,
+    output: &mut Vec<u8>,
+) -> Result<usize, ArchiveError> {
+    if !header.starts_with(CENTRAL_HEADER_SIGNATURE) {
+        return Err(invalid("malformed central directory"));
+    }
+    let name_len = usize::from(le16(header, 28)?);
+    let extra_len = usize::from(le16(header, 30)?);
+    let comment_len = usize::from(le16(header, 32)?);
+    let extra_start = CENTRAL_HEADER_SIZE + name_len;
+    let size = extra_start + extra_len + comment_len;
+    if header.len() < size {
+        return Err(invalid("truncated central directory"));
+    }
+    let compressed_saturated = le32(header, 20)? == u32::MAX;
+    let uncompressed_saturated = le32(header, 24)? == u32::MAX;
+    let mut disk = u64::from(le16(header, 34)?);
+    let mut local_offset = u64::from(le32(header, 42)?);
+    let disk_saturated = disk == u64::from(u16::MAX);
+    let offset_saturated = local_offset == u64::from(u32::MAX);
+
+    // Keep every extra field except ZIP64 information, which is rebuilt
+    // with the new offset and without a part number. Some writers repeat
+    // the ZIP64 field; like other readers, the last one wins.
+    let mut zip64 = Vec::new();
+    let mut extras = Vec::new();
+    let mut extra = &header[extra_start..extra_start + extra_len];
+    while extra.len() >= 4 {
+        let id = le16(extra, 0)?;
+        let len = usize::from(le16(extra, 2)?);
+        let Some(field) = extra.get(..4 + len) else {
+            break;
+        };
+        if id == ZIP64_EXTRA_ID {
+            let data = &field[4..];
+            let mut at = 0;
+            zip64.clear();
+            for saturated in [uncompressed_saturated, compressed_saturated] {
+                if saturated {
+                    let size = data
+                        .get(at..at + 8)
+                        .ok_or_else(|| invalid("truncated ZIP64 extra field"))?;
+                    zip64.extend_from_slice(size);
+                    at += 8;
+                }
+            }
+            if offset_saturated {
+                local_offset = le64(data, at)?;
+                at += 8;
+            }
+            if disk_saturated {
+                disk = u64::from(le32(data, at)?);
+            }
+        } else {
+            extras.extend_from_slice(field);
+        }
+        extra = &extra[field.len()..];
+    }
+    let local_offset = part_start(disk)?
+        .checked_add(local_offset)
+        .ok_or_else(|| invalid("local header is out of range"))?;
+    if local_offset >= u64::from(u32::MAX) {
+        zip64.extend_from_slice(&local_offset.to_le_bytes());
+    }
+
+    let mut fixed = header[..CENTRAL_HEADER_SIZE].to_vec();
+    let new_extra_len = extras.len() + if zip64.is_empty() { 0 } else { 4 + zip64.len() };
+    let new_extra_len =
+        u16::try_from(new_extra_len).map_err(|_| invalid("extra fields are too large"))?;
+    fixed[30..32].copy_from_slice(&new_extra_len.to_le_bytes());
+    fixed[34..36].copy_from_slice(&0u16.to_le_bytes());
+    fixed[42..46].copy_from_slice(&saturate32(local_offset).to_le_bytes());
+    output.extend_from_slice(&fixed);
+    output.extend_from_slice(&header[CENTRAL_HEADER_SIZE..extra_start]);
+    if !zip64.is_empty() {
+        output.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+        output.extend_from_slice(&(zip64.len() as u16).to_le_bytes());
+        output.extend_from_slice(&zip64);
+    }
+    output.extend_from_slice(&extras);
+    output.extend_from_slice(&header[extra_start + extra_len..size]);
+    Ok(size)
+}
+
+/// Builds the end of central directory records for a central directory
+/// `central` starting at `offset`, with ZIP64 records if needed.
+fn end_records(offset: u64, entries: u64, central: &[u8], comment: &[u8]) -> Vec<u8> {
+    let size = central.len() as u64;
+    let mut records = Vec::new();
+    if entries >= u64::from(u16::MAX)
+        || size >= u64::from(u32::MAX)
+        || offset >= u64::from(u32::MAX)
+    {
+        let record = offset + size;
+        records.extend_from_slice(ZIP64_EOCD_SIGNATURE);
+        records.extend_from_slice(&((ZIP64_EOCD_SIZE - 12) as u64).to_le_bytes());
+        records.extend_from_slice(&ZIP64_VERSION.to_le_bytes());
+        records.extend_from_slice(&ZIP64_VERSION.to_le_bytes());
+        records.extend_from_slice(&[0; 8]);
+        records.extend_from_slice(&entries.to_le_bytes());
+        records.extend_from_slice(&entries.to_le_bytes());
+        records.extend_from_slice(&size.to_le_bytes());
+        records.extend_from_slice(&offset.to_le_bytes());
+        records.extend_from_slice(ZIP64_LOCATOR_SIGNATURE);
+        records.extend_from_slice(&0u32.to_le_bytes());
+        records.extend_from_slice(&record.to_le_bytes());
+        records.extend_from_slice(&1u32.to_le_bytes());
+    }
+    let entries = u16::try_from(entries).unwrap_or(u16::MAX);
+    records.extend_from_slice(EOCD_SIGNATURE);
+    records.extend_from_slice(&[0; 4]);
+    records.extend_from_slice(&entries.to_le_bytes());
+    records.extend_from_slice(&entries.to_le_bytes());
+    records.extend_from_slice(&saturate32(size).to_le_bytes());
+    records.extend_from_slice(&saturate32(offset).to_le_bytes());
+    records.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    records.extend_from_slice(comment);
+    records
+}
+
+fn saturate32(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+fn le16(data: &[u8], offset: usize) -> Result<u16, ArchiveError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| invalid("truncated central directory"))
+}
+
+fn le32(data: &[u8], offset: usize) -> Result<u32, ArchiveError> {
+    data.get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| invalid("truncated central directory"))
+}
+
+fn le64(data: &[u8], offset: usize) -> Result<u64, ArchiveError> {
+    data.get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| invalid("truncated central directory"))
+}
+
+/// One file of a split archive.
+struct Part {
+    path: PathBuf,
+    /// Offset of the part in the joined stream.
+    start: u64,
+    len: u64,
+}
+
+/// The parts of a split archive read back to back, followed by `tail`.
+pub(crate) struct Volumes {
+    parts: Vec<Part>,
+    /// Combined size of the parts.
+    len: u64,
+    tail: Vec<u8>,
+    position: u64,
+    /// The part being read, and whether its file position is `position`.
+    current: Option<(usize, File, bool)>,
+}
+
+impl Volumes {
+    fn new(paths: Vec<PathBuf>) -> Result<Self, ArchiveError> {
+        let mut parts = Vec::with_capacity(paths.len());
+        let mut len = 0u64;
+        for path in paths {
+            let size = fs::metadata(&path).at_path(&path)?.len();
+            parts.push(Part {
+                path,
+                start: len,
+                len: size,
+            });
+            len += size;
+        }
+        Ok(Volumes {
+            parts,
+            len,
+            tail: Vec::new(),
+            position: 0,
+            current: None,
+        })
+    }
+}
+
+impl Read for Volumes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len {
+            let start = usize::try_from(self.position - self.len).unwrap_or(usize::MAX);
+            let rest = self.tail.get(start..).unwrap_or_default();
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.position += n as u64;
+            return Ok(n);
+        }
+
+        let index = self
+            .parts
+            .partition_point(|part| part.start + part.len <= self.position);
+        let part = &self.parts[index];
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|(open, ..)| *open != index)
+        {
+            self.current = Some((index, hint::open_sequential(&part.path)?, false));
+        }
+        let Some((_, file, synced)) = self.current.as_mut() else {
+            unreachable!("the current part was just opened");
+        };
+        if !*synced {
+            file.seek(SeekFrom::Start(self.position - part.start))?;
+            *synced = true;
+        }
+        let available = part.start + part.len - self.position;
+        let limit = buf
+            .len()
+            .min(usize::try_from(available).unwrap_or(usize::MAX));
+        let n = file.read(&mut buf[..limit])?;
+        if n == 0 && limit > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "part of a split archive was truncated while being read",
+            ));
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Volumes {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let end = self.len + self.tail.len() as u64;
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => end.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the archive",
+            )
+        })?;
+        if target != self.position
+            && let Some((_, _, synced)) = self.current.as_mut()
+        {
+            *synced = false;
+        }
+        self.position = target;
+        Ok(target)
+    }
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Zip,
+        reason: reason.into(),
+    }
+}
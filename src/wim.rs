@@ -0,0 +1,647 @@
+//! Windows Imaging (WIM) files.
+//!
+//! A WIM holds one or more directory trees ("images") whose file contents
+//! are stored once per distinct SHA-1 in resources, optionally compressed
+//! in independent chunks with XPRESS or LZX. Each image's tree lives in
+//! its own metadata resource, located through the blob table. Split and
+//! solid (ESD) WIMs, and LZMS compression, are rejected as unsupported.
+
+mod xpress;
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    entry::{self, EntryInfo, EntryKind},
+    error::ArchiveError,
+    format::ArchiveFormat,
+    lzx,
+};
+
+/// Magic number at the start of every WIM file.
+pub(crate) const WIM_MAGIC: &[u8] = b"MSWIM\0\0\0";
+/// Size of the file header.
+const HEADER_SIZE: usize = 208;
+/// Size of a resource header.
+const RESHDR_SIZE: usize = 24;
+/// Size of a blob table entry.
+const BLOB_ENTRY_SIZE: usize = 50;
+/// Size of the fixed part of a directory entry.
+const DENTRY_SIZE: usize = 102;
+/// Size of the fixed part of an extra stream entry.
+const STREAM_ENTRY_SIZE: usize = 38;
+/// Chunk size of compressed resources when the header leaves it at zero.
+const DEFAULT_CHUNK_SIZE: u32 = 32768;
+/// Upper bound on directory nesting, which also guards against loops.
+const MAX_DEPTH: usize = 256;
+/// Largest reparse point the file system allows.
+const MAX_REPARSE_SIZE: u64 = 16 * 1024;
+
+/// Header flags
+const HDR_COMPRESSION: u32 = 0x2;
+const HDR_COMPRESS_XPRESS: u32 = 0x2_0000;
+const HDR_COMPRESS_LZX: u32 = 0x4_0000;
+const HDR_COMPRESS_LZMS: u32 = 0x8_0000;
+
+/// Resource header flags
+const RES_METADATA: u8 = 0x2;
+const RES_COMPRESSED: u8 = 0x4;
+const RES_SOLID: u8 = 0x10;
+
+/// File attributes and reparse tags
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+const ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+const REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+const REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+
+/// Codecs a WIM's resources may be compressed with.
+#[derive(Clone, Copy)]
+enum Compression {
+    Xpress,
+    Lzx,
+}
+
+/// Location and size of a resource in the file.
+#[derive(Clone, Copy)]
+struct Resource {
+    size_in_wim: u64,
+    flags: u8,
+    offset: u64,
+    original_size: u64,
+}
+
+impl Resource {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            size_in_wim: le64(bytes) & 0x00FF_FFFF_FFFF_FFFF,
+            flags: bytes[7],
+            offset: le64(&bytes[8..]),
+            original_size: le64(&bytes[16..]),
+        }
+    }
+}
+
+/// A decoded directory entry.
+struct Dentry {
+    name: String,
+    attributes: u32,
+    subdir_offset: u64,
+    last_write_time: u64,
+    /// Hash of the unnamed data stream, or of the reparse data for reparse
+    /// points; all zero for empty streams.
+    hash: [u8; 20],
+    reparse_tag: u32,
+    /// Offset of the next entry in the same directory.
+    next: u64,
+}
+
+/// An open WIM with its decoded header and blob table.
+struct Wim<R> {
+    reader: R,
+    file_size: u64,
+    compression: Option<Compression>,
+    chunk_size: u32,
+    blobs: HashMap<[u8; 20], Resource>,
+    /// Metadata resources in image order.
+    images: Vec<Resource>,
+}
+
+/// Walks the entries of a WIM, parents before their children.
+///
+/// Only `image` (counting from 1) is read if given, at the root of the
+/// listing. Otherwise every image is read, each below a directory named
+/// after its index unless the WIM holds just one.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(
+    mut reader: R,
+    image: Option<u32>,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let mut header = [0u8; HEADER_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(WIM_MAGIC) {
+        return Err(invalid("missing 'MSWIM' magic"));
+    }
+    if le32(&header[8..]) as usize != HEADER_SIZE {
+        return Err(invalid("unexpected header size"));
+    }
+    if le16(&header[42..]) != 1 {
+        return Err(ArchiveError::unsupported_static("split WIM images"));
+    }
+    let flags = le32(&header[16..]);
+    let compression = if flags & HDR_COMPRESSION == 0 {
+        None
+    } else if flags & HDR_COMPRESS_XPRESS != 0 {
+        Some(Compression::Xpress)
+    } else if flags & HDR_COMPRESS_LZX != 0 {
+        Some(Compression::Lzx)
+    } else if flags & HDR_COMPRESS_LZMS != 0 {
+        return Err(ArchiveError::unsupported_static(
+            "LZMS-compressed WIM images",
+        ));
+    } else {
+        return Err(ArchiveError::unsupported_static("unknown WIM compression"));
+    };
+    let chunk_size = match le32(&header[20..]) {
+        0 => DEFAULT_CHUNK_SIZE,
+        size => size,
+    };
+    let valid_chunk_size = match compression {
+        None => true,
+        Some(Compression::Xpress) => (1 << 12..=1 << 16).contains(&chunk_size),
+        Some(Compression::Lzx) => (1 << 15..=1 << 21).contains(&chunk_size),
+    };
+    if !chunk_size.is_power_of_two() || !valid_chunk_size {
+        return Err(invalid("unsupported chunk size"));
+    }
+
+    let mut wim = Wim {
+        reader,
+        file_size,
+        compression,
+        chunk_size,
+        blobs: HashMap::new(),
+        images: Vec::new(),
+    };
+    wim.read_blob_table(Resource::parse(&header[48..]))?;
+    let count = le32(&header[44..]);
+    if wim.images.len() != count as usize {
+        return Err(invalid("image count does not match the blob table"));
+    }
+
+    let selected = match image {
+        Some(index) if index == 0 || index > count => {
+            return Err(ArchiveError::NotFound {
+                path: format!("image {index}").into(),
+            });
+        }
+        Some(index) => index..=index,
+        None => 1..=count,
+    };
+    for index in selected {
+        let prefix = if image.is_none() && count > 1 {
+            let info = EntryInfo {
+                name: index.to_string(),
+                kind: EntryKind::Directory,
+                size: 0,
+                compressed_size: None,
+                mtime: None,
+                mode: None,
+                link_target: None,
+                owner: None,
+                xattrs: Vec::new(),
+                raw_name: None,
+                special: None,
+                comment: None,
+                extra_fields: Vec::new(),
+                encrypted: false,
+            };
+            if !visit(&info, &mut io::empty())? {
+                return Ok(());
+            }
+            info.name
+        } else {
+            String::new()
+        };
+        if !wim.image(index, &prefix, &mut visit)? {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+impl<R: Read + Seek> Wim<R> {
+    /// Reads the blob table, collecting the metadata resources in order.
+    fn read_blob_table(&mut self, resource: Resource) -> Result<(), ArchiveError> {
+        let table = self.read_resource(resource)?;
+        if table.len() % BLOB_ENTRY_SIZE != 0 {
+            return Err(invalid("truncated blob table"));
+        }
+        for entry in table.chunks_exact(BLOB_ENTRY_SIZE) {
+            let resource = Resource::parse(entry);
+            if resource.flags & RES_SOLID != 0 {
+                return Err(ArchiveError::unsupported_static("solid WIM resources"));
+            }
+            if resource.flags & RES_METADATA != 0 {
+                self.images.push(resource);
+            } else {
+                let mut hash = [0u8; 20];
+                hash.copy_from_slice(&entry[RESHDR_SIZE + 6..BLOB_ENTRY_SIZE]);
+                self.blobs.insert(hash, resource);
+            }
+        }
+        Ok(())
+    }
+
+    /// Visits the tree of image `index`, returning `false` once the
+    /// callback asked to stop.
+    fn image<F>(&mut self, index: u32, prefix: &str, visit: &mut F) -> Result<bool, ArchiveError>
+    where
+        F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    {
+        let metadata = self.read_resource(self.images[index as usize - 1])?;
+        // The tree follows the security descriptors, whose total length
+        // leads the resource
+        let security = metadata
+            .get(..4)
+            .map(|bytes| u64::from(le32(bytes)).next_multiple_of(8).max(8))
+            .ok_or_else(|| invalid("truncated image metadata"))?;
+        let root = parse_dentry(&metadata, security)?
+            .ok_or_else(|| invalid("image has no root directory"))?;
+        if root.attributes & ATTRIBUTE_DIRECTORY == 0 {
+            return Err(invalid("root entry is not a directory"));
+        }
+        let mut visited = HashSet::new();
+        self.directory(
+            &metadata,
+            prefix,
+            root.subdir_offset,
+            0,
+            &mut visited,
+            visit,
+        )
+    }
+
+    /// Visits the entries of the directory listing at `offset`, returning
+    /// `false` once the callback asked to stop.
+    fn directory<F>(
+        &mut self,
+        metadata: &[u8],
+        prefix: &str,
+        offset: u64,
+        depth: usize,
+        visited: &mut HashSet<u64>,
+        visit: &mut F,
+    ) -> Result<bool, ArchiveError>
+    where
+        F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+    {
+        if depth > MAX_DEPTH {
+            return Err(invalid("directory hierarchy is too deep"));
+        }
+        if offset == 0 {
+            return Ok(true);
+        }
+        let mut next = parse_dentry(metadata, offset)?;
+        // Empty listings may be shared, so only others count as visited
+        if next.is_some() && !visited.insert(offset) {
+            return Err(invalid("directory hierarchy loops"));
+        }
+
+        while let Some(dentry) = next {
+            next = parse_dentry(metadata, dentry.next)?;
+            if dentry.name.is_empty()
+                || dentry.name == "."
+                || dentry.name == ".."
+                || dentry.name.contains('/')
+            {
+                return Err(invalid("invalid entry name"));
+            }
+            let name = if prefix.is_empty() {
+                dentry.name.clone()
+            } else {
+                format!("{}/{}", prefix, dentry.name)
+            };
+            let mut info = EntryInfo {
+                name,
+                kind: EntryKind::File,
+                size: 0,
+                compressed_size: None,
+                mtime: entry::filetime(dentry.last_write_time),
+                mode: None,
+                link_target: None,
+                owner: None,
+                xattrs: Vec::new(),
+                raw_name: None,
+                special: None,
+                comment: None,
+                extra_fields: Vec::new(),
+                encrypted: false,
+            };
+
+            let keep_going = if dentry.attributes & ATTRIBUTE_REPARSE_POINT != 0 {
+                match dentry.reparse_tag {
+                    REPARSE_TAG_SYMLINK | REPARSE_TAG_MOUNT_POINT => {
+                        info.kind = EntryKind::Symlink;
+                        info.link_target = Some(self.link_target(&dentry)?);
+                    }
+                    _ => info.kind = EntryKind::Other,
+                }
+                visit(&info, &mut io::empty())?
+            } else if dentry.attributes & ATTRIBUTE_DIRECTORY != 0 {
+                info.kind = EntryKind::Directory;
+                visit(&info, &mut io::empty())?
+                    && self.directory(
+                        metadata,
+                        &info.name,
+                        dentry.subdir_offset,
+                        depth + 1,
+                        visited,
+                        visit,
+                    )?
+            } else if let Some(resource) = self.blob(&dentry.hash)? {
+                info.size = resource.original_size;
+                info.compressed_size = Some(resource.size_in_wim);
+                let mut reader = self.resource_reader(resource)?;
+                visit(&info, &mut reader)?
+            } else {
+                visit(&info, &mut io::empty())?
+            };
+            if !keep_going {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the resource holding the stream with `hash`, or `None` for
+    /// an empty stream.
+    fn blob(&self, hash: &[u8; 20]) -> Result<Option<Resource>, ArchiveError> {
+        if hash.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        match self.blobs.get(hash) {
+            Some(resource) => Ok(Some(*resource)),
+            None => Err(invalid("file data missing from the blob table")),
+        }
+    }
+
+    /// Reads the target of a symbolic link or junction, preferring the
+    /// name meant for display.
+    fn link_target(&mut self, dentry: &Dentry) -> Result<String, ArchiveError> {
+        let resource = self
+            .blob(&dentry.hash)?
+            .ok_or_else(|| invalid("reparse point has no data"))?;
+        if resource.original_size > MAX_REPARSE_SIZE {
+            return Err(invalid("reparse point is too large"));
+        }
+        let data = self.read_resource(resource)?;
+        // Symbolic links have a flags field that junctions lack
+        let buffer_start = if dentry.reparse_tag == REPARSE_TAG_SYMLINK {
+            12
+        } else {
+            8
+        };
+        if data.len() < buffer_start {
+            return Err(invalid("truncated reparse point"));
+        }
+        let name = |at: usize| {
+            let start = buffer_start + usize::from(le16(&data[at..]));
+            data.get(start..start + usize::from(le16(&data[at + 2..])))
+                .ok_or_else(|| invalid("truncated reparse point"))
+        };
+        let (substitute, print) = (name(0)?, name(4)?);
+        let target = if print.is_empty() { substitute } else { print };
+        Ok(utf16(target).replace('\\', "/"))
+    }
+
+    /// Reads a whole resource into memory.
+    fn read_resource(&mut self, resource: Resource) -> Result<Vec<u8>, ArchiveError> {
+        let mut data = Vec::new();
+        self.resource_reader(resource)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Returns a reader over the uncompressed contents of `resource`,
+    /// loading its chunk table if it is compressed.
+    fn resource_reader(
+        &mut self,
+        resource: Resource,
+    ) -> Result<ResourceReader<'_, R>, ArchiveError> {
+        if resource
+            .offset
+            .checked_add(resource.size_in_wim)
+            .is_none_or(|end| end > self.file_size)
+        {
+            return Err(invalid("resource extends past the end of the file"));
+        }
+        let compression = self
+            .compression
+            .filter(|_| resource.flags & RES_COMPRESSED != 0);
+        let chunks = resource.original_size.div_ceil(u64::from(self.chunk_size));
+        let mut chunk_offsets = Vec::new();
+        let mut data_start = resource.offset;
+        if compression.is_some() && chunks > 0 {
+            // Each chunk but the first has its offset recorded, relative to
+            // the end of the table
+            let entry_size = if resource.original_size > u64::from(u32::MAX) {
+                8
+            } else {
+                4
+            };
+            let table_size = (chunks - 1) * entry_size;
+            if table_size > resource.size_in_wim {
+                return Err(invalid("truncated chunk table"));
+            }
+            let mut table = vec![0u8; table_size as usize];
+            self.reader.seek(SeekFrom::Start(resource.offset))?;
+            self.reader.read_exact(&mut table)?;
+            chunk_offsets.push(0);
+            for entry in table.chunks_exact(entry_size as usize) {
+                chunk_offsets.push(if entry_size == 8 {
+                    le64(entry)
+                } else {
+                    u64::from(le32(entry))
+                });
+            }
+            chunk_offsets.push(resource.size_in_wim - table_size);
+            if chunk_offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+                return Err(invalid("invalid chunk table"));
+            }
+            data_start += table_size;
+        } else if compression.is_none() && resource.size_in_wim < resource.original_size {
+            return Err(invalid("uncompressed resource is truncated"));
+        }
+        Ok(ResourceReader {
+            wim: self,
+            compression,
+            data_start,
+            chunk_offsets,
+            next_chunk: 0,
+            remaining: resource.original_size,
+            buffer: Vec::new(),
+            consumed: 0,
+            lzx: None,
+        })
+    }
+}
+
+/// Reads a resource's chunks in order.
+struct ResourceReader<'a, R> {
+    wim: &'a mut Wim<R>,
+    compression: Option<Compression>,
+    /// Offset of the first chunk's data.
+    data_start: u64,
+    /// Start of every compressed chunk relative to `data_start`, followed
+    /// by the end of the last; empty for uncompressed resources.
+    chunk_offsets: Vec<u64>,
+    next_chunk: usize,
+    remaining: u64,
+    buffer: Vec<u8>,
+    consumed: usize,
+    /// Decoder state reused across LZX chunks.
+    lzx: Option<lzx::Decoder>,
+}
+
+impl<R: Read + Seek> ResourceReader<'_, R> {
+    /// Loads the next chunk into the buffer.
+    fn fill(&mut self) -> Result<(), ArchiveError> {
+        let chunk_size = u64::from(self.wim.chunk_size);
+        let expected = self.remaining.min(chunk_size) as usize;
+        let index = self.next_chunk;
+        self.next_chunk += 1;
+        self.consumed = 0;
+
+        let Some(compression) = self.compression else {
+            self.buffer.resize(expected, 0);
+            let offset = self.data_start + index as u64 * chunk_size;
+            self.wim.reader.seek(SeekFrom::Start(offset))?;
+            self.wim.reader.read_exact(&mut self.buffer)?;
+            return Ok(());
+        };
+        let start = self.chunk_offsets[index];
+        let stored = (self.chunk_offsets[index + 1] - start) as usize;
+        if stored > expected {
+            return Err(invalid("chunk is larger than its contents"));
+        }
+        let mut data = vec![0u8; stored];
+        self.wim
+            .reader
+            .seek(SeekFrom::Start(self.data_start + start))?;
+        self.wim.reader.read_exact(&mut data)?;
+        // Chunks that don't shrink are stored as they are
+        if stored == expected {
+            self.buffer = data;
+            return Ok(());
+        }
+        self.buffer.resize(expected, 0);
+        match compression {
+            Compression::Xpress => xpress::decompress(&data, &mut self.buffer),
+            Compression::Lzx => {
+                let window_order = self.wim.chunk_size.trailing_zeros();
+                let decoder = match &mut self.lzx {
+                    Some(decoder) => decoder,
+                    None => {
+                        self.lzx
+                            .insert(lzx::Decoder::new(lzx::Variant::Wim, window_order)?)
+                    }
+                };
+                // Every chunk is compressed on its own
+                decoder.reset();
+                decoder.decompress(&data, &mut self.buffer)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for ResourceReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.consumed == self.buffer.len() {
+            self.fill().map_err(io::Error::other)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.consumed);
+        buf[..n].copy_from_slice(&self.buffer[self.consumed..self.consumed + n]);
+        self.consumed += n;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Decodes the directory entry at `offset` in an image's metadata, or
+/// returns `None` for the end-of-directory marker.
+fn parse_dentry(metadata: &[u8], offset: u64) -> Result<Option<Dentry>, ArchiveError> {
+    let truncated = || invalid("truncated directory entry");
+    let rest = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| metadata.get(offset..))
+        .ok_or_else(truncated)?;
+    let length = le64(rest.get(..8).ok_or_else(truncated)?);
+    if length <= 8 {
+        return Ok(None);
+    }
+    if length < DENTRY_SIZE as u64 || length > rest.len() as u64 {
+        return Err(truncated());
+    }
+    let entry = &rest[..length as usize];
+    let name_len = usize::from(le16(&entry[100..]));
+    let name = entry
+        .get(DENTRY_SIZE..DENTRY_SIZE + name_len)
+        .ok_or_else(truncated)?;
+
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&entry[64..84]);
+    let attributes = le32(&entry[8..]);
+    let is_reparse = attributes & ATTRIBUTE_REPARSE_POINT != 0;
+
+    // Alternate data streams follow the entry; an unnamed one holds the
+    // contents in some writers' output, and alongside the reparse data of
+    // reparse points
+    let mut next = offset + length.next_multiple_of(8);
+    for _ in 0..le16(&entry[96..]) {
+        let stream = usize::try_from(next)
+            .ok()
+            .and_then(|next| metadata.get(next..))
+            .filter(|stream| stream.len() >= STREAM_ENTRY_SIZE)
+            .ok_or_else(|| invalid("truncated stream entry"))?;
+        let stream_len = le64(stream);
+        if stream_len < STREAM_ENTRY_SIZE as u64 || stream_len > stream.len() as u64 {
+            return Err(invalid("truncated stream entry"));
+        }
+        if le16(&stream[36..]) == 0 && !is_reparse && hash.iter().all(|&b| b == 0) {
+            hash.copy_from_slice(&stream[16..36]);
+        }
+        next += stream_len.next_multiple_of(8);
+    }
+
+    Ok(Some(Dentry {
+        name: utf16(name),
+        attributes,
+        subdir_offset: le64(&entry[16..]),
+        last_write_time: le64(&entry[56..]),
+        hash,
+        reparse_tag: le32(&entry[88..]),
+        next,
+    }))
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units = bytes.chunks_exact(2).map(le16);
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(array)
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Wim,
+        reason: reason.into(),
+    }
+}
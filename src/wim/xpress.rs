@@ -0,0 +1,116 @@
+//! XPRESS Huffman decompression of WIM chunks.
+//!
+//! Each chunk starts with the codeword lengths of its 512 symbols, packed
+//! two per byte. A bitstream of 16-bit little-endian words follows, with
+//! the long match lengths stored as raw bytes between the words, exactly
+//! where the decoder has reached when it needs them.
+
+use super::invalid;
+use crate::{error::ArchiveError, huffman::HuffmanCode};
+
+/// Size of the table of codeword lengths.
+const LENGTHS_SIZE: usize = 256;
+/// Shortest match the format encodes.
+const MIN_MATCH: usize = 3;
+
+pub(super) fn decompress(input: &[u8], output: &mut [u8]) -> Result<(), ArchiveError> {
+    let lengths = input
+        .get(..LENGTHS_SIZE)
+        .ok_or_else(|| invalid("truncated XPRESS chunk"))?
+        .iter()
+        .flat_map(|&byte| [byte & 0xF, byte >> 4])
+        .collect::<Vec<_>>();
+    let code = HuffmanCode::new(&lengths)?;
+
+    let mut input = Input {
+        data: input,
+        position: LENGTHS_SIZE,
+        bits: 0,
+        extra: 16,
+    };
+    input.bits = (input.word() << 16) | input.word();
+    let mut out = 0;
+    while out < output.len() {
+        let (symbol, len) = code.decode(input.bits >> 16)?;
+        input.consume(len);
+        if symbol < 256 {
+            output[out] = symbol as u8;
+            out += 1;
+            continue;
+        }
+
+        let symbol = usize::from(symbol - 256);
+        let offset_bits = (symbol >> 4) as u32;
+        let mut length = symbol & 0xF;
+        if length == 0xF {
+            length = usize::from(input.byte()?);
+            if length == 0xFF {
+                length = usize::from(input.byte()?) | usize::from(input.byte()?) << 8;
+                length = length
+                    .checked_sub(0xF)
+                    .ok_or_else(|| invalid("invalid XPRESS match length"))?;
+            }
+            length += 0xF;
+        }
+        length += MIN_MATCH;
+        let offset = if offset_bits == 0 {
+            1
+        } else {
+            (1 << offset_bits) | (input.bits >> (32 - offset_bits)) as usize
+        };
+        input.consume(offset_bits);
+
+        if offset > out || length > output.len() - out {
+            return Err(invalid("XPRESS match is out of bounds"));
+        }
+        for i in out..out + length {
+            output[i] = output[i - offset];
+        }
+        out += length;
+    }
+    Ok(())
+}
+
+/// The interleaved bitstream and raw bytes of a chunk.
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// Buffered bits, most significant first; at least 16 are valid.
+    bits: u32,
+    /// Valid bits in `bits` beyond the 16 guaranteed.
+    extra: u32,
+}
+
+impl Input<'_> {
+    /// Reads the next 16-bit word, padding the end of the chunk with zeros.
+    fn word(&mut self) -> u32 {
+        let word = self
+            .data
+            .get(self.position..self.position + 2)
+            .map_or(0, |word| u32::from(u16::from_le_bytes([word[0], word[1]])));
+        self.position += 2;
+        word
+    }
+
+    fn byte(&mut self) -> Result<u8, ArchiveError> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| invalid("truncated XPRESS chunk"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// Drops `n` bits of at most 16, loading the next word once fewer than
+    /// 16 remain.
+    fn consume(&mut self, n: u32) {
+        self.bits = self.bits.checked_shl(n).unwrap_or(0);
+        if n > self.extra {
+            let missing = n - self.extra;
+            self.bits |= self.word() << missing;
+            self.extra = 16 - missing;
+        } else {
+            self.extra -= n;
+        }
+    }
+}
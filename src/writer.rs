@@ -0,0 +1,2495 @@
+//! Archive creation.
+//!
+//! [`ArchiveBuilder`] assembles an archive entry by entry from files,
+//! in-memory buffers and arbitrary readers, without staging anything on disk.
+//! Builders created with [`ArchiveBuilder::new_stream`] write into any
+//! [`Write`] sink, such as a pipe or an HTTP response body, and
+//! [`ArchiveBuilder::append`] adds entries to an existing ZIP archive.
+//! Compression levels are set through [`CompressionOptions`], as is the
+//! [`TarFlavor`] of TAR headers.
+//!
+//! ZIP archives switch to ZIP64 records wherever the classic ones run out:
+//! for entries of 4 GiB or more, for entries starting 4 GiB or more into
+//! the archive, and for archives of more than 65,534 entries.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub use zip::write::StreamWriter;
+
+use crate::{
+    central::{self, Commented, EntryComments},
+    entry::{EntryInfo, EntryKind, ExtraField},
+    error::{ArchiveError, PathContext},
+    format::{self, ArchiveFormat},
+    progress::{CancellationToken, Progress, ProgressTracker},
+    sparse::{self, Region},
+    volume,
+};
+
+/// Set in 7z attributes when the high 16 bits carry a unix mode.
+const UNIX_EXTENSION: u32 = 0x8000;
+/// Windows attribute marking directories.
+const WINDOWS_DIRECTORY: u32 = 0x10;
+/// `S_IFDIR` file type bits.
+const UNIX_DIR: u32 = 0o040000;
+/// `S_IFREG` file type bits.
+const UNIX_FILE: u32 = 0o100000;
+/// `S_IFLNK` file type bits.
+const UNIX_SYMLINK: u32 = 0o120000;
+/// Header ID of the ZIP extended timestamp extra field.
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+/// Header ID of the ZIP NTFS extra field, recording Windows file times.
+const NTFS_TIMES: u16 = 0x000a;
+/// Seconds from the Windows epoch, 1601-01-01, to the Unix epoch.
+const WINDOWS_EPOCH_OFFSET: u64 = 11_644_473_600;
+/// Header IDs of the ZIP extra fields written from the entry itself, which
+/// copies from [`EntryInfo::extra_fields`] would contradict: ZIP64 sizes,
+/// AES encryption, the extended timestamp and NTFS times, and the Unicode
+/// Path of a name now stored as UTF-8.
+const DERIVED_EXTRA_FIELDS: [u16; 5] = [0x0001, 0x9901, EXTENDED_TIMESTAMP, NTFS_TIMES, 0x7075];
+/// Size recorded for files added from a reader of unknown length, so that
+/// ZIP entries reserve room for 64-bit sizes.
+const UNKNOWN_SIZE: u64 = u64::MAX;
+
+/// A compression method together with its level.
+///
+/// Used by [`Archive::recompress`](crate::Archive::recompress) to pick the
+/// compression of the rewritten archive. How a codec is applied depends on
+/// the container: TAR archives and single-file streams are compressed as a
+/// whole, while ZIP and 7z archives compress each entry.
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::Codec;
+///
+/// let codec = Codec::Zstd { level: 19 };
+/// assert_eq!(codec.name(), "zstd");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// No compression.
+    Store,
+    /// Deflate at levels 1-9, written as gzip around TAR archives and
+    /// single files.
+    Deflate { level: u32 },
+    /// bzip2 at levels 1-9.
+    Bzip2 { level: u32 },
+    /// xz at presets 0-9, or LZMA2 inside 7z archives.
+    Xz { level: u32 },
+    /// Zstandard at levels 1-22.
+    Zstd { level: i32 },
+}
+
+impl Codec {
+    /// Returns the usual name of the compression method.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Store => "store",
+            Codec::Deflate {
+                ..
+            } => "deflate",
+            Codec::Bzip2 {
+                ..
+            } => "bzip2",
+            Codec::Xz {
+                ..
+            } => "xz",
+            Codec::Zstd {
+                ..
+            } => "zstd",
+        }
+    }
+
+    /// Checks that the level is in the codec's range.
+    pub(crate) fn validate(self) -> Result<Self, ArchiveError> {
+        let valid = match self {
+            Codec::Store => true,
+            Codec::Deflate {
+                level,
+            }
+            | Codec::Bzip2 {
+                level,
+            } => (1..=9).contains(&level),
+            Codec::Xz {
+                level,
+            } => level <= 9,
+            Codec::Zstd {
+                level,
+            } => (1..=22).contains(&level),
+        };
+        if !valid {
+            return Err(ArchiveError::Compression {
+                algorithm: self.name().into(),
+                message: "compression level out of range".into(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Returns the TAR-based format compressed with this codec.
+    pub(crate) fn tar_format(self) -> ArchiveFormat {
+        match self {
+            Codec::Store => ArchiveFormat::Tar,
+            Codec::Deflate {
+                ..
+            } => ArchiveFormat::TarGz,
+            Codec::Bzip2 {
+                ..
+            } => ArchiveFormat::TarBz2,
+            Codec::Xz {
+                ..
+            } => ArchiveFormat::TarXz,
+            Codec::Zstd {
+                ..
+            } => ArchiveFormat::TarZst,
+        }
+    }
+
+    /// Returns the single-file format compressed with this codec.
+    pub(crate) fn single_format(self) -> Result<ArchiveFormat, ArchiveError> {
+        match self {
+            Codec::Store => {
+                Err(ArchiveError::unsupported_static(
+                    "uncompressed single-file streams",
+                ))
+            }
+            Codec::Deflate {
+                ..
+            } => Ok(ArchiveFormat::Gzip),
+            Codec::Bzip2 {
+                ..
+            } => Ok(ArchiveFormat::Bzip2),
+            Codec::Xz {
+                ..
+            } => Ok(ArchiveFormat::Xz),
+            Codec::Zstd {
+                ..
+            } => Ok(ArchiveFormat::Zstd),
+        }
+    }
+
+    /// Returns the ZIP compression method and level for this codec.
+    fn zip_method(self) -> (zip::CompressionMethod, Option<i64>) {
+        match self {
+            Codec::Store => (zip::CompressionMethod::Stored, None),
+            Codec::Deflate {
+                level,
+            } => (zip::CompressionMethod::Deflated, Some(i64::from(level))),
+            Codec::Bzip2 {
+                level,
+            } => (zip::CompressionMethod::Bzip2, Some(i64::from(level))),
+            Codec::Xz {
+                level,
+            } => (zip::CompressionMethod::Xz, Some(i64::from(level))),
+            Codec::Zstd {
+                level,
+            } => (zip::CompressionMethod::Zstd, Some(i64::from(level))),
+        }
+    }
+
+    /// Returns the 7z content method for this codec.
+    pub(crate) fn sevenz_method(self) -> Result<sevenz_rust2::EncoderConfiguration, ArchiveError> {
+        use sevenz_rust2::{
+            EncoderConfiguration, EncoderMethod,
+            encoder_options::{Bzip2Options, Lzma2Options},
+        };
+
+        match self {
+            Codec::Store => Ok(EncoderConfiguration::new(EncoderMethod::COPY)),
+            Codec::Xz {
+                level,
+            } => {
+                Ok(EncoderConfiguration::new(EncoderMethod::LZMA2)
+                    .with_options(Lzma2Options::from_level(level).into()))
+            }
+            Codec::Bzip2 {
+                level,
+            } => {
+                Ok(EncoderConfiguration::new(EncoderMethod::BZIP2)
+                    .with_options(Bzip2Options::from_level(level).into()))
+            }
+            Codec::Deflate {
+                ..
+            }
+            | Codec::Zstd {
+                ..
+            } => {
+                Err(ArchiveError::unsupported_static(
+                    "Deflate and Zstandard compression of 7z entries",
+                ))
+            }
+        }
+    }
+}
+
+/// How the headers of TAR archives are written.
+///
+/// Either way, extended attributes in [`EntryInfo::xattrs`], keywords set
+/// with [`ArchiveBuilder::pax_keywords`] and owner names of more than 32
+/// bytes go into PAX extended headers.
+///
+/// Names and link targets are never truncated in either flavor, however
+/// deeply entries are nested.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use compak::{Archive, ArchiveFormat, CompressionOptions, TarFlavor, writer::ArchiveBuilder};
+///
+/// // Forty levels of directories, far past the 100 bytes of a header field
+/// let deep: String = (0..40).map(|level| format!("level-{level:03}/")).collect();
+/// let file = format!("{deep}file.txt");
+/// for flavor in [TarFlavor::Gnu, TarFlavor::Pax] {
+///     let options = CompressionOptions::new().tar_flavor(flavor);
+///     let mut builder =
+///         ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Tar, &options).unwrap();
+///     builder.add_empty_dir(&deep).unwrap();
+///     builder.add_bytes(&file, b"deep").unwrap();
+///     builder.add_symlink("shortcut", &file).unwrap();
+///     let tar = builder.finish().unwrap().into_inner();
+///
+///     let archive = Archive::from_reader(Cursor::new(tar)).unwrap();
+///     let entries: Vec<_> = archive.entries().unwrap().collect();
+///     assert_eq!(entries[0].name, deep);
+///     assert_eq!(entries[1].name, file);
+///     assert_eq!(entries[2].link_target.as_ref(), Some(&file));
+///     assert_eq!(archive.read_entry(&file).unwrap(), b"deep");
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TarFlavor {
+    /// GNU headers, with `././@LongLink` entries for names and link targets
+    /// of more than 100 bytes and base-256 numbers for large sizes and IDs.
+    #[default]
+    Gnu,
+    /// POSIX ustar headers, with PAX extended headers for whatever they
+    /// cannot hold: long or non-ASCII names and link targets, large sizes
+    /// and IDs, and fractional modification times.
+    Pax,
+}
+
+/// Compression levels used when creating archives.
+///
+/// Each compression method has its own level, and the format being written
+/// decides which one applies: gzip for `.tar.gz` and `.gz`, bzip2 for
+/// `.tar.bz2` and `.bz2`, xz for `.tar.xz` and `.xz` as well as the LZMA2
+/// compression of 7z archives, Zstandard for `.tar.zst` and `.zst`, and the
+/// ZIP Deflate level for the files in ZIP archives, unless
+/// [`zip_compression`](Self::zip_compression) picks another method for them.
+/// The defaults are the usual ones of the command-line tools: level 6 for
+/// gzip, bzip2, xz and ZIP, and level 3 for Zstandard.
+///
+/// Levels are checked when an archive is created with them; call
+/// [`validate`](Self::validate) to check them earlier.
+///
+/// When the `SOURCE_DATE_EPOCH` environment variable holds a number of
+/// seconds since the Unix epoch, the default options clamp the modification
+/// time of every entry to that moment, as reproducible builds expect; see
+/// [`clamp_mtime`](Self::clamp_mtime).
+///
+/// # Examples
+///
+/// ```rust
+/// use compak::CompressionOptions;
+///
+/// let options = CompressionOptions::new().zstd_level(19).gzip_level(9);
+/// assert!(options.validate().is_ok());
+/// assert!(CompressionOptions::new().zstd_level(30).validate().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionOptions {
+    gzip_level: u32,
+    bzip2_level: u32,
+    xz_level: u32,
+    zstd_level: i32,
+    zip_codec: Codec,
+    mtime_clamp: Option<SystemTime>,
+    tar_flavor: TarFlavor,
+    zip_ntfs_times: bool,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            gzip_level: 6,
+            bzip2_level: 6,
+            xz_level: 6,
+            zstd_level: 3,
+            zip_codec: Codec::Deflate {
+                level: 6,
+            },
+            mtime_clamp: source_date_epoch(),
+            tar_flavor: TarFlavor::Gnu,
+            zip_ntfs_times: false,
+        }
+    }
+}
+
+impl CompressionOptions {
+    /// Creates options with the default levels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the gzip level, from 1 (fastest) to 9 (smallest).
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        self.gzip_level = level;
+        self
+    }
+
+    /// Sets the bzip2 level, from 1 (fastest) to 9 (smallest).
+    pub fn bzip2_level(mut self, level: u32) -> Self {
+        self.bzip2_level = level;
+        self
+    }
+
+    /// Sets the xz preset, from 0 (fastest) to 9 (smallest), which also
+    /// applies to 7z archives.
+    pub fn xz_level(mut self, level: u32) -> Self {
+        self.xz_level = level;
+        self
+    }
+
+    /// Sets the Zstandard level, from 1 (fastest) to 22 (smallest).
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.zstd_level = level;
+        self
+    }
+
+    /// Sets the Deflate level of files in ZIP archives, from 1 (fastest)
+    /// to 9 (smallest).
+    ///
+    /// This compresses files with Deflate again if
+    /// [`zip_compression`](Self::zip_compression) picked another method.
+    pub fn zip_deflate_level(mut self, level: u32) -> Self {
+        self.zip_codec = Codec::Deflate {
+            level,
+        };
+        self
+    }
+
+    /// Sets the compression method and level of files in ZIP archives.
+    ///
+    /// Besides Deflate, ZIP archives can hold files compressed with bzip2
+    /// (method 12), xz (method 95) or Zstandard (method 93), which often
+    /// come out smaller. Older tools, including the ZIP support built into
+    /// Windows and macOS, can't extract them, so only pick one of these when
+    /// whoever reads the archive can. [`Codec::Store`] stores files as they
+    /// are.
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, Codec, CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// let options = CompressionOptions::new().zip_compression(Codec::Zstd { level: 19 });
+    /// let mut builder =
+    ///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &options).unwrap();
+    /// builder.add_bytes("report.txt", &[b'a'; 4096]).unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let archive = Archive::from_bytes(zip).unwrap();
+    /// assert_eq!(archive.read_entry("report.txt").unwrap(), [b'a'; 4096]);
+    /// ```
+    pub fn zip_compression(mut self, codec: Codec) -> Self {
+        self.zip_codec = codec;
+        self
+    }
+
+    /// Sets the latest modification time entries are stored with, or
+    /// `None` to store them as they are.
+    ///
+    /// Entries modified later are stored as modified at `mtime`, so that
+    /// archives of the same files come out identical however recently they
+    /// were touched. This replaces the clamp taken from
+    /// `SOURCE_DATE_EPOCH`.
+    ///
+    /// ```rust
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// use compak::CompressionOptions;
+    ///
+    /// let release = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    /// let options = CompressionOptions::new().clamp_mtime(Some(release));
+    /// assert_eq!(options.mtime_clamp(), Some(release));
+    /// ```
+    pub fn clamp_mtime(mut self, mtime: Option<SystemTime>) -> Self {
+        self.mtime_clamp = mtime;
+        self
+    }
+
+    /// Returns the latest modification time entries are stored with, if
+    /// any.
+    pub fn mtime_clamp(&self) -> Option<SystemTime> {
+        self.mtime_clamp
+    }
+
+    /// Sets how the headers of TAR-based archives are written.
+    pub fn tar_flavor(mut self, flavor: TarFlavor) -> Self {
+        self.tar_flavor = flavor;
+        self
+    }
+
+    /// Sets whether entries of ZIP archives record their modification time
+    /// in an NTFS extra field too, to the 100 nanoseconds, as Windows tools
+    /// write it.
+    ///
+    /// Either way every entry modified between 1970 and 2038 gets an
+    /// extended timestamp field, which holds whole seconds. Entries that
+    /// carry an NTFS field already, such as those converted from a ZIP
+    /// archive written on Windows, keep it even when this is off.
+    ///
+    /// ```rust
+    /// use std::{
+    ///     io::Cursor,
+    ///     time::{Duration, UNIX_EPOCH},
+    /// };
+    ///
+    /// use compak::{Archive, ArchiveFormat, CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// // Clamping to a past moment stores every entry as modified then
+    /// let mtime = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_700);
+    /// let options = CompressionOptions::new().clamp_mtime(Some(mtime)).zip_ntfs_times(true);
+    /// let mut builder =
+    ///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &options).unwrap();
+    /// builder.add_bytes("report.txt", b"foo").unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let archive = Archive::from_bytes(zip).unwrap();
+    /// assert_eq!(archive.entry_info(0).unwrap().mtime, Some(mtime));
+    /// ```
+    pub fn zip_ntfs_times(mut self, enabled: bool) -> Self {
+        self.zip_ntfs_times = enabled;
+        self
+    }
+
+    /// Checks that every level is in the range of its compression method.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ArchiveError::Compression`] naming the method whose level
+    /// is out of range.
+    pub fn validate(&self) -> Result<(), ArchiveError> {
+        let levels = [
+            (
+                "gzip",
+                Codec::Deflate {
+                    level: self.gzip_level,
+                },
+            ),
+            (
+                "bzip2",
+                Codec::Bzip2 {
+                    level: self.bzip2_level,
+                },
+            ),
+            (
+                "xz",
+                Codec::Xz {
+                    level: self.xz_level,
+                },
+            ),
+            (
+                "zstd",
+                Codec::Zstd {
+                    level: self.zstd_level,
+                },
+            ),
+            (&format!("ZIP {}", self.zip_codec.name()), self.zip_codec),
+        ];
+        for (algorithm, codec) in levels {
+            codec.validate().map_err(|_| {
+                ArchiveError::Compression {
+                    algorithm: algorithm.to_string().into(),
+                    message: "compression level out of range".into(),
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the modification time to store for an entry modified at
+    /// `mtime`.
+    pub(crate) fn clamp(&self, mtime: SystemTime) -> SystemTime {
+        self.mtime_clamp.map_or(mtime, |clamp| mtime.min(clamp))
+    }
+
+    /// Returns `info` with its modification time clamped, borrowing it
+    /// unchanged if there is nothing to clamp.
+    pub(crate) fn clamped<'a>(&self, info: &'a EntryInfo) -> Cow<'a, EntryInfo> {
+        match info.mtime {
+            Some(mtime) if self.clamp(mtime) != mtime => {
+                Cow::Owned(EntryInfo {
+                    mtime: Some(self.clamp(mtime)),
+                    ..info.clone()
+                })
+            }
+            _ => Cow::Borrowed(info),
+        }
+    }
+
+    /// Returns the codec compressing the whole of a TAR-based or
+    /// single-file `format`, or `None` for other formats.
+    pub(crate) fn codec(&self, format: ArchiveFormat) -> Option<Codec> {
+        match format {
+            ArchiveFormat::Tar => Some(Codec::Store),
+            ArchiveFormat::TarGz | ArchiveFormat::Gzip => {
+                Some(Codec::Deflate {
+                    level: self.gzip_level,
+                })
+            }
+            ArchiveFormat::TarBz2 | ArchiveFormat::Bzip2 => {
+                Some(Codec::Bzip2 {
+                    level: self.bzip2_level,
+                })
+            }
+            ArchiveFormat::TarXz | ArchiveFormat::Xz => {
+                Some(Codec::Xz {
+                    level: self.xz_level,
+                })
+            }
+            ArchiveFormat::TarZst | ArchiveFormat::Zstd => {
+                Some(Codec::Zstd {
+                    level: self.zstd_level,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the codec compressing files in ZIP archives.
+    pub(crate) fn zip_codec(&self) -> Codec {
+        self.zip_codec
+    }
+
+    /// Returns the codec compressing 7z archives.
+    fn sevenz_codec(&self) -> Codec {
+        Codec::Xz {
+            level: self.xz_level,
+        }
+    }
+}
+
+/// Reads the moment `SOURCE_DATE_EPOCH` holds, ignoring values that aren't
+/// a number of seconds.
+fn source_date_epoch() -> Option<SystemTime> {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Compression layer wrapped around a TAR stream or a single file.
+pub(crate) enum Encoder<W: Write> {
+    Plain(W),
+    Gz(flate2::write::GzEncoder<W>),
+    Xz(liblzma::write::XzEncoder<W>),
+    Bz2(bzip2::write::BzEncoder<W>),
+    Zst(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates the encoder matching the outer layer of a TAR-based format or
+    /// the compression of a single-file format, at the level set in
+    /// `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `format` is neither TAR-based nor a single-file
+    /// stream, or if the encoder cannot be initialized.
+    pub(crate) fn new(
+        format: ArchiveFormat,
+        writer: W,
+        options: &CompressionOptions,
+    ) -> Result<Self, ArchiveError> {
+        Ok(match format {
+            ArchiveFormat::Tar
+            | ArchiveFormat::TarGz
+            | ArchiveFormat::Gzip
+            | ArchiveFormat::TarXz
+            | ArchiveFormat::Xz
+            | ArchiveFormat::TarBz2
+            | ArchiveFormat::Bzip2
+            | ArchiveFormat::TarZst
+            | ArchiveFormat::Zstd => {
+                let codec = options.codec(format).unwrap_or(Codec::Store);
+                Encoder::with_codec(codec, writer)?
+            }
+            ArchiveFormat::TarLz4 | ArchiveFormat::Lz4 => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating LZ4-compressed archives",
+                ));
+            }
+            ArchiveFormat::TarBr | ArchiveFormat::Brotli => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating Brotli-compressed archives",
+                ));
+            }
+            ArchiveFormat::TarLz | ArchiveFormat::Lzip => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating lzip-compressed archives",
+                ));
+            }
+            ArchiveFormat::TarLzma | ArchiveFormat::Lzma => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating legacy LZMA archives",
+                ));
+            }
+            ArchiveFormat::TarZ | ArchiveFormat::Compress => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating compress archives",
+                ));
+            }
+            ArchiveFormat::Zip
+            | ArchiveFormat::SevenZ
+            | ArchiveFormat::Rar
+            | ArchiveFormat::Ar
+            | ArchiveFormat::Deb
+            | ArchiveFormat::Iso
+            | ArchiveFormat::Squashfs
+            | ArchiveFormat::Wim
+            | ArchiveFormat::Xar
+            | ArchiveFormat::Msi
+            | ArchiveFormat::AppImage
+            | ArchiveFormat::Custom(_) => {
+                return Err(ArchiveError::unsupported_static(
+                    "stream compression of non-TAR formats",
+                ));
+            }
+        })
+    }
+
+    /// Creates the encoder for `codec`, which must have a valid level.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder cannot be initialized.
+    pub(crate) fn with_codec(codec: Codec, writer: W) -> Result<Self, ArchiveError> {
+        Ok(match codec {
+            Codec::Store => Encoder::Plain(writer),
+            Codec::Deflate {
+                level,
+            } => {
+                Encoder::Gz(flate2::write::GzEncoder::new(
+                    writer,
+                    flate2::Compression::new(level),
+                ))
+            }
+            Codec::Bzip2 {
+                level,
+            } => {
+                Encoder::Bz2(bzip2::write::BzEncoder::new(
+                    writer,
+                    bzip2::Compression::new(level),
+                ))
+            }
+            Codec::Xz {
+                level,
+            } => Encoder::Xz(liblzma::write::XzEncoder::new(writer, level)),
+            Codec::Zstd {
+                level,
+            } => Encoder::Zst(zstd::stream::write::Encoder::new(writer, level)?),
+        })
+    }
+
+    /// Flushes any buffered compressed data and returns the inner writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(w) => Ok(w),
+            Encoder::Gz(w) => w.finish(),
+            Encoder::Xz(w) => w.finish(),
+            Encoder::Bz2(w) => w.finish(),
+            Encoder::Zst(w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gz(w) => w.write(buf),
+            Encoder::Xz(w) => w.write(buf),
+            Encoder::Bz2(w) => w.write(buf),
+            Encoder::Zst(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gz(w) => w.flush(),
+            Encoder::Xz(w) => w.flush(),
+            Encoder::Bz2(w) => w.flush(),
+            Encoder::Zst(w) => w.flush(),
+        }
+    }
+}
+
+/// Format-specific archive writer that accepts entries one at a time.
+pub(crate) enum FormatWriter<W: Write + Seek> {
+    Zip {
+        writer: Box<zip::ZipWriter<Commented<W>>>,
+        /// Compression of the files added.
+        codec: Codec,
+        /// Whether entries get an NTFS times field.
+        ntfs_times: bool,
+        /// Comments of the entries added, which go into the central
+        /// directory as it is written.
+        comments: EntryComments,
+    },
+    /// A ZIP archive written to a sink that can't seek, whose central
+    /// directory can't be held back for comments.
+    ZipStream {
+        writer: Box<zip::ZipWriter<W>>,
+        /// Compression of the files added.
+        codec: Codec,
+        /// Whether entries get an NTFS times field.
+        ntfs_times: bool,
+    },
+    Tar {
+        builder: tar::Builder<Encoder<W>>,
+        flavor: TarFlavor,
+        /// PAX keywords recorded for every entry.
+        keywords: Vec<(String, Vec<u8>)>,
+    },
+    SevenZ(sevenz_rust2::ArchiveWriter<W>),
+    /// A single-file stream, which accepts exactly one file entry.
+    Single {
+        encoder: Encoder<W>,
+        written: bool,
+    },
+}
+
+impl<W: Write + Seek> FormatWriter<W> {
+    /// Starts a new archive of the given format on top of `writer`, with
+    /// the default compression levels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format's writer cannot be initialized.
+    pub(crate) fn new(format: ArchiveFormat, writer: W) -> Result<Self, ArchiveError> {
+        Self::with_options(format, writer, &CompressionOptions::default())
+    }
+
+    /// Starts a new archive of the given format on top of `writer`,
+    /// compressed at the levels set in `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a level in `options` is out of range or the
+    /// format's writer cannot be initialized.
+    pub(crate) fn with_options(
+        format: ArchiveFormat,
+        writer: W,
+        options: &CompressionOptions,
+    ) -> Result<Self, ArchiveError> {
+        options.validate()?;
+        Ok(match format {
+            ArchiveFormat::Zip => {
+                let (writer, comments) = central::wrap(writer);
+                FormatWriter::Zip {
+                    writer: Box::new(zip::ZipWriter::new(writer)),
+                    codec: options.zip_codec(),
+                    ntfs_times: options.zip_ntfs_times,
+                    comments,
+                }
+            }
+            ArchiveFormat::SevenZ => {
+                let mut writer = sevenz_rust2::ArchiveWriter::new(writer)?;
+                writer.set_content_methods(vec![options.sevenz_codec().sevenz_method()?]);
+                FormatWriter::SevenZ(writer)
+            }
+            ArchiveFormat::Rar => {
+                return Err(ArchiveError::unsupported_static("creating RAR archives"));
+            }
+            ArchiveFormat::Ar | ArchiveFormat::Deb => {
+                return Err(ArchiveError::unsupported_static("creating ar archives"));
+            }
+            ArchiveFormat::Iso => {
+                return Err(ArchiveError::unsupported_static("creating ISO images"));
+            }
+            ArchiveFormat::Squashfs => {
+                return Err(ArchiveError::unsupported_static("creating SquashFS images"));
+            }
+            ArchiveFormat::Wim => {
+                return Err(ArchiveError::unsupported_static("creating WIM images"));
+            }
+            ArchiveFormat::Xar => {
+                return Err(ArchiveError::unsupported_static("creating xar archives"));
+            }
+            ArchiveFormat::Msi => {
+                return Err(ArchiveError::unsupported_static("creating MSI packages"));
+            }
+            ArchiveFormat::AppImage => {
+                return Err(ArchiveError::unsupported_static("creating AppImages"));
+            }
+            ArchiveFormat::Custom(_) => {
+                return Err(ArchiveError::unsupported_static(
+                    "creating archives in custom formats",
+                ));
+            }
+            ArchiveFormat::Gzip
+            | ArchiveFormat::Xz
+            | ArchiveFormat::Bzip2
+            | ArchiveFormat::Zstd
+            | ArchiveFormat::Lz4
+            | ArchiveFormat::Brotli
+            | ArchiveFormat::Lzip
+            | ArchiveFormat::Lzma
+            | ArchiveFormat::Compress => {
+                FormatWriter::Single {
+                    encoder: Encoder::new(format, writer, options)?,
+                    written: false,
+                }
+            }
+            ArchiveFormat::Tar
+            | ArchiveFormat::TarGz
+            | ArchiveFormat::TarXz
+            | ArchiveFormat::TarBz2
+            | ArchiveFormat::TarZst
+            | ArchiveFormat::TarLz4
+            | ArchiveFormat::TarBr
+            | ArchiveFormat::TarLz
+            | ArchiveFormat::TarLzma
+            | ArchiveFormat::TarZ => {
+                FormatWriter::Tar {
+                    builder: tar::Builder::new(Encoder::new(format, writer, options)?),
+                    flavor: options.tar_flavor,
+                    keywords: Vec::new(),
+                }
+            }
+        })
+    }
+
+    /// Appends a single entry.
+    ///
+    /// For regular files `data` must yield exactly `info.size` bytes. For
+    /// directories and links it is not read; the link target is taken from
+    /// `info.link_target`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The entry type cannot be represented in the target format
+    /// * Reading `data` or writing the archive fails
+    pub(crate) fn add(
+        &mut self,
+        info: &EntryInfo,
+        data: &mut dyn Read,
+    ) -> Result<(), ArchiveError> {
+        match self {
+            FormatWriter::Zip {
+                writer,
+                codec,
+                ntfs_times,
+                comments,
+            } => {
+                if let Some(comment) = &info.comment {
+                    check_zip_comment(comment.as_bytes())?;
+                }
+                add_zip(writer, info, data, Some(*codec), *ntfs_times)?;
+                if let Some(comment) = &info.comment {
+                    comments.insert(zip_name(info), comment.clone());
+                }
+                Ok(())
+            }
+            FormatWriter::ZipStream {
+                writer,
+                codec,
+                ntfs_times,
+            } => {
+                if info.comment.is_some() {
+                    return Err(ArchiveError::unsupported_static(
+                        "entry comments in streamed ZIP archives",
+                    ));
+                }
+                add_zip(writer, info, data, Some(*codec), *ntfs_times)
+            }
+            FormatWriter::Tar {
+                builder,
+                flavor,
+                keywords,
+            } => add_tar(builder, info, data, None, *flavor, keywords),
+            FormatWriter::SevenZ(writer) => add_7z(writer, info, data),
+            FormatWriter::Single {
+                encoder,
+                written,
+            } => {
+                if info.kind != EntryKind::File {
+                    return Err(ArchiveError::unsupported_static(
+                        "non-file entries in single-file compressed streams",
+                    ));
+                }
+                if *written {
+                    return Err(ArchiveError::unsupported_static(
+                        "more than one file in a single-file compressed stream",
+                    ));
+                }
+                io::copy(data, encoder)?;
+                *written = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Appends a file whose size is not known up front.
+    ///
+    /// TAR headers record the size before the data, so for TAR-based
+    /// formats `data` is buffered in memory first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading `data` or writing the archive fails.
+    pub(crate) fn add_unsized(
+        &mut self,
+        info: &EntryInfo,
+        data: &mut dyn Read,
+    ) -> Result<(), ArchiveError> {
+        let mut info = info.clone();
+        if let FormatWriter::Tar {
+            ..
+        } = self
+        {
+            let mut buffer = Vec::new();
+            data.read_to_end(&mut buffer)?;
+            info.size = buffer.len() as u64;
+            return self.add(&info, &mut buffer.as_slice());
+        }
+        info.size = UNKNOWN_SIZE;
+        self.add(&info, data)
+    }
+
+    /// Whether the format can record hard links.
+    pub(crate) fn supports_hard_links(&self) -> bool {
+        matches!(self, FormatWriter::Tar { .. })
+    }
+
+    /// Returns the data regions to store of a file of `len` bytes, if it
+    /// has holes and the format can record them.
+    pub(crate) fn sparse_regions(&self, file: &File, len: u64) -> Option<Vec<Region>> {
+        match self {
+            FormatWriter::Tar {
+                ..
+            } => sparse::data_regions(file, len),
+            _ => None,
+        }
+    }
+
+    /// Appends a sparse file, of which `data` yields just the bytes in
+    /// `regions`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format cannot record sparse files or writing
+    /// the archive fails.
+    pub(crate) fn add_sparse(
+        &mut self,
+        info: &EntryInfo,
+        data: &mut dyn Read,
+        regions: &[Region],
+    ) -> Result<(), ArchiveError> {
+        match self {
+            FormatWriter::Tar {
+                builder,
+                keywords,
+                ..
+            } => add_tar(builder, info, data, Some(regions), TarFlavor::Gnu, keywords),
+            _ => {
+                Err(ArchiveError::unsupported_static(
+                    "sparse files outside TAR archives",
+                ))
+            }
+        }
+    }
+
+    /// Writes the archive trailer and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the trailer or buffered compressed data cannot be written.
+    pub(crate) fn finish(self) -> Result<W, ArchiveError> {
+        match self {
+            FormatWriter::Zip {
+                writer,
+                comments,
+                ..
+            } => comments.finish(*writer),
+            FormatWriter::ZipStream {
+                writer, ..
+            } => Ok(writer.finish()?),
+            FormatWriter::Tar {
+                builder, ..
+            } => Ok(builder.into_inner()?.finish()?),
+            FormatWriter::SevenZ(writer) => Ok(writer.finish()?),
+            FormatWriter::Single {
+                encoder, ..
+            } => Ok(encoder.finish()?),
+        }
+    }
+}
+
+/// A builder that assembles an archive one entry at a time.
+///
+/// Entries are written to the underlying writer as they are added, so the
+/// builder never holds more than one entry in memory (with the exception of
+/// [`add_reader`](Self::add_reader) on TAR-based formats, which need the
+/// entry size up front). Call [`finish`](Self::finish) to write the archive
+/// trailer; dropping the builder without finishing leaves a truncated archive.
+///
+/// # Examples
+///
+/// ```no_run
+/// use compak::writer::ArchiveBuilder;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut builder = ArchiveBuilder::create("bundle.tar.gz")?;
+///     builder
+///         .add_empty_dir("config")?
+///         .add_bytes("config/app.toml", b"debug = false\n")?
+///         .add_file("README.md")?
+///         .add_reader("logs/today.log", std::io::stdin())?;
+///     builder.finish()?;
+///     Ok(())
+/// }
+/// ```
+pub struct ArchiveBuilder<W: Write + Seek> {
+    format: ArchiveFormat,
+    writer: FormatWriter<W>,
+    options: CompressionOptions,
+    progress: ProgressTracker,
+    /// Comment of the entry added next.
+    entry_comment: Option<String>,
+    /// Extra fields of the entry added next.
+    entry_extra_fields: Vec<ExtraField>,
+}
+
+impl ArchiveBuilder<BufWriter<File>> {
+    /// Creates a builder writing to a new file at `path`.
+    ///
+    /// The format is determined by the file extension, as with
+    /// [`Archive::new`](crate::Archive::new). Any existing file is overwritten.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file extension is not recognized
+    /// * The file cannot be created
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        Self::create_with(path, &CompressionOptions::default())
+    }
+
+    /// Creates a builder writing to a new file at `path`, compressed at the
+    /// levels set in `options`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file extension is not recognized
+    /// * A level in `options` is out of range
+    /// * The file cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let options = CompressionOptions::new().zstd_level(19);
+    ///     let mut builder = ArchiveBuilder::create_with("release.tar.zst", &options)?;
+    ///     builder.add_file("target/release/app")?;
+    ///     builder.finish()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn create_with<P: AsRef<Path>>(
+        path: P,
+        options: &CompressionOptions,
+    ) -> Result<Self, ArchiveError> {
+        let format = format::detect_from_extension(path.as_ref())?;
+        options.validate()?;
+        let file = BufWriter::new(File::create(path.as_ref()).at_path(&path)?);
+        Self::new_with(file, format, options)
+    }
+
+    /// Creates a builder writing a TAR archive compressed with `codec` to a
+    /// new file at `path`.
+    ///
+    /// Covers plain `.tar` with [`Codec::Store`] as well as `.tar.gz`,
+    /// `.tar.bz2`, `.tar.xz` and `.tar.zst`. The extension of `path` is not
+    /// consulted, so it is up to the caller to name the file to match.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The level of `codec` is out of range
+    /// * The file cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::{Codec, writer::ArchiveBuilder};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     for (path, codec) in [
+    ///         ("site.tar", Codec::Store),
+    ///         ("site.tar.gz", Codec::Deflate { level: 9 }),
+    ///         ("site.tar.zst", Codec::Zstd { level: 19 }),
+    ///     ] {
+    ///         let mut builder = ArchiveBuilder::create_tar(path, codec)?;
+    ///         builder.add_file("index.html")?;
+    ///         builder.finish()?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn create_tar<P: AsRef<Path>>(path: P, codec: Codec) -> Result<Self, ArchiveError> {
+        let options = CompressionOptions::default();
+        let options = match codec.validate()? {
+            Codec::Store => options,
+            Codec::Deflate {
+                level,
+            } => options.gzip_level(level),
+            Codec::Bzip2 {
+                level,
+            } => options.bzip2_level(level),
+            Codec::Xz {
+                level,
+            } => options.xz_level(level),
+            Codec::Zstd {
+                level,
+            } => options.zstd_level(level),
+        };
+        let file = BufWriter::new(File::create(path.as_ref()).at_path(&path)?);
+        Self::new_with(file, codec.tar_format(), &options)
+    }
+}
+
+impl ArchiveBuilder<File> {
+    /// Opens the ZIP archive at `path` for adding entries to it.
+    ///
+    /// The existing entries are left in place. New entries are written over
+    /// the old central directory, and [`finish`](Self::finish) writes a new
+    /// one listing the old entries and the new. Until then the file is not
+    /// a readable archive, so keep a copy if an interrupted append must not
+    /// lose it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The file cannot be opened for reading and writing
+    /// * The file is not a ZIP archive, or is a split one
+    /// * The central directory cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use compak::writer::ArchiveBuilder;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut builder = ArchiveBuilder::append("artifacts.zip")?;
+    ///     builder.add_file_as("builds/1.4.2/app.tar.gz", "target/app.tar.gz")?;
+    ///     builder.finish()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn append<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        let path = path.as_ref();
+        if format::detect_from_file(path)? != ArchiveFormat::Zip {
+            return Err(ArchiveError::unsupported_static(
+                "appending to non-ZIP archives",
+            ));
+        }
+        if volume::is_split_zip(path)? {
+            return Err(ArchiveError::unsupported_static(
+                "appending to split ZIP archives",
+            ));
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .at_path(path)?;
+        // Shares the file position with `file`
+        let mut handle = file.try_clone()?;
+        let central_start = zip::ZipArchive::new(&mut handle)?.central_directory_start();
+        let (file, comments) = central::wrap(file);
+        let writer = zip::ZipWriter::new_append(file)?;
+        // The writer carries on where it finished reading, after the old
+        // central directory, which would be left behind as dead weight
+        handle.seek(SeekFrom::Start(central_start))?;
+        Ok(Self {
+            format: ArchiveFormat::Zip,
+            writer: FormatWriter::Zip {
+                writer: Box::new(writer),
+                codec: CompressionOptions::default().zip_codec(),
+                ntfs_times: false,
+                comments,
+            },
+            options: CompressionOptions::default(),
+            progress: ProgressTracker::default(),
+            entry_comment: None,
+            entry_extra_fields: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> ArchiveBuilder<StreamWriter<W>> {
+    /// Creates a builder writing to a non-seekable sink.
+    ///
+    /// TAR-based formats are naturally streamable. ZIP entries are written
+    /// with data descriptors, so sizes and CRCs follow the entry data
+    /// instead of being patched into the local header afterwards. 7z needs
+    /// to seek back to its start header and cannot be streamed.
+    ///
+    /// Use [`StreamWriter::into_inner`] on the value returned by
+    /// [`finish`](Self::finish) to get `writer` back.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `format` is 7z, or if the
+    /// format's writer cannot be initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::Write;
+    ///
+    /// use compak::{ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let stdout = std::io::stdout().lock();
+    ///     let mut builder = ArchiveBuilder::new_stream(stdout, ArchiveFormat::TarGz)?;
+    ///     builder.add_bytes("report.csv", b"id,value\n1,42\n")?;
+    ///     builder.finish()?.into_inner().flush()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new_stream(writer: W, format: ArchiveFormat) -> Result<Self, ArchiveError> {
+        Self::new_stream_with(writer, format, &CompressionOptions::default())
+    }
+
+    /// Creates a builder writing to a non-seekable sink, compressed at the
+    /// levels set in `options`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `format` is 7z, if a level in
+    /// `options` is out of range, or if the format's writer cannot be
+    /// initialized.
+    pub fn new_stream_with(
+        writer: W,
+        format: ArchiveFormat,
+        options: &CompressionOptions,
+    ) -> Result<Self, ArchiveError> {
+        let writer = match format {
+            ArchiveFormat::Zip => {
+                options.validate()?;
+                FormatWriter::ZipStream {
+                    writer: Box::new(zip::ZipWriter::new_stream(writer)),
+                    codec: options.zip_codec(),
+                    ntfs_times: options.zip_ntfs_times,
+                }
+            }
+            ArchiveFormat::SevenZ => {
+                return Err(ArchiveError::unsupported_static(
+                    "streaming creation of 7z archives",
+                ));
+            }
+            _ => FormatWriter::with_options(format, StreamWriter::new(writer), options)?,
+        };
+        Ok(Self {
+            format,
+            writer,
+            options: *options,
+            progress: ProgressTracker::default(),
+            entry_comment: None,
+            entry_extra_fields: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write + Seek> ArchiveBuilder<W> {
+    /// Creates a builder writing an archive of `format` to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format's writer cannot be initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    /// builder.add_bytes("hello.txt", b"hello").unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    /// assert!(zip.starts_with(b"PK"));
+    /// ```
+    pub fn new(writer: W, format: ArchiveFormat) -> Result<Self, ArchiveError> {
+        Self::new_with(writer, format, &CompressionOptions::default())
+    }
+
+    /// Creates a builder writing an archive of `format` to `writer`,
+    /// compressed at the levels set in `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a level in `options` is out of range or the
+    /// format's writer cannot be initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{ArchiveFormat, CompressionOptions, writer::ArchiveBuilder};
+    ///
+    /// let options = CompressionOptions::new().gzip_level(9);
+    /// let mut builder =
+    ///     ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::TarGz, &options).unwrap();
+    /// builder.add_bytes("hello.txt", b"hello").unwrap();
+    /// let tar_gz = builder.finish().unwrap().into_inner();
+    /// assert!(tar_gz.starts_with(&[0x1f, 0x8b]));
+    /// ```
+    pub fn new_with(
+        writer: W,
+        format: ArchiveFormat,
+        options: &CompressionOptions,
+    ) -> Result<Self, ArchiveError> {
+        Ok(Self {
+            format,
+            writer: FormatWriter::with_options(format, writer, options)?,
+            options: *options,
+            progress: ProgressTracker::default(),
+            entry_comment: None,
+            entry_extra_fields: Vec::new(),
+        })
+    }
+
+    /// Registers a callback that receives a [`Progress`] snapshot while
+    /// entries are added.
+    ///
+    /// The callback runs after every chunk of entry data is read and once
+    /// per completed entry. `bytes_processed` counts uncompressed input.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{ArchiveFormat, Progress, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Tar)
+    ///     .unwrap()
+    ///     .on_progress(|p: &Progress<'_>| println!("{}: {} bytes", p.current_entry, p.bytes_processed));
+    /// builder.add_bytes("hello.txt", b"hello").unwrap();
+    /// builder.finish().unwrap();
+    /// ```
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress<'_>) + Send + Sync + 'static,
+    {
+        self.progress.set_callback(Arc::new(callback));
+        self
+    }
+
+    /// Makes adding entries fail with [`ArchiveError::Cancelled`] once
+    /// `token` is cancelled.
+    ///
+    /// The token is checked before each entry and before each chunk of
+    /// entry data. A builder that was cancelled should be dropped; the
+    /// archive written so far is incomplete.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.progress.set_cancellation(token);
+        self
+    }
+
+    /// Sets PAX keywords to record in the extended header of every entry
+    /// of a TAR-based archive added from now on, replacing those set
+    /// before.
+    ///
+    /// Keywords are free-form; by convention, vendor keywords start with
+    /// the vendor's name in capitals followed by a dot, such as
+    /// `COMPAK.build`. Keywords the PAX format defines, like `comment`,
+    /// override what is derived from the entry. Pass an empty list to stop
+    /// recording keywords.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not TAR-based (`ArchiveError::Unsupported`)
+    /// * A keyword is empty or holds `=` or a newline
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Tar).unwrap();
+    /// builder
+    ///     .pax_keywords([("COMPAK.build", "1234"), ("comment", "nightly")])
+    ///     .unwrap()
+    ///     .add_bytes("app.bin", b"...")
+    ///     .unwrap();
+    /// builder.finish().unwrap();
+    /// ```
+    pub fn pax_keywords<I, K, V>(&mut self, keywords: I) -> Result<&mut Self, ArchiveError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: AsRef<[u8]>,
+    {
+        let FormatWriter::Tar {
+            keywords: recorded,
+            ..
+        } = &mut self.writer
+        else {
+            return Err(ArchiveError::unsupported_static(
+                "PAX keywords outside TAR archives",
+            ));
+        };
+        let mut parsed = Vec::new();
+        for (key, value) in keywords {
+            let key = key.into();
+            if key.is_empty() || key.contains(['=', '\n']) {
+                return Err(ArchiveError::Custom {
+                    message: format!("'{key}' is not a valid PAX keyword").into(),
+                });
+            }
+            parsed.push((key, value.as_ref().to_vec()));
+        }
+        *recorded = parsed;
+        Ok(self)
+    }
+
+    /// Sets the comment stored at the end of a ZIP archive, replacing the
+    /// one set before or, when appending, the one the archive had.
+    ///
+    /// The bytes are stored as given; UTF-8 is what readers expect today.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive is not a ZIP archive (`ArchiveError::Unsupported`)
+    /// * The comment is longer than 65,535 bytes, the most ZIP can record
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    /// builder.comment("commit 1a2b3c4").unwrap().add_bytes("app.bin", b"...").unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let archive = Archive::from_bytes(zip).unwrap();
+    /// assert_eq!(archive.comment().unwrap(), b"commit 1a2b3c4");
+    /// ```
+    pub fn comment(&mut self, comment: impl Into<Vec<u8>>) -> Result<&mut Self, ArchiveError> {
+        let comment = comment.into();
+        check_zip_comment(&comment)?;
+        let comment = comment.into_boxed_slice();
+        match &mut self.writer {
+            FormatWriter::Zip {
+                writer, ..
+            } => writer.set_raw_comment(comment),
+            FormatWriter::ZipStream {
+                writer, ..
+            } => writer.set_raw_comment(comment),
+            _ => {
+                return Err(ArchiveError::unsupported_static(
+                    "archive comments outside ZIP archives",
+                ));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Sets the comment of the entry added next.
+    ///
+    /// ZIP archives store the comment in the central directory and TAR
+    /// archives in a PAX `comment` record; either way it is read back as
+    /// [`EntryInfo::comment`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive can't record comments: 7z archives, single-file
+    ///   streams and ZIP archives written with
+    ///   [`new_stream`](ArchiveBuilder::new_stream)
+    ///   (`ArchiveError::Unsupported`)
+    /// * A ZIP comment is longer than 65,535 bytes
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    /// builder
+    ///     .entry_comment("sha256:2c26b46b")
+    ///     .unwrap()
+    ///     .add_bytes("app.bin", b"foo")
+    ///     .unwrap()
+    ///     .add_bytes("README", b"...")
+    ///     .unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let archive = Archive::from_bytes(zip).unwrap();
+    /// assert_eq!(archive.entry_info(0).unwrap().comment.as_deref(), Some("sha256:2c26b46b"));
+    /// assert_eq!(archive.entry_info(1).unwrap().comment, None);
+    /// ```
+    pub fn entry_comment(&mut self, comment: impl Into<String>) -> Result<&mut Self, ArchiveError> {
+        let comment = comment.into();
+        match self.writer {
+            FormatWriter::Zip {
+                ..
+            } => check_zip_comment(comment.as_bytes())?,
+            FormatWriter::Tar {
+                ..
+            } => {}
+            FormatWriter::ZipStream {
+                ..
+            } => {
+                return Err(ArchiveError::unsupported_static(
+                    "entry comments in streamed ZIP archives",
+                ));
+            }
+            _ => {
+                return Err(ArchiveError::unsupported_static(
+                    "entry comments outside ZIP and TAR archives",
+                ));
+            }
+        }
+        self.entry_comment = Some(comment);
+        Ok(self)
+    }
+
+    /// Adds a ZIP extra field to the entry added next.
+    ///
+    /// The field is written to both the local header and the central
+    /// directory, after any fields carried over in
+    /// [`EntryInfo::extra_fields`], and is read back there. It can be
+    /// called repeatedly to add several fields.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The archive isn't a ZIP archive (`ArchiveError::Unsupported`)
+    /// * `id` is that of a field written from the entry itself: ZIP64
+    ///   (`0x0001`), AES (`0x9901`), extended timestamp (`0x5455`) or
+    ///   Unicode Path (`0x7075`)
+    /// * The extra fields of the entry would exceed 65,535 bytes, which is
+    ///   reported when the entry is added
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use compak::{Archive, ArchiveFormat, ExtraField, writer::ArchiveBuilder};
+    ///
+    /// let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    /// builder
+    ///     .entry_extra_field(0xcafe, *b"build 42")
+    ///     .unwrap()
+    ///     .add_bytes("app.bin", b"foo")
+    ///     .unwrap();
+    /// let zip = builder.finish().unwrap().into_inner();
+    ///
+    /// let info = Archive::from_bytes(zip).unwrap().entry_info(0).unwrap();
+    /// assert!(info.extra_fields.contains(&ExtraField {
+    ///     id: 0xcafe,
+    ///     data: b"build 42".to_vec(),
+    /// }));
+    /// ```
+    pub fn entry_extra_field(
+        &mut self,
+        id: u16,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<&mut Self, ArchiveError> {
+        if !matches!(
+            self.writer,
+            FormatWriter::Zip { .. } | FormatWriter::ZipStream { .. }
+        ) {
+            return Err(ArchiveError::unsupported_static(
+                "extra fields outside ZIP archives",
+            ));
+        }
+        if DERIVED_EXTRA_FIELDS.contains(&id) {
+            return Err(ArchiveError::Custom {
+                message: format!("ZIP extra field {id:#06x} is written from the entry itself")
+                    .into(),
+            });
+        }
+        self.entry_extra_fields.push(ExtraField {
+            id,
+            data: data.into(),
+        });
+        Ok(self)
+    }
+
+    /// Returns the format of the archive being built.
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Adds a file from the filesystem, named after its final path component.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be opened or
+    /// read, or if `path` has no file name.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, ArchiveError> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or(ArchiveError::not_found_static("file name"))?
+            .to_string_lossy()
+            .into_owned();
+        self.add_file_as(name, path)
+    }
+
+    /// Adds a file from the filesystem under an explicit entry name.
+    ///
+    /// The file's modification time and (on unix) permission bits are
+    /// recorded in the entry. In TAR archives a file with holes is stored
+    /// as a sparse entry on Linux, Android and FreeBSD.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be opened or read.
+    pub fn add_file_as<P: AsRef<Path>>(
+        &mut self,
+        name: impl Into<String>,
+        path: P,
+    ) -> Result<&mut Self, ArchiveError> {
+        let mut file = File::open(path.as_ref()).at_path(&path)?;
+        let metadata = file.metadata().at_path(&path)?;
+        let info = EntryInfo {
+            name: name.into(),
+            kind: EntryKind::File,
+            size: metadata.len(),
+            compressed_size: None,
+            mtime: metadata.modified().ok(),
+            mode: unix_mode(&metadata),
+            link_target: None,
+            owner: None,
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        self.add_file_entry(&info, &mut file)?;
+        Ok(self)
+    }
+
+    /// Adds a file with the given contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn add_bytes(
+        &mut self,
+        name: impl Into<String>,
+        data: impl AsRef<[u8]>,
+    ) -> Result<&mut Self, ArchiveError> {
+        let data = data.as_ref();
+        let info = new_entry(name.into(), EntryKind::File, data.len() as u64);
+        self.add_entry(&info, &mut &data[..], None)?;
+        Ok(self)
+    }
+
+    /// Adds a file whose contents are read from `reader` until EOF.
+    ///
+    /// ZIP and 7z entries are streamed straight into the archive, ZIP
+    /// entries with room for 64-bit sizes since their length is not known
+    /// up front. TAR headers record the entry size before its data, so for
+    /// TAR-based formats the reader is buffered in memory first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from `reader` or writing the entry fails.
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: impl Into<String>,
+        mut reader: R,
+    ) -> Result<&mut Self, ArchiveError> {
+        let name = name.into();
+        if let FormatWriter::Tar {
+            ..
+        } = self.writer
+        {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            return self.add_bytes(name, data);
+        }
+        let info = new_entry(name, EntryKind::File, UNKNOWN_SIZE);
+        self.add_entry(&info, &mut reader, None)?;
+        Ok(self)
+    }
+
+    /// Adds an empty directory entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn add_empty_dir(&mut self, name: impl Into<String>) -> Result<&mut Self, ArchiveError> {
+        let mut name = name.into();
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+        let info = new_entry(name, EntryKind::Directory, 0);
+        self.add_entry(&info, &mut io::empty(), None)?;
+        Ok(self)
+    }
+
+    /// Adds a symbolic link pointing at `target`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn add_symlink(
+        &mut self,
+        name: impl Into<String>,
+        target: impl Into<String>,
+    ) -> Result<&mut Self, ArchiveError> {
+        let mut info = new_entry(name.into(), EntryKind::Symlink, 0);
+        info.link_target = Some(target.into());
+        self.add_entry(&info, &mut io::empty(), None)?;
+        Ok(self)
+    }
+
+    /// Writes the archive trailer and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the trailer or buffered compressed data cannot be written.
+    pub fn finish(self) -> Result<W, ArchiveError> {
+        self.writer.finish()
+    }
+
+    /// Writes a file from the filesystem, leaving out the holes of sparse
+    /// files where the format can record them.
+    fn add_file_entry(&mut self, info: &EntryInfo, file: &mut File) -> Result<(), ArchiveError> {
+        match self.writer.sparse_regions(file, info.size) {
+            Some(regions) => {
+                let mut data = sparse::Packed::new(file, &regions);
+                self.add_entry(info, &mut data, Some(&regions))
+            }
+            None => self.add_entry(info, file, None),
+        }
+    }
+
+    /// Writes one entry, reporting progress as its data is consumed.
+    ///
+    /// With `regions`, `data` yields just the bytes of those regions of a
+    /// sparse file.
+    fn add_entry(
+        &mut self,
+        info: &EntryInfo,
+        data: &mut dyn Read,
+        regions: Option<&[Region]>,
+    ) -> Result<(), ArchiveError> {
+        self.progress.check()?;
+        let mut info = self.options.clamped(info);
+        if let Some(comment) = self.entry_comment.take() {
+            info.to_mut().comment = Some(comment);
+        }
+        if !self.entry_extra_fields.is_empty() {
+            info.to_mut()
+                .extra_fields
+                .append(&mut self.entry_extra_fields);
+        }
+        let info = &*info;
+        let mut reader = self.progress.reader(&info.name, data);
+        let result = match regions {
+            Some(regions) => self.writer.add_sparse(info, &mut reader, regions),
+            None => self.writer.add(info, &mut reader),
+        };
+        if let Err(err) = result {
+            self.progress.check()?;
+            return Err(err);
+        }
+        self.progress.finish_entry(&info.name);
+        Ok(())
+    }
+}
+
+/// Creates metadata for an entry that doesn't come from the filesystem.
+fn new_entry(name: String, kind: EntryKind, size: u64) -> EntryInfo {
+    EntryInfo {
+        name,
+        kind,
+        size,
+        compressed_size: None,
+        mtime: Some(SystemTime::now()),
+        mode: None,
+        link_target: None,
+        owner: None,
+        xattrs: Vec::new(),
+        raw_name: None,
+        special: None,
+        comment: None,
+        extra_fields: Vec::new(),
+        encrypted: false,
+    }
+}
+
+/// Packs the contents of `src_dir` into a new archive at `dst`.
+///
+/// Entries are named relative to `src_dir` and added in sorted order so the
+/// output does not depend on directory iteration order. Symlinks are stored
+/// as links rather than followed. Files with several names are stored
+/// once in TAR archives, with the later names as hard links to the first;
+/// other formats store a copy under each name. Files with holes are stored
+/// as sparse TAR entries where the platform can find the holes. If `dst`
+/// lies inside `src_dir` it is skipped rather than packed into itself.
+///
+/// # Arguments
+///
+/// * `dst` - Path of the archive to create
+/// * `format` - Format of the archive to create
+/// * `src_dir` - Directory whose contents should be packed
+/// * `options` - Compression levels of the archive
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * A level in `options` is out of range
+/// * `src_dir` cannot be read
+/// * A file cannot be opened or its metadata cannot be read
+/// * The destination file cannot be written
+pub(crate) fn create_from_dir(
+    dst: &Path,
+    format: ArchiveFormat,
+    src_dir: &Path,
+    options: &CompressionOptions,
+) -> Result<(), ArchiveError> {
+    options.validate()?;
+    let file = BufWriter::new(File::create(dst).at_path(dst)?);
+    let dst = fs::canonicalize(dst).at_path(dst)?;
+    let mut writer = FormatWriter::with_options(format, file, options)?;
+    add_dir_contents(&mut writer, src_dir, "", &dst, options, &mut HashMap::new())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes an archive at `dst` holding a file for each of `entries`, with
+/// contents read from its reader until EOF.
+pub(crate) fn create_from_entries<I, R>(
+    dst: &Path,
+    format: ArchiveFormat,
+    entries: I,
+    options: &CompressionOptions,
+) -> Result<(), ArchiveError>
+where
+    I: IntoIterator<Item = (String, R)>,
+    R: Read,
+{
+    options.validate()?;
+    let file = BufWriter::new(File::create(dst).at_path(dst)?);
+    let mut builder = ArchiveBuilder::new_with(file, format, options)?;
+    for (name, reader) in entries {
+        builder.add_reader(name, reader)?;
+    }
+    builder.finish()?;
+    Ok(())
+}
+
+/// Recursively adds the contents of `dir` with names prefixed by `prefix`,
+/// skipping the archive being written (`dst`).
+///
+/// `links` maps the identity of each multiply linked file already added to
+/// its name.
+fn add_dir_contents<W: Write + Seek>(
+    writer: &mut FormatWriter<W>,
+    dir: &Path,
+    prefix: &str,
+    dst: &Path,
+    options: &CompressionOptions,
+    links: &mut HashMap<(u64, u64), String>,
+) -> Result<(), ArchiveError> {
+    let mut children = fs::read_dir(dir)
+        .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+        .at_path(dir)?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = child.path();
+        if fs::canonicalize(&path).is_ok_and(|p| p == dst) {
+            continue;
+        }
+        let name = format!("{}{}", prefix, child.file_name().to_string_lossy());
+        let metadata = fs::symlink_metadata(&path).at_path(&path)?;
+        let mut info = EntryInfo {
+            name,
+            kind: EntryKind::File,
+            size: 0,
+            compressed_size: None,
+            mtime: metadata.modified().ok().map(|mtime| options.clamp(mtime)),
+            mode: unix_mode(&metadata),
+            link_target: None,
+            owner: None,
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+
+        if metadata.file_type().is_symlink() {
+            info.kind = EntryKind::Symlink;
+            let target = fs::read_link(&path).at_path(&path)?;
+            info.link_target = Some(target.to_string_lossy().into_owned());
+            writer.add(&info, &mut io::empty())?;
+        } else if metadata.is_dir() {
+            info.kind = EntryKind::Directory;
+            info.name.push('/');
+            writer.add(&info, &mut io::empty())?;
+            add_dir_contents(writer, &path, &info.name, dst, options, links)?;
+        } else if let Some(id) = file_id(&metadata)
+            && matches!(writer, FormatWriter::Tar { .. })
+        {
+            if let Some(first) = links.get(&id) {
+                info.kind = EntryKind::Hardlink;
+                info.link_target = Some(first.clone());
+                writer.add(&info, &mut io::empty())?;
+            } else {
+                info.size = metadata.len();
+                add_file(writer, &info, &path)?;
+                links.insert(id, info.name);
+            }
+        } else {
+            info.size = metadata.len();
+            add_file(writer, &info, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the file at `path`, leaving out its holes where the format can
+/// record them.
+fn add_file<W: Write + Seek>(
+    writer: &mut FormatWriter<W>,
+    info: &EntryInfo,
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    let mut file = File::open(path).at_path(path)?;
+    match writer.sparse_regions(&file, info.size) {
+        Some(regions) => {
+            writer.add_sparse(info, &mut sparse::Packed::new(&file, &regions), &regions)
+        }
+        None => writer.add(info, &mut file),
+    }
+}
+
+/// Returns the device and inode numbers of a file with more than one name.
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns the unix mode of a file, if the platform has one.
+fn unix_mode(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        Some(metadata.permissions().mode())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Appends an entry to a ZIP archive, compressing files with `codec` or
+/// the default compression if there is none.
+pub(crate) fn add_zip<W: Write + Seek>(
+    writer: &mut zip::ZipWriter<W>,
+    info: &EntryInfo,
+    data: &mut dyn Read,
+    codec: Option<Codec>,
+    ntfs_times: bool,
+) -> Result<(), ArchiveError> {
+    let mut options =
+        zip::write::FullFileOptions::default().large_file(info.size > u32::MAX as u64);
+    if let Some(codec) = codec {
+        let (method, level) = codec.zip_method();
+        options = options.compression_method(method).compression_level(level);
+    }
+    if let Some(mtime) = info.mtime {
+        options = options.last_modified_time(dos_time(mtime));
+        // DOS times only have two-second resolution, so also record the
+        // exact second in an extended timestamp field where it fits
+        if let Some(secs) = mtime
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|d| i32::try_from(d.as_secs()).ok())
+        {
+            let mut field = vec![1];
+            field.extend_from_slice(&secs.to_le_bytes());
+            options.add_extra_data(EXTENDED_TIMESTAMP, field, false)?;
+        }
+        let previous = info
+            .extra_fields
+            .iter()
+            .find(|field| field.id == NTFS_TIMES);
+        if (ntfs_times || previous.is_some())
+            && let Some(field) = ntfs_field(mtime, previous)
+        {
+            options.add_extra_data(NTFS_TIMES, field, false)?;
+        }
+    }
+    if let Some(mode) = info.mode {
+        options = options.unix_permissions(mode & 0o7777);
+    }
+    for field in &info.extra_fields {
+        if !DERIVED_EXTRA_FIELDS.contains(&field.id) {
+            options.add_extra_data(field.id, field.data.as_slice(), false)?;
+        }
+    }
+
+    match info.kind {
+        EntryKind::File => {
+            writer.start_file(info.name.as_str(), options)?;
+            io::copy(data, writer)?;
+        }
+        EntryKind::Directory => writer.add_directory(info.name.as_str(), options)?,
+        EntryKind::Symlink => {
+            writer.add_symlink(
+                info.name.as_str(),
+                info.link_target.as_deref().unwrap_or_default(),
+                options,
+            )?;
+        }
+        EntryKind::Hardlink | EntryKind::Other => {
+            return Err(ArchiveError::unsupported_static(
+                "hard links and special files in ZIP archives",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name the `zip` crate stores an entry under, which has a
+/// trailing slash for directories.
+fn zip_name(info: &EntryInfo) -> String {
+    match info.kind {
+        EntryKind::Directory if !info.name.ends_with(['/', '\\']) => format!("{}/", info.name),
+        _ => info.name.clone(),
+    }
+}
+
+/// Checks that a ZIP header has room for `comment`.
+fn check_zip_comment(comment: &[u8]) -> Result<(), ArchiveError> {
+    if comment.len() > usize::from(u16::MAX) {
+        return Err(ArchiveError::Custom {
+            message: format!(
+                "a ZIP comment holds at most {} bytes, not {}",
+                u16::MAX,
+                comment.len()
+            )
+            .into(),
+        });
+    }
+    Ok(())
+}
+
+/// Appends an entry to a TAR archive.
+///
+/// Files with `regions` are written as old GNU sparse entries, with `data`
+/// yielding just the bytes of those regions, whatever the `flavor`. With
+/// extended attributes, `keywords` or owner names too long for the header,
+/// the entry is preceded by a PAX extended header.
+fn add_tar<W: Write>(
+    builder: &mut tar::Builder<W>,
+    info: &EntryInfo,
+    data: &mut dyn Read,
+    regions: Option<&[Region]>,
+    flavor: TarFlavor,
+    keywords: &[(String, Vec<u8>)],
+) -> Result<(), ArchiveError> {
+    let pax = flavor == TarFlavor::Pax && regions.is_none();
+    let mut header = if pax {
+        tar::Header::new_ustar()
+    } else {
+        tar::Header::new_gnu()
+    };
+    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut record = |key: &str, value: &[u8]| records.push((key.to_string(), value.to_vec()));
+
+    let mtime = info
+        .mtime
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    header.set_mtime(mtime.as_secs());
+    if pax && mtime.subsec_nanos() != 0 {
+        record("mtime", pax_time(mtime).as_bytes());
+    }
+    match &info.owner {
+        Some(owner) => {
+            header.set_uid(owner.uid);
+            header.set_gid(owner.gid);
+            if pax && owner.uid > USTAR_ID_MAX {
+                record("uid", owner.uid.to_string().as_bytes());
+            }
+            if pax && owner.gid > USTAR_ID_MAX {
+                record("gid", owner.gid.to_string().as_bytes());
+            }
+            if let Some(user) = &owner.user {
+                if !fits_ustar(user, USTAR_OWNER_LEN) {
+                    record("uname", user.as_bytes());
+                } else {
+                    header.set_username(user)?;
+                }
+            }
+            if let Some(group) = &owner.group {
+                if !fits_ustar(group, USTAR_OWNER_LEN) {
+                    record("gname", group.as_bytes());
+                } else {
+                    header.set_groupname(group)?;
+                }
+            }
+        }
+        None => {
+            header.set_uid(0);
+            header.set_gid(0);
+        }
+    }
+    let link_target = info.link_target.as_deref().unwrap_or_default();
+    if pax {
+        if !info.name.is_ascii() || header.set_path(&info.name).is_err() {
+            record("path", info.name.as_bytes());
+            if let Some(ustar) = header.as_ustar_mut() {
+                ustar.prefix.fill(0);
+            }
+            truncate_into(&mut header.as_old_mut().name, &info.name);
+        }
+        if matches!(info.kind, EntryKind::Symlink | EntryKind::Hardlink)
+            && (!link_target.is_ascii() || header.set_link_name(link_target).is_err())
+        {
+            record("linkpath", link_target.as_bytes());
+            truncate_into(&mut header.as_old_mut().linkname, link_target);
+        }
+        if info.kind == EntryKind::File && info.size > USTAR_SIZE_MAX {
+            record("size", info.size.to_string().as_bytes());
+        }
+    }
+    if let Some(comment) = &info.comment {
+        record("comment", comment.as_bytes());
+    }
+    for (name, value) in &info.xattrs {
+        record(&format!("SCHILY.xattr.{name}"), value);
+    }
+    for (key, value) in keywords {
+        record(key, value);
+    }
+
+    match info.kind {
+        EntryKind::File => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(info.mode.map_or(0o644, |m| m & 0o7777));
+            header.set_size(info.size);
+        }
+        EntryKind::Directory => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(info.mode.map_or(0o755, |m| m & 0o7777));
+            header.set_size(0);
+        }
+        EntryKind::Symlink | EntryKind::Hardlink => {
+            let entry_type = if info.kind == EntryKind::Symlink {
+                tar::EntryType::Symlink
+            } else {
+                tar::EntryType::Link
+            };
+            header.set_entry_type(entry_type);
+            header.set_mode(info.mode.map_or(0o777, |m| m & 0o7777));
+            header.set_size(0);
+        }
+        EntryKind::Other => {
+            return Err(ArchiveError::unsupported_static(
+                "special files in TAR archives",
+            ));
+        }
+    }
+    if !records.is_empty() {
+        append_pax_header(builder, &info.name, &records)?;
+    }
+
+    let data: &mut dyn Read = match info.kind {
+        EntryKind::File => data,
+        _ => &mut io::empty(),
+    };
+    if pax {
+        header.set_cksum();
+        builder.append(&header, data)?;
+        return Ok(());
+    }
+    match (info.kind, regions) {
+        (EntryKind::File, Some(regions)) => {
+            let extensions = sparse_header(&mut header, info.size, regions);
+            builder.append_data(
+                &mut header,
+                &info.name,
+                io::Cursor::new(extensions).chain(data),
+            )?;
+        }
+        (EntryKind::Symlink | EntryKind::Hardlink, _) => {
+            builder.append_link(&mut header, &info.name, link_target)?;
+        }
+        _ => builder.append_data(&mut header, &info.name, data)?,
+    }
+    Ok(())
+}
+
+/// Largest size a ustar header holds, in 11 octal digits.
+const USTAR_SIZE_MAX: u64 = 0o77777777777;
+/// Largest user or group ID a ustar header holds, in 7 octal digits.
+const USTAR_ID_MAX: u64 = 0o7777777;
+/// Room for a user or group name in a ustar header.
+const USTAR_OWNER_LEN: usize = 32;
+
+/// Returns `true` if `value` can be stored as is in a ustar field of `len`
+/// bytes.
+fn fits_ustar(value: &str, len: usize) -> bool {
+    value.is_ascii() && value.len() <= len
+}
+
+/// Fills `field` with as much of `value` as fits, for readers that don't
+/// know the PAX record holding all of it.
+fn truncate_into(field: &mut [u8], value: &str) {
+    let len = value.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+/// Formats a time as the seconds since the Unix epoch, with as many
+/// fractional digits as it takes.
+fn pax_time(time: Duration) -> String {
+    let formatted = format!("{}.{:09}", time.as_secs(), time.subsec_nanos());
+    formatted.trim_end_matches('0').to_string()
+}
+
+/// Appends a PAX extended header holding `records` for the entry `name`
+/// that follows it.
+fn append_pax_header<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    records: &[(String, Vec<u8>)],
+) -> Result<(), ArchiveError> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        // The length counts every byte of the record, its own digits too
+        let base = key.len() + value.len() + 3;
+        let mut len = base + 1;
+        while len != base + len.to_string().len() {
+            len = base + len.to_string().len();
+        }
+        data.extend_from_slice(format!("{len} {key}=").as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    // Named the way GNU tar names them, for tars that unpack them as files
+    let base = name
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    truncate_into(&mut header.as_old_mut().name, &format!("PaxHeaders/{base}"));
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data.as_slice())?;
+    Ok(())
+}
+
+/// Turns `header` into an old GNU sparse header for a file of `size` bytes
+/// with data in `regions`, returning the extension blocks that hold the
+/// regions that don't fit.
+fn sparse_header(header: &mut tar::Header, size: u64, regions: &[Region]) -> Vec<u8> {
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(regions.iter().map(|&(_, len)| len).sum());
+    let gnu = header
+        .as_gnu_mut()
+        .expect("TAR entries are written with GNU headers");
+    gnu.set_real_size(size);
+    let (first, rest) = regions.split_at(regions.len().min(gnu.sparse.len()));
+    for (slot, &(offset, len)) in gnu.sparse.iter_mut().zip(first) {
+        slot.set_offset(offset);
+        slot.set_length(len);
+    }
+    gnu.set_is_extended(!rest.is_empty());
+
+    let mut extensions = Vec::new();
+    let mut chunks = rest
+        .chunks(tar::GnuExtSparseHeader::new().sparse.len())
+        .peekable();
+    while let Some(chunk) = chunks.next() {
+        let mut extension = tar::GnuExtSparseHeader::new();
+        for (slot, &(offset, len)) in extension.sparse.iter_mut().zip(chunk) {
+            slot.set_offset(offset);
+            slot.set_length(len);
+        }
+        extension.set_is_extended(chunks.peek().is_some());
+        extensions.extend_from_slice(extension.as_bytes());
+    }
+    extensions
+}
+
+fn add_7z<W: Write + Seek>(
+    writer: &mut sevenz_rust2::ArchiveWriter<W>,
+    info: &EntryInfo,
+    data: &mut dyn Read,
+) -> Result<(), ArchiveError> {
+    use sevenz_rust2::{ArchiveEntry, NtTime};
+
+    let name = info.name.trim_end_matches('/');
+    let mut entry = match info.kind {
+        EntryKind::Directory => ArchiveEntry::new_directory(name),
+        EntryKind::File | EntryKind::Symlink => ArchiveEntry::new_file(name),
+        EntryKind::Hardlink | EntryKind::Other => {
+            return Err(ArchiveError::unsupported_static(
+                "hard links and special files in 7z archives",
+            ));
+        }
+    };
+    if let Some(mtime) = info.mtime.and_then(|t| NtTime::try_from(t).ok()) {
+        entry.last_modified_date = mtime;
+        entry.has_last_modified_date = true;
+    }
+
+    match info.kind {
+        EntryKind::Directory => {
+            if let Some(mode) = info.mode {
+                entry.has_windows_attributes = true;
+                entry.windows_attributes =
+                    WINDOWS_DIRECTORY | UNIX_EXTENSION | ((UNIX_DIR | (mode & 0o7777)) << 16);
+            }
+            writer.push_archive_entry::<&[u8]>(entry, None)?;
+        }
+        EntryKind::Symlink => {
+            // 7z stores symlinks as files whose content is the target and
+            // whose unix mode marks them as links.
+            entry.has_windows_attributes = true;
+            entry.windows_attributes = UNIX_EXTENSION | ((UNIX_SYMLINK | 0o777) << 16);
+            let target = info.link_target.as_deref().unwrap_or_default();
+            writer.push_archive_entry(entry, Some(target.as_bytes()))?;
+        }
+        _ => {
+            if let Some(mode) = info.mode {
+                entry.has_windows_attributes = true;
+                entry.windows_attributes = UNIX_EXTENSION | ((UNIX_FILE | (mode & 0o7777)) << 16);
+            }
+            writer.push_archive_entry(entry, Some(data))?;
+        }
+    }
+    Ok(())
+}
+
+/// Converts a `SystemTime` to a zip DOS timestamp (UTC), clamped to the
+/// representable 1980-2107 range.
+fn dos_time(time: SystemTime) -> zip::DateTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let rem = secs.rem_euclid(86_400);
+
+    if year < 1980 {
+        return zip::DateTime::default();
+    }
+    let year = year.min(2107) as u16;
+    zip::DateTime::from_date_and_time(
+        year,
+        month as u8,
+        day as u8,
+        (rem / 3_600) as u8,
+        (rem % 3_600 / 60) as u8,
+        (rem % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Builds the content of an NTFS extra field recording `mtime`, keeping the
+/// access and creation times of a `previous` field, or `None` if `mtime`
+/// precedes 1601.
+fn ntfs_field(mtime: SystemTime, previous: Option<&ExtraField>) -> Option<Vec<u8>> {
+    let since_1601 = match mtime.duration_since(UNIX_EPOCH) {
+        Ok(since_1970) => since_1970.checked_add(Duration::from_secs(WINDOWS_EPOCH_OFFSET)),
+        Err(err) => Duration::from_secs(WINDOWS_EPOCH_OFFSET).checked_sub(err.duration()),
+    }?;
+    let ticks = u64::try_from(since_1601.as_nanos() / 100).ok()?;
+    // A reserved word, then attribute 1 of 24 bytes: the times modified,
+    // accessed and created
+    let mut field = vec![0, 0, 0, 0, 1, 0, 24, 0];
+    field.extend_from_slice(&ticks.to_le_bytes());
+    match previous.map(|field| field.data.as_slice()) {
+        Some([_, _, _, _, 1, 0, 24, 0, _, _, _, _, _, _, _, _, times @ ..])
+            if times.len() >= 16 =>
+        {
+            field.extend_from_slice(&times[..16]);
+        }
+        _ => {
+            field.extend_from_slice(&ticks.to_le_bytes());
+            field.extend_from_slice(&ticks.to_le_bytes());
+        }
+    }
+    Some(field)
+}
+
+/// Returns the civil date for a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
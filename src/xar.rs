@@ -0,0 +1,488 @@
+//! xar archives and the macOS installer packages built on them.
+//!
+//! A xar archive starts with a small binary header and a zlib-compressed
+//! XML table of contents describing every entry. File data lives in the
+//! heap following it, each file compressed on its own. Flat installer
+//! packages (`.pkg`) are xar archives whose `Payload` and `Scripts`
+//! members are compressed `cpio` archives.
+
+mod xml;
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use self::xml::Element;
+use crate::{
+    cpio,
+    entry::{self, EntryInfo, EntryKind, Exact, Owner},
+    error::ArchiveError,
+    format::ArchiveFormat,
+};
+
+/// Magic number at the start of every xar archive.
+pub(crate) const XAR_MAGIC: &[u8] = b"xar!";
+/// Size of the header up to the checksum algorithm.
+const HEADER_SIZE: usize = 28;
+/// Upper bound on the size of the decompressed table of contents.
+const MAX_TOC_SIZE: u64 = 64 * 1024 * 1024;
+/// Upper bound on directory nesting.
+const MAX_DEPTH: usize = 256;
+/// Magic number of the chunked xz framing used by newer payloads.
+const PBZX_MAGIC: &[u8] = b"pbzx";
+/// Set in a pbzx chunk header when another chunk follows.
+const PBZX_MORE_CHUNKS: u64 = 1 << 24;
+/// Upper bound on the size of a pbzx chunk, compressed or not.
+const MAX_PBZX_CHUNK: u64 = 64 * 1024 * 1024;
+const XZ_MAGIC: &[u8] = &[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// Walks every entry of a xar archive, parents before their children.
+///
+/// See [`for_each_entry`](crate::entry::for_each_entry) for the callback
+/// contract.
+pub(crate) fn walk<R, F>(mut reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    let (root, heap) = read_toc(&mut reader)?;
+    let toc = root
+        .child("toc")
+        .filter(|_| root.name == "xar")
+        .ok_or_else(|| invalid("missing table of contents"))?;
+    let mut files = Vec::new();
+    collect(toc, "", 0, &mut files)?;
+    let ids: HashMap<&str, usize> = files
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (_, file))| Some((file.attribute("id")?, index)))
+        .collect();
+
+    for (index, (name, file)) in files.iter().enumerate() {
+        let kind_element = file.child("type");
+        let mut data = file.child("data");
+        let mut info = EntryInfo {
+            name: name.clone(),
+            kind: EntryKind::File,
+            size: 0,
+            compressed_size: None,
+            mtime: file.child_text("mtime").and_then(timestamp),
+            mode: file
+                .child_text("mode")
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .map(|mode| mode & 0o7777),
+            link_target: None,
+            owner: file
+                .child_text("uid")
+                .and_then(|uid| uid.parse().ok())
+                .zip(file.child_text("gid").and_then(|gid| gid.parse().ok()))
+                .map(|(uid, gid)| {
+                    Owner {
+                        uid,
+                        gid,
+                        user: file.child_text("user").map(str::to_string),
+                        group: file.child_text("group").map(str::to_string),
+                    }
+                }),
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        match kind_element.map_or("file", |kind| kind.text.trim()) {
+            "file" => {}
+            "directory" => info.kind = EntryKind::Directory,
+            "symlink" => {
+                info.kind = EntryKind::Symlink;
+                let target = file
+                    .child_text("link")
+                    .ok_or_else(|| invalid("symlink without a target"))?;
+                info.link_target = Some(target.to_string());
+                data = None;
+            }
+            "hardlink" => {
+                // Every link names the original, which holds the data
+                let original = kind_element.and_then(|kind| kind.attribute("link"));
+                if original != Some("original") {
+                    let target = original
+                        .and_then(|id| ids.get(id))
+                        .ok_or_else(|| invalid("hard link to a missing entry"))?;
+                    if *target < index {
+                        info.kind = EntryKind::Hardlink;
+                        info.link_target = Some(files[*target].0.clone());
+                        data = None;
+                    } else {
+                        // The original comes later, so its data is read now
+                        data = files[*target].1.child("data");
+                    }
+                }
+            }
+            _ => {
+                info.kind = EntryKind::Other;
+                data = None;
+            }
+        }
+
+        let keep_going = match data {
+            Some(data) => {
+                let location = Location::parse(data)?;
+                info.size = location.size;
+                info.compressed_size = Some(location.length);
+                let offset = heap
+                    .checked_add(location.offset)
+                    .ok_or_else(|| invalid("file data offset out of range"))?;
+                reader.seek(SeekFrom::Start(offset))?;
+                let stored = (&mut reader).take(location.length);
+                let mut decoder =
+                    Exact::new(decoder(stored, data)?.take(location.size), location.size);
+                visit(&info, &mut decoder)?
+            }
+            None => visit(&info, &mut io::empty())?,
+        };
+        if !keep_going {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Walks a macOS installer package, listing each `Payload` and `Scripts`
+/// member as a directory of the `cpio` archive it holds.
+///
+/// This matches the layout of `pkgutil --expand-full`. Payloads may be
+/// compressed with gzip, bzip2, xz or pbzx, or stored as they are.
+pub(crate) fn walk_pkg<R, F>(reader: R, mut visit: F) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&EntryInfo, &mut dyn Read) -> Result<bool, ArchiveError>,
+{
+    walk(reader, |info, data| {
+        let member = info.name.rsplit('/').next().unwrap_or_default();
+        if info.kind != EntryKind::File || (member != "Payload" && member != "Scripts") {
+            return visit(info, data);
+        }
+
+        let directory = EntryInfo {
+            name: info.name.clone(),
+            kind: EntryKind::Directory,
+            size: 0,
+            compressed_size: None,
+            mtime: info.mtime,
+            mode: None,
+            link_target: None,
+            owner: None,
+            xattrs: Vec::new(),
+            raw_name: None,
+            special: None,
+            comment: None,
+            extra_fields: Vec::new(),
+            encrypted: false,
+        };
+        if !visit(&directory, &mut io::empty())? {
+            return Ok(false);
+        }
+        let mut keep_going = true;
+        cpio::walk(
+            payload_decoder(data)?,
+            ArchiveFormat::Xar,
+            |entry, reader| {
+                let mut entry = entry.clone();
+                entry.name = format!("{}/{}", info.name, entry.name);
+                if entry.kind == EntryKind::Hardlink {
+                    entry.link_target = entry
+                        .link_target
+                        .map(|target| format!("{}/{}", info.name, target));
+                }
+                keep_going = visit(&entry, reader)?;
+                Ok(keep_going)
+            },
+        )?;
+        Ok(keep_going)
+    })
+}
+
+/// Reads the header and table of contents, returning the parsed document
+/// and the offset of the heap.
+fn read_toc<R: Read + Seek>(reader: &mut R) -> Result<(Element, u64), ArchiveError> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(XAR_MAGIC) {
+        return Err(invalid("missing 'xar!' magic"));
+    }
+    let header_size = u64::from(u16::from_be_bytes([header[4], header[5]]));
+    if header_size < HEADER_SIZE as u64 {
+        return Err(invalid("header is too short"));
+    }
+    let compressed = be64(&header[8..]);
+    let uncompressed = be64(&header[16..]);
+    if uncompressed > MAX_TOC_SIZE {
+        return Err(invalid("table of contents is too large"));
+    }
+
+    // Named checksum algorithms extend the header
+    reader.seek(SeekFrom::Start(header_size))?;
+    let mut toc = Vec::new();
+    flate2::read::ZlibDecoder::new(reader.take(compressed))
+        .take(uncompressed)
+        .read_to_end(&mut toc)?;
+    let toc = String::from_utf8(toc).map_err(|_| invalid("table of contents is not UTF-8"))?;
+    let heap = header_size
+        .checked_add(compressed)
+        .ok_or_else(|| invalid("table of contents size out of range"))?;
+    Ok((xml::parse(&toc)?, heap))
+}
+
+/// Collects the `file` elements below `parent` with their full names, in
+/// document order.
+fn collect<'a>(
+    parent: &'a Element,
+    prefix: &str,
+    depth: usize,
+    files: &mut Vec<(String, &'a Element)>,
+) -> Result<(), ArchiveError> {
+    if depth > MAX_DEPTH {
+        return Err(invalid("directory hierarchy is too deep"));
+    }
+    for file in parent.children.iter().filter(|child| child.name == "file") {
+        let element = file
+            .child("name")
+            .ok_or_else(|| invalid("entry without a name"))?;
+        let name = if element.attribute("enctype") == Some("base64") {
+            let decoded = base64(element.text.trim())
+                .ok_or_else(|| invalid("malformed base64 entry name"))?;
+            String::from_utf8_lossy(&decoded).into_owned()
+        } else {
+            element.text.trim().to_string()
+        };
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(invalid("invalid entry name"));
+        }
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        files.push((name.clone(), file));
+        collect(file, &name, depth + 1, files)?;
+    }
+    Ok(())
+}
+
+/// Where a file's data lives in the heap.
+struct Location {
+    offset: u64,
+    /// Size as stored.
+    length: u64,
+    /// Size once decoded.
+    size: u64,
+}
+
+impl Location {
+    fn parse(data: &Element) -> Result<Self, ArchiveError> {
+        let number = |name: &str| {
+            data.child_text(name)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| invalid("malformed file data location"))
+        };
+        Ok(Self {
+            offset: number("offset")?,
+            length: number("length")?,
+            size: number("size")?,
+        })
+    }
+}
+
+/// Returns a reader decoding file data stored with the encoding named by
+/// `data`.
+fn decoder<'a, R: Read + 'a>(
+    stored: R,
+    data: &Element,
+) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    entry::decoded(stored, |stored| encoding_decoder(stored, data))
+}
+
+/// Returns the decoder of [`decoder`] without the error mapping.
+fn encoding_decoder<'a, R: Read + 'a>(
+    stored: R,
+    data: &Element,
+) -> Result<Box<dyn Read + 'a>, ArchiveError> {
+    let style = data
+        .child("encoding")
+        .and_then(|encoding| encoding.attribute("style"))
+        .unwrap_or("application/octet-stream");
+    Ok(match style {
+        "application/octet-stream" => Box::new(stored),
+        // Despite the name, the data is a zlib stream
+        "application/x-gzip" => Box::new(flate2::read::ZlibDecoder::new(stored)),
+        "application/x-bzip2" => Box::new(bzip2::read::BzDecoder::new(stored)),
+        "application/x-xz" => Box::new(liblzma::read::XzDecoder::new(stored)),
+        "application/x-lzma" => {
+            let stream =
+                liblzma::stream::Stream::new_auto_decoder(u64::MAX, 0).map_err(io::Error::from)?;
+            Box::new(liblzma::read::XzDecoder::new_stream(stored, stream))
+        }
+        _ => {
+            return Err(ArchiveError::Unsupported {
+                feature: format!("xar encoding {}", style).into(),
+            });
+        }
+    })
+}
+
+/// Returns a reader decoding a package payload, recognising its
+/// compression by magic number.
+fn payload_decoder(data: &mut dyn Read) -> Result<Box<dyn Read + '_>, ArchiveError> {
+    let mut magic = Vec::new();
+    data.take(XZ_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let stored = io::Cursor::new(magic.clone()).chain(data);
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(flate2::read::MultiGzDecoder::new(stored))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Box::new(bzip2::read::MultiBzDecoder::new(stored))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(liblzma::read::XzDecoder::new_multi_decoder(stored))
+    } else if magic.starts_with(PBZX_MAGIC) {
+        Box::new(Pbzx {
+            reader: stored,
+            started: false,
+            more: true,
+            chunk: Vec::new(),
+            position: 0,
+        })
+    } else if cpio::is_cpio(&magic) {
+        Box::new(stored)
+    } else {
+        return Err(invalid("unknown payload compression"));
+    })
+}
+
+/// Decoder for pbzx, a sequence of chunks that are each either an xz
+/// stream or stored as they are.
+struct Pbzx<R> {
+    reader: R,
+    /// Whether the magic and initial flags were read.
+    started: bool,
+    more: bool,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Pbzx<R> {
+    /// Reads a big-endian chunk header field.
+    fn field(&mut self) -> io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Loads the next chunk, returning `false` after the last.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        if !self.started {
+            self.started = true;
+            let mut magic = [0u8; 4];
+            self.reader.read_exact(&mut magic)?;
+            self.more = self.field()? & PBZX_MORE_CHUNKS != 0;
+        }
+        if !self.more {
+            return Ok(false);
+        }
+        self.more = self.field()? & PBZX_MORE_CHUNKS != 0;
+        let length = self.field()?;
+        if length > MAX_PBZX_CHUNK {
+            return Err(io::Error::other(invalid("pbzx chunk is too large")));
+        }
+        let mut stored = vec![0u8; length as usize];
+        self.reader.read_exact(&mut stored)?;
+        self.chunk = if stored.starts_with(XZ_MAGIC) {
+            let mut chunk = Vec::new();
+            liblzma::read::XzDecoder::new(stored.as_slice())
+                .take(MAX_PBZX_CHUNK)
+                .read_to_end(&mut chunk)?;
+            chunk
+        } else {
+            stored
+        };
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Pbzx<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Parses an ISO 8601 timestamp such as `2024-03-01T12:30:00Z`, treating
+/// times without a zone as UTC.
+fn timestamp(text: &str) -> Option<SystemTime> {
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = text.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    // Fractional seconds are dropped
+    let time = time.split('.').next()?;
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let secs =
+        entry::days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Decodes standard base64, ignoring whitespace.
+fn base64(text: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in text.bytes().filter(|byte| !byte.is_ascii_whitespace()) {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            output.push((bits >> count) as u8);
+        }
+    }
+    Some(output)
+}
+
+fn be64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(array)
+}
+
+fn invalid(reason: &'static str) -> ArchiveError {
+    ArchiveError::InvalidArchive {
+        format: ArchiveFormat::Xar,
+        reason: reason.into(),
+    }
+}
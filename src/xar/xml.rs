@@ -0,0 +1,228 @@
+//! A minimal XML parser for xar tables of contents.
+//!
+//! Builds a tree of elements with their attributes and text. Comments,
+//! processing instructions and the document type declaration are skipped;
+//! namespaces and entities beyond the predefined and numeric ones are not
+//! interpreted.
+
+use super::invalid;
+use crate::error::ArchiveError;
+
+/// Upper bound on element nesting.
+const MAX_DEPTH: usize = 512;
+
+/// An element with its attributes, child elements and text content.
+pub(super) struct Element {
+    pub(super) name: String,
+    pub(super) attributes: Vec<(String, String)>,
+    pub(super) children: Vec<Element>,
+    /// Text directly inside the element, with entities decoded.
+    pub(super) text: String,
+}
+
+impl Element {
+    /// Returns the first child element named `name`.
+    pub(super) fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Returns the trimmed text of the first child element named `name`.
+    pub(super) fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+
+    pub(super) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses a document and returns its root element.
+pub(super) fn parse(document: &str) -> Result<Element, ArchiveError> {
+    let mut parser = Parser {
+        input: document,
+        position: 0,
+    };
+    parser.skip_misc()?;
+    let root = parser.element(0)?;
+    parser.skip_misc()?;
+    if parser.position != document.len() {
+        return Err(invalid("unexpected content after the XML root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.position += prefix.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips past the next occurrence of `end`.
+    fn skip_past(&mut self, end: &str) -> Result<(), ArchiveError> {
+        let found = self
+            .rest()
+            .find(end)
+            .ok_or_else(|| invalid("unterminated XML markup"))?;
+        self.position += found + end.len();
+        Ok(())
+    }
+
+    /// Skips whitespace, comments, processing instructions and document
+    /// type declarations.
+    fn skip_misc(&mut self) -> Result<(), ArchiveError> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<?") {
+                self.skip_past("?>")?;
+            } else if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else if self.eat("<!DOCTYPE") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ArchiveError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '='))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(invalid("missing XML name"));
+        }
+        self.position += len;
+        Ok(rest[..len].to_string())
+    }
+
+    /// Parses the element starting at the current position.
+    fn element(&mut self, depth: usize) -> Result<Element, ArchiveError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("XML elements are nested too deeply"));
+        }
+        if !self.eat("<") {
+            return Err(invalid("expected an XML element"));
+        }
+        let mut element = Element {
+            name: self.name()?,
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.eat("=") {
+                return Err(invalid("malformed XML attribute"));
+            }
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(invalid("malformed XML attribute")),
+            };
+            self.position += 1;
+            let len = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| invalid("unterminated XML attribute"))?;
+            let value = unescape(&self.rest()[..len])?;
+            self.position += len + 1;
+            element.attributes.push((key, value));
+        }
+
+        loop {
+            let len = self.rest().find('<').unwrap_or(self.rest().len());
+            element.text.push_str(&unescape(&self.rest()[..len])?);
+            self.position += len;
+            if self.eat("</") {
+                if self.name()? != element.name {
+                    return Err(invalid("mismatched XML end tag"));
+                }
+                self.skip_whitespace();
+                if !self.eat(">") {
+                    return Err(invalid("malformed XML end tag"));
+                }
+                return Ok(element);
+            } else if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else if self.eat("<![CDATA[") {
+                let len = self
+                    .rest()
+                    .find("]]>")
+                    .ok_or_else(|| invalid("unterminated XML CDATA section"))?;
+                element.text.push_str(&self.rest()[..len]);
+                self.position += len + 3;
+            } else if self.eat("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().is_empty() {
+                return Err(invalid("unterminated XML element"));
+            } else {
+                let child = self.element(depth + 1)?;
+                element.children.push(child);
+            }
+        }
+    }
+}
+
+/// Decodes the predefined and numeric character references in `text`.
+fn unescape(text: &str) -> Result<String, ArchiveError> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(';')
+            .ok_or_else(|| invalid("unterminated XML entity"))?;
+        let decoded = match &rest[..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        output.push(decoded.ok_or_else(|| invalid("unknown XML entity"))?);
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
@@ -0,0 +1,80 @@
+//! Restoring the extended attributes of extracted entries.
+//!
+//! Attributes are set on Linux, Android and macOS, without following a
+//! symlink at the path. Names the filesystem has no room for, such as the
+//! `com.apple.*` attributes of macOS archives on Linux, are skipped the way
+//! `tar --xattrs` skips them; other failures, like setting `security.*` or
+//! `trusted.*` attributes without privileges, are errors.
+
+use std::{io, path::Path};
+
+/// Sets every attribute of `xattrs` on `path`.
+///
+/// Has no effect on platforms without extended attributes.
+pub(crate) fn apply(path: &Path, xattrs: &[(String, Vec<u8>)]) -> io::Result<()> {
+    for (name, value) in xattrs {
+        match set(path, name, value) {
+            Err(err) if is_unsupported(&err) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name)?;
+    // SAFETY: both strings are NUL-terminated and `value` is valid for
+    // `value.len()` bytes for the duration of the call.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let status = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    // SAFETY: as above.
+    #[cfg(target_os = "macos")]
+    let status = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let _ = (path, name, value);
+    Ok(())
+}
+
+/// Returns `true` if `err` says the filesystem can't store the attribute.
+fn is_unsupported(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        // The two are the same on Linux but not everywhere
+        err.raw_os_error()
+            .is_some_and(|code| code == libc::ENOTSUP || code == libc::EOPNOTSUPP)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = err;
+        false
+    }
+}
@@ -0,0 +1,109 @@
+//! Entry digests, against the BLAKE3 and SHA-256 of inputs around the
+//! BLAKE3 chunk and tree boundaries.
+
+use std::io::Cursor;
+
+use compak::{Archive, ArchiveFormat, HashAlgo, writer::ArchiveBuilder};
+
+/// Digests of the bytes `0, 1, ..., 250, 0, 1, ...` of each length, as in
+/// the official BLAKE3 test vectors.
+const VECTORS: &[(usize, &str, &str)] = &[
+    (
+        0,
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ),
+    (
+        1,
+        "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+    ),
+    (
+        1023,
+        "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11",
+        "1c5e88a585b61754df6137d66632a7348557a88358afc401b0a0a4fc427104a9",
+    ),
+    (
+        1024,
+        "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+        "2bce1ba628720664be4b9fdd77aae0678e5f0f3f02fc6ff641ec879094f6a404",
+    ),
+    (
+        1025,
+        "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+        "bc0b6b10b89b9487a12fda2a8cc13194e7091c217aabf8b92846274026f4bcd0",
+    ),
+    (
+        2048,
+        "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a",
+        "b2a8170614e23194ae2951423d601987f518ce2f11205d7b0b708080103b9f76",
+    ),
+    (
+        2049,
+        "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030",
+        "26e1e2808e3a6cf967ca03f6749a063c5ed55f92f5874653a1faabed78346f00",
+    ),
+    (
+        3072,
+        "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2",
+        "5f24b2f16026ec7d0450a5a08283d3cfd47302fe859f579ed79fe7d2663b73f9",
+    ),
+    (
+        3073,
+        "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3",
+        "b870cdfe188c14fbfc31a1be12cd7e83b63551fff30f847fa275d5d4ac409471",
+    ),
+    (
+        4096,
+        "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969",
+        "d67c656e01756650d77717b0839985a056ec28ffe174601d690fc407a2ceffca",
+    ),
+    (
+        8192,
+        "aae792484c8efe4f19e2ca7d371d8c467ffb10748d8a5a1ae579948f718a2a63",
+        "25df2449b2e5a35fea14e02a7158e283801a1069c9f84631b9a9dacb2f809a7f",
+    ),
+    (
+        31744,
+        "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47",
+        "3cfe29c8d109f9f2c47826c78f931f31fdec70a2cf0ddfbba8fe8009a729dd42",
+    ),
+    (
+        102400,
+        "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085",
+        "74588b7f0bcc354ac14d9cf199fa3a20c05f0c7293b9075b2f2e146e718de800",
+    ),
+];
+
+fn archive() -> Archive {
+    let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    for &(len, _, _) in VECTORS {
+        let input: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        builder.add_bytes(format!("{len}.bin"), &input).unwrap();
+    }
+    Archive::from_bytes(builder.finish().unwrap().into_inner()).unwrap()
+}
+
+#[test]
+fn blake3() {
+    let manifest = archive().checksums(HashAlgo::Blake3).unwrap();
+    assert_eq!(manifest.entries.len(), VECTORS.len());
+    for (entry, &(len, expected, _)) in manifest.entries.iter().zip(VECTORS) {
+        assert_eq!(entry.name, format!("{len}.bin"));
+        assert_eq!(entry.to_hex(), expected, "{} bytes", len);
+    }
+}
+
+#[test]
+fn sha256() {
+    let manifest = archive().checksums(HashAlgo::Sha256).unwrap();
+    assert_eq!(manifest.entries.len(), VECTORS.len());
+    for (entry, &(len, _, expected)) in manifest.entries.iter().zip(VECTORS) {
+        assert_eq!(entry.to_hex(), expected, "{} bytes", len);
+    }
+    assert!(
+        manifest.to_string().starts_with(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  0.bin\n"
+        )
+    );
+}
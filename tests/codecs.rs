@@ -0,0 +1,64 @@
+//! The LZ4, LZW and Brotli decoders, against streams written by other
+//! encoders.
+
+mod common;
+
+use common::{PROSE, assert_tree, bases, data, output_dir, text};
+use compak::{Archive, ArchiveFormat};
+
+/// Reads the one file of a single-file stream.
+fn decode(name: &str) -> Vec<u8> {
+    let archive = Archive::open(data(name)).unwrap();
+    let entry = archive.entries().unwrap().next().unwrap();
+    archive.read_entry(&entry.name).unwrap()
+}
+
+#[test]
+fn lz4_frames() {
+    assert_eq!(
+        Archive::open(data("text.lz4")).unwrap().format(),
+        ArchiveFormat::Lz4
+    );
+    // independent blocks with content checksums, linked blocks, the legacy
+    // format, and two frames around a skippable one
+    for name in [
+        "text.lz4",
+        "text-linked.lz4",
+        "text-legacy.lz4",
+        "text-frames.lz4",
+    ] {
+        assert_eq!(decode(name), text(), "{name}");
+    }
+}
+
+#[test]
+fn lzw_stream() {
+    assert_eq!(
+        Archive::open(data("bases.Z")).unwrap().format(),
+        ArchiveFormat::Compress
+    );
+    // 12-bit codes, so the code table fills up and is cleared
+    assert_eq!(decode("bases.Z"), bases(30_000));
+}
+
+#[test]
+fn brotli_stream() {
+    let mut expected = PROSE.repeat(3).into_bytes();
+    expected.extend(text());
+    assert_eq!(decode("prose.br"), expected);
+}
+
+#[test]
+fn compressed_tarballs() {
+    for (name, format) in [
+        ("tree.tar.lz4", ArchiveFormat::TarLz4),
+        ("tree.tar.Z", ArchiveFormat::TarZ),
+        ("tree.tar.br", ArchiveFormat::TarBr),
+    ] {
+        let archive = Archive::open(data(name)).unwrap();
+        assert_eq!(archive.format(), format);
+        let dir = output_dir(name);
+        archive.extract_to(&dir).unwrap();
+        assert_tree(&dir);
+    }
+}
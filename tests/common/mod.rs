@@ -0,0 +1,75 @@
+//! Fixtures shared by the integration tests.
+//!
+//! The contents of the images under `tests/data` are regenerated here
+//! rather than checked in next to them; `tests/data/README.md` says how
+//! each image was made.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Contents of `hello.txt` in most images.
+pub const HELLO: &[u8] = b"hello, world\n";
+
+/// Text that hits the Brotli static dictionary, in `prose.br`.
+pub const PROSE: &str = "The quick brown fox jumps over the lazy dog. Many archive formats \
+    compress their contents with general purpose algorithms, and this sentence is here so \
+    that words from the static dictionary appear in the input, including Capitalized ones \
+    such as Information and Government. ";
+
+/// Returns the path of a checked-in image.
+pub fn data(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name)
+}
+
+/// Returns an empty directory for a test to extract into.
+pub fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("compak-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns the contents of `docs/text`: 3000 numbered lines, 82680 bytes.
+pub fn text() -> Vec<u8> {
+    (0..3000)
+        .map(|line| format!("line {} of the sample text\n", line % 250))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Returns `len` pseudo-random nucleotides, which LZW codes well.
+pub fn bases(len: usize) -> Vec<u8> {
+    lcg(1, len)
+        .map(|x| b"acgt"[(x >> 16) as usize & 3])
+        .collect()
+}
+
+/// Returns `len` pseudo-random bytes, which don't compress.
+pub fn noise(len: usize) -> Vec<u8> {
+    lcg(7, len).map(|x| (x >> 16) as u8).collect()
+}
+
+fn lcg(seed: u64, len: usize) -> impl Iterator<Item = u64> {
+    std::iter::successors(Some(seed), |x| {
+        Some(x.wrapping_mul(1_103_515_245).wrapping_add(12_345) & 0x7fff_ffff)
+    })
+    .skip(1)
+    .take(len)
+}
+
+/// Asserts that `dir` holds the `hello.txt`, `docs/text` and `link`
+/// tree that the compressed TAR fixtures were made from.
+pub fn assert_tree(dir: &Path) {
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text());
+    assert_eq!(
+        fs::read_link(dir.join("link")).unwrap(),
+        Path::new("hello.txt")
+    );
+}
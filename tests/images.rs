@@ -0,0 +1,183 @@
+//! Disk and file system images: ISO 9660, SquashFS, AppImage and WIM.
+
+mod common;
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::{HELLO, bases, data, noise, output_dir, text};
+use compak::{Archive, ArchiveFormat, EntryKind, ExtractOptions};
+
+/// When every fixture image was made: 2023-11-14 22:13:20 UTC.
+fn mtime() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+#[test]
+fn iso_rock_ridge() {
+    let archive = Archive::open(data("rr.iso")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::Iso);
+    let entries: Vec<_> = archive.entries().unwrap().collect();
+    let entry = |name: &str| {
+        entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .unwrap_or_else(|| panic!("no {name}"))
+    };
+    assert_eq!(
+        entry("script.sh").mode.map(|mode| mode & 0o777),
+        Some(0o755)
+    );
+    assert_eq!(entry("hello.txt").mtime, Some(mtime()));
+    assert_eq!(entry("link").kind, EntryKind::Symlink);
+    assert_eq!(
+        entry("link").link_target.as_deref(),
+        Some("./many/file-00.txt")
+    );
+    // a directory spanning several sectors
+    let many = entries
+        .iter()
+        .filter(|entry| entry.name.starts_with("many/"))
+        .count();
+    assert_eq!(many, 40);
+    // the relocated directory shows up where its child link is
+    assert!(
+        entries
+            .iter()
+            .all(|entry| !entry.name.starts_with("rr_moved"))
+    );
+
+    let dir = output_dir("rr.iso");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(
+        fs::read(dir.join("nest/deep/inside.txt")).unwrap(),
+        b"relocated\n"
+    );
+    // the name is in a continuation area
+    assert_eq!(
+        fs::read(dir.join("a name that needs a continuation area.txt")).unwrap(),
+        b"continued\n"
+    );
+    assert_eq!(fs::read(dir.join("many/file-39.txt")).unwrap(), b"");
+}
+
+#[test]
+fn iso_joliet() {
+    let archive = Archive::open(data("joliet.iso")).unwrap();
+    let dir = output_dir("joliet.iso");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(
+        fs::read(dir.join("Grüße.txt")).unwrap(),
+        "grüße\n".as_bytes()
+    );
+    assert_eq!(
+        fs::read(dir.join("Long Directory Name/hello world.txt")).unwrap(),
+        HELLO
+    );
+}
+
+#[test]
+fn squashfs() {
+    let mut mixed = vec![0; 8192];
+    mixed.extend(noise(4096));
+    mixed.extend(b"tail of the mixed file\n");
+
+    for name in ["gzip.squashfs", "xz.squashfs", "zstd.squashfs"] {
+        let archive = Archive::open(data(name)).unwrap();
+        assert_eq!(archive.format(), ArchiveFormat::Squashfs);
+        let entries: Vec<_> = archive.entries().unwrap().collect();
+        let script = entries
+            .iter()
+            .find(|entry| entry.name == "script.sh")
+            .unwrap();
+        assert_eq!(script.mode.map(|mode| mode & 0o777), Some(0o755), "{name}");
+        assert_eq!(script.mtime, Some(mtime()), "{name}");
+        // enough inodes to cross a metadata block
+        let many = entries
+            .iter()
+            .filter(|entry| entry.name.starts_with("many/"))
+            .count();
+        assert_eq!(many, 300, "{name}");
+
+        let dir = output_dir(name);
+        archive.extract_to(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO, "{name}");
+        // ends in a fragment
+        assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text(), "{name}");
+        // sparse blocks, an uncompressed block and a fragment
+        assert_eq!(fs::read(dir.join("docs/mixed")).unwrap(), mixed, "{name}");
+        assert_eq!(
+            fs::read_link(dir.join("link")).unwrap(),
+            Path::new("docs/text")
+        );
+        assert_eq!(fs::read(dir.join("many/entry-299")).unwrap(), b"");
+    }
+}
+
+#[test]
+fn appimage() {
+    // a SquashFS image after the runtime
+    let archive = Archive::open(data("test.AppImage")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::AppImage);
+    let dir = output_dir("test.AppImage");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text());
+
+    // an ISO 9660 image whose system area holds the runtime
+    let archive = Archive::open(data("type1.AppImage")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::AppImage);
+    let dir = output_dir("type1.AppImage");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(
+        fs::read(dir.join("Long Directory Name/hello world.txt")).unwrap(),
+        HELLO
+    );
+}
+
+#[test]
+fn wim() {
+    for name in ["lzx.wim", "xpress.wim"] {
+        let archive = Archive::open(data(name)).unwrap();
+        assert_eq!(archive.format(), ArchiveFormat::Wim);
+        let dir = output_dir(name);
+        archive.extract_to(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("1/hello.txt")).unwrap(), HELLO, "{name}");
+        assert_eq!(fs::read(dir.join("1/docs/text")).unwrap(), text(), "{name}");
+        assert_eq!(fs::read(dir.join("1/empty")).unwrap(), b"", "{name}");
+        assert_eq!(
+            fs::read_link(dir.join("1/link")).unwrap(),
+            Path::new("hello.txt")
+        );
+        assert_eq!(
+            fs::read(dir.join("2/readme.txt")).unwrap(),
+            b"the second image\n"
+        );
+        assert_eq!(fs::read(dir.join("2/bases")).unwrap(), bases(40_000));
+        // the same blob as in the first image
+        assert_eq!(fs::read(dir.join("2/docs/text")).unwrap(), text(), "{name}");
+    }
+}
+
+#[test]
+fn wim_image() {
+    let archive = Archive::open(data("lzx.wim")).unwrap();
+    let dir = output_dir("wim-image");
+    archive
+        .extract_wim_image(2, &dir, &ExtractOptions::new())
+        .unwrap();
+    assert_eq!(
+        fs::read(dir.join("readme.txt")).unwrap(),
+        b"the second image\n"
+    );
+    assert!(!dir.join("hello.txt").exists());
+    assert!(
+        archive
+            .extract_wim_image(3, output_dir("wim-missing"), &ExtractOptions::new())
+            .is_err()
+    );
+}
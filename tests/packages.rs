@@ -0,0 +1,134 @@
+//! Installer packages: MSI databases with their cabinets, and flat macOS
+//! packages with their cpio payloads.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{HELLO, data, output_dir, text};
+use compak::{Archive, ArchiveFormat, ExtractOptions};
+
+/// Returns the contents of the LZX folder of `data1.cab`: runs of text,
+/// each followed by an x86 call for the E8 translation to rewrite.
+fn code() -> Vec<u8> {
+    let text = text();
+    let mut code = Vec::new();
+    for call in 0.. {
+        if code.len() >= 90_000 {
+            break;
+        }
+        code.extend(text.iter().skip(call * 400).take(400));
+        code.push(0xe8);
+        code.extend(((call as i32 * 977) % 40_000 - 20_000).to_le_bytes());
+    }
+    code.truncate(90_000);
+    code
+}
+
+#[test]
+fn msi() {
+    let archive = Archive::open(data("test.msi")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::Msi);
+    let dir = output_dir("test.msi");
+    archive.extract_msi(&dir, &ExtractOptions::new()).unwrap();
+
+    // a stored, an MSZIP and an LZX folder
+    let cab = dir.join("data1.cab");
+    assert_eq!(fs::read(cab.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(fs::read(cab.join("docs/text")).unwrap(), text());
+    let code = code();
+    assert_eq!(fs::read(cab.join("bin/app.exe")).unwrap(), code[..60_000]);
+    assert_eq!(fs::read(cab.join("bin/data.bin")).unwrap(), code[60_000..]);
+    // streams other than cabinets are kept as they are
+    assert_eq!(
+        fs::read(dir.join("Binary/notes.txt")).unwrap(),
+        b"an embedded stream\n"
+    );
+    assert_eq!(fs::read(dir.join("!File")).unwrap().len(), 40);
+    assert!(dir.join("[5]SummaryInformation").is_file());
+}
+
+#[test]
+fn pkg() {
+    let archive = Archive::open(data("test.pkg")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::Xar);
+    let dir = output_dir("test.pkg");
+    archive.extract_pkg(&dir, &ExtractOptions::new()).unwrap();
+
+    // gzip, bzip2 and xz encoded heap files
+    assert!(
+        fs::read_to_string(dir.join("Distribution"))
+            .unwrap()
+            .contains("<installer-gui-script")
+    );
+    assert!(
+        fs::read_to_string(dir.join("app.pkg/PackageInfo"))
+            .unwrap()
+            .contains("org.example.hello")
+    );
+    assert!(
+        fs::read_to_string(dir.join("tools.pkg/PackageInfo"))
+            .unwrap()
+            .contains("org.example.tools")
+    );
+    assert_eq!(
+        fs::read_link(dir.join("Resources/current")).unwrap(),
+        Path::new("../Distribution")
+    );
+
+    // a gzip'd newc payload
+    let app = dir.join("app.pkg/Payload/Applications/Hello.app");
+    assert_eq!(
+        fs::read(app.join("hello")).unwrap(),
+        b"#!/bin/sh\necho hello\n"
+    );
+    assert_eq!(fs::read(app.join("text")).unwrap(), text());
+    assert_eq!(
+        fs::read_link(app.join("current")).unwrap(),
+        Path::new("text")
+    );
+    // newc stores the data of hard links with the last of them
+    assert_eq!(fs::read(app.join("first")).unwrap(), b"linked data\n");
+    assert_eq!(fs::read(app.join("second")).unwrap(), b"linked data\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(
+            fs::metadata(app.join("first")).unwrap().ino(),
+            fs::metadata(app.join("second")).unwrap().ino()
+        );
+    }
+
+    // odc scripts, plain and in bzip2
+    for package in ["app.pkg", "tools.pkg"] {
+        assert_eq!(
+            fs::read(dir.join(package).join("Scripts/postinstall")).unwrap(),
+            b"#!/bin/sh\nexit 0\n"
+        );
+    }
+    // a pbzx payload with a stored chunk, holding a newc-crc archive
+    assert_eq!(
+        fs::read(dir.join("tools.pkg/Payload/bin/tool")).unwrap(),
+        text()[..40_000]
+    );
+}
+
+#[test]
+fn pkg_as_xar() {
+    // without unpacking the payloads
+    let archive = Archive::open(data("test.pkg")).unwrap();
+    let dir = output_dir("test.xar");
+    archive.extract_to(&dir).unwrap();
+    assert!(dir.join("app.pkg/Payload").is_file());
+    assert!(dir.join("tools.pkg/Scripts").is_file());
+}
+
+#[test]
+fn xar_short_data() {
+    // files whose heap data decodes to less than their recorded size
+    for name in ["short.xar", "shortz.xar"] {
+        let archive = Archive::open(data(name)).unwrap();
+        let error = archive.extract_to(output_dir(name)).unwrap_err();
+        assert!(error.is_corruption(), "{name}: {error}");
+    }
+}
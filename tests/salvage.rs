@@ -0,0 +1,35 @@
+//! Recovering the intact entries of a damaged ZIP archive.
+
+mod common;
+
+use std::fs;
+
+use common::{data, output_dir, text};
+use compak::{Archive, ExtractOptions};
+
+#[test]
+fn damaged_zip() {
+    // one flipped byte in `broken.txt`, and cut off inside the central
+    // directory, which leaves only the local headers to go by
+    let archive = Archive::open(data("damaged.zip")).unwrap();
+    let dir = output_dir("damaged.zip");
+    let report = archive.salvage_to(&dir, &ExtractOptions::new()).unwrap();
+
+    let names: Vec<_> = report
+        .recovered()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    assert_eq!(names, ["first.txt", "docs/text", "last.txt"]);
+    let damaged: Vec<_> = report.damaged().collect();
+    assert_eq!(damaged.len(), 1);
+    assert_eq!(damaged[0].name, "broken.txt");
+    assert!(damaged[0].error.as_ref().unwrap().is_corruption());
+
+    assert_eq!(
+        fs::read(dir.join("first.txt")).unwrap(),
+        b"the first entry\n".repeat(20)
+    );
+    assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text());
+    assert_eq!(fs::read(dir.join("last.txt")).unwrap(), b"the last entry\n");
+    assert!(!dir.join("broken.txt").exists());
+}
@@ -0,0 +1,137 @@
+//! TAR extensions: GNU and PAX sparse files, and container images whose
+//! layers are TAR archives.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{data, output_dir};
+use compak::{Archive, ExtractOptions, extract_layers};
+
+/// The layers of `docker.tar`, bottom first, as listed in its manifest.
+const LAYERS: [&str; 3] = [
+    "ff5eba71a8188ea1e1d463b58f6ad20fb3736ae6e2fe50bc84a5a2c672c95e35/layer.tar",
+    "922badbaf192e1a4a5af64df422de6d73e96e70d0ab52245bd3d692bcea9cfad/layer.tar",
+    "da0002e0cd62c639edf0b88ba0216b00084f5abf275328f44d39784450a625f4/layer.tar",
+];
+
+/// Returns `len` zeros with `data` written at each offset.
+fn sparse(len: usize, data: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut file = vec![0; len];
+    for &(offset, data) in data {
+        file[offset..offset + data.len()].copy_from_slice(data);
+    }
+    file
+}
+
+#[test]
+fn sparse_files() {
+    let middle = b"middle block\n".repeat(100);
+    let disk = sparse(
+        1_048_577,
+        &[
+            (0, b"begin of the sparse file\n"),
+            (300_000, &middle),
+            (1_048_555, b"end of the sparse file"),
+        ],
+    );
+    let hole_at_end = sparse(200_000, &[(0, b"data then a hole\n")]);
+    let lines: Vec<_> = (0..8).map(|region| format!("region {region}\n")).collect();
+    let placed: Vec<_> = lines
+        .iter()
+        .enumerate()
+        .map(|(region, line)| (region * 65_536, line.as_bytes()))
+        .collect();
+    let regions = sparse(524_288, &placed);
+
+    for name in [
+        "sparse-gnu.tar.gz",
+        "sparse-posix-0.0.tar.gz",
+        "sparse-posix-0.1.tar.gz",
+        "sparse-posix-1.0.tar.gz",
+    ] {
+        let dir = output_dir(name);
+        Archive::open(data(name)).unwrap().extract_to(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("disk.img")).unwrap(), disk, "{name}");
+        assert_eq!(
+            fs::read(dir.join("hole-at-end.img")).unwrap(),
+            hole_at_end,
+            "{name}"
+        );
+        assert_eq!(
+            fs::read(dir.join("regions.img")).unwrap(),
+            regions,
+            "{name}"
+        );
+        #[cfg(unix)]
+        {
+            // the holes are left unwritten
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(dir.join("disk.img")).unwrap();
+            assert!(metadata.blocks() * 512 < metadata.len(), "{name}");
+        }
+    }
+}
+
+/// Asserts that `dir` holds the three layers applied in order.
+fn assert_root_fs(dir: &Path) {
+    assert_eq!(
+        fs::read(dir.join("etc/os-release")).unwrap(),
+        b"NAME=compak\nVERSION=2\n"
+    );
+    assert_eq!(fs::read(dir.join("srv/data")).unwrap(), b"served\n");
+    // the base file removed by a whiteout
+    assert!(!dir.join("etc/hostname").exists());
+    // the base files hidden by an opaque directory
+    assert_eq!(fs::read(dir.join("var/cache/new")).unwrap(), b"fresh\n");
+    assert!(!dir.join("var/cache/old-a").exists());
+    assert!(!dir.join("var/cache/old-b").exists());
+    // removed by the top layer, leaving a dangling link to it
+    assert!(!dir.join("usr/bin/tool").exists());
+    assert_eq!(
+        fs::read_link(dir.join("usr/bin/tool-link")).unwrap(),
+        Path::new("tool")
+    );
+    for whiteout in [
+        "etc/.wh.hostname",
+        "var/cache/.wh..wh..opq",
+        "usr/bin/.wh.tool",
+    ] {
+        assert!(
+            fs::symlink_metadata(dir.join(whiteout)).is_err(),
+            "{whiteout}"
+        );
+    }
+}
+
+#[test]
+fn docker_save() {
+    // the middle layer is gzip'd and the top one is listed through a
+    // symlink to another layer's file
+    let archive = Archive::open(data("docker.tar")).unwrap();
+    let dir = output_dir("docker.tar");
+    archive.extract_image(&dir, &ExtractOptions::new()).unwrap();
+    assert_root_fs(&dir);
+}
+
+#[test]
+fn oci_layout() {
+    // an index pointing at an index, and tar, xz and zstd layers
+    let archive = Archive::open(data("oci.tar")).unwrap();
+    let dir = output_dir("oci.tar");
+    archive.extract_image(&dir, &ExtractOptions::new()).unwrap();
+    assert_root_fs(&dir);
+}
+
+#[test]
+fn layers() {
+    let image = output_dir("layers");
+    Archive::open(data("docker.tar"))
+        .unwrap()
+        .extract_to(&image)
+        .unwrap();
+    let layers = LAYERS.map(|layer| image.join(layer));
+    let dir = output_dir("layers-root");
+    extract_layers(&layers, &dir, &ExtractOptions::new()).unwrap();
+    assert_root_fs(&dir);
+}
@@ -0,0 +1,31 @@
+//! Archives split across several files, opened from the file that starts
+//! the set.
+
+mod common;
+
+use std::fs;
+
+use common::{HELLO, data, output_dir, text};
+use compak::{Archive, ArchiveFormat};
+
+#[test]
+fn split_zip() {
+    // `split.z01` then `split.zip`, which holds the central directory
+    let archive = Archive::open(data("split.zip")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::Zip);
+    assert_eq!(archive.read_entry("docs/text").unwrap(), text());
+    let dir = output_dir("split.zip");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text());
+}
+
+#[test]
+fn numbered_7z_volumes() {
+    let archive = Archive::open(data("vol.7z.001")).unwrap();
+    assert_eq!(archive.format(), ArchiveFormat::SevenZ);
+    let dir = output_dir("vol.7z");
+    archive.extract_to(&dir).unwrap();
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), HELLO);
+    assert_eq!(fs::read(dir.join("docs/text")).unwrap(), text());
+}
@@ -0,0 +1,60 @@
+//! ZIP archives past the limits of the classic format, built with
+//! [`ArchiveBuilder`] and read back.
+
+use std::io::{self, Cursor, Read};
+
+use compak::{Archive, ArchiveFormat, Codec, CompressionOptions, writer::ArchiveBuilder};
+
+#[test]
+fn many_entries() {
+    const COUNT: usize = 65_536;
+
+    let mut builder = ArchiveBuilder::new(Cursor::new(Vec::new()), ArchiveFormat::Zip).unwrap();
+    for index in 0..COUNT {
+        builder.add_bytes(format!("{index:05}.txt"), b"").unwrap();
+    }
+    let zip = builder.finish().unwrap().into_inner();
+
+    let archive = Archive::from_bytes(zip).unwrap();
+    let entries = archive.entries().unwrap();
+    assert!(entries.requires_zip64());
+    assert_eq!(entries.len(), COUNT);
+    let last = archive.entries().unwrap().last().unwrap();
+    assert_eq!(last.name, format!("{:05}.txt", COUNT - 1));
+    assert_eq!(archive.read_entry(&last.name).unwrap(), b"");
+}
+
+#[test]
+#[ignore = "streams 4 GiB through the compressor and back"]
+fn large_entry() {
+    const SIZE: u64 = (4 << 30) + 1;
+
+    let options = CompressionOptions::new().zip_compression(Codec::Zstd {
+        level: 1,
+    });
+    let mut builder =
+        ArchiveBuilder::new_with(Cursor::new(Vec::new()), ArchiveFormat::Zip, &options).unwrap();
+    builder
+        .add_reader("zeros.bin", io::repeat(0).take(SIZE))
+        .unwrap();
+    let zip = builder.finish().unwrap().into_inner();
+
+    let archive = Archive::from_bytes(zip).unwrap();
+    let entries = archive.entries().unwrap();
+    assert!(entries.requires_zip64());
+    let entry = archive.entries().unwrap().next().unwrap();
+    assert_eq!(entry.size, SIZE);
+
+    let mut reader = archive.entry_reader("zeros.bin").unwrap();
+    let mut buffer = vec![0; 1 << 20];
+    let mut read = 0;
+    loop {
+        let n = reader.read(&mut buffer).unwrap();
+        if n == 0 {
+            break;
+        }
+        assert!(buffer[..n].iter().all(|&byte| byte == 0));
+        read += n as u64;
+    }
+    assert_eq!(read, SIZE);
+}